@@ -17,15 +17,28 @@ import static net.minecraft.client.gui.DrawableHelper.drawStringWithShadow;
 @Mixin(TitleScreen.class)
 public class TitleScreenMixin {
 
+    private boolean startedBake = false;
     private boolean updatedTitle = false;
 
     @Inject(method = "render", at = @At("HEAD"))
     private void render(MatrixStack matrices, int mouseX, int mouseY, float delta, CallbackInfo ci) {
-        if(!updatedTitle && Wgpu.INITIALIZED) {
-            WgpuNative.cacheBlockStates();
-            MinecraftClient.getInstance().updateWindowTitle();
-            updatedTitle = true;
+        if(updatedTitle || !Wgpu.INITIALIZED) return;
+
+        if(!startedBake) {
+            WgpuNative.bakeBlocks();
+            startedBake = true;
+        }
+
+        float progress = WgpuNative.getBakeProgress();
+        if(progress < 1.0f) {
+            MinecraftClient client = MinecraftClient.getInstance();
+            drawStringWithShadow(matrices, client.textRenderer, "Baking blocks... " + (int) (progress * 100.0f) + "%", 2, 2, 0xFFFFFF);
+            return;
         }
+
+        WgpuNative.cacheBlockStates();
+        MinecraftClient.getInstance().updateWindowTitle();
+        updatedTitle = true;
     }
 
 }
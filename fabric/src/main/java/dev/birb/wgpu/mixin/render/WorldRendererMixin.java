@@ -1,9 +1,11 @@
 package dev.birb.wgpu.mixin.render;
 
+import com.mojang.blaze3d.systems.RenderSystem;
 import dev.birb.wgpu.rust.WgpuNative;
 import it.unimi.dsi.fastutil.objects.ObjectArrayList;
 import net.minecraft.client.MinecraftClient;
 import net.minecraft.client.network.ClientPlayerEntity;
+import net.minecraft.client.render.BackgroundRenderer;
 import net.minecraft.client.render.BuiltChunkStorage;
 import net.minecraft.client.render.Camera;
 import net.minecraft.client.render.Frustum;
@@ -17,9 +19,11 @@ import net.minecraft.client.util.math.Vector3d;
 import net.minecraft.client.world.ClientWorld;
 import net.minecraft.resource.ResourceManager;
 import net.minecraft.util.math.ChunkPos;
+import net.minecraft.util.math.MathHelper;
 import net.minecraft.util.math.Matrix4f;
 import net.minecraft.util.math.Vec3d;
 import net.minecraft.util.math.Vec3f;
+import net.minecraft.world.World;
 import org.jetbrains.annotations.Nullable;
 import org.spongepowered.asm.mixin.Final;
 import org.spongepowered.asm.mixin.Mixin;
@@ -78,10 +82,11 @@ public abstract class WorldRendererMixin {
 
     /**
      * @author wgpu-mc
-     * @reason do no such thing
+     * @reason the resources are reloaded by wgpu-mc on its next frame
      */
     @Overwrite
     public void reload(ResourceManager manager) {
+        WgpuNative.reloadResources();
     }
 
     @Inject(method = "render", cancellable = true, at = @At("HEAD"))
@@ -126,9 +131,35 @@ public abstract class WorldRendererMixin {
         floatBuffer.get(out);
         WgpuNative.setMatrix(0, out);
 
+        this.setFrameUniforms(tickDelta, camera, gameRenderer);
+
         ci.cancel();
     }
 
+    private void setFrameUniforms(float tickDelta, Camera camera, GameRenderer gameRenderer) {
+        float viewDistance = gameRenderer.getViewDistance();
+
+        //Sets the fog color like vanilla does before drawing the world
+        BackgroundRenderer.render(camera, tickDelta, this.world, (int) (viewDistance / 16.0f), gameRenderer.getSkyDarkness(tickDelta));
+        float[] fogColor = RenderSystem.getShaderFogColor().clone();
+
+        //The sky angle is 0 at noon, whereas the time of day starts at sunrise
+        float timeOfDay = (this.world.getSkyAngle(tickDelta) + 0.25f) % 1.0f;
+        float sunAngle = timeOfDay * (float) (Math.PI * 2.0);
+        float[] sunDirection = {MathHelper.cos(sunAngle), MathHelper.sin(sunAngle), 0.0f};
+
+        int dimension = 0;
+        if (this.world.getRegistryKey() == World.NETHER) {
+            dimension = -1;
+        } else if (this.world.getRegistryKey() == World.END) {
+            dimension = 1;
+        }
+
+        float fogStart = viewDistance - MathHelper.clamp(viewDistance / 10.0f, 4.0f, 64.0f);
+
+        WgpuNative.setFrameUniforms(tickDelta, fogStart, viewDistance, fogColor, sunDirection, dimension, timeOfDay, sunAngle);
+    }
+
     @Inject(method = "setWorld", at = @At("HEAD"))
     public void setWorld(ClientWorld world, CallbackInfo ci) {
         WgpuNative.clearChunks();
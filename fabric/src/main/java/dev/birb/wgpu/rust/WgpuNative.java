@@ -158,10 +158,14 @@ public class WgpuNative {
 
     public static native void clearPalette(long l);
 
-    public static native void createChunk(int x, int z, long[] pointers, long[] storagePointers);
+    public static native void createChunk(int x, int z, long[] pointers, long[] storagePointers, byte[] blockLight, byte[] skyLight, int[] biomeColors);
 
     public static native void destroyPaletteStorage(long paletteStorage);
 
+    public static native void bakeBlocks();
+
+    public static native float getBakeProgress();
+
     public static native void cacheBlockStates();
 
     public static native void setCamera(double x, double y, double z, float renderYaw, float renderPitch);
@@ -178,10 +182,14 @@ public class WgpuNative {
 
     public static native void setChunkOffset(int x, int z);
 
+    public static native void setFrameUniforms(float tickDelta, float fogStart, float fogEnd, float[] fogColor, float[] sunDirection, int dimension, float timeOfDay, float sunAngle);
+
     public static native void setCursorLocked(boolean locked);
 
     public native static void centerCursor();
 
     public static native void clearChunks();
+
+    public static native void reloadResources();
     
 }
@@ -5,6 +5,7 @@ import net.minecraft.resource.ResourceManager;
 import net.minecraft.util.Identifier;
 
 import java.io.IOException;
+import java.util.Collection;
 
 public class WgpuResourceProvider {
 
@@ -20,4 +21,17 @@ public class WgpuResourceProvider {
         }
     }
 
+    /**
+     * Every resource somewhere under the directory, like "minecraft:optifine/ctm"
+     */
+    public static String[] findResources(String directory) {
+        Identifier id = new Identifier(directory);
+        Collection<Identifier> resources = manager.findResources(id.getPath(), path -> true);
+
+        return resources.stream()
+            .filter(resource -> resource.getNamespace().equals(id.getNamespace()))
+            .map(Identifier::toString)
+            .toArray(String[]::new);
+    }
+
 }
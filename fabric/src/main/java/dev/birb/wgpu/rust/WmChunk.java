@@ -8,15 +8,28 @@ import net.minecraft.client.MinecraftClient;
 import net.minecraft.network.PacketByteBuf;
 import net.minecraft.util.collection.IndexedIterable;
 import net.minecraft.util.collection.PaletteStorage;
+import net.minecraft.util.math.ChunkSectionPos;
+import net.minecraft.world.LightType;
+import net.minecraft.world.biome.Biome;
+import net.minecraft.world.chunk.ChunkNibbleArray;
+import net.minecraft.world.chunk.ChunkSection;
 import net.minecraft.world.chunk.Palette;
 import net.minecraft.world.chunk.PalettedContainer;
 import net.minecraft.world.chunk.WorldChunk;
+import net.minecraft.world.chunk.light.ChunkLightingView;
+
+import java.util.Arrays;
 
 public class WmChunk {
     public WorldChunk worldChunk;
     public int x;
     public int z;
 
+    //Bytes of a ChunkNibbleArray
+    private static final int SECTION_LIGHT_SIZE = 2048;
+    //Biomes are stored in cells of 4x4x4 blocks
+    private static final int SECTION_BIOME_CELLS = 64;
+
     public WmChunk(WorldChunk worldChunk) {
         MinecraftClient client = MinecraftClient.getInstance();
 
@@ -75,14 +88,69 @@ public class WmChunk {
             }
         }
 
+        boolean hasSkyLight = this.worldChunk.getWorld().getDimension().hasSkyLight();
+        byte[] blockLight = this.copyLight(LightType.BLOCK, (byte) 0);
+        //Sections without sky light data are above everything which could block it
+        byte[] skyLight = this.copyLight(LightType.SKY, hasSkyLight ? (byte) 0xff : (byte) 0);
+        int[] biomeColors = this.copyBiomeColors();
+
         int x = this.x;
         int z = this.z;
 
         Thread thread = new Thread(() -> {
-            WgpuNative.createChunk(x, z, paletteIndices, storageIndices);
+            WgpuNative.createChunk(x, z, paletteIndices, storageIndices, blockLight, skyLight, biomeColors);
             WgpuNative.bakeChunk(x, z);
         });
 
         thread.start();
     }
+
+    private byte[] copyLight(LightType type, byte missing) {
+        byte[] light = new byte[24 * SECTION_LIGHT_SIZE];
+        ChunkLightingView view = this.worldChunk.getWorld().getLightingProvider().get(type);
+
+        for(int i=0;i<24;i++) {
+            ChunkSectionPos pos = ChunkSectionPos.from(this.worldChunk.getPos(), this.worldChunk.getBottomSectionCoord() + i);
+            ChunkNibbleArray section = view.getLightSection(pos);
+
+            if(section == null || section.isUninitialized()) {
+                Arrays.fill(light, i * SECTION_LIGHT_SIZE, (i + 1) * SECTION_LIGHT_SIZE, missing);
+            } else {
+                System.arraycopy(section.asByteArray(), 0, light, i * SECTION_LIGHT_SIZE, SECTION_LIGHT_SIZE);
+            }
+        }
+
+        return light;
+    }
+
+    //The grass, foliage and water color of each biome cell, in the same order as the biome container
+    private int[] copyBiomeColors() {
+        int[] colors = new int[24 * SECTION_BIOME_CELLS * 3];
+
+        for(int i=0;i<24;i++) {
+            ChunkSection section;
+            try {
+                section = this.worldChunk.getSection(i);
+            } catch(ArrayIndexOutOfBoundsException e) {
+                continue;
+            }
+
+            var biomes = section.getBiomeContainer();
+
+            for(int y=0;y<4;y++) {
+                for(int z=0;z<4;z++) {
+                    for(int x=0;x<4;x++) {
+                        Biome biome = biomes.get(x, y, z).value();
+                        int index = (i * SECTION_BIOME_CELLS + (((y << 2) | z) << 2 | x)) * 3;
+
+                        colors[index] = biome.getGrassColorAt((this.x << 4) + (x << 2) + 2, (this.z << 4) + (z << 2) + 2);
+                        colors[index + 1] = biome.getFoliageColor();
+                        colors[index + 2] = biome.getWaterColor();
+                    }
+                }
+            }
+        }
+
+        return colors;
+    }
 }
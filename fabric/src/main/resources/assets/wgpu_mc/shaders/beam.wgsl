@@ -0,0 +1,49 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+};
+
+@group(0) @binding(0)
+var<uniform> proj: CameraUniform;
+
+@group(1) @binding(0)
+var t_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var t_sampler: sampler;
+
+struct VertexResult {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) region: vec4<f32>,
+    @location(2) color: vec4<f32>,
+};
+
+@vertex
+fn vert(
+    @location(0) pos_in: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) region: vec4<f32>,
+    @location(3) color: vec4<f32>
+) -> VertexResult {
+    var vr: VertexResult;
+    vr.pos = proj.view_proj * vec4<f32>(pos_in, 1.0);
+    vr.tex_coords = tex_coords;
+    vr.region = region;
+    vr.color = color;
+
+    return vr;
+}
+
+@fragment
+fn frag(in: VertexResult) -> @location(0) vec4<f32> {
+    //The texture repeats along the beam, so the coordinates are wrapped into its region of the atlas
+    let uv = mix(in.region.xy, in.region.zw, fract(in.tex_coords));
+    let texture = textureSample(t_texture, t_sampler, uv);
+    let color = select(texture, vec4<f32>(1.0), in.region.x < 0.0) * in.color;
+
+    if (color.a < 0.01) {
+        discard;
+    }
+
+    return color;
+}
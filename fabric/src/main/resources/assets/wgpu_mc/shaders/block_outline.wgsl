@@ -0,0 +1,30 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+};
+
+@group(0) @binding(0)
+var<uniform> proj: CameraUniform;
+
+struct PushConstants {
+    //Moves the lines towards the camera so they don't Z-fight with the faces of the block
+    line_depth_offset: f32
+}
+
+var<push_constant> push_constants: PushConstants;
+
+@vertex
+fn vert(
+    @location(0) pos_in: vec3<f32>,
+    @location(1) color: vec3<f32>
+) -> @builtin(position) vec4<f32> {
+    var pos = proj.view_proj * vec4<f32>(pos_in, 1.0);
+    pos.z += push_constants.line_depth_offset;
+
+    return pos;
+}
+
+@fragment
+fn frag() -> @location(0) vec4<f32> {
+    //Vanilla's translucent black
+    return vec4<f32>(0.0, 0.0, 0.0, 0.4);
+}
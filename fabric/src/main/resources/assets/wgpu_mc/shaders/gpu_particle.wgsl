@@ -0,0 +1,71 @@
+struct CameraUniform {
+    view_proj: mat4x4<f32>
+};
+
+@group(0) @binding(0)
+var<uniform> proj: CameraUniform;
+
+@group(1) @binding(0)
+var<uniform> view: CameraUniform;
+
+@group(2) @binding(0)
+var t_texture: texture_2d<f32>;
+
+@group(2) @binding(1)
+var t_sampler: sampler;
+
+struct VertexResult {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
+};
+
+@vertex
+fn vert(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) size: f32,
+    @location(2) uv: vec4<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) alive: u32
+) -> VertexResult {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0)
+    );
+
+    let corner = corners[vertex_index];
+
+    //The rows of the view matrix are the axes of the camera in the world
+    let right = vec3<f32>(view.view_proj[0][0], view.view_proj[1][0], view.view_proj[2][0]);
+    let up = vec3<f32>(view.view_proj[0][1], view.view_proj[1][1], view.view_proj[2][1]);
+
+    let world_position = position + (right * corner.x + up * corner.y) * size;
+
+    var vr: VertexResult;
+    vr.pos = proj.view_proj * vec4<f32>(world_position, 1.0);
+    vr.tex_coords = mix(uv.xy, uv.zw, vec2<f32>(corner.x * 0.5 + 0.5, 0.5 - corner.y * 0.5));
+    vr.color = color;
+
+    //Free slots are collapsed into a point, which draws nothing
+    if (alive == 0u) {
+        vr.pos = vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    return vr;
+}
+
+@fragment
+fn frag(in: VertexResult) -> @location(0) vec4<f32> {
+    let color = textureSample(t_texture, t_sampler, in.tex_coords) * in.color;
+
+    if (color.a < 0.1) {
+        discard;
+    }
+
+    return color;
+}
@@ -0,0 +1,68 @@
+//Writes the indirect draws of the sections, with the ones outside the frustum drawing nothing, see
+// wgpu_mc::render::gpu_culler
+
+struct Params {
+    //Left, right, bottom, top, near and far, pointing inwards
+    planes: array<vec4<f32>, 6>,
+    draw_count: u32,
+    padding0: u32,
+    padding1: u32,
+    padding2: u32
+};
+
+//The arguments of a draw_indexed_indirect call
+struct Draw {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32
+};
+
+struct Bounds {
+    min: vec4<f32>,
+    max: vec4<f32>
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> draws: array<Draw>;
+
+@group(0) @binding(2)
+var<storage, read> bounds: array<Bounds>;
+
+@group(0) @binding(3)
+var<storage, read_write> culled: array<Draw>;
+
+//The same as SectionBounds::is_visible in gpu_culler.rs
+fn is_visible(section: Bounds) -> bool {
+    for (var i = 0; i < 6; i = i + 1) {
+        let plane = params.planes[i];
+
+        //The corner furthest along the plane's normal
+        let corner = select(section.min.xyz, section.max.xyz, plane.xyz >= vec3<f32>(0.0));
+
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return false;
+        }
+    }
+
+    return true;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.draw_count) {
+        return;
+    }
+
+    var draw = draws[id.x];
+
+    if (!is_visible(bounds[id.x])) {
+        draw.instance_count = 0u;
+    }
+
+    culled[id.x] = draw;
+}
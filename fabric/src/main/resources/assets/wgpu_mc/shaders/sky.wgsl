@@ -1,28 +1,50 @@
-struct Uniforms {
+struct CameraUniform {
     view_proj: mat4x4<f32>
 };
 
+@group(0) @binding(0)
+var<uniform> proj: CameraUniform;
+
 @group(1) @binding(0)
-var<uniform> uniform_data: Uniforms;
+var t_texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var t_sampler: sampler;
 
 struct VertexResult {
-    @builtin(position) pos: vec4<f32>
+    @builtin(position) pos: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    @location(1) color: vec4<f32>,
 };
 
 @vertex
-fn vs_main(@location(0) pos: vec3<f32>) -> VertexResult {
+fn vert(
+    @location(0) pos_in: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) color: vec4<f32>
+) -> VertexResult {
     var vr: VertexResult;
-    vr.pos = vec4<f32>(pos, 1.0);
+    vr.pos = proj.view_proj * vec4<f32>(pos_in, 1.0);
+    vr.tex_coords = tex_coords;
+    vr.color = color;
 
     return vr;
 }
 
-@group(0) @binding(0)
-var t_texture: texture_cube<f32>;
-@group(0) @binding(1)
-var t_sampler: sampler;
-
 @fragment
-fn fs_main() -> @location(0) vec4<f32> {
-    return textureSample(t_texture, t_sampler, vec3<f32>(0.0, 0.0, 0.0));
-}
\ No newline at end of file
+fn frag(in: VertexResult) -> @location(0) vec4<f32> {
+    if (in.tex_coords.x < 0.0) {
+        return in.color;
+    }
+
+    //The sun and moon are black where they're empty, as vanilla adds them onto the sky
+    let texel = textureSample(t_texture, t_sampler, in.tex_coords);
+    let brightness = max(texel.r, max(texel.g, texel.b));
+    let alpha = brightness * texel.a * in.color.a;
+
+    if (alpha < 0.01) {
+        discard;
+    }
+
+    return vec4<f32>(texel.rgb / max(brightness, 0.0001) * in.color.rgb, alpha);
+}
@@ -18,47 +18,102 @@ struct ChunkOffset {
     z: i32
 }
 
-struct PushConstants {
-    chunk_x: i32,
-    chunk_z: i32,
-    fb_width: f32,
-    fb_height: f32
-}
-
-var<push_constant> push_constants: PushConstants;
+struct FrameUniforms {
+    fog_color: vec4<f32>,
+    sun_direction: vec3<f32>,
+    elapsed_time: f32,
+    viewport_size: vec2<f32>,
+    fog_start: f32,
+    fog_end: f32,
+    tick_delta: f32,
+    dimension: i32,
+    time_of_day: f32,
+    sun_angle: f32,
+    camera_position: vec3<f32>,
+};
 
 @group(0) @binding(0)
 var<uniform> proj: CameraUniform;
 
 struct VertexResult {
-    @builtin(position) pos: vec4<f32>,
+    //The depth pre-pass and the pass after it have to compute the exact same depth, see WmRenderer::depth_prepass
+    @builtin(position) @invariant pos: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) tex_coords2: vec2<f32>,
     @location(2) blend: f32,
     @location(3) normal: vec3<f32>,
-    @location(4) world_pos: vec3<f32>
+    @location(4) world_pos: vec3<f32>,
+    @location(5) color: vec4<f32>,
+    @location(6) lightmap_coords: vec2<f32>,
+    @location(7) emissive_tex_coords: vec2<f32>,
+    @location(8) fog_distance: f32,
+#ifdef PBR
+    @location(9) tangent: vec4<f32>,
+#endif
+    @location(10) @interpolate(flat) region: vec4<f32>,
 //    @location(4) screen_pos: vec4<f32>
 };
 
 @vertex
 fn vert(
+#ifdef PACKED_VERTICES
+    //In 64ths of a block, offset by 8 blocks, see PackedVertex
+    @location(0) packed_pos: vec4<u32>,
+#else
     @location(0) pos_in: vec3<f32>,
+#endif
     @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-    @location(6) uv_offset: u32
+    @location(2) lightmap_coords: vec2<f32>,
+    @location(3) normal: vec4<f32>,
+    @location(4) color: vec4<f32>,
+#ifdef PBR
+#ifndef PACKED_VERTICES
+    @location(5) tangent: vec4<f32>,
+#endif
+#endif
+    @location(6) uv_offset: u32,
+    @location(8) emissive_tex_coords: vec2<f32>,
+    @location(9) region: vec4<f32>,
+    //Relative to the chunk offset, one per drawn chunk
+    @location(7) chunk_position: vec2<i32>
 ) -> VertexResult {
+#ifdef PACKED_VERTICES
+    let pos_in = vec3<f32>(packed_pos.xyz) / 64.0 - 8.0;
+#endif
+
     // var uv = uv_offsets.uvs[uv_offset];
 
     var vr: VertexResult;
 
-    var world_pos = pos_in + vec3<f32>(f32(push_constants.chunk_x) * 16.0, 0.0, f32(push_constants.chunk_z) * 16.0);
+    var world_pos = pos_in + vec3<f32>(f32(chunk_position.x) * 16.0, 0.0, f32(chunk_position.y) * 16.0);
 
     vr.world_pos = world_pos;
     vr.pos = proj.view_proj * vec4<f32>(world_pos, 1.0);
+    //How far in front of the camera the vertex is
+    vr.fog_distance = vr.pos.w;
+#ifdef PACKED_VERTICES
+    //Repeating texture coordinates are divided by the size of a section, see PackedVertex::REPEAT_SCALE
+    let repeats = region.z > region.x || region.w > region.y;
+    vr.tex_coords = select(tex_coords, tex_coords * 16.0, repeats);
+#else
     vr.tex_coords = tex_coords;
-    vr.tex_coords2 = tex_coords;
+#endif
+    vr.tex_coords2 = vr.tex_coords;
+    vr.region = region;
     vr.blend = 1.0;
-    vr.normal = normal;
+    vr.normal = normal.xyz;
+    vr.lightmap_coords = lightmap_coords;
+    vr.emissive_tex_coords = emissive_tex_coords;
+    //Darkened by ambient occlusion while baking
+    vr.color = color;
+#ifdef PBR
+#ifdef PACKED_VERTICES
+    //Packed vertices have no tangent, it's derived in the fragment shader instead
+    vr.tangent = vec4<f32>(0.0);
+#else
+    vr.tangent = tangent;
+#endif
+#endif
 
     return vr;
 }
@@ -69,14 +124,106 @@ var t_texture: texture_2d<f32>;
 @group(1) @binding(1)
 var t_sampler: sampler;
 
+@group(2) @binding(0)
+var lightmap_texture: texture_2d<f32>;
+
+@group(2) @binding(1)
+var lightmap_sampler: sampler;
+
+@group(3) @binding(0)
+var<uniform> frame: FrameUniforms;
+
+#ifdef PBR
+@group(4) @binding(0)
+var normal_texture: texture_2d<f32>;
+
+@group(4) @binding(1)
+var normal_sampler: sampler;
+
+@group(5) @binding(0)
+var specular_texture: texture_2d<f32>;
+
+@group(5) @binding(1)
+var specular_sampler: sampler;
+
+//Roughly where vanilla's block shading comes from, faces are already shaded by it while baking
+fn shade(normal: vec3<f32>) -> f32 {
+    let sun_direction = normalize(vec3<f32>(0.18, 0.9, -0.4));
+
+    return 0.6 + 0.4 * max(dot(normal, sun_direction), 0.0);
+}
+#endif
+
+//Quads merged by greedy meshing repeat their texture once per block. Their coordinates are relative to the region
+//of the atlas the texture is in and wrapped into it, see Vertex::region
+fn atlas_coords(tex_coords: vec2<f32>, region: vec4<f32>) -> vec2<f32> {
+    let repeats = region.z > region.x || region.w > region.y;
+
+    return select(tex_coords, mix(region.xy, region.zw, fract(tex_coords)), repeats);
+}
+
 @fragment
 fn frag(
     in: VertexResult
 ) -> @location(0) vec4<f32> {
-    let col1 = textureSample(t_texture, t_sampler, in.tex_coords);
-    let col2 = textureSample(t_texture, t_sampler, in.tex_coords2);
+    let uv = atlas_coords(in.tex_coords, in.region);
+    let col1 = textureSample(t_texture, t_sampler, uv);
+    let col2 = textureSample(t_texture, t_sampler, atlas_coords(in.tex_coords2, in.region));
 
     let col = mix(col1, col2, in.blend);
 
-    return col1;
-}
\ No newline at end of file
+    let light = textureSample(lightmap_texture, lightmap_sampler, in.lightmap_coords);
+
+    //The emissive overlay is drawn on top without being darkened, negative coordinates mean there isn't one
+    let emissive = textureSample(t_texture, t_sampler, max(in.emissive_tex_coords, vec2<f32>(0.0)));
+    let emissive_alpha = select(0.0, emissive.a, in.emissive_tex_coords.x >= 0.0);
+#ifdef PBR
+    let normal_sample = textureSample(normal_texture, normal_sampler, uv);
+    let specular_sample = textureSample(specular_texture, specular_sampler, uv);
+
+    //The tangent from the texture coordinates of the screen, for vertices without one
+    let dp1 = dpdx(in.world_pos);
+    let dp2 = dpdy(in.world_pos);
+    let duv1 = dpdx(in.tex_coords);
+    let duv2 = dpdy(in.tex_coords);
+    let derived_tangent = dp1 * duv2.y - dp2 * duv1.y;
+    let derived_handedness = select(1.0, -1.0, duv1.x * duv2.y - duv2.x * duv1.y < 0.0);
+
+    let normal = normalize(in.normal);
+    let has_tangent = dot(in.tangent.xyz, in.tangent.xyz) > 0.0;
+    let tangent = normalize(select(derived_tangent, in.tangent.xyz, has_tangent));
+    let bitangent = cross(normal, tangent) * select(derived_handedness, in.tangent.w, has_tangent);
+
+    //LabPBR only stores x and y, in the DirectX convention where y points down the texture like v
+    let xy = normal_sample.xy * 2.0 - 1.0;
+    let tangent_normal = vec3<f32>(xy, sqrt(max(1.0 - dot(xy, xy), 0.0)));
+    let mapped_normal = normalize(mat3x3<f32>(tangent, bitangent, normal) * tangent_normal);
+
+    //Only the change the normal map makes to the shading, with the ambient occlusion of the map in blue
+    let shading = shade(mapped_normal) / shade(normal) * normal_sample.b;
+
+    //Emission is in alpha from 0 to 254, 255 means there is none
+    let emission = select(specular_sample.a * 255.0 / 254.0, 0.0, specular_sample.a >= 1.0);
+    let lit = mix(light.rgb, vec3<f32>(1.0), emission);
+
+    let rgb = mix(col1.rgb * in.color.rgb * lit * shading, emissive.rgb, emissive_alpha);
+#else
+    let rgb = mix(col1.rgb * in.color.rgb * light.rgb, emissive.rgb, emissive_alpha);
+#endif
+
+    //Fades into the fog towards the end of the render distance, like vanilla's linear fog
+    let fog = smoothstep(frame.fog_start, max(frame.fog_end, frame.fog_start + 0.001), in.fog_distance);
+    let fogged = mix(rgb, frame.fog_color.rgb, fog * frame.fog_color.a);
+
+#ifdef CUTOUT
+    //Cutout textures are either fully opaque or fully transparent
+    if (col1.a < 0.5) {
+        discard;
+    }
+
+    return vec4<f32>(fogged, 1.0);
+#else
+    //Translucent terrain is blended with alpha blending, and drawn after the solid and cutout terrain
+    return vec4<f32>(fogged, col1.a);
+#endif
+}
@@ -0,0 +1,234 @@
+//Emits the visible faces of a chunk made of cubes, see wgpu_mc::render::gpu_mesher
+
+struct Params {
+    max_quads: u32,
+    ambient_occlusion: u32,
+    padding0: u32,
+    padding1: u32
+};
+
+struct PaletteEntry {
+    //Bit 0: meshed here, bit 1: culls the faces of its neighbours, bits 2-7: which faces the model has
+    flags: u32,
+    padding0: u32,
+    padding1: u32,
+    padding2: u32,
+    //Two corners of a face in each element, four corners for each of the six faces
+    tex_coords: array<vec4<f32>, 12>,
+    animation_uv_offsets: array<vec4<u32>, 2>
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> palette: array<PaletteEntry>;
+
+//The chunk with a border of one block, palette index in bits 0-15, block light in bits 16-19 and sky light in
+// bits 20-23
+@group(0) @binding(2)
+var<storage, read> blocks: array<u32>;
+
+@group(0) @binding(3)
+var<storage, read_write> vertices: array<f32>;
+
+@group(0) @binding(4)
+var<storage, read_write> indices: array<u32>;
+
+@group(0) @binding(5)
+var<storage, read_write> quad_count: atomic<u32>;
+
+const BORDERED_WIDTH: i32 = 18;
+const HEIGHT: i32 = 384;
+//The size of wgpu_mc::render::pipeline::Vertex in floats
+const VERTEX_FLOATS: u32 = 26u;
+
+fn block_at(pos: vec3<i32>) -> u32 {
+    return blocks[(pos.y * BORDERED_WIDTH + pos.z + 1) * BORDERED_WIDTH + pos.x + 1];
+}
+
+fn occludes(pos: vec3<i32>) -> bool {
+    //There is never anything above or below the world
+    if (pos.y < 0 || pos.y >= HEIGHT) {
+        return false;
+    }
+
+    return (palette[block_at(pos) & 0xffffu].flags & 2u) != 0u;
+}
+
+fn lightmap_coords(pos: vec3<i32>) -> vec2<f32> {
+    var light = vec2<u32>(0u, 15u);
+
+    if (pos.y >= 0 && pos.y < HEIGHT) {
+        let block = block_at(pos);
+        light = vec2<u32>((block >> 16u) & 15u, (block >> 20u) & 15u);
+    }
+
+    return (vec2<f32>(light) + 0.5) / 16.0;
+}
+
+//In the order north, east, south, west, up, down
+fn face_normal(face: u32) -> vec3<i32> {
+    var normals = array<vec3<i32>, 6>(
+        vec3<i32>(0, 0, -1),
+        vec3<i32>(1, 0, 0),
+        vec3<i32>(0, 0, 1),
+        vec3<i32>(-1, 0, 0),
+        vec3<i32>(0, 1, 0),
+        vec3<i32>(0, -1, 0)
+    );
+
+    return normals[face];
+}
+
+//Must match FACE_CORNERS in gpu_mesher.rs
+fn face_corner(face: u32, corner: u32) -> vec3<f32> {
+    var corners = array<vec3<f32>, 24>(
+        vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(1.0, 0.0, 1.0), vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(1.0, 1.0, 0.0),
+        vec3<f32>(1.0, 0.0, 1.0), vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 1.0, 1.0), vec3<f32>(1.0, 1.0, 1.0),
+        vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(0.0, 1.0, 1.0),
+        vec3<f32>(0.0, 1.0, 0.0), vec3<f32>(1.0, 1.0, 0.0), vec3<f32>(1.0, 1.0, 1.0), vec3<f32>(0.0, 1.0, 1.0),
+        vec3<f32>(0.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 1.0), vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, 0.0, 0.0)
+    );
+
+    return corners[face * 4u + corner];
+}
+
+//The same as face_ambient_occlusion in chunk.rs
+fn ambient_occlusion(front: vec3<i32>, normal: vec3<i32>, corner: vec3<f32>) -> f32 {
+    var brightness = array<f32, 4>(0.4, 0.6, 0.8, 1.0);
+
+    let towards_corner = select(vec3<i32>(-1), vec3<i32>(1), corner > vec3<f32>(0.5));
+
+    //The two axes along the face
+    var u = vec3<i32>(towards_corner.x, 0, 0);
+    var v = vec3<i32>(0, towards_corner.y, 0);
+
+    if (normal.x != 0) {
+        u = vec3<i32>(0, 0, towards_corner.z);
+    } else if (normal.y != 0) {
+        v = vec3<i32>(0, 0, towards_corner.z);
+    }
+
+    let side_u = occludes(front + u);
+    let side_v = occludes(front + v);
+
+    //The diagonal block can't be seen past two sides
+    var level = 0;
+    if (!(side_u && side_v)) {
+        level = 3 - select(0, 1, side_u) - select(0, 1, side_v) - select(0, 1, occludes(front + u + v));
+    }
+
+    return brightness[level];
+}
+
+fn write_vertex(
+    vertex: u32,
+    position: vec3<f32>,
+    tex_coords: vec2<f32>,
+    lightmap: vec2<f32>,
+    normal: vec3<i32>,
+    brightness: f32,
+    uv_offset: u32
+) {
+    let base = vertex * VERTEX_FLOATS;
+
+    vertices[base] = position.x;
+    vertices[base + 1u] = position.y;
+    vertices[base + 2u] = position.z;
+    vertices[base + 3u] = tex_coords.x;
+    vertices[base + 4u] = tex_coords.y;
+    vertices[base + 5u] = lightmap.x;
+    vertices[base + 6u] = lightmap.y;
+    vertices[base + 7u] = f32(normal.x);
+    vertices[base + 8u] = f32(normal.y);
+    vertices[base + 9u] = f32(normal.z);
+    vertices[base + 10u] = 0.0;
+    vertices[base + 11u] = brightness;
+    vertices[base + 12u] = brightness;
+    vertices[base + 13u] = brightness;
+    vertices[base + 14u] = 1.0;
+    vertices[base + 15u] = 0.0;
+    vertices[base + 16u] = 0.0;
+    vertices[base + 17u] = 0.0;
+    vertices[base + 18u] = 0.0;
+    vertices[base + 19u] = bitcast<f32>(uv_offset);
+    //No emissive overlay
+    vertices[base + 20u] = -1.0;
+    vertices[base + 21u] = -1.0;
+    //Faces aren't merged, so they don't repeat their texture
+    vertices[base + 22u] = 0.0;
+    vertices[base + 23u] = 0.0;
+    vertices[base + 24u] = 0.0;
+    vertices[base + 25u] = 0.0;
+}
+
+@compute @workgroup_size(64)
+fn mesh(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= 16u * 16u * u32(HEIGHT)) {
+        return;
+    }
+
+    let block = vec3<i32>(i32(id.x % 16u), i32(id.x / 256u), i32((id.x / 16u) % 16u));
+    let entry = palette[block_at(block) & 0xffffu];
+
+    if ((entry.flags & 1u) == 0u) {
+        return;
+    }
+
+    for (var face = 0u; face < 6u; face = face + 1u) {
+        if ((entry.flags & (4u << face)) == 0u) {
+            continue;
+        }
+
+        let normal = face_normal(face);
+        let front = block + normal;
+
+        if (occludes(front)) {
+            continue;
+        }
+
+        let quad = atomicAdd(&quad_count, 1u);
+
+        //Too many faces, the chunk is baked on the CPU instead
+        if (quad >= params.max_quads) {
+            continue;
+        }
+
+        //Cube faces are lit by the block in front of them
+        let lightmap = lightmap_coords(front);
+
+        for (var corner = 0u; corner < 4u; corner = corner + 1u) {
+            let position = face_corner(face, corner);
+
+            var brightness = 1.0;
+            if (params.ambient_occlusion != 0u) {
+                brightness = ambient_occlusion(front, normal, position);
+            }
+
+            let corner_pair = entry.tex_coords[face * 2u + corner / 2u];
+            let tex_coords = select(corner_pair.xy, corner_pair.zw, corner % 2u == 1u);
+
+            write_vertex(
+                quad * 4u + corner,
+                vec3<f32>(block) + position,
+                tex_coords,
+                lightmap,
+                normal,
+                brightness,
+                entry.animation_uv_offsets[face / 4u][face % 4u]
+            );
+        }
+
+        let base = quad * 6u;
+        let first = quad * 4u;
+
+        indices[base] = first;
+        indices[base + 1u] = first + 1u;
+        indices[base + 2u] = first + 2u;
+        indices[base + 3u] = first + 2u;
+        indices[base + 4u] = first + 3u;
+        indices[base + 5u] = first;
+    }
+}
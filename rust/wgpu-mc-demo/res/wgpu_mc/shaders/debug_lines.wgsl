@@ -1,26 +1,31 @@
 struct Uniforms {
-    view_proj: mat4x4<f32>
+    view_proj: mat4x4<f32>,
 };
 
 @group(0) @binding(0)
 var<uniform> uniform_data: Uniforms;
 
+struct PushConstants {
+    //Moves the lines towards the camera so they don't Z-fight with the terrain they run along
+    line_depth_offset: f32
+}
+
+var<push_constant> push_constants: PushConstants;
+
 struct VertexResult {
     @builtin(position) pos: vec4<f32>,
     @location(0) color: vec3<f32>
 };
 
 @vertex
-fn vs_main(
+fn vert(
     @location(0) pos_in: vec3<f32>,
     @location(1) color: vec3<f32>
 ) -> VertexResult {
     var vr: VertexResult;
 
     vr.pos = uniform_data.view_proj * vec4<f32>(pos_in, 1.0);
-    vr.pos.x = vr.pos.x * 0.4;
-    vr.pos.y = vr.pos.y * 0.4;
-    vr.pos.z = vr.pos.z + 0.5;
+    vr.pos.z += push_constants.line_depth_offset;
 
     vr.color = color;
 
@@ -28,7 +33,6 @@ fn vs_main(
 }
 
 @fragment
-fn fs_main(in: VertexResult) -> @location(0) vec4<f32> {
+fn frag(in: VertexResult) -> @location(0) vec4<f32> {
     return vec4<f32>(in.color, 1.0);
-   // return textureSample(t_texture, t_sampler, in.tex_coords);
-}
\ No newline at end of file
+}
@@ -1,53 +1,76 @@
-struct Uniforms {
+struct CameraUniform {
     view_proj: mat4x4<f32>
 };
 
-struct Transforms {
-    mats: array<mat4x4<f32>>
+struct EntityInstance {
+    overlay: vec4<f32>,
+    uv_offset: vec2<f32>,
+    first_part: u32,
+    parts: u32
 };
 
+@group(0) @binding(0)
+var<uniform> proj: CameraUniform;
+
+@group(1) @binding(0)
+var<storage> instances: array<EntityInstance>;
+
+@group(1) @binding(1)
+var<storage> transforms: array<mat4x4<f32>>;
+
+@group(1) @binding(2)
+var<storage> tints: array<vec4<f32>>;
+
 @group(2) @binding(0)
-var<uniform> uniform_data: Uniforms;
+var t_texture: texture_2d<f32>;
 
-@group(0) @binding(0)
-var<storage> transforms: Transforms;
+@group(2) @binding(1)
+var t_sampler: sampler;
 
 struct VertexResult {
     @builtin(position) pos: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
-    @location(1) normal: vec3<f32>
+    @location(1) normal: vec3<f32>,
+    @location(2) overlay: vec4<f32>,
+    @location(3) emissive_tex_coords: vec2<f32>,
+    @location(4) tint: vec4<f32>
 };
 
 @vertex
-fn vs_main(
+fn vert(
     @location(0) pos_in: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
     @location(3) part_id: u32,
-    @location(4) entity_index: u32,
-    @location(5) entity_texture_offset: vec2<f32>,
-    @location(6) parts_per: u32
+    @location(4) emissive_tex_coords: vec2<f32>,
+    @builtin(instance_index) instance_index: u32
 ) -> VertexResult {
-    var vr: VertexResult;
+    let instance = instances[instance_index];
+    let part_transform = transforms[instance.first_part + part_id];
 
-    var part_transform_index: u32 = (entity_index * parts_per) + part_id;
-    var part_transform: mat4x4<f32> = transforms.mats[part_transform_index];
-
-    //vr.pos = uniform_data.view_proj * part_transform * vec4<f32>(pos_in, 0.0);
-    vr.pos = uniform_data.view_proj * part_transform * vec4<f32>(pos_in, 1.0);
-    vr.tex_coords = tex_coords + entity_texture_offset;
+    var vr: VertexResult;
+    vr.pos = proj.view_proj * part_transform * vec4<f32>(pos_in, 1.0);
+    vr.tex_coords = tex_coords + instance.uv_offset;
     vr.normal = mat3x3<f32>(part_transform[0].xyz, part_transform[1].xyz, part_transform[2].xyz) * normal;
+    vr.overlay = instance.overlay;
+    vr.tint = tints[instance.first_part + part_id];
+    vr.emissive_tex_coords = select(emissive_tex_coords, emissive_tex_coords + instance.uv_offset, emissive_tex_coords.x >= 0.0);
 
     return vr;
 }
 
-@group(1) @binding(0)
-var t_texture: texture_2d<f32>;
+@fragment
+fn frag(in: VertexResult) -> @location(0) vec4<f32> {
+    let color = textureSample(t_texture, t_sampler, in.tex_coords);
 
-@group(1) @binding(1)
-var t_sampler: sampler;
+    if (color.a < 0.1) {
+        discard;
+    }
 
-@fragment
-fn fs_main(in: VertexResult) -> @location(0) vec4<f32> {
-   return textureSample(t_texture, t_sampler, in.tex_coords);
-}
\ No newline at end of file
+    let emissive = textureSample(t_texture, t_sampler, max(in.emissive_tex_coords, vec2<f32>(0.0)));
+    let emissive_alpha = select(0.0, emissive.a, in.emissive_tex_coords.x >= 0.0);
+
+    let rgb = mix(color.rgb * in.tint.rgb, in.overlay.rgb, in.overlay.a);
+
+    return vec4<f32>(mix(rgb, emissive.rgb, emissive_alpha), color.a * in.tint.a);
+}
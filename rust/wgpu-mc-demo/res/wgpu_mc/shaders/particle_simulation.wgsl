@@ -0,0 +1,89 @@
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+    color: vec4<f32>,
+    uv: vec4<f32>,
+    size: f32,
+    gravity: f32,
+    drag: f32,
+    alive: u32,
+};
+
+struct Params {
+    spawn_count: u32,
+    capacity: u32,
+    delta: f32,
+    padding: u32,
+};
+
+//A stack of the free slots, with the number of them at the top
+struct FreeList {
+    count: atomic<i32>,
+    slots: array<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+@group(0) @binding(1)
+var<storage, read> spawns: array<Particle>;
+
+@group(0) @binding(2)
+var<storage, read_write> particles: array<Particle>;
+
+@group(0) @binding(3)
+var<storage, read_write> free_list: FreeList;
+
+@compute @workgroup_size(64)
+fn emit(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.spawn_count) {
+        return;
+    }
+
+    let free = atomicSub(&free_list.count, 1);
+
+    //Every slot is taken, so the particle is dropped
+    if (free <= 0) {
+        atomicAdd(&free_list.count, 1);
+        return;
+    }
+
+    var particle = spawns[id.x];
+    particle.alive = 1u;
+
+    particles[free_list.slots[free - 1]] = particle;
+}
+
+@compute @workgroup_size(64)
+fn simulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.capacity) {
+        return;
+    }
+
+    var particle = particles[id.x];
+
+    if (particle.alive == 0u) {
+        return;
+    }
+
+    particle.age += params.delta;
+
+    if (particle.age >= particle.lifetime) {
+        particle.alive = 0u;
+        particles[id.x] = particle;
+
+        let free = atomicAdd(&free_list.count, 1);
+        free_list.slots[free] = id.x;
+
+        return;
+    }
+
+    //Like vanilla's, 0.04 blocks per tick per tick with a gravity of 1
+    particle.velocity.y -= 0.04 * particle.gravity * params.delta;
+    particle.position += particle.velocity * params.delta;
+    particle.velocity *= pow(particle.drag, params.delta);
+
+    particles[id.x] = particle;
+}
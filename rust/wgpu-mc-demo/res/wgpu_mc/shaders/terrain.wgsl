@@ -19,8 +19,6 @@ struct ChunkOffset {
 }
 
 struct PushConstants {
-    chunk_x: i32,
-    chunk_z: i32,
     fb_width: f32,
     fb_height: f32
 }
@@ -31,40 +29,74 @@ var<push_constant> push_constants: PushConstants;
 var<uniform> camera_uniform: CameraUniform;
 
 @group(3) @binding(0)
-var<uniform> inverse_camera_uniform: CameraUniform;
+var lightmap_texture: texture_2d<f32>;
+
+@group(3) @binding(1)
+var lightmap_sampler: sampler;
 
 struct VertexResult {
-    @builtin(position) pos: vec4<f32>,
+    //The depth pre-pass and the pass after it have to compute the exact same depth, see WmRenderer::depth_prepass
+    @builtin(position) @invariant pos: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) tex_coords2: vec2<f32>,
     @location(2) blend: f32,
     @location(3) normal: vec3<f32>,
-    @location(4) world_pos: vec3<f32>
+    @location(4) world_pos: vec3<f32>,
+    @location(5) color: vec4<f32>,
+    @location(6) lightmap_coords: vec2<f32>,
+    @location(7) emissive_tex_coords: vec2<f32>,
+    @location(8) @interpolate(flat) region: vec4<f32>
 //    @location(4) screen_pos: vec4<f32>
 };
 
 @vertex
 fn vert(
+#ifdef PACKED_VERTICES
+    //In 64ths of a block, offset by 8 blocks, see PackedVertex
+    @location(0) packed_pos: vec4<u32>,
+#else
     @location(0) pos_in: vec3<f32>,
+#endif
     @location(1) tex_coords: vec2<f32>,
-    @location(2) normal: vec3<f32>,
-    @location(6) uv_offset: u32
+    @location(2) lightmap_coords: vec2<f32>,
+    @location(3) normal: vec4<f32>,
+    @location(4) color: vec4<f32>,
+    @location(6) uv_offset: u32,
+    @location(8) emissive_tex_coords: vec2<f32>,
+    @location(9) region: vec4<f32>,
+    //Relative to the chunk offset, one per drawn chunk
+    @location(7) chunk_position: vec2<i32>
 ) -> VertexResult {
+#ifdef PACKED_VERTICES
+    let pos_in = vec3<f32>(packed_pos.xyz) / 64.0 - 8.0;
+#endif
+
     // var uv = uv_offsets.uvs[uv_offset];
 
     var vr: VertexResult;
 
-    var world_pos = pos_in + vec3<f32>(f32(push_constants.chunk_x) * 16.0, 0.0, f32(push_constants.chunk_z) * 16.0);
+    var world_pos = pos_in + vec3<f32>(f32(chunk_position.x) * 16.0, 0.0, f32(chunk_position.y) * 16.0);
 
     vr.world_pos = world_pos;
     vr.pos = camera_uniform.view_proj * vec4<f32>(world_pos, 1.0);
+#ifdef PACKED_VERTICES
+    //Repeating texture coordinates are divided by the size of a section, see PackedVertex::REPEAT_SCALE
+    let repeats = region.z > region.x || region.w > region.y;
+    vr.tex_coords = select(tex_coords, tex_coords * 16.0, repeats);
+#else
     vr.tex_coords = tex_coords;
-    vr.tex_coords2 = tex_coords;
+#endif
+    vr.tex_coords2 = vr.tex_coords;
+    vr.region = region;
     vr.blend = 1.0;
     // vr.tex_coords = tex_coords + uv.uv1;
     // vr.tex_coords2 = tex_coords + uv.uv2;
     // vr.blend = uv.blend;
-    vr.normal = normal;
+    vr.normal = normal.xyz;
+    vr.lightmap_coords = lightmap_coords;
+    vr.emissive_tex_coords = emissive_tex_coords;
+    //Darkened by ambient occlusion while baking
+    vr.color = color;
 //    vr.screen_pos =
 
     return vr;
@@ -82,18 +114,44 @@ var shadow_texture: texture_depth_2d;
 @group(2) @binding(1)
 var shadow_sampler: sampler;
 
+//Quads merged by greedy meshing repeat their texture once per block. Their coordinates are relative to the region
+//of the atlas the texture is in and wrapped into it, see Vertex::region
+fn atlas_coords(tex_coords: vec2<f32>, region: vec4<f32>) -> vec2<f32> {
+    let repeats = region.z > region.x || region.w > region.y;
+
+    return select(tex_coords, mix(region.xy, region.zw, fract(tex_coords)), repeats);
+}
+
 @fragment
 fn frag(
     in: VertexResult
 ) -> @location(0) vec4<f32> {
-    let col1 = textureSample(t_texture, t_sampler, in.tex_coords);
-    let col2 = textureSample(t_texture, t_sampler, in.tex_coords2);
+    let uv = atlas_coords(in.tex_coords, in.region);
+    let col1 = textureSample(t_texture, t_sampler, uv);
+    let col2 = textureSample(t_texture, t_sampler, atlas_coords(in.tex_coords2, in.region));
 
     let col = mix(col1, col2, in.blend);
 
-    let uv = in.pos.xy / vec2<f32>(push_constants.fb_width, push_constants.fb_height);
+    let screen_uv = in.pos.xy / vec2<f32>(push_constants.fb_width, push_constants.fb_height);
 
-//    let depth = textureSample(shadow_texture, shadow_sampler, uv);
+//    let depth = textureSample(shadow_texture, shadow_sampler, screen_uv);
 
-    return col1;
-}
\ No newline at end of file
+    let light = textureSample(lightmap_texture, lightmap_sampler, in.lightmap_coords);
+
+    //The emissive overlay is drawn on top without being darkened, negative coordinates mean there isn't one
+    let emissive = textureSample(t_texture, t_sampler, max(in.emissive_tex_coords, vec2<f32>(0.0)));
+    let emissive_alpha = select(0.0, emissive.a, in.emissive_tex_coords.x >= 0.0);
+    let rgb = mix(col1.rgb * in.color.rgb * light.rgb, emissive.rgb, emissive_alpha);
+
+#ifdef CUTOUT
+    //Cutout textures are either fully opaque or fully transparent
+    if (col1.a < 0.5) {
+        discard;
+    }
+
+    return vec4<f32>(rgb, 1.0);
+#else
+    //Translucent terrain is blended with alpha blending, and drawn after the solid and cutout terrain
+    return vec4<f32>(rgb, col1.a);
+#endif
+}
@@ -1,4 +1,5 @@
 use cgmath::{Point3, Vector3};
+use wgpu_mc::render::reverse_z::reverse_z_projection;
 
 #[derive(Debug, Copy, Clone)]
 pub struct Camera {
@@ -10,6 +11,8 @@ pub struct Camera {
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    /// Has to match [wgpu_mc::WmRenderer::reverse_z]
+    pub reverse_z: bool,
 }
 
 impl Camera {
@@ -24,6 +27,7 @@ impl Camera {
             fovy: 110.0,
             znear: 0.1,
             zfar: 1000.0,
+            reverse_z: false,
         }
     }
 
@@ -45,6 +49,13 @@ impl Camera {
     }
 
     pub fn build_perspective_matrix(&self) -> cgmath::Matrix4<f32> {
-        cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+        let projection =
+            cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+
+        if self.reverse_z {
+            reverse_z_projection(projection)
+        } else {
+            projection
+        }
     }
 }
@@ -3,14 +3,50 @@ use std::sync::Arc;
 use std::time::Instant;
 
 use wgpu_mc::mc::block::{BlockstateKey, ChunkBlockState};
-use wgpu_mc::mc::chunk::{BlockStateProvider, Chunk};
+use wgpu_mc::mc::chunk::{BlockStateProvider, Chunk, CHUNK_HEIGHT, CHUNK_WIDTH};
 use wgpu_mc::mc::MinecraftState;
 use wgpu_mc::minecraft_assets::schemas::blockstates::multipart::StateValue;
 
-use wgpu_mc::render::pipeline::BLOCK_ATLAS;
+use wgpu_mc::render::atlas::AtlasKind;
 use wgpu_mc::WmRenderer;
 
-struct SimpleBlockstateProvider(Arc<MinecraftState>, BlockstateKey);
+pub struct SimpleBlockstateProvider(Arc<MinecraftState>, BlockstateKey);
+
+impl SimpleBlockstateProvider {
+    /// The world-space collision boxes of the block at the given position. Only chunk [0, 0] is filled.
+    pub fn collision_boxes(&self, x: i32, y: i32, z: i32) -> Vec<[f32; 6]> {
+        let width = CHUNK_WIDTH as i32;
+
+        if !(0..width).contains(&x)
+            || !(0..width).contains(&z)
+            || !(0..CHUNK_HEIGHT as i32).contains(&y)
+        {
+            return Vec::new();
+        }
+
+        match self.get_state(x, y as i16, z) {
+            ChunkBlockState::Air => Vec::new(),
+            ChunkBlockState::State(key) => self
+                .0
+                .block_manager
+                .read()
+                .get_shape(key.block)
+                .aabbs()
+                .into_iter()
+                .map(|aabb| {
+                    [
+                        aabb[0] + x as f32,
+                        aabb[1] + y as f32,
+                        aabb[2] + z as f32,
+                        aabb[3] + x as f32,
+                        aabb[4] + y as f32,
+                        aabb[5] + z as f32,
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
 
 impl BlockStateProvider for SimpleBlockstateProvider {
     fn get_state(&self, _x: i32, _y: i16, _z: i32) -> ChunkBlockState {
@@ -28,16 +64,9 @@ impl Debug for SimpleBlockstateProvider {
     }
 }
 
-pub fn make_chunks(wm: &WmRenderer) -> Chunk {
+pub fn make_chunks(wm: &WmRenderer) -> (Chunk, SimpleBlockstateProvider) {
     let bm = wm.mc.block_manager.read();
-    let atlas = wm
-        .mc
-        .texture_manager
-        .atlases
-        .load()
-        .get(BLOCK_ATLAS)
-        .unwrap()
-        .load();
+    let atlas = wm.mc.texture_manager.atlas(AtlasKind::Block);
 
     let (index, _, anvil) = bm.blocks.get_full("minecraft:anvil").unwrap();
 
@@ -70,5 +99,5 @@ pub fn make_chunks(wm: &WmRenderer) -> Chunk {
         Instant::now().duration_since(time).as_micros()
     );
 
-    chunk
+    (chunk, provider)
 }
@@ -0,0 +1,185 @@
+use cgmath::{InnerSpace, Point3, Vector3};
+use winit::event::{ElementState, KeyboardInput, VirtualKeyCode};
+
+use crate::camera::Camera;
+
+const GRAVITY: f32 = 32.0;
+const JUMP_VELOCITY: f32 = 9.0;
+const WALK_SPEED: f32 = 4.3;
+const SPRINT_SPEED: f32 = 5.6;
+/// Falling speed is capped, like in Minecraft
+const TERMINAL_VELOCITY: f32 = 78.4;
+
+const PLAYER_WIDTH: f32 = 0.6;
+const PLAYER_HEIGHT: f32 = 1.8;
+const EYE_HEIGHT: f32 = 1.62;
+
+/// Reads keyboard and mouse input and moves the [Camera] around like a Minecraft player would.
+/// WASD to move, Space to jump, Shift to sprint.
+pub struct PlayerController {
+    /// The position of the player's feet
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    /// Radians of rotation per pixel of mouse movement
+    pub sensitivity: f32,
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    jump: bool,
+    sprint: bool,
+}
+
+impl PlayerController {
+    pub fn new(position: Point3<f32>, sensitivity: f32) -> Self {
+        Self {
+            position,
+            velocity: Vector3::new(0.0, 0.0, 0.0),
+            on_ground: false,
+            sensitivity,
+            forward: false,
+            back: false,
+            left: false,
+            right: false,
+            jump: false,
+            sprint: false,
+        }
+    }
+
+    /// Returns true if the key is one the controller uses
+    pub fn process_keyboard(&mut self, input: &KeyboardInput) -> bool {
+        let pressed = input.state == ElementState::Pressed;
+
+        let key = match input.virtual_keycode {
+            Some(VirtualKeyCode::W) => &mut self.forward,
+            Some(VirtualKeyCode::S) => &mut self.back,
+            Some(VirtualKeyCode::A) => &mut self.left,
+            Some(VirtualKeyCode::D) => &mut self.right,
+            Some(VirtualKeyCode::Space) => &mut self.jump,
+            Some(VirtualKeyCode::LShift) | Some(VirtualKeyCode::RShift) => &mut self.sprint,
+            _ => return false,
+        };
+
+        *key = pressed;
+
+        true
+    }
+
+    pub fn process_mouse(&mut self, camera: &mut Camera, delta: (f64, f64)) {
+        let max_pitch = 89.0f32.to_radians();
+
+        camera.yaw += delta.0 as f32 * self.sensitivity;
+        camera.pitch =
+            (camera.pitch - delta.1 as f32 * self.sensitivity).clamp(-max_pitch, max_pitch);
+    }
+
+    /// Integrate the velocity and resolve collisions, then move the camera to the player's eyes.
+    ///
+    /// `collision_boxes` returns the AABBs of the block at the given position, in world space, formatted as
+    /// `[min_x, min_y, min_z, max_x, max_y, max_z]`
+    pub fn update(
+        &mut self,
+        camera: &mut Camera,
+        delta_time: f32,
+        collision_boxes: impl Fn(i32, i32, i32) -> Vec<[f32; 6]>,
+    ) {
+        let forward = Vector3::new(camera.yaw.cos(), 0.0, camera.yaw.sin());
+        let right = Vector3::new(-camera.yaw.sin(), 0.0, camera.yaw.cos());
+
+        let mut wish_direction = Vector3::new(0.0, 0.0, 0.0);
+
+        if self.forward {
+            wish_direction += forward;
+        }
+        if self.back {
+            wish_direction -= forward;
+        }
+        if self.right {
+            wish_direction += right;
+        }
+        if self.left {
+            wish_direction -= right;
+        }
+
+        let speed = if self.sprint {
+            SPRINT_SPEED
+        } else {
+            WALK_SPEED
+        };
+
+        let horizontal = if wish_direction.magnitude2() > 0.0 {
+            wish_direction.normalize() * speed
+        } else {
+            wish_direction
+        };
+
+        self.velocity.x = horizontal.x;
+        self.velocity.z = horizontal.z;
+
+        if self.jump && self.on_ground {
+            self.velocity.y = JUMP_VELOCITY;
+        }
+
+        self.velocity.y = (self.velocity.y - GRAVITY * delta_time).max(-TERMINAL_VELOCITY);
+
+        let movement = self.velocity * delta_time;
+
+        self.on_ground = false;
+
+        // Resolve each axis separately so that the player can slide along walls
+        for axis in [1, 0, 2] {
+            let mut new_position = self.position;
+            new_position[axis] += movement[axis];
+
+            if self.collides(new_position, &collision_boxes) {
+                if axis == 1 && movement.y < 0.0 {
+                    self.on_ground = true;
+                }
+
+                self.velocity[axis] = 0.0;
+            } else {
+                self.position = new_position;
+            }
+        }
+
+        camera.position = self.position + Vector3::new(0.0, EYE_HEIGHT, 0.0);
+    }
+
+    fn collides(
+        &self,
+        position: Point3<f32>,
+        collision_boxes: &impl Fn(i32, i32, i32) -> Vec<[f32; 6]>,
+    ) -> bool {
+        let half_width = PLAYER_WIDTH / 2.0;
+
+        let min = [position.x - half_width, position.y, position.z - half_width];
+        let max = [
+            position.x + half_width,
+            position.y + PLAYER_HEIGHT,
+            position.z + half_width,
+        ];
+
+        // Fences are 1.5 blocks tall, so check one block below the player's feet as well
+        for x in min[0].floor() as i32..=max[0].floor() as i32 {
+            for y in min[1].floor() as i32 - 1..=max[1].floor() as i32 {
+                for z in min[2].floor() as i32..=max[2].floor() as i32 {
+                    let overlaps = collision_boxes(x, y, z).iter().any(|aabb| {
+                        min[0] < aabb[3]
+                            && max[0] > aabb[0]
+                            && min[1] < aabb[4]
+                            && max[1] > aabb[1]
+                            && min[2] < aabb[5]
+                            && max[2] > aabb[2]
+                    });
+
+                    if overlaps {
+                        return true;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
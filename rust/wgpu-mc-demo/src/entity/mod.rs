@@ -163,10 +163,12 @@ pub fn describe_entity(wm: &WmRenderer) -> (Arc<Entity>, EntityInstances) {
     let test_entity_atlas = Atlas::new(&wm.wgpu_state, &wm.pipelines.load_full(), false);
 
     //Allocate the image with the alex_skin_ns variable as the key
-    test_entity_atlas.allocate(
-        [(&alex_skin_ns, &alex_skin_resource)],
-        &*wm.mc.resource_provider,
-    );
+    test_entity_atlas
+        .allocate(
+            [(&alex_skin_ns, &alex_skin_resource)],
+            &*wm.mc.resource_provider,
+        )
+        .unwrap();
 
     //Uploads the atlas texture to the GPU
     test_entity_atlas.upload(wm);
@@ -176,7 +178,7 @@ pub fn describe_entity(wm: &WmRenderer) -> (Arc<Entity>, EntityInstances) {
     let test_entity = Arc::new(Entity::new(
         entity_root,
         &wm.wgpu_state,
-        test_entity_atlas.bindable_texture.load_full(),
+        test_entity_atlas.bindable_texture.clone(),
     ));
 
     {
@@ -194,12 +196,15 @@ pub fn describe_entity(wm: &WmRenderer) -> (Arc<Entity>, EntityInstances) {
             position: (0.0, 0.0, 0.0),
             looking_yaw: 0.0,
             uv_offset: (0.0, 0.0),
+            overlay: [0.0; 4],
             part_transforms: vec![
                 PartTransform::identity(),
                 PartTransform::identity(),
                 PartTransform::identity(),
                 PartTransform::identity(),
             ],
+            part_tints: Vec::new(),
+            attached_to: None,
         }],
     );
 
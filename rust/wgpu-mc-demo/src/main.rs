@@ -4,9 +4,10 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::camera::Camera;
+use crate::controller::PlayerController;
 use arc_swap::ArcSwap;
 
 use futures::executor::block_on;
@@ -15,15 +16,15 @@ use raw_window_handle::{
     HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle, RawWindowHandle,
 };
 use wgpu_mc::mc::block::{BlockMeshVertex, BlockstateKey};
-use wgpu_mc::mc::chunk::RenderLayer;
+use wgpu_mc::mc::chunk::{RenderLayer, CHUNK_HEIGHT};
 use wgpu_mc::mc::resource::{ResourcePath, ResourceProvider};
 use wgpu_mc::render::graph::{CustomResource, ResourceInternal, ShaderGraph};
 use wgpu_mc::render::pipeline::Vertex;
-use wgpu_mc::render::shaderpack::{Mat4, Mat4ValueOrMult};
-use wgpu_mc::util::BindableBuffer;
+use wgpu_mc::render::shaderpack::pack::{DirectoryResourceProvider, ShaderPack};
+use wgpu_mc::render::shaderpack::{Mat4ValueOrMult, ShaderPackConfig};
+use wgpu_mc::util::UniformSlot;
 
-use wgpu_mc::wgpu::BufferUsages;
-use wgpu_mc::{wgpu, HasWindowSize, WindowSize, WmRenderer};
+use wgpu_mc::{wgpu, HasWindowSize, WindowSize, WmConfig, WmRenderer};
 use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::Window;
@@ -33,6 +34,7 @@ use crate::entity::describe_entity;
 
 mod camera;
 mod chunk;
+mod controller;
 mod entity;
 
 struct FsResourceProvider {
@@ -46,6 +48,10 @@ impl ResourceProvider for FsResourceProvider {
 
         fs::read(real_path).ok()
     }
+
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.asset_root.join(id.0.replace(':', "/")).is_file()
+    }
 }
 
 struct WinitWindowWrapper {
@@ -100,9 +106,11 @@ fn main() {
         .join("assets")
         .join("minecraft");
 
-    let wgpu_state = block_on(WmRenderer::init_wgpu(&wrapper, false));
+    let config = WmConfig::default();
+
+    let wgpu_state = block_on(WmRenderer::init_wgpu(&wrapper, false, &config)).unwrap();
 
-    let wm = WmRenderer::new(wgpu_state, rsp);
+    let wm = WmRenderer::with_config(wgpu_state, rsp, config).unwrap();
 
     wm.init();
 
@@ -133,6 +141,7 @@ fn main() {
 
     let now = Instant::now();
 
+    wm.mc.load_connected_textures();
     wm.mc.bake_blocks(&wm, blocks.iter().map(|(a, b)| (a, b)));
 
     let end = Instant::now();
@@ -166,8 +175,12 @@ impl RenderLayer for TerrainLayer {
             lightmap_coords: [0.0, 0.0],
             normal: vert.normal,
             color: [1.0, 1.0, 1.0, 1.0],
+            //Filled in for the whole face once it's baked
             tangent: [0.0, 0.0, 0.0, 0.0],
             uv_offset: vert.animation_uv_offset,
+            emissive_tex_coords: vert.emissive_tex_coords,
+            //Set for quads merged by greedy meshing, which repeat their texture
+            region: [0.0; 4],
         }
     }
 
@@ -177,14 +190,15 @@ impl RenderLayer for TerrainLayer {
 }
 
 fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
-    let (_entity, _instances) = describe_entity(&wm);
+    let (_entity, instances) = describe_entity(&wm);
+    wm.entities.set_instances(vec![Arc::new(instances)]);
 
     wm.pipelines
         .load_full()
         .chunk_layers
         .store(Arc::new(vec![Box::new(TerrainLayer)]));
 
-    let chunk = make_chunks(&wm);
+    let (chunk, world) = make_chunks(&wm);
 
     {
         wm.mc
@@ -195,16 +209,14 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
     }
 
     let mut frame_start = Instant::now();
+    let mut last_tick = Instant::now();
 
-    let mut forward = 0.0;
     let mut spin: f32 = 0.0;
     let mut _frame: u32 = 0;
 
-    let pack = serde_yaml::from_str(
-        &wm.mc
-            .resource_provider
-            .get_string(&ResourcePath("wgpu_mc:graph.yaml".into()))
-            .unwrap(),
+    let pack = ShaderPackConfig::load(
+        &*wm.mc.resource_provider,
+        &ResourcePath("wgpu_mc:graph.yaml".into()),
     )
     .unwrap();
     let mut resources = HashMap::new();
@@ -215,25 +227,10 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
     };
 
     let mut camera = Camera::new(aspect);
-    let projection_bindable = Arc::new(BindableBuffer::new(
-        &wm,
-        &[0u8; 64],
-        BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        "matrix",
-    ));
-    let view_bindable = Arc::new(BindableBuffer::new(
-        &wm,
-        &[0u8; 64],
-        BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        "matrix",
-    ));
-    let rotation_bindable = Arc::new(BindableBuffer::new(
-        &wm,
-        &[0u8; 64],
-        BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-        "matrix",
-    ));
-
+    let mut controller = PlayerController::new(
+        cgmath::Point3::new(8.0, CHUNK_HEIGHT as f32 + 1.0, 8.0),
+        0.01,
+    );
     let view_matrix = Arc::new(RwLock::new(camera.build_view_matrix()));
     let projection_matrix = Arc::new(RwLock::new(camera.build_perspective_matrix()));
     let rotation_matrix = Arc::new(RwLock::new(camera.build_rotation_matrix()));
@@ -247,7 +244,7 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     value: [[0.0; 4]; 4],
                 },
                 projection_matrix.clone(),
-                projection_bindable.clone(),
+                Arc::new(UniformSlot::default()),
             )),
         },
     );
@@ -261,7 +258,7 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     value: [[0.0; 4]; 4],
                 },
                 view_matrix.clone(),
-                view_bindable.clone(),
+                Arc::new(UniformSlot::default()),
             )),
         },
     );
@@ -275,72 +272,128 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     value: [[0.0; 4]; 4],
                 },
                 rotation_matrix.clone(),
-                rotation_bindable.clone(),
+                Arc::new(UniformSlot::default()),
             )),
         },
     );
 
     let mut graph = ShaderGraph::new(pack, resources, HashMap::new());
 
-    graph.init(&wm, None, None);
+    if let Err(error) = graph.init(&wm, None, None) {
+        log::error!("Couldn't build the builtin pipelines: {error}");
+        return;
+    }
+
+    //A shaderpack in the layout of a resource pack can be passed as the first argument
+    if let Some(directory) = std::env::args().nth(1) {
+        let shader_pack = ShaderPack::new(
+            directory.clone(),
+            Arc::new(DirectoryResourceProvider::new(directory)),
+        );
+
+        if let Err(error) = graph.set_shader_pack(&wm, Some(Arc::new(shader_pack))) {
+            log::error!("Couldn't load the shaderpack: {error}");
+        }
+    }
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
             Event::MainEventsCleared => window.request_redraw(),
+            Event::Suspended => wm.pause(),
+            Event::Resumed => {
+                if let Err(error) = wm.resume(&window) {
+                    log::error!("Couldn't recreate the surface, staying paused: {error}");
+                }
+            }
             Event::WindowEvent {
                 ref event,
                 window_id,
             } if window_id == window.id() => {
+                #[cfg(feature = "egui")]
+                if wm.egui_input(event) {
+                    return;
+                }
+
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Space),
-                            ..
-                        } => {
-                            //Update a block and re-generate the chunk mesh for testing
-
-                            //removed atm
-                        }
-                        KeyboardInput {
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        if let KeyboardInput {
                             state: ElementState::Pressed,
                             virtual_keycode: Some(VirtualKeyCode::Escape),
                             ..
-                        } => {
+                        } = input
+                        {
                             *control_flow = ControlFlow::Exit;
-                        }
-                        KeyboardInput {
+                        } else if let KeyboardInput {
                             state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::W),
+                            virtual_keycode: Some(VirtualKeyCode::F5),
                             ..
-                        } => {
-                            forward = 1.0;
-                        }
-                        KeyboardInput {
-                            state: ElementState::Released,
-                            virtual_keycode: Some(VirtualKeyCode::W),
+                        } = input
+                        {
+                            //Picks up shaders edited while the demo is running
+                            match graph.rebuild_pipelines(&wm) {
+                                Ok(()) => log::info!("Reloaded shaders"),
+                                Err(error) => log::error!("Couldn't reload shaders: {error}"),
+                            }
+                        } else if let KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F6),
                             ..
-                        } => {
-                            forward = 0.0;
-                        }
-                        KeyboardInput {
+                        } = input
+                        {
+                            //Cycles through the MSAA sample counts the device supports
+                            let samples = **wm.msaa_samples.load();
+                            let next = [1, 2, 4, 8]
+                                .into_iter()
+                                .cycle()
+                                .skip_while(|&count| count != samples)
+                                .skip(1)
+                                .find(|&count| wm.supports_msaa_samples(count))
+                                .unwrap();
+
+                            wm.set_msaa_samples(next);
+                            log::info!("MSAA: {next}x");
+                        } else if let KeyboardInput {
                             state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::S),
+                            virtual_keycode: Some(VirtualKeyCode::F7),
                             ..
-                        } => {
-                            forward = -1.0;
-                        }
-                        KeyboardInput {
-                            state: ElementState::Released,
-                            virtual_keycode: Some(VirtualKeyCode::S),
+                        } = input
+                        {
+                            let depth_prepass = !**wm.depth_prepass.load();
+
+                            wm.depth_prepass.store(Arc::new(depth_prepass));
+                            log::info!("Depth pre-pass: {depth_prepass}");
+                        } else if let KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F8),
+                            ..
+                        } = input
+                        {
+                            let polygon_mode = match **wm.debug_polygon_mode.load() {
+                                wgpu::PolygonMode::Fill => wgpu::PolygonMode::Line,
+                                _ => wgpu::PolygonMode::Fill,
+                            };
+
+                            if wm.set_debug_polygon_mode(polygon_mode) {
+                                log::info!("Polygon mode: {polygon_mode:?}");
+                            } else {
+                                log::warn!("The device can't draw wireframes");
+                            }
+                        } else if let KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(VirtualKeyCode::F9),
                             ..
-                        } => {
-                            forward = 0.0;
+                        } = input
+                        {
+                            camera.reverse_z = !camera.reverse_z;
+
+                            wm.reverse_z.store(Arc::new(camera.reverse_z));
+                            log::info!("Reverse-Z: {}", camera.reverse_z);
+                        } else {
+                            controller.process_keyboard(input);
                         }
-                        _ => {}
-                    },
+                    }
                     WindowEvent::Resized(physical_size) => {
                         wm.resize(WindowSize {
                             width: physical_size.width,
@@ -356,10 +409,12 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     _ => {}
                 }
             }
-            Event::RedrawRequested(_) => {
+            Event::RedrawRequested(_) if !wm.is_paused() => {
                 let frame_time = Instant::now().duration_since(frame_start).as_secs_f32();
 
-                camera.position += camera.get_direction() * forward * frame_time * 40.0;
+                controller.update(&mut camera, frame_time, |x, y, z| {
+                    world.collision_boxes(x, y, z)
+                });
 
                 {
                     *projection_matrix.write() = camera.build_perspective_matrix();
@@ -367,37 +422,27 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     *rotation_matrix.write() = camera.build_rotation_matrix();
                 }
 
-                let proj_mat: Mat4 = camera.build_perspective_matrix().into();
-                let view_mat: Mat4 = camera.build_view_matrix().into();
-                let rot_mat: Mat4 = camera.build_rotation_matrix().into();
-
-                wm.wgpu_state.queue.write_buffer(
-                    &projection_bindable.buffer,
-                    0,
-                    bytemuck::cast_slice(&proj_mat),
-                );
-
-                wm.wgpu_state.queue.write_buffer(
-                    &view_bindable.buffer,
-                    0,
-                    bytemuck::cast_slice(&view_mat),
-                );
-
-                wm.wgpu_state.queue.write_buffer(
-                    &rotation_bindable.buffer,
-                    0,
-                    bytemuck::cast_slice(&rot_mat),
-                );
-
                 spin += 0.5;
                 _frame += 1;
 
+                //Textures are animated at the 20 ticks per second of the game
+                while last_tick.elapsed() >= Duration::from_millis(50) {
+                    wm.tick_texture_animations();
+                    last_tick += Duration::from_millis(50);
+                }
+
+                wm.mc.chunks.defragment(&wm);
+
                 let surface_state = wm.wgpu_state.surface.read();
-                let surface = surface_state.0.as_ref().unwrap();
+
+                //The surface is gone while the application is suspended
+                let Some(surface) = surface_state.0.as_ref() else {
+                    return;
+                };
                 let texture = surface.get_current_texture().unwrap();
                 let view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
                     label: None,
-                    format: Some(wgpu::TextureFormat::Bgra8Unorm),
+                    format: Some(surface_state.1.format),
                     dimension: Some(wgpu::TextureViewDimension::D2),
                     aspect: Default::default(),
                     base_mip_level: 0,
@@ -406,6 +451,9 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                     array_layer_count: None,
                 });
 
+                #[cfg(feature = "egui")]
+                wm.egui.run(&wm, &window, &graph, |_| {});
+
                 let _ = wm.render(&graph, &view, &surface_state.1);
 
                 texture.present();
@@ -418,8 +466,7 @@ fn begin_rendering(event_loop: EventLoop<()>, window: Window, wm: WmRenderer) {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
             } => {
-                camera.yaw += (delta.0 / 100.0) as f32;
-                camera.pitch -= (delta.1 / 100.0) as f32;
+                controller.process_mouse(&mut camera, delta);
             }
             _ => {}
         }
@@ -0,0 +1,51 @@
+use wgpu_mc::mc::biome::Biome;
+use wgpu_mc::mc::chunk::CHUNK_SECTIONS_PER;
+
+/// Minecraft stores biomes in cells of 4x4x4 blocks, 64 of them per section
+pub const SECTION_BIOME_CELLS: usize = 64;
+
+/// The colors of the biome of every cell of a chunk, as Minecraft's `Biome` resolves them. Grass and foliage
+/// colors already come from the colormaps and the biome's own modifiers on the Java side, so they replace the
+/// lookup of [wgpu_mc::mc::biome::BlockColors].
+#[derive(Clone, Debug)]
+pub struct ChunkBiomes {
+    //Grass, foliage and water color of each cell as 0xRRGGBB
+    colors: Box<[[i32; 3]]>,
+}
+
+impl ChunkBiomes {
+    /// [None] if there aren't three colors for each cell of a chunk
+    #[must_use]
+    pub fn new(colors: &[i32]) -> Option<Self> {
+        if colors.len() != SECTION_BIOME_CELLS * CHUNK_SECTIONS_PER * 3 {
+            return None;
+        }
+
+        Some(Self {
+            colors: colors
+                .chunks_exact(3)
+                .map(|cell| [cell[0], cell[1], cell[2]])
+                .collect(),
+        })
+    }
+
+    /// The biome of the cell the position is in, only the chunk-local part of x and z is used
+    pub fn get(&self, x: i32, y: i16, z: i32) -> Biome {
+        let section = y as usize / 16;
+        //Same order as the biome PalettedContainer of a ChunkSection
+        let cell = ((((y as usize & 0xf) >> 2) << 2 | ((z as usize & 0xf) >> 2)) << 2)
+            | ((x as usize & 0xf) >> 2);
+        let [grass, foliage, water] = self.colors[section * SECTION_BIOME_CELLS + cell];
+
+        Biome {
+            water_color: rgb(water),
+            grass_color: Some(rgb(grass)),
+            foliage_color: Some(rgb(foliage)),
+            ..Biome::PLAINS
+        }
+    }
+}
+
+fn rgb(color: i32) -> [u8; 3] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8]
+}
@@ -1,14 +1,8 @@
 use std::{collections::HashMap, sync::Arc};
 
-use once_cell::sync::OnceCell;
 use serde::Deserialize;
 
-use wgpu_mc::{
-    mc::entity::{Cuboid, CuboidUV, EntityPart, PartTransform},
-    render::atlas::Atlas,
-};
-
-pub static ENTITY_ATLAS: OnceCell<Arc<Atlas>> = OnceCell::new();
+use wgpu_mc::mc::entity::{Cuboid, CuboidUV, EntityPart, PartTransform};
 
 #[derive(Debug, Deserialize)]
 pub struct ModelCuboidData {
@@ -7,7 +7,6 @@ use wgpu_mc::texture::{BindableTexture, TextureHandle};
 
 use std::sync::Arc;
 
-use arc_swap::ArcSwap;
 use bytemuck::{Pod, Zeroable};
 use cgmath::{Matrix4, SquareMatrix};
 use once_cell::sync::Lazy;
@@ -16,16 +15,16 @@ use std::mem::{align_of, replace};
 use std::ops::Range;
 use wgpu_mc::mc::chunk::ChunkPos;
 use wgpu_mc::render::graph::{
-    bind_uniforms, set_push_constants, CustomResource, GeometryCallback, ResourceInternal,
-    ShaderGraph, TextureResource,
-};
-use wgpu_mc::render::shaderpack::{Mat4, Mat4ValueOrMult, PipelineConfig};
-use wgpu_mc::util::{BindableBuffer, WmArena};
-use wgpu_mc::wgpu::{
-    vertex_attr_array, Buffer, BufferUsages, IndexFormat, RenderPass, SurfaceConfiguration,
+    bind_uniforms, CustomResource, GeometryCallback, PushConstantValues, ShaderGraph,
 };
+use wgpu_mc::render::shaderpack::{Mat4, PipelineConfig};
+use wgpu_mc::util::{UniformSlot, WmArena};
+use wgpu_mc::wgpu::{vertex_attr_array, Buffer, IndexFormat, RenderPass, SurfaceConfiguration};
 use wgpu_mc::{wgpu, WmRenderer};
 
+const ELECTRUM_TEXTURE: &str = "wm_electrum_gl_texture";
+const ELECTRUM_MATRIX: &str = "wm_electrum_mat4";
+
 pub static GL_ALLOC: Lazy<RwLock<HashMap<u32, GlTexture>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 pub static GL_COMMANDS: Lazy<RwLock<(Vec<GLCommand>, Vec<GLCommand>)>> =
@@ -214,46 +213,41 @@ enum PipelineState {
     PositionColorUvLight,
 }
 
-fn augment_resources<'arena: 'resources, 'resources>(
+/// Binds the texture and matrix of a draw. The bind groups already exist, the texture's since it was uploaded and
+/// the matrix's in [WmRenderer::uniforms], so nothing is created per draw.
+fn bind_draw<'pass>(
     wm: &WmRenderer,
-    resources: &'resources HashMap<String, CustomResource>,
-    arena: &'arena WmArena<'arena>,
+    config: &PipelineConfig,
+    arena: &WmArena<'pass>,
+    render_pass: &mut RenderPass<'pass>,
     texture: Arc<BindableTexture>,
     matrix: Mat4,
-) -> &'arena HashMap<&'arena String, &'resources CustomResource> {
-    arena.alloc(
-        resources
-            .into_iter()
-            .chain([
-                (
-                    &*arena.alloc("wm_electrum_gl_texture".into()),
-                    &*arena.alloc(CustomResource {
-                        update: None,
-                        data: Arc::new(ResourceInternal::Texture(
-                            TextureResource::Bindable(Arc::new(ArcSwap::new(texture))),
-                            false,
-                        )),
-                    }),
-                ),
-                (
-                    &*arena.alloc("wm_electrum_mat4".into()),
-                    &*arena.alloc(CustomResource {
-                        update: None,
-                        data: Arc::new(ResourceInternal::Mat4(
-                            Mat4ValueOrMult::Value { value: matrix },
-                            Arc::new(RwLock::new(matrix.into())),
-                            Arc::new(BindableBuffer::new(
-                                wm,
-                                bytemuck::cast_slice(&matrix),
-                                BufferUsages::UNIFORM,
-                                "matrix",
-                            )),
-                        )),
-                    }),
-                ),
-            ])
-            .collect(),
-    )
+) {
+    for (index, uniform) in &config.uniforms {
+        match &uniform[..] {
+            ELECTRUM_TEXTURE => {
+                let texture = arena.alloc(texture.clone());
+
+                render_pass.set_bind_group(*index as u32, &texture.bind_group, &[]);
+            }
+            ELECTRUM_MATRIX => {
+                let slot = UniformSlot::default();
+                slot.write(wm, bytemuck::cast_slice(&matrix));
+
+                //Only missing if the matrix didn't fit a slot, which has been logged
+                if let Some(allocation) = slot.get() {
+                    let allocation = arena.alloc(allocation);
+
+                    render_pass.set_bind_group(
+                        *index as u32,
+                        &allocation.block.bind_group,
+                        &[allocation.offset],
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
 }
 
 pub struct BufferPool {
@@ -291,7 +285,7 @@ impl GeometryCallback for ElectrumGeometry {
         resources: &'resource HashMap<String, CustomResource>,
         arena: &'resource WmArena<'resource>,
         surface_config: &SurfaceConfiguration,
-        chunk_offset: ChunkPos,
+        _chunk_offset: ChunkPos,
     ) {
         let mut buffer_pool = BufferPool { data: Vec::new() };
 
@@ -397,6 +391,14 @@ impl GeometryCallback for ElectrumGeometry {
         //     }
         // }
 
+        //The resources of the graph are the same for every draw
+        bind_uniforms(
+            config,
+            arena.alloc(resources.iter().collect()),
+            arena,
+            render_pass,
+        );
+
         for call in calls {
             match call {
                 DrawCall::Verts(draw) => {
@@ -408,11 +410,22 @@ impl GeometryCallback for ElectrumGeometry {
                         }
                     }
 
-                    let augmented_resources =
-                        augment_resources(wm, &resources, arena, texture, draw.matrix);
-
-                    bind_uniforms(config, augmented_resources, arena, render_pass);
-                    set_push_constants(config, render_pass, None, surface_config, chunk_offset);
+                    bind_draw(wm, config, arena, render_pass, texture, draw.matrix);
+
+                    if let Err(error) = graph.set_push_constants(
+                        wm,
+                        config,
+                        arena,
+                        render_pass,
+                        &PushConstantValues {
+                            framebuffer_size: [surface_config.width, surface_config.height],
+                            model_matrix: Some(draw.matrix),
+                            ..Default::default()
+                        },
+                    ) {
+                        log::error!("Skipped an Electrum draw: {error:?}");
+                        continue;
+                    }
 
                     let buffer_slice = buffer_pool.allocate(&draw.vertex_buffer);
 
@@ -429,11 +442,22 @@ impl GeometryCallback for ElectrumGeometry {
                         }
                     }
 
-                    let augmented_resources =
-                        augment_resources(wm, &resources, arena, texture, draw.matrix);
-
-                    bind_uniforms(config, augmented_resources, arena, render_pass);
-                    set_push_constants(config, render_pass, None, surface_config, chunk_offset);
+                    bind_draw(wm, config, arena, render_pass, texture, draw.matrix);
+
+                    if let Err(error) = graph.set_push_constants(
+                        wm,
+                        config,
+                        arena,
+                        render_pass,
+                        &PushConstantValues {
+                            framebuffer_size: [surface_config.width, surface_config.height],
+                            model_matrix: Some(draw.matrix),
+                            ..Default::default()
+                        },
+                    ) {
+                        log::error!("Skipped an Electrum draw: {error:?}");
+                        continue;
+                    }
 
                     let vertices = match draw.pipeline_state {
                         PipelineState::PositionColorUint => ElectrumVertex::map_pos_color_uint(
@@ -20,8 +20,8 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use cgmath::Matrix4;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use jni::objects::{
-    GlobalRef, JByteArray, JClass, JFloatArray, JIntArray, JLongArray, JObject, JString, JValue,
-    ReleaseMode,
+    GlobalRef, JByteArray, JClass, JFloatArray, JIntArray, JLongArray, JObject, JObjectArray,
+    JString, JValue, ReleaseMode,
 };
 use jni::sys::{jboolean, jdouble, jfloat, jint, jlong, jstring, JNI_FALSE, JNI_TRUE};
 use jni::{JNIEnv, JavaVM};
@@ -38,23 +38,29 @@ use winit::event::{ElementState, MouseButton};
 use winit::window::{CursorGrabMode, Window};
 
 use entity::TexturedModelData;
+use wgpu_mc::mc::biome::Biome;
 use wgpu_mc::mc::block::{BlockstateKey, ChunkBlockState};
 use wgpu_mc::mc::chunk::{BlockStateProvider, Chunk, ChunkPos, CHUNK_HEIGHT, CHUNK_SECTIONS_PER};
-use wgpu_mc::mc::resource::{ResourcePath, ResourceProvider};
+use wgpu_mc::mc::resource::{ResourcePackStack, ResourcePath, ResourceProvider};
+use wgpu_mc::mc::{BlockBake, MinecraftState};
 use wgpu_mc::minecraft_assets::schemas::blockstates::multipart::StateValue;
-use wgpu_mc::render::pipeline::BLOCK_ATLAS;
+use wgpu_mc::render::atlas::AtlasKind;
 use wgpu_mc::texture::{BindableTexture, TextureSamplerView};
 use wgpu_mc::wgpu;
 use wgpu_mc::wgpu::ImageDataLayout;
 use wgpu_mc::{HasWindowSize, WindowSize, WmRenderer};
 
+use crate::biome::ChunkBiomes;
 use crate::entity::tmd_to_wm;
+use crate::light::ChunkLight;
 use crate::palette::{IdList, JavaPalette, PALETTE_STORAGE};
 use crate::pia::{PackedIntegerArray, PIA_STORAGE};
 use crate::settings::Settings;
 
+mod biome;
 mod entity;
 mod gl;
+mod light;
 mod palette;
 mod pia;
 mod renderer;
@@ -89,6 +95,8 @@ struct MouseState {
 static RENDERER: OnceCell<WmRenderer> = OnceCell::new();
 static WINDOW: OnceCell<Arc<Window>> = OnceCell::new();
 static RUN_DIRECTORY: OnceCell<PathBuf> = OnceCell::new();
+/// The resources of the game, reloaded with [reloadResources]
+static RESOURCE_PACKS: OnceCell<Arc<ResourcePackStack>> = OnceCell::new();
 
 static CHANNELS: Lazy<(Sender<RenderMessage>, Receiver<RenderMessage>)> = Lazy::new(unbounded);
 static TASK_CHANNELS: Lazy<(
@@ -125,11 +133,17 @@ static AIR: Lazy<BlockstateKey> = Lazy::new(|| BlockstateKey {
 
 static BLOCKS: Mutex<Vec<String>> = Mutex::new(Vec::new());
 static BLOCK_STATES: Mutex<Vec<(String, String, GlobalRef)>> = Mutex::new(Vec::new());
+//Started by bakeBlocks, joined by cacheBlockStates
+static BLOCK_BAKE: Mutex<Option<BlockBake>> = Mutex::new(None);
 pub static SETTINGS: RwLock<Option<Settings>> = RwLock::new(None);
 
 #[derive(Debug)]
 struct ChunkHolder {
     pub sections: [Option<(JavaPalette, PackedIntegerArray)>; 24],
+    //None if Java sent light arrays of the wrong size, which has been logged
+    pub light: Option<ChunkLight>,
+    //None if Java sent biome colors of the wrong size, which has been logged
+    pub biomes: Option<ChunkBiomes>,
 }
 
 #[derive(Debug)]
@@ -145,6 +159,19 @@ struct MinecraftBlockstateProvider<'a> {
     pub air: BlockstateKey,
 }
 
+impl<'a> MinecraftBlockstateProvider<'a> {
+    fn get_chunk(&self, pos: ChunkPos) -> Option<&'a ChunkHolder> {
+        match [pos[0] - self.pos[0], pos[1] - self.pos[1]] {
+            [0, 0] => Some(self.center),
+            [0, -1] => self.north,
+            [0, 1] => self.south,
+            [1, 0] => self.east,
+            [-1, 0] => self.west,
+            _pos => None,
+        }
+    }
+}
+
 impl<'a> BlockStateProvider for MinecraftBlockstateProvider<'a> {
     fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
         //Minecraft technically has negative y values now, but chunk data is indexed [0,384} instead of [-64,256}
@@ -152,19 +179,7 @@ impl<'a> BlockStateProvider for MinecraftBlockstateProvider<'a> {
             return ChunkBlockState::Air;
         }
 
-        let chunk_x = (x >> 4) - self.pos[0];
-        let chunk_z = (z >> 4) - self.pos[1];
-
-        let chunk_option = match [chunk_x, chunk_z] {
-            [0, 0] => Some(self.center),
-            [0, -1] => self.north,
-            [0, 1] => self.south,
-            [1, 0] => self.east,
-            [-1, 0] => self.west,
-            _pos => return ChunkBlockState::Air,
-        };
-
-        let chunk = match chunk_option {
+        let chunk = match self.get_chunk([x >> 4, z >> 4]) {
             None => return ChunkBlockState::Air,
             Some(chunk) => chunk,
         };
@@ -193,6 +208,38 @@ impl<'a> BlockStateProvider for MinecraftBlockstateProvider<'a> {
 
         self.center.sections[index].is_none()
     }
+
+    fn is_chunk_loaded(&self, pos: ChunkPos) -> bool {
+        self.get_chunk(pos).is_some()
+    }
+
+    fn get_light(&self, x: i32, y: i16, z: i32) -> (u8, u8) {
+        if y >= CHUNK_HEIGHT as i16 || y < 0 {
+            return (0, 15);
+        }
+
+        match self
+            .get_chunk([x >> 4, z >> 4])
+            .and_then(|chunk| chunk.light.as_ref())
+        {
+            Some(light) => light.get(x, y, z),
+            None => (0, 15),
+        }
+    }
+
+    fn get_biome(&self, x: i32, y: i16, z: i32) -> Biome {
+        if y >= CHUNK_HEIGHT as i16 || y < 0 {
+            return Biome::PLAINS;
+        }
+
+        match self
+            .get_chunk([x >> 4, z >> 4])
+            .and_then(|chunk| chunk.biomes.as_ref())
+        {
+            Some(biomes) => biomes.get(x, y, z),
+            None => Biome::PLAINS,
+        }
+    }
 }
 
 struct WinitWindowWrapper<'a> {
@@ -252,6 +299,43 @@ impl ResourceProvider for MinecraftResourceManagerAdapter {
             slice::from_raw_parts(elements.as_ptr() as *const u8, size)
         }))
     }
+    fn list(&self, directory: &ResourcePath) -> Vec<ResourcePath> {
+        let mut env = self.jvm.attach_current_thread().unwrap();
+
+        let directory = env.new_string(&directory.0).unwrap();
+
+        let resources = match env
+            .call_static_method(
+                "dev/birb/wgpu/rust/WgpuResourceProvider",
+                "findResources",
+                "(Ljava/lang/String;)[Ljava/lang/String;",
+                &[JValue::Object(&directory)],
+            )
+            .and_then(|resources| resources.l())
+        {
+            Ok(resources) => JObjectArray::from(resources),
+            Err(_) => return Vec::new(),
+        };
+
+        let length = env.get_array_length(&resources).unwrap_or(0);
+
+        (0..length)
+            .filter_map(|index| {
+                let resource = JString::from(env.get_object_array_element(&resources, index).ok()?);
+                let resource: String = env.get_string(&resource).ok()?.into();
+
+                Some(ResourcePath::from(resource))
+            })
+            .collect()
+    }
+}
+
+/// Called when Minecraft reloads its resources, like with F3+T. The renderer loads them again on its next frame.
+#[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
+pub fn reloadResources(_env: JNIEnv, _class: JClass) {
+    if let Some(resource_packs) = RESOURCE_PACKS.get() {
+        resource_packs.reload();
+    }
 }
 
 #[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
@@ -328,6 +412,9 @@ pub fn createChunk(
     z: jint,
     palettes: JLongArray,
     storages: JLongArray,
+    block_light: JByteArray,
+    sky_light: JByteArray,
+    biome_colors: JIntArray,
 ) {
     let palette_elements =
         unsafe { env.get_array_elements(&palettes, ReleaseMode::NoCopyBack) }.unwrap();
@@ -351,6 +438,29 @@ pub fn createChunk(
         .try_into()
         .unwrap();
 
+    let light = ChunkLight::new(
+        env.convert_byte_array(&block_light).unwrap(),
+        env.convert_byte_array(&sky_light).unwrap(),
+    );
+
+    if light.is_none() {
+        log::error!(
+            "Chunk [{x}, {z}] was sent with light arrays of the wrong size, it's baked fullbright"
+        );
+    }
+
+    let biome_elements =
+        unsafe { env.get_array_elements(&biome_colors, ReleaseMode::NoCopyBack) }.unwrap();
+    let biomes = ChunkBiomes::new(unsafe {
+        slice::from_raw_parts(biome_elements.as_ptr(), biome_elements.len())
+    });
+
+    if biomes.is_none() {
+        log::error!(
+            "Chunk [{x}, {z}] was sent with biome colors of the wrong size, it's baked as plains"
+        );
+    }
+
     let mut write = CHUNKS.write();
 
     write.insert(
@@ -367,6 +477,8 @@ pub fn createChunk(
                     PIA_STORAGE.read().get(storage - 1).unwrap().clone(),
                 ))
             }),
+            light,
+            biomes,
         },
     );
 }
@@ -412,6 +524,11 @@ pub fn bake_chunk(x: i32, z: i32) {
 
             chunk.bake_chunk(wm, &wm.pipelines.load_full().chunk_layers.load(), &bm, &bsp);
         }
+
+        //Neighbours baked before this chunk was loaded can cull the faces along the border now
+        for [neighbour_x, neighbour_z] in wm.mc.chunks.neighbours_to_rebake([x, z]) {
+            bake_chunk(neighbour_x, neighbour_z);
+        }
     });
 }
 
@@ -444,31 +561,48 @@ pub fn startRendering(env: JNIEnv, _class: JClass, title: JString) {
     renderer::start_rendering(env, title);
 }
 
+/// Starts baking the registered blocks on a loading thread, see [getBakeProgress] and [cacheBlockStates]
+#[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
+pub fn bakeBlocks(_env: JNIEnv, _class: JClass) {
+    let wm = RENDERER.get().unwrap();
+
+    let blockstates = BLOCKS
+        .lock()
+        .iter()
+        .map(|identifier| {
+            (
+                identifier.clone(),
+                ResourcePath::try_from(&identifier[..])
+                    .unwrap()
+                    .prepend("blockstates/")
+                    .append(".json"),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    wm.mc.load_connected_textures();
+
+    *BLOCK_BAKE.lock() = Some(MinecraftState::bake_blocks_in_background(wm, blockstates));
+}
+
+/// From 0 to 1 while the blocks are being baked, 1 once they're done and [cacheBlockStates] won't block
+#[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
+pub fn getBakeProgress(_env: JNIEnv, _class: JClass) -> jfloat {
+    match &*BLOCK_BAKE.lock() {
+        Some(bake) if bake.is_finished() => 1.0,
+        //The atlas is still being uploaded after the last block
+        Some(bake) => bake.progress().fraction().min(0.99),
+        None => 1.0,
+    }
+}
+
 #[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
 pub fn cacheBlockStates(mut env: JNIEnv, _class: JClass) {
     let wm = RENDERER.get().unwrap();
-    {
-        let blocks = BLOCKS.lock();
-
-        let blockstates = blocks
-            .iter()
-            .map(|identifier| {
-                (
-                    identifier.clone(),
-                    ResourcePath::try_from(&identifier[..])
-                        .unwrap()
-                        .prepend("blockstates/")
-                        .append(".json"),
-                )
-            })
-            .collect::<Vec<_>>();
 
-        wm.mc.bake_blocks(
-            wm,
-            blockstates
-                .iter()
-                .map(|(string, resource)| (string, resource)),
-        );
+    //Waits for the bake if the loading screen didn't
+    if let Some(bake) = BLOCK_BAKE.lock().take() {
+        bake.join();
     }
 
     let states = BLOCK_STATES.lock();
@@ -504,14 +638,7 @@ pub fn cacheBlockStates(mut env: JNIEnv, _class: JClass) {
                 vec![]
             };
 
-            let atlas = wm
-                .mc
-                .texture_manager
-                .atlases
-                .load()
-                .get(BLOCK_ATLAS)
-                .unwrap()
-                .load_full();
+            let atlas = wm.mc.texture_manager.atlas(AtlasKind::Block);
 
             let model = wm_block.get_model_by_key(
                 key_iter
@@ -0,0 +1,43 @@
+use wgpu_mc::mc::chunk::CHUNK_SECTIONS_PER;
+
+/// Bytes of a section of Minecraft's `ChunkNibbleArray`, two light levels per byte
+pub const SECTION_LIGHT_SIZE: usize = 2048;
+
+/// The block light and sky light of every section of a chunk, copied from the nibble arrays of Minecraft's
+/// lighting provider. Sections Minecraft has no light data for have been filled in on the Java side.
+#[derive(Clone, Debug)]
+pub struct ChunkLight {
+    block: Box<[u8]>,
+    sky: Box<[u8]>,
+}
+
+impl ChunkLight {
+    /// [None] if either array isn't [SECTION_LIGHT_SIZE] bytes for each of the sections of a chunk
+    #[must_use]
+    pub fn new(block: Vec<u8>, sky: Vec<u8>) -> Option<Self> {
+        let size = SECTION_LIGHT_SIZE * CHUNK_SECTIONS_PER;
+
+        if block.len() != size || sky.len() != size {
+            return None;
+        }
+
+        Some(Self {
+            block: block.into_boxed_slice(),
+            sky: sky.into_boxed_slice(),
+        })
+    }
+
+    /// The block light and sky light at the position, only the chunk-local part of x and z is used
+    pub fn get(&self, x: i32, y: i16, z: i32) -> (u8, u8) {
+        let section = y as usize / 16;
+        //Same order as ChunkNibbleArray#getIndex
+        let index = (((y as usize & 0xf) << 8) | ((z as usize & 0xf) << 4)) | (x as usize & 0xf);
+        let byte = section * SECTION_LIGHT_SIZE + (index >> 1);
+        let shift = (index & 1) << 2;
+
+        (
+            (self.block[byte] >> shift) & 0xf,
+            (self.sky[byte] >> shift) & 0xf,
+        )
+    }
+}
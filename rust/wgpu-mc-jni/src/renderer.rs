@@ -4,11 +4,11 @@ use std::collections::HashMap;
 use std::io::Cursor;
 use std::mem::size_of;
 use std::{slice, thread};
-use std::{sync::Arc, time::Instant};
+use std::{sync::Arc, time::Duration, time::Instant};
 
 use futures::executor::block_on;
 use jni::objects::{JClass, JFloatArray, ReleaseMode};
-use jni::sys::jint;
+use jni::sys::{jfloat, jint};
 use jni::{
     objects::{JString, JValue},
     JNIEnv,
@@ -26,19 +26,22 @@ use winit::{
 
 use wgpu_mc::mc::block::{BlockMeshVertex, BlockstateKey};
 use wgpu_mc::mc::chunk::RenderLayer;
+use wgpu_mc::mc::resource::ResourcePackStack;
 use wgpu_mc::render::graph::{CustomResource, GeometryCallback, ResourceInternal, ShaderGraph};
 use wgpu_mc::render::pipeline::Vertex;
+use wgpu_mc::render::reverse_z::reverse_z_projection;
 use wgpu_mc::render::shaderpack::{Mat4, Mat4ValueOrMult, ShaderPackConfig};
-use wgpu_mc::util::BindableBuffer;
+use wgpu_mc::render::uniforms::FrameUniforms;
+use wgpu_mc::util::{UniformAllocator, UniformSlot};
 use wgpu_mc::wgpu;
 use wgpu_mc::wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu_mc::wgpu::{BufferUsages, TextureFormat};
-use wgpu_mc::{render::atlas::Atlas, WmRenderer};
+use wgpu_mc::{WmConfig, WmRenderer};
 
 use crate::gl::{ElectrumGeometry, ElectrumVertex};
 use crate::{
-    entity::ENTITY_ATLAS, MinecraftResourceManagerAdapter, RenderMessage, WinitWindowWrapper,
-    CHANNELS, MC_STATE, RENDERER, WINDOW,
+    MinecraftResourceManagerAdapter, RenderMessage, WinitWindowWrapper, CHANNELS, MC_STATE,
+    RENDERER, RESOURCE_PACKS, WINDOW,
 };
 
 pub static MATRICES: Lazy<Mutex<Matrices>> = Lazy::new(|| {
@@ -71,8 +74,12 @@ impl RenderLayer for TerrainLayer {
             lightmap_coords: [0.0, 0.0],
             normal: vert.normal,
             color: [1.0, 1.0, 1.0, 1.0],
+            //Filled in for the whole face once it's baked
             tangent: [0.0, 0.0, 0.0, 0.0],
             uv_offset: vert.animation_uv_offset,
+            emissive_tex_coords: vert.emissive_tex_coords,
+            //Set for quads merged by greedy meshing, which repeat their texture
+            region: [0.0; 4],
         }
     }
 
@@ -105,6 +112,44 @@ pub fn setMatrix(mut env: JNIEnv, _class: JClass, id: jint, float_array: JFloatA
     MATRICES.lock().projection = slice_4x4;
 }
 
+#[jni_fn("dev.birb.wgpu.rust.WgpuNative")]
+#[allow(clippy::too_many_arguments)]
+pub fn setFrameUniforms(
+    env: JNIEnv,
+    _class: JClass,
+    tick_delta: jfloat,
+    fog_start: jfloat,
+    fog_end: jfloat,
+    fog_color: JFloatArray,
+    sun_direction: JFloatArray,
+    dimension: jint,
+    time_of_day: jfloat,
+    sun_angle: jfloat,
+) {
+    let mut fog_color_out = [0.0; 4];
+    env.get_float_array_region(&fog_color, 0, &mut fog_color_out)
+        .unwrap();
+
+    let mut sun_direction_out = [0.0; 3];
+    env.get_float_array_region(&sun_direction, 0, &mut sun_direction_out)
+        .unwrap();
+
+    let wm = RENDERER.get().unwrap();
+
+    //The graph fills in the time, the viewport and the camera itself
+    wm.frame_uniforms.store(Arc::new(FrameUniforms {
+        tick_delta,
+        fog_start,
+        fog_end,
+        fog_color: fog_color_out,
+        sun_direction: sun_direction_out,
+        dimension,
+        time_of_day,
+        sun_angle,
+        ..**wm.frame_uniforms.load()
+    }));
+}
+
 pub fn start_rendering(mut env: JNIEnv, title: JString) {
     let title: String = env.get_string(&title).unwrap().into();
 
@@ -136,16 +181,31 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
 
     let wrapper = &WinitWindowWrapper { window: &window };
 
+    //The block outline is stencil-tested like vanilla's
+    let config = WmConfig {
+        stencil: true,
+        ..Default::default()
+    };
+
     let wgpu_state = block_on(WmRenderer::init_wgpu(
         wrapper,
         super::SETTINGS.read().as_ref().unwrap().vsync.value,
-    ));
+        &config,
+    ))
+    .unwrap();
 
-    let resource_provider = Arc::new(MinecraftResourceManagerAdapter {
-        jvm: env.get_java_vm().unwrap(),
-    });
+    //Minecraft's resource manager already layers the enabled packs, F3+T reloads them
+    let resource_packs = RESOURCE_PACKS.get_or_init(|| Arc::new(ResourcePackStack::new()));
+    resource_packs.insert(
+        "minecraft",
+        0,
+        Arc::new(MinecraftResourceManagerAdapter {
+            jvm: env.get_java_vm().unwrap(),
+        }),
+    );
 
-    let wm = WmRenderer::new(wgpu_state, resource_provider);
+    let wm = WmRenderer::with_config(wgpu_state, resource_packs.clone(), config).unwrap();
+    wm.mc.subscribe_reloads(resource_packs);
 
     wm.pipelines
         .load()
@@ -169,8 +229,11 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
 
     let wm_clone = wm.clone();
 
-    let shader_pack: ShaderPackConfig =
+    let mut shader_pack: ShaderPackConfig =
         serde_yaml::from_str(include_str!("../graph.yaml")).unwrap();
+    shader_pack
+        .apply_overrides(&*wm.mc.resource_provider)
+        .unwrap();
 
     let mut render_geometry = HashMap::new();
 
@@ -198,12 +261,6 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
 
     let matrix = Matrix4::identity();
     let mat: Mat4 = matrix.into();
-    let bindable_buffer = BindableBuffer::new(
-        &wm,
-        bytemuck::cast_slice(&mat),
-        BufferUsages::UNIFORM,
-        "matrix",
-    );
 
     resources.insert(
         "wm_mat4_projection".into(),
@@ -212,19 +269,13 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
             data: Arc::new(ResourceInternal::Mat4(
                 Mat4ValueOrMult::Value { value: mat.into() },
                 Arc::new(RwLock::new(matrix)),
-                Arc::new(bindable_buffer),
+                Arc::new(UniformSlot::default()),
             )),
         },
     );
 
     let matrix = Matrix4::identity();
     let mat: Mat4 = matrix.into();
-    let bindable_buffer = BindableBuffer::new(
-        &wm,
-        bytemuck::cast_slice(&mat),
-        BufferUsages::UNIFORM,
-        "matrix",
-    );
 
     resources.insert(
         "wm_mat4_view".into(),
@@ -233,7 +284,7 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
             data: Arc::new(ResourceInternal::Mat4(
                 Mat4ValueOrMult::Value { value: mat.into() },
                 Arc::new(RwLock::new(matrix)),
-                Arc::new(bindable_buffer),
+                Arc::new(UniformSlot::default()),
             )),
         },
     );
@@ -242,7 +293,7 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
 
     let mut types = HashMap::new();
 
-    types.insert("wm_electrum_mat4".into(), "matrix".into());
+    types.insert("wm_electrum_mat4".into(), UniformAllocator::LAYOUT.into());
     types.insert("wm_electrum_gl_texture".into(), "texture".into());
 
     let mut geometry_layouts = HashMap::new();
@@ -256,12 +307,23 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
         },
     );
 
-    shader_graph.init(&wm, Some(&types), Some(geometry_layouts));
+    if let Err(error) = shader_graph.init(&wm, Some(&types), Some(geometry_layouts)) {
+        log::error!("Couldn't build the builtin pipelines: {error}");
+        return;
+    }
 
     thread::spawn(move || {
         let wm = wm_clone;
 
         loop {
+            if wm.is_paused() {
+                thread::sleep(Duration::from_millis(50));
+                continue;
+            }
+
+            wm.mc.poll_reloads(&wm);
+            wm.mc.chunks.defragment(&wm);
+
             let mc_state = MC_STATE.load();
 
             let surface_state = wm.wgpu_state.surface.read();
@@ -275,21 +337,30 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
 
                 if let ResourceInternal::Mat4(val, lock, _) = &*res_mat_proj.data {
                     let matrix4: Matrix4<f32> = matrices.projection.into();
-                    *lock.write() = perspective(
+                    let projection = perspective(
                         Deg(100.0),
                         (surface_state.1.width as f32) / (surface_state.1.height as f32),
                         0.01,
                         1000.0,
                     ) * matrix4;
+
+                    *lock.write() = if **wm.reverse_z.load() {
+                        reverse_z_projection(projection)
+                    } else {
+                        projection
+                    };
                 }
             }
 
-            let surface = surface_state.0.as_ref().unwrap();
+            //The surface may have been dropped by a pause since it was checked above
+            let Some(surface) = surface_state.0.as_ref() else {
+                continue;
+            };
             let texture = surface.get_current_texture().unwrap();
 
             let view = texture.texture.create_view(&wgpu::TextureViewDescriptor {
                 label: None,
-                format: Some(wgpu::TextureFormat::Bgra8Unorm),
+                format: Some(surface_state.1.format),
                 dimension: Some(wgpu::TextureViewDimension::D2),
                 aspect: Default::default(),
                 base_mip_level: 0,
@@ -306,18 +377,16 @@ pub fn start_rendering(mut env: JNIEnv, title: JString) {
         }
     });
 
-    ENTITY_ATLAS
-        .set(Arc::new(Atlas::new(
-            &wm.wgpu_state,
-            &wm.pipelines.load(),
-            false,
-        )))
-        .unwrap();
-
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
             Event::MainEventsCleared => window.request_redraw(),
+            Event::Suspended => wm.pause(),
+            Event::Resumed => {
+                if let Err(error) = wm.resume(&*window) {
+                    log::error!("Couldn't recreate the surface, staying paused: {error}");
+                }
+            }
             Event::WindowEvent {
                 ref event,
                 window_id,
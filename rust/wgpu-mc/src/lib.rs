@@ -22,8 +22,9 @@ as you pass in a valid window handle. See [init_wgpu]
 
 # Rendering
 
-wgpu-mc makes use of a trait called `WmPipeline` to describe any struct which is used for
-rendering. There are multiple built in pipelines, but they aren't required to use while rendering.
+Frames are drawn by a [render::graph::ShaderGraph], whose pipelines come from a shaderpack. Other render passes
+implement [render::registry::WmPipeline] and are added to [WmRenderer::pipeline_registry], which draws them before
+or after the terrain of the pack.
 
 ## Terrain Rendering
 
@@ -40,12 +41,14 @@ See the [render::entity] module for an example of rendering an example entity.
 
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::mem::size_of;
+use std::num::NonZeroU32;
 use std::sync::Arc;
 
 use arc_swap::ArcSwap;
 pub use minecraft_assets;
 pub use naga;
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 pub use wgpu;
 use wgpu::{
@@ -53,12 +56,28 @@ use wgpu::{
     SurfaceConfiguration,
 };
 
-use crate::mc::resource::ResourceProvider;
+use crate::mc::block::{BlockPos, BlockShape};
+use crate::mc::chunk::CHUNK_SECTIONS_PER;
+use crate::mc::resource::{ResourcePath, ResourceProvider};
 use crate::mc::MinecraftState;
-use crate::render::atlas::Atlas;
+use crate::render::atlas::{texture_dimensions, Atlas, AtlasKind, ATLAS_DIMENSIONS};
+use crate::render::chunk_allocator::DEFAULT_PAGE_SIZE;
+use crate::render::gpu_culler::SectionBounds;
+use crate::render::gpu_mesher::GpuMesher;
 use crate::render::graph::ShaderGraph;
-use crate::render::pipeline::{WmPipelines, BLOCK_ATLAS, ENTITY_ATLAS};
+use crate::render::lightmap::{
+    default_lightmap, generate_lightmap, LightmapSettings, LIGHTMAP_SIZE,
+};
+use crate::render::particle::gpu::GpuParticles;
+use crate::render::pipeline::entity::EntityPipeline;
+use crate::render::pipeline::first_person::FirstPerson;
+use crate::render::pipeline::{ChunkVertexFormat, WmPipelines};
+use crate::render::profiler::{GpuProfiler, RenderProfile};
+use crate::render::registry::PipelineRegistry;
+use crate::render::shader::ShaderFeatures;
+use crate::render::uniforms::FrameUniforms;
 use crate::texture::{BindableTexture, TextureHandle, TextureSamplerView};
+use crate::util::{UniformAllocator, UploadBelt};
 
 pub mod mc;
 pub mod render;
@@ -68,11 +87,40 @@ pub mod util;
 /// Provides access to most of the wgpu structs relating directly to communicating/getting
 /// information about the gpu.
 pub struct WgpuState {
+    //Kept around to recreate the surface when the platform destroys it while paused
+    pub instance: wgpu::Instance,
     pub surface: RwLock<(Option<wgpu::Surface>, SurfaceConfiguration)>,
     pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub size: Option<ArcSwap<WindowSize>>,
+    /// The format of the depth textures, see [TextureSamplerView::depth_format]
+    pub depth_format: wgpu::TextureFormat,
+}
+
+impl WgpuState {
+    /// See [WmRenderer::supports_msaa_samples]
+    pub fn supports_msaa_samples(&self, samples: u32) -> bool {
+        match samples {
+            1 | 4 => true,
+            2 | 8 => {
+                let surface_format = self.surface.read().1.format;
+
+                self.device
+                    .features()
+                    .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                    && [surface_format, self.depth_format]
+                        .into_iter()
+                        .all(|format| {
+                            self.adapter
+                                .get_texture_format_features(format)
+                                .flags
+                                .sample_count_supported(samples)
+                        })
+            }
+            _ => false,
+        }
+    }
 }
 
 /// The main wgpu-mc renderer struct. This mostly just contains wgpu state.
@@ -83,6 +131,217 @@ pub struct WmRenderer {
     pub texture_handles: Arc<RwLock<HashMap<String, TextureHandle>>>,
     pub pipelines: Arc<ArcSwap<WmPipelines>>,
     pub mc: Arc<MinecraftState>,
+    pub paused: Arc<ArcSwap<PausedState>>,
+    pub config: WmConfig,
+    /// Buffer uploads which haven't been submitted yet, see [WmRenderer::queue_upload]
+    pub uploads: Arc<Mutex<UploadBelt>>,
+    /// Uniform data which changes every frame, see [UniformAllocator]
+    pub uniforms: Arc<UniformAllocator>,
+    /// Render passes drawn in between the pipelines of the shader graph
+    pub pipeline_registry: Arc<PipelineRegistry>,
+    /// The number of samples per pixel the shader graph draws with, see [WmRenderer::set_msaa_samples]
+    pub msaa_samples: Arc<ArcSwap<u32>>,
+    /// Draws solid terrain into the depth texture first, so that its fragment shader only runs once per pixel.
+    /// Saves time in dense scenes like jungles and caves, but costs more than it saves in simple ones, so it's off
+    /// by default
+    pub depth_prepass: Arc<ArcSwap<bool>>,
+    /// Whether the depth buffer goes from 1 at the near plane to 0 at the far plane, see [render::reverse_z]. Off
+    /// by default, the shader graph rebuilds its pipelines before the next frame when it's changed.
+    pub reverse_z: Arc<ArcSwap<bool>>,
+    /// Uploaded for the shaders once per frame, see [render::uniforms]
+    pub frame_uniforms: Arc<ArcSwap<FrameUniforms>>,
+    /// The block whose outline is drawn, see [WmRenderer::set_block_outline]
+    pub block_outline: Arc<ArcSwap<Option<(BlockPos, BlockShape)>>>,
+    /// Whether the borders of the chunk the camera is in are drawn, see [WmRenderer::set_chunk_borders]
+    pub chunk_borders: Arc<ArcSwap<bool>>,
+    /// How the terrain and entities are rasterized, see [WmRenderer::set_debug_polygon_mode]
+    pub debug_polygon_mode: Arc<ArcSwap<wgpu::PolygonMode>>,
+    /// The features the shaders are compiled with, see [WmRenderer::set_shader_feature]
+    pub shader_features: Arc<ArcSwap<ShaderFeatures>>,
+    /// The entities drawn by the shader graph, see [render::pipeline::entity]
+    pub entities: Arc<EntityPipeline>,
+    /// The arm and held items, see [render::pipeline::first_person]
+    pub first_person: Arc<FirstPerson>,
+    /// Set if [WmConfig::gpu_profiling] is enabled and the device supports it, see [WmRenderer::last_frame_profile]
+    pub profiler: Option<Arc<GpuProfiler>>,
+    #[cfg(feature = "egui")]
+    pub egui: Arc<render::debug_ui::EguiPipeline>,
+}
+
+/// Whether the renderer is currently allowed to submit frames to the GPU.
+/// See [WmRenderer::pause] and [WmRenderer::resume]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PausedState {
+    Running,
+    Paused,
+}
+
+/// Options which are fixed for the lifetime of a [WmRenderer]
+#[derive(Copy, Clone, Debug)]
+pub struct WmConfig {
+    /// The width and height of the block texture atlas, which must be a power of two no larger than the device's
+    /// maximum texture size, see [WmConfig::validate]. The atlas doesn't grow past it, textures which don't fit
+    /// fail with [render::atlas::AtlasError::Full]. [None], the default, picks the initial size in
+    /// [WmRenderer::init] with [WmConfig::auto_atlas_size] from the block and item textures of the resource
+    /// provider, see [WmConfig::initial_atlas_size], and grows the atlas up to the device's maximum texture size
+    /// when it runs out of space, which re-bakes the blocks.
+    pub atlas_size: Option<u32>,
+    /// How many pixels every texture of the atlases is extruded by, so that neighbouring textures don't bleed into
+    /// each other at their edges, see [Atlas::padding]
+    pub atlas_padding: u32,
+    /// Packs the LabPBR normal and specular maps of the block and entity textures into atlases of their own, and
+    /// turns on the `PBR` shader feature, see [render::atlas::PbrMap]. The builtin terrain shader then shades blocks
+    /// with their normal maps, using the tangents baked into each face.
+    pub pbr: bool,
+    /// The vertex format chunk meshes are uploaded with. [ChunkVertexFormat::Packed] uses about a third of the
+    /// memory, but has no tangents, so shaders with the `PBR` feature have to derive them
+    pub chunk_vertex_format: ChunkVertexFormat,
+    /// Experimental, meshes chunks with a compute shader where possible, see [render::gpu_mesher]
+    pub gpu_meshing: bool,
+    /// Culls chunk sections against the frustum with a compute shader, see [render::gpu_culler]
+    pub gpu_culling: bool,
+    /// Simulates the particles spawned with [render::particle::ParticleManager::spawn_gpu] with a compute shader,
+    /// see [render::particle::gpu]
+    pub gpu_particles: bool,
+    /// Times each pass of the shader graph on the GPU where timestamp queries are supported, see
+    /// [render::profiler]
+    pub gpu_profiling: bool,
+    /// The furthest chunks will be loaded from the camera, in chunks. The buffers holding something for every
+    /// section in range, like the bounds the GPU culler reads, are sized for it in
+    /// [WmRenderer::compute_required_limits]
+    pub render_distance: u32,
+    /// Gives the depth textures a stencil aspect, see [TextureSamplerView::depth_format]. The block outline is then
+    /// stencil-tested like vanilla's, so that the pixels where its lines meet aren't blended twice.
+    pub stencil: bool,
+}
+
+impl Default for WmConfig {
+    fn default() -> Self {
+        Self {
+            atlas_size: None,
+            atlas_padding: 1,
+            pbr: false,
+            chunk_vertex_format: ChunkVertexFormat::Full,
+            gpu_meshing: false,
+            gpu_culling: false,
+            gpu_particles: false,
+            gpu_profiling: false,
+            //The furthest vanilla goes
+            render_distance: 32,
+            stencil: false,
+        }
+    }
+}
+
+/// The size the block atlas starts at if [WmConfig::atlas_size] is picked automatically, but the resource provider
+/// can't list its textures
+pub const DEFAULT_ATLAS_SIZE: u32 = 4096;
+
+/// Why a [WmConfig] can't be used with a device, see [WmConfig::validate]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ConfigError {
+    /// [WmConfig::atlas_size] isn't a power of two
+    AtlasSizeNotPowerOfTwo(u32),
+    /// [WmConfig::atlas_size] is larger than the device's maximum texture size
+    AtlasSizeTooLarge { size: u32, max_size: u32 },
+    /// The depth format of the [WgpuState] doesn't match [WmConfig::stencil], it has to be created by
+    /// [WmRenderer::init_wgpu] with the same config
+    DepthFormat(wgpu::TextureFormat),
+}
+
+impl WmConfig {
+    /// Checks the options which depend on each other or on the device, whose `limits` are passed in
+    pub fn validate(&self, limits: &wgpu::Limits) -> Result<(), ConfigError> {
+        if let Some(size) = self.atlas_size {
+            if !size.is_power_of_two() {
+                return Err(ConfigError::AtlasSizeNotPowerOfTwo(size));
+            }
+
+            if size > limits.max_texture_dimension_2d {
+                return Err(ConfigError::AtlasSizeTooLarge {
+                    size,
+                    max_size: limits.max_texture_dimension_2d,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The size the block atlas starts at, [WmConfig::atlas_size] if it's set. Otherwise it's picked with
+    /// [WmConfig::auto_atlas_size] from the textures in `minecraft:textures/block` and `minecraft:textures/item`,
+    /// or `max_size` if they don't fit. [DEFAULT_ATLAS_SIZE] is used if the resource provider can't list them.
+    pub fn initial_atlas_size(
+        &self,
+        resource_provider: &dyn ResourceProvider,
+        max_size: u32,
+    ) -> u32 {
+        if let Some(atlas_size) = self.atlas_size {
+            return atlas_size;
+        }
+
+        let textures = texture_dimensions(
+            resource_provider,
+            &[
+                ResourcePath::from("minecraft:textures/block"),
+                ResourcePath::from("minecraft:textures/item"),
+            ],
+        );
+
+        if textures.is_empty() {
+            return DEFAULT_ATLAS_SIZE.min(max_size);
+        }
+
+        //Every texture is extruded by the padding on each side
+        let padded = textures.into_iter().map(|(width, height)| {
+            (
+                width + 2 * self.atlas_padding,
+                height + 2 * self.atlas_padding,
+            )
+        });
+
+        Self::auto_atlas_size(padded, max_size).unwrap_or(max_size)
+    }
+
+    /// Picks the smallest power of two atlas size which has enough area for textures of the given dimensions,
+    /// leaving some headroom for packing inefficiency. Returns None if it would be larger than `max_size`.
+    pub fn auto_atlas_size(
+        textures: impl IntoIterator<Item = (u32, u32)>,
+        max_size: u32,
+    ) -> Option<u32> {
+        let (area, largest) =
+            textures
+                .into_iter()
+                .fold((0u64, 0u32), |(area, largest), (width, height)| {
+                    (
+                        area + width as u64 * height as u64,
+                        largest.max(width).max(height),
+                    )
+                });
+
+        //Rectangle packing rarely achieves more than ~80% density
+        let required_area = area + area / 4;
+
+        let mut size = largest.max(16).next_power_of_two();
+
+        while (size as u64 * size as u64) < required_area {
+            size *= 2;
+        }
+
+        (size <= max_size).then_some(size)
+    }
+}
+
+/// Returned by [WmRenderer::init_wgpu] when the adapter can't be used to render with the given [WmConfig]
+#[derive(Debug)]
+pub enum WgpuInitError {
+    NoAdapter,
+    /// The adapter supports lower limits than required, see [WmRenderer::compute_required_limits]
+    UnsupportedLimits {
+        /// The name of each limit which isn't supported, along with the required and supported value
+        unsupported: Vec<(&'static str, u64, u64)>,
+    },
+    RequestDevice(wgpu::RequestDeviceError),
 }
 
 #[derive(Copy, Clone)]
@@ -96,6 +355,35 @@ pub trait HasWindowSize {
 }
 
 impl WmRenderer {
+    /// The minimum limits a device needs to render with the given config
+    pub fn compute_required_limits(config: &WmConfig) -> wgpu::Limits {
+        let defaults = wgpu::Limits::default();
+
+        //The GPU culler binds the bounds of every section in render distance at once
+        let sections = (2 * config.render_distance as u64 + 1).pow(2) * CHUNK_SECTIONS_PER as u64;
+        let section_bounds = sections * size_of::<SectionBounds>() as u64;
+
+        wgpu::Limits {
+            max_push_constant_size: 128,
+            //The terrain pipelines bind the frame uniforms in group 3, and the normal and specular maps for PBR in
+            //groups 4 and 5, which the shader graph leaves out without it
+            max_bind_groups: if config.pbr { 6 } else { 4 },
+            //The block atlas is a single texture
+            max_texture_dimension_2d: defaults
+                .max_texture_dimension_2d
+                .max(config.atlas_size.unwrap_or(0)),
+            max_storage_buffer_binding_size: defaults
+                .max_storage_buffer_binding_size
+                .max(section_bounds.try_into().unwrap_or(u32::MAX)),
+            //Chunk meshes are sub-allocated from pages of this size
+            max_buffer_size: defaults
+                .max_buffer_size
+                .max(section_bounds)
+                .max(DEFAULT_PAGE_SIZE),
+            ..defaults
+        }
+    }
+
     /// This is a convenience method;
     ///
     /// This takes in a raw window handle and returns a [WgpuState], which is then used to
@@ -103,7 +391,8 @@ impl WmRenderer {
     pub async fn init_wgpu<W: HasRawWindowHandle + HasRawDisplayHandle + HasWindowSize>(
         window: &W,
         vsync: bool,
-    ) -> WgpuState {
+        config: &WmConfig,
+    ) -> Result<WgpuState, WgpuInitError> {
         let size = window.get_window_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -119,12 +408,27 @@ impl WmRenderer {
                 compatible_surface: Some(&surface),
             })
             .await
-            .unwrap();
+            .ok_or(WgpuInitError::NoAdapter)?;
 
-        let limits = wgpu::Limits {
-            max_push_constant_size: 128,
-            ..Default::default()
-        };
+        let mut limits = Self::compute_required_limits(config);
+
+        //The shader graph falls back to uniform buffers without push constants, in a bind group after the uniforms
+        if !adapter.features().contains(wgpu::Features::PUSH_CONSTANTS) {
+            limits.max_push_constant_size = 0;
+            limits.max_bind_groups += 1;
+        }
+
+        let mut unsupported = Vec::new();
+        limits.check_limits_with_fail_fn(&adapter.limits(), false, |name, required, supported| {
+            unsupported.push((name, required, supported));
+        });
+
+        if !unsupported.is_empty() {
+            return Err(WgpuInitError::UnsupportedLimits { unsupported });
+        }
+
+        //The block atlas can grow up to the largest texture the adapter supports
+        limits.max_texture_dimension_2d = adapter.limits().max_texture_dimension_2d;
 
         let (device, queue) = adapter
             .request_device(
@@ -132,17 +436,45 @@ impl WmRenderer {
                     label: None,
                     features: wgpu::Features::default()
                         | wgpu::Features::DEPTH_CLIP_CONTROL
-                        | wgpu::Features::PUSH_CONSTANTS,
+                        | (adapter.features() & wgpu::Features::PUSH_CONSTANTS)
+                        //Terrain is drawn with multi-draw indirect where the adapter supports it
+                        | (adapter.features()
+                            & (wgpu::Features::MULTI_DRAW_INDIRECT
+                                | wgpu::Features::INDIRECT_FIRST_INSTANCE
+                                //MSAA with other sample counts than 4
+                                | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                                //Debug polygon modes
+                                | wgpu::Features::POLYGON_MODE_LINE
+                                | wgpu::Features::POLYGON_MODE_POINT
+                                //GPU profiling
+                                | wgpu::Features::TIMESTAMP_QUERY))
+                        //Keeps the depth a 32 bit float along with the stencil
+                        | if config.stencil {
+                            adapter.features() & wgpu::Features::DEPTH32FLOAT_STENCIL8
+                        } else {
+                            wgpu::Features::empty()
+                        },
                     limits,
                 },
                 None, // Trace path
             )
             .await
-            .unwrap();
+            .map_err(WgpuInitError::RequestDevice)?;
+
+        //Shaders write colors which are already in sRGB, so the surface shouldn't convert them again
+        let formats = surface.get_capabilities(&adapter).formats;
+        let format = [
+            wgpu::TextureFormat::Bgra8Unorm,
+            wgpu::TextureFormat::Rgba8Unorm,
+        ]
+        .into_iter()
+        .find(|format| formats.contains(format))
+        .or_else(|| formats.first().copied())
+        .unwrap_or(wgpu::TextureFormat::Bgra8Unorm);
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8Unorm,
+            format,
             width: size.width,
             height: size.height,
             present_mode: if vsync {
@@ -156,52 +488,191 @@ impl WmRenderer {
 
         surface.configure(&device, &surface_config);
 
-        WgpuState {
+        let depth_format = TextureSamplerView::depth_format(config.stencil, device.features());
+
+        Ok(WgpuState {
+            instance,
             surface: RwLock::new((Some(surface), surface_config)),
             adapter,
             device,
             queue,
             size: Some(ArcSwap::new(Arc::new(size))),
-        }
+            depth_format,
+        })
+    }
+
+    pub fn new(
+        wgpu_state: WgpuState,
+        resource_provider: Arc<dyn ResourceProvider>,
+    ) -> Result<WmRenderer, ConfigError> {
+        Self::with_config(wgpu_state, resource_provider, WmConfig::default())
     }
 
-    pub fn new(wgpu_state: WgpuState, resource_provider: Arc<dyn ResourceProvider>) -> WmRenderer {
+    /// Fails if the config can't be used with the device of the [WgpuState], see [WmConfig::validate]
+    pub fn with_config(
+        wgpu_state: WgpuState,
+        resource_provider: Arc<dyn ResourceProvider>,
+        config: WmConfig,
+    ) -> Result<WmRenderer, ConfigError> {
+        config.validate(&wgpu_state.device.limits())?;
+
+        if config.stencil != TextureSamplerView::has_stencil(wgpu_state.depth_format) {
+            return Err(ConfigError::DepthFormat(wgpu_state.depth_format));
+        }
+
         let pipelines = WmPipelines::new(resource_provider.clone());
 
         let mc = MinecraftState::new(resource_provider);
 
-        Self {
+        //256 bytes fit the matrices of the graph, the frame uniforms and the 128 bytes of push constants the pipelines
+        //can have, see ShaderGraph::set_push_constants. It grows if a frame needs more than 4096 of them.
+        let uniforms = Arc::new(UniformAllocator::new(
+            &wgpu_state.device,
+            "uniforms",
+            wgpu::ShaderStages::VERTEX_FRAGMENT,
+            256,
+            4096,
+        ));
+
+        let profiler = config
+            .gpu_profiling
+            .then(|| GpuProfiler::new(&wgpu_state))
+            .flatten()
+            .map(Arc::new);
+
+        let pipeline_registry = Arc::new(PipelineRegistry::new());
+
+        #[cfg(feature = "egui")]
+        let egui = {
+            let egui = Arc::new(render::debug_ui::EguiPipeline::new(
+                &wgpu_state,
+                wgpu_state.surface.read().1.format,
+            ));
+
+            pipeline_registry.register(
+                render::debug_ui::EGUI_PIPELINE,
+                render::registry::RenderPhase::Overlay,
+                i32::MAX,
+                egui.clone(),
+            );
+
+            egui
+        };
+
+        Ok(Self {
             wgpu_state: Arc::new(wgpu_state),
 
             texture_handles: Arc::new(RwLock::new(HashMap::new())),
             pipelines: Arc::new(ArcSwap::new(Arc::new(pipelines))),
             mc: Arc::new(mc),
-        }
+            paused: Arc::new(ArcSwap::new(Arc::new(PausedState::Running))),
+            config,
+            uploads: Arc::new(Mutex::new(UploadBelt::default())),
+            uniforms,
+            pipeline_registry,
+            msaa_samples: Arc::new(ArcSwap::new(Arc::new(1))),
+            depth_prepass: Arc::new(ArcSwap::new(Arc::new(false))),
+            reverse_z: Arc::new(ArcSwap::new(Arc::new(false))),
+            frame_uniforms: Arc::new(ArcSwap::new(Arc::new(FrameUniforms::default()))),
+            block_outline: Arc::new(ArcSwap::new(Arc::new(None))),
+            chunk_borders: Arc::new(ArcSwap::new(Arc::new(false))),
+            debug_polygon_mode: Arc::new(ArcSwap::new(Arc::new(wgpu::PolygonMode::Fill))),
+            shader_features: Arc::new(ArcSwap::new(Arc::new(ShaderFeatures::new()))),
+            entities: Arc::new(EntityPipeline::new()),
+            first_person: Arc::new(FirstPerson::new()),
+            profiler,
+            #[cfg(feature = "egui")]
+            egui,
+        })
     }
 
     pub fn init(&self) {
         let pipelines = self.pipelines.load();
         pipelines.init(self);
 
-        let atlases = [BLOCK_ATLAS, ENTITY_ATLAS]
-            .iter()
-            .map(|&name| {
-                (
-                    name.into(),
-                    Arc::new(ArcSwap::new(Arc::new(Atlas::new(
-                        &self.wgpu_state,
-                        &pipelines,
-                        false,
-                    )))),
+        let block_atlas_size = self.config.initial_atlas_size(
+            &*self.mc.resource_provider,
+            self.wgpu_state.device.limits().max_texture_dimension_2d,
+        );
+
+        let atlases = AtlasKind::ALL
+            .into_iter()
+            .map(|kind| {
+                let atlas = Atlas::with_size(
+                    &self.wgpu_state,
+                    &pipelines,
+                    if kind == AtlasKind::Block {
+                        block_atlas_size
+                    } else {
+                        ATLAS_DIMENSIONS
+                    },
+                    kind == AtlasKind::Block,
                 )
+                .with_padding(self.config.atlas_padding);
+
+                //A block atlas of a fixed size doesn't grow
+                let atlas = match (kind, self.config.atlas_size) {
+                    (AtlasKind::Block, Some(atlas_size)) => atlas.with_max_size(atlas_size),
+                    _ => atlas,
+                };
+
+                //Only blocks and entities are lit
+                let atlas =
+                    if self.config.pbr && matches!(kind, AtlasKind::Block | AtlasKind::Entity) {
+                        atlas.with_pbr_maps(&self.wgpu_state, &pipelines)
+                    } else {
+                        atlas
+                    };
+
+                (kind, Arc::new(ArcSwap::new(Arc::new(atlas))))
             })
             .collect();
 
         self.mc.texture_manager.atlases.store(Arc::new(atlases));
 
+        if self.config.pbr {
+            self.set_shader_feature("PBR", true);
+        }
+
+        let lightmap = BindableTexture::from_tsv(
+            &self.wgpu_state,
+            &pipelines,
+            TextureSamplerView::from_rgb_bytes(
+                &self.wgpu_state,
+                &default_lightmap(),
+                Extent3d {
+                    width: LIGHTMAP_SIZE,
+                    height: LIGHTMAP_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                Some("Lightmap"),
+                wgpu::TextureFormat::Rgba8Unorm,
+            )
+            .unwrap(),
+            false,
+        );
+
+        self.mc.lightmap.store(Arc::new(Some(Arc::new(lightmap))));
+
+        render::colormap::load_colormaps(self);
+
+        if self.config.gpu_meshing {
+            self.mc
+                .chunks
+                .gpu_mesher
+                .store(Arc::new(GpuMesher::new(self)));
+        }
+
+        if self.config.gpu_particles {
+            self.mc
+                .particles
+                .gpu
+                .store(Arc::new(GpuParticles::new(self)));
+        }
+
         self.create_texture_handle(
             "wm_framebuffer_depth".into(),
-            wgpu::TextureFormat::Depth32Float,
+            self.wgpu_state.depth_format,
             &self.wgpu_state.surface.read().1,
         );
     }
@@ -230,7 +701,7 @@ impl WmRenderer {
                 &self.wgpu_state,
                 &self.pipelines.load(),
                 tsv,
-                matches!(format, wgpu::TextureFormat::Depth32Float),
+                TextureSamplerView::is_depth_format(format),
             )))),
         };
 
@@ -249,18 +720,19 @@ impl WmRenderer {
             return;
         }
 
-        let surface_state = self.wgpu_state.surface.write(); //Guarantee the Surface is not in use
+        let mut surface_state = self.wgpu_state.surface.write(); //Guarantee the Surface is not in use
 
-        let mut surface_config = surface_state.1.clone();
+        surface_state.1.width = new_size.width;
+        surface_state.1.height = new_size.height;
 
-        surface_config.width = new_size.width;
-        surface_config.height = new_size.height;
+        let surface_config = surface_state.1.clone();
 
-        surface_state
-            .0
-            .as_ref()
-            .unwrap()
-            .configure(&self.wgpu_state.device, &surface_config);
+        //Without a surface the new size is only stored, and used once it's recreated in resume
+        if let Some(surface) = &surface_state.0 {
+            surface.configure(&self.wgpu_state.device, &surface_config);
+        }
+
+        drop(surface_state);
 
         let handles = { self.texture_handles.read().clone() };
 
@@ -271,6 +743,118 @@ impl WmRenderer {
         });
     }
 
+    /// Stop submitting frames to the GPU, for example when the application is backgrounded.
+    /// Chunk bakes submitted to the [mc::chunk::ChunkBakery] are held and entity instances aren't uploaded until
+    /// [WmRenderer::resume] is called. All CPU-side state (chunks, entities, resources) is preserved, but the surface
+    /// is dropped, as platforms like Android destroy the window it belongs to.
+    pub fn pause(&self) {
+        self.paused.store(Arc::new(PausedState::Paused));
+
+        self.wgpu_state.surface.write().0 = None;
+    }
+
+    /// Restart frame submission after a call to [WmRenderer::pause]. The surface is recreated for the window, which
+    /// may be a different one than before the pause, and configured with the last known configuration. If the
+    /// surface can't be created, the renderer stays paused, and resuming can be tried again later.
+    pub fn resume<W: HasRawWindowHandle + HasRawDisplayHandle>(
+        &self,
+        window: &W,
+    ) -> Result<(), wgpu::CreateSurfaceError> {
+        if !self.is_paused() {
+            return Ok(());
+        }
+
+        {
+            let mut surface_state = self.wgpu_state.surface.write();
+
+            if surface_state.0.is_none() {
+                surface_state.0 = Some(unsafe { self.wgpu_state.instance.create_surface(window) }?);
+            }
+
+            if let Some(surface) = &surface_state.0 {
+                surface.configure(&self.wgpu_state.device, &surface_state.1);
+            }
+        }
+
+        self.paused.store(Arc::new(PausedState::Running));
+
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        **self.paused.load() == PausedState::Paused
+    }
+
+    /// Whether the surface and depth textures can be multisampled with the sample count, which can be 1, 2, 4 or 8.
+    /// Every device supports 1 and 4.
+    pub fn supports_msaa_samples(&self, samples: u32) -> bool {
+        self.wgpu_state.supports_msaa_samples(samples)
+    }
+
+    /// Turns MSAA on with the sample count, or off with 1. The shader graph draws into multisampled textures, which
+    /// are resolved to the surface at the end of the frame, and rebuilds its pipelines with the new count before the
+    /// next frame. Returns false and keeps the current count if the device doesn't support it, see
+    /// [WmRenderer::supports_msaa_samples].
+    pub fn set_msaa_samples(&self, samples: u32) -> bool {
+        if !self.supports_msaa_samples(samples) {
+            return false;
+        }
+
+        self.msaa_samples.store(Arc::new(samples));
+
+        true
+    }
+
+    /// Outlines the shape of the block at the position, e.g. the one the player is looking at, or nothing with [None].
+    /// The outline is drawn by the pipelines of the shader graph with the `wm_geo_block_outline` geometry, see
+    /// [render::pipeline::block_outline].
+    pub fn set_block_outline(&self, outline: Option<(BlockPos, BlockShape)>) {
+        self.block_outline.store(Arc::new(outline));
+    }
+
+    /// Shows or hides the borders of the chunk the camera is in, like vanilla's F3 + G. They're drawn by the
+    /// pipelines of the shader graph with the `wm_geo_chunk_borders` geometry, see [render::pipeline::debug_lines].
+    pub fn set_chunk_borders(&self, shown: bool) {
+        self.chunk_borders.store(Arc::new(shown));
+    }
+
+    /// Draws the terrain and entities as wireframes with [wgpu::PolygonMode::Line] or as points with
+    /// [wgpu::PolygonMode::Point], to look at how chunks were meshed, or normally again with
+    /// [wgpu::PolygonMode::Fill]. The shader graph rebuilds its pipelines before the next frame. Returns false and
+    /// keeps the current mode if the device doesn't support it.
+    pub fn set_debug_polygon_mode(&self, polygon_mode: wgpu::PolygonMode) -> bool {
+        let required = match polygon_mode {
+            wgpu::PolygonMode::Fill => wgpu::Features::empty(),
+            wgpu::PolygonMode::Line => wgpu::Features::POLYGON_MODE_LINE,
+            wgpu::PolygonMode::Point => wgpu::Features::POLYGON_MODE_POINT,
+        };
+
+        if !self.wgpu_state.device.features().contains(required) {
+            return false;
+        }
+
+        self.debug_polygon_mode.store(Arc::new(polygon_mode));
+
+        true
+    }
+
+    /// Turns a feature of the shaders like `FOG` or `SMOOTH_LIGHTING` on or off. Pipelines which list the feature
+    /// in their `features` switch to the permutation of their shader with it defined before the next frame, which
+    /// is only compiled the first time it's needed, see [render::shader::ShaderSource::with_features].
+    pub fn set_shader_feature(&self, feature: &str, enabled: bool) {
+        self.shader_features.rcu(|features| {
+            let mut features = ShaderFeatures::clone(features);
+
+            if enabled {
+                features.insert(feature.into());
+            } else {
+                features.remove(feature);
+            }
+
+            features
+        });
+    }
+
     pub fn upload_animated_block_buffer(&self, data: Vec<f32>) {
         let d = data.as_slice();
 
@@ -318,17 +902,114 @@ impl WmRenderer {
         );
     }
 
+    /// Advances the animated textures of every atlas by a tick, see [render::atlas::Atlas::tick_animations]. Should
+    /// be called once per game tick.
+    pub fn tick_texture_animations(&self) {
+        for atlas in self.mc.texture_manager.atlases.load().values() {
+            atlas.load().tick_animations(self);
+        }
+    }
+
+    /// Replaces the lightmap with 16x16 RGBA pixels, block light along the x axis and sky light along the y axis.
+    /// See [render::lightmap]
+    pub fn upload_lightmap(&self, data: &[u8]) {
+        assert_eq!(
+            data.len(),
+            (LIGHTMAP_SIZE * LIGHTMAP_SIZE * 4) as usize,
+            "The lightmap must be 16x16 RGBA pixels"
+        );
+
+        let lightmap = self.mc.lightmap.load();
+        let lightmap = (**lightmap)
+            .as_ref()
+            .expect("WmRenderer::init has to be called first");
+
+        self.wgpu_state.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &lightmap.tsv.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: NonZeroU32::new(LIGHTMAP_SIZE * 4),
+                rows_per_image: NonZeroU32::new(LIGHTMAP_SIZE),
+            },
+            Extent3d {
+                width: LIGHTMAP_SIZE,
+                height: LIGHTMAP_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Generates the lightmap from the settings the same way Minecraft does, and uploads it. See [render::lightmap]
+    pub fn update_lightmap(&self, settings: &LightmapSettings) {
+        self.upload_lightmap(&generate_lightmap(settings));
+    }
+
     pub fn render(
         &self,
         graph: &ShaderGraph,
         output_texture_view: &wgpu::TextureView,
         surface_config: &SurfaceConfiguration,
     ) -> Result<(), wgpu::SurfaceError> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        self.flush_uploads();
+
+        //Chunks re-baked since the last frame are only drawn once their buffers have been written
+        self.wgpu_state.device.poll(wgpu::Maintain::Poll);
+        self.mc.chunks.swap_buffers(self.uploads.lock().completed());
+        self.pipelines.load().bind_group_cache.evict_dropped();
+        self.uniforms.next_frame();
+
         graph.render(self, output_texture_view, surface_config);
 
         Ok(())
     }
 
+    /// How long the GPU spent on each pass of the shader graph in the last frame which has been timed, see
+    /// [render::profiler]. [None] without [WmRenderer::profiler] or until the first frame has been read back.
+    pub fn last_frame_profile(&self) -> Option<RenderProfile> {
+        self.profiler.as_ref()?.last_profile()
+    }
+
+    /// Copies the data into the buffer through a staging belt. This can be called from any thread, and the copy
+    /// is only recorded; it's submitted to the GPU by the next [WmRenderer::flush_uploads], which
+    /// [WmRenderer::render] calls before every frame.
+    ///
+    /// Both the offset and the length of the data have to be multiples of [wgpu::COPY_BUFFER_ALIGNMENT]
+    pub fn queue_upload(&self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+
+        self.uploads
+            .lock()
+            .write(&self.wgpu_state.device, buffer, offset, data);
+    }
+
+    /// Submits every upload queued by [WmRenderer::queue_upload] since the last flush
+    pub fn flush_uploads(&self) {
+        self.uploads.lock().flush(&self.wgpu_state.queue);
+    }
+
+    #[cfg(feature = "egui")]
+    pub fn egui_ctx(&self) -> &egui::Context {
+        &self.egui.ctx
+    }
+
+    /// Forward a window event to egui. Returns true if egui consumed the event.
+    #[cfg(feature = "egui")]
+    pub fn egui_input(&self, event: &winit::event::WindowEvent) -> bool {
+        self.egui.on_event(event)
+    }
+
     pub fn get_backend_description(&self) -> String {
         format!(
             "wgpu 0.15 ({:?})",
@@ -336,3 +1017,45 @@ impl WmRenderer {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ConfigError, WmConfig, WmRenderer};
+
+    #[test]
+    fn atlas_size_is_validated() {
+        let limits = wgpu::Limits::default();
+        let config = |atlas_size| WmConfig {
+            atlas_size,
+            ..Default::default()
+        };
+
+        assert_eq!(config(None).validate(&limits), Ok(()));
+        assert_eq!(config(Some(4096)).validate(&limits), Ok(()));
+        assert_eq!(
+            config(Some(3000)).validate(&limits),
+            Err(ConfigError::AtlasSizeNotPowerOfTwo(3000))
+        );
+        assert_eq!(
+            config(Some(16384)).validate(&limits),
+            Err(ConfigError::AtlasSizeTooLarge {
+                size: 16384,
+                max_size: 8192
+            })
+        );
+    }
+
+    #[test]
+    fn pbr_requires_two_more_bind_groups() {
+        let limits = |pbr| {
+            WmRenderer::compute_required_limits(&WmConfig {
+                pbr,
+                ..Default::default()
+            })
+        };
+
+        //What every WebGPU device supports
+        assert_eq!(limits(false).max_bind_groups, 4);
+        assert_eq!(limits(true).max_bind_groups, 6);
+    }
+}
@@ -0,0 +1,227 @@
+//! # Biome colors
+//!
+//! Grass, foliage and water are grey in their textures, and the faces of their models with a `tintindex` are
+//! multiplied with a color which depends on the biome they're in, like Minecraft's `BlockColors`. Grass and foliage
+//! look their color up in the `grass.png` and `foliage.png` colormaps by the temperature and downfall of the biome,
+//! water uses the color of the biome directly.
+//!
+//! The biome of each block comes from [crate::mc::chunk::BlockStateProvider::get_biome], and the tints are
+//! resolved while baking, so chunks have to be re-baked for a change of the colormaps to show up.
+
+use std::collections::HashMap;
+
+use image::RgbaImage;
+
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+
+/// The climate and colors of a biome
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Biome {
+    pub temperature: f32,
+    pub downfall: f32,
+    pub water_color: [u8; 3],
+    /// Replaces the color from the grass colormap, like in badlands
+    pub grass_color: Option<[u8; 3]>,
+    /// Replaces the color from the foliage colormap, like in badlands
+    pub foliage_color: Option<[u8; 3]>,
+}
+
+impl Biome {
+    pub const PLAINS: Self = Self {
+        temperature: 0.8,
+        downfall: 0.4,
+        water_color: [0x3f, 0x76, 0xe4],
+        grass_color: None,
+        foliage_color: None,
+    };
+}
+
+impl Default for Biome {
+    fn default() -> Self {
+        Self::PLAINS
+    }
+}
+
+/// A 256x256 colormap like `textures/colormap/grass.png`. The colder a biome the further right its color is, and
+/// the drier the further down.
+#[derive(Debug)]
+pub struct Colormap {
+    image: RgbaImage,
+}
+
+impl Colormap {
+    /// Loads the colormap, [None] if it doesn't exist or isn't a valid image
+    pub fn load(resource_provider: &dyn ResourceProvider, path: &ResourcePath) -> Option<Self> {
+        let bytes = resource_provider.get_bytes(path)?;
+        let image = image::load_from_memory(&bytes).ok()?.to_rgba8();
+
+        Some(Self { image })
+    }
+
+    /// The pixels of the colormap, which [crate::render::colormap] uploads for the shaders
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// The same lookup as Minecraft's `GrassColor.get` and `FoliageColor.get`
+    #[must_use]
+    pub fn sample(&self, temperature: f32, downfall: f32) -> [u8; 3] {
+        let temperature = temperature.clamp(0.0, 1.0);
+        let downfall = downfall.clamp(0.0, 1.0) * temperature;
+
+        let x = ((1.0 - temperature) * 255.0) as u32;
+        let y = ((1.0 - downfall) * 255.0) as u32;
+
+        if x >= self.image.width() || y >= self.image.height() {
+            return MISSING_COLOR;
+        }
+
+        let [r, g, b, _] = self.image.get_pixel(x, y).0;
+
+        [r, g, b]
+    }
+}
+
+/// What Minecraft returns for positions outside of a colormap which is too small
+const MISSING_COLOR: [u8; 3] = [0xff, 0x00, 0xff];
+
+/// The color of grass and foliage when their colormap couldn't be loaded
+pub(crate) const DEFAULT_COLOR: [u8; 3] = [0x48, 0xb5, 0x18];
+
+/// What the tinted faces of a block are multiplied with
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum TintSource {
+    Grass,
+    Foliage,
+    Water,
+    /// The same color in every biome, like spruce and birch leaves
+    Fixed([u8; 3]),
+}
+
+/// The [TintSource] of the vanilla block, by its name like `minecraft:grass_block`
+#[must_use]
+pub fn vanilla_tint(block: &str) -> Option<TintSource> {
+    let path = block.strip_prefix("minecraft:")?;
+
+    Some(match path {
+        "grass_block" | "grass" | "short_grass" | "tall_grass" | "fern" | "large_fern"
+        | "potted_fern" | "sugar_cane" => TintSource::Grass,
+        "oak_leaves" | "jungle_leaves" | "acacia_leaves" | "dark_oak_leaves"
+        | "mangrove_leaves" | "vine" => TintSource::Foliage,
+        "water" | "bubble_column" | "water_cauldron" => TintSource::Water,
+        "spruce_leaves" => TintSource::Fixed([0x61, 0x99, 0x61]),
+        "birch_leaves" => TintSource::Fixed([0x80, 0xa7, 0x55]),
+        "lily_pad" => TintSource::Fixed([0x20, 0x80, 0x30]),
+        "attached_melon_stem" | "attached_pumpkin_stem" => TintSource::Fixed([0xe0, 0xc7, 0x1c]),
+        _ => return None,
+    })
+}
+
+/// The colormaps and the tints of the blocks
+#[derive(Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct BlockColors {
+    /// Loaded again from the resource packs after deserializing, see [BlockColors::load_colormaps]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub grass: Option<Colormap>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub foliage: Option<Colormap>,
+    /// Maps indices into [crate::mc::BlockManager::blocks] to what their tinted faces are multiplied with. Blocks
+    /// without an entry aren't tinted, even if their models have a `tintindex`.
+    pub tints: HashMap<u16, TintSource>,
+}
+
+impl BlockColors {
+    /// Loads `grass.png` and `foliage.png` again, after the resource packs changed
+    pub fn load_colormaps(&mut self, resource_provider: &dyn ResourceProvider) {
+        self.grass = Colormap::load(
+            resource_provider,
+            &ResourcePath::from("minecraft:textures/colormap/grass.png"),
+        );
+        self.foliage = Colormap::load(
+            resource_provider,
+            &ResourcePath::from("minecraft:textures/colormap/foliage.png"),
+        );
+    }
+
+    /// The color the tinted faces of the block are multiplied with in the biome, from 0 to 1. [None] if the block
+    /// isn't tinted.
+    #[must_use]
+    pub fn tint(&self, block: u16, biome: &Biome) -> Option<[f32; 3]> {
+        let sample = |colormap: &Option<Colormap>| {
+            colormap.as_ref().map_or(DEFAULT_COLOR, |colormap| {
+                colormap.sample(biome.temperature, biome.downfall)
+            })
+        };
+
+        let color = match self.tints.get(&block)? {
+            TintSource::Grass => biome.grass_color.unwrap_or_else(|| sample(&self.grass)),
+            TintSource::Foliage => biome.foliage_color.unwrap_or_else(|| sample(&self.foliage)),
+            TintSource::Water => biome.water_color,
+            TintSource::Fixed(color) => *color,
+        };
+
+        Some(color.map(|channel| channel as f32 / 255.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::{vanilla_tint, Biome, BlockColors, Colormap, TintSource};
+
+    #[test]
+    fn colormaps_are_sampled_by_temperature_and_downfall() {
+        let colormap = Colormap {
+            image: RgbaImage::from_fn(256, 256, |x, y| Rgba([x as u8, y as u8, 0, 255])),
+        };
+
+        //Hot and wet biomes are in the top left corner
+        assert_eq!(colormap.sample(1.0, 1.0), [0, 0, 0]);
+        //Downfall is scaled by the temperature, so cold biomes are always at the bottom
+        assert_eq!(colormap.sample(0.0, 1.0), [255, 255, 0]);
+        assert_eq!(colormap.sample(0.5, 0.5), [127, 191, 0]);
+        //Out of range climates are clamped
+        assert_eq!(colormap.sample(2.0, -1.0), [0, 255, 0]);
+    }
+
+    #[test]
+    fn blocks_are_tinted_by_their_source() {
+        let mut colors = BlockColors::default();
+        colors
+            .tints
+            .insert(0, vanilla_tint("minecraft:grass_block").unwrap());
+        colors
+            .tints
+            .insert(1, vanilla_tint("minecraft:water").unwrap());
+
+        let biome = Biome {
+            grass_color: Some([255, 0, 0]),
+            ..Biome::PLAINS
+        };
+
+        assert_eq!(colors.tint(0, &biome), Some([1.0, 0.0, 0.0]));
+        assert_eq!(
+            colors.tint(1, &biome),
+            Some([
+                0x3f as f32 / 255.0,
+                0x76 as f32 / 255.0,
+                0xe4 as f32 / 255.0
+            ])
+        );
+        assert_eq!(colors.tint(2, &biome), None);
+        assert_eq!(vanilla_tint("minecraft:stone"), None);
+        assert_eq!(
+            vanilla_tint("minecraft:birch_leaves"),
+            Some(TintSource::Fixed([0x80, 0xa7, 0x55]))
+        );
+    }
+}
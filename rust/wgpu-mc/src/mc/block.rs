@@ -1,11 +1,15 @@
+use std::sync::Arc;
+
 use bytemuck::{Pod, Zeroable};
-use cgmath::{Deg, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
+use cgmath::{Deg, InnerSpace, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
 use minecraft_assets::api::ModelResolver;
 use minecraft_assets::schemas;
 use serde_derive::{Deserialize, Serialize};
 
+use crate::mc::chunk::FACE_NORMALS;
+use crate::mc::ctm::{ConnectedFace, ConnectedTextures};
 use crate::mc::resource::ResourceProvider;
-use crate::render::atlas::{Atlas, ATLAS_DIMENSIONS};
+use crate::render::atlas::{Atlas, AtlasError};
 use crate::texture::UV;
 
 use super::resource::ResourcePath;
@@ -42,8 +46,59 @@ impl From<u32> for BlockstateKey {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Up,
+    Down,
+    North,
+    East,
+    South,
+    West,
+}
+
+/// The collision/selection shape of a block, independent of how it's rendered. Used for ray-casting.
+/// Shapes are read from `<namespace>:shapes/<block>.json`, for example `{"half_slab": "down"}`
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlockShape {
+    #[default]
+    FullCube,
+    /// A half-block which is attached to the given side of the block space
+    HalfSlab(Direction),
+    Fence,
+    /// Cross-shaped plants such as flowers and saplings
+    Cross,
+    /// A list of AABBs in block units (0.0 to 1.0), formatted as `[min_x, min_y, min_z, max_x, max_y, max_z]`
+    Custom(Vec<[f32; 6]>),
+}
+
+impl BlockShape {
+    /// Get the AABBs which make up this shape, in block units
+    pub fn aabbs(&self) -> Vec<[f32; 6]> {
+        match self {
+            BlockShape::FullCube => vec![[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]],
+            BlockShape::HalfSlab(direction) => vec![match direction {
+                Direction::Up => [0.0, 0.5, 0.0, 1.0, 1.0, 1.0],
+                Direction::Down => [0.0, 0.0, 0.0, 1.0, 0.5, 1.0],
+                Direction::North => [0.0, 0.0, 0.0, 1.0, 1.0, 0.5],
+                Direction::South => [0.0, 0.0, 0.5, 1.0, 1.0, 1.0],
+                Direction::West => [0.0, 0.0, 0.0, 0.5, 1.0, 1.0],
+                Direction::East => [0.5, 0.0, 0.0, 1.0, 1.0, 1.0],
+            }],
+            BlockShape::Fence => vec![[0.375, 0.0, 0.375, 0.625, 1.5, 0.625]],
+            BlockShape::Cross => vec![[0.3125, 0.0, 0.3125, 0.6875, 0.625, 0.6875]],
+            BlockShape::Custom(aabbs) => aabbs.clone(),
+        }
+    }
+}
+
 ///The state of one block, describing which variant
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub enum ChunkBlockState {
     Air,
     State(BlockstateKey),
@@ -58,14 +113,38 @@ impl ChunkBlockState {
 ///Represents a vertex in a block mesh, including an additional UV offset index for animated textures.
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct BlockMeshVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 4],
     pub animation_uv_offset: u32,
+    /// Where the emissive overlay of the texture is at this vertex, or [NO_EMISSIVE]
+    pub emissive_tex_coords: [f32; 2],
+    /// The `tintindex` of the model face, or [NO_TINT]. Tinted faces are multiplied with the color of the block
+    /// in its biome while baking, see [crate::mc::biome].
+    pub tint_index: i32,
 }
 
+/// The suffix of the emissive overlay of a texture, like `block/sea_lantern_e`, which is drawn on top of the
+/// texture without being darkened by the lightmap
+pub const EMISSIVE_SUFFIX: &str = "_e";
+
+/// The `emissive_tex_coords` of vertices whose texture has no emissive overlay. Shaders check for a negative
+/// coordinate.
+pub const NO_EMISSIVE: [f32; 2] = [-1.0, -1.0];
+
+/// The `tint_index` of vertices whose face isn't tinted, the same as Minecraft's default `tintindex`
+pub const NO_TINT: i32 = -1;
+
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct BlockModelFaces {
     pub north: Option<[BlockMeshVertex; 6]>,
     pub east: Option<[BlockMeshVertex; 6]>,
@@ -73,9 +152,16 @@ pub struct BlockModelFaces {
     pub west: Option<[BlockMeshVertex; 6]>,
     pub up: Option<[BlockMeshVertex; 6]>,
     pub down: Option<[BlockMeshVertex; 6]>,
+    /// The faces which are connected textures, in north, east, south, west, up, down order, see [crate::mc::ctm]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub connected: [Option<Arc<ConnectedFace>>; 6],
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 ///Makes chunk mesh baking a bit faster
 pub enum CubeOrComplexMesh {
     ///Known to be a simple cube. Only cubes are eligible for sides to be culled depending on the state of it's neighbours
@@ -90,6 +176,7 @@ fn recurse_model_parents(
     models: &mut Vec<ResourcePath>,
 ) {
     match &model.parent {
+        Some(parent_path_string) if is_builtin(parent_path_string) => {}
         Some(parent_path_string) => {
             let parent_path: ResourcePath = ResourcePath::from(parent_path_string)
                 .prepend("models/")
@@ -110,7 +197,16 @@ fn recurse_model_parents(
     }
 }
 
-fn resolve_model(
+/// Whether the model is one of Minecraft's built-in models like `builtin/generated`, which are at the root of item
+/// models and don't exist as files
+pub(crate) fn is_builtin(model: &str) -> bool {
+    model
+        .split(':')
+        .last()
+        .map_or(false, |path| path.starts_with("builtin/"))
+}
+
+pub(crate) fn resolve_model(
     model: schemas::Model,
     resource_provider: &dyn ResourceProvider,
 ) -> schemas::Model {
@@ -148,7 +244,9 @@ fn get_atlas_uv(face: &schemas::models::ElementFace, block_atlas: &Atlas) -> Opt
 
     let atlas_uv = atlas_map.get(&(&face.texture.0).into())?;
 
-    const ATLAS: f32 = ATLAS_DIMENSIONS as f32;
+    let atlas_size = block_atlas.size() as f32;
+    //Keeps the edges of the face from sampling the neighbouring textures
+    let inset = block_atlas.uv_inset();
 
     let _middle_x = atlas_uv.0 .0 + (atlas_uv.1 .0 / 2.0);
     let _middle_y = atlas_uv.0 .1 + (atlas_uv.1 .1 / 2.0);
@@ -159,12 +257,661 @@ fn get_atlas_uv(face: &schemas::models::ElementFace, block_atlas: &Atlas) -> Opt
 
     let mat = Matrix3::identity();
 
-    let uv1 = mat * Vector3::new((atlas_uv.0 .0) / ATLAS, (atlas_uv.0 .1) / ATLAS, 1.0);
-    let uv2 = mat * Vector3::new((atlas_uv.1 .0) / ATLAS, (atlas_uv.1 .1) / ATLAS, 1.0);
+    let uv1 = mat
+        * Vector3::new(
+            (atlas_uv.0 .0 + inset) / atlas_size,
+            (atlas_uv.0 .1 + inset) / atlas_size,
+            1.0,
+        );
+    let uv2 = mat
+        * Vector3::new(
+            (atlas_uv.1 .0 - inset) / atlas_size,
+            (atlas_uv.1 .1 - inset) / atlas_size,
+            1.0,
+        );
 
     Some(((uv1.x, uv1.y), (uv2.x, uv2.y)))
 }
 
+/// Moves texture coordinates within one UV to the same place within another
+pub(crate) fn remap_tex_coords([u, v]: [f32; 2], from: UV, to: UV) -> [f32; 2] {
+    let ((min_u, min_v), (max_u, max_v)) = from;
+    let ((to_min_u, to_min_v), (to_max_u, to_max_v)) = to;
+
+    [
+        to_min_u + (u - min_u) / (max_u - min_u) * (to_max_u - to_min_u),
+        to_min_v + (v - min_v) / (max_v - min_v) * (to_max_v - to_min_v),
+    ]
+}
+
+/// Points the vertices of a face at the emissive overlay of its texture, if it has one in the atlas
+fn add_emissive(vertices: &mut [BlockMeshVertex; 6], texture: &ResourcePath, block_atlas: &Atlas) {
+    let uvs =
+        atlas_uv(block_atlas, texture).zip(atlas_uv(block_atlas, &texture.append(EMISSIVE_SUFFIX)));
+
+    if let Some((uv, emissive_uv)) = uvs {
+        for vertex in vertices {
+            vertex.emissive_tex_coords = remap_tex_coords(vertex.tex_coords, uv, emissive_uv);
+        }
+    }
+}
+
+/// The UV of the texture in the atlas, from 0 to 1
+fn atlas_uv(block_atlas: &Atlas, texture: &ResourcePath) -> Option<UV> {
+    let ((min_x, min_y), (max_x, max_y)) = *block_atlas.uv_map.read().get(texture)?;
+    let atlas_size = block_atlas.size() as f32;
+
+    Some((
+        (min_x / atlas_size, min_y / atlas_size),
+        (max_x / atlas_size, max_y / atlas_size),
+    ))
+}
+
+/// The face as a connected texture, if one of the rules applies to it. The tiles of the rule are allocated in the
+/// atlas if they aren't already.
+fn connected_face(
+    face: &schemas::models::ElementFace,
+    connected_textures: &ConnectedTextures,
+    block: &ResourcePath,
+    resource_provider: &dyn ResourceProvider,
+    block_atlas: &Atlas,
+) -> Result<Option<Arc<ConnectedFace>>, MeshBakeError> {
+    let texture: ResourcePath = (&face.texture.0).into();
+
+    let rule = match connected_textures.rule(block, &texture) {
+        Some(rule) => rule,
+        None => return Ok(None),
+    };
+
+    let unallocated_tiles: Vec<(&ResourcePath, Vec<u8>)> = rule
+        .tiles
+        .iter()
+        .filter(|tile| !block_atlas.uv_map.read().contains_key(tile))
+        .map(|tile| {
+            resource_provider
+                .get_bytes(tile)
+                .map(|bytes| (tile, bytes))
+                .ok_or_else(|| MeshBakeError::UnresolvedResourcePath(tile.clone()))
+        })
+        .collect::<Result<_, _>>()?;
+
+    if !unallocated_tiles.is_empty() {
+        block_atlas
+            .allocate_missing(
+                unallocated_tiles.iter().map(|(tile, bytes)| (*tile, bytes)),
+                resource_provider,
+            )
+            .map_err(MeshBakeError::AtlasError)?;
+    }
+
+    let tiles = rule
+        .tiles
+        .iter()
+        .map(|tile| atlas_uv(block_atlas, tile))
+        .collect::<Option<Vec<UV>>>();
+
+    Ok(atlas_uv(block_atlas, &texture)
+        .zip(tiles)
+        .map(|(texture, tiles)| {
+            Arc::new(ConnectedFace {
+                rule: rule.clone(),
+                texture,
+                tiles,
+            })
+        }))
+}
+
+/// Allocates the textures of the resolved model in the block atlas if they aren't already, together with their
+/// emissive overlays and LabPBR maps, see [Atlas::allocate]
+pub(crate) fn allocate_textures(
+    model: &schemas::Model,
+    resource_provider: &dyn ResourceProvider,
+    block_atlas: &Atlas,
+) -> Result<(), MeshBakeError> {
+    if let Some(textures) = &model.textures {
+        //Make sure the textures in the model are fully resolved with no references
+        if let Some(reference) = textures
+            .iter()
+            .find(|(_key, value)| value.reference().is_some())
+        {
+            return Err(MeshBakeError::UnresolvedTextureReference(format!(
+                "key: {} value: {:?}",
+                reference.0, reference.1
+            )));
+        }
+
+        let uv_map = block_atlas.uv_map.read();
+
+        let unallocated_textures: Vec<ResourcePath> = textures
+            .iter()
+            .filter_map(|(_, texture)| {
+                let texture_id: ResourcePath = (&texture.0).into();
+                if !uv_map.contains_key(&texture_id) {
+                    //Block UV atlas doesn't contain a texture, so we add it
+                    Some(texture_id)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        drop(uv_map);
+
+        //Emissive overlays are packed alongside the textures they belong to, if there are any
+        let unallocated_textures: Vec<(ResourcePath, Vec<u8>)> = unallocated_textures
+            .iter()
+            .flat_map(|path| {
+                let emissive = path.append(EMISSIVE_SUFFIX);
+                let emissive_bytes =
+                    resource_provider.get_bytes(&emissive.prepend("textures/").append(".png"));
+
+                [
+                    (
+                        path.clone(),
+                        Some(
+                            resource_provider
+                                .get_bytes(&path.prepend("textures/").append(".png"))
+                                .unwrap(),
+                        ),
+                    ),
+                    (emissive, emissive_bytes),
+                ]
+            })
+            .filter_map(|(path, bytes)| Some((path, bytes?)))
+            .collect();
+
+        //Other models baked in parallel might have allocated some of them since the check above
+        if !unallocated_textures.is_empty() {
+            block_atlas
+                .allocate_missing(
+                    unallocated_textures.iter().map(|(path, data)| (path, data)),
+                    resource_provider,
+                )
+                .map_err(MeshBakeError::AtlasError)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A position of a model in pixels, as a position in the block space from 0 to 1. The x coordinate is mirrored.
+fn model_position([x, y, z]: [f32; 3]) -> [f32; 3] {
+    [1.0 - x / 16.0, y / 16.0, z / 16.0]
+}
+
+/// The texture coordinates in pixels of a position on a face pointing in the direction (north, east, south, west,
+/// up, down), which is what faces without a `uv` use. Faces are textured the same way as before models could be
+/// rotated.
+fn default_uv(direction: usize, position: [f32; 3]) -> [f32; 2] {
+    let [x, y, z] = position.map(|coordinate| coordinate * 16.0);
+
+    match direction {
+        0 => [16.0 - x, 16.0 - y],
+        1 => [16.0 - z, 16.0 - y],
+        2 => [x, 16.0 - y],
+        3 => [z, 16.0 - y],
+        4 => [16.0 - x, 16.0 - z],
+        _ => [x, z],
+    }
+}
+
+/// Like [default_uv], with the default UVs of the element stretched to the `uv` of the face if it has one
+fn element_face_uv(
+    direction: usize,
+    position: [f32; 3],
+    element: &schemas::models::Element,
+    uv: Option<[f32; 4]>,
+) -> [f32; 2] {
+    let [u, v] = default_uv(direction, position);
+
+    let [min_u, min_v, max_u, max_v] = match uv {
+        None => return [u, v],
+        Some(uv) => uv,
+    };
+
+    let [from_u, from_v] = default_uv(direction, model_position(element.from));
+    let [to_u, to_v] = default_uv(direction, model_position(element.to));
+
+    let remap = |value: f32, from: f32, to: f32, min: f32, max: f32| {
+        let (low, high) = (from.min(to), from.max(to));
+
+        //The face has no extent along this axis
+        if high - low < f32::EPSILON {
+            min
+        } else {
+            min + (value - low) / (high - low) * (max - min)
+        }
+    };
+
+    [
+        remap(u, from_u, to_u, min_u, max_u),
+        remap(v, from_v, to_v, min_v, max_v),
+    ]
+}
+
+/// Texture coordinates in pixels of a 16x16 texture, as coordinates in the atlas
+fn atlas_tex_coords([u, v]: [f32; 2], uv: UV) -> [f32; 2] {
+    let ((min_u, min_v), (max_u, max_v)) = uv;
+
+    [
+        min_u + u / 16.0 * (max_u - min_u),
+        min_v + v / 16.0 * (max_v - min_v),
+    ]
+}
+
+/// The rotation of the element around its origin, scaled back up along the other two axes with `rescale`. The
+/// rotations around y and z go the other way, as model x coordinates are mirrored.
+fn element_rotation(rotation: &schemas::models::ElementRotation) -> Matrix4<f32> {
+    let origin = Vector3::from(model_position(rotation.origin));
+    let scale = if rotation.rescale {
+        1.0 / rotation.angle.to_radians().cos()
+    } else {
+        1.0
+    };
+
+    let (rotation, scale) = match rotation.axis {
+        schemas::models::Axis::X => (
+            Matrix4::from_angle_x(Deg(rotation.angle)),
+            [1.0, scale, scale],
+        ),
+        schemas::models::Axis::Y => (
+            Matrix4::from_angle_y(Deg(-rotation.angle)),
+            [scale, 1.0, scale],
+        ),
+        schemas::models::Axis::Z => (
+            Matrix4::from_angle_z(Deg(-rotation.angle)),
+            [scale, scale, 1.0],
+        ),
+    };
+
+    Matrix4::from_translation(origin)
+        * Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2])
+        * rotation
+        * Matrix4::from_translation(-origin)
+}
+
+fn transform_vertex(vertex: &mut BlockMeshVertex, transform: Matrix4<f32>) {
+    let [x, y, z] = vertex.position;
+    let [normal_x, normal_y, normal_z, normal_w] = vertex.normal;
+
+    let normal = (transform * Vector4::new(normal_x, normal_y, normal_z, 0.0))
+        .truncate()
+        .normalize();
+
+    vertex.position = (transform * Vector4::new(x, y, z, 1.0)).truncate().into();
+    vertex.normal = [normal.x, normal.y, normal.z, normal_w];
+}
+
+/// The direction (north, east, south, west, up, down) the vertex is facing, if it's facing straight along an axis
+fn facing(vertex: &BlockMeshVertex) -> Option<usize> {
+    FACE_NORMALS.iter().position(|face_normal| {
+        let dot: f32 = (0..3)
+            .map(|axis| face_normal[axis] as f32 * vertex.normal[axis])
+            .sum();
+
+        dot > 0.999
+    })
+}
+
+/// Bakes one element of a resolved model, with its vertices transformed by its own rotation and then the matrix.
+/// With `uv_lock` the textures of the faces are aligned to the block grid after the transform, otherwise they turn
+/// with the faces. The faces are connected textures if one of the rules of `connected_textures` applies to them for
+/// the named block.
+pub(crate) fn bake_element(
+    element: &schemas::models::Element,
+    matrix: Matrix4<f32>,
+    uv_lock: bool,
+    connected_textures: Option<(&ConnectedTextures, &ResourcePath)>,
+    resource_provider: &dyn ResourceProvider,
+    block_atlas: &Atlas,
+) -> Result<BlockModelFaces, MeshBakeError> {
+    //Face textures
+
+    let north = element
+        .faces
+        .get(&schemas::models::BlockFace::North)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let east = element
+        .faces
+        .get(&schemas::models::BlockFace::East)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let south = element
+        .faces
+        .get(&schemas::models::BlockFace::South)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let west = element
+        .faces
+        .get(&schemas::models::BlockFace::West)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let up = element
+        .faces
+        .get(&schemas::models::BlockFace::Up)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let down = element
+        .faces
+        .get(&schemas::models::BlockFace::Down)
+        .as_ref()
+        .and_then(|tex| {
+            get_atlas_uv(tex, block_atlas).map(|uv| {
+                (
+                    //The default UV for this texture
+                    uv,
+                    //If this texture has an animation, get the offset, otherwise default to 0
+                    *block_atlas
+                        .animated_texture_offsets
+                        .read()
+                        .get(&(&tex.texture.0).into())
+                        .unwrap_or(&0),
+                )
+            })
+        });
+
+    let [from_x, from_y, from_z] = element.from;
+    let [to_x, to_y, to_z] = element.to;
+
+    //The corners of the element before it's rotated
+    let a = model_position([from_x, from_y, from_z]);
+    let b = model_position([to_x, from_y, from_z]);
+    let c = model_position([to_x, to_y, from_z]);
+    let d = model_position([from_x, to_y, from_z]);
+    let e = model_position([from_x, from_y, to_z]);
+    let f = model_position([to_x, from_y, to_z]);
+    let g = model_position([to_x, to_y, to_z]);
+    let h = model_position([from_x, to_y, to_z]);
+
+    #[rustfmt::skip]
+    let mut faces = BlockModelFaces {
+        south: south.map(|south| {[
+            BlockMeshVertex { position: e, tex_coords: [south.0.1.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: h, tex_coords: [south.0.1.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: f, tex_coords: [south.0.0.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: h, tex_coords: [south.0.1.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: g, tex_coords: [south.0.0.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: f, tex_coords: [south.0.0.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        west: west.map(|west| {[
+            BlockMeshVertex { position: g, tex_coords: [west.0.1.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: b, tex_coords: [west.0.0.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: f, tex_coords: [west.0.1.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: c, tex_coords: [west.0.0.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: b, tex_coords: [west.0.0.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: g, tex_coords: [west.0.1.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        north: north.map(|north| {[
+            BlockMeshVertex { position: c, tex_coords: [north.0.1.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: a, tex_coords: [north.0.0.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: b, tex_coords: [north.0.1.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: d, tex_coords: [north.0.0.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: a, tex_coords: [north.0.0.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: c, tex_coords: [north.0.1.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        east: east.map(|east| {[
+            BlockMeshVertex { position: e, tex_coords: [east.0.0.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: a, tex_coords: [east.0.1.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: d, tex_coords: [east.0.1.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: d, tex_coords: [east.0.1.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: h, tex_coords: [east.0.0.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: e, tex_coords: [east.0.0.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        up: up.map(|up| {[
+            BlockMeshVertex { position: g, tex_coords: [up.0.1.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: h, tex_coords: [up.0.0.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: d, tex_coords: [up.0.0.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: c, tex_coords: [up.0.1.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: g, tex_coords: [up.0.1.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: d, tex_coords: [up.0.0.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        down: down.map(|down| {[
+            BlockMeshVertex { position: f, tex_coords: [down.0.0.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: b, tex_coords: [down.0.0.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: a, tex_coords: [down.0.1.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: f, tex_coords: [down.0.0.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: a, tex_coords: [down.0.1.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+            BlockMeshVertex { position: e, tex_coords: [down.0.1.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1, emissive_tex_coords: NO_EMISSIVE, tint_index: NO_TINT },
+        ]}),
+        connected: Default::default(),
+    };
+
+    let directions = [
+        schemas::models::BlockFace::North,
+        schemas::models::BlockFace::East,
+        schemas::models::BlockFace::South,
+        schemas::models::BlockFace::West,
+        schemas::models::BlockFace::Up,
+        schemas::models::BlockFace::Down,
+    ];
+
+    let transform = matrix * element_rotation(&element.rotation);
+
+    let face_vertices = [
+        faces.north.take(),
+        faces.east.take(),
+        faces.south.take(),
+        faces.west.take(),
+        faces.up.take(),
+        faces.down.take(),
+    ];
+
+    let mut baked = Vec::new();
+
+    for (index, (vertices, direction)) in face_vertices.into_iter().zip(directions).enumerate() {
+        let (mut vertices, face) = match (vertices, element.faces.get(&direction)) {
+            (Some(vertices), Some(face)) => (vertices, face),
+            _ => continue,
+        };
+
+        //Texture coordinates come from where the face is in the element before it's rotated
+        if let Some(uv) = get_atlas_uv(face, block_atlas) {
+            for vertex in &mut vertices {
+                let face_uv = element_face_uv(index, vertex.position, element, face.uv);
+                vertex.tex_coords = atlas_tex_coords(face_uv, uv);
+            }
+        }
+
+        for vertex in &mut vertices {
+            transform_vertex(vertex, transform);
+        }
+
+        baked.push((index, vertices, face));
+    }
+
+    //Faces which are rotated by a multiple of 90 degrees take the place of the direction they're facing now, so
+    //that they're culled against the right neighbour. Other rotations leave every face where it was.
+    let facings: Option<Vec<usize>> = baked
+        .iter()
+        .map(|(_, vertices, _)| facing(&vertices[0]))
+        .collect();
+
+    let mut slots: [Option<[BlockMeshVertex; 6]>; 6] = Default::default();
+
+    for (baked_index, (index, mut vertices, face)) in baked.into_iter().enumerate() {
+        let slot = facings
+            .as_ref()
+            .map_or(index, |facings| facings[baked_index]);
+
+        //The texture stays aligned to the block grid, however the model is rotated
+        if uv_lock {
+            if let Some(uv) = get_atlas_uv(face, block_atlas) {
+                for vertex in &mut vertices {
+                    vertex.tex_coords = atlas_tex_coords(default_uv(slot, vertex.position), uv);
+                }
+            }
+        }
+
+        add_emissive(&mut vertices, &(&face.texture.0).into(), block_atlas);
+
+        for vertex in &mut vertices {
+            vertex.tint_index = face.tint_index;
+        }
+
+        if let Some((connected_textures, block)) = connected_textures {
+            faces.connected[slot] = connected_face(
+                face,
+                connected_textures,
+                block,
+                resource_provider,
+                block_atlas,
+            )?;
+        }
+
+        slots[slot] = Some(vertices);
+    }
+
+    let [north, east, south, west, up, down] = slots;
+    faces.north = north;
+    faces.east = east;
+    faces.south = south;
+    faces.west = west;
+    faces.up = up;
+    faces.down = down;
+
+    Ok(faces)
+}
+
+/// Which pass a block is drawn in, the same as Minecraft's render layers. Unlike in Minecraft, it's inferred from
+/// the alpha of the block's textures.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum RenderType {
+    /// Every pixel is fully opaque
+    #[default]
+    Solid,
+    /// Pixels are either fully opaque or fully transparent, like leaves or glass. Drawn with alpha testing.
+    Cutout,
+    /// Some pixels are partially transparent, like water or stained glass. Drawn with blending, after the
+    /// other render types.
+    Translucent,
+}
+
+impl RenderType {
+    pub const ALL: [Self; 3] = [Self::Solid, Self::Cutout, Self::Translucent];
+
+    /// Defined for the shaders of the terrain pipelines which draw this render type, so that they can share a
+    /// shader with a permutation for each, see [crate::render::shader::ShaderSource::with_features]
+    #[must_use]
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Self::Solid => "SOLID",
+            Self::Cutout => "CUTOUT",
+            Self::Translucent => "TRANSLUCENT",
+        }
+    }
+}
+
+/// Looks at the alpha of every pixel of the texture in the atlas. Textures which aren't in the atlas are treated
+/// as [RenderType::Cutout], so that they never cull their neighbours.
+fn texture_render_type(texture: &ResourcePath, block_atlas: &Atlas) -> RenderType {
+    let uv_map = block_atlas.uv_map.read();
+
+    let ((min_x, min_y), (max_x, max_y)) = match uv_map.get(texture) {
+        Some(uv) => *uv,
+        None => return RenderType::Cutout,
+    };
+
+    let image = block_atlas.image.read();
+
+    (min_y as u32..max_y as u32)
+        .flat_map(|y| (min_x as u32..max_x as u32).map(move |x| (x, y)))
+        .map(|(x, y)| match image.get_pixel(x, y).0[3] {
+            255 => RenderType::Solid,
+            0 => RenderType::Cutout,
+            _ => RenderType::Translucent,
+        })
+        .max()
+        .unwrap_or(RenderType::Solid)
+}
+
+/// The most transparent render type of the textures of the elements
+pub(crate) fn elements_render_type<'a>(
+    elements: impl IntoIterator<Item = &'a schemas::models::Element>,
+    block_atlas: &Atlas,
+) -> RenderType {
+    elements
+        .into_iter()
+        .flat_map(|element| element.faces.values())
+        .map(|face| texture_render_type(&(&face.texture.0).into(), block_atlas))
+        .max()
+        .unwrap_or(RenderType::Solid)
+}
+
 pub struct RenderSettings {
     pub opaque: bool,
 }
@@ -175,259 +922,317 @@ pub enum MeshBakeError {
     UnresolvedTextureReference(String),
     UnresolvedResourcePath(ResourcePath),
     JsonError(serde_json::Error),
+    AtlasError(AtlasError),
 }
 
 /// A block model which has been baked into a mesh and is ready for rendering
 /// The bool is true when the blocks next to this block should be rendered,
 /// i.e. when this block does not fully obscure all six faces.
-/// NOTE: Currently, only the first bool in the Vec is actually
-/// important for the implementation.
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct ModelMesh {
     pub models: Vec<(CubeOrComplexMesh, bool)>,
+    /// True when the geometry of every one of the models covers all six faces of the block space with fully
+    /// opaque pixels. Only these blocks are allowed to cull the faces of their neighbours.
+    pub is_full_opaque_cube: bool,
+    /// The most transparent render type of any of the textures of the models
+    pub render_type: RenderType,
+    /// The weight of each of the models. The models of a blockstate variant are alternatives, and each block
+    /// picks one of them by its position, see [ModelMesh::pick_model]
+    pub weights: Vec<u32>,
+}
+
+/// Based on the hash of a block position Minecraft seeds the randomness of its block models with, followed by
+/// the first `nextLong` of a `java.util.Random` with that seed
+fn position_random(x: i32, y: i32, z: i32) -> u32 {
+    let mut hash = x.wrapping_mul(3129871) as i64 ^ (z as i64).wrapping_mul(116129781) ^ y as i64;
+    hash = hash
+        .wrapping_mul(hash)
+        .wrapping_mul(42317861)
+        .wrapping_add(hash.wrapping_mul(11));
+
+    const MASK: u64 = (1 << 48) - 1;
+    let mut seed = ((hash >> 16) as u64 ^ 0x5DEECE66D) & MASK;
+
+    let mut next = || {
+        seed = seed.wrapping_mul(0x5DEECE66D).wrapping_add(0xB) & MASK;
+        (seed >> 16) as i32
+    };
+
+    let long = ((next() as i64) << 32).wrapping_add(next() as i64);
+
+    (long as i32).unsigned_abs()
+}
+
+/// Whether every model a block could pick is a full opaque cube. A block whose first model is one but whose other
+/// weighted models aren't mustn't cull its neighbours, as it doesn't know which model it ends up with.
+fn all_full_opaque_cubes(models: &[(CubeOrComplexMesh, bool)]) -> bool {
+    !models.is_empty()
+        && models
+            .iter()
+            .all(|(_, render_neighbours)| !render_neighbours)
 }
 
 impl ModelMesh {
+    /// The index of the model a blockstate variant uses at the absolute position, chosen by the weights of its
+    /// models. Always the same for the same position.
+    #[must_use]
+    pub fn pick_model(&self, x: i32, y: i32, z: i32) -> usize {
+        let total: u32 = self.weights.iter().sum();
+
+        if self.models.len() < 2 || total == 0 {
+            return 0;
+        }
+
+        let mut choice = position_random(x, y, z) % total;
+
+        for (index, weight) in self.weights.iter().enumerate() {
+            if choice < *weight {
+                return index;
+            }
+
+            choice -= weight;
+        }
+
+        0
+    }
+
     pub fn bake<'a>(
         variants: impl IntoIterator<Item = &'a schemas::blockstates::Variant>,
         resource_provider: &dyn ResourceProvider,
         block_atlas: &Atlas,
     ) -> Result<Self, MeshBakeError> {
-        let models = variants.into_iter()
+        Self::bake_connected(variants, resource_provider, block_atlas, None)
+    }
+
+    /// Like [ModelMesh::bake], but the faces of cube models which one of the rules of `connected_textures` applies
+    /// to for the named block are connected textures, see [crate::mc::ctm]
+    pub fn bake_connected<'a>(
+        variants: impl IntoIterator<Item = &'a schemas::blockstates::Variant>,
+        resource_provider: &dyn ResourceProvider,
+        block_atlas: &Atlas,
+        connected_textures: Option<(&ConnectedTextures, &ResourcePath)>,
+    ) -> Result<Self, MeshBakeError> {
+        let mut render_type = RenderType::Solid;
+
+        let model_properties: Vec<_> = variants
+            .into_iter()
             .flat_map(|variant| variant.models())
+            .collect();
+
+        let weights = model_properties
+            .iter()
+            .map(|model_properties| model_properties.weight)
+            .collect();
+
+        let models = model_properties
+            .into_iter()
             .map(|model_properties| {
-                let model_resource_path = ResourcePath::from(&model_properties.model).prepend("models/").append(".json");
+                let model_resource_path = ResourcePath::from(&model_properties.model)
+                    .prepend("models/")
+                    .append(".json");
 
                 //Recursively resolve the model using it's parents if it has any
                 let model: schemas::Model = resolve_model(
                     //Parse the JSON into the model schema
                     serde_json::from_str(
                         //Get the model JSON
-                        &resource_provider.get_string(&model_resource_path)
-                            .ok_or_else(|| MeshBakeError::UnresolvedResourcePath(model_resource_path))?
-                    ).map_err(MeshBakeError::JsonError)?,
-                    resource_provider
+                        &resource_provider
+                            .get_string(&model_resource_path)
+                            .ok_or_else(|| {
+                                MeshBakeError::UnresolvedResourcePath(model_resource_path)
+                            })?,
+                    )
+                    .map_err(MeshBakeError::JsonError)?,
+                    resource_provider,
                 );
 
-                if let Some(textures) = model.textures {
-                    //Make sure the textures in the model are fully resolved with no references
-                    if let Some(reference) = textures.iter().find(|(_key, value)| value.reference().is_some()) { return Err(MeshBakeError::UnresolvedTextureReference(format!("key: {} value: {:?}", reference.0, reference.1))) }
-
-                    let uv_map = block_atlas.uv_map.read();
-
-                    let unallocated_textures: Vec<ResourcePath> = textures.iter()
-                        .filter_map(|(_, texture)| {
-                            let texture_id: ResourcePath = (&texture.0).into();
-                            if !uv_map.contains_key(&texture_id) {
-                                //Block UV atlas doesn't contain a texture, so we add it
-                                Some(texture_id)
-                            } else {
-                                None
-                            }
-                        }).collect();
+                allocate_textures(&model, resource_provider, block_atlas)?;
 
-                    drop(uv_map);
-
-                    let unallocated_textures: Vec<(&ResourcePath, Vec<u8>)> = unallocated_textures
-                        .iter()
-                        .map(|path| (path, resource_provider.get_bytes(&path.prepend("textures/").append(".png")).unwrap()))
-                        .collect();
-
-                    if !unallocated_textures.is_empty() {
-                        block_atlas.allocate(
-                            unallocated_textures.iter()
-                                .map(|(path, data)| (*path, data)),
-                            resource_provider,
-                        );
-                    }
-                };
-
-                let matrix =
-                    Matrix4::from_translation(Vector3::new(0.5, 0.5, 0.5))
+                //Like in Minecraft the model is turned around x first, the rotation around x goes the other way as
+                //model x coordinates are mirrored
+                let matrix = Matrix4::from_translation(Vector3::new(0.5, 0.5, 0.5))
                     * Matrix4::from_angle_y(Deg(model_properties.y as f32))
+                    * Matrix4::from_angle_x(Deg(-(model_properties.x as f32)))
                     * Matrix4::from_translation(Vector3::new(-0.5, -0.5, -0.5));
 
-                let is_cube = model.elements.iter().len() == 1 && {
-                    match model.elements.iter().flatten().next() {
-                        Some(first) => {
-                            first.from[0] == 0.0
-                            && first.from[1] == 0.0
-                            && first.from[2] == 0.0
-                            && first.to[0] == 16.0
-                            && first.to[1] == 16.0
-                            && first.to[2] == 16.0
-                        },
-                        None => false,
-                    }
-                };
+                let is_cube = model
+                    .elements
+                    .as_ref()
+                    .map_or(false, |elements| elements.len() == 1)
+                    && {
+                        match model.elements.iter().flatten().next() {
+                            Some(first) => {
+                                first.from[0] == 0.0
+                                    && first.from[1] == 0.0
+                                    && first.from[2] == 0.0
+                                    && first.to[0] == 16.0
+                                    && first.to[1] == 16.0
+                                    && first.to[2] == 16.0
+                                    && first.rotation.angle == 0.0
+                            }
+                            None => false,
+                        }
+                    };
 
                 let mut results = model
                     .elements
                     .iter()
                     .flatten()
                     .map(|element| {
-                        //Face textures
-
-                        let north = element.faces.get(&schemas::models::BlockFace::North).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let east = element.faces.get(&schemas::models::BlockFace::East).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let south = element.faces.get(&schemas::models::BlockFace::South).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let west = element.faces.get(&schemas::models::BlockFace::West).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let up = element.faces.get(&schemas::models::BlockFace::Up).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let down = element.faces.get(&schemas::models::BlockFace::Down).as_ref().and_then(|tex|
-                            get_atlas_uv(
-                                tex,
-                                block_atlas,
-                            ).map(|uv| (
-                                //The default UV for this texture
-                                uv,
-                                //If this texture has an animation, get the offset, otherwise default to 0
-                                *block_atlas.animated_texture_offsets.read()
-                                    .get(&(&tex.texture.0).into())
-                                    .unwrap_or(&0)
-                            ))
-                        );
-
-                        let a = (matrix * Vector4::new(1.0 - element.from[0] / 16.0, element.from[1] / 16.0, element.from[2] / 16.0, 1.0)).truncate().into();
-                        let b = (matrix * Vector4::new(1.0 - element.to[0] / 16.0, element.from[1] / 16.0, element.from[2] / 16.0, 1.0)).truncate().into();
-                        let c = (matrix * Vector4::new(1.0 - element.to[0] / 16.0, element.to[1] / 16.0, element.from[2] / 16.0, 1.0)).truncate().into();
-                        let d = (matrix * Vector4::new(1.0 - element.from[0] / 16.0, element.to[1] / 16.0, element.from[2] / 16.0, 1.0)).truncate().into();
-                        let e = (matrix * Vector4::new(1.0 - element.from[0] / 16.0, element.from[1] / 16.0, element.to[2] / 16.0, 1.0)).truncate().into();
-                        let f = (matrix * Vector4::new(1.0 - element.to[0] / 16.0, element.from[1] / 16.0, element.to[2] / 16.0, 1.0)).truncate().into();
-                        let g = (matrix * Vector4::new(1.0 - element.to[0] / 16.0, element.to[1] / 16.0, element.to[2] / 16.0, 1.0)).truncate().into();
-                        let h = (matrix * Vector4::new(1.0 - element.from[0] / 16.0, element.to[1] / 16.0, element.to[2] / 16.0, 1.0)).truncate().into();
-
-                        #[rustfmt::skip]
-                        let faces = BlockModelFaces {
-                            south: south.map(|south| {[
-                                BlockMeshVertex { position: e, tex_coords: [south.0.1.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                                BlockMeshVertex { position: h, tex_coords: [south.0.1.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                                BlockMeshVertex { position: f, tex_coords: [south.0.0.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                                BlockMeshVertex { position: h, tex_coords: [south.0.1.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                                BlockMeshVertex { position: g, tex_coords: [south.0.0.0, south.0.0.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                                BlockMeshVertex { position: f, tex_coords: [south.0.0.0, south.0.1.1], normal: [0.0, 0.0, 1.0, 1.0], animation_uv_offset: south.1 },
-                            ]}),
-                            west: west.map(|west| {[
-                                BlockMeshVertex { position: g, tex_coords: [west.0.1.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                                BlockMeshVertex { position: b, tex_coords: [west.0.0.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                                BlockMeshVertex { position: f, tex_coords: [west.0.1.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                                BlockMeshVertex { position: c, tex_coords: [west.0.0.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                                BlockMeshVertex { position: b, tex_coords: [west.0.0.0, west.0.1.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                                BlockMeshVertex { position: g, tex_coords: [west.0.1.0, west.0.0.1], normal: [-1.0, 0.0, 0.0, 1.0], animation_uv_offset: west.1 },
-                            ]}),
-                            north: north.map(|north| {[
-                                BlockMeshVertex { position: c, tex_coords: [north.0.1.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                                BlockMeshVertex { position: a, tex_coords: [north.0.0.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                                BlockMeshVertex { position: b, tex_coords: [north.0.1.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                                BlockMeshVertex { position: d, tex_coords: [north.0.0.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                                BlockMeshVertex { position: a, tex_coords: [north.0.0.0, north.0.1.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                                BlockMeshVertex { position: c, tex_coords: [north.0.1.0, north.0.0.1], normal: [0.0, 0.0, -1.0, 1.0], animation_uv_offset: north.1 },
-                            ]}),
-                            east: east.map(|east| {[
-                                BlockMeshVertex { position: e, tex_coords: [east.0.0.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                                BlockMeshVertex { position: a, tex_coords: [east.0.1.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                                BlockMeshVertex { position: d, tex_coords: [east.0.1.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                                BlockMeshVertex { position: d, tex_coords: [east.0.1.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                                BlockMeshVertex { position: h, tex_coords: [east.0.0.0, east.0.0.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                                BlockMeshVertex { position: e, tex_coords: [east.0.0.0, east.0.1.1], normal: [1.0, 0.0, 0.0, 1.0], animation_uv_offset: east.1 },
-                            ]}),
-                            up: up.map(|up| {[
-                                BlockMeshVertex { position: g, tex_coords: [up.0.1.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                                BlockMeshVertex { position: h, tex_coords: [up.0.0.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                                BlockMeshVertex { position: d, tex_coords: [up.0.0.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                                BlockMeshVertex { position: c, tex_coords: [up.0.1.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                                BlockMeshVertex { position: g, tex_coords: [up.0.1.0, up.0.0.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                                BlockMeshVertex { position: d, tex_coords: [up.0.0.0, up.0.1.1], normal: [0.0, 1.0, 0.0, 1.0], animation_uv_offset: up.1 },
-                            ]}),
-                            down: down.map(|down| {[
-                                BlockMeshVertex { position: f, tex_coords: [down.0.0.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                                BlockMeshVertex { position: b, tex_coords: [down.0.0.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                                BlockMeshVertex { position: a, tex_coords: [down.0.1.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                                BlockMeshVertex { position: f, tex_coords: [down.0.0.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                                BlockMeshVertex { position: a, tex_coords: [down.0.1.0, down.0.0.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                                BlockMeshVertex { position: e, tex_coords: [down.0.1.0, down.0.1.1], normal: [0.0, -1.0, 0.0, 1.0], animation_uv_offset: down.1 },
-                            ]}),
-                        };
-
-                        Ok(faces)
-                    }).collect::<Result<Vec<BlockModelFaces>, MeshBakeError>>()?;
-
-                //TODO
-                let has_transparency = false;
-
-                Ok((if is_cube {
+                        bake_element(
+                            element,
+                            matrix,
+                            model_properties.uv_lock,
+                            connected_textures.filter(|_| is_cube),
+                            resource_provider,
+                            block_atlas,
+                        )
+                    })
+                    .collect::<Result<Vec<BlockModelFaces>, MeshBakeError>>()?;
+
+                let covers_all_faces = results.iter().all(|faces| {
+                    faces.north.is_some()
+                        && faces.east.is_some()
+                        && faces.south.is_some()
+                        && faces.west.is_some()
+                        && faces.up.is_some()
+                        && faces.down.is_some()
+                });
+
+                let model_render_type =
+                    elements_render_type(model.elements.iter().flatten(), block_atlas);
+
+                render_type = render_type.max(model_render_type);
+
+                let is_full_opaque_cube =
+                    is_cube && covers_all_faces && model_render_type == RenderType::Solid;
+
+                Ok((
+                    if is_cube {
                         CubeOrComplexMesh::Cube(Box::new(results.pop().unwrap()))
                     } else {
                         CubeOrComplexMesh::Complex(results)
                     },
-                    !is_cube || has_transparency
+                    !is_full_opaque_cube,
                 ))
-            }).collect::<Result<Vec<_>, MeshBakeError>>()?;
+            })
+            .collect::<Result<Vec<_>, MeshBakeError>>()?;
+
+        let is_full_opaque_cube = all_full_opaque_cubes(&models);
+
+        Ok(Self {
+            models,
+            is_full_opaque_cube,
+            render_type,
+            weights,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector4;
+    use minecraft_assets::schemas;
+
+    use super::{
+        all_full_opaque_cubes, element_face_uv, element_rotation, model_position, BlockModelFaces,
+        CubeOrComplexMesh, ModelMesh, RenderType,
+    };
+
+    fn weighted(weights: Vec<u32>) -> ModelMesh {
+        ModelMesh {
+            models: weights
+                .iter()
+                .map(|_| (CubeOrComplexMesh::Complex(Vec::new()), true))
+                .collect(),
+            is_full_opaque_cube: false,
+            render_type: RenderType::Solid,
+            weights,
+        }
+    }
+
+    #[test]
+    fn models_are_picked_by_position_and_weight() {
+        let mesh = weighted(vec![1, 1, 1, 1]);
+
+        let picks: Vec<usize> = (0..64).map(|x| mesh.pick_model(x, 64, -12)).collect();
+
+        //Neighbouring blocks don't all get the same model
+        assert!((0..4).all(|model| picks.contains(&model)));
+
+        //Models without weight are never picked
+        let mesh = weighted(vec![0, 3, 0]);
+        assert!((0..64).all(|x| mesh.pick_model(x, 64, -12) == 1));
+    }
+
+    #[test]
+    fn only_blocks_whose_every_model_is_a_full_cube_cull_neighbours() {
+        //Only whether the neighbours are rendered matters, not the faces
+        let cube = || {
+            let faces = BlockModelFaces {
+                north: None,
+                east: None,
+                south: None,
+                west: None,
+                up: None,
+                down: None,
+                connected: Default::default(),
+            };
+
+            (CubeOrComplexMesh::Cube(Box::new(faces)), false)
+        };
+        let cross = || (CubeOrComplexMesh::Complex(Vec::new()), true);
+
+        assert!(all_full_opaque_cubes(&[cube(), cube()]));
+        assert!(!all_full_opaque_cubes(&[]));
+
+        //A weighted variant whose first alternative is a full cube, but whose others aren't
+        assert!(!all_full_opaque_cubes(&[cube(), cross(), cube()]));
+        assert!(!all_full_opaque_cubes(&[cross(), cube()]));
+    }
+
+    #[test]
+    fn explicit_uvs_stretch_the_default_ones() {
+        let slab: schemas::models::Element = serde_json::from_value(serde_json::json!({
+            "from": [0, 0, 0],
+            "to": [16, 8, 16],
+            "faces": {},
+        }))
+        .unwrap();
+
+        let bottom = model_position([0.0, 0.0, 16.0]);
+        let top = model_position([0.0, 8.0, 16.0]);
+
+        //The south face of a bottom slab shows the lower half of the texture by default
+        assert_eq!(element_face_uv(2, bottom, &slab, None), [16.0, 16.0]);
+        assert_eq!(element_face_uv(2, top, &slab, None), [16.0, 8.0]);
+
+        let uv = Some([0.0, 0.0, 16.0, 8.0]);
+        assert_eq!(element_face_uv(2, bottom, &slab, uv), [16.0, 8.0]);
+        assert_eq!(element_face_uv(2, top, &slab, uv), [16.0, 0.0]);
+    }
+
+    #[test]
+    fn rescaled_elements_reach_the_corners() {
+        let rotation: schemas::models::ElementRotation =
+            serde_json::from_value(serde_json::json!({
+                "origin": [8, 8, 8],
+                "axis": "y",
+                "angle": 45,
+                "rescale": true,
+            }))
+            .unwrap();
+
+        let [x, y, z] = model_position([8.0, 8.0, 0.0]);
+        let rotated = element_rotation(&rotation) * Vector4::new(x, y, z, 1.0);
 
-        Ok(Self { models })
+        //The middle of the north edge ends up in a corner of the block
+        assert!((rotated.x - 1.0).abs() < 1e-5);
+        assert!((rotated.y - 0.5).abs() < 1e-5);
+        assert!(rotated.z.abs() < 1e-5);
     }
 }
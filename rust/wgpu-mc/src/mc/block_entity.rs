@@ -0,0 +1,169 @@
+//! # Block entities
+//!
+//! Blocks like chests, signs and bells move, so they aren't baked into the chunks. Instead, the frontend registers
+//! a [BlockEntityRenderer] for each of their blocks in the [BlockEntityRenderers] of the
+//! [crate::mc::MinecraftState], and every frame [BlockEntityRenderers::instances] turns the block entities which
+//! could be visible into [EntityInstances], one per block, to be drawn by the
+//! [crate::render::pipeline::entity::EntityPipeline] along with the other entities.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use parking_lot::{Mutex, RwLock};
+
+use crate::mc::block::BlockPos;
+use crate::mc::chunk::{ChunkPos, CHUNK_SECTION_HEIGHT, CHUNK_WIDTH};
+use crate::mc::entity::{Entity, EntityInstanceTransforms, EntityInstances};
+use crate::mc::resource::ResourcePath;
+use crate::WmRenderer;
+
+/// How a block entity is animated, interpolated between ticks with [BlockEntityState::lerp]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct BlockEntityState {
+    /// How far along its animation the block entity is, from 0 to 1, like how far the lid of a chest is open
+    pub progress: f32,
+    /// Rotation around Y in degrees, like the facing of a chest or the rotation of a standing sign
+    pub yaw: f32,
+    pub age_in_ticks: f32,
+}
+
+impl BlockEntityState {
+    #[must_use]
+    pub fn lerp(&self, next: &Self, tick_delta: f32) -> Self {
+        let lerp = |from: f32, to: f32| from + (to - from) * tick_delta;
+
+        Self {
+            progress: lerp(self.progress, next.progress),
+            yaw: lerp(self.yaw, next.yaw),
+            age_in_ticks: lerp(self.age_in_ticks, next.age_in_ticks),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockEntity {
+    /// The block it belongs to, like `minecraft:chest`
+    pub block: ResourcePath,
+    pub position: BlockPos,
+    pub state: BlockEntityState,
+}
+
+impl BlockEntity {
+    /// The chunk section it's in, like the ones returned by [crate::mc::visibility::visible_sections]
+    #[must_use]
+    pub fn section(&self) -> (ChunkPos, usize) {
+        let (x, y, z) = self.position;
+
+        (
+            [
+                x.div_euclid(CHUNK_WIDTH as i32),
+                z.div_euclid(CHUNK_WIDTH as i32),
+            ],
+            y as usize / CHUNK_SECTION_HEIGHT,
+        )
+    }
+}
+
+/// Draws the block entities of a block with a model, see [BlockEntityRenderers::register]
+pub trait BlockEntityRenderer: Send + Sync {
+    /// The model every block entity of the block is drawn with
+    fn entity(&self) -> Arc<Entity>;
+
+    /// The instance of the model which draws the block entity, with its parts posed for the state of the block
+    /// entity, like the lid of a chest rotated by how far it's open
+    fn instance(&self, block_entity: &BlockEntity) -> EntityInstanceTransforms;
+}
+
+/// The [BlockEntityRenderer] of each block, see [crate::mc::MinecraftState::block_entity_renderers]
+#[derive(Default)]
+pub struct BlockEntityRenderers {
+    renderers: RwLock<HashMap<ResourcePath, Arc<dyn BlockEntityRenderer>>>,
+    /// The instances of each block from the last frame, whose buffers are written again if the number of them
+    /// doesn't change
+    uploaded: Mutex<HashMap<ResourcePath, Arc<EntityInstances>>>,
+}
+
+impl BlockEntityRenderers {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draws the block entities of the block with the renderer from now on, replacing its previous one
+    pub fn register(&self, block: ResourcePath, renderer: Arc<dyn BlockEntityRenderer>) {
+        self.renderers.write().insert(block, renderer);
+    }
+
+    #[must_use]
+    pub fn get(&self, block: &ResourcePath) -> Option<Arc<dyn BlockEntityRenderer>> {
+        self.renderers.read().get(block).cloned()
+    }
+
+    /// Uploads the instances of every block entity in a section which could be visible, or all of them if that's
+    /// [None], for the [crate::render::pipeline::entity::EntityPipeline] to draw. Block entities of blocks without a
+    /// renderer are skipped.
+    pub fn instances<'a>(
+        &self,
+        wm: &WmRenderer,
+        block_entities: impl IntoIterator<Item = &'a BlockEntity>,
+        visible_sections: Option<&HashSet<(ChunkPos, usize)>>,
+    ) -> Vec<Arc<EntityInstances>> {
+        let renderers = self.renderers.read();
+        let mut instances: HashMap<&ResourcePath, Vec<EntityInstanceTransforms>> = HashMap::new();
+
+        block_entities
+            .into_iter()
+            .filter(|block_entity| {
+                visible_sections.map_or(true, |visible| visible.contains(&block_entity.section()))
+            })
+            .for_each(|block_entity| {
+                if let Some(renderer) = renderers.get(&block_entity.block) {
+                    instances
+                        .entry(&block_entity.block)
+                        .or_default()
+                        .push(renderer.instance(block_entity));
+                }
+            });
+
+        let mut uploaded = self.uploaded.lock();
+        let mut current = HashMap::new();
+
+        for (block, transforms) in instances {
+            let entity = renderers[block].entity();
+            let previous = uploaded
+                .get(block)
+                .filter(|previous| Arc::ptr_eq(&previous.entity, &entity))
+                .and_then(|previous| previous.uploaded.read().clone());
+
+            let block_instances = EntityInstances {
+                entity,
+                instances: transforms,
+                uploaded: RwLock::new(previous),
+            };
+            block_instances.upload(wm);
+
+            current.insert(block.clone(), Arc::new(block_instances));
+        }
+
+        *uploaded = current;
+
+        uploaded.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockEntity, BlockEntityState};
+
+    #[test]
+    fn block_entities_are_in_the_section_of_their_block() {
+        let block_entity = |position| BlockEntity {
+            block: "minecraft:chest".into(),
+            position,
+            state: BlockEntityState::default(),
+        };
+
+        assert_eq!(block_entity((5, 70, 31)).section(), ([0, 1], 4));
+        assert_eq!(block_entity((-1, 0, -17)).section(), ([-1, -2], 0));
+    }
+}
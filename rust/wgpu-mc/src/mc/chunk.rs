@@ -10,19 +10,31 @@
 
 use arc_swap::ArcSwap;
 use parking_lot::{Mutex, RwLock};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::sync::Arc;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use wgpu::BufferUsages;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
+use crate::mc::biome::Biome;
 use crate::mc::block::{
-    BlockMeshVertex, BlockstateKey, ChunkBlockState, CubeOrComplexMesh, ModelMesh,
+    BlockMeshVertex, BlockstateKey, ChunkBlockState, CubeOrComplexMesh, ModelMesh, RenderType,
+    NO_TINT,
 };
-use crate::mc::BlockManager;
-use crate::render::pipeline::Vertex;
+use crate::mc::lod::{bake_lod, LodLevel};
+use crate::mc::visibility::SectionVisibility;
+use crate::mc::{Block, BlockManager, MinecraftState};
+use crate::render::chunk_allocator::{ChunkAllocation, ChunkBufferAllocator, DEFRAGMENT_THRESHOLD};
+use crate::render::gpu_mesher::GpuMesher;
+use crate::render::lightmap::lightmap_coords;
+use crate::render::pipeline::{ChunkVertexFormat, PackedVertex, Vertex};
 
-use crate::WmRenderer;
+use crate::{WgpuState, WmRenderer};
 
 pub const CHUNK_WIDTH: usize = 16;
 pub const CHUNK_AREA: usize = CHUNK_WIDTH * CHUNK_WIDTH;
@@ -34,10 +46,129 @@ pub const SECTION_VOLUME: usize = CHUNK_AREA * CHUNK_SECTION_HEIGHT;
 
 pub type ChunkPos = [i32; 2];
 
+/// The block coordinates of the north-west corner of the chunk. None for chunks so far out that they, or the
+/// neighbouring chunks which are looked at for culling and LODs, can't be addressed with i32 block coordinates.
+pub fn chunk_origin(pos: ChunkPos) -> Option<[i32; 2]> {
+    let origin = |axis: i32| {
+        let origin = axis.checked_mul(CHUNK_WIDTH as i32)?;
+
+        origin.checked_sub(CHUNK_WIDTH as i32)?;
+        origin.checked_add(2 * CHUNK_WIDTH as i32 - 1)?;
+
+        Some(origin)
+    };
+
+    Some([origin(pos[0])?, origin(pos[1])?])
+}
+
+/// The offsets of the horizontal neighbours of a chunk, in the order north, east, south, west
+const NEIGHBOUR_OFFSETS: [ChunkPos; 4] = [[0, -1], [1, 0], [0, 1], [-1, 0]];
+
+/// A bit for each of the [NEIGHBOUR_OFFSETS]
+const ALL_NEIGHBOURS: u8 = 0b1111;
+
 #[derive(Debug, Default)]
 pub struct ChunkManager {
     pub loaded_chunks: RwLock<HashMap<ChunkPos, ArcSwap<Chunk>>>,
     pub chunk_offset: Mutex<ChunkPos>,
+    /// The vertex and index buffers of every [BakedLayer] are sub-allocated from this
+    pub buffer_allocator: ChunkBufferAllocator,
+    /// Set by [WmRenderer::init] if [crate::WmConfig::gpu_meshing] is enabled and the device supports it
+    pub gpu_mesher: ArcSwap<Option<GpuMesher>>,
+    /// The buffers of every uploaded layer, see [ChunkManager::share_mesh]
+    shared_meshes: Mutex<SharedMeshes>,
+    /// Buffers moved by [ChunkManager::defragment] which other sections still use, and where they were moved to
+    relocated: Mutex<Vec<(Weak<ChunkAllocation>, Arc<ChunkAllocation>)>>,
+}
+
+/// How many sections [ChunkManager::defragment] moves the buffers of at most per call
+const DEFRAGMENT_BATCH: usize = 64;
+
+/// The vertex and index buffers of a [BakedLayer], which can be shared by several chunks, with the contents they
+/// were allocated for
+#[derive(Debug)]
+struct SharedMesh<T> {
+    key: LayerKey,
+    vertices: Box<[u8]>,
+    indices: Box<[u32]>,
+    vertex_buffer: Weak<T>,
+    index_buffer: Weak<T>,
+}
+
+/// The [SharedMesh]es by a hash of their contents. Generic over the allocations so that it can be tested without a
+/// device.
+#[derive(Debug)]
+struct SharedMeshes<T = ChunkAllocation> {
+    meshes: HashMap<u64, Vec<SharedMesh<T>>>,
+}
+
+impl<T> Default for SharedMeshes<T> {
+    fn default() -> Self {
+        Self {
+            meshes: HashMap::new(),
+        }
+    }
+}
+
+impl<T> SharedMeshes<T> {
+    /// See [ChunkManager::share_mesh]. The contents of meshes with the same hash are compared, so that a collision
+    /// doesn't draw another chunk's mesh.
+    fn share(
+        &mut self,
+        key: &LayerKey,
+        vertices: &[Vertex],
+        indices: &[u32],
+        allocate: impl FnOnce() -> (T, T),
+    ) -> (Arc<T>, Arc<T>) {
+        let hash = mesh_hash(key, vertices, indices);
+        let vertex_bytes: &[u8] = bytemuck::cast_slice(vertices);
+
+        let shared = self.meshes.get(&hash).and_then(|meshes| {
+            meshes
+                .iter()
+                .filter(|mesh| {
+                    mesh.key == *key && *mesh.vertices == *vertex_bytes && *mesh.indices == *indices
+                })
+                .find_map(|mesh| {
+                    Some((mesh.vertex_buffer.upgrade()?, mesh.index_buffer.upgrade()?))
+                })
+        });
+
+        if let Some(buffers) = shared {
+            return buffers;
+        }
+
+        let (vertex_buffer, index_buffer) = allocate();
+        let (vertex_buffer, index_buffer) = (Arc::new(vertex_buffer), Arc::new(index_buffer));
+
+        //Forget the meshes which nobody uses anymore
+        self.meshes.retain(|_, meshes| {
+            meshes.retain(|mesh| mesh.vertex_buffer.strong_count() > 0);
+            !meshes.is_empty()
+        });
+        self.meshes.entry(hash).or_default().push(SharedMesh {
+            key: key.clone(),
+            vertices: vertex_bytes.into(),
+            indices: indices.into(),
+            vertex_buffer: Arc::downgrade(&vertex_buffer),
+            index_buffer: Arc::downgrade(&index_buffer),
+        });
+
+        (vertex_buffer, index_buffer)
+    }
+
+    /// Points the meshes using the allocation at the one it was moved to, see [ChunkManager::defragment]
+    fn relocate(&mut self, old: &Arc<T>, new: &Arc<T>) {
+        let old = Arc::downgrade(old);
+
+        for mesh in self.meshes.values_mut().flatten() {
+            for buffer in [&mut mesh.vertex_buffer, &mut mesh.index_buffer] {
+                if buffer.ptr_eq(&old) {
+                    *buffer = Arc::downgrade(new);
+                }
+            }
+        }
+    }
 }
 
 impl ChunkManager {
@@ -46,7 +177,191 @@ impl ChunkManager {
         ChunkManager {
             loaded_chunks: RwLock::new(HashMap::new()),
             chunk_offset: Mutex::new([0, 0]),
+            buffer_allocator: ChunkBufferAllocator::default(),
+            gpu_mesher: ArcSwap::new(Arc::new(None)),
+            shared_meshes: Mutex::new(SharedMeshes::default()),
+            relocated: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Makes the layers uploaded by [Chunk::upload] and [Chunk::upload_lods] the ones which are drawn, for every
+    /// loaded chunk whose uploads are part of the `completed` [crate::util::UploadBelt::generation] or an earlier
+    /// one. Called by [WmRenderer::render] before every frame.
+    pub fn swap_buffers(&self, completed: u64) {
+        self.loaded_chunks.read().values().for_each(|chunk| {
+            chunk.load().swap_buffers(completed);
+        });
+    }
+
+    /// Returns the buffers of a mesh with the same contents which is still in use, or allocates them. Vertex
+    /// positions are relative to their chunk, so identical chunks, like the ones of a superflat world or an
+    /// ocean, only take up memory once.
+    fn share_mesh(
+        &self,
+        key: &LayerKey,
+        vertices: &[Vertex],
+        indices: &[u32],
+        allocate: impl FnOnce() -> (ChunkAllocation, ChunkAllocation),
+    ) -> (Arc<ChunkAllocation>, Arc<ChunkAllocation>) {
+        self.shared_meshes
+            .lock()
+            .share(key, vertices, indices, allocate)
+    }
+
+    /// Moves the vertex and index buffers of up to [DEFRAGMENT_BATCH] sections into gaps before them, see
+    /// [ChunkBufferAllocator::relocate], if the pages are more fragmented than [DEFRAGMENT_THRESHOLD]. Meant to be
+    /// called once per frame, so that compacting the pages is spread out over many frames. The moved buffers are
+    /// drawn once the copies have completed, like the ones of [Chunk::upload]. Returns how many sections were moved.
+    pub fn defragment(&self, wm: &WmRenderer) -> usize {
+        if self.buffer_allocator.fragmentation_ratio() <= DEFRAGMENT_THRESHOLD {
+            return 0;
+        }
+
+        let mut encoder =
+            wm.wgpu_state
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Chunk buffer defragmentation"),
+                });
+
+        let mut relocated = self.relocated.lock();
+        relocated.retain(|(old, _)| old.strong_count() > 0);
+
+        let mut moved = 0;
+
+        for chunk in self.loaded_chunks.read().values() {
+            if moved >= DEFRAGMENT_BATCH {
+                break;
+            }
+
+            let chunk = chunk.load();
+
+            //Read before the pending layers are locked, like in Chunk::set_pending
+            let generation = wm.uploads.lock().generation();
+            let mut pending = chunk.pending_layers.lock();
+
+            //Its buffers are about to be replaced anyway
+            if pending.is_some() {
+                continue;
+            }
+
+            let mut baked_layers = chunk.baked_layers.read().clone();
+            let mut changed = false;
+
+            let sections = baked_layers
+                .values_mut()
+                .flat_map(|layer| layer.sections.iter_mut().flatten());
+
+            for section in sections {
+                let vertex_buffer =
+                    self.relocate(wm, &section.vertex_buffer, &mut relocated, &mut encoder);
+                let index_buffer =
+                    self.relocate(wm, &section.index_buffer, &mut relocated, &mut encoder);
+
+                if vertex_buffer.is_none() && index_buffer.is_none() {
+                    continue;
+                }
+
+                *section = Arc::new(SectionBuffers {
+                    vertex_buffer: vertex_buffer.unwrap_or_else(|| section.vertex_buffer.clone()),
+                    index_buffer: index_buffer.unwrap_or_else(|| section.index_buffer.clone()),
+                    index_count: section.index_count,
+                    sortable_quads: section.sortable_quads.as_ref().map(SortableQuads::unsorted),
+                });
+
+                changed = true;
+                moved += 1;
+            }
+
+            if changed {
+                *pending = Some(PendingLayers {
+                    generation,
+                    baked_layers: Some(baked_layers),
+                    lod_layers: None,
+                });
+            }
+        }
+
+        wm.wgpu_state.queue.submit([encoder.finish()]);
+
+        moved
+    }
+
+    /// Moves the allocation, or returns where it was already moved to for another section which shares it
+    fn relocate(
+        &self,
+        wm: &WmRenderer,
+        allocation: &Arc<ChunkAllocation>,
+        relocated: &mut Vec<(Weak<ChunkAllocation>, Arc<ChunkAllocation>)>,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<Arc<ChunkAllocation>> {
+        let old = Arc::downgrade(allocation);
+
+        if let Some((_, new)) = relocated.iter().find(|(moved, _)| moved.ptr_eq(&old)) {
+            return Some(new.clone());
+        }
+
+        let new = Arc::new(self.buffer_allocator.relocate(wm, allocation, encoder)?);
+
+        self.shared_meshes.lock().relocate(allocation, &new);
+        relocated.push((old, new.clone()));
+
+        Some(new)
+    }
+
+    /// Should be called once the chunk at `pos` has been loaded. Returns every loaded neighbour which was baked
+    /// while it wasn't, as the faces along their shared border can be culled now. The caller is expected to
+    /// re-bake them, with [Chunk::bake_chunk] or [ChunkBakery::submit].
+    pub fn neighbours_to_rebake(&self, pos: ChunkPos) -> Vec<ChunkPos> {
+        let chunks = self.loaded_chunks.read();
+
+        NEIGHBOUR_OFFSETS
+            .iter()
+            .enumerate()
+            .filter_map(|(direction, offset)| {
+                let neighbour_pos = [pos[0] + offset[0], pos[1] + offset[1]];
+                let neighbour = chunks.get(&neighbour_pos)?.load();
+
+                //The neighbour sees this chunk in the opposite direction
+                let bit = 1 << ((direction + 2) % NEIGHBOUR_OFFSETS.len());
+                let previous = neighbour.baked_neighbours.fetch_or(bit, Ordering::Relaxed);
+
+                (previous & bit == 0).then_some(neighbour_pos)
+            })
+            .collect()
+    }
+
+    /// Removes the chunk at `pos` from the loaded chunks, so it isn't drawn anymore, and drops its baked meshes.
+    /// Their vertex and index buffer ranges go back to the [ChunkBufferAllocator] right away, even while a frame
+    /// or a bake still holds on to the chunk. Returns false if the chunk wasn't loaded.
+    ///
+    /// Bakes of the chunk which are still in a [ChunkBakery] would load it again, see [ChunkBakery::cancel].
+    /// Neighbours baked while this chunk was loaded are returned by [ChunkManager::neighbours_to_rebake] once
+    /// it's loaded again.
+    pub fn unload_chunk(&self, pos: ChunkPos) -> bool {
+        let chunk = match self.loaded_chunks.write().remove(&pos) {
+            None => return false,
+            Some(chunk) => chunk.load_full(),
+        };
+
+        *chunk.pending_layers.lock() = None;
+        chunk.baked_layers.write().clear();
+        chunk.lod_layers.write().clear();
+        chunk.sections.write().iter_mut().for_each(HashMap::clear);
+
+        let chunks = self.loaded_chunks.read();
+
+        for (direction, offset) in NEIGHBOUR_OFFSETS.iter().enumerate() {
+            if let Some(neighbour) = chunks.get(&[pos[0] + offset[0], pos[1] + offset[1]]) {
+                let bit = 1 << ((direction + 2) % NEIGHBOUR_OFFSETS.len());
+                neighbour
+                    .load()
+                    .baked_neighbours
+                    .fetch_and(!bit, Ordering::Relaxed);
+            }
         }
+
+        true
     }
 }
 
@@ -64,10 +379,47 @@ pub struct ChunkSection {
 }
 
 /// Return a BlockState within the provided world coordinates.
+///
+/// Baking a chunk also queries the blocks just outside of it in its four horizontal neighbours, so that faces
+/// on the chunk border can be culled. Blocks in chunks the provider doesn't have are [ChunkBlockState::Air].
 pub trait BlockStateProvider: Send + Sync + Debug {
     fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState;
 
     fn is_section_empty(&self, index: usize) -> bool;
+
+    /// Whether the provider has the blocks of the chunk. A chunk baked while one of its neighbours wasn't
+    /// loaded is returned by [ChunkManager::neighbours_to_rebake] once that neighbour is.
+    fn is_chunk_loaded(&self, _pos: ChunkPos) -> bool {
+        true
+    }
+
+    /// The block light and sky light at the position, from 0 to 15. Providers without light data are lit by
+    /// the sky everywhere.
+    fn get_light(&self, _x: i32, _y: i16, _z: i32) -> (u8, u8) {
+        (0, 15)
+    }
+
+    /// One above the topmost block of the column which isn't air, like Minecraft's `WORLD_SURFACE` heightmap.
+    /// Everything from there up is skipped while baking. [None] if the provider doesn't know, and the whole
+    /// column is looked at.
+    fn column_height(&self, _x: i32, _z: i32) -> Option<i16> {
+        None
+    }
+
+    /// The biome at the position, which tints grass, foliage and water, see [crate::mc::biome]. Providers
+    /// without biome data are plains everywhere.
+    fn get_biome(&self, _x: i32, _y: i16, _z: i32) -> Biome {
+        Biome::PLAINS
+    }
+}
+
+/// A bit for each neighbour of the chunk which the provider has the blocks of
+fn loaded_neighbours(provider: &impl BlockStateProvider, pos: ChunkPos) -> u8 {
+    NEIGHBOUR_OFFSETS
+        .iter()
+        .enumerate()
+        .filter(|(_, offset)| provider.is_chunk_loaded([pos[0] + offset[0], pos[1] + offset[1]]))
+        .fold(0, |mask, (direction, _)| mask | (1 << direction))
 }
 
 pub trait RenderLayer: Send + Sync {
@@ -76,232 +428,2680 @@ pub trait RenderLayer: Send + Sync {
     fn mapper(&self) -> fn(&BlockMeshVertex, f32, f32, f32) -> Vertex;
 
     fn name(&self) -> &str;
+
+    fn meshing_strategy(&self) -> MeshingStrategy {
+        MeshingStrategy::PerFace
+    }
 }
 
-/// A representation of a chunk, containing buffers and vertices for rendering.
-#[derive(Debug)]
-pub struct Chunk {
-    pub pos: ChunkPos,
-    /// The layers here don't have to be sections, and the [String] keys are used to distinguish
-    /// which [RenderLayer] the vertices come from.
-    pub baked_layers: RwLock<HashMap<String, (wgpu::Buffer, Vec<Vertex>)>>,
+/// Vertices which are lit while baking, by ambient occlusion and by block and sky light, and tinted by the biome
+pub trait ShadedVertex {
+    fn shade(&mut self, brightness: f32);
+
+    fn tint(&mut self, color: [f32; 3]);
+
+    fn light(&mut self, block_light: u8, sky_light: u8);
+
+    /// Repeats the texture across a quad which covers several blocks. The `tex_coords` are relative to the `region`
+    /// of the atlas the texture is in, given as its min and max texture coordinates, and go past 1 once per block.
+    fn repeat_texture(&mut self, tex_coords: [f32; 2], region: [f32; 4]);
 }
 
-impl Chunk {
-    pub fn new(pos: ChunkPos) -> Self {
-        Self {
-            pos,
-            baked_layers: Default::default(),
-        }
+impl ShadedVertex for Vertex {
+    fn shade(&mut self, brightness: f32) {
+        self.color[0] *= brightness;
+        self.color[1] *= brightness;
+        self.color[2] *= brightness;
     }
 
-    /// Bakes the layers, and uploads them to the GPU.
-    pub fn bake_chunk<T: BlockStateProvider>(
-        &self,
-        wm: &WmRenderer,
-        layers: &[Box<dyn RenderLayer>],
-        block_manager: &BlockManager,
-        provider: &T,
-    ) {
-        let baked_layers = layers
-            .iter()
-            .map(|layer| {
-                let verts = bake_layer(
-                    block_manager,
-                    self,
-                    layer.mapper(),
-                    layer.filter(),
-                    provider,
-                );
+    fn tint(&mut self, color: [f32; 3]) {
+        self.color[0] *= color[0];
+        self.color[1] *= color[1];
+        self.color[2] *= color[2];
+    }
 
-                (
-                    layer.name().into(),
-                    (
-                        wm.wgpu_state
-                            .device
-                            .create_buffer_init(&BufferInitDescriptor {
-                                label: None,
-                                contents: bytemuck::cast_slice(&verts),
-                                usage: BufferUsages::VERTEX,
-                            }),
-                        verts,
-                    ),
-                )
-            })
-            .collect();
+    fn light(&mut self, block_light: u8, sky_light: u8) {
+        self.lightmap_coords = lightmap_coords(block_light, sky_light);
+    }
 
-        *self.baked_layers.write() = baked_layers;
+    fn repeat_texture(&mut self, tex_coords: [f32; 2], region: [f32; 4]) {
+        self.tex_coords = tex_coords;
+        self.region = region;
     }
 }
 
-#[inline]
-fn block_add_face_vertices<T, Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T>(
-    mapper: Mapper,
-    vertices: &mut Vec<T>,
-    x: i32,
-    y: i16,
-    z: i32,
-    face_vertices: &Option<[BlockMeshVertex; 6]>,
-) {
-    match face_vertices {
-        None => {}
-        Some(north) => vertices.extend(
-            north
-                .iter()
-                .map(|v| mapper(v, x as f32, y as f32, z as f32)),
-        ),
-    };
+/// How the visible faces of a [RenderLayer] are turned into vertices
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum MeshingStrategy {
+    /// Every visible face becomes its own quad
+    #[default]
+    PerFace,
+    /// Coplanar faces of neighbouring cubes with identical vertex data (and therefore the same texture) are merged
+    /// into larger quads, which cuts down the vertex count of flat terrain by a lot. The texture of a merged quad is
+    /// repeated once per block, see [ShadedVertex::repeat_texture], so the shaders of the layer have to wrap the
+    /// texture coordinates of vertices with a [Vertex::region] into it.
+    Greedy,
 }
 
-/// Returns true when blocks adjacent to the one at (x, y, z) should render their faces.
-#[inline]
-fn should_render_face(
-    block_manager: &BlockManager,
-    state_provider: &impl BlockStateProvider,
-    x: i32,
-    y: i16,
-    z: i32,
-) -> bool {
-    let state = get_block(block_manager, state_provider.get_state(x, y, z));
+/// The name of a [RenderLayer] and the [RenderType] of the blocks in it
+pub type LayerKey = (String, RenderType);
 
-    match state {
-        Some(mesh) => mesh.models[0].1,
-        None => true,
-    }
-}
+/// The indexed vertices of each [RenderLayer] and [RenderType] in a single chunk section
+pub type SectionMesh = HashMap<LayerKey, (Vec<Vertex>, Vec<u32>)>;
 
-fn get_block(block_manager: &BlockManager, state: ChunkBlockState) -> Option<Arc<ModelMesh>> {
-    let key = match state {
-        ChunkBlockState::Air => return None,
-        ChunkBlockState::State(key) => key,
-    };
+/// The indexed heightmap shell of each [LodLevel], the output of [Chunk::bake_lods]
+pub type LodMeshes = Vec<(LodLevel, (Vec<Vertex>, Vec<u32>))>;
 
-    Some(
-        block_manager
-            .blocks
-            .get_index(key.block as usize)?
-            .1
-            .get_model(key.augment),
-    )
+/// The output of [Chunk::bake_sections] for a single section
+#[derive(Debug)]
+pub struct BakedSection {
+    pub index: usize,
+    pub mesh: SectionMesh,
+    pub visibility: SectionVisibility,
 }
 
-pub fn bake_layer<
-    T,
-    Provider: BlockStateProvider,
-    Filter: Fn(BlockstateKey) -> bool,
-    Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T,
->(
-    block_manager: &BlockManager,
-    chunk: &Chunk,
-    mapper: Mapper,
-    filter: Filter,
-    state_provider: &Provider,
-) -> Vec<T> {
-    //Generates the mesh for this chunk, culling faces whenever possible
-    let mut vertices = Vec::with_capacity(300_000);
+/// A representation of a chunk, containing buffers and vertices for rendering. With the `serde` feature, everything
+/// but the buffers is serialized, so a deserialized chunk only has to be [uploaded](Chunk::upload) to be drawn.
+#[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct Chunk {
+    pub pos: ChunkPos,
+    /// The buffers of the meshes of every section, and the keys are used to distinguish
+    /// which [RenderLayer] and [RenderType] the vertices come from.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub baked_layers: RwLock<HashMap<LayerKey, BakedLayer>>,
+    /// The heightmap shells drawn instead of [Chunk::baked_layers] when the chunk is far away, see [crate::mc::lod]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub lod_layers: RwLock<HashMap<LodLevel, BakedLayer>>,
+    /// Layers which replace [Chunk::baked_layers] and [Chunk::lod_layers] once their uploads have completed, so
+    /// a frame never draws buffers whose contents haven't reached the GPU yet
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pending_layers: Mutex<Option<PendingLayers>>,
+    /// The mesh of each section, so that a change to a single section doesn't require baking the whole chunk
+    #[cfg_attr(feature = "serde", serde(with = "serde_sections"))]
+    pub sections: RwLock<Vec<SectionMesh>>,
+    /// Which faces of each section can see each other, used for cave culling
+    pub visibility: RwLock<[SectionVisibility; CHUNK_SECTIONS_PER]>,
+    /// A bit for every section which has to be re-baked, see [Chunk::mark_section_dirty]
+    dirty_sections: AtomicU32,
+    /// A bit for each neighbour which was loaded when this chunk was last baked, see
+    /// [ChunkManager::neighbours_to_rebake]
+    baked_neighbours: AtomicU8,
+}
 
-    let mut block_index = 0;
+/// See [Chunk::swap_buffers]
+#[derive(Debug, Default)]
+struct PendingLayers {
+    /// The [crate::util::UploadBelt::generation] of the newest buffers
+    generation: u64,
+    baked_layers: Option<HashMap<LayerKey, BakedLayer>>,
+    lod_layers: Option<HashMap<LodLevel, BakedLayer>>,
+}
 
-    loop {
-        if block_index >= CHUNK_VOLUME {
-            break;
-        }
+fn empty_sections() -> RwLock<Vec<SectionMesh>> {
+    RwLock::new((0..CHUNK_SECTIONS_PER).map(|_| HashMap::new()).collect())
+}
 
-        let x = (block_index % CHUNK_WIDTH) as i32;
-        let y = (block_index / CHUNK_AREA) as i16;
-        let z = ((block_index % CHUNK_AREA) / CHUNK_WIDTH) as i32;
+/// [Chunk::sections] with the layers of each section as a list, as formats like JSON only allow strings as the keys
+/// of maps
+#[cfg(feature = "serde")]
+mod serde_sections {
+    use parking_lot::RwLock;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-        if x == 0 && z == 0 && (y as usize % CHUNK_SECTION_HEIGHT) == 0 {
-            let section_index = y as usize / CHUNK_SECTION_HEIGHT;
-            if state_provider.is_section_empty(section_index) {
-                block_index += CHUNK_SECTION_HEIGHT * CHUNK_AREA;
-                continue;
-            }
-        }
+    use super::{LayerKey, SectionMesh, CHUNK_SECTIONS_PER};
+    use crate::render::pipeline::Vertex;
 
-        block_index += 1;
+    pub fn serialize<S: Serializer>(
+        sections: &RwLock<Vec<SectionMesh>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let sections = sections.read();
+        let layers: Vec<Vec<_>> = sections
+            .iter()
+            .map(|section| section.iter().collect())
+            .collect();
 
-        let absolute_x = (chunk.pos[0] * 16) + x;
-        let absolute_z = (chunk.pos[1] * 16) + z;
+        layers.serialize(serializer)
+    }
 
-        let block_state: ChunkBlockState = state_provider.get_state(absolute_x, y, absolute_z);
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RwLock<Vec<SectionMesh>>, D::Error> {
+        let layers = Vec::<Vec<(LayerKey, (Vec<Vertex>, Vec<u32>))>>::deserialize(deserializer)?;
 
-        if block_state.is_air() {
-            continue;
+        if layers.len() != CHUNK_SECTIONS_PER {
+            return Err(D::Error::invalid_length(
+                layers.len(),
+                &"a mesh for every section",
+            ));
         }
 
-        let state_key = match block_state {
-            ChunkBlockState::Air => unreachable!(),
-            ChunkBlockState::State(key) => key,
-        };
+        Ok(RwLock::new(
+            layers
+                .into_iter()
+                .map(|section| section.into_iter().collect())
+                .collect(),
+        ))
+    }
+}
 
-        if !filter(state_key) {
-            continue;
-        }
+/// The meshes of a single [RenderLayer] of a chunk, with buffers of their own for each section so that re-baking a
+/// section only uploads that section again, see [Chunk::upload_sections]
+#[derive(Clone, Debug)]
+pub struct BakedLayer {
+    /// Indexed by section, [None] for the sections without any blocks of the layer. Heightmap shells aren't split up
+    /// into sections, and only have one.
+    pub sections: Vec<Option<Arc<SectionBuffers>>>,
+}
 
-        let mesh = get_block(block_manager, block_state).unwrap();
+/// The indexed mesh of a single section of a [BakedLayer], drawn with [wgpu::RenderPass::draw_indexed]
+#[derive(Debug)]
+pub struct SectionBuffers {
+    /// Shared with other sections whose mesh is identical, unless the layer is [RenderType::Translucent]
+    pub vertex_buffer: Arc<ChunkAllocation>,
+    pub index_buffer: Arc<ChunkAllocation>,
+    pub index_count: u32,
+    /// Only kept for [RenderType::Translucent] layers, which have to be drawn back to front
+    pub sortable_quads: Option<SortableQuads>,
+}
 
-        // TODO: randomly select a mesh if there are multiple
+/// The quads of a translucent [SectionBuffers], kept around so that the index buffer can be rewritten with the quads
+/// sorted by their distance to the camera whenever it moves into another block
+#[derive(Debug)]
+pub struct SortableQuads {
+    /// The chunk-local centroid of each quad
+    pub centroids: Vec<[f32; 3]>,
+    /// The 6 indices of each quad, in the order they were baked
+    pub indices: Vec<[u32; 6]>,
+    /// The chunk-local block the camera was in when the index buffer was last sorted
+    sorted_at: Mutex<Option<[i32; 3]>>,
+}
 
-        match &mesh.models[0].0 {
-            CubeOrComplexMesh::Cube(model) => {
-                let baked_should_render_face = |x_: i32, y_: i16, z_: i32| {
-                    should_render_face(block_manager, state_provider, x_, y_, z_)
-                };
+impl SortableQuads {
+    fn new(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let indices: Vec<[u32; 6]> = indices
+            .chunks_exact(6)
+            .map(|quad| quad.try_into().unwrap())
+            .collect();
 
-                let render_east = baked_should_render_face(absolute_x + 1, y, absolute_z);
-                let render_west = baked_should_render_face(absolute_x - 1, y, absolute_z);
-                let render_up = baked_should_render_face(absolute_x, y + 1, absolute_z);
-                let render_down = baked_should_render_face(absolute_x, y - 1, absolute_z);
-                let render_south = baked_should_render_face(absolute_x, y, absolute_z + 1);
-                let render_north = baked_should_render_face(absolute_x, y, absolute_z - 1);
+        //Averaging both triangles lands on the centroid, as the shared corners are on opposite ends
+        let centroids = indices
+            .iter()
+            .map(|quad| {
+                quad.iter().fold([0.0; 3], |centroid, index| {
+                    let position = vertices[*index as usize].position;
 
-                let mut baked_block_add_face_vertices =
-                    |face_vertices: &Option<[BlockMeshVertex; 6]>| {
-                        block_add_face_vertices(&mapper, &mut vertices, x, y, z, face_vertices);
-                    };
+                    [0, 1, 2].map(|axis| centroid[axis] + position[axis] / 6.0)
+                })
+            })
+            .collect();
 
-                if render_north {
-                    baked_block_add_face_vertices(&model.north);
-                }
-                if render_east {
-                    baked_block_add_face_vertices(&model.east);
-                }
-                if render_south {
-                    baked_block_add_face_vertices(&model.south);
-                }
-                if render_west {
-                    baked_block_add_face_vertices(&model.west);
-                }
-                if render_up {
-                    baked_block_add_face_vertices(&model.up);
-                }
-                if render_down {
-                    baked_block_add_face_vertices(&model.down);
-                }
-            }
-            CubeOrComplexMesh::Complex(model) => {
-                vertices.extend(
-                    model
-                        .iter()
-                        .flat_map(|faces| {
-                            [
-                                faces.north.as_ref(),
-                                faces.east.as_ref(),
-                                faces.south.as_ref(),
-                                faces.west.as_ref(),
-                                faces.up.as_ref(),
-                                faces.down.as_ref(),
-                            ]
-                        })
-                        .flatten()
-                        .flatten()
-                        .map(|v| mapper(v, x as f32, y as f32, z as f32)),
-                );
-            }
+        Self {
+            centroids,
+            indices,
+            sorted_at: Mutex::new(None),
+        }
+    }
+
+    /// A copy which is sorted again by the next [SortableQuads::sort_if_moved], for when the quads are moved to
+    /// another index buffer
+    fn unsorted(&self) -> Self {
+        Self {
+            centroids: self.centroids.clone(),
+            indices: self.indices.clone(),
+            sorted_at: Mutex::new(None),
+        }
+    }
+
+    /// Like [SortableQuads::sort], but [None] if the camera is still in the block the quads were last sorted for,
+    /// so the index buffer is only rewritten when the order can have changed
+    #[must_use]
+    pub fn sort_if_moved(&self, camera: [f32; 3]) -> Option<Vec<u32>> {
+        let block = camera.map(|coordinate| coordinate.floor() as i32);
+        let mut sorted_at = self.sorted_at.lock();
+
+        if *sorted_at == Some(block) {
+            return None;
         }
+
+        *sorted_at = Some(block);
+
+        Some(self.sort(camera))
     }
 
-    vertices.shrink_to_fit();
-    vertices
+    /// Sorts the quads from back to front, relative to the chunk-local camera position
+    #[must_use]
+    pub fn sort(&self, camera: [f32; 3]) -> Vec<u32> {
+        let distances: Vec<f32> = self
+            .centroids
+            .iter()
+            .map(|centroid| {
+                (0..3)
+                    .map(|axis| (centroid[axis] - camera[axis]).powi(2))
+                    .sum()
+            })
+            .collect();
+
+        let mut quads: Vec<usize> = (0..self.indices.len()).collect();
+        quads.sort_unstable_by(|a, b| distances[*b].total_cmp(&distances[*a]));
+
+        quads
+            .into_iter()
+            .flat_map(|quad| self.indices[quad])
+            .collect()
+    }
+}
+
+impl Chunk {
+    pub fn new(pos: ChunkPos) -> Self {
+        Self {
+            pos,
+            baked_layers: Default::default(),
+            lod_layers: Default::default(),
+            pending_layers: Mutex::new(None),
+            sections: empty_sections(),
+            visibility: Default::default(),
+            dirty_sections: AtomicU32::new(0),
+            //Nothing has been baked yet, so there's nothing to re-bake either
+            baked_neighbours: AtomicU8::new(ALL_NEIGHBOURS),
+        }
+    }
+
+    /// Bakes the layers, and uploads them to the GPU.
+    pub fn bake_chunk<T: BlockStateProvider>(
+        &self,
+        wm: &WmRenderer,
+        layers: &[Box<dyn RenderLayer>],
+        block_manager: &BlockManager,
+        provider: &T,
+    ) {
+        self.dirty_sections.store(0, Ordering::Relaxed);
+        self.baked_neighbours
+            .store(loaded_neighbours(provider, self.pos), Ordering::Relaxed);
+
+        let sections = bake_all_sections(
+            &wm.wgpu_state,
+            &wm.mc,
+            self.pos,
+            layers,
+            block_manager,
+            provider,
+        );
+
+        self.replace_sections(sections);
+        self.upload(wm);
+
+        let lods = Self::bake_lods(
+            self.pos,
+            block_manager,
+            provider,
+            **wm.mc.lod_distance.load(),
+        );
+        self.upload_lods(wm, lods);
+    }
+
+    /// Marks the section containing the block at height `y` to be re-baked by [Chunk::rebake_dirty].
+    /// Blocks on the edge of a section also mark the neighbouring section, as they can cull its faces.
+    pub fn mark_section_dirty(&self, y: i16) {
+        self.dirty_sections
+            .fetch_or(dirty_section_mask(y), Ordering::Relaxed);
+    }
+
+    /// Marks every section to be re-baked by [Chunk::rebake_dirty], e.g. after the models of the blocks changed
+    pub fn mark_all_dirty(&self) {
+        self.dirty_sections
+            .store((1 << CHUNK_SECTIONS_PER) - 1, Ordering::Relaxed);
+    }
+
+    /// Re-bakes only the sections marked with [Chunk::mark_section_dirty] and uploads them again.
+    /// Returns the number of sections which were baked.
+    pub fn rebake_dirty<T: BlockStateProvider>(
+        &self,
+        wm: &WmRenderer,
+        layers: &[Box<dyn RenderLayer>],
+        block_manager: &BlockManager,
+        provider: &T,
+    ) -> usize {
+        let dirty = self.dirty_sections.swap(0, Ordering::Relaxed);
+
+        self.rebake_sections(wm, layers, block_manager, provider, dirty)
+    }
+
+    /// Re-bakes the sections with a bit set in `dirty` and uploads them again
+    fn rebake_sections<T: BlockStateProvider>(
+        &self,
+        wm: &WmRenderer,
+        layers: &[Box<dyn RenderLayer>],
+        block_manager: &BlockManager,
+        provider: &T,
+        dirty: u32,
+    ) -> usize {
+        if dirty == 0 {
+            return 0;
+        }
+
+        let sections = Self::bake_sections(
+            self.pos,
+            layers,
+            block_manager,
+            provider,
+            **wm.mc.smooth_lighting.load(),
+            (0..CHUNK_SECTIONS_PER).filter(|section| dirty & (1 << section) != 0),
+        );
+
+        let baked: Vec<usize> = sections.iter().map(|section| section.index).collect();
+
+        self.replace_sections(sections);
+        self.upload_sections(wm, baked.iter().copied());
+
+        //Shells are cheap enough to bake whole
+        let lods = Self::bake_lods(
+            self.pos,
+            block_manager,
+            provider,
+            **wm.mc.lod_distance.load(),
+        );
+        self.upload_lods(wm, lods);
+
+        baked.len()
+    }
+
+    /// Bakes the vertices of each layer in the given sections without touching the GPU, which makes it safe to
+    /// call from any thread. See [Chunk::replace_sections] and [Chunk::upload]
+    pub fn bake_sections<T: BlockStateProvider>(
+        pos: ChunkPos,
+        layers: &[Box<dyn RenderLayer>],
+        block_manager: &BlockManager,
+        provider: &T,
+        ambient_occlusion: bool,
+        sections: impl IntoIterator<Item = usize>,
+    ) -> Vec<BakedSection> {
+        //Only the position of the chunk is used while baking
+        let chunk = Chunk::new(pos);
+
+        //Sections are baked in parallel, on the thread pool of the caller if it's running on one
+        sections
+            .into_iter()
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|section| {
+                let mesh = layers
+                    .iter()
+                    .flat_map(|layer| {
+                        let render_types = bake_section_layer(
+                            block_manager,
+                            &chunk,
+                            layer.mapper(),
+                            layer.filter(),
+                            provider,
+                            layer.meshing_strategy(),
+                            ambient_occlusion,
+                            section,
+                        );
+
+                        RenderType::ALL
+                            .into_iter()
+                            .zip(render_types)
+                            .filter(|(_, verts)| !verts.is_empty())
+                            .map(|(render_type, verts)| {
+                                ((layer.name().into(), render_type), index_quads(&verts))
+                            })
+                    })
+                    .collect();
+
+                BakedSection {
+                    index: section,
+                    mesh,
+                    visibility: SectionVisibility::compute(block_manager, provider, pos, section),
+                }
+            })
+            .collect()
+    }
+
+    /// Bakes the heightmap shell of every [LodLevel] without touching the GPU, or nothing when `lod_distance` is
+    /// [None]. See [Chunk::upload_lods]
+    pub fn bake_lods<T: BlockStateProvider>(
+        pos: ChunkPos,
+        block_manager: &BlockManager,
+        provider: &T,
+        lod_distance: Option<u32>,
+    ) -> LodMeshes {
+        if lod_distance.is_none() {
+            return Vec::new();
+        }
+
+        LodLevel::ALL
+            .into_iter()
+            .map(|level| {
+                let vertices = bake_lod(block_manager, provider, pos, level);
+
+                (level, index_quads(&vertices))
+            })
+            .filter(|(_, (vertices, _))| !vertices.is_empty())
+            .collect()
+    }
+
+    pub fn replace_sections(&self, baked: Vec<BakedSection>) {
+        let mut sections = self.sections.write();
+        let mut visibility = self.visibility.write();
+
+        baked.into_iter().for_each(|section| {
+            sections[section.index] = section.mesh;
+            visibility[section.index] = section.visibility;
+        });
+    }
+
+    /// Allocates the vertex and index buffers of every section from the [ChunkBufferAllocator], see
+    /// [Chunk::upload_sections]
+    pub fn upload(&self, wm: &WmRenderer) {
+        self.upload_sections(wm, 0..CHUNK_SECTIONS_PER);
+    }
+
+    /// Allocates the vertex and index buffers of the meshes of the sections from the [ChunkBufferAllocator], keeping
+    /// the buffers of the other sections. They replace the current ones in the first frame after the GPU has received
+    /// them, see [Chunk::swap_buffers].
+    pub fn upload_sections(&self, wm: &WmRenderer, sections: impl IntoIterator<Item = usize>) {
+        let meshes = self.sections.read();
+
+        //Builds on the newest buffers, which might not have been swapped in yet
+        let pending = self
+            .pending_layers
+            .lock()
+            .as_ref()
+            .and_then(|pending| pending.baked_layers.clone());
+        let mut baked_layers = pending.unwrap_or_else(|| self.baked_layers.read().clone());
+
+        for section in sections {
+            //Layers the section doesn't have anymore
+            for layer in baked_layers.values_mut() {
+                layer.sections[section] = None;
+            }
+
+            for (key, (vertices, indices)) in &meshes[section] {
+                let buffers = upload_section(wm, key, vertices, indices);
+
+                baked_layers
+                    .entry(key.clone())
+                    .or_insert_with(|| BakedLayer {
+                        sections: vec![None; CHUNK_SECTIONS_PER],
+                    })
+                    .sections[section] = Some(Arc::new(buffers));
+            }
+        }
+
+        baked_layers.retain(|_, layer| layer.sections.iter().any(Option::is_some));
+
+        self.set_pending(wm, |pending| pending.baked_layers = Some(baked_layers));
+    }
+
+    /// Allocates the buffers for the heightmap shells, which replace the current ones like [Chunk::upload]. Empty
+    /// `lods` frees them.
+    pub fn upload_lods(&self, wm: &WmRenderer, lods: LodMeshes) {
+        let allocator = &wm.mc.chunks.buffer_allocator;
+
+        let lod_layers = lods
+            .into_iter()
+            .map(|(level, (vertices, indices))| {
+                (
+                    level,
+                    BakedLayer {
+                        sections: vec![Some(Arc::new(SectionBuffers {
+                            vertex_buffer: Arc::new(allocate_vertices(wm, &vertices)),
+                            index_buffer: Arc::new(
+                                allocator.allocate(wm, bytemuck::cast_slice(&indices)),
+                            ),
+                            index_count: indices.len() as u32,
+                            sortable_quads: None,
+                        }))],
+                    },
+                )
+            })
+            .collect();
+
+        self.set_pending(wm, |pending| pending.lod_layers = Some(lod_layers));
+    }
+
+    fn set_pending(&self, wm: &WmRenderer, set: impl FnOnce(&mut PendingLayers)) {
+        //Read after the buffers were written, a flush in between only delays the swap by a frame
+        let generation = wm.uploads.lock().generation();
+
+        let mut pending = self.pending_layers.lock();
+        let pending = pending.get_or_insert_with(PendingLayers::default);
+
+        pending.generation = generation;
+        set(pending);
+    }
+
+    /// Replaces the drawn layers with the ones from the last [Chunk::upload] and [Chunk::upload_lods], if their
+    /// uploads are part of the `completed` generation or an earlier one. Returns whether they were replaced.
+    pub fn swap_buffers(&self, completed: u64) -> bool {
+        let mut pending_layers = self.pending_layers.lock();
+
+        match &*pending_layers {
+            Some(pending) if pending.generation <= completed => {}
+            _ => return false,
+        }
+
+        let pending = pending_layers.take().unwrap();
+
+        if let Some(baked_layers) = pending.baked_layers {
+            *self.baked_layers.write() = baked_layers;
+        }
+
+        if let Some(lod_layers) = pending.lod_layers {
+            *self.lod_layers.write() = lod_layers;
+        }
+
+        true
+    }
+}
+
+/// Allocates the buffers of the mesh of a single section. Translucent meshes get buffers of their own, as their
+/// indices are sorted for the camera, others are shared with identical meshes, see [ChunkManager::share_mesh].
+fn upload_section(
+    wm: &WmRenderer,
+    key: &LayerKey,
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> SectionBuffers {
+    let allocate = || {
+        (
+            allocate_vertices(wm, vertices),
+            wm.mc
+                .chunks
+                .buffer_allocator
+                .allocate(wm, bytemuck::cast_slice(indices)),
+        )
+    };
+
+    let (vertex_buffer, index_buffer) = if key.1 == RenderType::Translucent {
+        let (vertex_buffer, index_buffer) = allocate();
+        (Arc::new(vertex_buffer), Arc::new(index_buffer))
+    } else {
+        wm.mc.chunks.share_mesh(key, vertices, indices, allocate)
+    };
+
+    SectionBuffers {
+        vertex_buffer,
+        index_buffer,
+        index_count: indices.len() as u32,
+        sortable_quads: (key.1 == RenderType::Translucent)
+            .then(|| SortableQuads::new(vertices, indices)),
+    }
+}
+
+/// Buckets the contents of a layer for [ChunkManager::share_mesh]
+fn mesh_hash(key: &LayerKey, vertices: &[Vertex], indices: &[u32]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    key.hash(&mut hasher);
+    bytemuck::cast_slice::<_, u8>(vertices).hash(&mut hasher);
+    indices.hash(&mut hasher);
+
+    hasher.finish()
+}
+
+/// Allocates a vertex buffer in the [crate::WmConfig::chunk_vertex_format], starting at a whole vertex of its page
+fn allocate_vertices(wm: &WmRenderer, vertices: &[Vertex]) -> ChunkAllocation {
+    let allocator = &wm.mc.chunks.buffer_allocator;
+    let format = wm.config.chunk_vertex_format;
+
+    match format {
+        ChunkVertexFormat::Full => {
+            allocator.allocate_aligned(wm, bytemuck::cast_slice(vertices), format.stride())
+        }
+        ChunkVertexFormat::Packed => {
+            let packed: Vec<PackedVertex> = vertices.iter().map(PackedVertex::pack).collect();
+
+            allocator.allocate_aligned(wm, bytemuck::cast_slice(&packed), format.stride())
+        }
+    }
+}
+
+/// Every face is baked as two triangles with 6 vertices, two of which are shared between the triangles.
+/// This deduplicates the vertices of each face, so that a face only needs 4 vertices and 6 indices. The tangent of
+/// each face is filled in here too, as the mappers of the [RenderLayer]s only see one vertex at a time.
+fn index_quads(vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique = Vec::with_capacity(vertices.len() / 6 * 4);
+    let mut indices = Vec::with_capacity(vertices.len());
+
+    for face in vertices.chunks(6) {
+        let face_start = unique.len();
+        let tangent = Vertex::face_tangent(face);
+
+        for vertex in face {
+            let vertex = &Vertex { tangent, ..*vertex };
+
+            let existing = unique[face_start..]
+                .iter()
+                .position(|other: &Vertex| bytemuck::bytes_of(other) == bytemuck::bytes_of(vertex));
+
+            let index = match existing {
+                Some(offset) => face_start + offset,
+                None => {
+                    unique.push(*vertex);
+                    unique.len() - 1
+                }
+            };
+
+            indices.push(index as u32);
+        }
+    }
+
+    (unique, indices)
+}
+
+/// A bit for the section containing height `y`, and for the neighbouring section if the block is on its edge, as
+/// it can cull the faces of that section too
+fn dirty_section_mask(y: i16) -> u32 {
+    if y < 0 || y >= CHUNK_HEIGHT as i16 {
+        return 0;
+    }
+
+    let y = y as usize;
+    let section = y / CHUNK_SECTION_HEIGHT;
+    let mut mask = 1 << section;
+
+    if y % CHUNK_SECTION_HEIGHT == 0 && section > 0 {
+        mask |= 1 << (section - 1);
+    }
+    if y % CHUNK_SECTION_HEIGHT == CHUNK_SECTION_HEIGHT - 1 && section + 1 < CHUNK_SECTIONS_PER {
+        mask |= 1 << (section + 1);
+    }
+
+    mask
+}
+
+/// Bakes every section of the chunk, on the GPU if there's a [GpuMesher] and the chunk can be meshed there
+fn bake_all_sections<T: BlockStateProvider>(
+    wgpu_state: &WgpuState,
+    mc: &MinecraftState,
+    pos: ChunkPos,
+    layers: &[Box<dyn RenderLayer>],
+    block_manager: &BlockManager,
+    provider: &T,
+) -> Vec<BakedSection> {
+    let ambient_occlusion = **mc.smooth_lighting.load();
+
+    //The GPU ignores the filter and mapper, so it can't tell several layers apart
+    if let (Some(gpu_mesher), [layer]) = (&**mc.chunks.gpu_mesher.load(), layers) {
+        if let Some(sections) = gpu_mesher.bake_sections(
+            wgpu_state,
+            pos,
+            layer.name(),
+            block_manager,
+            provider,
+            ambient_occlusion,
+        ) {
+            return sections;
+        }
+    }
+
+    Chunk::bake_sections(
+        pos,
+        layers,
+        block_manager,
+        provider,
+        ambient_occlusion,
+        0..CHUNK_SECTIONS_PER,
+    )
+}
+
+/// The output of a bake submitted to a [ChunkBakery]
+struct BakedChunk {
+    pos: ChunkPos,
+    /// Used to throw away results which were overtaken by a newer bake of the same chunk
+    generation: u64,
+    sections: Vec<BakedSection>,
+    lods: LodMeshes,
+    neighbours: u8,
+}
+
+/// Bakes chunks on a thread pool, so that the thread submitting them (usually the render thread) doesn't stall.
+///
+/// Finished chunks are queued up until [ChunkBakery::upload_finished] is called, which should happen once
+/// per frame. Only the GPU upload happens on that thread.
+pub struct ChunkBakery {
+    pool: rayon::ThreadPool,
+    sender: Mutex<Sender<BakedChunk>>,
+    receiver: Mutex<Receiver<BakedChunk>>,
+    /// The generation of the newest bake submitted for each chunk which hasn't been uploaded yet
+    generations: Mutex<HashMap<ChunkPos, u64>>,
+    next_generation: AtomicU64,
+    /// Bakes submitted while the renderer is paused, which are started once it's resumed
+    held: Mutex<HashMap<ChunkPos, Box<dyn FnOnce() + Send>>>,
+}
+
+impl ChunkBakery {
+    /// `threads` works the same as [rayon::ThreadPoolBuilder::num_threads], 0 picks the amount of threads
+    /// automatically
+    pub fn new(threads: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .thread_name(|index| format!("wgpu-mc chunk baker {index}"))
+                .build()
+                .unwrap(),
+            sender: Mutex::new(sender),
+            receiver: Mutex::new(receiver),
+            generations: Mutex::new(HashMap::new()),
+            next_generation: AtomicU64::new(0),
+            held: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Queues the chunk to be baked with the current chunk layers and block manager. If the chunk is submitted
+    /// again before this bake is uploaded, only the newest one is kept. While the renderer is paused the bake is
+    /// held until the first call to [ChunkBakery::upload_finished] after it's resumed.
+    pub fn submit<T: BlockStateProvider + 'static>(
+        &self,
+        wm: &WmRenderer,
+        pos: ChunkPos,
+        provider: T,
+    ) {
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        self.generations.lock().insert(pos, generation);
+
+        let wgpu_state = wm.wgpu_state.clone();
+        let mc = wm.mc.clone();
+        let pipelines = wm.pipelines.load_full();
+        let sender = self.sender.lock().clone();
+
+        let bake = move || {
+            let neighbours = loaded_neighbours(&provider, pos);
+
+            let sections = bake_all_sections(
+                &wgpu_state,
+                &mc,
+                pos,
+                &pipelines.chunk_layers.load(),
+                &mc.block_manager.read(),
+                &provider,
+            );
+
+            let lods = Chunk::bake_lods(
+                pos,
+                &mc.block_manager.read(),
+                &provider,
+                **mc.lod_distance.load(),
+            );
+
+            //The receiver only goes away together with the bakery, at which point nobody cares about the result
+            let _ = sender.send(BakedChunk {
+                pos,
+                generation,
+                sections,
+                lods,
+                neighbours,
+            });
+        };
+
+        if wm.is_paused() {
+            self.held.lock().insert(pos, Box::new(bake));
+        } else {
+            self.pool.spawn(bake);
+        }
+    }
+
+    /// Throws away the bakes of the chunk which haven't been uploaded yet, for chunks which were unloaded with
+    /// [ChunkManager::unload_chunk]
+    pub fn cancel(&self, pos: ChunkPos) {
+        self.generations.lock().remove(&pos);
+        self.held.lock().remove(&pos);
+    }
+
+    /// Uploads every chunk which finished baking since the last call, adding it to the loaded chunks if it isn't
+    /// already. Returns how many chunks were uploaded. Nothing is uploaded while the renderer is paused.
+    pub fn upload_finished(&self, wm: &WmRenderer) -> usize {
+        if wm.is_paused() {
+            return 0;
+        }
+
+        for (_, bake) in self.held.lock().drain() {
+            self.pool.spawn(bake);
+        }
+
+        let finished: Vec<BakedChunk> = self.receiver.lock().try_iter().collect();
+
+        if finished.is_empty() {
+            return 0;
+        }
+
+        let mut generations = self.generations.lock();
+        let mut loaded_chunks = wm.mc.chunks.loaded_chunks.write();
+        let mut uploaded = 0;
+
+        for baked in finished {
+            if generations.get(&baked.pos) != Some(&baked.generation) {
+                continue;
+            }
+
+            generations.remove(&baked.pos);
+
+            let chunk = loaded_chunks
+                .entry(baked.pos)
+                .or_insert_with(|| ArcSwap::new(Arc::new(Chunk::new(baked.pos))))
+                .load();
+
+            chunk
+                .baked_neighbours
+                .store(baked.neighbours, Ordering::Relaxed);
+            let sections: Vec<usize> = baked.sections.iter().map(|section| section.index).collect();
+
+            chunk.replace_sections(baked.sections);
+            chunk.upload_sections(wm, sections);
+            chunk.upload_lods(wm, baked.lods);
+
+            uploaded += 1;
+        }
+
+        uploaded
+    }
+}
+
+/// Collects block updates and re-bakes the sections they touched a few chunks at a time, so that a burst of
+/// updates doesn't stall a frame.
+///
+/// Updates to the same section are coalesced until it's re-baked. Each call to [RebakeQueue::rebake] re-bakes
+/// the chunks with a dirty section closest to the camera first, and stops once [RebakeQueue::budget] is used
+/// up. The rest stays queued for the next call.
+#[derive(Debug)]
+pub struct RebakeQueue {
+    /// A bit for every dirty section of each chunk
+    dirty: Mutex<HashMap<ChunkPos, u32>>,
+    /// How long a single [RebakeQueue::rebake] may take. At least one chunk is re-baked per call regardless.
+    pub budget: ArcSwap<Duration>,
+}
+
+impl RebakeQueue {
+    #[must_use]
+    pub fn new(budget: Duration) -> Self {
+        Self {
+            dirty: Mutex::new(HashMap::new()),
+            budget: ArcSwap::new(Arc::new(budget)),
+        }
+    }
+
+    /// Marks the section containing the block at the absolute position, plus the sections around it which it can
+    /// cull the faces of, including ones in the neighbouring chunks. Chunks diagonal to a block in the corner are
+    /// marked too, as the ambient occlusion and smooth lighting of their blocks sample it.
+    pub fn mark_block(&self, x: i32, y: i16, z: i32) {
+        let mask = dirty_section_mask(y);
+
+        if mask == 0 {
+            return;
+        }
+
+        let pos = [
+            x.div_euclid(CHUNK_WIDTH as i32),
+            z.div_euclid(CHUNK_WIDTH as i32),
+        ];
+        let local = [
+            x.rem_euclid(CHUNK_WIDTH as i32),
+            z.rem_euclid(CHUNK_WIDTH as i32),
+        ];
+
+        let mut dirty = self.dirty.lock();
+        *dirty.entry(pos).or_default() |= mask;
+
+        let offsets = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| [x, z]))
+            .filter(|&offset| offset != [0, 0]);
+
+        for offset in offsets {
+            let on_edge = (0..2).all(|axis| match offset[axis] {
+                -1 => local[axis] == 0,
+                1 => local[axis] == CHUNK_WIDTH as i32 - 1,
+                _ => true,
+            });
+
+            if on_edge {
+                *dirty
+                    .entry([pos[0] + offset[0], pos[1] + offset[1]])
+                    .or_default() |= mask;
+            }
+        }
+    }
+
+    /// The number of sections waiting to be re-baked
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.dirty
+            .lock()
+            .values()
+            .map(|mask| mask.count_ones() as usize)
+            .sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dirty.lock().is_empty()
+    }
+
+    /// The dirty chunks, ordered by the distance from the camera to the centre of their closest dirty section
+    fn by_distance(&self, camera: [f32; 3]) -> Vec<ChunkPos> {
+        let dirty = self.dirty.lock();
+
+        let mut chunks: Vec<(ChunkPos, f32)> = dirty
+            .iter()
+            .map(|(pos, mask)| {
+                let distance = (0..CHUNK_SECTIONS_PER)
+                    .filter(|section| mask & (1 << section) != 0)
+                    .map(|section| {
+                        let centre = [
+                            (pos[0] as f32 + 0.5) * CHUNK_WIDTH as f32,
+                            (section as f32 + 0.5) * CHUNK_SECTION_HEIGHT as f32,
+                            (pos[1] as f32 + 0.5) * CHUNK_WIDTH as f32,
+                        ];
+
+                        (0..3)
+                            .map(|axis| (centre[axis] - camera[axis]).powi(2))
+                            .sum::<f32>()
+                    })
+                    .fold(f32::MAX, f32::min);
+
+                (*pos, distance)
+            })
+            .collect();
+
+        chunks.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+
+        chunks.into_iter().map(|(pos, _)| pos).collect()
+    }
+
+    /// Re-bakes and uploads dirty sections until the budget is used up, closest to the absolute camera position
+    /// first. `provider` returns the blocks around a chunk, chunks it returns [None] for are dropped from the
+    /// queue just like chunks which aren't loaded. Returns the number of sections which were baked.
+    pub fn rebake<T: BlockStateProvider>(
+        &self,
+        wm: &WmRenderer,
+        layers: &[Box<dyn RenderLayer>],
+        block_manager: &BlockManager,
+        camera: [f32; 3],
+        provider: impl Fn(ChunkPos) -> Option<T>,
+    ) -> usize {
+        let start = Instant::now();
+        let budget = **self.budget.load();
+        let mut baked = 0;
+
+        for pos in self.by_distance(camera) {
+            if baked > 0 && start.elapsed() >= budget {
+                break;
+            }
+
+            //Updates arriving while the chunk is baked mark it again
+            let dirty = match self.dirty.lock().remove(&pos) {
+                None => continue,
+                Some(dirty) => dirty,
+            };
+
+            let chunk = match wm.mc.chunks.loaded_chunks.read().get(&pos) {
+                None => continue,
+                Some(chunk) => chunk.load_full(),
+            };
+
+            if let Some(provider) = provider(pos) {
+                baked += chunk.rebake_sections(wm, layers, block_manager, &provider, dirty);
+            }
+        }
+
+        baked
+    }
+}
+
+#[inline]
+fn block_add_face_vertices<T, Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T>(
+    mapper: Mapper,
+    vertices: &mut Vec<T>,
+    x: i32,
+    y: i16,
+    z: i32,
+    face_vertices: &Option<[BlockMeshVertex; 6]>,
+) {
+    match face_vertices {
+        None => {}
+        Some(north) => vertices.extend(
+            north
+                .iter()
+                .map(|v| mapper(v, x as f32, y as f32, z as f32)),
+        ),
+    };
+}
+
+/// Returns true when blocks adjacent to the one at (x, y, z) should render their faces.
+#[inline]
+fn should_render_face(
+    block_manager: &BlockManager,
+    state_provider: &impl BlockStateProvider,
+    x: i32,
+    y: i16,
+    z: i32,
+) -> bool {
+    // Chunk data is indexed [0, CHUNK_HEIGHT), there is never anything above or below that
+    if y < 0 || y >= CHUNK_HEIGHT as i16 {
+        return true;
+    }
+
+    let state = get_block(block_manager, state_provider.get_state(x, y, z));
+
+    match state {
+        Some(mesh) => !mesh.is_full_opaque_cube,
+        None => true,
+    }
+}
+
+/// How bright a vertex is when 0 to 3 of the blocks around it don't occlude it, like Minecraft's smooth lighting
+const AMBIENT_OCCLUSION_BRIGHTNESS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+/// The direction each face points towards, in the order north, east, south, west, up, down
+pub(crate) const FACE_NORMALS: [[i32; 3]; 6] = [
+    [0, 0, -1],
+    [1, 0, 0],
+    [0, 0, 1],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+];
+
+/// Computes the brightness of each vertex of a cube face from the opaque blocks around the corner it lies on.
+/// Each corner touches two blocks next to the block in front of the face and one diagonal to it.
+fn face_ambient_occlusion(
+    block_manager: &BlockManager,
+    state_provider: &impl BlockStateProvider,
+    block: [i32; 3],
+    direction: usize,
+    vertices: &[BlockMeshVertex; 6],
+) -> [f32; 6] {
+    let (_, u_axis, v_axis) = FACE_AXES[direction];
+    let normal = FACE_NORMALS[direction];
+
+    let front = [
+        block[0] + normal[0],
+        block[1] + normal[1],
+        block[2] + normal[2],
+    ];
+
+    let occludes = |u: i32, v: i32| {
+        let mut pos = front;
+        pos[u_axis] += u;
+        pos[v_axis] += v;
+
+        !should_render_face(block_manager, state_provider, pos[0], pos[1] as i16, pos[2])
+    };
+
+    vertices.map(|vertex| {
+        let u = if vertex.position[u_axis] > 0.5 { 1 } else { -1 };
+        let v = if vertex.position[v_axis] > 0.5 { 1 } else { -1 };
+
+        let side_u = occludes(u, 0);
+        let side_v = occludes(0, v);
+
+        //The diagonal block can't be seen past two sides
+        let level = if side_u && side_v {
+            0
+        } else {
+            3 - side_u as usize - side_v as usize - occludes(u, v) as usize
+        };
+
+        AMBIENT_OCCLUSION_BRIGHTNESS[level]
+    })
+}
+
+/// The light at the position, the sky is visible above and below the world
+fn get_light(state_provider: &impl BlockStateProvider, x: i32, y: i32, z: i32) -> (u8, u8) {
+    if y < 0 || y >= CHUNK_HEIGHT as i32 {
+        return (0, 15);
+    }
+
+    state_provider.get_light(x, y as i16, z)
+}
+
+/// The biome color the tinted faces of the block at the position are multiplied with, see [crate::mc::biome].
+/// Most blocks aren't tinted, so the biome is only looked up for those which are.
+pub(crate) fn block_tint(
+    block_manager: &BlockManager,
+    state_provider: &impl BlockStateProvider,
+    key: BlockstateKey,
+    x: i32,
+    y: i16,
+    z: i32,
+) -> Option<[f32; 3]> {
+    if !block_manager.colors.tints.contains_key(&key.block) {
+        return None;
+    }
+
+    block_manager
+        .colors
+        .tint(key.block, &state_provider.get_biome(x, y, z))
+}
+
+pub(crate) fn get_block(
+    block_manager: &BlockManager,
+    state: ChunkBlockState,
+) -> Option<Arc<ModelMesh>> {
+    let key = match state {
+        ChunkBlockState::Air => return None,
+        ChunkBlockState::State(key) => key,
+    };
+
+    Some(
+        block_manager
+            .blocks
+            .get_index(key.block as usize)?
+            .1
+            .get_model(key.augment),
+    )
+}
+
+/// Bakes every block of the chunk which passes the filter. The vertices of each [RenderType] are kept separate,
+/// indexed by `RenderType as usize`. With `ambient_occlusion`, the corners of cube faces next to opaque blocks are
+/// darkened the same way as with Minecraft's smooth lighting.
+///
+/// Each section is baked on its own with rayon, and the vertices are concatenated in section order. Greedy
+/// meshing never merges faces across sections.
+pub fn bake_layer<
+    T: ShadedVertex + Send,
+    Provider: BlockStateProvider,
+    Filter: Fn(BlockstateKey) -> bool + Sync,
+    Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T + Sync,
+>(
+    block_manager: &BlockManager,
+    chunk: &Chunk,
+    mapper: Mapper,
+    filter: Filter,
+    state_provider: &Provider,
+    strategy: MeshingStrategy,
+    ambient_occlusion: bool,
+) -> [Vec<T>; 3] {
+    let sections: Vec<[Vec<T>; 3]> = (0..CHUNK_SECTIONS_PER)
+        .into_par_iter()
+        .map(|section| {
+            bake_section_layer(
+                block_manager,
+                chunk,
+                &mapper,
+                &filter,
+                state_provider,
+                strategy,
+                ambient_occlusion,
+                section,
+            )
+        })
+        .collect();
+
+    let mut meshes: [Vec<T>; 3] = std::array::from_fn(|render_type| {
+        Vec::with_capacity(
+            sections
+                .iter()
+                .map(|section| section[render_type].len())
+                .sum(),
+        )
+    });
+
+    for section in sections {
+        for (mesh, vertices) in meshes.iter_mut().zip(section) {
+            mesh.extend(vertices);
+        }
+    }
+
+    meshes
+}
+
+/// Like [bake_layer], but only bakes the blocks in one chunk section
+pub fn bake_section_layer<
+    T: ShadedVertex,
+    Provider: BlockStateProvider,
+    Filter: Fn(BlockstateKey) -> bool,
+    Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T,
+>(
+    block_manager: &BlockManager,
+    chunk: &Chunk,
+    mapper: Mapper,
+    filter: Filter,
+    state_provider: &Provider,
+    strategy: MeshingStrategy,
+    ambient_occlusion: bool,
+    section: usize,
+) -> [Vec<T>; 3] {
+    assert!(section < CHUNK_SECTIONS_PER);
+
+    bake_blocks(
+        block_manager,
+        chunk,
+        mapper,
+        filter,
+        state_provider,
+        strategy,
+        ambient_occlusion,
+        section * SECTION_VOLUME..(section + 1) * SECTION_VOLUME,
+    )
+}
+
+/// Bakes the blocks in the range of block indices, which have to start and end on a section boundary
+fn bake_blocks<
+    T: ShadedVertex,
+    Provider: BlockStateProvider,
+    Filter: Fn(BlockstateKey) -> bool,
+    Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T,
+>(
+    block_manager: &BlockManager,
+    chunk: &Chunk,
+    mapper: Mapper,
+    filter: Filter,
+    state_provider: &Provider,
+    strategy: MeshingStrategy,
+    ambient_occlusion: bool,
+    blocks: Range<usize>,
+) -> [Vec<T>; 3] {
+    let [chunk_world_x, chunk_world_z] = match chunk_origin(chunk.pos) {
+        Some(origin) => origin,
+        None => {
+            log::error!("Chunk {:?} is out of the addressable range", chunk.pos);
+            return Default::default();
+        }
+    };
+
+    //Generates the mesh of each render type for this chunk, culling faces whenever possible. Most blocks are solid.
+    let mut meshes: [Vec<T>; 3] = [
+        Vec::with_capacity(300_000 * blocks.len() / CHUNK_VOLUME),
+        Vec::new(),
+        Vec::new(),
+    ];
+
+    //Faces which are merged after every block has been visited, only used by MeshingStrategy::Greedy
+    let mut greedy_faces: [Vec<GreedyFace>; 3] = Default::default();
+
+    let heights: [i16; CHUNK_AREA] = std::array::from_fn(|column| {
+        state_provider
+            .column_height(
+                chunk_world_x + (column % CHUNK_WIDTH) as i32,
+                chunk_world_z + (column / CHUNK_WIDTH) as i32,
+            )
+            .unwrap_or(CHUNK_HEIGHT as i16)
+    });
+    let max_height = heights.iter().copied().max().unwrap_or(0);
+
+    let mut block_index = blocks.start;
+
+    loop {
+        if block_index >= blocks.end {
+            break;
+        }
+
+        let x = (block_index % CHUNK_WIDTH) as i32;
+        let y = (block_index / CHUNK_AREA) as i16;
+        let z = ((block_index % CHUNK_AREA) / CHUNK_WIDTH) as i32;
+
+        if x == 0 && z == 0 && (y as usize % CHUNK_SECTION_HEIGHT) == 0 {
+            let section_index = y as usize / CHUNK_SECTION_HEIGHT;
+            debug_assert!(section_index < CHUNK_SECTIONS_PER);
+
+            if state_provider.is_section_empty(section_index) || y >= max_height {
+                block_index += CHUNK_SECTION_HEIGHT * CHUNK_AREA;
+                continue;
+            }
+        }
+
+        block_index += 1;
+
+        if y >= heights[z as usize * CHUNK_WIDTH + x as usize] {
+            continue;
+        }
+
+        let absolute_x = chunk_world_x + x;
+        let absolute_z = chunk_world_z + z;
+
+        let block_state: ChunkBlockState = state_provider.get_state(absolute_x, y, absolute_z);
+
+        if block_state.is_air() {
+            continue;
+        }
+
+        let state_key = match block_state {
+            ChunkBlockState::Air => unreachable!(),
+            ChunkBlockState::State(key) => key,
+        };
+
+        if !filter(state_key) {
+            continue;
+        }
+
+        let mesh = get_block(block_manager, block_state).unwrap();
+
+        let tint = block_tint(
+            block_manager,
+            state_provider,
+            state_key,
+            absolute_x,
+            y,
+            absolute_z,
+        );
+
+        //The color the vertices of a face are multiplied with, only faces with a tintindex are tinted
+        let face_tint = |face_vertices: &[BlockMeshVertex; 6]| match tint {
+            Some(tint) if face_vertices[0].tint_index != NO_TINT => tint,
+            _ => [1.0; 3],
+        };
+
+        let vertices = &mut meshes[mesh.render_type as usize];
+        let greedy_faces = &mut greedy_faces[mesh.render_type as usize];
+
+        let is_multipart = matches!(
+            block_manager.blocks.get_index(state_key.block as usize),
+            Some((_, Block::Multipart(_)))
+        );
+
+        //Every part of a multipart model is drawn, whereas the models of a variant are alternatives
+        let parts = if is_multipart {
+            &mesh.models[..]
+        } else {
+            let model = mesh.pick_model(absolute_x, y as i32, absolute_z);
+            &mesh.models[model..=model]
+        };
+
+        for (part, _) in parts {
+            match part {
+                CubeOrComplexMesh::Cube(model) => {
+                    let baked_should_render_face = |x_: i32, y_: i16, z_: i32| {
+                        should_render_face(block_manager, state_provider, x_, y_, z_)
+                    };
+
+                    let render_east = baked_should_render_face(absolute_x + 1, y, absolute_z);
+                    let render_west = baked_should_render_face(absolute_x - 1, y, absolute_z);
+                    let render_up = baked_should_render_face(absolute_x, y + 1, absolute_z);
+                    let render_down = baked_should_render_face(absolute_x, y - 1, absolute_z);
+                    let render_south = baked_should_render_face(absolute_x, y, absolute_z + 1);
+                    let render_north = baked_should_render_face(absolute_x, y, absolute_z - 1);
+
+                    let faces = [
+                        (&model.north, render_north),
+                        (&model.east, render_east),
+                        (&model.south, render_south),
+                        (&model.west, render_west),
+                        (&model.up, render_up),
+                        (&model.down, render_down),
+                    ];
+
+                    for (direction, (face_vertices, render)) in faces.into_iter().enumerate() {
+                        let face_vertices = match face_vertices {
+                            Some(face_vertices) if render => face_vertices,
+                            _ => continue,
+                        };
+
+                        let connected_vertices;
+                        let face_vertices = match &model.connected[direction] {
+                            Some(connected) => {
+                                connected_vertices = connected.connect(face_vertices, |offset| {
+                                    connected.connects_at(
+                                        block_manager,
+                                        state_provider,
+                                        state_key,
+                                        direction,
+                                        [absolute_x, y as i32, absolute_z],
+                                        offset,
+                                    )
+                                });
+
+                                &connected_vertices
+                            }
+                            None => face_vertices,
+                        };
+
+                        let normal = FACE_NORMALS[direction];
+                        let light = get_light(
+                            state_provider,
+                            absolute_x + normal[0],
+                            y as i32 + normal[1],
+                            absolute_z + normal[2],
+                        );
+
+                        let tint = face_tint(face_vertices);
+
+                        let brightness = if ambient_occlusion {
+                            face_ambient_occlusion(
+                                block_manager,
+                                state_provider,
+                                [absolute_x, y as i32, absolute_z],
+                                direction,
+                                face_vertices,
+                            )
+                        } else {
+                            [1.0; 6]
+                        };
+
+                        match strategy {
+                            MeshingStrategy::Greedy => greedy_faces.push(GreedyFace {
+                                direction,
+                                pos: [x, y as i32, z],
+                                vertices: *face_vertices,
+                                brightness,
+                                light,
+                                tint,
+                            }),
+                            MeshingStrategy::PerFace => {
+                                vertices.extend(face_vertices.iter().zip(brightness).map(
+                                    |(vertex, brightness)| {
+                                        let mut vertex =
+                                            mapper(vertex, x as f32, y as f32, z as f32);
+                                        vertex.shade(brightness);
+                                        vertex.tint(tint);
+                                        vertex.light(light.0, light.1);
+                                        vertex
+                                    },
+                                ))
+                            }
+                        }
+                    }
+                }
+                CubeOrComplexMesh::Complex(model) => {
+                    //Faces of complex models usually don't line up with the block space, so they're lit by the
+                    // block itself
+                    let (block_light, sky_light) =
+                        get_light(state_provider, absolute_x, y as i32, absolute_z);
+                    let start = vertices.len();
+
+                    model.iter().for_each(|faces| {
+                        [
+                            &faces.north,
+                            &faces.east,
+                            &faces.south,
+                            &faces.west,
+                            &faces.up,
+                            &faces.down,
+                        ]
+                        .into_iter()
+                        .for_each(|face_vertices| {
+                            let face_start = vertices.len();
+                            block_add_face_vertices(&mapper, vertices, x, y, z, face_vertices);
+
+                            if let Some(face_vertices) = face_vertices {
+                                let tint = face_tint(face_vertices);
+                                vertices[face_start..]
+                                    .iter_mut()
+                                    .for_each(|vertex| vertex.tint(tint));
+                            }
+                        });
+                    });
+
+                    vertices[start..]
+                        .iter_mut()
+                        .for_each(|vertex| vertex.light(block_light, sky_light));
+                }
+            }
+        }
+    }
+
+    meshes
+        .iter_mut()
+        .zip(&greedy_faces)
+        .for_each(|(vertices, greedy_faces)| {
+            greedy_mesh(&mapper, vertices, greedy_faces);
+            vertices.shrink_to_fit();
+        });
+
+    meshes
+}
+
+/// A visible face of a cube, waiting to be merged with its neighbours
+struct GreedyFace {
+    /// Index into north, east, south, west, up, down
+    direction: usize,
+    /// Chunk-local block position
+    pos: [i32; 3],
+    vertices: [BlockMeshVertex; 6],
+    /// The ambient occlusion of each vertex
+    brightness: [f32; 6],
+    /// The block light and sky light in front of the face
+    light: (u8, u8),
+    /// The biome color the face is multiplied with
+    tint: [f32; 3],
+}
+
+/// The axis each face direction points along, and the two axes of the plane the face lies in
+const FACE_AXES: [(usize, usize, usize); 6] = [
+    (2, 0, 1),
+    (0, 2, 1),
+    (2, 0, 1),
+    (0, 2, 1),
+    (1, 0, 2),
+    (1, 0, 2),
+];
+
+const CHUNK_DIMENSIONS: [usize; 3] = [CHUNK_WIDTH, CHUNK_HEIGHT, CHUNK_WIDTH];
+
+/// Whether the attribute of the vertices is the same at both ends of the face along the axis, so that stretching
+/// the face along it doesn't stretch what the attribute shows
+fn constant_along<T: PartialEq>(
+    face: &GreedyFace,
+    other_axis: usize,
+    attribute: impl Fn(&BlockMeshVertex) -> T,
+) -> bool {
+    face.vertices.iter().all(|a| {
+        face.vertices.iter().all(|b| {
+            a.position[other_axis] != b.position[other_axis] || attribute(a) == attribute(b)
+        })
+    })
+}
+
+/// How the texture of a face repeats when it's stretched over several blocks, see [ShadedVertex::repeat_texture]
+struct TextureRepeat {
+    /// The min and max texture coordinates of the face
+    region: [f32; 4],
+    /// The texture coordinates of each vertex, relative to the region
+    tex_coords: [[f32; 2]; 6],
+    /// How much the relative texture coordinates change per block along the u and v axes of the face
+    per_block: [[f32; 2]; 2],
+}
+
+impl TextureRepeat {
+    /// None if the texture coordinates are the same all across the face, or if the face doesn't span an area
+    fn new(face: &GreedyFace, u_axis: usize, v_axis: usize) -> Option<Self> {
+        let (min, max) =
+            face.vertices
+                .iter()
+                .fold(([f32::MAX; 2], [f32::MIN; 2]), |(min, max), vertex| {
+                    (
+                        [0, 1].map(|axis| min[axis].min(vertex.tex_coords[axis])),
+                        [0, 1].map(|axis| max[axis].max(vertex.tex_coords[axis])),
+                    )
+                });
+
+        if max[0] <= min[0] && max[1] <= min[1] {
+            return None;
+        }
+
+        //Axes the texture doesn't change along stay at the edge of the region
+        let tex_coords = face.vertices.map(|vertex| {
+            [0, 1].map(|axis| {
+                if max[axis] > min[axis] {
+                    (vertex.tex_coords[axis] - min[axis]) / (max[axis] - min[axis])
+                } else {
+                    0.0
+                }
+            })
+        });
+
+        //The relative texture coordinates are linear in the position, which the first triangle solves for
+        let [a, b, c] = [0, 1, 2].map(|vertex| {
+            let position = face.vertices[vertex].position;
+            [position[u_axis], position[v_axis]]
+        });
+        let (edge1, edge2) = ([b[0] - a[0], b[1] - a[1]], [c[0] - a[0], c[1] - a[1]]);
+        let determinant = edge1[0] * edge2[1] - edge2[0] * edge1[1];
+
+        if determinant.abs() <= f32::EPSILON {
+            return None;
+        }
+
+        let delta1 = [0, 1].map(|axis| tex_coords[1][axis] - tex_coords[0][axis]);
+        let delta2 = [0, 1].map(|axis| tex_coords[2][axis] - tex_coords[0][axis]);
+
+        let per_block = [
+            [0, 1].map(|axis| (delta1[axis] * edge2[1] - delta2[axis] * edge1[1]) / determinant),
+            [0, 1].map(|axis| (edge1[0] * delta2[axis] - edge2[0] * delta1[axis]) / determinant),
+        ];
+
+        Some(Self {
+            region: [min[0], min[1], max[0], max[1]],
+            tex_coords,
+            per_block,
+        })
+    }
+
+    /// The relative texture coordinates of each vertex once the face is stretched over `size` blocks along its u
+    /// and v axes, shifted by whole repeats so that they start at 0
+    fn stretched(&self, face: &GreedyFace, axes: [usize; 2], size: [usize; 2]) -> [[f32; 2]; 6] {
+        let mut stretched = [[0.0; 2]; 6];
+
+        for (vertex, tex_coords) in stretched.iter_mut().enumerate() {
+            let position = face.vertices[vertex].position;
+
+            *tex_coords = [0, 1].map(|axis| {
+                self.tex_coords[vertex][axis]
+                    + (0..2)
+                        .map(|plane_axis| {
+                            position[axes[plane_axis]]
+                                * (size[plane_axis] - 1) as f32
+                                * self.per_block[plane_axis][axis]
+                        })
+                        .sum::<f32>()
+            });
+        }
+
+        let start = [0, 1].map(|axis| {
+            stretched
+                .iter()
+                .map(|tex_coords| tex_coords[axis])
+                .fold(f32::MAX, f32::min)
+                .floor()
+        });
+
+        stretched.map(|tex_coords| [tex_coords[0] - start[0], tex_coords[1] - start[1]])
+    }
+}
+
+/// Merges the faces into as few quads as possible. Faces can only be merged if they point in the same direction,
+/// lie in the same plane and have the exact same vertex data and light, and the texture of a merged quad repeats
+/// once per block, see [MeshingStrategy::Greedy]. Emissive overlays aren't repeated, so faces with one are only
+/// merged along the axes the overlay doesn't change along. Faces which aren't evenly lit by ambient occlusion are
+/// never merged, as their shading would be stretched across the whole quad.
+fn greedy_mesh<T: ShadedVertex, Mapper: Fn(&BlockMeshVertex, f32, f32, f32) -> T>(
+    mapper: Mapper,
+    vertices: &mut Vec<T>,
+    faces: &[GreedyFace],
+) {
+    let mut slices: HashMap<(usize, i32), Vec<&GreedyFace>> = HashMap::new();
+
+    faces.iter().for_each(|face| {
+        let (normal_axis, _, _) = FACE_AXES[face.direction];
+
+        slices
+            .entry((face.direction, face.pos[normal_axis]))
+            .or_default()
+            .push(face);
+    });
+
+    for ((direction, _), slice) in slices {
+        let (_, u_axis, v_axis) = FACE_AXES[direction];
+        let u_len = CHUNK_DIMENSIONS[u_axis];
+        let v_len = CHUNK_DIMENSIONS[v_axis];
+
+        let mut grid: Vec<Option<&GreedyFace>> = vec![None; u_len * v_len];
+
+        slice.iter().for_each(|&face| {
+            grid[face.pos[v_axis] as usize * u_len + face.pos[u_axis] as usize] = Some(face);
+        });
+
+        let mergeable = |cell: Option<&GreedyFace>, face: &GreedyFace| {
+            cell.map_or(false, |cell| {
+                bytemuck::bytes_of(&cell.vertices) == bytemuck::bytes_of(&face.vertices)
+                    && cell.brightness == face.brightness
+                    && cell.light == face.light
+                    && cell.tint == face.tint
+                    && face
+                        .brightness
+                        .iter()
+                        .all(|&brightness| brightness == face.brightness[0])
+            })
+        };
+
+        for v in 0..v_len {
+            let mut u = 0;
+
+            while u < u_len {
+                let face = match grid[v * u_len + u] {
+                    None => {
+                        u += 1;
+                        continue;
+                    }
+                    Some(face) => face,
+                };
+
+                let repeat = TextureRepeat::new(face, u_axis, v_axis);
+
+                //Along the u axis, the vertices at either end differ in their v coordinate
+                let stretchable = |other_axis: usize| {
+                    constant_along(face, other_axis, |vertex| vertex.emissive_tex_coords)
+                        && (repeat.is_some()
+                            || constant_along(face, other_axis, |vertex| vertex.tex_coords))
+                };
+
+                let mut width = 1;
+                while stretchable(v_axis)
+                    && u + width < u_len
+                    && mergeable(grid[v * u_len + u + width], face)
+                {
+                    width += 1;
+                }
+
+                let mut height = 1;
+                while stretchable(u_axis)
+                    && v + height < v_len
+                    && (u..u + width).all(|u_| mergeable(grid[(v + height) * u_len + u_], face))
+                {
+                    height += 1;
+                }
+
+                for v_ in v..v + height {
+                    grid[v_ * u_len + u..v_ * u_len + u + width].fill(None);
+                }
+
+                let mut scale = [1.0; 3];
+                scale[u_axis] = width as f32;
+                scale[v_axis] = height as f32;
+
+                //Single faces keep the texture coordinates of the atlas
+                let repeated = repeat
+                    .as_ref()
+                    .filter(|_| width > 1 || height > 1)
+                    .map(|repeat| {
+                        (
+                            repeat.stretched(face, [u_axis, v_axis], [width, height]),
+                            repeat.region,
+                        )
+                    });
+
+                vertices.extend(face.vertices.iter().zip(face.brightness).enumerate().map(
+                    |(index, (vertex, brightness))| {
+                        let stretched = BlockMeshVertex {
+                            position: [
+                                vertex.position[0] * scale[0],
+                                vertex.position[1] * scale[1],
+                                vertex.position[2] * scale[2],
+                            ],
+                            ..*vertex
+                        };
+
+                        let mut vertex = mapper(
+                            &stretched,
+                            face.pos[0] as f32,
+                            face.pos[1] as f32,
+                            face.pos[2] as f32,
+                        );
+                        vertex.shade(brightness);
+                        vertex.tint(face.tint);
+                        vertex.light(face.light.0, face.light.1);
+
+                        if let Some((tex_coords, region)) = &repeated {
+                            vertex.repeat_texture(tex_coords[index], *region);
+                        }
+
+                        vertex
+                    },
+                ));
+
+                u += width;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use arc_swap::ArcSwap;
+    use indexmap::IndexMap;
+    use parking_lot::RwLock;
+
+    use super::{
+        bake_layer, chunk_origin, index_quads, mesh_hash, BlockStateProvider, Chunk, ChunkManager,
+        MeshingStrategy, PendingLayers, RebakeQueue, ShadedVertex, SharedMeshes, SortableQuads,
+        ALL_NEIGHBOURS, AMBIENT_OCCLUSION_BRIGHTNESS, CHUNK_WIDTH,
+    };
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::{
+        BlockMeshVertex, BlockModelFaces, BlockstateKey, ChunkBlockState, CubeOrComplexMesh,
+        ModelMesh, RenderType, NO_EMISSIVE, NO_TINT,
+    };
+    use crate::mc::{Block, BlockManager, Multipart};
+    use crate::render::lightmap::lightmap_coords;
+    use crate::render::pipeline::Vertex;
+
+    /// Contains the same block at each of the positions, and air everywhere else
+    #[derive(Debug)]
+    struct BlocksProvider {
+        positions: Vec<(i32, i16, i32)>,
+    }
+
+    impl BlockStateProvider for BlocksProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            if self.positions.contains(&(x, y, z)) {
+                ChunkBlockState::State(BlockstateKey {
+                    block: 0,
+                    augment: 0,
+                })
+            } else {
+                ChunkBlockState::Air
+            }
+        }
+
+        fn is_section_empty(&self, index: usize) -> bool {
+            !self
+                .positions
+                .iter()
+                .any(|(_, y, _)| *y as usize / 16 == index)
+        }
+    }
+
+    /// [BlocksProvider] with the light levels of each block depending on its height
+    #[derive(Debug)]
+    struct LitProvider(BlocksProvider);
+
+    impl BlockStateProvider for LitProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            self.0.get_state(x, y, z)
+        }
+
+        fn is_section_empty(&self, index: usize) -> bool {
+            self.0.is_section_empty(index)
+        }
+
+        fn get_light(&self, _x: i32, y: i16, _z: i32) -> (u8, u8) {
+            (y as u8, 15 - y as u8)
+        }
+    }
+
+    //Most tests only look at the positions
+    impl ShadedVertex for [f32; 3] {
+        fn shade(&mut self, _brightness: f32) {}
+
+        fn tint(&mut self, _color: [f32; 3]) {}
+
+        fn light(&mut self, _block_light: u8, _sky_light: u8) {}
+
+        fn repeat_texture(&mut self, _tex_coords: [f32; 2], _region: [f32; 4]) {}
+    }
+
+    fn face(position: [f32; 3]) -> Option<[BlockMeshVertex; 6]> {
+        Some(
+            [BlockMeshVertex {
+                position,
+                tex_coords: [0.0, 0.0],
+                normal: [0.0, 0.0, 0.0, 1.0],
+                animation_uv_offset: 0,
+                emissive_tex_coords: NO_EMISSIVE,
+                tint_index: NO_TINT,
+            }; 6],
+        )
+    }
+
+    fn faces() -> BlockModelFaces {
+        BlockModelFaces {
+            north: face([0.0, 0.0, 0.0]),
+            east: face([1.0, 0.0, 0.0]),
+            south: face([0.0, 0.0, 1.0]),
+            west: face([0.0, 1.0, 0.0]),
+            up: face([1.0, 1.0, 0.0]),
+            down: face([1.0, 1.0, 1.0]),
+            connected: Default::default(),
+        }
+    }
+
+    fn block_manager(block: Block) -> BlockManager {
+        BlockManager {
+            blocks: [("wgpu_mc:test".into(), block)].into_iter().collect(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        }
+    }
+
+    fn mesh(models: Vec<CubeOrComplexMesh>) -> Arc<ModelMesh> {
+        Arc::new(ModelMesh {
+            weights: vec![1; models.len()],
+            models: models.into_iter().map(|model| (model, true)).collect(),
+            is_full_opaque_cube: false,
+            render_type: RenderType::Solid,
+        })
+    }
+
+    fn variants(model: CubeOrComplexMesh) -> Block {
+        Block::Variants(IndexMap::from([("".into(), mesh(vec![model]))]))
+    }
+
+    /// Bakes the block at the given chunk-local positions in chunk [2, -1] and returns the positions of the vertices
+    /// of each render type
+    fn bake_render_types(
+        block: Block,
+        positions: &[(i32, i16, i32)],
+        strategy: MeshingStrategy,
+    ) -> [Vec<[f32; 3]>; 3] {
+        let block_manager = block_manager(block);
+        let chunk = Chunk::new([2, -1]);
+        let provider = BlocksProvider {
+            positions: positions
+                .iter()
+                .map(|(x, y, z)| (2 * 16 + x, *y, -16 + z))
+                .collect(),
+        };
+
+        bake_layer(
+            &block_manager,
+            &chunk,
+            |vertex, x, y, z| {
+                [
+                    vertex.position[0] + x,
+                    vertex.position[1] + y,
+                    vertex.position[2] + z,
+                ]
+            },
+            |_| true,
+            &provider,
+            strategy,
+            false,
+        )
+    }
+
+    /// Like [bake_render_types], but with the vertices of every render type in one list
+    fn bake_blocks(
+        block: Block,
+        positions: &[(i32, i16, i32)],
+        strategy: MeshingStrategy,
+    ) -> Vec<[f32; 3]> {
+        bake_render_types(block, positions, strategy)
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// Bakes a single block at chunk-local (1, 2, 3)
+    fn bake_single_block(block: Block) -> Vec<[f32; 3]> {
+        bake_blocks(block, &[(1, 2, 3)], MeshingStrategy::PerFace)
+    }
+
+    fn expected_positions() -> Vec<[f32; 3]> {
+        let faces = faces();
+
+        [
+            faces.north,
+            faces.east,
+            faces.south,
+            faces.west,
+            faces.up,
+            faces.down,
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|vertex| {
+            [
+                vertex.position[0] + 1.0,
+                vertex.position[1] + 2.0,
+                vertex.position[2] + 3.0,
+            ]
+        })
+        .collect()
+    }
+
+    #[test]
+    fn complex_mesh_uses_chunk_local_position() {
+        let positions = bake_single_block(variants(CubeOrComplexMesh::Complex(vec![faces()])));
+
+        assert_eq!(positions, expected_positions());
+    }
+
+    #[test]
+    fn cube_mesh_uses_chunk_local_position() {
+        let positions = bake_single_block(variants(CubeOrComplexMesh::Cube(Box::new(faces()))));
+
+        assert_eq!(positions, expected_positions());
+    }
+
+    #[test]
+    fn faces_are_lit_by_the_block_in_front_of_them() {
+        let block_manager = block_manager(variants(CubeOrComplexMesh::Cube(Box::new(faces()))));
+        let provider = LitProvider(BlocksProvider {
+            positions: vec![(1, 2, 3)],
+        });
+
+        let [vertices, _, _] = bake_layer(
+            &block_manager,
+            &Chunk::new([0, 0]),
+            |vertex, x, y, z| Vertex {
+                position: [
+                    vertex.position[0] + x,
+                    vertex.position[1] + y,
+                    vertex.position[2] + z,
+                ],
+                ..bytemuck::Zeroable::zeroed()
+            },
+            |_| true,
+            &provider,
+            MeshingStrategy::PerFace,
+            false,
+        );
+
+        //North, east, south and west are next to the block, up is above and down below it
+        let expected = [(2, 13), (2, 13), (2, 13), (2, 13), (3, 12), (1, 14)];
+
+        assert_eq!(vertices.len(), 36);
+
+        for (face, (block_light, sky_light)) in vertices.chunks(6).zip(expected) {
+            assert!(face
+                .iter()
+                .all(|vertex| vertex.lightmap_coords == lightmap_coords(block_light, sky_light)));
+        }
+    }
+
+    #[test]
+    fn multipart_bakes_every_part() {
+        let multipart = Multipart {
+            cases: Vec::new(),
+            conditions: Vec::new(),
+            keys: RwLock::new(IndexMap::from([(
+                "".into(),
+                mesh(vec![
+                    CubeOrComplexMesh::Cube(Box::new(faces())),
+                    CubeOrComplexMesh::Complex(vec![faces()]),
+                ]),
+            )])),
+        };
+
+        let positions = bake_single_block(Block::Multipart(multipart));
+
+        assert_eq!(
+            positions,
+            [expected_positions(), expected_positions()].concat()
+        );
+    }
+
+    #[test]
+    fn translucent_blocks_are_baked_separately() {
+        let translucent = Block::Variants(IndexMap::from([(
+            "".into(),
+            Arc::new(ModelMesh {
+                models: vec![(CubeOrComplexMesh::Cube(Box::new(faces())), true)],
+                is_full_opaque_cube: false,
+                render_type: RenderType::Translucent,
+                weights: vec![1],
+            }),
+        )]));
+
+        let [solid, cutout, translucent] =
+            bake_render_types(translucent, &[(1, 2, 3)], MeshingStrategy::PerFace);
+
+        assert!(solid.is_empty());
+        assert!(cutout.is_empty());
+        assert_eq!(translucent, expected_positions());
+    }
+
+    #[test]
+    fn greedy_meshing_merges_coplanar_faces() {
+        let cube = || variants(CubeOrComplexMesh::Cube(Box::new(faces())));
+        let positions = [(1, 2, 3), (2, 2, 3)];
+
+        //The test mesh isn't opaque, so the faces between the two blocks aren't culled
+        let per_face = bake_blocks(cube(), &positions, MeshingStrategy::PerFace);
+        assert_eq!(per_face.len(), 12 * 6);
+
+        //North, south, up and down are merged, the east and west faces are each in their own plane
+        let greedy = bake_blocks(cube(), &positions, MeshingStrategy::Greedy);
+        assert_eq!(greedy.len(), 8 * 6);
+    }
+
+    #[test]
+    fn greedy_meshing_repeats_the_texture_of_merged_faces() {
+        //A 16x16 sprite of a 256x256 atlas
+        const SPRITE: [f32; 4] = [0.25, 0.5, 0.3125, 0.5625];
+
+        //Only an up face showing the whole sprite, whose u axis is x and v axis is z
+        let up_face = |emissive_tex_coords: fn([f32; 2]) -> [f32; 2]| {
+            let vertex = |x: f32, z: f32| BlockMeshVertex {
+                position: [x, 1.0, z],
+                tex_coords: [
+                    SPRITE[0] + x * (SPRITE[2] - SPRITE[0]),
+                    SPRITE[1] + z * (SPRITE[3] - SPRITE[1]),
+                ],
+                normal: [0.0, 1.0, 0.0, 1.0],
+                animation_uv_offset: 0,
+                emissive_tex_coords: emissive_tex_coords([x, z]),
+                tint_index: NO_TINT,
+            };
+
+            variants(CubeOrComplexMesh::Cube(Box::new(BlockModelFaces {
+                north: None,
+                east: None,
+                south: None,
+                west: None,
+                up: Some([
+                    vertex(0.0, 0.0),
+                    vertex(1.0, 0.0),
+                    vertex(1.0, 1.0),
+                    vertex(1.0, 1.0),
+                    vertex(0.0, 1.0),
+                    vertex(0.0, 0.0),
+                ]),
+                down: None,
+                connected: Default::default(),
+            })))
+        };
+
+        let bake = |block, positions: Vec<(i32, i16, i32)>| {
+            let [vertices, _, _] = bake_layer(
+                &block_manager(block),
+                &Chunk::new([0, 0]),
+                |vertex, x, y, z| Vertex {
+                    position: [
+                        vertex.position[0] + x,
+                        vertex.position[1] + y,
+                        vertex.position[2] + z,
+                    ],
+                    tex_coords: vertex.tex_coords,
+                    ..bytemuck::Zeroable::zeroed()
+                },
+                |_| true,
+                &BlocksProvider { positions },
+                MeshingStrategy::Greedy,
+                false,
+            );
+
+            vertices
+        };
+
+        //A flat 16x16 plane becomes a single quad, which shows the sprite once per block
+        let plane = (0..16)
+            .flat_map(|x| (0..16).map(move |z| (x, 2, z)))
+            .collect();
+        let merged = bake(up_face(|_| NO_EMISSIVE), plane);
+        assert_eq!(merged.len(), 6);
+
+        for vertex in &merged {
+            assert!(vertex.repeats_texture());
+            assert_eq!(vertex.region, SPRITE);
+            assert!([0.0, 16.0].contains(&vertex.position[0]));
+            assert!([0.0, 16.0].contains(&vertex.position[2]));
+            assert_eq!(vertex.tex_coords, [vertex.position[0], vertex.position[2]]);
+        }
+
+        //A face on its own keeps the texture coordinates of the atlas
+        let single = bake(up_face(|_| NO_EMISSIVE), vec![(1, 2, 3)]);
+        assert_eq!(single.len(), 6);
+        assert!(single.iter().all(|vertex| !vertex.repeats_texture()));
+        assert!(single
+            .iter()
+            .any(|vertex| vertex.tex_coords == [SPRITE[2], SPRITE[3]]));
+
+        //The emissive overlay isn't repeated, so faces whose overlay changes along an axis aren't merged along it
+        let striped = bake(
+            up_face(|[_, z]| [0.5, z * 0.1]),
+            vec![(1, 2, 3), (2, 2, 3), (1, 2, 4)],
+        );
+        assert_eq!(striped.len(), 12);
+
+        let emissive = bake(
+            up_face(|[x, z]| [x * 0.1, z * 0.1]),
+            vec![(1, 2, 3), (2, 2, 3)],
+        );
+        assert_eq!(emissive.len(), 12);
+    }
+
+    #[test]
+    fn index_quads_shares_vertices_within_faces() {
+        let vertex = |x: f32, y: f32| Vertex {
+            position: [x, y, 0.0],
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        //Two faces which touch, the vertices on the shared edge are in separate faces so they aren't shared
+        let vertices: Vec<Vertex> = [0.0, 1.0]
+            .into_iter()
+            .flat_map(|x| {
+                [
+                    vertex(x, 0.0),
+                    vertex(x + 1.0, 0.0),
+                    vertex(x + 1.0, 1.0),
+                    vertex(x + 1.0, 1.0),
+                    vertex(x, 1.0),
+                    vertex(x, 0.0),
+                ]
+            })
+            .collect();
+
+        let (unique, indices) = index_quads(&vertices);
+
+        assert_eq!(unique.len(), 8);
+        assert_eq!(indices.len(), 12);
+
+        let expanded: Vec<[f32; 3]> = indices
+            .iter()
+            .map(|index| unique[*index as usize].position)
+            .collect();
+        let original: Vec<[f32; 3]> = vertices.iter().map(|vertex| vertex.position).collect();
+
+        assert_eq!(expanded, original);
+    }
+
+    #[test]
+    fn index_quads_fills_in_the_tangent_of_each_face() {
+        //A face looking south whose texture runs along x, and one whose texture is mirrored
+        let face = |u: f32| {
+            [
+                ([0.0, 0.0], [0.0, 1.0]),
+                ([1.0, 0.0], [u, 1.0]),
+                ([1.0, 1.0], [u, 0.0]),
+                ([1.0, 1.0], [u, 0.0]),
+                ([0.0, 1.0], [0.0, 0.0]),
+                ([0.0, 0.0], [0.0, 1.0]),
+            ]
+            .map(|([x, y], tex_coords)| Vertex {
+                position: [x, y, 1.0],
+                tex_coords,
+                normal: [0.0, 0.0, 1.0, 1.0],
+                ..bytemuck::Zeroable::zeroed()
+            })
+        };
+
+        let vertices: Vec<Vertex> = [1.0, -1.0].into_iter().flat_map(face).collect();
+        let (unique, _) = index_quads(&vertices);
+
+        //v grows downwards, so the bitangent of the first face points away from the normal crossed with the tangent
+        assert!(unique[..4]
+            .iter()
+            .all(|vertex| vertex.tangent == [1.0, 0.0, 0.0, -1.0]));
+        assert!(unique[4..]
+            .iter()
+            .all(|vertex| vertex.tangent == [-1.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn sortable_quads_are_sorted_back_to_front() {
+        let quad = |z: f32| {
+            [
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [1.0, 1.0],
+                [0.0, 1.0],
+                [0.0, 0.0],
+            ]
+            .map(|[x, y]| Vertex {
+                position: [x, y, z],
+                ..bytemuck::Zeroable::zeroed()
+            })
+        };
+
+        //The camera is in front of the first quad
+        let vertices: Vec<Vertex> = [0.0, 2.0, 1.0, 3.0].into_iter().flat_map(quad).collect();
+        let (vertices, indices) = index_quads(&vertices);
+        let quads = SortableQuads::new(&vertices, &indices);
+
+        assert_eq!(quads.centroids[1], [0.5, 0.5, 2.0]);
+
+        let sorted = quads.sort([0.5, 0.5, -1.0]);
+
+        let order: Vec<f32> = sorted
+            .chunks(6)
+            .map(|quad| vertices[quad[0] as usize].position[2])
+            .collect();
+
+        assert_eq!(order, [3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn sortable_quads_are_only_resorted_in_another_block() {
+        let (vertices, indices) = index_quads(&[bytemuck::Zeroable::zeroed(); 6]);
+        let quads = SortableQuads::new(&vertices, &indices);
+
+        assert!(quads.sort_if_moved([0.5, 0.5, 0.5]).is_some());
+        assert!(quads.sort_if_moved([0.9, 0.1, 0.7]).is_none());
+        assert!(quads.sort_if_moved([1.1, 0.1, 0.7]).is_some());
+    }
+
+    #[test]
+    fn sections_are_merged_in_order() {
+        let cube = || variants(CubeOrComplexMesh::Cube(Box::new(faces())));
+
+        //The block in the higher section comes first here, but is baked after the lower one
+        let vertices = bake_blocks(cube(), &[(1, 40, 3), (1, 2, 3)], MeshingStrategy::PerFace);
+        assert_eq!(vertices.len(), 12 * 6);
+
+        assert!(vertices[..6 * 6].iter().all(|position| position[1] < 16.0));
+        assert!(vertices[6 * 6..].iter().all(|position| position[1] >= 32.0));
+    }
+
+    /// A [BlocksProvider] which claims every column ends below `height`
+    #[derive(Debug)]
+    struct HeightmapProvider {
+        blocks: BlocksProvider,
+        height: i16,
+    }
+
+    impl BlockStateProvider for HeightmapProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            self.blocks.get_state(x, y, z)
+        }
+
+        fn is_section_empty(&self, index: usize) -> bool {
+            self.blocks.is_section_empty(index)
+        }
+
+        fn column_height(&self, _x: i32, _z: i32) -> Option<i16> {
+            Some(self.height)
+        }
+    }
+
+    #[test]
+    fn blocks_above_the_heightmap_are_skipped() {
+        let block_manager = block_manager(variants(CubeOrComplexMesh::Cube(Box::new(faces()))));
+        let provider = HeightmapProvider {
+            blocks: BlocksProvider {
+                positions: vec![(1, 2, 3), (1, 40, 3)],
+            },
+            height: 3,
+        };
+
+        let [vertices, _, _] = bake_layer(
+            &block_manager,
+            &Chunk::new([0, 0]),
+            |vertex, x, y, z| {
+                [
+                    vertex.position[0] + x,
+                    vertex.position[1] + y,
+                    vertex.position[2] + z,
+                ]
+            },
+            |_| true,
+            &provider,
+            MeshingStrategy::PerFace,
+            false,
+        );
+
+        assert_eq!(vertices, expected_positions());
+    }
+
+    #[test]
+    fn rebake_queue_coalesces_updates() {
+        let queue = RebakeQueue::new(Duration::from_millis(2));
+
+        for _ in 0..100 {
+            queue.mark_block(5, 40, 5);
+        }
+        assert_eq!(queue.len(), 1);
+
+        //The corner of a section next to the chunks to the west and north, and the one diagonal to both
+        queue.mark_block(16, 16, -16);
+        assert_eq!(queue.len(), 1 + 2 * 4);
+
+        let dirty = queue.dirty.lock();
+        assert_eq!(dirty[&[1, -1]], 0b11);
+        assert_eq!(dirty[&[0, -1]], 0b11);
+        assert_eq!(dirty[&[1, -2]], 0b11);
+        assert_eq!(dirty[&[0, -2]], 0b11);
+    }
+
+    #[test]
+    fn rebake_queue_prefers_sections_near_the_camera() {
+        let queue = RebakeQueue::new(Duration::ZERO);
+
+        queue.mark_block(5, 300, 5);
+        queue.mark_block(40, 8, 8);
+        queue.mark_block(-60, 8, 8);
+
+        assert_eq!(
+            queue.by_distance([30.0, 10.0, 8.0]),
+            vec![[2, 0], [-4, 0], [0, 0]]
+        );
+        assert_eq!(
+            queue.by_distance([8.0, 290.0, 8.0]),
+            vec![[0, 0], [2, 0], [-4, 0]]
+        );
+    }
+
+    #[test]
+    fn marking_section_edges_marks_neighbours() {
+        let dirty = |y: i16| {
+            let chunk = Chunk::new([0, 0]);
+            chunk.mark_section_dirty(y);
+            chunk.dirty_sections.load(Ordering::Relaxed)
+        };
+
+        assert_eq!(dirty(20), 0b10);
+        assert_eq!(dirty(16), 0b11);
+        assert_eq!(dirty(31), 0b110);
+        assert_eq!(dirty(0), 0b1);
+        assert_eq!(dirty(383), 1 << 23);
+        assert_eq!(dirty(-1), 0);
+        assert_eq!(dirty(384), 0);
+    }
+
+    #[test]
+    fn ambient_occlusion_darkens_corners_next_to_opaque_blocks() {
+        let vertex = |x: f32, z: f32| BlockMeshVertex {
+            position: [x, 1.0, z],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 1.0, 0.0, 0.0],
+            animation_uv_offset: 0,
+            emissive_tex_coords: NO_EMISSIVE,
+            tint_index: NO_TINT,
+        };
+
+        let block = Block::Variants(IndexMap::from([(
+            "".into(),
+            Arc::new(ModelMesh {
+                models: vec![(
+                    CubeOrComplexMesh::Cube(Box::new(BlockModelFaces {
+                        north: None,
+                        east: None,
+                        south: None,
+                        west: None,
+                        up: Some([
+                            vertex(0.0, 0.0),
+                            vertex(1.0, 0.0),
+                            vertex(1.0, 1.0),
+                            vertex(1.0, 1.0),
+                            vertex(0.0, 1.0),
+                            vertex(0.0, 0.0),
+                        ]),
+                        down: None,
+                        connected: Default::default(),
+                    })),
+                    true,
+                )],
+                is_full_opaque_cube: true,
+                render_type: RenderType::Solid,
+                weights: vec![1],
+            }),
+        )]));
+
+        let block_manager = block_manager(block);
+        //The second block sits diagonally above the east edge of the first one
+        let provider = BlocksProvider {
+            positions: vec![(1, 2, 3), (2, 3, 3)],
+        };
+
+        let [vertices, _, _] = bake_layer(
+            &block_manager,
+            &Chunk::new([0, 0]),
+            |vertex, x, y, z| Vertex {
+                position: [
+                    vertex.position[0] + x,
+                    vertex.position[1] + y,
+                    vertex.position[2] + z,
+                ],
+                color: [1.0; 4],
+                ..bytemuck::Zeroable::zeroed()
+            },
+            |_| true,
+            &provider,
+            MeshingStrategy::PerFace,
+            true,
+        );
+
+        //The up face of the lower block comes first
+        for vertex in &vertices[..6] {
+            let expected = if vertex.position[0] == 2.0 {
+                AMBIENT_OCCLUSION_BRIGHTNESS[2]
+            } else {
+                AMBIENT_OCCLUSION_BRIGHTNESS[3]
+            };
+
+            assert_eq!(vertex.color[0], expected);
+        }
+
+        assert!(vertices[6..].iter().all(|vertex| vertex.color[0] == 1.0));
+    }
+
+    #[test]
+    fn loading_a_neighbour_rebakes_once() {
+        let manager = ChunkManager::new();
+
+        let chunk = Chunk::new([0, 0]);
+        //Baked while the chunk to the east wasn't loaded
+        chunk
+            .baked_neighbours
+            .store(ALL_NEIGHBOURS & !0b10, Ordering::Relaxed);
+
+        manager
+            .loaded_chunks
+            .write()
+            .insert([0, 0], ArcSwap::new(Arc::new(chunk)));
+
+        assert!(manager.neighbours_to_rebake([-1, 0]).is_empty());
+        assert_eq!(manager.neighbours_to_rebake([1, 0]), vec![[0, 0]]);
+        assert!(manager.neighbours_to_rebake([1, 0]).is_empty());
+    }
+
+    #[test]
+    fn unloading_a_chunk_rebakes_its_neighbours_when_it_returns() {
+        let manager = ChunkManager::new();
+
+        for pos in [[0, 0], [1, 0]] {
+            manager
+                .loaded_chunks
+                .write()
+                .insert(pos, ArcSwap::new(Arc::new(Chunk::new(pos))));
+        }
+
+        assert!(manager.unload_chunk([1, 0]));
+        assert!(!manager.unload_chunk([1, 0]));
+        assert!(!manager.loaded_chunks.read().contains_key(&[1, 0]));
+
+        assert_eq!(manager.neighbours_to_rebake([1, 0]), vec![[0, 0]]);
+    }
+
+    #[test]
+    fn buffers_are_swapped_once_their_uploads_completed() {
+        let chunk = Chunk::new([0, 0]);
+
+        assert!(!chunk.swap_buffers(1));
+
+        *chunk.pending_layers.lock() = Some(PendingLayers {
+            generation: 3,
+            baked_layers: Some(HashMap::new()),
+            lod_layers: None,
+        });
+
+        assert!(!chunk.swap_buffers(2));
+        assert!(chunk.swap_buffers(3));
+        assert!(chunk.pending_layers.lock().is_none());
+        assert!(!chunk.swap_buffers(3));
+    }
+
+    #[test]
+    fn meshes_are_only_shared_when_their_contents_match() {
+        let key = ("minecraft:block".to_string(), RenderType::Solid);
+        let vertex = |x: f32| Vertex {
+            position: [x, 0.0, 0.0],
+            ..bytemuck::Zeroable::zeroed()
+        };
+
+        let first = [vertex(0.0), vertex(1.0), vertex(2.0)];
+        let second = [vertex(0.0), vertex(1.0), vertex(3.0)];
+        let indices = [0, 1, 2];
+
+        let mut shared: SharedMeshes<u32> = SharedMeshes::default();
+        let mut allocations = 0;
+        let mut allocate = || {
+            allocations += 1;
+            (allocations, allocations)
+        };
+
+        let (vertex_buffer, _index_buffer) = shared.share(&key, &first, &indices, &mut allocate);
+        let (again, _) = shared.share(&key, &first, &indices, &mut allocate);
+        assert!(Arc::ptr_eq(&vertex_buffer, &again));
+
+        //Moves the first mesh to the hash of the second, as if their hashes collided
+        let first_meshes = shared
+            .meshes
+            .remove(&mesh_hash(&key, &first, &indices))
+            .unwrap();
+        shared
+            .meshes
+            .insert(mesh_hash(&key, &second, &indices), first_meshes);
+
+        let (collided, _) = shared.share(&key, &second, &indices, &mut allocate);
+        assert!(!Arc::ptr_eq(&vertex_buffer, &collided));
+        assert_eq!(*collided, 2);
+    }
+
+    #[test]
+    fn chunks_past_the_addressable_range_have_no_origin() {
+        assert_eq!(chunk_origin([2, -1]), Some([32, -16]));
+
+        //The furthest chunk whose neighbours can still be addressed
+        let furthest = i32::MAX / CHUNK_WIDTH as i32 - 1;
+        assert_eq!(
+            chunk_origin([furthest, -furthest]),
+            Some([furthest * 16, -furthest * 16])
+        );
+
+        assert_eq!(chunk_origin([furthest + 1, 0]), None);
+        assert_eq!(chunk_origin([0, i32::MIN / CHUNK_WIDTH as i32]), None);
+        assert_eq!(chunk_origin([i32::MAX, 0]), None);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn chunks_and_block_managers_round_trip() {
+        use crate::mc::visibility::SectionVisibility;
+
+        use super::BakedSection;
+
+        let vertices = vec![
+            Vertex {
+                position: [1.0, 2.0, 3.0],
+                tex_coords: [0.25, 0.5],
+                region: [0.0, 0.0, 0.5, 0.5],
+                ..bytemuck::Zeroable::zeroed()
+            };
+            4
+        ];
+        let key = ("wgpu_mc:terrain".to_string(), RenderType::Cutout);
+
+        let chunk = Chunk::new([2, -1]);
+        chunk.replace_sections(vec![BakedSection {
+            index: 3,
+            mesh: HashMap::from([(key.clone(), (vertices.clone(), vec![0, 1, 2, 2, 3, 0]))]),
+            visibility: SectionVisibility::NONE,
+        }]);
+        chunk.mark_section_dirty(100);
+
+        let json = serde_json::to_string(&chunk).unwrap();
+        let deserialized: Chunk = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(deserialized.pos, [2, -1]);
+        assert_eq!(deserialized.visibility.read()[3], SectionVisibility::NONE);
+        assert_eq!(deserialized.visibility.read()[4], SectionVisibility::ALL);
+        assert_eq!(
+            deserialized.dirty_sections.load(Ordering::Relaxed),
+            chunk.dirty_sections.load(Ordering::Relaxed)
+        );
+
+        let sections = deserialized.sections.read();
+        let (section_vertices, indices) = &sections[3][&key];
+        assert_eq!(
+            bytemuck::cast_slice::<_, u8>(section_vertices),
+            bytemuck::cast_slice::<_, u8>(&vertices)
+        );
+        assert_eq!(indices, &[0, 1, 2, 2, 3, 0]);
+        assert!(sections
+            .iter()
+            .enumerate()
+            .all(|(index, section)| index == 3 || section.is_empty()));
+
+        let mut block_manager = block_manager(variants(CubeOrComplexMesh::Cube(Box::new(faces()))));
+        block_manager
+            .shapes
+            .insert(0, crate::mc::block::BlockShape::Cross);
+
+        let json = serde_json::to_string(&block_manager).unwrap();
+        let deserialized: BlockManager = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            deserialized.get_shape(0),
+            &crate::mc::block::BlockShape::Cross
+        );
+        let model = deserialized.blocks["wgpu_mc:test"].get_model(0);
+        assert!(matches!(&model.models[0].0, CubeOrComplexMesh::Cube(faces) if faces.up.is_some()));
+    }
 }
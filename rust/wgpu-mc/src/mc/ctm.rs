@@ -0,0 +1,587 @@
+//! # Connected textures
+//!
+//! Cube faces can swap their texture for one of a set of tiles depending on which of the blocks around them in
+//! the plane of the face they connect to, so that glass panes join up into one window and bookshelves into one
+//! shelf. The rules are OptiFine's `.properties` files, which resource packs keep in `optifine/ctm/`, for example
+//!
+//! ```properties
+//! matchBlocks=glass
+//! method=ctm
+//! tiles=0-46
+//! ```
+//!
+//! The rules are found with [ResourceProvider::list] and loaded into [crate::mc::MinecraftState::connected_textures]
+//! by [crate::mc::MinecraftState::load_connected_textures], before the blocks are baked again in every
+//! [crate::mc::MinecraftState::reload_resources]. Providers which can't list their resources have no rules, unless
+//! the frontend finds the files itself and loads them with [ConnectedTextures::load].
+//!
+//! The tiles are allocated into the block atlas when a block with a matching face is baked. Only the faces of
+//! cube models of blocks with variants are connected, and the tile is picked while the chunk is baked, see
+//! [ConnectedFace::connect].
+
+use std::sync::Arc;
+
+use crate::mc::block::{
+    remap_tex_coords, BlockMeshVertex, BlockstateKey, ChunkBlockState, CubeOrComplexMesh, ModelMesh,
+};
+use crate::mc::chunk::{get_block, BlockStateProvider, CHUNK_HEIGHT};
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::mc::BlockManager;
+use crate::texture::UV;
+
+#[derive(Debug)]
+pub enum CtmError {
+    MissingProperty(&'static str),
+    UnknownMethod(String),
+    UnknownConnect(String),
+    InvalidTiles(String),
+    /// The method needs a different number of tiles
+    TileCount {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+/// How the tile of a face is picked
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CtmMethod {
+    /// 47 tiles for every combination of the four sides and the corners between them, laid out like OptiFine's
+    /// `ctm` template. Also called `glass`.
+    Ctm,
+    /// 4 tiles which only connect left and right: the left end, the middle, the right end and a lone face. Also
+    /// called `bookshelf`.
+    Horizontal,
+    /// 4 tiles which only connect up and down: the bottom end, the middle, the top end and a lone face
+    Vertical,
+}
+
+impl CtmMethod {
+    fn parse(method: &str) -> Result<Self, CtmError> {
+        match method {
+            "ctm" | "glass" => Ok(Self::Ctm),
+            "horizontal" | "bookshelf" => Ok(Self::Horizontal),
+            "vertical" => Ok(Self::Vertical),
+            _ => Err(CtmError::UnknownMethod(method.into())),
+        }
+    }
+
+    /// How many tiles a rule with this method has
+    pub fn tile_count(self) -> usize {
+        match self {
+            Self::Ctm => 47,
+            Self::Horizontal | Self::Vertical => 4,
+        }
+    }
+
+    /// The index of the tile of a face, given whether it connects to the face the given number of blocks right
+    /// and down of it, as its texture is seen
+    pub fn tile(self, connects: impl Fn(i32, i32) -> bool) -> usize {
+        let left = connects(-1, 0);
+        let right = connects(1, 0);
+        let up = connects(0, -1);
+        let down = connects(0, 1);
+
+        match self {
+            Self::Horizontal => match (left, right) {
+                (true, true) => 1,
+                (true, false) => 2,
+                (false, true) => 0,
+                (false, false) => 3,
+            },
+            Self::Vertical => match (up, down) {
+                (true, true) => 1,
+                (true, false) => 0,
+                (false, true) => 2,
+                (false, false) => 3,
+            },
+            Self::Ctm => {
+                //A corner only shows a seam if the two sides next to it connect but it doesn't
+                let corner = |u: i32, v: i32| !connects(u, v);
+
+                match (left, right, up, down) {
+                    (false, false, false, false) => 0,
+                    (false, true, false, false) => 1,
+                    (true, true, false, false) => 2,
+                    (true, false, false, false) => 3,
+                    (false, false, false, true) => 12,
+                    (false, false, true, true) => 24,
+                    (false, false, true, false) => 36,
+                    (false, true, false, true) => {
+                        if corner(1, 1) {
+                            4
+                        } else {
+                            13
+                        }
+                    }
+                    (true, false, false, true) => {
+                        if corner(-1, 1) {
+                            5
+                        } else {
+                            15
+                        }
+                    }
+                    (false, true, true, false) => {
+                        if corner(1, -1) {
+                            16
+                        } else {
+                            37
+                        }
+                    }
+                    (true, false, true, false) => {
+                        if corner(-1, -1) {
+                            17
+                        } else {
+                            39
+                        }
+                    }
+                    (true, true, false, true) => match (corner(-1, 1), corner(1, 1)) {
+                        (false, false) => 14,
+                        (false, true) => 6,
+                        (true, false) => 7,
+                        (true, true) => 28,
+                    },
+                    (true, true, true, false) => match (corner(-1, -1), corner(1, -1)) {
+                        (false, false) => 38,
+                        (false, true) => 18,
+                        (true, false) => 19,
+                        (true, true) => 29,
+                    },
+                    (false, true, true, true) => match (corner(1, -1), corner(1, 1)) {
+                        (false, false) => 25,
+                        (true, false) => 30,
+                        (false, true) => 31,
+                        (true, true) => 40,
+                    },
+                    (true, false, true, true) => match (corner(-1, -1), corner(-1, 1)) {
+                        (false, false) => 27,
+                        (true, false) => 41,
+                        (false, true) => 42,
+                        (true, true) => 43,
+                    },
+                    (true, true, true, true) => {
+                        const OPEN_CORNERS: [usize; 15] =
+                            [8, 9, 10, 11, 20, 21, 22, 23, 32, 33, 34, 35, 44, 45, 46];
+
+                        let open = corner(-1, -1) as usize
+                            | (corner(1, -1) as usize) << 1
+                            | (corner(-1, 1) as usize) << 2
+                            | (corner(1, 1) as usize) << 3;
+
+                        match open {
+                            0 => 26,
+                            open => OPEN_CORNERS[open - 1],
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Which neighbouring faces a face connects to
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Connect {
+    /// Faces of the same block, in any state
+    Block,
+    /// Faces which are connected by the same rule
+    Tile,
+}
+
+/// One `.properties` file
+#[derive(Debug)]
+pub struct CtmRule {
+    pub method: CtmMethod,
+    pub connect: Connect,
+    /// The block textures whose faces are connected, like `minecraft:block/glass`
+    pub match_tiles: Vec<ResourcePath>,
+    /// The blocks whose faces are connected
+    pub match_blocks: Vec<ResourcePath>,
+    /// The full paths of the tiles, like `minecraft:optifine/ctm/glass/0.png`, which are also their names in the
+    /// block atlas
+    pub tiles: Vec<ResourcePath>,
+}
+
+impl CtmRule {
+    /// Parses the `.properties` file at the path. Tiles which aren't namespaced are relative to its directory.
+    pub fn parse(path: &ResourcePath, properties: &str) -> Result<Self, CtmError> {
+        let properties: Vec<(&str, &str)> = properties
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect();
+
+        let property = |name: &str| {
+            properties
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| *value)
+        };
+
+        let method = CtmMethod::parse(property("method").unwrap_or("ctm"))?;
+
+        let match_tiles: Vec<ResourcePath> = property("matchTiles")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|tile| {
+                if tile.contains('/') {
+                    ResourcePath::from(tile)
+                } else {
+                    ResourcePath::from(tile).prepend("block/")
+                }
+            })
+            .collect();
+
+        let match_blocks: Vec<ResourcePath> = property("matchBlocks")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(ResourcePath::from)
+            .collect();
+
+        if match_tiles.is_empty() && match_blocks.is_empty() {
+            return Err(CtmError::MissingProperty("matchTiles"));
+        }
+
+        let connect = match property("connect") {
+            Some("block") => Connect::Block,
+            Some("tile") => Connect::Tile,
+            Some(connect) => return Err(CtmError::UnknownConnect(connect.into())),
+            None if match_blocks.is_empty() => Connect::Tile,
+            None => Connect::Block,
+        };
+
+        let directory = path.0.rsplit_once('/').map_or(&path.0[..], |(dir, _)| dir);
+
+        let tiles = property("tiles")
+            .ok_or(CtmError::MissingProperty("tiles"))?
+            .split_whitespace()
+            .map(|tiles| parse_tiles(directory, tiles))
+            .collect::<Result<Vec<Vec<ResourcePath>>, CtmError>>()?
+            .concat();
+
+        if tiles.len() != method.tile_count() {
+            return Err(CtmError::TileCount {
+                expected: method.tile_count(),
+                actual: tiles.len(),
+            });
+        }
+
+        Ok(Self {
+            method,
+            connect,
+            match_tiles,
+            match_blocks,
+            tiles,
+        })
+    }
+
+    /// Whether the rule applies to a face with the texture of the block. If a rule matches both blocks and
+    /// textures, the face has to match both.
+    pub fn matches(&self, block: &ResourcePath, texture: &ResourcePath) -> bool {
+        (self.match_blocks.is_empty() || self.match_blocks.contains(block))
+            && (self.match_tiles.is_empty() || self.match_tiles.contains(texture))
+    }
+}
+
+/// A range of numbered tiles like `0-46`, or the name of one
+fn parse_tiles(directory: &str, tiles: &str) -> Result<Vec<ResourcePath>, CtmError> {
+    let tile = |name: &str| {
+        let name = name.strip_suffix(".png").unwrap_or(name);
+
+        if name.contains(':') {
+            ResourcePath::from(name).append(".png")
+        } else {
+            ResourcePath(format!("{directory}/{name}.png"))
+        }
+    };
+
+    match tiles.split_once('-') {
+        Some((start, end)) => {
+            let parse = |number: &str| {
+                number
+                    .parse::<usize>()
+                    .map_err(|_| CtmError::InvalidTiles(tiles.into()))
+            };
+
+            Ok((parse(start)?..=parse(end)?)
+                .map(|index| tile(&index.to_string()))
+                .collect())
+        }
+        None => Ok(vec![tile(tiles)]),
+    }
+}
+
+/// Where resource packs keep the rules
+pub const CTM_DIRECTORY: &str = "minecraft:optifine/ctm";
+
+/// Every [CtmRule] of the resource packs. Empty unless the frontend loads them, which turns connected textures
+/// off.
+#[derive(Debug, Default)]
+pub struct ConnectedTextures {
+    pub rules: Vec<Arc<CtmRule>>,
+}
+
+impl ConnectedTextures {
+    /// Parses the `.properties` files at the paths. Files which can't be read or parsed are skipped.
+    pub fn load<'a>(
+        resource_provider: &dyn ResourceProvider,
+        properties: impl IntoIterator<Item = &'a ResourcePath>,
+    ) -> Self {
+        Self {
+            rules: properties
+                .into_iter()
+                .filter_map(|path| {
+                    let rule = resource_provider
+                        .get_string(path)
+                        .map(|properties| CtmRule::parse(path, &properties));
+
+                    match rule {
+                        Some(Ok(rule)) => Some(Arc::new(rule)),
+                        Some(Err(error)) => {
+                            log::warn!("Couldn't load connected textures {path}: {error:?}");
+                            None
+                        }
+                        None => None,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Parses every `.properties` file under `optifine/ctm/` which the resource provider lists
+    pub fn load_all(resource_provider: &dyn ResourceProvider) -> Self {
+        let properties: Vec<ResourcePath> = resource_provider
+            .list(&ResourcePath::from(CTM_DIRECTORY))
+            .into_iter()
+            .filter(|path| path.0.ends_with(".properties"))
+            .collect();
+
+        Self::load(resource_provider, &properties)
+    }
+
+    /// The first rule which applies to a face with the texture of the block
+    pub fn rule(&self, block: &ResourcePath, texture: &ResourcePath) -> Option<&Arc<CtmRule>> {
+        self.rules.iter().find(|rule| rule.matches(block, texture))
+    }
+}
+
+/// A face which is connected by a rule, with the UVs of its texture and tiles in the block atlas
+#[derive(Debug)]
+pub struct ConnectedFace {
+    pub rule: Arc<CtmRule>,
+    /// The UV of the texture the face is baked with
+    pub texture: UV,
+    /// The UV of each of the tiles of the rule
+    pub tiles: Vec<UV>,
+}
+
+impl ConnectedFace {
+    /// Whether a face of the block next to the face, with its own state and mesh, is connected to this one.
+    /// `direction` is the index of the face in north, east, south, west, up, down order.
+    pub fn connects_to(
+        &self,
+        block: BlockstateKey,
+        direction: usize,
+        neighbour: BlockstateKey,
+        neighbour_mesh: &ModelMesh,
+    ) -> bool {
+        match self.rule.connect {
+            Connect::Block => neighbour.block == block.block,
+            Connect::Tile => neighbour_mesh.models.iter().any(|(model, _)| match model {
+                CubeOrComplexMesh::Cube(faces) => faces.connected[direction]
+                    .as_ref()
+                    .map_or(false, |face| Arc::ptr_eq(&face.rule, &self.rule)),
+                CubeOrComplexMesh::Complex(_) => false,
+            }),
+        }
+    }
+
+    /// Whether the face of the block at the position in the world connects to the block at the offset from it
+    pub(crate) fn connects_at(
+        &self,
+        block_manager: &BlockManager,
+        state_provider: &impl BlockStateProvider,
+        block: BlockstateKey,
+        direction: usize,
+        position: [i32; 3],
+        offset: [i32; 3],
+    ) -> bool {
+        let neighbour_y = position[1] + offset[1];
+
+        if neighbour_y < 0 || neighbour_y >= CHUNK_HEIGHT as i32 {
+            return false;
+        }
+
+        let neighbour = state_provider.get_state(
+            position[0] + offset[0],
+            neighbour_y as i16,
+            position[2] + offset[2],
+        );
+
+        match (neighbour, get_block(block_manager, neighbour)) {
+            (ChunkBlockState::State(key), Some(mesh)) => {
+                self.connects_to(block, direction, key, &mesh)
+            }
+            _ => false,
+        }
+    }
+
+    /// The UV of the tile for the blocks the face connects to, given whether it connects to the block at each
+    /// offset from it. [None] if the face keeps its texture.
+    pub fn connected_tile(
+        &self,
+        vertices: &[BlockMeshVertex; 6],
+        connects: impl Fn([i32; 3]) -> bool,
+    ) -> Option<UV> {
+        let (right, down) = texture_axes(vertices)?;
+
+        let tile = self
+            .rule
+            .method
+            .tile(|u, v| connects(std::array::from_fn(|axis| right[axis] * u + down[axis] * v)));
+
+        self.tiles.get(tile).copied()
+    }
+
+    /// The vertices of the face with the tile for the blocks it connects to, see [ConnectedFace::connected_tile]
+    pub fn connect(
+        &self,
+        vertices: &[BlockMeshVertex; 6],
+        connects: impl Fn([i32; 3]) -> bool,
+    ) -> [BlockMeshVertex; 6] {
+        match self.connected_tile(vertices, connects) {
+            Some(tile) => vertices.map(|mut vertex| {
+                vertex.tex_coords = remap_tex_coords(vertex.tex_coords, self.texture, tile);
+                vertex
+            }),
+            None => *vertices,
+        }
+    }
+}
+
+/// The block offsets which the texture of the face goes right and down along, found from the edges of the face
+/// along which only one of the texture coordinates changes
+fn texture_axes(vertices: &[BlockMeshVertex; 6]) -> Option<([i32; 3], [i32; 3])> {
+    const EPSILON: f32 = 1e-4;
+
+    let along = |texture_axis: usize| {
+        vertices.iter().find_map(|from| {
+            vertices.iter().find_map(|to| {
+                let delta = to.tex_coords[texture_axis] - from.tex_coords[texture_axis];
+                let other = to.tex_coords[1 - texture_axis] - from.tex_coords[1 - texture_axis];
+
+                (delta > EPSILON && other.abs() < EPSILON).then(|| {
+                    std::array::from_fn(|axis| {
+                        let offset = to.position[axis] - from.position[axis];
+
+                        if offset.abs() < EPSILON {
+                            0
+                        } else {
+                            offset.signum() as i32
+                        }
+                    })
+                })
+            })
+        })
+    };
+
+    Some((along(0)?, along(1)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{texture_axes, ConnectedFace, CtmMethod, CtmRule};
+    use crate::mc::block::{BlockMeshVertex, NO_EMISSIVE, NO_TINT};
+    use crate::mc::resource::ResourcePath;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> BlockMeshVertex {
+        BlockMeshVertex {
+            position,
+            tex_coords,
+            normal: [0.0, 0.0, -1.0, 1.0],
+            animation_uv_offset: 0,
+            emissive_tex_coords: NO_EMISSIVE,
+            tint_index: NO_TINT,
+        }
+    }
+
+    /// A north face with the texture going along +x and down along -y
+    fn face() -> [BlockMeshVertex; 6] {
+        [
+            vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+            vertex([1.0, 0.0, 0.0], [1.0, 1.0]),
+            vertex([0.0, 1.0, 0.0], [0.0, 0.0]),
+            vertex([0.0, 0.0, 0.0], [0.0, 1.0]),
+            vertex([1.0, 1.0, 0.0], [1.0, 0.0]),
+        ]
+    }
+
+    #[test]
+    fn parses_properties() {
+        let rule = CtmRule::parse(
+            &ResourcePath("minecraft:optifine/ctm/glass/glass.properties".into()),
+            "# Glass\nmatchBlocks=glass\nmethod=glass\ntiles=0-46\n",
+        )
+        .unwrap();
+
+        assert_eq!(rule.method, CtmMethod::Ctm);
+        assert_eq!(rule.tiles.len(), 47);
+        assert_eq!(
+            rule.tiles[46],
+            ResourcePath("minecraft:optifine/ctm/glass/46.png".into())
+        );
+        assert!(rule.matches(
+            &ResourcePath("minecraft:glass".into()),
+            &ResourcePath("minecraft:block/glass".into())
+        ));
+
+        assert!(CtmRule::parse(
+            &ResourcePath("minecraft:optifine/ctm/bookshelf.properties".into()),
+            "matchTiles=bookshelf\nmethod=horizontal\ntiles=0-2",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn picks_tiles_by_connected_sides_and_corners() {
+        let all = |_: i32, _: i32| true;
+        let none = |_: i32, _: i32| false;
+        let sides = |u: i32, v: i32| u == 0 || v == 0;
+
+        assert_eq!(CtmMethod::Ctm.tile(none), 0);
+        assert_eq!(CtmMethod::Ctm.tile(all), 26);
+        assert_eq!(CtmMethod::Ctm.tile(sides), 46);
+        assert_eq!(CtmMethod::Ctm.tile(|u, v| v == 0 && u > 0), 1);
+        assert_eq!(CtmMethod::Horizontal.tile(|u, v| v == 0 && u < 0), 2);
+        assert_eq!(CtmMethod::Vertical.tile(all), 1);
+    }
+
+    #[test]
+    fn connected_faces_use_the_tile() {
+        assert_eq!(texture_axes(&face()), Some(([1, 0, 0], [0, -1, 0])));
+
+        let rule = CtmRule::parse(
+            &ResourcePath("minecraft:optifine/ctm/bookshelf.properties".into()),
+            "matchTiles=bookshelf\nmethod=horizontal\ntiles=0-3",
+        )
+        .unwrap();
+
+        let connected_face = ConnectedFace {
+            rule: Arc::new(rule),
+            texture: ((0.0, 0.0), (1.0, 1.0)),
+            tiles: (0..4)
+                .map(|tile| ((tile as f32 * 10.0, 0.0), (tile as f32 * 10.0 + 2.0, 2.0)))
+                .collect(),
+        };
+
+        //Only the block to the west, which is left of a north face
+        let connected = connected_face.connect(&face(), |offset| offset == [-1, 0, 0]);
+
+        assert_eq!(connected[0].tex_coords, [22.0, 0.0]);
+        assert_eq!(connected[1].tex_coords, [20.0, 2.0]);
+    }
+}
@@ -0,0 +1,380 @@
+//! # Entity animations
+//!
+//! Each frame, an [Animator] poses the parts of an [Entity] from the [AnimationState] of an instance, and the
+//! [PartTransform]s of the pose become the [EntityInstanceTransforms::part_transforms] of the instance. They're
+//! applied on top of the transforms of the model, around the pivots of the parts, so that a bone rotated by an
+//! animation turns around its joint.
+//!
+//! The state is ticked 20 times a second like vanilla's, and [AnimationState::lerp] interpolates it between the
+//! previous and the current tick with the partial tick of the frame, before the entity is posed with it.
+//!
+//! [EntityInstanceTransforms::part_transforms]: crate::mc::entity::EntityInstanceTransforms::part_transforms
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use serde_derive::Deserialize;
+
+use crate::mc::entity::{Entity, PartTransform};
+
+/// What the animations of an entity are driven by, like the arguments of vanilla's `EntityModel::setAngles`
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AnimationState {
+    /// How far the limbs have swung, which grows as the entity walks
+    pub limb_swing: f32,
+    /// How far the limbs swing out, from 0 when standing still to 1 when walking
+    pub limb_swing_amount: f32,
+    pub age_in_ticks: f32,
+    /// In degrees, relative to the yaw of the body
+    pub head_yaw: f32,
+    /// In degrees
+    pub head_pitch: f32,
+}
+
+impl AnimationState {
+    /// The state `tick_delta` of the way from this one to `next`, turning the head the shorter way around
+    #[must_use]
+    pub fn lerp(&self, next: &Self, tick_delta: f32) -> Self {
+        let lerp = |from: f32, to: f32| from + (to - from) * tick_delta;
+        let yaw_difference = (next.head_yaw - self.head_yaw + 180.0).rem_euclid(360.0) - 180.0;
+
+        Self {
+            limb_swing: lerp(self.limb_swing, next.limb_swing),
+            limb_swing_amount: lerp(self.limb_swing_amount, next.limb_swing_amount),
+            age_in_ticks: lerp(self.age_in_ticks, next.age_in_ticks),
+            head_yaw: self.head_yaw + yaw_difference * tick_delta,
+            head_pitch: lerp(self.head_pitch, next.head_pitch),
+        }
+    }
+}
+
+/// The transforms of the parts of an entity, by the index of each part in [Entity::parts]
+pub struct Pose<'a> {
+    entity: &'a Entity,
+    pub transforms: Vec<PartTransform>,
+}
+
+impl<'a> Pose<'a> {
+    #[must_use]
+    pub fn new(entity: &'a Entity) -> Self {
+        Self {
+            entity,
+            transforms: vec![PartTransform::identity(); entity.parts.len()],
+        }
+    }
+
+    /// The transform of the part called `name`, if the entity has one
+    pub fn bone(&mut self, name: &str) -> Option<&mut PartTransform> {
+        let index = *self.entity.parts.get(name)?;
+        self.transforms.get_mut(index)
+    }
+}
+
+/// Moves the bones of a [Pose], see [Animator]. Bones which the entity doesn't have are skipped.
+pub trait Animation: Send + Sync {
+    fn animate(&self, state: &AnimationState, pose: &mut Pose);
+}
+
+/// Turns a bone, usually the head, to where the entity looks
+#[derive(Clone, Debug)]
+pub struct HeadLook {
+    pub bone: String,
+}
+
+impl Animation for HeadLook {
+    fn animate(&self, state: &AnimationState, pose: &mut Pose) {
+        if let Some(bone) = pose.bone(&self.bone) {
+            bone.yaw += state.head_yaw;
+            bone.pitch += state.head_pitch;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SwingingLimb {
+    pub bone: String,
+    /// In radians, [PI] for the limbs which swing forwards while the others swing back
+    pub phase: f32,
+    /// How far the limb swings at full speed, in degrees
+    pub amplitude: f32,
+}
+
+/// Swings limbs back and forth around X as the entity walks, like vanilla's models do
+#[derive(Clone, Debug)]
+pub struct LimbSwing {
+    pub limbs: Vec<SwingingLimb>,
+}
+
+impl LimbSwing {
+    /// How much faster than [AnimationState::limb_swing] the limbs swing
+    pub const FREQUENCY: f32 = 0.6662;
+
+    /// The arms and legs of vanilla's `BipedEntityModel`
+    #[must_use]
+    pub fn biped() -> Self {
+        let limb = |bone: &str, phase: f32, amplitude: f32| SwingingLimb {
+            bone: bone.into(),
+            phase,
+            amplitude: amplitude.to_degrees(),
+        };
+
+        Self {
+            limbs: vec![
+                limb("right_arm", PI, 1.0),
+                limb("left_arm", 0.0, 1.0),
+                limb("right_leg", 0.0, 1.4),
+                limb("left_leg", PI, 1.4),
+            ],
+        }
+    }
+
+    /// The legs of vanilla's `QuadrupedEntityModel`
+    #[must_use]
+    pub fn quadruped() -> Self {
+        let limb = |bone: &str, phase: f32| SwingingLimb {
+            bone: bone.into(),
+            phase,
+            amplitude: 1.4f32.to_degrees(),
+        };
+
+        Self {
+            limbs: vec![
+                limb("right_hind_leg", 0.0),
+                limb("left_hind_leg", PI),
+                limb("right_front_leg", PI),
+                limb("left_front_leg", 0.0),
+            ],
+        }
+    }
+}
+
+impl Animation for LimbSwing {
+    fn animate(&self, state: &AnimationState, pose: &mut Pose) {
+        for limb in &self.limbs {
+            if let Some(bone) = pose.bone(&limb.bone) {
+                bone.pitch += (state.limb_swing * Self::FREQUENCY + limb.phase).cos()
+                    * limb.amplitude
+                    * state.limb_swing_amount;
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    Linear,
+    CatmullRom,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChannelTarget {
+    /// Degrees around X, Y and Z
+    Rotation,
+    /// Pixels, 16 to a block
+    Position,
+    /// Multiplies the size of the bone
+    Scale,
+}
+
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    /// In seconds
+    pub time: f32,
+    pub value: [f32; 3],
+    /// How the value changes from the previous keyframe to this one
+    #[serde(default = "Keyframe::default_interpolation")]
+    pub interpolation: Interpolation,
+}
+
+impl Keyframe {
+    fn default_interpolation() -> Interpolation {
+        Interpolation::Linear
+    }
+}
+
+/// The keyframes of one target of a bone, sorted by time
+#[derive(Deserialize, Clone, Debug)]
+pub struct Channel {
+    pub bone: String,
+    pub target: ChannelTarget,
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Channel {
+    /// The value at `time`, which holds the first and last keyframes before and after them
+    #[must_use]
+    pub fn sample(&self, time: f32) -> Option<[f32; 3]> {
+        let frames = &self.keyframes;
+        let next = frames.partition_point(|frame| frame.time <= time);
+
+        if next == 0 || next == frames.len() {
+            return frames
+                .get(next.min(frames.len().saturating_sub(1)))
+                .map(|frame| frame.value);
+        }
+
+        let (from, to) = (&frames[next - 1], &frames[next]);
+        let t = (time - from.time) / (to.time - from.time);
+
+        let value = match to.interpolation {
+            Interpolation::Linear => std::array::from_fn(|axis| {
+                from.value[axis] + (to.value[axis] - from.value[axis]) * t
+            }),
+            Interpolation::CatmullRom => {
+                let before = &frames[next.saturating_sub(2)];
+                let after = &frames[(next + 1).min(frames.len() - 1)];
+
+                std::array::from_fn(|axis| {
+                    catmull_rom(
+                        t,
+                        before.value[axis],
+                        from.value[axis],
+                        to.value[axis],
+                        after.value[axis],
+                    )
+                })
+            }
+        };
+
+        Some(value)
+    }
+}
+
+fn catmull_rom(t: f32, p0: f32, p1: f32, p2: f32, p3: f32) -> f32 {
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t * t)
+}
+
+/// An animation made in a tool like Blockbench, like vanilla's `Animation`, played from when the entity was
+/// spawned. It can be loaded from JSON:
+///
+/// ```json
+/// { "length": 2.0, "looping": true, "channels": [
+///   { "bone": "tail", "target": "rotation", "keyframes": [
+///     { "time": 0.0, "value": [0, -20, 0] },
+///     { "time": 1.0, "value": [0, 20, 0], "interpolation": "catmull_rom" },
+///     { "time": 2.0, "value": [0, -20, 0], "interpolation": "catmull_rom" }
+///   ]}
+/// ]}
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+pub struct KeyframeAnimation {
+    /// In seconds
+    pub length: f32,
+    #[serde(default)]
+    pub looping: bool,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation for KeyframeAnimation {
+    fn animate(&self, state: &AnimationState, pose: &mut Pose) {
+        let seconds = state.age_in_ticks / 20.0;
+        let time = if self.looping && self.length > 0.0 {
+            seconds.rem_euclid(self.length)
+        } else {
+            seconds.min(self.length)
+        };
+
+        for channel in &self.channels {
+            let (Some([x, y, z]), Some(bone)) = (channel.sample(time), pose.bone(&channel.bone))
+            else {
+                continue;
+            };
+
+            match channel.target {
+                ChannelTarget::Rotation => {
+                    bone.pitch += x;
+                    bone.yaw += y;
+                    bone.roll += z;
+                }
+                ChannelTarget::Position => {
+                    bone.x += x / 16.0;
+                    bone.y += y / 16.0;
+                    bone.z += z / 16.0;
+                }
+                ChannelTarget::Scale => {
+                    bone.scale_x *= x;
+                    bone.scale_y *= y;
+                    bone.scale_z *= z;
+                }
+            }
+        }
+    }
+}
+
+/// The animations of a kind of entity, which are applied in order
+#[derive(Default, Clone)]
+pub struct Animator {
+    pub animations: Vec<Arc<dyn Animation>>,
+}
+
+impl Animator {
+    #[must_use]
+    pub fn new(animations: Vec<Arc<dyn Animation>>) -> Self {
+        Self { animations }
+    }
+
+    /// The [PartTransform] of every part of the entity in the state, to use as the
+    /// [crate::mc::entity::EntityInstanceTransforms::part_transforms] of an instance
+    #[must_use]
+    pub fn pose(&self, entity: &Entity, state: &AnimationState) -> Vec<PartTransform> {
+        let mut pose = Pose::new(entity);
+
+        for animation in &self.animations {
+            animation.animate(state, &mut pose);
+        }
+
+        pose.transforms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{AnimationState, KeyframeAnimation};
+
+    #[test]
+    fn head_turns_the_short_way_between_ticks() {
+        let previous = AnimationState {
+            head_yaw: 170.0,
+            age_in_ticks: 10.0,
+            ..Default::default()
+        };
+        let current = AnimationState {
+            head_yaw: -170.0,
+            age_in_ticks: 11.0,
+            ..Default::default()
+        };
+
+        let state = previous.lerp(&current, 0.5);
+        assert_eq!(state.head_yaw, 180.0);
+        assert_eq!(state.age_in_ticks, 10.5);
+    }
+
+    #[test]
+    fn keyframes_are_interpolated() {
+        let animation: KeyframeAnimation = serde_json::from_value(json!({
+            "length": 2.0,
+            "looping": true,
+            "channels": [{ "bone": "tail", "target": "rotation", "keyframes": [
+                { "time": 0.0, "value": [0, -20, 0] },
+                { "time": 1.0, "value": [0, 20, 0] },
+                { "time": 2.0, "value": [0, -20, 0], "interpolation": "catmull_rom" },
+            ]}]
+        }))
+        .unwrap();
+
+        let tail = &animation.channels[0];
+        assert_eq!(tail.sample(0.5), Some([0.0, 0.0, 0.0]));
+        assert_eq!(tail.sample(1.0), Some([0.0, 20.0, 0.0]));
+        //Catmull-Rom eases in towards the previous keyframe instead of a straight line through 0
+        assert_eq!(tail.sample(1.5), Some([0.0, 2.5, 0.0]));
+        //Held after the last keyframe
+        assert_eq!(tail.sample(3.0), Some([0.0, -20.0, 0.0]));
+    }
+}
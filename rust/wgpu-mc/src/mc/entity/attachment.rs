@@ -0,0 +1,154 @@
+//! # Attachments
+//!
+//! Equipment is drawn as models of its own which follow the bones of the entity wearing or holding it. An
+//! [Attachment] turns each instance of the entity into an instance of the attached model, either:
+//! - at a [Locator] of the entity, like an item held at its `right_hand`, the attached model being drawn relative to
+//!   the locator as it's animated
+//! - on its skeleton, like an armor layer, each part of which follows the part of the entity with the same name
+//!
+//! Locators are named points on the parts of a model, usually from the `locators` of an
+//! [EntityModel](crate::mc::entity::model::EntityModel), and are given to an entity with [Entity::with_locators].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::Matrix4;
+
+use crate::mc::entity::{Entity, EntityInstanceTransforms, PartTransform};
+
+/// Vanilla's locators for held items
+pub const RIGHT_HAND: &str = "right_hand";
+pub const LEFT_HAND: &str = "left_hand";
+/// Where a block or skull worn on the head sits
+pub const HEAD: &str = "head";
+
+/// A point on a part of a model which other models are attached to
+#[derive(Clone, Debug)]
+pub struct Locator {
+    /// The name of the part it moves with
+    pub part: String,
+    /// Relative to the part
+    pub transform: PartTransform,
+}
+
+/// Where an [Attachment] is attached to the entity
+#[derive(Clone, Debug)]
+pub enum AttachPoint {
+    /// At the locator with the name
+    Locator(String),
+    /// Each part of the attached model follows the part of the entity with the same name
+    Skeleton,
+}
+
+/// A model drawn along with every instance of an entity, following its bones, like a held item or an armor layer
+#[derive(Clone, Debug)]
+pub struct Attachment {
+    pub entity: Arc<Entity>,
+    pub point: AttachPoint,
+}
+
+impl Attachment {
+    /// The instance of the attached model which follows the instance of the `parent` entity, or [None] if the parent
+    /// has no such locator. The overlay and tints of the parent aren't carried over, like vanilla doesn't flash
+    /// armor red.
+    #[must_use]
+    pub fn instance(
+        &self,
+        parent: &Entity,
+        instance: &EntityInstanceTransforms,
+    ) -> Option<EntityInstanceTransforms> {
+        let (attached_to, part_transforms) = match &self.point {
+            AttachPoint::Locator(name) => {
+                let locator = parent.locators.get(name)?;
+                let part = *parent.parts.get(&locator.part)?;
+                let matrix: Matrix4<f32> = (*instance.get_matrices(parent).get(part)?).into();
+
+                (
+                    Some((matrix * locator.transform.describe()).into()),
+                    Vec::new(),
+                )
+            }
+            AttachPoint::Skeleton => (
+                instance.attached_to,
+                skeleton_transforms(&parent.parts, &self.entity.parts, &instance.part_transforms),
+            ),
+        };
+
+        Some(EntityInstanceTransforms {
+            position: instance.position,
+            looking_yaw: instance.looking_yaw,
+            uv_offset: (0.0, 0.0),
+            overlay: [0.0; 4],
+            part_transforms,
+            part_tints: Vec::new(),
+            attached_to,
+        })
+    }
+
+    /// The instances of the attached model which follow each of the instances of the `parent` entity
+    #[must_use]
+    pub fn instances(
+        &self,
+        parent: &Entity,
+        instances: &[EntityInstanceTransforms],
+    ) -> Vec<EntityInstanceTransforms> {
+        instances
+            .iter()
+            .filter_map(|instance| self.instance(parent, instance))
+            .collect()
+    }
+}
+
+/// The transforms of the parts of the attached model, each of which is that of the parent's part with the same name
+fn skeleton_transforms(
+    parent_parts: &HashMap<String, usize>,
+    attached_parts: &HashMap<String, usize>,
+    transforms: &[PartTransform],
+) -> Vec<PartTransform> {
+    let mut attached = vec![PartTransform::identity(); attached_parts.len()];
+
+    for (name, &index) in attached_parts {
+        if let Some(transform) = parent_parts
+            .get(name)
+            .and_then(|&parent| transforms.get(parent))
+        {
+            attached[index] = *transform;
+        }
+    }
+
+    attached
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::skeleton_transforms;
+    use crate::mc::entity::PartTransform;
+
+    #[test]
+    fn skeletons_follow_parts_by_name() {
+        let parent = HashMap::from([
+            ("root".to_string(), 0),
+            ("head".to_string(), 1),
+            ("tail".to_string(), 2),
+        ]);
+        //An armor layer without a tail, whose parts are in another order
+        let attached = HashMap::from([
+            ("root".to_string(), 0),
+            ("helmet".to_string(), 1),
+            ("head".to_string(), 2),
+        ]);
+
+        let mut transforms = vec![PartTransform::identity(); 3];
+        transforms[1].pitch = 30.0;
+        transforms[2].yaw = 10.0;
+
+        let attached = skeleton_transforms(&parent, &attached, &transforms);
+
+        assert_eq!(attached.len(), 3);
+        assert_eq!(attached[2].pitch, 30.0);
+        assert_eq!(attached[1].pitch, 0.0);
+        assert!(attached.iter().all(|transform| transform.yaw == 0.0));
+    }
+}
@@ -0,0 +1,224 @@
+//! # Entity culling and LOD
+//!
+//! Before their buffers are filled, the instances of every model are culled against the frustum of the
+//! [EntityCamera], which the shader graph sets on [crate::WmRenderer::entities] every frame, so a farm of thousands
+//! of mobs behind the camera costs nothing on the GPU. Each instance is tested with the [EntityBounds] of its model
+//! in its rest pose, moved to where the instance is and grown by [BOUNDS_MARGIN] so that swinging limbs aren't cut
+//! off at the edges of the screen.
+//!
+//! Models can have cheaper meshes for far away instances, given with [Entity::with_lods]. Streamed instances, see
+//! [crate::mc::entity::stream], are drawn with the mesh of the furthest [EntityLod] they're beyond, and the model
+//! itself when they're closer than all of them. The parts of a LOD mesh have to be named like those of the model,
+//! as they're posed with the same transforms.
+
+use std::sync::Arc;
+
+use cgmath::{Matrix4, Vector4};
+use treeculler::{BVol, Frustum, Vec3, AABB};
+
+use crate::mc::entity::{Entity, EntityInstanceTransforms};
+
+/// How far the bounds of an instance are grown on every side, in blocks
+pub const BOUNDS_MARGIN: f32 = 0.5;
+
+/// The box around a model in its rest pose, relative to the position of its instances
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct EntityBounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl EntityBounds {
+    /// The box around the points, which is empty at the origin if there aren't any
+    #[must_use]
+    pub fn from_points(points: impl IntoIterator<Item = [f32; 3]>) -> Self {
+        points
+            .into_iter()
+            .fold(None, |bounds: Option<Self>, point| {
+                Some(match bounds {
+                    None => Self {
+                        min: point,
+                        max: point,
+                    },
+                    Some(bounds) => Self {
+                        min: [0, 1, 2].map(|axis| bounds.min[axis].min(point[axis])),
+                        max: [0, 1, 2].map(|axis| bounds.max[axis].max(point[axis])),
+                    },
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    /// The box around the corners of the bounds once they're transformed, grown by the margin
+    #[must_use]
+    pub fn transformed(&self, matrix: Matrix4<f32>, margin: f32) -> Self {
+        let corners = (0..8).map(|corner| {
+            let pick = |axis: usize| {
+                if corner & (1 << axis) == 0 {
+                    self.min[axis]
+                } else {
+                    self.max[axis]
+                }
+            };
+
+            let corner = matrix * Vector4::new(pick(0), pick(1), pick(2), 1.0);
+
+            [corner.x, corner.y, corner.z]
+        });
+
+        let bounds = Self::from_points(corners);
+
+        Self {
+            min: bounds.min.map(|min| min - margin),
+            max: bounds.max.map(|max| max + margin),
+        }
+    }
+
+    /// Whether any of the box is inside the frustum
+    #[must_use]
+    pub fn visible(&self, frustum: &Frustum<f32>) -> bool {
+        let [min_x, min_y, min_z] = self.min;
+        let [max_x, max_y, max_z] = self.max;
+
+        AABB::<f32>::new(
+            Vec3::new(min_x, min_y, min_z),
+            Vec3::new(max_x, max_y, max_z),
+        )
+        .test_against_frustum(frustum, 0)
+            != u8::MAX
+    }
+}
+
+/// A cheaper mesh of a model which is drawn from the distance on
+#[derive(Clone, Debug)]
+pub struct EntityLod {
+    /// From the camera, in blocks
+    pub distance: f32,
+    pub entity: Arc<Entity>,
+}
+
+/// Where the frame is seen from, which instances are culled against
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EntityCamera {
+    /// The projection matrix times the view matrix
+    pub view_projection: [[f32; 4]; 4],
+    pub position: [f32; 3],
+}
+
+impl EntityCamera {
+    #[must_use]
+    pub fn frustum(&self) -> Frustum<f32> {
+        Frustum::from_modelview_projection(self.view_projection)
+    }
+
+    fn distance(&self, matrix: &Matrix4<f32>) -> f32 {
+        let [x, y, z] = self.position;
+
+        ((matrix.w.x - x).powi(2) + (matrix.w.y - y).powi(2) + (matrix.w.z - z).powi(2)).sqrt()
+    }
+}
+
+/// The index of the furthest of the distances, which are in ascending order, the instance is beyond
+fn lod_index(distances: impl IntoIterator<Item = f32>, distance: f32) -> Option<usize> {
+    distances
+        .into_iter()
+        .take_while(|&lod| distance >= lod)
+        .enumerate()
+        .last()
+        .map(|(index, _)| index)
+}
+
+/// Whether the instance is in view
+#[must_use]
+pub fn instance_visible(
+    frustum: &Frustum<f32>,
+    entity: &Entity,
+    instance: &EntityInstanceTransforms,
+) -> bool {
+    entity
+        .bounds
+        .transformed(instance.base_matrix(), BOUNDS_MARGIN)
+        .visible(frustum)
+}
+
+/// The instances of the model which are in view, grouped by the LOD they're drawn with. Every instance is drawn with
+/// the model itself if there's no camera.
+#[must_use]
+pub fn cull<'a>(
+    camera: Option<&EntityCamera>,
+    entity: &Arc<Entity>,
+    instances: &'a [EntityInstanceTransforms],
+) -> Vec<(Arc<Entity>, Vec<&'a EntityInstanceTransforms>)> {
+    let camera = match camera {
+        Some(camera) => camera,
+        None => return vec![(entity.clone(), instances.iter().collect())],
+    };
+
+    let frustum = camera.frustum();
+    let mut groups = vec![Vec::new(); entity.lods.len() + 1];
+
+    for instance in instances {
+        let matrix = instance.base_matrix();
+
+        if !entity
+            .bounds
+            .transformed(matrix, BOUNDS_MARGIN)
+            .visible(&frustum)
+        {
+            continue;
+        }
+
+        let lod = lod_index(
+            entity.lods.iter().map(|lod| lod.distance),
+            camera.distance(&matrix),
+        );
+
+        groups[lod.map_or(0, |lod| lod + 1)].push(instance);
+    }
+
+    std::iter::once(entity.clone())
+        .chain(entity.lods.iter().map(|lod| lod.entity.clone()))
+        .zip(groups)
+        .filter(|(_, instances)| !instances.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, Matrix4, SquareMatrix, Vector3};
+    use treeculler::Frustum;
+
+    use super::{lod_index, EntityBounds};
+
+    #[test]
+    fn bounds_are_culled_behind_the_camera() {
+        //Looking down -Z from the origin
+        let frustum =
+            Frustum::from_modelview_projection(perspective(Deg(70.0), 1.0, 0.05, 100.0).into());
+        let bounds = EntityBounds::from_points([[-0.5, 0.0, -0.5], [0.5, 2.0, 0.5]]);
+
+        let ahead = bounds.transformed(
+            Matrix4::from_translation(Vector3::new(0.0, 0.0, -10.0)),
+            0.5,
+        );
+        let behind =
+            bounds.transformed(Matrix4::from_translation(Vector3::new(0.0, 0.0, 10.0)), 0.5);
+
+        assert_eq!(ahead.min, [-1.0, -0.5, -11.0]);
+        assert!(ahead.visible(&frustum));
+        assert!(!behind.visible(&frustum));
+        assert!(bounds
+            .transformed(Matrix4::identity(), 0.0)
+            .visible(&frustum));
+    }
+
+    #[test]
+    fn the_furthest_lod_is_picked() {
+        let lods = [16.0, 32.0, 64.0];
+
+        assert_eq!(lod_index(lods, 8.0), None);
+        assert_eq!(lod_index(lods, 16.0), Some(0));
+        assert_eq!(lod_index(lods, 40.0), Some(1));
+        assert_eq!(lod_index(lods, 1000.0), Some(2));
+    }
+}
@@ -1,10 +1,21 @@
+pub mod animation;
+pub mod attachment;
+pub mod culling;
+pub mod model;
+pub mod skin;
+pub mod stream;
+
+use std::ops::Range;
 use std::sync::Arc;
 
+use crate::mc::block::NO_EMISSIVE;
+use crate::mc::entity::attachment::Locator;
+use crate::mc::entity::culling::{EntityBounds, EntityLod};
 use crate::render::atlas::Atlas;
 use crate::texture::{BindableTexture, UV};
 
 use crate::render::entity::EntityVertex;
-use crate::render::pipeline::WmPipelines;
+use crate::render::pipeline::{CachedBinding, WmPipelines};
 use crate::wgpu::util::{BufferInitDescriptor, DeviceExt};
 use crate::{WgpuState, WmRenderer};
 use arc_swap::ArcSwap;
@@ -19,6 +30,7 @@ pub type EntityType = usize;
 
 pub struct EntityManager {
     pub mob_texture_atlas: RwLock<Atlas>,
+    /// Grows as [crate::mc::MinecraftState::skins] are registered
     pub player_texture_atlas: RwLock<Atlas>,
     pub entity_types: RwLock<Vec<Arc<Entity>>>,
     pub entity_vertex_buffers: ArcSwap<HashMap<usize, Arc<wgpu::BindGroup>>>,
@@ -28,8 +40,7 @@ impl EntityManager {
     pub fn new(wgpu_state: &WgpuState, pipelines: &WmPipelines) -> Self {
         Self {
             mob_texture_atlas: RwLock::new(Atlas::new(wgpu_state, pipelines, false)),
-            //TODO: support resizing the atlas
-            player_texture_atlas: RwLock::new(Atlas::new(wgpu_state, pipelines, false)),
+            player_texture_atlas: RwLock::new(Atlas::new(wgpu_state, pipelines, true)),
             entity_types: RwLock::new(Vec::new()),
             entity_vertex_buffers: Default::default(),
         }
@@ -158,36 +169,42 @@ impl Cuboid {
                     tex_coords: [self.textures.south.1 .0, self.textures.south.1 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: h,
                     tex_coords: [self.textures.south.1 .0, self.textures.south.0 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: f,
                     tex_coords: [self.textures.south.0 .0, self.textures.south.1 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: h,
                     tex_coords: [self.textures.south.1 .0, self.textures.south.0 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: g,
                     tex_coords: [self.textures.south.0 .0, self.textures.south.0 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: f,
                     tex_coords: [self.textures.south.0 .0, self.textures.south.1 .1],
                     normal: [0.0, 0.0, 1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
             [
@@ -196,36 +213,42 @@ impl Cuboid {
                     tex_coords: [self.textures.west.1 .0, self.textures.west.0 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: b,
                     tex_coords: [self.textures.west.0 .0, self.textures.west.1 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: f,
                     tex_coords: [self.textures.west.1 .0, self.textures.west.1 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: c,
                     tex_coords: [self.textures.west.0 .0, self.textures.west.0 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: b,
                     tex_coords: [self.textures.west.0 .0, self.textures.west.1 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: g,
                     tex_coords: [self.textures.west.1 .0, self.textures.west.0 .1],
                     normal: [-1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
             [
@@ -234,36 +257,42 @@ impl Cuboid {
                     tex_coords: [self.textures.north.1 .0, self.textures.north.0 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: a,
                     tex_coords: [self.textures.north.0 .0, self.textures.north.1 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: b,
                     tex_coords: [self.textures.north.1 .0, self.textures.north.1 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: d,
                     tex_coords: [self.textures.north.0 .0, self.textures.north.0 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: a,
                     tex_coords: [self.textures.north.0 .0, self.textures.north.1 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: c,
                     tex_coords: [self.textures.north.1 .0, self.textures.north.0 .1],
                     normal: [0.0, 0.0, -1.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
             [
@@ -272,36 +301,42 @@ impl Cuboid {
                     tex_coords: [self.textures.east.0 .0, self.textures.east.1 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: a,
                     tex_coords: [self.textures.east.1 .0, self.textures.east.1 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: d,
                     tex_coords: [self.textures.east.1 .0, self.textures.east.0 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: d,
                     tex_coords: [self.textures.east.1 .0, self.textures.east.0 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: h,
                     tex_coords: [self.textures.east.0 .0, self.textures.east.0 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: e,
                     tex_coords: [self.textures.east.0 .0, self.textures.east.1 .1],
                     normal: [1.0, 0.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
             [
@@ -310,36 +345,42 @@ impl Cuboid {
                     tex_coords: [self.textures.up.1 .0, self.textures.up.0 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: h,
                     tex_coords: [self.textures.up.0 .0, self.textures.up.0 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: d,
                     tex_coords: [self.textures.up.0 .0, self.textures.up.1 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: c,
                     tex_coords: [self.textures.up.1 .0, self.textures.up.1 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: g,
                     tex_coords: [self.textures.up.1 .0, self.textures.up.0 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: d,
                     tex_coords: [self.textures.up.0 .0, self.textures.up.1 .1],
                     normal: [0.0, 1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
             [
@@ -348,36 +389,42 @@ impl Cuboid {
                     tex_coords: [self.textures.down.0 .0, self.textures.down.1 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: b,
                     tex_coords: [self.textures.down.0 .0, self.textures.down.0 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: a,
                     tex_coords: [self.textures.down.1 .0, self.textures.down.0 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: f,
                     tex_coords: [self.textures.down.0 .0, self.textures.down.1 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: a,
                     tex_coords: [self.textures.down.1 .0, self.textures.down.0 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
                 EntityVertex {
                     position: e,
                     tex_coords: [self.textures.down.1 .0, self.textures.down.1 .1],
                     normal: [0.0, -1.0, 0.0],
                     part_id,
+                    emissive_tex_coords: NO_EMISSIVE,
                 },
             ],
         ]
@@ -398,14 +445,32 @@ pub struct EntityPart {
 #[derive(Debug)]
 pub struct Entity {
     pub model_root: EntityPart,
-    pub texture: Arc<BindableTexture>,
+    /// The texture the entity is drawn with, usually the [Atlas::bindable_texture] of its atlas. That one is
+    /// replaced whenever the atlas grows, so the entity is drawn with the texture of the latest
+    /// [generation](Atlas::generation).
+    pub texture: Arc<ArcSwap<BindableTexture>>,
     /// Names of each part referencing an index for applicable transforms
     pub parts: HashMap<String, usize>,
     pub mesh: Arc<wgpu::Buffer>,
     pub vertices: u32,
+    /// The vertices of each part in [Entity::mesh], by the index in [Entity::parts]
+    pub part_vertices: Vec<Range<u32>>,
+    /// The points other models can be attached to by name, see [attachment]
+    pub locators: HashMap<String, Locator>,
+    /// The box around the mesh in its rest pose, which instances are culled with, see [culling]
+    pub bounds: EntityBounds,
+    /// The meshes drawn further away, in ascending order of distance, see [culling]
+    pub lods: Vec<EntityLod>,
 }
 
-fn recurse_get_mesh(part: &EntityPart, vertices: &mut Vec<EntityVertex>, part_id: &mut u32) {
+fn recurse_get_mesh(
+    part: &EntityPart,
+    vertices: &mut Vec<EntityVertex>,
+    part_vertices: &mut Vec<Range<u32>>,
+    part_id: &mut u32,
+) {
+    let start = vertices.len() as u32;
+
     part.cuboids.iter().for_each(|cuboid| {
         vertices.extend(
             cuboid
@@ -416,10 +481,11 @@ fn recurse_get_mesh(part: &EntityPart, vertices: &mut Vec<EntityVertex>, part_id
         );
     });
 
+    part_vertices.push(start..vertices.len() as u32);
     *part_id += 1;
 
     part.children.iter().for_each(|part| {
-        recurse_get_mesh(part, vertices, part_id);
+        recurse_get_mesh(part, vertices, part_vertices, part_id);
     });
 }
 
@@ -432,16 +498,53 @@ fn recurse_get_names(part: &EntityPart, index: &mut usize, names: &mut HashMap<S
 }
 
 impl Entity {
-    ///Create an entity from an [EntityPart] and upload it's mesh to the GPU
-    pub fn new(root: EntityPart, wgpu_state: &WgpuState, texture: Arc<BindableTexture>) -> Self {
+    ///Create an entity from an [EntityPart] and upload it's mesh to the GPU, like one baked from an
+    /// [model::EntityModel]
+    pub fn new(
+        root: EntityPart,
+        wgpu_state: &WgpuState,
+        texture: Arc<ArcSwap<BindableTexture>>,
+    ) -> Self {
+        Self::with_emissive(root, wgpu_state, texture, None)
+    }
+
+    ///Like [Entity::new], with an emissive overlay which is the same size as the entity's texture and
+    /// `emissive_offset` away from it in the atlas, see [Atlas::emissive_offset]
+    pub fn with_emissive(
+        root: EntityPart,
+        wgpu_state: &WgpuState,
+        texture: Arc<ArcSwap<BindableTexture>>,
+        emissive_offset: Option<[f32; 2]>,
+    ) -> Self {
         let mut parts = HashMap::new();
 
         recurse_get_names(&root, &mut 0, &mut parts);
 
         let mut mesh = Vec::new();
 
+        let mut part_vertices = Vec::new();
         let mut part_id = 0;
-        recurse_get_mesh(&root, &mut mesh, &mut part_id);
+        recurse_get_mesh(&root, &mut mesh, &mut part_vertices, &mut part_id);
+
+        if let Some([offset_u, offset_v]) = emissive_offset {
+            for vertex in &mut mesh {
+                vertex.emissive_tex_coords = [
+                    vertex.tex_coords[0] + offset_u,
+                    vertex.tex_coords[1] + offset_v,
+                ];
+            }
+        }
+
+        //Where each part sits in the rest pose
+        let mut rest = Vec::new();
+        recurse_transforms(Matrix4::identity(), &root, &mut rest, &mut 0, &[]);
+
+        let bounds = EntityBounds::from_points(mesh.iter().map(|vertex| {
+            let [x, y, z] = vertex.position;
+            let position = rest[vertex.part_id as usize] * Vector4::new(x, y, z, 1.0);
+
+            [position.x, position.y, position.z]
+        }));
 
         Self {
             model_root: root,
@@ -453,43 +556,98 @@ impl Entity {
                 usage: wgpu::BufferUsages::VERTEX,
             })),
             vertices: mesh.len() as u32,
+            part_vertices,
+            locators: HashMap::new(),
+            bounds,
+            lods: Vec::new(),
+        }
+    }
+
+    ///Gives the entity the locators, like those of the [model::EntityModel] it was baked from
+    #[must_use]
+    pub fn with_locators(mut self, locators: HashMap<String, Locator>) -> Self {
+        self.locators = locators;
+        self
+    }
+
+    ///Gives the entity cheaper meshes which are drawn from the distances on, see [culling]
+    #[must_use]
+    pub fn with_lods(mut self, mut lods: Vec<EntityLod>) -> Self {
+        lods.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        self.lods = lods;
+        self
+    }
+
+    ///Create an entity with a single part called `name` from a mesh which was baked elsewhere, like an item model.
+    /// Every vertex has to be of part 0.
+    pub fn from_vertices(
+        name: &str,
+        vertices: &[EntityVertex],
+        wgpu_state: &WgpuState,
+        texture: Arc<ArcSwap<BindableTexture>>,
+    ) -> Self {
+        Self {
+            model_root: EntityPart {
+                name: Arc::new(name.into()),
+                transform: PartTransform::identity(),
+                cuboids: Vec::new(),
+                children: Vec::new(),
+            },
+            texture,
+            parts: HashMap::from([(name.into(), 0)]),
+            mesh: Arc::new(wgpu_state.device.create_buffer_init(&BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            })),
+            vertices: vertices.len() as u32,
+            part_vertices: vec![0..vertices.len() as u32],
+            locators: HashMap::new(),
+            bounds: EntityBounds::from_points(vertices.iter().map(|vertex| vertex.position)),
+            lods: Vec::new(),
         }
     }
 }
 
-#[allow(dead_code)]
 #[derive(Clone)]
 pub(crate) struct UploadedEntityInstances {
-    pub(crate) transform_ssbo: (Arc<wgpu::Buffer>, Arc<wgpu::BindGroup>),
-    pub(crate) instance_vbo: Arc<wgpu::Buffer>,
+    pub(crate) transforms: Arc<wgpu::Buffer>,
+    pub(crate) instance_data: Arc<wgpu::Buffer>,
+    pub(crate) tints: Arc<wgpu::Buffer>,
+    /// The `entity_instances` bind group of the buffers
+    pub(crate) bind_group: Arc<wgpu::BindGroup>,
     pub(crate) count: u32,
 }
 
-#[derive(Copy, Clone, Zeroable, Pod)]
+#[derive(Copy, Clone, Debug, PartialEq, Zeroable, Pod)]
 #[repr(C)]
-/// POD representation of an entity instance that is used in the instance VBO
-pub(crate) struct EntityInstanceVBOEntry {
-    /// Index into mat4[]
-    pub(crate) entity_index: u32,
-    pub(crate) uv_offset: [f32; 2],
-    pub(crate) parts_per_entity: u32,
+/// POD representation of an entity instance in the storage buffer the entity pipelines read at the
+/// `instance_index`, see [crate::render::pipeline::entity]. Laid out like the WGSL struct
+/// `{ overlay: vec4<f32>, uv_offset: vec2<f32>, first_part: u32, parts: u32 }`
+pub struct EntityInstanceData {
+    pub overlay: [f32; 4],
+    pub uv_offset: [f32; 2],
+    /// Index of the transform of the first part into the part transforms
+    pub first_part: u32,
+    pub parts: u32,
 }
 
-impl EntityInstanceVBOEntry {
-    const _VAA: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
-        4 => Uint32,
-        5 => Float32x2,
-        6 => Uint32
-    ];
-
-    pub fn _desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<EntityInstanceVBOEntry>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &Self::_VAA,
-        }
-    }
+/// The data of each instance, whose part transforms start at `first_part`
+fn instance_data<'a>(
+    instances: impl IntoIterator<Item = &'a EntityInstanceTransforms>,
+    parts: u32,
+    first_part: u32,
+) -> Vec<EntityInstanceData> {
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(index, instance)| EntityInstanceData {
+            overlay: instance.overlay,
+            uv_offset: [instance.uv_offset.0, instance.uv_offset.1],
+            first_part: first_part + index as u32 * parts,
+            parts,
+        })
+        .collect()
 }
 
 pub struct EntityInstances {
@@ -507,9 +665,28 @@ impl EntityInstances {
         }
     }
 
+    /// Fills the buffers the instances are drawn from, leaving out the ones which are out of view of the camera of
+    /// [WmRenderer::entities], see [culling]. Nothing is uploaded while the renderer is paused, the instances are
+    /// drawn as they were last uploaded.
     pub fn upload(&self, wm: &WmRenderer) {
-        let matrices = self
+        if wm.is_paused() {
+            return;
+        }
+
+        let camera = **wm.entities.camera.load();
+        let frustum = camera.map(|camera| camera.frustum());
+
+        let visible: Vec<&EntityInstanceTransforms> = self
             .instances
+            .iter()
+            .filter(|instance| {
+                frustum.as_ref().map_or(true, |frustum| {
+                    culling::instance_visible(frustum, &self.entity, instance)
+                })
+            })
+            .collect();
+
+        let matrices = visible
             .iter()
             .flat_map(|transforms| {
                 transforms
@@ -520,54 +697,74 @@ impl EntityInstances {
             })
             .collect::<Vec<f32>>();
 
-        let instances: Vec<EntityInstanceVBOEntry> = self
-            .instances
+        let tints = visible
             .iter()
-            .enumerate()
-            .map(|(index, instance)| EntityInstanceVBOEntry {
-                entity_index: index as u32,
-                uv_offset: [instance.uv_offset.0, instance.uv_offset.1],
-                parts_per_entity: self.entity.parts.len() as u32,
-            })
-            .collect();
+            .flat_map(|transforms| transforms.get_tints(&self.entity))
+            .collect::<Vec<[f32; 4]>>();
 
-        let instances_bytes = bytemuck::cast_slice(&instances[..]);
+        let instances = instance_data(visible, self.entity.parts.len() as u32, 0);
 
-        let instance_vbo = Arc::new(wm.wgpu_state.device.create_buffer_init(
-            &BufferInitDescriptor {
-                label: None,
-                contents: instances_bytes,
-                usage: wgpu::BufferUsages::VERTEX,
-            },
-        ));
+        //Storage buffers can't be empty
+        if instances.is_empty() {
+            *self.uploaded.write() = None;
+            return;
+        }
 
-        let transform_buffer = wm
-            .wgpu_state
-            .device
-            .create_buffer_init(&BufferInitDescriptor {
-                label: None,
-                contents: bytemuck::cast_slice(&matrices[..]),
-                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
-            });
-
-        let pipelines = wm.pipelines.load();
-
-        let transform_bind_group =
-            wm.wgpu_state
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: None,
-                    layout: pipelines.bind_group_layouts.read().get("ssbo").unwrap(),
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: transform_buffer.as_entire_binding(),
-                    }],
-                });
+        let uploaded = self.uploaded.read().clone();
+
+        //The buffers are written again while the number of instances stays the same, which also keeps their bind
+        //group
+        let upload = |previous: Option<Arc<wgpu::Buffer>>, bytes: &[u8]| match previous
+            .filter(|buffer| buffer.size() == bytes.len() as u64)
+        {
+            Some(buffer) => {
+                wm.wgpu_state.queue.write_buffer(&buffer, 0, bytes);
+                buffer
+            }
+            None => Arc::new(
+                wm.wgpu_state
+                    .device
+                    .create_buffer_init(&BufferInitDescriptor {
+                        label: None,
+                        contents: bytes,
+                        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                    }),
+            ),
+        };
+
+        let instance_data = upload(
+            uploaded
+                .as_ref()
+                .map(|uploaded| uploaded.instance_data.clone()),
+            bytemuck::cast_slice(&instances),
+        );
+        let transforms = upload(
+            uploaded
+                .as_ref()
+                .map(|uploaded| uploaded.transforms.clone()),
+            bytemuck::cast_slice(&matrices),
+        );
+        let tints = upload(
+            uploaded.map(|uploaded| uploaded.tints),
+            bytemuck::cast_slice(&tints),
+        );
+
+        let bind_group = wm.pipelines.load().bind_group(
+            &wm.wgpu_state.device,
+            "entity_instances",
+            &[
+                CachedBinding::Buffer(instance_data.clone()),
+                CachedBinding::Buffer(transforms.clone()),
+                CachedBinding::Buffer(tints.clone()),
+            ],
+        );
 
         *self.uploaded.write() = Some(UploadedEntityInstances {
-            transform_ssbo: (Arc::new(transform_buffer), Arc::new(transform_bind_group)),
-            instance_vbo,
-            count: self.instances.len() as u32,
+            transforms,
+            instance_data,
+            tints,
+            bind_group,
+            count: instances.len() as u32,
         });
     }
 }
@@ -578,10 +775,50 @@ pub struct EntityInstanceTransforms {
     ///Rotation around the Y axis
     pub looking_yaw: f32,
     pub uv_offset: (f32, f32),
+    /// The color the entity is tinted with, mixed in by its alpha, like [HURT_OVERLAY] or [flash_overlay]
+    pub overlay: [f32; 4],
+    /// The transform of each part on top of the model's, by the index in [Entity::parts], usually posed by an
+    /// [animation::Animator]
     pub part_transforms: Vec<PartTransform>,
+    /// The color each part is multiplied by, by the index in [Entity::parts], like the wool of a dyed sheep or dyed
+    /// leather armor. Parts without one are left as they are.
+    pub part_tints: Vec<[f32; 4]>,
+    /// The matrix the model is drawn relative to in place of its position and yaw, like that of the locator it's
+    /// attached to, see [attachment::Attachment]
+    pub attached_to: Option<[[f32; 4]; 4]>,
+}
+
+/// The overlay of a mob which was hurt, like vanilla's red flash
+pub const HURT_OVERLAY: [f32; 4] = [1.0, 0.0, 0.0, 0.3];
+
+/// The overlay of an entity flashing white, like a creeper about to explode, from 0 to 1
+#[must_use]
+pub fn flash_overlay(amount: f32) -> [f32; 4] {
+    [1.0, 1.0, 1.0, amount.clamp(0.0, 1.0)]
 }
 
 impl EntityInstanceTransforms {
+    /// The tint of every part of the entity, in the order of [Entity::parts]
+    pub fn get_tints(&self, entity: &Entity) -> Vec<[f32; 4]> {
+        (0..entity.parts.len())
+            .map(|index| self.part_tints.get(index).copied().unwrap_or([1.0; 4]))
+            .collect()
+    }
+
+    /// The matrix the root of the model is posed relative to
+    pub fn base_matrix(&self) -> Matrix4<f32> {
+        match self.attached_to {
+            Some(matrix) => matrix.into(),
+            None => {
+                Matrix4::from_translation(cgmath::Vector3::new(
+                    self.position.0,
+                    self.position.1,
+                    self.position.2,
+                )) * Matrix4::from_angle_y(cgmath::Deg(self.looking_yaw))
+            }
+        }
+    }
+
     pub fn get_matrices(&self, entity: &Entity) -> Vec<[[f32; 4]; 4]> {
         let transforms: Vec<Matrix4<f32>> = self
             .part_transforms
@@ -591,16 +828,11 @@ impl EntityInstanceTransforms {
 
         let mut vec = Vec::new();
 
-        // let mut index = 0;
         recurse_transforms(
-            Matrix4::from_translation(cgmath::Vector3::new(
-                self.position.0,
-                self.position.1,
-                self.position.2,
-            )) * Matrix4::from_angle_y(cgmath::Deg(self.looking_yaw)),
+            self.base_matrix(),
             &entity.model_root,
             &mut vec,
-            // &mut index,
+            &mut 0,
             &transforms[..],
         );
 
@@ -612,10 +844,15 @@ fn recurse_transforms(
     mat: Matrix4<f32>,
     part: &EntityPart,
     vec: &mut Vec<Matrix4<f32>>,
-    // index: &mut usize,
+    index: &mut usize,
     instance_transforms: &[Matrix4<f32>],
 ) {
-    let instance_part_transform = instance_transforms[0];
+    //The parts are indexed in the same order as they're visited in, see recurse_get_names. Parts without a transform,
+    //like all of them if the instance isn't animated, are left as they are in the model
+    let instance_part_transform = instance_transforms
+        .get(*index)
+        .copied()
+        .unwrap_or_else(Matrix4::identity);
 
     //mat is a transformation matrix that has been composed recursively from it's parent's and ancestors' transforms
     //part.transform.describe() gets the transformation that was described in the model
@@ -624,13 +861,84 @@ fn recurse_transforms(
     let new_mat = mat * part.transform.describe() * instance_part_transform;
 
     vec.push(new_mat);
-
-    let mut slice = &instance_transforms[1..];
+    *index += 1;
 
     part.children.iter().for_each(|child| {
-        // *index += 1;
-        // recurse_transforms(new_mat, child, vec, index, instance_transforms);
-        recurse_transforms(new_mat, child, vec, slice);
-        slice = &slice[1..];
+        recurse_transforms(new_mat, child, vec, index, instance_transforms);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use cgmath::{Matrix4, SquareMatrix, Vector3};
+
+    use super::{
+        instance_data, recurse_transforms, EntityInstanceTransforms, EntityPart, PartTransform,
+        HURT_OVERLAY,
+    };
+
+    fn part(name: &str, children: Vec<EntityPart>) -> EntityPart {
+        EntityPart {
+            name: Arc::new(name.into()),
+            transform: PartTransform::identity(),
+            cuboids: Vec::new(),
+            children,
+        }
+    }
+
+    #[test]
+    fn part_transforms_are_indexed_depth_first() {
+        let root = part(
+            "root",
+            vec![
+                part("body", vec![part("head", vec![])]),
+                part("tail", vec![]),
+            ],
+        );
+
+        let mut transforms = vec![Matrix4::identity(); 4];
+        transforms[3] = Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0));
+
+        let mut matrices = Vec::new();
+        recurse_transforms(
+            Matrix4::identity(),
+            &root,
+            &mut matrices,
+            &mut 0,
+            &transforms,
+        );
+
+        assert_eq!(matrices.len(), 4);
+        assert_eq!(matrices[2], Matrix4::identity());
+        assert_eq!(matrices[3], transforms[3]);
+
+        //Instances without transforms keep the model's
+        let mut matrices = Vec::new();
+        recurse_transforms(Matrix4::identity(), &root, &mut matrices, &mut 0, &[]);
+        assert_eq!(matrices, vec![Matrix4::identity(); 4]);
+    }
+
+    #[test]
+    fn instances_index_their_parts() {
+        let instance = |overlay| EntityInstanceTransforms {
+            position: (0.0, 0.0, 0.0),
+            looking_yaw: 0.0,
+            uv_offset: (0.5, 0.0),
+            overlay,
+            part_transforms: Vec::new(),
+            part_tints: Vec::new(),
+            attached_to: None,
+        };
+
+        let hurt = HURT_OVERLAY;
+        let data = instance_data(&[instance([0.0; 4]), instance(hurt)], 4, 0);
+
+        assert_eq!(data[0].first_part, 0);
+        assert_eq!(data[1].first_part, 4);
+        assert_eq!(data[1].parts, 4);
+        assert_eq!(data[1].overlay, hurt);
+        assert_eq!(data[1].uv_offset, [0.5, 0.0]);
+    }
+}
@@ -0,0 +1,435 @@
+//! # Entity models
+//!
+//! An [EntityModel] describes the parts of an entity like vanilla's `TexturedModelData` does, as JSON which is
+//! usually at `<namespace>:models/entity/<name>.json`:
+//!
+//! ```json
+//! {
+//!   "texture_width": 64,
+//!   "texture_height": 32,
+//!   "parts": [
+//!     { "name": "body", "pivot": [0, 12, 2], "rotation": [90, 0, 0],
+//!       "cuboids": [{ "uv": [28, 8], "origin": [-5, -10, -7], "size": [10, 16, 8] }] },
+//!     { "name": "head", "parent": "body", "pivot": [0, 12, -6],
+//!       "cuboids": [{ "uv": [0, 0], "origin": [-4, -4, -8], "size": [8, 8, 8], "dilation": 0.5 }] }
+//!   ]
+//! }
+//! ```
+//!
+//! Positions are in pixels, 16 to a block, and rotations are in degrees around X, Y and Z. Like vanilla's, cuboids
+//! are relative to the pivot of their part, and pivots to the pivot of the parent part. Each cuboid is textured
+//! like vanilla unwraps a box starting at its `uv`, and `mirror` flips it horizontally. Parts without a `parent` are
+//! children of the part called `root`, so the model has a single [EntityPart] at its root like vanilla's. The
+//! coordinates are taken as they are, vanilla's renderers turn the models upside down with the matrix of the entity.
+//!
+//! Parts can have `locators`, named points which other models like held items are attached to, see
+//! [EntityModel::locators] and [crate::mc::entity::attachment]:
+//!
+//! ```json
+//! { "name": "right_arm", "pivot": [-5, 2, 0],
+//!   "locators": { "right_hand": { "offset": [-1, 10, -2], "rotation": [-90, 0, 0] } } }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde_derive::Deserialize;
+
+use crate::mc::entity::attachment::Locator;
+use crate::mc::entity::{Cuboid, CuboidUV, EntityPart, PartTransform};
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::texture::UV;
+
+/// The part every part without a parent belongs to
+pub const ROOT_PART: &str = "root";
+
+#[derive(Debug)]
+pub enum EntityModelError {
+    Missing(ResourcePath),
+    Json(ResourcePath, serde_json::Error),
+    /// A part names a parent which doesn't exist
+    UnknownParent {
+        part: String,
+        parent: String,
+    },
+    DuplicatePart(String),
+    /// A part is one of its own ancestors
+    Cycle(String),
+}
+
+fn default_texture_width() -> u32 {
+    64
+}
+
+fn default_texture_height() -> u32 {
+    32
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EntityModel {
+    /// The size of the texture in pixels, which the UVs of the cuboids are relative to
+    #[serde(default = "default_texture_width")]
+    pub texture_width: u32,
+    #[serde(default = "default_texture_height")]
+    pub texture_height: u32,
+    pub parts: Vec<PartDefinition>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PartDefinition {
+    pub name: String,
+    /// The name of the part this one inherits the transform of, [ROOT_PART] if none
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub pivot: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default)]
+    pub cuboids: Vec<CuboidDefinition>,
+    #[serde(default)]
+    pub locators: HashMap<String, LocatorDefinition>,
+}
+
+/// A point relative to the pivot of its part, rotated in degrees around X, Y and Z
+#[derive(Deserialize, Debug, Clone)]
+pub struct LocatorDefinition {
+    #[serde(default)]
+    pub offset: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CuboidDefinition {
+    /// The top left corner of the unwrapped box in the texture
+    #[serde(default)]
+    pub uv: [f32; 2],
+    pub origin: [f32; 3],
+    pub size: [f32; 3],
+    /// How much the cuboid is grown by on every side, without changing its UVs
+    #[serde(default)]
+    pub dilation: f32,
+    #[serde(default)]
+    pub mirror: bool,
+}
+
+impl CuboidDefinition {
+    /// The UVs of each face in pixels, laid out like vanilla unwraps a box:
+    ///
+    /// ```text
+    ///        [ down ][  up  ]
+    /// [ west ][north ][ east ][south ]
+    /// ```
+    #[must_use]
+    pub fn pixel_uvs(&self) -> CuboidUV {
+        let [u, v] = self.uv;
+        let [width, height, length] = self.size;
+
+        let side = |start: f32, size: f32| {
+            (
+                (u + start, v + length),
+                (u + start + size, v + length + height),
+            )
+        };
+
+        let mut uvs = CuboidUV {
+            down: ((u + length, v), (u + length + width, v + length)),
+            //The top is flipped vertically
+            up: (
+                (u + length + width, v + length),
+                (u + length + width * 2.0, v),
+            ),
+            west: side(0.0, length),
+            north: side(length, width),
+            east: side(length + width, length),
+            south: side(length * 2.0 + width, width),
+        };
+
+        if self.mirror {
+            std::mem::swap(&mut uvs.east, &mut uvs.west);
+
+            for face in [
+                &mut uvs.north,
+                &mut uvs.east,
+                &mut uvs.south,
+                &mut uvs.west,
+                &mut uvs.up,
+                &mut uvs.down,
+            ] {
+                std::mem::swap(&mut face.0 .0, &mut face.1 .0);
+            }
+        }
+
+        uvs
+    }
+}
+
+impl EntityModel {
+    pub fn load(
+        provider: &dyn ResourceProvider,
+        path: &ResourcePath,
+    ) -> Result<Self, EntityModelError> {
+        let json = provider
+            .get_string(path)
+            .ok_or_else(|| EntityModelError::Missing(path.clone()))?;
+
+        serde_json::from_str(&json).map_err(|error| EntityModelError::Json(path.clone(), error))
+    }
+
+    /// Builds the tree of parts, with the UVs mapped into `region`, the UVs of the model's texture in the texture it's
+    /// drawn with, like its atlas. `((0.0, 0.0), (1.0, 1.0))` if it's drawn with a texture of its own.
+    pub fn bake(&self, region: UV) -> Result<EntityPart, EntityModelError> {
+        let mut children: HashMap<&str, Vec<&PartDefinition>> = HashMap::new();
+
+        for part in &self.parts {
+            if part.name == ROOT_PART
+                || self
+                    .parts
+                    .iter()
+                    .filter(|other| other.name == part.name)
+                    .count()
+                    > 1
+            {
+                return Err(EntityModelError::DuplicatePart(part.name.clone()));
+            }
+
+            let parent = part.parent.as_deref().unwrap_or(ROOT_PART);
+
+            if parent != ROOT_PART && !self.parts.iter().any(|other| other.name == parent) {
+                return Err(EntityModelError::UnknownParent {
+                    part: part.name.clone(),
+                    parent: parent.into(),
+                });
+            }
+
+            children.entry(parent).or_default().push(part);
+        }
+
+        let root = PartDefinition {
+            name: ROOT_PART.into(),
+            parent: None,
+            pivot: [0.0; 3],
+            rotation: [0.0; 3],
+            cuboids: Vec::new(),
+            locators: HashMap::new(),
+        };
+
+        let mut baked = 0;
+        let root = self.bake_part(&root, &children, region, &mut baked);
+
+        //Parts which are their own ancestors are never reached from the root
+        if baked != self.parts.len() + 1 {
+            let cycle = self
+                .parts
+                .iter()
+                .find(|part| !contains(&root, &part.name))
+                .unwrap();
+
+            return Err(EntityModelError::Cycle(cycle.name.clone()));
+        }
+
+        Ok(root)
+    }
+
+    /// The locators of every part, for [crate::mc::entity::Entity::with_locators]
+    #[must_use]
+    pub fn locators(&self) -> HashMap<String, Locator> {
+        self.parts
+            .iter()
+            .flat_map(|part| {
+                part.locators.iter().map(|(name, locator)| {
+                    let [x, y, z] = locator.offset.map(|offset| offset / 16.0);
+                    let [pitch, yaw, roll] = locator.rotation;
+
+                    (
+                        name.clone(),
+                        Locator {
+                            part: part.name.clone(),
+                            //Rotated around the point, like parts are around their pivots
+                            transform: PartTransform {
+                                x,
+                                y,
+                                z,
+                                pivot_x: x,
+                                pivot_y: y,
+                                pivot_z: z,
+                                yaw,
+                                pitch,
+                                roll,
+                                ..PartTransform::identity()
+                            },
+                        },
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn bake_part(
+        &self,
+        part: &PartDefinition,
+        children: &HashMap<&str, Vec<&PartDefinition>>,
+        region: UV,
+        baked: &mut usize,
+    ) -> EntityPart {
+        *baked += 1;
+
+        let ((u0, v0), (u1, v1)) = region;
+        let scale_u = (u1 - u0) / self.texture_width as f32;
+        let scale_v = (v1 - v0) / self.texture_height as f32;
+        let map = |((a, b), (c, d)): UV| {
+            (
+                (u0 + a * scale_u, v0 + b * scale_v),
+                (u0 + c * scale_u, v0 + d * scale_v),
+            )
+        };
+
+        let [pitch, yaw, roll] = part.rotation;
+
+        EntityPart {
+            name: Arc::new(part.name.clone()),
+            //Moved to the pivot and rotated around it, so that the cuboids and children are relative to the pivot
+            transform: PartTransform {
+                x: part.pivot[0] / 16.0,
+                y: part.pivot[1] / 16.0,
+                z: part.pivot[2] / 16.0,
+                pivot_x: part.pivot[0] / 16.0,
+                pivot_y: part.pivot[1] / 16.0,
+                pivot_z: part.pivot[2] / 16.0,
+                yaw,
+                pitch,
+                roll,
+                ..PartTransform::identity()
+            },
+            cuboids: part
+                .cuboids
+                .iter()
+                .map(|cuboid| {
+                    let uvs = cuboid.pixel_uvs();
+                    let dilation = cuboid.dilation;
+
+                    Cuboid {
+                        x: cuboid.origin[0] - dilation,
+                        y: cuboid.origin[1] - dilation,
+                        z: cuboid.origin[2] - dilation,
+                        width: cuboid.size[0] + dilation * 2.0,
+                        height: cuboid.size[1] + dilation * 2.0,
+                        length: cuboid.size[2] + dilation * 2.0,
+                        textures: CuboidUV {
+                            north: map(uvs.north),
+                            east: map(uvs.east),
+                            south: map(uvs.south),
+                            west: map(uvs.west),
+                            up: map(uvs.up),
+                            down: map(uvs.down),
+                        },
+                    }
+                })
+                .collect(),
+            children: children
+                .get(&part.name[..])
+                .map(|parts| {
+                    parts
+                        .iter()
+                        .map(|child| self.bake_part(child, children, region, baked))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn contains(part: &EntityPart, name: &str) -> bool {
+    *part.name == name || part.children.iter().any(|child| contains(child, name))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{EntityModel, EntityModelError, ROOT_PART};
+
+    fn model(parts: serde_json::Value) -> EntityModel {
+        serde_json::from_value(json!({ "texture_width": 64, "texture_height": 64, "parts": parts }))
+            .unwrap()
+    }
+
+    #[test]
+    fn parts_are_nested_under_their_parents() {
+        let model = model(json!([
+            { "name": "head", "parent": "body", "pivot": [0, 16, 0], "rotation": [10, 0, 0],
+              "cuboids": [{ "uv": [0, 0], "origin": [-4, -8, -4], "size": [8, 8, 8], "dilation": 0.5 }] },
+            { "name": "body", "cuboids": [{ "uv": [16, 16], "origin": [-4, 0, -2], "size": [8, 12, 4] }] },
+        ]));
+
+        let root = model.bake(((0.0, 0.0), (0.5, 0.5))).unwrap();
+        assert_eq!(*root.name, ROOT_PART);
+
+        let body = &root.children[0];
+        let head = &body.children[0];
+        assert_eq!(*body.name, "body");
+        assert_eq!(*head.name, "head");
+
+        assert_eq!(head.transform.y, 1.0);
+        assert_eq!(head.transform.pivot_y, 1.0);
+        assert_eq!(head.transform.pitch, 10.0);
+        assert_eq!(head.cuboids[0].x, -4.5);
+        assert_eq!(head.cuboids[0].width, 9.0);
+
+        //The front of the head is at (8, 8) to (16, 16) of the 64x64 texture, which is the top left quarter of the
+        //atlas
+        assert_eq!(
+            head.cuboids[0].textures.north,
+            ((0.0625, 0.0625), (0.125, 0.125))
+        );
+    }
+
+    #[test]
+    fn locators_belong_to_their_parts() {
+        let model = model(json!([
+            { "name": "right_arm", "pivot": [-5, 2, 0],
+              "locators": { "right_hand": { "offset": [-1, 8, -2], "rotation": [-90, 0, 0] } } },
+        ]));
+
+        let locators = model.locators();
+        let hand = &locators["right_hand"];
+
+        assert_eq!(hand.part, "right_arm");
+        assert_eq!(hand.transform.y, 0.5);
+        assert_eq!(hand.transform.pivot_z, -0.125);
+        assert_eq!(hand.transform.pitch, -90.0);
+    }
+
+    #[test]
+    fn mirrored_cuboids_swap_their_sides() {
+        let model = model(json!([
+            { "name": "leg", "cuboids": [{ "uv": [0, 16], "origin": [0, 0, 0], "size": [4, 12, 4], "mirror": true }] },
+        ]));
+
+        let root = model.bake(((0.0, 0.0), (64.0, 64.0))).unwrap();
+        let textures = root.children[0].cuboids[0].textures;
+
+        assert_eq!(textures.east, ((4.0, 20.0), (0.0, 32.0)));
+        assert_eq!(textures.west, ((12.0, 20.0), (8.0, 32.0)));
+        assert_eq!(textures.north, ((8.0, 20.0), (4.0, 32.0)));
+    }
+
+    #[test]
+    fn invalid_hierarchies() {
+        let unknown = model(json!([{ "name": "head", "parent": "body" }]));
+        assert!(matches!(
+            unknown.bake(((0.0, 0.0), (1.0, 1.0))),
+            Err(EntityModelError::UnknownParent { .. })
+        ));
+
+        let cycle = model(json!([
+            { "name": "a", "parent": "b" },
+            { "name": "b", "parent": "a" },
+        ]));
+        assert!(matches!(
+            cycle.bake(((0.0, 0.0), (1.0, 1.0))),
+            Err(EntityModelError::Cycle(_))
+        ));
+    }
+}
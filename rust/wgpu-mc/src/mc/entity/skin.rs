@@ -0,0 +1,166 @@
+//! # Skins
+//!
+//! Some entities have textures of their own, like the skins of players. Rather than a texture and bind group for
+//! each of them, the [Skins] of [crate::mc::MinecraftState::skins] pack them into one atlas which grows as more are registered, usually the
+//! [EntityManager::player_texture_atlas](crate::mc::entity::EntityManager::player_texture_atlas), so every instance
+//! of a model is drawn in one draw however many skins there are.
+//!
+//! A model drawn with skins is baked with its texture at the top left of the atlas, see [skin_region], and each
+//! instance is moved to its skin by its [uv_offset](crate::mc::entity::EntityInstanceTransforms::uv_offset), see
+//! [Skins::uv_offset]. When the atlas grows, its texture is replaced and the UVs of every skin shrink, so models have
+//! to be baked again whenever the [generation](Atlas::generation) of the atlas changes, like chunks are.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use image::ImageError;
+use parking_lot::{Mutex, RwLock};
+
+use crate::mc::resource::ResourcePath;
+use crate::render::atlas::{Atlas, AtlasError};
+use crate::texture::UV;
+use crate::WmRenderer;
+
+#[derive(Debug)]
+pub enum SkinError {
+    Image(ImageError),
+    Atlas(AtlasError),
+}
+
+/// The skins registered in an atlas. Each skin is allocated in a slot of its own, and the slots of skins which are
+/// unregistered are reused by the next skins of the same size, like those of the players who join after others
+/// left.
+#[derive(Default)]
+pub struct Skins {
+    /// The slot of the atlas each skin is in
+    slots: RwLock<HashMap<ResourcePath, ResourcePath>>,
+    /// The slots of skins which were unregistered
+    free: Mutex<Vec<ResourcePath>>,
+    next_slot: AtomicU32,
+}
+
+impl Skins {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a skin to the atlas and uploads it, or replaces the pixels of the skin with the `id` if it's already
+    /// registered. The PNG is usually downloaded, so it's decoded here rather than with the resources.
+    pub fn register(
+        &self,
+        wm: &WmRenderer,
+        atlas: &Atlas,
+        id: &ResourcePath,
+        png: &[u8],
+    ) -> Result<(), SkinError> {
+        let image = image::load_from_memory(png)
+            .map_err(SkinError::Image)?
+            .to_rgba8();
+        let size = image.dimensions();
+
+        let slot_size = |slot: &ResourcePath| {
+            atlas
+                .uv_map
+                .read()
+                .get(slot)
+                .map(|((min_x, min_y), (max_x, max_y))| {
+                    ((max_x - min_x) as u32, (max_y - min_y) as u32)
+                })
+        };
+
+        let existing = self.slots.read().get(id).cloned();
+
+        let slot = match existing {
+            Some(slot) if slot_size(&slot) == Some(size) => Some(slot),
+            Some(slot) => {
+                self.free.lock().push(slot);
+                None
+            }
+            None => None,
+        };
+
+        let slot = slot.or_else(|| {
+            let mut free = self.free.lock();
+            let index = free.iter().position(|slot| slot_size(slot) == Some(size))?;
+
+            Some(free.swap_remove(index))
+        });
+
+        match slot {
+            Some(slot) => {
+                atlas
+                    .update_texture(wm, &slot, image.as_raw(), size.0, size.1)
+                    .map_err(SkinError::Atlas)?;
+
+                self.slots.write().insert(id.clone(), slot);
+            }
+            None => {
+                let slot = ResourcePath(format!(
+                    "wgpu_mc:skins/{}",
+                    self.next_slot.fetch_add(1, Ordering::Relaxed)
+                ));
+
+                atlas
+                    .allocate([(&slot, &png)], &*wm.mc.resource_provider)
+                    .map_err(SkinError::Atlas)?;
+                atlas.upload(wm);
+
+                self.slots.write().insert(id.clone(), slot);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Frees the slot of the skin for the next one of the same size
+    pub fn unregister(&self, id: &ResourcePath) {
+        if let Some(slot) = self.slots.write().remove(id) {
+            self.free.lock().push(slot);
+        }
+    }
+
+    /// How far the skin is from the top left of the atlas, for the
+    /// [uv_offset](crate::mc::entity::EntityInstanceTransforms::uv_offset) of instances of a model baked with
+    /// [skin_region]
+    #[must_use]
+    pub fn uv_offset(&self, atlas: &Atlas, id: &ResourcePath) -> Option<(f32, f32)> {
+        let slot = self.slots.read().get(id)?.clone();
+        let uv = *atlas.uv_map.read().get(&slot)?;
+
+        Some(offset(uv, atlas.size()))
+    }
+
+    /// Forgets every skin, for when the atlas is cleared
+    pub fn clear(&self) {
+        self.slots.write().clear();
+        self.free.lock().clear();
+    }
+}
+
+fn offset(((min_x, min_y), _): UV, atlas_size: u32) -> (f32, f32) {
+    (min_x / atlas_size as f32, min_y / atlas_size as f32)
+}
+
+/// The region to [bake](crate::mc::entity::model::EntityModel::bake) a model drawn with skins of the size into,
+/// at the top left of the atlas
+#[must_use]
+pub fn skin_region(atlas: &Atlas, width: u32, height: u32) -> UV {
+    let size = atlas.size() as f32;
+
+    ((0.0, 0.0), (width as f32 / size, height as f32 / size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::offset;
+
+    #[test]
+    fn offsets_are_relative_to_the_atlas() {
+        let uv = ((64.0, 128.0), (128.0, 192.0));
+
+        assert_eq!(offset(uv, 256), (0.25, 0.5));
+        //The atlas grew, so the same skin is a smaller part of it
+        assert_eq!(offset(uv, 512), (0.125, 0.25));
+    }
+}
@@ -0,0 +1,280 @@
+//! # Instance streaming
+//!
+//! [EntityInstances] keep buffers of their own, which is fine for a few kinds of entities but means a bind group
+//! switch for every draw. When there are thousands of entities whose transforms change every frame, they're better
+//! streamed with an [InstanceStream] instead, see [crate::render::pipeline::entity::EntityPipeline::stream_instances].
+//!
+//! Each frame, the instances of every model are staged on the CPU, sorted by texture and then by model so that the
+//! draws which share a texture are next to each other, and written into one set of buffers, so the whole frame is
+//! drawn with a single `entity_instances` bind group. The buffers are a ring of [STREAM_FRAMES] sets, so a frame
+//! never writes the buffers the GPU may still be reading for an earlier one, and only the ranges of a buffer which
+//! changed since it was last written are uploaded again. Instances which are out of view are left out before
+//! they're staged, and the rest are drawn with the LOD of their model for their distance, see
+//! [crate::mc::entity::culling].
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use rayon::prelude::*;
+
+use crate::mc::entity::culling::{cull, EntityCamera};
+use crate::mc::entity::{instance_data, Entity, EntityInstanceData, EntityInstanceTransforms};
+use crate::render::pipeline::CachedBinding;
+use crate::WmRenderer;
+
+/// How many frames of buffers the ring has
+pub const STREAM_FRAMES: usize = 3;
+
+/// The granularity the contents of the buffers are compared with, in bytes
+const DIRTY_GRANULARITY: usize = 256;
+
+/// The smallest the buffers are made, in bytes
+const MIN_BUFFER_SIZE: u64 = 64 * 1024;
+
+/// The instances of one model in a [StreamedFrame]
+#[derive(Clone)]
+pub struct StreamBatch {
+    pub entity: Arc<Entity>,
+    /// The `instance_index`es of the instances
+    pub instances: Range<u32>,
+}
+
+/// The instances of a frame which were written into the ring, drawn in the order of the batches
+#[derive(Clone)]
+pub struct StreamedFrame {
+    /// The `entity_instances` bind group of every batch
+    pub bind_group: Arc<wgpu::BindGroup>,
+    pub batches: Vec<StreamBatch>,
+}
+
+/// A storage buffer of the ring, along with what was last written to it
+struct StreamBuffer {
+    buffer: Arc<wgpu::Buffer>,
+    contents: Vec<u8>,
+}
+
+impl StreamBuffer {
+    fn new(wm: &WmRenderer, size: usize) -> Self {
+        let size = (size as u64).next_power_of_two().max(MIN_BUFFER_SIZE);
+
+        Self {
+            buffer: Arc::new(wm.wgpu_state.device.create_buffer(&wgpu::BufferDescriptor {
+                label: None,
+                size,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })),
+            contents: Vec::new(),
+        }
+    }
+
+    /// Uploads the ranges of the bytes which changed, making the buffer again if they don't fit. Returns whether it
+    /// was made again, so its bind group has to be as well.
+    fn write(buffer: &mut Option<Self>, wm: &WmRenderer, bytes: &[u8]) -> bool {
+        let recreated = match buffer {
+            Some(buffer) if buffer.buffer.size() >= bytes.len() as u64 => false,
+            _ => {
+                *buffer = Some(Self::new(wm, bytes.len()));
+                true
+            }
+        };
+
+        let buffer = buffer.as_mut().unwrap();
+
+        for range in dirty_ranges(&buffer.contents, bytes, DIRTY_GRANULARITY) {
+            wm.wgpu_state.queue.write_buffer(
+                &buffer.buffer,
+                range.start as u64,
+                &bytes[range.clone()],
+            );
+        }
+
+        buffer.contents.clear();
+        buffer.contents.extend_from_slice(bytes);
+
+        recreated
+    }
+}
+
+/// One frame of the ring
+#[derive(Default)]
+struct StreamSlot {
+    instance_data: Option<StreamBuffer>,
+    transforms: Option<StreamBuffer>,
+    tints: Option<StreamBuffer>,
+    bind_group: Option<Arc<wgpu::BindGroup>>,
+}
+
+#[derive(Default)]
+struct StreamState {
+    slots: Vec<StreamSlot>,
+    next: usize,
+}
+
+/// The ring of buffers the entity instances of every frame are streamed through, see [crate::mc::entity::stream]
+#[derive(Default)]
+pub struct InstanceStream {
+    state: Mutex<StreamState>,
+}
+
+impl InstanceStream {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes the instances of each model into the next buffers of the ring. Returns [None] if there aren't any
+    /// instances, as storage buffers can't be empty.
+    pub fn upload<'a>(
+        &self,
+        wm: &WmRenderer,
+        camera: Option<&EntityCamera>,
+        models: impl IntoIterator<Item = (Arc<Entity>, &'a [EntityInstanceTransforms])>,
+    ) -> Option<StreamedFrame> {
+        let mut models: Vec<(Arc<Entity>, Vec<&EntityInstanceTransforms>)> = models
+            .into_iter()
+            .flat_map(|(entity, instances)| cull(camera, &entity, instances))
+            .filter(|(_, instances)| !instances.is_empty())
+            .collect();
+
+        if models.is_empty() {
+            return None;
+        }
+
+        //Draws of the same texture, and then of the same model, end up next to each other
+        models.sort_by_key(|(entity, _)| {
+            (
+                Arc::as_ptr(&entity.texture) as usize,
+                Arc::as_ptr(entity) as usize,
+            )
+        });
+
+        let mut batches: Vec<StreamBatch> = Vec::new();
+        let mut instances: Vec<EntityInstanceData> = Vec::new();
+        let mut first_part = 0;
+
+        for (entity, transforms) in &models {
+            let parts = entity.parts.len() as u32;
+            let first_instance = instances.len() as u32;
+
+            instances.extend(instance_data(transforms.iter().copied(), parts, first_part));
+            first_part += parts * transforms.len() as u32;
+
+            let end = instances.len() as u32;
+
+            match batches.last_mut() {
+                Some(batch) if Arc::ptr_eq(&batch.entity, entity) => batch.instances.end = end,
+                _ => batches.push(StreamBatch {
+                    entity: entity.clone(),
+                    instances: first_instance..end,
+                }),
+            }
+        }
+
+        //Posing the parts is most of the work, so it's spread over the threads
+        let matrices: Vec<[[f32; 4]; 4]> = models
+            .par_iter()
+            .flat_map_iter(|(entity, transforms)| {
+                transforms
+                    .iter()
+                    .flat_map(|transforms| transforms.get_matrices(entity))
+            })
+            .collect();
+        let tints: Vec<[f32; 4]> = models
+            .iter()
+            .flat_map(|(entity, transforms)| {
+                transforms
+                    .iter()
+                    .flat_map(|transforms| transforms.get_tints(entity))
+            })
+            .collect();
+
+        let mut state = self.state.lock();
+        let index = state.next;
+        state.next = (index + 1) % STREAM_FRAMES;
+
+        if state.slots.len() < STREAM_FRAMES {
+            state.slots.resize_with(STREAM_FRAMES, StreamSlot::default);
+        }
+
+        let slot = &mut state.slots[index];
+
+        let instances_recreated = StreamBuffer::write(
+            &mut slot.instance_data,
+            wm,
+            bytemuck::cast_slice(&instances),
+        );
+        let transforms_recreated =
+            StreamBuffer::write(&mut slot.transforms, wm, bytemuck::cast_slice(&matrices));
+        let tints_recreated =
+            StreamBuffer::write(&mut slot.tints, wm, bytemuck::cast_slice(&tints));
+
+        if instances_recreated
+            || transforms_recreated
+            || tints_recreated
+            || slot.bind_group.is_none()
+        {
+            slot.bind_group = Some(wm.pipelines.load().bind_group(
+                &wm.wgpu_state.device,
+                "entity_instances",
+                &[
+                    CachedBinding::Buffer(slot.instance_data.as_ref().unwrap().buffer.clone()),
+                    CachedBinding::Buffer(slot.transforms.as_ref().unwrap().buffer.clone()),
+                    CachedBinding::Buffer(slot.tints.as_ref().unwrap().buffer.clone()),
+                ],
+            ));
+        }
+
+        Some(StreamedFrame {
+            bind_group: slot.bind_group.clone().unwrap(),
+            batches,
+        })
+    }
+
+    /// Drops the buffers of the ring, for when the bind group layouts are made again
+    pub fn clear(&self) {
+        *self.state.lock() = StreamState::default();
+    }
+}
+
+/// The ranges of the new contents of a buffer which differ from the old, compared a `granularity` of bytes at a time
+/// and merged where they touch. Anything past the end of the old contents is dirty.
+fn dirty_ranges(old: &[u8], new: &[u8], granularity: usize) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+
+    for (index, chunk) in new.chunks(granularity).enumerate() {
+        let start = index * granularity;
+        let end = start + chunk.len();
+
+        if old.get(start..end) == Some(chunk) {
+            continue;
+        }
+
+        match ranges.last_mut() {
+            Some(range) if range.end == start => range.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::dirty_ranges;
+
+    #[test]
+    fn only_changed_ranges_are_dirty() {
+        let old = [0u8; 16];
+        let mut new = [0u8; 20];
+        new[1] = 1;
+        new[5] = 1;
+        new[13] = 1;
+
+        //The first two chunks touch, and the last is past the end of the old contents
+        assert_eq!(dirty_ranges(&old, &new, 4), vec![0..8, 12..20]);
+        assert!(dirty_ranges(&old, &old, 4).is_empty());
+        assert_eq!(dirty_ranges(&[], &old, 4), vec![0..16]);
+    }
+}
@@ -0,0 +1,615 @@
+//! # Items
+//!
+//! Item models are read from `<namespace>:models/item/<item>.json`. Most of them have `item/generated` as their
+//! parent, whose root is Minecraft's built-in `builtin/generated`: a sprite for each of their `layerN` textures,
+//! stacked in order, with faces along the edges of its opaque pixels like vanilla's `ItemModelGenerator`. Block items use the model of their block instead, with its elements. Both are baked
+//! against the block atlas, which Minecraft also keeps the item textures in.
+//!
+//! The baked vertices are from 0 to 1 like those of blocks, and [ItemModel::transformed] applies the display
+//! transform of a context like `gui` for inventory slots or `ground` for dropped items.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cgmath::{Deg, InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+use image::{GenericImageView, RgbaImage};
+use indexmap::IndexMap;
+use minecraft_assets::schemas;
+use serde_derive::Deserialize;
+
+use crate::mc::block::{
+    allocate_textures, bake_element, elements_render_type, is_builtin, resolve_model,
+    BlockMeshVertex, MeshBakeError, RenderType,
+};
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::render::atlas::{texture_file, Atlas};
+
+/// How an item model is placed in a context like `gui`, `ground` or `firstperson_righthand`, the `display` of the
+/// model JSON
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+pub struct ItemTransform {
+    /// Rotation around the x, y and z axes in degrees, applied in that order
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    /// In 16ths of a block
+    #[serde(default)]
+    pub translation: [f32; 3],
+    #[serde(default = "ItemTransform::default_scale")]
+    pub scale: [f32; 3],
+}
+
+impl Default for ItemTransform {
+    fn default() -> Self {
+        Self {
+            rotation: [0.0; 3],
+            translation: [0.0; 3],
+            scale: Self::default_scale(),
+        }
+    }
+}
+
+impl ItemTransform {
+    fn default_scale() -> [f32; 3] {
+        [1.0; 3]
+    }
+
+    /// The transform of positions from 0 to 1, which ends up centered on the origin the same way Minecraft's
+    /// `ItemRenderer` does. Model x coordinates are mirrored (see [crate::mc::block]), which flips the direction of
+    /// the translation along x and of the rotations around y and z.
+    #[must_use]
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let [translation_x, translation_y, translation_z] = self.translation;
+        let [rotation_x, rotation_y, rotation_z] = self.rotation;
+        let [scale_x, scale_y, scale_z] = self.scale;
+
+        Matrix4::from_translation(Vector3::new(
+            -translation_x / 16.0,
+            translation_y / 16.0,
+            translation_z / 16.0,
+        )) * Matrix4::from_angle_x(Deg(rotation_x))
+            * Matrix4::from_angle_y(Deg(-rotation_y))
+            * Matrix4::from_angle_z(Deg(-rotation_z))
+            * Matrix4::from_nonuniform_scale(scale_x, scale_y, scale_z)
+            * Matrix4::from_translation(Vector3::new(-0.5, -0.5, -0.5))
+    }
+}
+
+/// A baked item model
+#[derive(Debug)]
+pub struct ItemModel {
+    /// Every face of the model, from 0 to 1 like blocks, before any display transform
+    pub vertices: Vec<BlockMeshVertex>,
+    /// The most transparent render type of any of the textures of the model
+    pub render_type: RenderType,
+    /// Whether the model is a flat sprite of `builtin/generated`, which Minecraft lights from the front in GUIs
+    /// rather than like a block
+    pub generated: bool,
+    /// The display transforms of the model by context, inherited from its parents
+    pub display: HashMap<String, ItemTransform>,
+}
+
+impl ItemModel {
+    /// The vertices with the display transform of the context applied, centered on the origin. Contexts the model
+    /// has no transform for get the identity transform.
+    #[must_use]
+    pub fn transformed(&self, context: &str) -> Vec<BlockMeshVertex> {
+        let matrix = self
+            .display
+            .get(context)
+            .copied()
+            .unwrap_or_default()
+            .matrix();
+
+        self.vertices
+            .iter()
+            .map(|vertex| {
+                let [x, y, z] = vertex.position;
+                let [normal_x, normal_y, normal_z, normal_w] = vertex.normal;
+
+                let normal = (matrix * Vector4::new(normal_x, normal_y, normal_z, 0.0))
+                    .truncate()
+                    .normalize();
+
+                BlockMeshVertex {
+                    position: (matrix * Vector4::new(x, y, z, 1.0)).truncate().into(),
+                    normal: [normal.x, normal.y, normal.z, normal_w],
+                    ..*vertex
+                }
+            })
+            .collect()
+    }
+
+    /// The vertices as they're drawn in an inventory slot
+    #[must_use]
+    pub fn gui(&self) -> Vec<BlockMeshVertex> {
+        self.transformed("gui")
+    }
+
+    /// The vertices as they're drawn for an item entity on the ground
+    #[must_use]
+    pub fn ground(&self) -> Vec<BlockMeshVertex> {
+        self.transformed("ground")
+    }
+}
+
+/// The parts of an item model JSON which [schemas::Model] doesn't resolve
+#[derive(Deserialize)]
+struct ItemModelJson {
+    parent: Option<String>,
+    #[serde(default)]
+    display: HashMap<String, ItemTransform>,
+}
+
+/// Walks the parents of the model, collecting the display transforms. The transforms of a model win over those of
+/// its parents. Also returns whether the root of the model is `builtin/generated`.
+fn display_transforms(
+    model_path: &ResourcePath,
+    resource_provider: &dyn ResourceProvider,
+) -> Result<(HashMap<String, ItemTransform>, bool), MeshBakeError> {
+    let mut display = HashMap::new();
+    let mut path = model_path.clone();
+
+    loop {
+        let json: ItemModelJson = serde_json::from_str(
+            &resource_provider
+                .get_string(&path)
+                .ok_or_else(|| MeshBakeError::UnresolvedResourcePath(path.clone()))?,
+        )
+        .map_err(MeshBakeError::JsonError)?;
+
+        for (context, transform) in json.display {
+            display.entry(context).or_insert(transform);
+        }
+
+        match json.parent {
+            None => return Ok((display, false)),
+            Some(parent) if is_builtin(&parent) => {
+                return Ok((display, parent.ends_with("builtin/generated")))
+            }
+            Some(parent) => {
+                path = ResourcePath::from(&parent)
+                    .prepend("models/")
+                    .append(".json")
+            }
+        }
+    }
+}
+
+/// A side of the pixels of a sprite, which `ItemModelGenerator` gives a face where the pixel next to it is
+/// transparent
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum EdgeSide {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl EdgeSide {
+    const ALL: [EdgeSide; 4] = [Self::Up, Self::Down, Self::Left, Self::Right];
+
+    /// The pixel next to a pixel on this side, y grows downwards like in the image
+    fn offset(self) -> (i64, i64) {
+        match self {
+            Self::Up => (0, -1),
+            Self::Down => (0, 1),
+            Self::Left => (-1, 0),
+            Self::Right => (1, 0),
+        }
+    }
+
+    fn is_vertical(self) -> bool {
+        matches!(self, Self::Up | Self::Down)
+    }
+
+    /// The face of the element, the left of the sprite faces east since it's looked at from the south
+    fn face(self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Down => "down",
+            Self::Left => "east",
+            Self::Right => "west",
+        }
+    }
+}
+
+/// Pixels facing the same [EdgeSide] on the same row (or column, for left and right), which get one face
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct EdgeSpan {
+    side: EdgeSide,
+    /// The first and last pixel along the row or column
+    min: u32,
+    max: u32,
+    /// The row or column
+    anchor: u32,
+}
+
+/// The spans of the opaque pixels of the sprite with a transparent pixel or the edge of the sprite on one of their
+/// sides, in the order `ItemModelGenerator` finds them. Like there, pixels facing the same way on the same row or
+/// column are one span even if there are gaps between them.
+fn edge_spans(sprite: &RgbaImage) -> Vec<EdgeSpan> {
+    let transparent = |x: i64, y: i64| {
+        x < 0
+            || y < 0
+            || x >= sprite.width() as i64
+            || y >= sprite.height() as i64
+            || sprite.get_pixel(x as u32, y as u32)[3] == 0
+    };
+
+    let mut spans: Vec<EdgeSpan> = Vec::new();
+
+    for y in 0..sprite.height() {
+        for x in 0..sprite.width() {
+            if transparent(x as i64, y as i64) {
+                continue;
+            }
+
+            for side in EdgeSide::ALL {
+                let (offset_x, offset_y) = side.offset();
+
+                if !transparent(x as i64 + offset_x, y as i64 + offset_y) {
+                    continue;
+                }
+
+                let (anchor, along) = if side.is_vertical() { (y, x) } else { (x, y) };
+
+                match spans
+                    .iter_mut()
+                    .find(|span| span.side == side && span.anchor == anchor)
+                {
+                    Some(span) => {
+                        span.min = span.min.min(along);
+                        span.max = span.max.max(along);
+                    }
+                    None => spans.push(EdgeSpan {
+                        side,
+                        min: along,
+                        max: along,
+                        anchor,
+                    }),
+                }
+            }
+        }
+    }
+
+    spans
+}
+
+/// The element of `ItemModelGenerator` for the span of a sprite of the size, a face one pixel deep with the texture
+/// of the pixels along the span
+fn edge_element(
+    span: &EdgeSpan,
+    [width, height]: [u32; 2],
+    texture: &str,
+    layer: usize,
+) -> serde_json::Value {
+    let (min, max, anchor) = (span.min as f32, span.max as f32, span.anchor as f32);
+    let (pixel_width, pixel_height) = (16.0 / width as f32, 16.0 / height as f32);
+
+    //The corners of the face in pixels of the sprite, and its texture coordinates, the same as in vanilla
+    let ([x1, y1, x2, y2], uv) = match span.side {
+        EdgeSide::Up => (
+            [min, anchor, max + 1.0, anchor],
+            [min, anchor, max + 1.0, anchor + 1.0],
+        ),
+        EdgeSide::Down => (
+            [min, anchor + 1.0, max + 1.0, anchor + 1.0],
+            [min, anchor, max + 1.0, anchor + 1.0],
+        ),
+        EdgeSide::Left => (
+            [anchor, min, anchor, max + 1.0],
+            [anchor, max + 1.0, anchor + 1.0, min],
+        ),
+        EdgeSide::Right => (
+            [anchor + 1.0, min, anchor + 1.0, max + 1.0],
+            [anchor, max + 1.0, anchor + 1.0, min],
+        ),
+    };
+
+    let [x1, x2] = [x1 * pixel_width, x2 * pixel_width];
+    //Model y grows upwards
+    let [y1, y2] = [16.0 - y1 * pixel_height, 16.0 - y2 * pixel_height];
+
+    serde_json::json!({
+        "from": [x1.min(x2), y1.min(y2), 7.5],
+        "to": [x1.max(x2), y1.max(y2), 8.5],
+        "faces": {
+            span.side.face(): {
+                "texture": texture,
+                "tintindex": layer,
+                "uv": [
+                    uv[0] * pixel_width,
+                    uv[1] * pixel_height,
+                    uv[2] * pixel_width,
+                    uv[3] * pixel_height,
+                ],
+            },
+        },
+    })
+}
+
+/// The first frame of the texture, or [None] if it can't be read
+fn first_frame(
+    texture: &ResourcePath,
+    resource_provider: &dyn ResourceProvider,
+) -> Option<RgbaImage> {
+    let file = texture_file(texture);
+    let image = image::load_from_memory(&resource_provider.get_bytes(&file)?)
+        .ok()?
+        .to_rgba8();
+
+    //Animated textures are a strip of square frames
+    let animated = resource_provider
+        .get_bytes(&file.append(".mcmeta"))
+        .is_some();
+    let height = if animated {
+        image.width().min(image.height())
+    } else {
+        image.height()
+    };
+
+    Some(image.view(0, 0, image.width(), height).to_image())
+}
+
+/// The elements of a `builtin/generated` model like vanilla's `ItemModelGenerator`: a sprite for each layer, tinted
+/// by the index of its layer, with the edges of its opaque pixels as faces of their own so that it looks one pixel
+/// thick from the side
+fn generated_elements(
+    model: &schemas::Model,
+    resource_provider: &dyn ResourceProvider,
+) -> Result<Vec<schemas::models::Element>, MeshBakeError> {
+    let textures = match &model.textures {
+        None => return Ok(Vec::new()),
+        Some(textures) => textures,
+    };
+
+    (0..)
+        .map_while(|layer| Some((layer, textures.get(&format!("layer{layer}"))?)))
+        .flat_map(|(layer, texture)| {
+            let face = serde_json::json!({ "texture": &texture.0, "tintindex": layer });
+
+            let sprite = serde_json::json!({
+                "from": [0.0, 0.0, 7.5],
+                "to": [16.0, 16.0, 8.5],
+                "faces": { "north": face, "south": face },
+            });
+
+            //Without the pixels of the texture, the layer is a flat sprite
+            let edges: Vec<serde_json::Value> =
+                first_frame(&(&texture.0).into(), resource_provider)
+                    .map(|frame| {
+                        edge_spans(&frame)
+                            .iter()
+                            .map(|span| {
+                                edge_element(
+                                    span,
+                                    [frame.width(), frame.height()],
+                                    &texture.0,
+                                    layer,
+                                )
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+            [sprite].into_iter().chain(edges)
+        })
+        .map(|element| serde_json::from_value(element).map_err(MeshBakeError::JsonError))
+        .collect()
+}
+
+/// Bakes the item model at the path, like `minecraft:models/item/stick.json`
+pub fn bake_item(
+    model_path: &ResourcePath,
+    resource_provider: &dyn ResourceProvider,
+    block_atlas: &Atlas,
+) -> Result<ItemModel, MeshBakeError> {
+    let (display, generated) = display_transforms(model_path, resource_provider)?;
+
+    let model: schemas::Model = resolve_model(
+        serde_json::from_str(
+            &resource_provider
+                .get_string(model_path)
+                .ok_or_else(|| MeshBakeError::UnresolvedResourcePath(model_path.clone()))?,
+        )
+        .map_err(MeshBakeError::JsonError)?,
+        resource_provider,
+    );
+
+    allocate_textures(&model, resource_provider, block_atlas)?;
+
+    let elements = if generated {
+        generated_elements(&model, resource_provider)?
+    } else {
+        model.elements.unwrap_or_default()
+    };
+
+    let vertices = elements
+        .iter()
+        .map(|element| {
+            bake_element(
+                element,
+                Matrix4::identity(),
+                false,
+                None,
+                resource_provider,
+                block_atlas,
+            )
+        })
+        .collect::<Result<Vec<_>, MeshBakeError>>()?
+        .into_iter()
+        .flat_map(|faces| {
+            [
+                faces.north,
+                faces.east,
+                faces.south,
+                faces.west,
+                faces.up,
+                faces.down,
+            ]
+        })
+        .flatten()
+        .flatten()
+        .collect();
+
+    Ok(ItemModel {
+        vertices,
+        render_type: elements_render_type(&elements, block_atlas),
+        generated,
+        display,
+    })
+}
+
+/// The baked models of every item, by the item's name like `minecraft:stick`
+#[derive(Debug, Default)]
+pub struct ItemManager {
+    pub items: IndexMap<String, Arc<ItemModel>>,
+}
+
+impl ItemManager {
+    #[must_use]
+    pub fn get(&self, item: &str) -> Option<Arc<ItemModel>> {
+        self.items.get(item).cloned()
+    }
+
+    /// Bakes the model of each item from `<namespace>:models/item/<item>.json`, replacing the ones which were
+    /// already baked. Items whose model can't be baked are logged and skipped. If the block atlas grows in the
+    /// meantime, the items are baked again so that none of them are left with stale UVs, see
+    /// [Atlas::generation]. The caller uploads the atlas afterwards.
+    pub fn bake_items(
+        &mut self,
+        items: impl IntoIterator<Item = impl AsRef<str>>,
+        resource_provider: &dyn ResourceProvider,
+        block_atlas: &Atlas,
+    ) {
+        let items: Vec<String> = items
+            .into_iter()
+            .map(|item| String::from(item.as_ref()))
+            .collect();
+
+        loop {
+            let generation = block_atlas.generation();
+
+            for item in &items {
+                let model_path = ResourcePath::from(&item[..])
+                    .prepend("models/item/")
+                    .append(".json");
+
+                match bake_item(&model_path, resource_provider, block_atlas) {
+                    Ok(model) => {
+                        self.items.insert(item.clone(), Arc::new(model));
+                    }
+                    Err(error) => log::error!("Couldn't bake the model of item {item}: {error:?}"),
+                }
+            }
+
+            //Every texture is in the atlas by now, so baking them again doesn't grow it any further
+            if block_atlas.generation() == generation {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector4};
+    use image::{Rgba, RgbaImage};
+
+    use super::{edge_element, edge_spans, EdgeSide, EdgeSpan, ItemTransform};
+
+    #[test]
+    fn edges_of_opaque_pixels_are_spans() {
+        //An L of opaque pixels in a 4x4 sprite
+        let sprite = RgbaImage::from_fn(4, 4, |x, y| {
+            Rgba([0, 0, 0, if x == 1 || (y == 3 && x < 3) { 255 } else { 0 }])
+        });
+
+        let span = |side, min, max, anchor| EdgeSpan {
+            side,
+            min,
+            max,
+            anchor,
+        };
+
+        assert_eq!(
+            edge_spans(&sprite),
+            [
+                span(EdgeSide::Up, 1, 1, 0),
+                span(EdgeSide::Left, 0, 2, 1),
+                span(EdgeSide::Right, 0, 2, 1),
+                span(EdgeSide::Up, 0, 2, 3),
+                span(EdgeSide::Down, 0, 2, 3),
+                span(EdgeSide::Left, 3, 3, 0),
+                span(EdgeSide::Right, 3, 3, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn edge_elements_match_item_model_generator() {
+        let element = |side| {
+            edge_element(
+                &EdgeSpan {
+                    side,
+                    min: 0,
+                    max: 0,
+                    anchor: 0,
+                },
+                [2, 2],
+                "minecraft:item/stick",
+                0,
+            )
+        };
+
+        //The top left pixel of a 2x2 sprite
+        let up = element(EdgeSide::Up);
+        assert_eq!(up["from"], serde_json::json!([0.0, 16.0, 7.5]));
+        assert_eq!(up["to"], serde_json::json!([8.0, 16.0, 8.5]));
+        assert_eq!(
+            up["faces"]["up"]["uv"],
+            serde_json::json!([0.0, 0.0, 8.0, 8.0])
+        );
+
+        let left = element(EdgeSide::Left);
+        assert_eq!(left["from"], serde_json::json!([0.0, 8.0, 7.5]));
+        assert_eq!(left["to"], serde_json::json!([0.0, 16.0, 8.5]));
+        assert_eq!(
+            left["faces"]["east"]["uv"],
+            serde_json::json!([0.0, 8.0, 8.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn display_transforms_are_centered_on_the_origin() {
+        let matrix = ItemTransform::default().matrix();
+        assert_eq!(
+            matrix * Vector4::new(0.5, 0.5, 0.5, 1.0),
+            Vector4::new(0.0, 0.0, 0.0, 1.0)
+        );
+
+        //Minecraft's gui transform of block items
+        let gui = ItemTransform {
+            rotation: [30.0, 225.0, 0.0],
+            translation: [0.0; 3],
+            scale: [0.625; 3],
+        };
+
+        let corner = gui.matrix() * Vector4::new(1.0, 1.0, 1.0, 1.0);
+        let length = corner.truncate().magnitude();
+
+        //Only the scale changes the distance to the center
+        assert!((length - 0.625 * 0.75f32.sqrt()).abs() < 1e-5);
+
+        let translated = ItemTransform {
+            translation: [16.0, 0.0, 0.0],
+            ..ItemTransform::default()
+        };
+
+        //Model x coordinates are mirrored
+        assert_eq!(
+            translated.matrix() * Vector4::new(0.5, 0.5, 0.5, 1.0),
+            Vector4::new(-1.0, 0.0, 0.0, 1.0)
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! # Item entities
+//!
+//! Dropped items, and anything else the frontend draws as an item in the world like the items in item frames, are
+//! [ItemEntityInstance]s, set every frame with [ItemEntities::set_instances]. Each baked [ItemModel] is turned into
+//! an [Entity] with a single part, textured with the block atlas, and [ItemEntities::instances] draws all the
+//! instances of an item in one instanced draw of the [crate::render::pipeline::entity::EntityPipeline].
+//!
+//! Dropped items bob up and down and spin like vanilla's `ItemEntityRenderer` does, items which are placed, like
+//! those in item frames, are drawn still with their yaw.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use parking_lot::{Mutex, RwLock};
+
+use crate::mc::entity::{
+    Entity, EntityInstanceTransforms, EntityInstances, PartTransform, Position,
+};
+use crate::mc::item::ItemModel;
+use crate::render::atlas::AtlasKind;
+use crate::render::entity::EntityVertex;
+use crate::texture::BindableTexture;
+use crate::WmRenderer;
+
+/// The display context item entities are drawn with
+pub const GROUND_CONTEXT: &str = "ground";
+
+#[derive(Clone, Debug)]
+pub struct ItemEntityInstance {
+    /// Like `minecraft:stick`, see [crate::mc::item::ItemManager]
+    pub item: String,
+    pub position: Position,
+    /// In ticks, interpolated with the partial tick of the frame. Drives the bobbing and spinning.
+    pub age: f32,
+    /// Keeps items which were dropped together from bobbing in step, like vanilla's `uniqueOffset`, in radians
+    pub bob_offset: f32,
+    /// Whether the item bobs and spins like a dropped item, or is drawn still like in an item frame
+    pub floating: bool,
+    /// In degrees, added to the spin of floating items
+    pub yaw: f32,
+}
+
+impl ItemEntityInstance {
+    fn transforms(&self, model: &ItemModel) -> EntityInstanceTransforms {
+        let (height, yaw) = if self.floating {
+            //Items sit on the ground a quarter of their height above their position
+            let scale = model
+                .display
+                .get(GROUND_CONTEXT)
+                .map_or(1.0, |transform| transform.scale[1]);
+
+            (
+                bob(self.age, self.bob_offset) + 0.25 * scale,
+                self.yaw + spin(self.age, self.bob_offset),
+            )
+        } else {
+            (0.0, self.yaw)
+        };
+
+        EntityInstanceTransforms {
+            position: self.position,
+            looking_yaw: yaw,
+            uv_offset: (0.0, 0.0),
+            overlay: [0.0; 4],
+            part_transforms: vec![PartTransform {
+                y: height,
+                ..PartTransform::identity()
+            }],
+            part_tints: Vec::new(),
+            attached_to: None,
+        }
+    }
+}
+
+/// How high a floating item is above the ground, from 0 to 0.2 blocks
+fn bob(age: f32, offset: f32) -> f32 {
+    (age / 10.0 + offset).sin() * 0.1 + 0.1
+}
+
+/// How far a floating item has spun, in degrees
+fn spin(age: f32, offset: f32) -> f32 {
+    (age / 20.0 + offset).to_degrees()
+}
+
+/// The model of the item as it's drawn in the display context, with a single part, or [None] if it has no faces
+pub(crate) fn item_entity(
+    wm: &WmRenderer,
+    item: &str,
+    model: &ItemModel,
+    context: &str,
+    texture: Arc<BindableTexture>,
+) -> Option<Entity> {
+    let vertices: Vec<EntityVertex> = model
+        .transformed(context)
+        .iter()
+        .map(|vertex| EntityVertex {
+            position: vertex.position,
+            tex_coords: vertex.tex_coords,
+            normal: [vertex.normal[0], vertex.normal[1], vertex.normal[2]],
+            part_id: 0,
+            emissive_tex_coords: vertex.emissive_tex_coords,
+        })
+        .collect();
+
+    if vertices.is_empty() {
+        return None;
+    }
+
+    //Baked against this texture of the block atlas, so it's baked again rather than following the atlas
+    Some(Entity::from_vertices(
+        item,
+        &vertices,
+        &wm.wgpu_state,
+        Arc::new(ArcSwap::new(texture)),
+    ))
+}
+
+/// The item entities of a frame, see [crate::mc::MinecraftState::item_entities]
+#[derive(Default)]
+pub struct ItemEntities {
+    instances: RwLock<Vec<ItemEntityInstance>>,
+    /// The model of each item which has been drawn, along with the baked model it was made from, so that it's made
+    /// again when the items are baked again or the block atlas grows
+    entities: RwLock<HashMap<String, (Arc<ItemModel>, Arc<Entity>)>>,
+    /// The instances of each item from the last frame, whose buffers are written again if the number of them
+    /// doesn't change
+    uploaded: Mutex<HashMap<String, Arc<EntityInstances>>>,
+}
+
+impl ItemEntities {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the item entities which are drawn
+    pub fn set_instances(&self, instances: Vec<ItemEntityInstance>) {
+        *self.instances.write() = instances;
+    }
+
+    /// The model of the item drawn on the ground along with its baked model, if the item has been baked
+    fn entity(&self, wm: &WmRenderer, item: &str) -> Option<(Arc<ItemModel>, Arc<Entity>)> {
+        let model = wm.mc.item_manager.read().get(item)?;
+        let texture = wm.mc.texture_manager.bindable_texture(AtlasKind::Block);
+
+        if let Some((baked, entity)) = self.entities.read().get(item) {
+            if Arc::ptr_eq(baked, &model) && Arc::ptr_eq(&entity.texture.load(), &texture) {
+                return Some((model, entity.clone()));
+            }
+        }
+
+        let entity = Arc::new(item_entity(wm, item, &model, GROUND_CONTEXT, texture)?);
+
+        self.entities
+            .write()
+            .insert(item.into(), (model.clone(), entity.clone()));
+
+        Some((model, entity))
+    }
+
+    /// Uploads the instances of every item entity for the [crate::render::pipeline::entity::EntityPipeline] to
+    /// draw, one [EntityInstances] per item. Items which haven't been baked are skipped.
+    pub fn instances(&self, wm: &WmRenderer) -> Vec<Arc<EntityInstances>> {
+        let mut items: HashMap<&str, Vec<&ItemEntityInstance>> = HashMap::new();
+        let instances = self.instances.read();
+
+        for instance in instances.iter() {
+            items.entry(&instance.item).or_default().push(instance);
+        }
+
+        let mut uploaded = self.uploaded.lock();
+        let mut current = HashMap::new();
+
+        for (item, instances) in items {
+            let (model, entity) = match self.entity(wm, item) {
+                Some(entity) => entity,
+                None => continue,
+            };
+
+            let previous = uploaded
+                .get(item)
+                .filter(|previous| Arc::ptr_eq(&previous.entity, &entity))
+                .and_then(|previous| previous.uploaded.read().clone());
+
+            let item_instances = EntityInstances {
+                entity,
+                instances: instances
+                    .iter()
+                    .map(|instance| instance.transforms(&model))
+                    .collect(),
+                uploaded: RwLock::new(previous),
+            };
+            item_instances.upload(wm);
+
+            current.insert(String::from(item), Arc::new(item_instances));
+        }
+
+        *uploaded = current;
+
+        uploaded.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bob, spin};
+
+    #[test]
+    fn floating_items_bob_above_the_ground() {
+        for age in 0..200 {
+            let height = bob(age as f32, 1.0);
+            assert!((0.0..=0.2).contains(&height));
+        }
+
+        //Half a turn every 20π ticks
+        let turn = spin(20.0 * std::f32::consts::PI, 0.0);
+        assert!((turn - 180.0).abs() < 1e-3);
+    }
+}
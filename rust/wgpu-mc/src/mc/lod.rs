@@ -0,0 +1,391 @@
+//! # Level of detail
+//!
+//! Chunks far away from the camera don't need every block to be meshed. Besides its full mesh, a [Chunk] can keep
+//! a heightmap shell for each [LodLevel]: the columns of the chunk are grouped into square cells, and only the top
+//! of the tallest column in each cell is meshed, together with the walls down to the neighbouring cells. Blocks
+//! which aren't cubes, like flowers or torches, are skipped.
+//!
+//! The shells are only baked while [crate::mc::MinecraftState::lod_distance] is set, which also decides which
+//! chunks are drawn with them.
+//!
+//! [Chunk]: crate::mc::chunk::Chunk
+
+use std::sync::Arc;
+
+use crate::mc::block::{
+    BlockMeshVertex, BlockModelFaces, ChunkBlockState, CubeOrComplexMesh, ModelMesh, NO_TINT,
+};
+use crate::mc::chunk::{
+    block_tint, chunk_origin, get_block, BlockStateProvider, ChunkPos, CHUNK_HEIGHT, CHUNK_WIDTH,
+};
+use crate::mc::BlockManager;
+use crate::render::lightmap::lightmap_coords;
+use crate::render::pipeline::Vertex;
+
+/// How coarse the heightmap shell of a chunk is
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum LodLevel {
+    /// Cells of 2x2 columns
+    Half,
+    /// Cells of 4x4 columns
+    Quarter,
+}
+
+impl LodLevel {
+    pub const ALL: [Self; 2] = [Self::Half, Self::Quarter];
+
+    /// The width of a cell in blocks
+    #[must_use]
+    pub fn scale(self) -> usize {
+        match self {
+            Self::Half => 2,
+            Self::Quarter => 4,
+        }
+    }
+
+    /// The level a chunk `distance` chunks away from the camera is drawn at, or [None] for its full mesh.
+    /// Chunks further away than `lod_distance` are drawn at [LodLevel::Half], and further than twice that at
+    /// [LodLevel::Quarter].
+    #[must_use]
+    pub fn for_distance(distance: u32, lod_distance: u32) -> Option<Self> {
+        if distance > lod_distance.saturating_mul(2) {
+            Some(Self::Quarter)
+        } else if distance > lod_distance {
+            Some(Self::Half)
+        } else {
+            None
+        }
+    }
+}
+
+/// The first cube of the block's models, which the shell borrows its faces from
+fn cube_faces(mesh: &ModelMesh) -> Option<&BlockModelFaces> {
+    mesh.models.iter().find_map(|(model, _)| match model {
+        CubeOrComplexMesh::Cube(faces) => Some(&**faces),
+        CubeOrComplexMesh::Complex(_) => None,
+    })
+}
+
+/// The top of a cell: its height, the block there and the biome color the block is tinted with
+type CellTop = (i16, Arc<ModelMesh>, Option<[f32; 3]>);
+
+/// The height of the tallest column in the cell starting at the absolute column (x, z), which is one above its
+/// topmost cube, and the block of that cube
+fn cell_top(
+    block_manager: &BlockManager,
+    provider: &impl BlockStateProvider,
+    x: i32,
+    z: i32,
+    scale: i32,
+) -> Option<CellTop> {
+    (0..scale)
+        .flat_map(|dx| (0..scale).map(move |dz| (x + dx, z + dz)))
+        .filter_map(|(x, z)| {
+            let height = provider
+                .column_height(x, z)
+                .unwrap_or(CHUNK_HEIGHT as i16)
+                .clamp(0, CHUNK_HEIGHT as i16);
+
+            (0..height).rev().find_map(|y| {
+                let state = provider.get_state(x, y, z);
+                let mesh = get_block(block_manager, state)?;
+                cube_faces(&mesh)?;
+
+                let tint = match state {
+                    ChunkBlockState::Air => None,
+                    ChunkBlockState::State(key) => {
+                        block_tint(block_manager, provider, key, x, y, z)
+                    }
+                };
+
+                Some((y + 1, mesh, tint))
+            })
+        })
+        .max_by_key(|(height, _)| *height)
+}
+
+/// Stretches a face of a unit cube across a cell, with the bottom of the face at `y` and the top `height` above it
+fn shell_vertex(
+    vertex: &BlockMeshVertex,
+    origin: [f32; 2],
+    scale: f32,
+    y: f32,
+    height: f32,
+    tint: Option<[f32; 3]>,
+) -> Vertex {
+    let [r, g, b] = match tint {
+        Some(tint) if vertex.tint_index != NO_TINT => tint,
+        _ => [1.0; 3],
+    };
+
+    Vertex {
+        position: [
+            origin[0] + vertex.position[0] * scale,
+            y + vertex.position[1] * height,
+            origin[1] + vertex.position[2] * scale,
+        ],
+        tex_coords: vertex.tex_coords,
+        //Light isn't sampled for the shell, distant terrain is lit by the sky
+        lightmap_coords: lightmap_coords(0, 15),
+        normal: vertex.normal,
+        color: [r, g, b, 1.0],
+        //Filled in for the whole face once it's baked
+        tangent: [0.0; 4],
+        uv_offset: vertex.animation_uv_offset,
+        emissive_tex_coords: vertex.emissive_tex_coords,
+        region: [0.0; 4],
+    }
+}
+
+/// Bakes the heightmap shell of the chunk, with chunk-local positions the same as [crate::mc::chunk::bake_layer].
+/// Cells along the border look at the neighbouring chunks, the same way faces are culled across chunks.
+pub fn bake_lod(
+    block_manager: &BlockManager,
+    provider: &impl BlockStateProvider,
+    pos: ChunkPos,
+    level: LodLevel,
+) -> Vec<Vertex> {
+    let [origin_x, origin_z] = match chunk_origin(pos) {
+        Some(origin) => origin,
+        None => {
+            log::error!("Chunk {pos:?} is out of the addressable range");
+            return Vec::new();
+        }
+    };
+
+    let scale = level.scale() as i32;
+    let cells = CHUNK_WIDTH as i32 / scale;
+
+    //The cells of this chunk, surrounded by one cell of each neighbour
+    let tops: Vec<Option<CellTop>> = (-1..=cells)
+        .flat_map(|cell_z| (-1..=cells).map(move |cell_x| (cell_x, cell_z)))
+        .map(|(cell_x, cell_z)| {
+            let x = origin_x + cell_x * scale;
+            let z = origin_z + cell_z * scale;
+
+            if !provider.is_chunk_loaded([
+                x.div_euclid(CHUNK_WIDTH as i32),
+                z.div_euclid(CHUNK_WIDTH as i32),
+            ]) {
+                return None;
+            }
+
+            cell_top(block_manager, provider, x, z, scale)
+        })
+        .collect();
+
+    let top = |cell_x: i32, cell_z: i32| &tops[((cell_z + 1) * (cells + 2) + cell_x + 1) as usize];
+    let height = |cell_x: i32, cell_z: i32| top(cell_x, cell_z).as_ref().map_or(0, |top| top.0);
+
+    let mut vertices = Vec::new();
+
+    for cell_z in 0..cells {
+        for cell_x in 0..cells {
+            let (cell_height, mesh, tint) = match top(cell_x, cell_z) {
+                None => continue,
+                Some(top) => top,
+            };
+
+            let faces = cube_faces(mesh).unwrap();
+            let origin = [(cell_x * scale) as f32, (cell_z * scale) as f32];
+
+            //The top face of the block sits at the top of the cell
+            if let Some(up) = &faces.up {
+                vertices.extend(up.iter().map(|vertex| {
+                    shell_vertex(
+                        vertex,
+                        origin,
+                        scale as f32,
+                        *cell_height as f32 - 1.0,
+                        1.0,
+                        *tint,
+                    )
+                }));
+            }
+
+            //Walls reach down to the top of each lower neighbour, in the order north, east, south, west
+            let walls = [
+                (&faces.north, [0, -1]),
+                (&faces.east, [1, 0]),
+                (&faces.south, [0, 1]),
+                (&faces.west, [-1, 0]),
+            ];
+
+            for (face, [dx, dz]) in walls {
+                let neighbour_height = height(cell_x + dx, cell_z + dz);
+
+                if let (Some(face), true) = (face, neighbour_height < *cell_height) {
+                    vertices.extend(face.iter().map(|vertex| {
+                        shell_vertex(
+                            vertex,
+                            origin,
+                            scale as f32,
+                            neighbour_height as f32,
+                            (*cell_height - neighbour_height) as f32,
+                            *tint,
+                        )
+                    }));
+                }
+            }
+        }
+    }
+
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use indexmap::IndexMap;
+
+    use super::{bake_lod, LodLevel};
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::{
+        BlockMeshVertex, BlockModelFaces, BlockstateKey, ChunkBlockState, CubeOrComplexMesh,
+        ModelMesh, RenderType, NO_EMISSIVE, NO_TINT,
+    };
+    use crate::mc::chunk::BlockStateProvider;
+    use crate::mc::{Block, BlockManager};
+
+    /// Contains the same block at each of the positions, and air everywhere else
+    #[derive(Debug)]
+    struct BlocksProvider {
+        positions: Vec<(i32, i16, i32)>,
+    }
+
+    impl BlockStateProvider for BlocksProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            if self.positions.contains(&(x, y, z)) {
+                ChunkBlockState::State(BlockstateKey {
+                    block: 0,
+                    augment: 0,
+                })
+            } else {
+                ChunkBlockState::Air
+            }
+        }
+
+        fn is_section_empty(&self, _index: usize) -> bool {
+            false
+        }
+    }
+
+    /// A face of the unit cube through the given corners
+    fn face(corners: [[f32; 3]; 4]) -> Option<[BlockMeshVertex; 6]> {
+        Some([0, 1, 2, 2, 3, 0].map(|corner| BlockMeshVertex {
+            position: corners[corner],
+            tex_coords: [0.0, 0.0],
+            normal: [0.0, 0.0, 0.0, 0.0],
+            animation_uv_offset: 0,
+            emissive_tex_coords: NO_EMISSIVE,
+            tint_index: NO_TINT,
+        }))
+    }
+
+    fn cube() -> BlockManager {
+        let faces = BlockModelFaces {
+            north: face([
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+            ]),
+            east: face([
+                [1.0, 0.0, 0.0],
+                [1.0, 0.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [1.0, 1.0, 0.0],
+            ]),
+            south: face([
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 1.0],
+                [1.0, 1.0, 1.0],
+                [0.0, 1.0, 1.0],
+            ]),
+            west: face([
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [0.0, 1.0, 1.0],
+                [0.0, 1.0, 0.0],
+            ]),
+            up: face([
+                [0.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [1.0, 1.0, 1.0],
+                [0.0, 1.0, 1.0],
+            ]),
+            down: face([
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 0.0, 1.0],
+                [0.0, 0.0, 1.0],
+            ]),
+            connected: Default::default(),
+        };
+
+        let mesh = Arc::new(ModelMesh {
+            models: vec![(CubeOrComplexMesh::Cube(Box::new(faces)), true)],
+            is_full_opaque_cube: true,
+            render_type: RenderType::Solid,
+            weights: vec![1],
+        });
+
+        BlockManager {
+            blocks: [(
+                "wgpu_mc:test".into(),
+                Block::Variants(IndexMap::from([("".into(), mesh)])),
+            )]
+            .into_iter()
+            .collect(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        }
+    }
+
+    #[test]
+    fn lod_level_grows_with_distance() {
+        assert_eq!(LodLevel::for_distance(8, 8), None);
+        assert_eq!(LodLevel::for_distance(9, 8), Some(LodLevel::Half));
+        assert_eq!(LodLevel::for_distance(16, 8), Some(LodLevel::Half));
+        assert_eq!(LodLevel::for_distance(17, 8), Some(LodLevel::Quarter));
+    }
+
+    #[test]
+    fn shell_covers_the_tallest_column_of_each_cell() {
+        //Both blocks are in the first cell of chunk [1, 0], the lower one is hidden by the taller column
+        let provider = BlocksProvider {
+            positions: vec![(17, 5, 1), (16, 2, 0)],
+        };
+
+        let vertices = bake_lod(&cube(), &provider, [1, 0], LodLevel::Half);
+
+        //The top and four walls
+        assert_eq!(vertices.len(), 5 * 6);
+
+        for vertex in &vertices[..6] {
+            assert_eq!(vertex.position[1], 6.0);
+            assert!(vertex.position[0] == 0.0 || vertex.position[0] == 2.0);
+            assert!(vertex.position[2] == 0.0 || vertex.position[2] == 2.0);
+        }
+
+        let (min_y, max_y) = vertices[6..]
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), vertex| {
+                (min.min(vertex.position[1]), max.max(vertex.position[1]))
+            });
+
+        assert_eq!((min_y, max_y), (0.0, 6.0));
+    }
+
+    #[test]
+    fn chunks_past_the_addressable_range_have_no_shell() {
+        let provider = BlocksProvider {
+            positions: vec![(0, 0, 0)],
+        };
+
+        assert!(bake_lod(&cube(), &provider, [i32::MAX, 0], LodLevel::Half).is_empty());
+        assert!(bake_lod(&cube(), &provider, [0, i32::MIN], LodLevel::Quarter).is_empty());
+    }
+}
@@ -1,38 +1,161 @@
 //! Rust implementations of minecraft concepts that are important to us.
 
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 
 use arc_swap::ArcSwap;
 use indexmap::map::IndexMap;
 use minecraft_assets::schemas;
-use parking_lot::RwLock;
-
-use crate::mc::chunk::ChunkManager;
+use parking_lot::{Mutex, RwLock};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::mc::biome::{vanilla_tint, BlockColors};
+use crate::mc::block_entity::BlockEntityRenderers;
+use crate::mc::chunk::{BlockStateProvider, ChunkManager};
+use crate::mc::ctm::ConnectedTextures;
+use crate::mc::entity::skin::Skins;
 use crate::mc::entity::Entity;
-use crate::mc::resource::ResourceProvider;
-use crate::render::atlas::{Atlas, TextureManager};
-use crate::render::pipeline::BLOCK_ATLAS;
+use crate::mc::item::ItemManager;
+use crate::mc::item_entity::ItemEntities;
+use crate::mc::multipart::{case_conditions, Condition};
+use crate::mc::reload::{ReloadListener, ReloadListeners, ReloadStage};
+use crate::mc::resource::{ResourcePackStack, ResourceProvider, ResourceReload};
+use crate::render::atlas::{
+    texture_file, Atlas, AtlasError, AtlasKind, AtlasRefresh, TextureManager,
+};
+use crate::render::clouds::{clouds_texture, Clouds};
+use crate::render::colormap::load_colormaps;
+use crate::render::font::{font_texture, Font};
+use crate::render::particle::ParticleManager;
+use crate::render::pipeline::beam::{beam_textures, BeamInstance};
+use crate::render::pipeline::block_breaking::{destroy_stage_texture, DESTROY_STAGES};
+use crate::render::pipeline::entity_shadow::{shadow_texture, EntityShadow, NameTag, ShadowQuad};
+use crate::render::sky::{sky_textures, SkyState};
+use crate::texture::BindableTexture;
 use crate::WmRenderer;
 
-use self::block::ModelMesh;
+use self::block::{BlockPos, BlockShape, BlockstateKey, ModelMesh};
 use self::resource::ResourcePath;
 
+pub mod biome;
 pub mod block;
+pub mod block_entity;
 pub mod chunk;
+pub mod ctm;
 pub mod entity;
+pub mod item;
+pub mod item_entity;
+pub mod lod;
+pub mod multipart;
+pub mod reload;
 pub mod resource;
+pub mod visibility;
 
 /// Take in a block name (not a [ResourcePath]!) and optionally a variant state key, e.g. "facing=north" and format it some way
 /// for example, `minecraft:anvil[facing=north]` or `Block{minecraft:anvil}[facing=north]`
 pub type BlockVariantFormatter = dyn Fn(&str, Option<&str>) -> String;
 
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct BlockManager {
     /// This maps block state keys to either a [VariantMesh] or a [Multipart] struct. How the keys are formatted
     /// is defined by the user of wgpu-mc. For example `Block{minecraft:anvil}[facing=west]` or `minecraft:anvil#facing=west`
     pub blocks: IndexMap<String, Block>,
+    /// Maps indices into [BlockManager::blocks] to their collision/selection shape. Blocks without an entry are
+    /// assumed to be a [BlockShape::FullCube]
+    pub shapes: HashMap<u16, BlockShape>,
+    /// The biome colormaps and which blocks are tinted with them, see [biome]
+    pub colors: BlockColors,
+}
+
+impl BlockManager {
+    pub fn get_shape(&self, block: u16) -> &BlockShape {
+        static FULL_CUBE: BlockShape = BlockShape::FullCube;
+
+        self.shapes.get(&block).unwrap_or(&FULL_CUBE)
+    }
+
+    /// Adds the block along with its shape, or replaces the one with the same name and keeps its index. Returns the
+    /// index, or [None] if there are already as many blocks as a [BlockstateKey] can refer to.
+    pub fn insert(&mut self, name: String, block: Block, shape: Option<BlockShape>) -> Option<u16> {
+        let (index, _) = self.blocks.insert_full(name, block);
+
+        let index = match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                //Only new blocks are past the end
+                self.blocks.pop();
+                return None;
+            }
+        };
+
+        //A re-baked block may have lost the shape it had before
+        match shape {
+            Some(shape) => self.shapes.insert(index, shape),
+            None => self.shapes.remove(&index),
+        };
+
+        Some(index)
+    }
+}
+
+/// How many of the blocks [MinecraftState::bake_blocks_with_progress] has baked so far
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub done: usize,
+    pub total: usize,
+}
+
+impl LoadProgress {
+    /// From 0 to 1, 1 if there's nothing to bake
+    #[must_use]
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done as f32 / self.total as f32
+        }
+    }
+}
+
+/// Blocks being baked on a loading thread, see [MinecraftState::bake_blocks_in_background]
+pub struct BlockBake {
+    done: Arc<AtomicUsize>,
+    total: usize,
+    thread: JoinHandle<()>,
+}
+
+impl BlockBake {
+    /// How many of the blocks have been baked so far. The atlas is still uploaded after the last one, so this can
+    /// be complete before [BlockBake::is_finished]
+    pub fn progress(&self) -> LoadProgress {
+        LoadProgress {
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total,
+        }
+    }
+
+    /// Whether the blocks are baked and can be looked up in the [BlockManager]
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits for the blocks to be baked
+    pub fn join(self) {
+        self.thread.join().unwrap();
+    }
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub enum Block {
     Multipart(Multipart),
     Variants(IndexMap<String, Arc<ModelMesh>>),
@@ -104,8 +227,14 @@ impl Block {
 }
 
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct Multipart {
     pub cases: Vec<schemas::blockstates::multipart::Case>,
+    /// The `when` of each of the cases, see [multipart]
+    pub conditions: Vec<Condition>,
     pub keys: RwLock<IndexMap<String, Arc<ModelMesh>>>,
 }
 
@@ -117,13 +246,17 @@ impl Multipart {
         resource_provider: &dyn ResourceProvider,
         block_atlas: &Atlas,
     ) -> Arc<ModelMesh> {
-        let apply_variants = self.cases.iter().filter_map(|case| {
-            if case.applies(key.clone()) {
-                Some(&case.apply)
-            } else {
-                None
-            }
-        });
+        let apply_variants =
+            self.cases
+                .iter()
+                .zip(&self.conditions)
+                .filter_map(|(case, condition)| {
+                    if condition.matches(key.clone()) {
+                        Some(&case.apply)
+                    } else {
+                        None
+                    }
+                });
 
         let mesh = ModelMesh::bake(apply_variants, resource_provider, block_atlas).unwrap();
 
@@ -145,18 +278,67 @@ pub struct BlockInstance {
 /// Minecraft-specific state and data structures go in here
 pub struct MinecraftState {
     pub sun_position: ArcSwap<f32>,
+    /// Whether chunks are baked with ambient occlusion (smooth lighting) or flat lighting. Only affects chunks
+    /// baked after it's changed.
+    pub smooth_lighting: ArcSwap<bool>,
+    /// How many chunks away from the camera chunks are drawn with their full mesh, further chunks are drawn with
+    /// heightmap shells, see [lod]. [None] disables the shells, and only affects chunks baked after it's changed.
+    pub lod_distance: ArcSwap<Option<u32>>,
 
     pub block_manager: RwLock<BlockManager>,
+    /// The baked item models, see [MinecraftState::bake_items]
+    pub item_manager: RwLock<ItemManager>,
+    /// The items drawn in the world, see [item_entity]
+    pub item_entities: ItemEntities,
 
     pub chunks: ChunkManager,
     pub entity_models: RwLock<Vec<Entity>>,
+    /// The skins of players and other entities with textures of their own, usually in the
+    /// [entity::EntityManager::player_texture_atlas]
+    pub skins: Skins,
+    /// How the block entities of each block are drawn, see [block_entity]
+    pub block_entity_renderers: BlockEntityRenderers,
 
     pub resource_provider: Arc<dyn ResourceProvider>,
 
     pub texture_manager: TextureManager,
 
+    /// The connected texture rules which blocks are baked with, see [ctm]. Only affects blocks baked after it's
+    /// changed.
+    pub connected_textures: ArcSwap<ConnectedTextures>,
+
     pub animated_block_buffer: ArcSwap<Option<wgpu::Buffer>>,
     pub animated_block_bind_group: ArcSwap<Option<wgpu::BindGroup>>,
+
+    /// See [crate::render::lightmap], created by [crate::WmRenderer::init]
+    pub lightmap: ArcSwap<Option<Arc<BindableTexture>>>,
+    /// The uploaded colormaps by name, see [crate::render::colormap]
+    pub colormaps: ArcSwap<HashMap<&'static str, Arc<ArcSwap<BindableTexture>>>>,
+
+    /// The blocks being broken, with their state and stage of cracks, see [MinecraftState::set_block_breaking]
+    pub block_breaking: ArcSwap<HashMap<BlockPos, (BlockstateKey, u8)>>,
+    /// The shadows of the entities, see [MinecraftState::set_entity_shadows]
+    pub entity_shadows: ArcSwap<Vec<ShadowQuad>>,
+    /// The name tags over the entities, see [MinecraftState::set_name_tags]
+    pub name_tags: ArcSwap<Vec<NameTag>>,
+    /// The font name tags are drawn with, loaded with the blocks
+    pub font: RwLock<Option<Font>>,
+    /// The leashes, fishing lines and beams drawn this frame, see [MinecraftState::set_beams]
+    pub beams: ArcSwap<Vec<BeamInstance>>,
+    /// The particles which are drawn, see [crate::render::particle]
+    pub particles: ParticleManager,
+    /// The sun, moon and stars, see [MinecraftState::set_sky]
+    pub sky: ArcSwap<SkyState>,
+    /// The clouds, see [crate::render::clouds]
+    pub clouds: Clouds,
+
+    /// What's loaded again in [MinecraftState::reload_resources] besides the built-in stages, see [reload]
+    pub reload_listeners: ReloadListeners,
+    /// Set by [MinecraftState::subscribe_reloads]
+    reloads: Mutex<Option<Receiver<ResourceReload>>>,
+    /// Incremented by every [ReloadStage::Shaders], after which each [crate::render::graph::ShaderGraph] compiles
+    /// its shaders again before its next frame
+    pub shader_generation: AtomicU64,
 }
 
 impl MinecraftState {
@@ -164,23 +346,254 @@ impl MinecraftState {
     pub fn new(resource_provider: Arc<dyn ResourceProvider>) -> Self {
         MinecraftState {
             sun_position: ArcSwap::new(Arc::new(0.0)),
+            smooth_lighting: ArcSwap::new(Arc::new(true)),
+            lod_distance: ArcSwap::new(Arc::new(None)),
             chunks: ChunkManager::new(),
             entity_models: RwLock::new(Vec::new()),
+            skins: Skins::new(),
+            block_entity_renderers: BlockEntityRenderers::new(),
 
             texture_manager: TextureManager::new(),
 
+            connected_textures: ArcSwap::new(Arc::new(ConnectedTextures::default())),
+
             block_manager: RwLock::new(BlockManager {
                 blocks: IndexMap::new(),
+                shapes: HashMap::new(),
+                colors: BlockColors::default(),
             }),
+            item_manager: RwLock::new(ItemManager::default()),
+            item_entities: ItemEntities::new(),
 
             resource_provider,
 
             animated_block_buffer: ArcSwap::new(Arc::new(None)),
             animated_block_bind_group: ArcSwap::new(Arc::new(None)),
+
+            lightmap: ArcSwap::new(Arc::new(None)),
+            colormaps: ArcSwap::new(Arc::new(HashMap::new())),
+
+            block_breaking: ArcSwap::new(Arc::new(HashMap::new())),
+            entity_shadows: ArcSwap::new(Arc::new(Vec::new())),
+            name_tags: ArcSwap::new(Arc::new(Vec::new())),
+            font: RwLock::new(None),
+            beams: ArcSwap::new(Arc::new(Vec::new())),
+            particles: ParticleManager::new(),
+            sky: ArcSwap::new(Arc::new(SkyState::default())),
+            clouds: Clouds::new(),
+
+            reload_listeners: ReloadListeners::default(),
+            reloads: Mutex::new(None),
+            shader_generation: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the stage of the cracks on the block at the position, from 0 to 9 like vanilla's `destroy_stage_N`
+    /// textures. [None] or a later stage removes them. The block's state is passed in as chunks don't keep it after
+    /// they're baked. The cracks are drawn by the pipelines of the shader graph with the `wm_geo_block_breaking`
+    /// geometry, see [crate::render::pipeline::block_breaking].
+    pub fn set_block_breaking(&self, pos: BlockPos, block: BlockstateKey, stage: Option<u8>) {
+        self.block_breaking.rcu(|breaking| {
+            let mut breaking = HashMap::clone(breaking);
+
+            match stage.filter(|&stage| stage < DESTROY_STAGES) {
+                Some(stage) => breaking.insert(pos, (block, stage)),
+                None => breaking.remove(&pos),
+            };
+
+            breaking
+        });
+    }
+
+    /// Replaces the shadows under entities, usually every frame, with those of the instances which cast one. They're
+    /// laid onto the blocks the provider has underneath them, and drawn by the pipelines with the
+    /// `wm_geo_entity_shadows` geometry, see [crate::render::pipeline::entity_shadow].
+    pub fn set_entity_shadows(
+        &self,
+        shadows: impl IntoIterator<Item = EntityShadow>,
+        provider: &dyn BlockStateProvider,
+    ) {
+        let block_manager = self.block_manager.read();
+
+        let quads = shadows
+            .into_iter()
+            .flat_map(|shadow| shadow.quads(&block_manager, provider))
+            .collect();
+
+        self.entity_shadows.store(Arc::new(quads));
+    }
+
+    /// Replaces the name tags over entities, usually every frame. They're drawn by the pipelines with the
+    /// `wm_geo_name_tags` geometry, see [crate::render::pipeline::entity_shadow].
+    pub fn set_name_tags(&self, name_tags: impl IntoIterator<Item = NameTag>) {
+        self.name_tags
+            .store(Arc::new(name_tags.into_iter().collect()));
+    }
+
+    /// Replaces the beams which are drawn, usually every frame, by the pipelines with the `wm_geo_beams` geometry, see
+    /// [crate::render::pipeline::beam]
+    pub fn set_beams(&self, beams: Vec<BeamInstance>) {
+        self.beams.store(Arc::new(beams));
+    }
+
+    /// Replaces what the sky looks like, drawn by the pipelines with the `wm_geo_sky` geometry, see
+    /// [crate::render::sky]
+    pub fn set_sky(&self, sky: SkyState) {
+        self.sky.store(Arc::new(sky));
+    }
+
+    /// Calls the listener in every [MinecraftState::reload_resources] from now on, after the built-in work of the
+    /// stage, see [reload]
+    pub fn register_reload_listener(&self, stage: ReloadStage, listener: Arc<dyn ReloadListener>) {
+        self.reload_listeners.register(stage, listener);
+    }
+
+    /// Reloads the resources in [MinecraftState::poll_reloads] whenever the packs of the stack are reloaded.
+    /// Replaces the stack subscribed to before.
+    pub fn subscribe_reloads(&self, stack: &ResourcePackStack) {
+        *self.reloads.lock() = Some(stack.subscribe());
+    }
+
+    /// Calls [MinecraftState::reload_resources] once if the subscribed stack was reloaded since the last call,
+    /// however many times that was, and returns the last reload. Called on the render thread, e.g. every frame.
+    pub fn poll_reloads(&self, wm: &WmRenderer) -> Option<ResourceReload> {
+        let reload = self.reloads.lock().as_ref()?.try_iter().last()?;
+
+        self.reload_resources(wm);
+
+        Some(reload)
+    }
+
+    /// Loads everything from the resources again as they are now, after the resource packs changed, see
+    /// [resource::ResourcePackStack]. Runs every [ReloadStage] in order with its listeners: the block atlas is
+    /// cleared so that it only has the textures of the new models, the [ctm] rules are loaded again, every block
+    /// and item is baked again with the blocks keeping their [BlockstateKey]s, and the loaded chunks are marked to
+    /// be re-baked, which the frontend does like for any other block change with [chunk::Chunk::rebake_dirty].
+    pub fn reload_resources(&self, wm: &WmRenderer) {
+        for stage in ReloadStage::ALL {
+            match stage {
+                ReloadStage::Atlases => {
+                    self.texture_manager.atlas(AtlasKind::Block).clear();
+                    self.reload_atlases(wm);
+                    load_colormaps(wm);
+                    self.load_connected_textures();
+                }
+                ReloadStage::Models => self.rebake_models(wm),
+                ReloadStage::Shaders => {
+                    self.shader_generation.fetch_add(1, Ordering::AcqRel);
+                }
+                ReloadStage::Chunks => self.mark_chunks_dirty(),
+            }
+
+            for listener in self.reload_listeners.stage(stage) {
+                listener.reload(wm);
+            }
+        }
+    }
+
+    /// Loads the textures of the atlases other than the block atlas again, which aren't baked into anything. Anything
+    /// holding their UVs notices by their [Atlas::generation].
+    fn reload_atlases(&self, wm: &WmRenderer) {
+        for kind in AtlasKind::ALL {
+            if kind == AtlasKind::Block {
+                continue;
+            }
+
+            let atlas = self.texture_manager.atlas(kind);
+
+            if let Err(error) = atlas.reload(wm, &*self.resource_provider, texture_file) {
+                log::error!("Couldn't reload the {} atlas: {error}", kind.name());
+            }
+        }
+    }
+
+    /// Replaces [MinecraftState::connected_textures] with the rules the resource provider lists now, see [ctm]
+    pub fn load_connected_textures(&self) {
+        self.connected_textures
+            .store(Arc::new(ConnectedTextures::load_all(
+                &*self.resource_provider,
+            )));
+    }
+
+    /// Loads the textures of the block atlas again for when the resource packs only swapped some textures, see
+    /// [Atlas::refresh]. Textures which kept their size are written over in place and nothing is baked again. Only
+    /// if a texture moved in the atlas are the blocks and items baked again and the loaded chunks marked to be
+    /// re-baked. Unlike [MinecraftState::reload_resources], changes to models aren't picked up.
+    pub fn reload_textures(&self, wm: &WmRenderer) -> Result<AtlasRefresh, AtlasError> {
+        let block_atlas = self.texture_manager.atlas(AtlasKind::Block);
+        let generation = block_atlas.generation();
+
+        let refresh = block_atlas.refresh(wm, &*self.resource_provider, texture_file)?;
+
+        if !refresh.moved.is_empty() || block_atlas.generation() != generation {
+            block_atlas.upload(wm);
+            self.rebake_blocks(wm);
+        }
+
+        Ok(refresh)
+    }
+
+    /// Bakes every known block and item again and marks the loaded chunks to be re-baked, keeping the textures
+    /// which are already in the block atlas
+    fn rebake_blocks(&self, wm: &WmRenderer) {
+        self.rebake_models(wm);
+        self.mark_chunks_dirty();
+    }
+
+    fn mark_chunks_dirty(&self) {
+        for chunk in self.chunks.loaded_chunks.read().values() {
+            chunk.load().mark_all_dirty();
         }
     }
 
-    /// Bake blocks from their blockstates
+    /// Bakes every known block and item again
+    fn rebake_models(&self, wm: &WmRenderer) {
+        //Items first, so that the blocks are baked again if their textures grow the atlas
+        let items: Vec<String> = self.item_manager.read().items.keys().cloned().collect();
+
+        self.item_manager.write().bake_items(
+            items,
+            &*self.resource_provider,
+            &self.texture_manager.atlas(AtlasKind::Block),
+        );
+
+        let blocks: Vec<(String, ResourcePath)> = self
+            .block_manager
+            .read()
+            .blocks
+            .keys()
+            .map(|name| {
+                (
+                    name.clone(),
+                    ResourcePath::from(&name[..])
+                        .prepend("blockstates/")
+                        .append(".json"),
+                )
+            })
+            .collect();
+
+        self.bake_blocks(wm, blocks.iter().map(|(name, path)| (name, path)));
+    }
+
+    /// Bakes the models of the items, by their names like `minecraft:stick`, see [item]. If the block atlas has to
+    /// grow to fit their textures, every block is baked again and the loaded chunks are marked to be re-baked.
+    pub fn bake_items(&self, wm: &WmRenderer, items: impl IntoIterator<Item = impl AsRef<str>>) {
+        let block_atlas = self.texture_manager.atlas(AtlasKind::Block);
+        let generation = block_atlas.generation();
+
+        self.item_manager
+            .write()
+            .bake_items(items, &*self.resource_provider, &block_atlas);
+
+        block_atlas.upload(wm);
+
+        if block_atlas.generation() != generation {
+            self.rebake_blocks(wm);
+        }
+    }
+
+    /// Bake blocks from their blockstates. If the block atlas has to grow to fit their textures, every block is baked
+    /// again and the loaded chunks are marked to be re-baked, see [Atlas::generation].
     ///
     /// # Example
     ///
@@ -202,51 +615,272 @@ impl MinecraftState {
         wm: &WmRenderer,
         block_states: impl IntoIterator<Item = (impl AsRef<str>, &'a ResourcePath)>,
     ) {
-        let mut block_manager = self.block_manager.write();
-        let block_atlas = self
-            .texture_manager
-            .atlases
-            .load()
-            .get(BLOCK_ATLAS)
-            .unwrap()
-            .load();
-
-        //Figure out which block models there are
-        block_states
+        self.bake_blocks_with_progress(wm, block_states, &|_| {});
+    }
+
+    /// Like [MinecraftState::bake_blocks], but calls `progress` after each block is baked, from whichever thread
+    /// baked it, so that the frontend can show a loading screen. The blocks are baked in parallel on rayon's
+    /// global thread pool, and this can be called from a loading thread to keep the render thread going in the
+    /// meantime.
+    pub fn bake_blocks_with_progress<'a>(
+        &self,
+        wm: &WmRenderer,
+        block_states: impl IntoIterator<Item = (impl AsRef<str>, &'a ResourcePath)>,
+        progress: &(dyn Fn(LoadProgress) + Sync),
+    ) {
+        let block_atlas = self.texture_manager.atlas(AtlasKind::Block);
+        let generation = block_atlas.generation();
+        let connected_textures = self.connected_textures.load_full();
+
+        let block_states: Vec<(String, &ResourcePath)> = block_states
             .into_iter()
-            .for_each(|(block_name, block_state)| {
-                let blockstates: schemas::BlockStates =
-                    serde_json::from_str(&self.resource_provider.get_string(block_state).unwrap())
-                        .unwrap();
+            .map(|(block_name, block_state)| (String::from(block_name.as_ref()), block_state))
+            .collect();
+
+        let total = block_states.len();
+        let done = AtomicUsize::new(0);
+
+        //Figure out which block models there are. Collecting keeps the order the blocks were passed in, so that
+        //their indices don't depend on which thread finished first.
+        let baked: Vec<(Block, Option<BlockShape>)> = block_states
+            .par_iter()
+            .map(|(block_name, block_state)| {
+                let baked =
+                    self.bake_block(block_name, block_state, &block_atlas, &connected_textures);
+
+                progress(LoadProgress {
+                    done: done.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                });
+
+                baked
+            })
+            .collect();
+
+        //The colormaps have been loaded along with the other textures, see render::colormap::load_colormaps
+        let mut block_manager = self.block_manager.write();
 
-                let block = match &blockstates {
-                    schemas::BlockStates::Variants { variants } => {
-                        let meshes: IndexMap<String, Arc<ModelMesh>> = variants
-                            .iter()
-                            .map(|(variant_id, variant)| {
-                                let mesh = ModelMesh::bake(
-                                    [variant],
-                                    &*self.resource_provider,
-                                    &block_atlas,
-                                )
-                                .unwrap();
-                                (variant_id.clone(), Arc::new(mesh))
-                            })
-                            .collect();
-
-                        Block::Variants(meshes)
+        for ((block_name, _), (block, shape)) in block_states.iter().zip(baked) {
+            let index = match block_manager.insert(block_name.clone(), block, shape) {
+                Some(index) => index,
+                None => {
+                    log::error!(
+                        "Skipped the block {block_name}, there can't be more than {} blocks",
+                        u16::MAX as usize + 1
+                    );
+                    continue;
+                }
+            };
+
+            //Tints set by the frontend win over the vanilla ones
+            if let Some(tint) = vanilla_tint(block_name) {
+                block_manager.colors.tints.entry(index).or_insert(tint);
+            }
+        }
+
+        //The cracks of blocks being broken, the shadows of entities, the font of name tags and the textures of beams,
+        //the sky and the clouds aren't part of any model
+        let destroy_stages: Vec<(ResourcePath, Vec<u8>)> = (0..DESTROY_STAGES)
+            .map(destroy_stage_texture)
+            .chain([shadow_texture(), font_texture()])
+            .chain(beam_textures())
+            .chain(sky_textures())
+            .chain([clouds_texture()])
+            .filter(|texture| !block_atlas.uv_map.read().contains_key(texture))
+            .filter_map(|texture| {
+                match self
+                    .resource_provider
+                    .get_bytes(&texture.prepend("textures/").append(".png"))
+                {
+                    Some(bytes) => Some((texture, bytes)),
+                    None => {
+                        log::error!(
+                            "Skipped the texture {texture}, the resource pack doesn't have it"
+                        );
+                        None
                     }
-                    schemas::BlockStates::Multipart { cases } => Block::Multipart(Multipart {
-                        cases: cases.clone(),
-                        keys: RwLock::new(IndexMap::new()),
-                    }),
-                };
+                }
+            })
+            .collect();
 
-                block_manager
-                    .blocks
-                    .insert(String::from(block_name.as_ref()), block);
-            });
+        //One at a time, so that a texture which doesn't fit anymore only loses itself instead of the rest
+        for (texture, bytes) in &destroy_stages {
+            if let Err(error) = block_atlas.allocate([(texture, bytes)], &*self.resource_provider) {
+                log::error!("Skipped the texture {texture}: {error}");
+            }
+        }
 
         block_atlas.upload(wm);
+
+        self.clouds.load(&*self.resource_provider);
+        *self.font.write() = Font::load(&*self.resource_provider);
+
+        drop(block_manager);
+
+        //The atlas grew, so the UVs of the models baked before that are stale. Every texture is in the atlas by
+        //now, so baking them again doesn't grow it any further.
+        if block_atlas.generation() != generation {
+            self.rebake_blocks(wm);
+        }
+    }
+
+    /// Like [MinecraftState::bake_blocks_with_progress], but bakes them on a new loading thread and returns right
+    /// away, so that the caller can keep drawing a loading screen with the [BlockBake::progress] until it
+    /// [is finished](BlockBake::is_finished)
+    #[must_use]
+    pub fn bake_blocks_in_background(
+        wm: &WmRenderer,
+        block_states: Vec<(String, ResourcePath)>,
+    ) -> BlockBake {
+        let done = Arc::new(AtomicUsize::new(0));
+        let total = block_states.len();
+
+        let thread = {
+            let wm = wm.clone();
+            let done = done.clone();
+
+            std::thread::spawn(move || {
+                wm.mc.bake_blocks_with_progress(
+                    &wm,
+                    block_states
+                        .iter()
+                        .map(|(block_name, block_state)| (block_name, block_state)),
+                    &|progress| {
+                        done.fetch_max(progress.done, Ordering::Relaxed);
+                    },
+                );
+            })
+        };
+
+        BlockBake {
+            done,
+            total,
+            thread,
+        }
+    }
+
+    /// Bakes the variants of the block, or reads the cases of its multipart, and reads its shape
+    fn bake_block(
+        &self,
+        block_name: &str,
+        block_state: &ResourcePath,
+        block_atlas: &Atlas,
+        connected_textures: &ConnectedTextures,
+    ) -> (Block, Option<BlockShape>) {
+        let json = self.resource_provider.get_string(block_state).unwrap();
+        let blockstates: schemas::BlockStates = serde_json::from_str(&json).unwrap();
+
+        let block = match &blockstates {
+            schemas::BlockStates::Variants { variants } => {
+                let block = ResourcePath::from(block_name);
+
+                let meshes: IndexMap<String, Arc<ModelMesh>> = variants
+                    .iter()
+                    .map(|(variant_id, variant)| {
+                        let mesh = ModelMesh::bake_connected(
+                            [variant],
+                            &*self.resource_provider,
+                            block_atlas,
+                            Some((connected_textures, &block)),
+                        )
+                        .unwrap();
+                        (variant_id.clone(), Arc::new(mesh))
+                    })
+                    .collect();
+
+                Block::Variants(meshes)
+            }
+            schemas::BlockStates::Multipart { cases } => Block::Multipart(Multipart {
+                cases: cases.clone(),
+                //Parsed from the JSON again, the schema's conditions don't cover negation or nested AND and OR
+                conditions: case_conditions(&serde_json::from_str(&json).unwrap()),
+                keys: RwLock::new(IndexMap::new()),
+            }),
+        };
+
+        let shape_path = ResourcePath::from(block_name)
+            .prepend("shapes/")
+            .append(".json");
+
+        let shape = self
+            .resource_provider
+            .get_string(&shape_path)
+            .and_then(|string| serde_json::from_str::<BlockShape>(&string).ok());
+
+        (block, shape)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use indexmap::IndexMap;
+
+    use super::{Block, BlockManager};
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::BlockShape;
+
+    fn block() -> Block {
+        Block::Variants(IndexMap::new())
+    }
+
+    #[test]
+    fn rebaking_a_block_replaces_its_shape() {
+        let mut block_manager = BlockManager {
+            blocks: IndexMap::new(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        };
+
+        assert_eq!(
+            block_manager.insert("minecraft:stone".into(), block(), None),
+            Some(0)
+        );
+        assert_eq!(
+            block_manager.insert(
+                "minecraft:oak_fence".into(),
+                block(),
+                Some(BlockShape::Fence)
+            ),
+            Some(1)
+        );
+        assert_eq!(block_manager.get_shape(1), &BlockShape::Fence);
+
+        //A resource pack without the shape
+        assert_eq!(
+            block_manager.insert("minecraft:oak_fence".into(), block(), None),
+            Some(1)
+        );
+        assert_eq!(block_manager.get_shape(1), &BlockShape::FullCube);
+        assert!(block_manager.shapes.is_empty());
+    }
+
+    #[test]
+    fn blocks_past_the_last_index_are_rejected() {
+        let mut block_manager = BlockManager {
+            blocks: (0..=u16::MAX)
+                .map(|index| (format!("wgpu_mc:block_{index}"), block()))
+                .collect(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        };
+
+        assert_eq!(
+            block_manager.insert(
+                "wgpu_mc:one_too_many".into(),
+                block(),
+                Some(BlockShape::Cross)
+            ),
+            None
+        );
+        assert_eq!(block_manager.blocks.len(), u16::MAX as usize + 1);
+        assert!(block_manager.shapes.is_empty());
+
+        //Existing blocks can still be replaced
+        assert_eq!(
+            block_manager.insert("wgpu_mc:block_65535".into(), block(), None),
+            Some(u16::MAX)
+        );
     }
 }
@@ -0,0 +1,258 @@
+//! # Multipart conditions
+//!
+//! The parts of a multipart blockstate are applied when the state of the block matches their `when`, which is one
+//! of
+//!
+//! ```json
+//! { "north": "side|up", "west": "!none" }
+//! { "OR": [{ "north": "side" }, { "east": "side" }] }
+//! { "AND": [{ "north": "side" }, { "OR": [...] }] }
+//! ```
+//!
+//! Each property of a plain condition has to match one of its `|` separated values, or none of them if the values
+//! start with `!`. `OR` and `AND` nest any conditions, and parts without a `when` always apply.
+
+use minecraft_assets::schemas::blockstates::multipart::StateValue;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum ConditionError {
+    /// `OR` or `AND` isn't a list
+    NotAList(String),
+    /// A property doesn't have a string, bool or number value
+    InvalidValue(String),
+    NotAnObject,
+}
+
+/// A property which has to have one of the values, or none of them if negated
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct PropertyCondition {
+    pub property: String,
+    pub values: Vec<String>,
+    pub negated: bool,
+}
+
+impl PropertyCondition {
+    /// Parses a value like `side|up` or `!none`
+    #[must_use]
+    pub fn new(property: &str, value: &str) -> Self {
+        let (negated, value) = match value.strip_prefix('!') {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+
+        Self {
+            property: property.into(),
+            values: value.split('|').map(String::from).collect(),
+            negated,
+        }
+    }
+
+    fn matches(&self, value: &str) -> bool {
+        self.values.iter().any(|allowed| allowed == value) != self.negated
+    }
+}
+
+/// The `when` of a multipart case
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub enum Condition {
+    /// Every property has to match
+    Properties(Vec<PropertyCondition>),
+    /// Any of the conditions has to match, so an empty list never does
+    Or(Vec<Condition>),
+    /// All of the conditions have to match, so an empty list always does
+    And(Vec<Condition>),
+}
+
+impl Condition {
+    /// The condition of parts without a `when`
+    pub const ALWAYS: Self = Self::And(Vec::new());
+
+    /// The condition of parts whose `when` couldn't be parsed
+    pub const NEVER: Self = Self::Or(Vec::new());
+
+    pub fn parse(json: &Value) -> Result<Self, ConditionError> {
+        let object = json.as_object().ok_or(ConditionError::NotAnObject)?;
+
+        let nested = |key: &str, list: &Value| {
+            list.as_array()
+                .ok_or_else(|| ConditionError::NotAList(key.into()))?
+                .iter()
+                .map(Self::parse)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        //Minecraft only allows OR and AND on their own
+        if object.len() == 1 {
+            if let Some(list) = object.get("OR") {
+                return Ok(Self::Or(nested("OR", list)?));
+            }
+
+            if let Some(list) = object.get("AND") {
+                return Ok(Self::And(nested("AND", list)?));
+            }
+        }
+
+        object
+            .iter()
+            .map(|(property, value)| {
+                let value = match value {
+                    Value::String(string) => string.clone(),
+                    Value::Bool(bool) => bool.to_string(),
+                    Value::Number(number) => number.to_string(),
+                    _ => return Err(ConditionError::InvalidValue(property.clone())),
+                };
+
+                Ok(PropertyCondition::new(property, &value))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self::Properties)
+    }
+
+    /// Whether the state of the block, like `[("north", "side"), ("up", true)]`, matches
+    pub fn matches<'a>(
+        &self,
+        state: impl IntoIterator<Item = (&'a str, &'a StateValue)> + Clone,
+    ) -> bool {
+        match self {
+            Condition::Properties(properties) => properties.iter().all(|condition| {
+                state
+                    .clone()
+                    .into_iter()
+                    .find(|(property, _)| *property == condition.property)
+                    //Properties missing from the state never match, Minecraft wouldn't load the blockstate
+                    .map_or(false, |(_, value)| match value {
+                        StateValue::Bool(bool) => condition.matches(&bool.to_string()),
+                        StateValue::String(string) => condition.matches(string),
+                    })
+            }),
+            Condition::Or(conditions) => conditions
+                .iter()
+                .any(|condition| condition.matches(state.clone())),
+            Condition::And(conditions) => conditions
+                .iter()
+                .all(|condition| condition.matches(state.clone())),
+        }
+    }
+}
+
+/// The conditions of every case of a multipart blockstate JSON, in the order of its `multipart` list. Cases whose
+/// `when` is invalid are logged and never apply.
+#[must_use]
+pub fn case_conditions(blockstate: &Value) -> Vec<Condition> {
+    blockstate
+        .get("multipart")
+        .and_then(Value::as_array)
+        .map(|cases| {
+            cases
+                .iter()
+                .map(|case| match case.get("when") {
+                    None => Condition::ALWAYS,
+                    Some(when) => Condition::parse(when).unwrap_or_else(|error| {
+                        log::error!("Invalid multipart condition {when}: {error:?}");
+                        Condition::NEVER
+                    }),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use minecraft_assets::schemas::blockstates::multipart::StateValue;
+    use serde_json::json;
+
+    use super::{case_conditions, Condition};
+
+    fn state(values: &[(&'static str, &str)]) -> Vec<(&'static str, StateValue)> {
+        values
+            .iter()
+            .map(|(property, value)| {
+                let value = match *value {
+                    "true" => StateValue::Bool(true),
+                    "false" => StateValue::Bool(false),
+                    other => StateValue::String(other.into()),
+                };
+
+                (*property, value)
+            })
+            .collect()
+    }
+
+    fn matches(condition: &Condition, values: &[(&'static str, &str)]) -> bool {
+        let state = state(values);
+        condition.matches(state.iter().map(|(property, value)| (*property, value)))
+    }
+
+    #[test]
+    fn properties_match_any_alternative() {
+        let condition = Condition::parse(&json!({ "north": "side|up", "up": true })).unwrap();
+
+        assert!(matches(&condition, &[("north", "side"), ("up", "true")]));
+        assert!(matches(&condition, &[("north", "up"), ("up", "true")]));
+        assert!(!matches(&condition, &[("north", "none"), ("up", "true")]));
+        assert!(!matches(&condition, &[("north", "side"), ("up", "false")]));
+        //Missing properties don't match
+        assert!(!matches(&condition, &[("north", "side")]));
+    }
+
+    #[test]
+    fn negated_properties_match_anything_else() {
+        let condition = Condition::parse(&json!({ "north": "!none|low" })).unwrap();
+
+        assert!(matches(&condition, &[("north", "tall")]));
+        assert!(!matches(&condition, &[("north", "none")]));
+        assert!(!matches(&condition, &[("north", "low")]));
+    }
+
+    #[test]
+    fn or_and_and_nest() {
+        let condition = Condition::parse(&json!({
+            "AND": [
+                { "up": "true" },
+                { "OR": [{ "north": "side" }, { "east": "side|up" }] },
+            ]
+        }))
+        .unwrap();
+
+        assert!(matches(
+            &condition,
+            &[("up", "true"), ("north", "side"), ("east", "none")]
+        ));
+        assert!(matches(
+            &condition,
+            &[("up", "true"), ("north", "none"), ("east", "up")]
+        ));
+        assert!(!matches(
+            &condition,
+            &[("up", "true"), ("north", "none"), ("east", "none")]
+        ));
+        assert!(!matches(
+            &condition,
+            &[("up", "false"), ("north", "side"), ("east", "side")]
+        ));
+    }
+
+    #[test]
+    fn cases_without_a_valid_when() {
+        let conditions = case_conditions(&json!({
+            "multipart": [
+                { "apply": { "model": "minecraft:block/stone" } },
+                { "when": { "OR": { "north": "side" } }, "apply": { "model": "minecraft:block/stone" } },
+            ]
+        }));
+
+        assert_eq!(conditions, [Condition::ALWAYS, Condition::NEVER]);
+        assert!(matches(&conditions[0], &[]));
+        assert!(!matches(&conditions[1], &[("north", "side")]));
+    }
+}
@@ -0,0 +1,119 @@
+//! # Resource reloads
+//!
+//! What's loaded from the resources depends on other things loaded from them: models are baked against the block
+//! atlas, and chunks are baked from the models. [crate::mc::MinecraftState::reload_resources] loads everything
+//! again in that order, like vanilla's F3+T, one [ReloadStage] at a time:
+//!
+//! 1. [ReloadStage::Atlases]: the block atlas is cleared, the other atlases are loaded again, and the colormaps and
+//!    connected texture rules too
+//! 2. [ReloadStage::Models]: every block and item is baked again, which fills the block atlas
+//! 3. [ReloadStage::Shaders]: every [crate::render::graph::ShaderGraph] compiles its shaders again before its next
+//!    frame, see [crate::mc::MinecraftState::shader_generation]
+//! 4. [ReloadStage::Chunks]: the loaded chunks are marked to be re-baked
+//!
+//! Anything else the frontend loads from the resources is reloaded by a [ReloadListener] registered for the stage it belongs to, which is called after the built-in
+//! work of the stage. Listeners of the same stage are called in the order they were registered in.
+
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::WmRenderer;
+
+/// The steps of a reload, in the order they run in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ReloadStage {
+    Atlases,
+    Models,
+    Shaders,
+    Chunks,
+}
+
+impl ReloadStage {
+    pub const ALL: [Self; 4] = [Self::Atlases, Self::Models, Self::Shaders, Self::Chunks];
+}
+
+/// Loads something from the resources again, see [ReloadListeners::register]. Implemented for closures.
+pub trait ReloadListener: Send + Sync {
+    fn reload(&self, wm: &WmRenderer);
+}
+
+impl<F: Fn(&WmRenderer) + Send + Sync> ReloadListener for F {
+    fn reload(&self, wm: &WmRenderer) {
+        self(wm)
+    }
+}
+
+/// The listeners of every stage, see [crate::mc::MinecraftState::reload_listeners]
+#[derive(Default)]
+pub struct ReloadListeners {
+    /// Sorted by stage, and in the order they were registered in within a stage
+    listeners: RwLock<Vec<(ReloadStage, Arc<dyn ReloadListener>)>>,
+}
+
+impl ReloadListeners {
+    /// Calls the listener in every reload from now on, after the built-in work of the stage
+    pub fn register(&self, stage: ReloadStage, listener: Arc<dyn ReloadListener>) {
+        let mut listeners = self.listeners.write();
+        let index = listeners.partition_point(|(other, _)| *other <= stage);
+
+        listeners.insert(index, (stage, listener));
+    }
+
+    /// The listeners of the stage. They're called without holding the lock, so that they can register others.
+    #[must_use]
+    pub fn stage(&self, stage: ReloadStage) -> Vec<Arc<dyn ReloadListener>> {
+        self.listeners
+            .read()
+            .iter()
+            .filter(|(other, _)| *other == stage)
+            .map(|(_, listener)| listener.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{ReloadListener, ReloadListeners, ReloadStage};
+    use crate::WmRenderer;
+
+    fn listener() -> Arc<dyn ReloadListener> {
+        Arc::new(|_: &WmRenderer| {})
+    }
+
+    #[test]
+    fn listeners_are_kept_in_stage_order() {
+        let listeners = ReloadListeners::default();
+        let entity_atlas = listener();
+        let particle_atlas = listener();
+
+        listeners.register(ReloadStage::Chunks, listener());
+        listeners.register(ReloadStage::Atlases, entity_atlas.clone());
+        listeners.register(ReloadStage::Shaders, listener());
+        listeners.register(ReloadStage::Atlases, particle_atlas.clone());
+
+        assert_eq!(
+            listeners
+                .listeners
+                .read()
+                .iter()
+                .map(|(stage, _)| *stage)
+                .collect::<Vec<_>>(),
+            [
+                ReloadStage::Atlases,
+                ReloadStage::Atlases,
+                ReloadStage::Shaders,
+                ReloadStage::Chunks
+            ]
+        );
+
+        //Listeners of the same stage are called in the order they were registered in
+        let atlases = listeners.stage(ReloadStage::Atlases);
+        assert!(Arc::ptr_eq(&atlases[0], &entity_atlas));
+        assert!(Arc::ptr_eq(&atlases[1], &particle_atlas));
+
+        assert!(listeners.stage(ReloadStage::Models).is_empty());
+    }
+}
@@ -1,4 +1,15 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+
+use parking_lot::{Mutex, RwLock};
+use zip::result::ZipError;
+use zip::ZipArchive;
 
 /// Describes a minecraft resource, like "minecraft:stone". Useful in combination with
 /// [ResourceProvider], which gets you the actual resource.
@@ -10,6 +21,13 @@ impl ResourcePath {
         Self(format!("{}{}", self.0, a))
     }
 
+    /// Whether the resource is somewhere under the directory, like `minecraft:optifine/ctm`
+    pub fn is_in(&self, directory: &ResourcePath) -> bool {
+        self.0
+            .strip_prefix(directory.0.trim_end_matches('/'))
+            .map_or(false, |rest| rest.starts_with('/'))
+    }
+
     pub fn prepend(&self, a: &str) -> Self {
         let mut split = self.0.split(':');
 
@@ -80,4 +98,441 @@ pub trait ResourceProvider: Send + Sync {
     fn get_string(&self, id: &ResourcePath) -> Option<String> {
         String::from_utf8(self.get_bytes(id)?).ok()
     }
+
+    /// Whether the resource exists. Providers which can tell without reading it should, as this is called for
+    /// every layer a resource is looked up in.
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.get_bytes(id).is_some()
+    }
+
+    /// The name of where the resource comes from, like the resource pack of a [ResourcePackStack] which has it.
+    /// [None] if the provider only has one source, or the resource doesn't exist.
+    fn source(&self, _id: &ResourcePath) -> Option<String> {
+        None
+    }
+
+    /// Every resource somewhere under the directory, like `minecraft:optifine/ctm`, in no particular order. Empty
+    /// if the provider can't list its resources.
+    fn list(&self, _directory: &ResourcePath) -> Vec<ResourcePath> {
+        Vec::new()
+    }
+}
+
+/// The resources listed by each of the providers, without the ones more than one of them have
+pub(crate) fn list_all<'a>(
+    providers: impl IntoIterator<Item = &'a dyn ResourceProvider>,
+    directory: &ResourcePath,
+) -> Vec<ResourcePath> {
+    let mut listed = HashSet::new();
+
+    providers
+        .into_iter()
+        .flat_map(|provider| provider.list(directory))
+        .filter(|resource| listed.insert(resource.clone()))
+        .collect()
+}
+
+/// The most memory reserved up front for a resource in a [ZipResourceProvider]. The size the archive declares
+/// isn't trusted beyond this, as a broken or hostile server resource pack could claim any size.
+const MAX_ZIP_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
+/// Reads resources straight out of a zipped resource pack, or the client jar, from `assets/<namespace>/<path>`
+/// like [crate::render::shaderpack::pack::DirectoryResourceProvider] does out of an unzipped one. The entries are
+/// indexed when it's opened, resources are decompressed whenever they're requested.
+pub struct ZipResourceProvider<R: Read + Seek = BufReader<File>> {
+    archive: Mutex<ZipArchive<R>>,
+    /// The name of the entry of each resource in the archive
+    entries: HashMap<ResourcePath, String>,
+}
+
+impl ZipResourceProvider {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ZipError> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> ZipResourceProvider<R> {
+    pub fn new(reader: R) -> Result<Self, ZipError> {
+        let archive = ZipArchive::new(reader)?;
+
+        let entries = archive
+            .file_names()
+            .filter_map(|name| {
+                let (namespace, path) = name.strip_prefix("assets/")?.split_once('/')?;
+
+                //Directories have entries of their own
+                (!path.is_empty() && !path.ends_with('/'))
+                    .then(|| (ResourcePath::from((namespace, path)), name.to_string()))
+            })
+            .collect();
+
+        Ok(Self {
+            archive: Mutex::new(archive),
+            entries,
+        })
+    }
+
+    /// Every resource in the pack
+    pub fn resources(&self) -> impl Iterator<Item = &ResourcePath> {
+        self.entries.keys()
+    }
+}
+
+impl<R: Read + Seek + Send> ResourceProvider for ZipResourceProvider<R> {
+    fn get_bytes(&self, id: &ResourcePath) -> Option<Vec<u8>> {
+        let name = self.entries.get(id)?;
+
+        let mut archive = self.archive.lock();
+        let mut file = archive.by_name(name).ok()?;
+
+        let mut bytes = Vec::with_capacity(file.size().min(MAX_ZIP_PREALLOCATION) as usize);
+        file.read_to_end(&mut bytes).ok()?;
+
+        Some(bytes)
+    }
+
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.entries.contains_key(id)
+    }
+
+    fn list(&self, directory: &ResourcePath) -> Vec<ResourcePath> {
+        self.entries
+            .keys()
+            .filter(|resource| resource.is_in(directory))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Resources kept in memory, the provider tests look resources up in
+#[cfg(test)]
+pub(crate) struct MapProvider(pub HashMap<&'static str, &'static str>);
+
+#[cfg(test)]
+impl MapProvider {
+    pub fn new(resources: &[(&'static str, &'static str)]) -> Self {
+        Self(resources.iter().copied().collect())
+    }
+}
+
+#[cfg(test)]
+impl ResourceProvider for MapProvider {
+    fn get_bytes(&self, id: &ResourcePath) -> Option<Vec<u8>> {
+        self.0.get(&id.0[..]).map(|data| data.as_bytes().to_vec())
+    }
+
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.0.contains_key(&id.0[..])
+    }
+
+    fn list(&self, directory: &ResourcePath) -> Vec<ResourcePath> {
+        self.0
+            .keys()
+            .map(|&name| ResourcePath::from(name))
+            .filter(|resource| resource.is_in(directory))
+            .collect()
+    }
+}
+
+/// Sent to the subscribers of a [ResourcePackStack] by [ResourcePackStack::reload]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResourceReload {
+    /// Counts up from 1 with every reload, so that a subscriber which is behind can skip to the last one
+    pub generation: u64,
+}
+
+struct StackedPack {
+    name: String,
+    priority: i32,
+    provider: Arc<dyn ResourceProvider>,
+}
+
+/// The resource packs which are enabled, looked up in order like vanilla does: a resource comes from the pack with
+/// the highest priority which has it, and of packs with the same priority, from the one inserted last.
+///
+/// Packs can be inserted and removed at any time, but what's already baked keeps the resources it was baked with
+/// until [ResourcePackStack::reload] tells the subscribers to load them again, usually
+/// [crate::mc::MinecraftState::poll_reloads] on the render thread, see
+/// [crate::mc::MinecraftState::subscribe_reloads]. Several changes can be made before a single reload.
+#[derive(Default)]
+pub struct ResourcePackStack {
+    /// Sorted by priority, highest first
+    packs: RwLock<Vec<StackedPack>>,
+    subscribers: Mutex<Vec<Sender<ResourceReload>>>,
+    generation: AtomicU64,
+}
+
+impl ResourcePackStack {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the pack, or replaces the one with the same name
+    pub fn insert(
+        &self,
+        name: impl Into<String>,
+        priority: i32,
+        provider: Arc<dyn ResourceProvider>,
+    ) {
+        let name = name.into();
+        let mut packs = self.packs.write();
+
+        packs.retain(|pack| pack.name != name);
+
+        let index = packs.partition_point(|pack| pack.priority > priority);
+
+        packs.insert(
+            index,
+            StackedPack {
+                name,
+                priority,
+                provider,
+            },
+        );
+    }
+
+    /// Returns false if there was no pack with the name
+    pub fn remove(&self, name: &str) -> bool {
+        let mut packs = self.packs.write();
+        let count = packs.len();
+
+        packs.retain(|pack| pack.name != name);
+
+        packs.len() != count
+    }
+
+    /// The names of the packs, in the order resources are looked up in them
+    pub fn names(&self) -> Vec<String> {
+        self.packs
+            .read()
+            .iter()
+            .map(|pack| pack.name.clone())
+            .collect()
+    }
+
+    /// Receives a [ResourceReload] for every reload from now on
+    pub fn subscribe(&self) -> Receiver<ResourceReload> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().push(sender);
+
+        receiver
+    }
+
+    /// Tells the subscribers that the packs changed. Subscribers whose receiver was dropped are forgotten.
+    pub fn reload(&self) -> ResourceReload {
+        let reload = ResourceReload {
+            generation: self.generation.fetch_add(1, Ordering::Relaxed) + 1,
+        };
+
+        self.subscribers
+            .lock()
+            .retain(|subscriber| subscriber.send(reload).is_ok());
+
+        reload
+    }
+}
+
+impl ResourceProvider for ResourcePackStack {
+    fn get_bytes(&self, id: &ResourcePath) -> Option<Vec<u8>> {
+        self.packs
+            .read()
+            .iter()
+            .find_map(|pack| pack.provider.get_bytes(id))
+    }
+
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.packs
+            .read()
+            .iter()
+            .any(|pack| pack.provider.contains(id))
+    }
+
+    //The source of a pack which is a stack itself is the pack of that stack which has the resource
+    fn source(&self, id: &ResourcePath) -> Option<String> {
+        self.packs
+            .read()
+            .iter()
+            .find(|pack| pack.provider.contains(id))
+            .map(|pack| {
+                pack.provider
+                    .source(id)
+                    .unwrap_or_else(|| pack.name.clone())
+            })
+    }
+
+    fn list(&self, directory: &ResourcePath) -> Vec<ResourcePath> {
+        list_all(
+            self.packs.read().iter().map(|pack| &*pack.provider),
+            directory,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::sync::Arc;
+
+    use zip::write::FileOptions;
+    use zip::ZipWriter;
+
+    use super::{
+        MapProvider, ResourcePackStack, ResourcePath, ResourceProvider, ResourceReload,
+        ZipResourceProvider,
+    };
+
+    fn pack(resources: &[(&'static str, &'static str)]) -> Arc<MapProvider> {
+        Arc::new(MapProvider::new(resources))
+    }
+
+    #[test]
+    fn packs_override_by_priority() {
+        let stack = ResourcePackStack::new();
+        let stone = || stack.get_string(&ResourcePath::from("minecraft:textures/block/stone.png"));
+
+        stack.insert(
+            "vanilla",
+            0,
+            pack(&[
+                ("minecraft:textures/block/stone.png", "vanilla"),
+                ("minecraft:textures/block/dirt.png", "vanilla"),
+            ]),
+        );
+        stack.insert(
+            "faithful",
+            10,
+            pack(&[("minecraft:textures/block/stone.png", "faithful")]),
+        );
+        //Inserted last, but below the other pack
+        stack.insert(
+            "programmer_art",
+            5,
+            pack(&[("minecraft:textures/block/stone.png", "programmer_art")]),
+        );
+
+        assert_eq!(stack.names(), ["faithful", "programmer_art", "vanilla"]);
+        assert_eq!(stone().unwrap(), "faithful");
+        assert_eq!(
+            stack
+                .get_string(&ResourcePath::from("minecraft:textures/block/dirt.png"))
+                .unwrap(),
+            "vanilla"
+        );
+        assert_eq!(
+            stack.source(&ResourcePath::from("minecraft:textures/block/stone.png")),
+            Some("faithful".into())
+        );
+        assert_eq!(
+            stack.source(&ResourcePath::from("minecraft:textures/block/grass.png")),
+            None
+        );
+
+        assert!(stack.remove("faithful"));
+        assert!(!stack.remove("faithful"));
+        assert_eq!(stone().unwrap(), "programmer_art");
+
+        //Replaces the pack with the same name, and wins over others with the same priority
+        stack.insert(
+            "vanilla",
+            5,
+            pack(&[("minecraft:textures/block/stone.png", "vanilla")]),
+        );
+        assert_eq!(stack.names(), ["vanilla", "programmer_art"]);
+        assert_eq!(stone().unwrap(), "vanilla");
+    }
+
+    #[test]
+    fn packs_are_listed_together() {
+        let stack = ResourcePackStack::new();
+
+        stack.insert(
+            "vanilla",
+            0,
+            pack(&[
+                ("minecraft:optifine/ctm/glass/glass.properties", "vanilla"),
+                ("minecraft:optifine/ctmx.properties", "vanilla"),
+            ]),
+        );
+        stack.insert(
+            "faithful",
+            10,
+            pack(&[
+                ("minecraft:optifine/ctm/glass/glass.properties", "faithful"),
+                ("minecraft:optifine/ctm/bookshelf.properties", "faithful"),
+            ]),
+        );
+
+        let mut listed = stack.list(&ResourcePath::from("minecraft:optifine/ctm"));
+        listed.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            listed,
+            [
+                ResourcePath::from("minecraft:optifine/ctm/bookshelf.properties"),
+                ResourcePath::from("minecraft:optifine/ctm/glass/glass.properties"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reloads_are_sent_to_subscribers() {
+        let stack = ResourcePackStack::new();
+
+        let subscriber = stack.subscribe();
+        drop(stack.subscribe());
+
+        stack.reload();
+        stack.reload();
+
+        assert_eq!(
+            subscriber.try_iter().collect::<Vec<_>>(),
+            [
+                ResourceReload { generation: 1 },
+                ResourceReload { generation: 2 }
+            ]
+        );
+        assert_eq!(stack.subscribers.lock().len(), 1);
+    }
+
+    #[test]
+    fn resources_are_served_from_the_assets_of_the_zip() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+
+        writer
+            .add_directory("assets/minecraft/textures/", FileOptions::default())
+            .unwrap();
+        for (name, contents) in [
+            ("pack.mcmeta", "{}"),
+            ("assets/minecraft/textures/block/stone.png", "stone"),
+            ("assets/wgpu_mc/shaders/sky.wgsl", "sky"),
+        ] {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        let provider = ZipResourceProvider::new(writer.finish().unwrap()).unwrap();
+
+        assert_eq!(
+            provider
+                .get_string(&ResourcePath::from("minecraft:textures/block/stone.png"))
+                .unwrap(),
+            "stone"
+        );
+        assert_eq!(
+            provider
+                .get_string(&ResourcePath::from("wgpu_mc:shaders/sky.wgsl"))
+                .unwrap(),
+            "sky"
+        );
+        assert!(provider
+            .get_bytes(&ResourcePath::from("minecraft:textures/block/dirt.png"))
+            .is_none());
+        //Only what's under assets
+        assert_eq!(provider.resources().count(), 2);
+        assert_eq!(
+            provider.list(&ResourcePath::from("minecraft:textures")),
+            [ResourcePath::from("minecraft:textures/block/stone.png")]
+        );
+    }
 }
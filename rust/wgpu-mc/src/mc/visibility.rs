@@ -0,0 +1,392 @@
+//! # Cave culling
+//!
+//! While baking, every chunk section records which of its faces can see each other through blocks which aren't
+//! opaque. When rendering, sections are flood-filled outwards from the camera, only crossing a section if the
+//! face it was entered through can see the face it's left through. Sections which are never reached, like caves
+//! underneath the camera while it's on the surface, aren't drawn.
+//!
+//! This is the same approach Minecraft itself uses.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use arc_swap::ArcSwap;
+use treeculler::{BVol, Frustum, Vec3, AABB};
+
+use crate::mc::block::ChunkBlockState;
+use crate::mc::chunk::{
+    chunk_origin, get_block, BlockStateProvider, Chunk, ChunkPos, CHUNK_SECTIONS_PER,
+    CHUNK_SECTION_HEIGHT, CHUNK_WIDTH,
+};
+use crate::mc::BlockManager;
+
+/// Sections are cubes
+const SECTION_SIZE: usize = CHUNK_SECTION_HEIGHT;
+
+/// Faces are in the order north, east, south, west, up, down, the same as everywhere else
+const FACE_COUNT: usize = 6;
+
+const OPPOSITE: [usize; FACE_COUNT] = [2, 3, 0, 1, 5, 4];
+
+/// The block offset each face points towards
+const FACE_OFFSETS: [[i32; 3]; FACE_COUNT] = [
+    [0, 0, -1],
+    [1, 0, 0],
+    [0, 0, 1],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+];
+
+/// Which faces of a chunk section can be seen from which other faces
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
+pub struct SectionVisibility(u64);
+
+impl Default for SectionVisibility {
+    /// Everything is visible from everywhere until a section has been baked
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl SectionVisibility {
+    pub const ALL: Self = Self(u64::MAX);
+    pub const NONE: Self = Self(0);
+
+    fn bit(from: usize, to: usize) -> u64 {
+        1 << (from * FACE_COUNT + to)
+    }
+
+    pub fn connects(&self, from: usize, to: usize) -> bool {
+        self.0 & Self::bit(from, to) != 0
+    }
+
+    /// Marks every face in the mask as visible from every other face in it
+    fn connect_all(&mut self, faces: u8) {
+        for from in 0..FACE_COUNT {
+            for to in 0..FACE_COUNT {
+                if faces & (1 << from) != 0 && faces & (1 << to) != 0 {
+                    self.0 |= Self::bit(from, to);
+                }
+            }
+        }
+    }
+
+    /// Flood-fills the section through every block which isn't a full opaque cube and records which faces
+    /// of the section each of the filled areas touches.
+    pub fn compute(
+        block_manager: &BlockManager,
+        provider: &impl BlockStateProvider,
+        chunk_pos: ChunkPos,
+        section: usize,
+    ) -> Self {
+        if provider.is_section_empty(section) {
+            return Self::ALL;
+        }
+
+        //The chunk is skipped by the baking as well, so nothing behind it is hidden
+        let [base_x, base_z] = match chunk_origin(chunk_pos) {
+            Some(origin) => origin,
+            None => return Self::ALL,
+        };
+        let base_y = (section * SECTION_SIZE) as i16;
+
+        let index = |x: usize, y: usize, z: usize| (y * SECTION_SIZE + z) * SECTION_SIZE + x;
+
+        //Opaque blocks start out as visited so that the flood fill never enters them
+        let mut visited = vec![false; SECTION_SIZE * SECTION_SIZE * SECTION_SIZE];
+
+        for y in 0..SECTION_SIZE {
+            for z in 0..SECTION_SIZE {
+                for x in 0..SECTION_SIZE {
+                    let state =
+                        provider.get_state(base_x + x as i32, base_y + y as i16, base_z + z as i32);
+
+                    visited[index(x, y, z)] = match state {
+                        ChunkBlockState::Air => false,
+                        state => get_block(block_manager, state)
+                            .map_or(false, |mesh| mesh.is_full_opaque_cube),
+                    };
+                }
+            }
+        }
+
+        let mut visibility = Self::NONE;
+        let mut queue = Vec::new();
+
+        for start in 0..visited.len() {
+            if visited[start] {
+                continue;
+            }
+
+            visited[start] = true;
+            queue.push(start);
+
+            let mut touched_faces = 0u8;
+
+            while let Some(current) = queue.pop() {
+                let x = current % SECTION_SIZE;
+                let z = (current / SECTION_SIZE) % SECTION_SIZE;
+                let y = current / (SECTION_SIZE * SECTION_SIZE);
+
+                let position = [x, y, z];
+
+                for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+                    let neighbour = [
+                        position[0] as i32 + offset[0],
+                        position[1] as i32 + offset[1],
+                        position[2] as i32 + offset[2],
+                    ];
+
+                    if neighbour
+                        .iter()
+                        .any(|&axis| axis < 0 || axis >= SECTION_SIZE as i32)
+                    {
+                        touched_faces |= 1 << face;
+                        continue;
+                    }
+
+                    let neighbour_index = index(
+                        neighbour[0] as usize,
+                        neighbour[1] as usize,
+                        neighbour[2] as usize,
+                    );
+
+                    if !visited[neighbour_index] {
+                        visited[neighbour_index] = true;
+                        queue.push(neighbour_index);
+                    }
+                }
+            }
+
+            visibility.connect_all(touched_faces);
+        }
+
+        visibility
+    }
+}
+
+/// Flood-fills outwards from the section the camera is in and returns every section which could be visible.
+/// Returns None if the camera isn't inside of a loaded section, in which case everything has to be drawn.
+pub fn visible_sections(
+    chunks: &HashMap<ChunkPos, ArcSwap<Chunk>>,
+    camera_position: [f32; 3],
+    frustum: &Frustum<f32>,
+) -> Option<HashSet<(ChunkPos, usize)>> {
+    let start_pos = [
+        (camera_position[0] / CHUNK_WIDTH as f32).floor() as i32,
+        (camera_position[2] / CHUNK_WIDTH as f32).floor() as i32,
+    ];
+    let start_section = (camera_position[1] / SECTION_SIZE as f32).floor();
+
+    if start_section < 0.0 || start_section >= CHUNK_SECTIONS_PER as f32 {
+        return None;
+    }
+
+    let start_section = start_section as usize;
+
+    if !chunks.contains_key(&start_pos) {
+        return None;
+    }
+
+    let mut visible = HashSet::new();
+    //Section, the face it was entered through, and a mask of the directions travelled to get there
+    let mut queue: VecDeque<(ChunkPos, usize, Option<usize>, u8)> = VecDeque::new();
+
+    visible.insert((start_pos, start_section));
+    queue.push_back((start_pos, start_section, None, 0));
+
+    while let Some((pos, section, entered, travelled)) = queue.pop_front() {
+        let visibility = match chunks.get(&pos) {
+            None => continue,
+            Some(chunk) => chunk.load().visibility.read()[section],
+        };
+
+        for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+            //Never go back towards the camera
+            if travelled & (1 << OPPOSITE[face]) != 0 {
+                continue;
+            }
+
+            if let Some(entered) = entered {
+                if !visibility.connects(entered, face) {
+                    continue;
+                }
+            }
+
+            let neighbour_section = section as i32 + offset[1];
+
+            if neighbour_section < 0 || neighbour_section >= CHUNK_SECTIONS_PER as i32 {
+                continue;
+            }
+
+            let neighbour = (
+                [pos[0] + offset[0], pos[1] + offset[2]],
+                neighbour_section as usize,
+            );
+
+            if visible.contains(&neighbour) || !chunks.contains_key(&neighbour.0) {
+                continue;
+            }
+
+            let min = Vec3::new(
+                neighbour.0[0] as f32 * CHUNK_WIDTH as f32,
+                (neighbour.1 * SECTION_SIZE) as f32,
+                neighbour.0[1] as f32 * CHUNK_WIDTH as f32,
+            );
+            let max = min + Vec3::new(16.0, 16.0, 16.0);
+
+            if AABB::<f32>::new(min, max).test_against_frustum(frustum, 0) == u8::MAX {
+                continue;
+            }
+
+            visible.insert(neighbour);
+            queue.push_back((
+                neighbour.0,
+                neighbour.1,
+                Some(OPPOSITE[face]),
+                travelled | (1 << face),
+            ));
+        }
+    }
+
+    Some(visible)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use arc_swap::ArcSwap;
+    use cgmath::{Matrix4, Vector3};
+    use indexmap::IndexMap;
+    use treeculler::Frustum;
+
+    use super::{visible_sections, SectionVisibility};
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::{BlockstateKey, ChunkBlockState, ModelMesh, RenderType};
+    use crate::mc::chunk::{BlockStateProvider, Chunk};
+    use crate::mc::{Block, BlockManager};
+
+    const NORTH: usize = 0;
+    const EAST: usize = 1;
+    const SOUTH: usize = 2;
+    const WEST: usize = 3;
+    const UP: usize = 4;
+    const DOWN: usize = 5;
+
+    /// Has a full opaque cube wherever the function says so, and air everywhere else
+    #[derive(Debug)]
+    struct OpaqueProvider(fn(i32, i16, i32) -> bool);
+
+    impl BlockStateProvider for OpaqueProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            if (self.0)(x, y, z) {
+                ChunkBlockState::State(BlockstateKey {
+                    block: 0,
+                    augment: 0,
+                })
+            } else {
+                ChunkBlockState::Air
+            }
+        }
+
+        fn is_section_empty(&self, _index: usize) -> bool {
+            false
+        }
+    }
+
+    fn block_manager() -> BlockManager {
+        let stone = Arc::new(ModelMesh {
+            models: Vec::new(),
+            is_full_opaque_cube: true,
+            render_type: RenderType::Solid,
+            weights: Vec::new(),
+        });
+
+        BlockManager {
+            blocks: [(
+                "minecraft:stone".into(),
+                Block::Variants(IndexMap::from([("".into(), stone)])),
+            )]
+            .into_iter()
+            .collect(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        }
+    }
+
+    fn compute(opaque: fn(i32, i16, i32) -> bool) -> SectionVisibility {
+        SectionVisibility::compute(&block_manager(), &OpaqueProvider(opaque), [0, 0], 0)
+    }
+
+    /// Contains everything within 1000 blocks of the origin, with depths between 0 and 1
+    fn frustum() -> Frustum<f32> {
+        let matrix = Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.5))
+            * Matrix4::from_nonuniform_scale(0.001, 0.001, 0.0005);
+
+        Frustum::from_modelview_projection(matrix.into())
+    }
+
+    #[test]
+    fn a_wall_separates_the_faces_on_either_side() {
+        //Fills the section from west to east and from bottom to top, halfway between north and south
+        let visibility = compute(|_, _, z| z == 8);
+
+        assert!(!visibility.connects(NORTH, SOUTH));
+        assert!(!visibility.connects(SOUTH, NORTH));
+
+        //Both halves still touch the faces along the wall
+        assert!(visibility.connects(NORTH, EAST));
+        assert!(visibility.connects(SOUTH, WEST));
+        assert!(visibility.connects(EAST, WEST));
+        assert!(visibility.connects(UP, DOWN));
+    }
+
+    #[test]
+    fn a_tunnel_connects_its_ends() {
+        //Solid except for a tunnel from west to east
+        let visibility = compute(|_, y, z| !(y == 8 && z == 8));
+
+        assert!(visibility.connects(WEST, EAST));
+        assert!(visibility.connects(EAST, WEST));
+
+        assert!(!visibility.connects(NORTH, SOUTH));
+        assert!(!visibility.connects(UP, DOWN));
+        assert!(!visibility.connects(WEST, UP));
+    }
+
+    #[test]
+    fn sealed_sections_below_the_camera_are_not_crossed() {
+        let chunk = Chunk::new([0, 0]);
+
+        //Stone below the camera with a cave underneath it
+        chunk.visibility.write()[4] = SectionVisibility::NONE;
+
+        let chunks = HashMap::from([([0, 0], ArcSwap::new(Arc::new(chunk)))]);
+        let camera_position = [8.0, 5.5 * 16.0, 8.0];
+
+        let visible = visible_sections(&chunks, camera_position, &frustum()).unwrap();
+
+        assert!(visible.contains(&([0, 0], 5)));
+        assert!(visible.contains(&([0, 0], 6)));
+        //The top of the stone can be seen, but not through it
+        assert!(visible.contains(&([0, 0], 4)));
+        assert!(!visible.contains(&([0, 0], 3)));
+        assert!(!visible.contains(&([0, 0], 0)));
+
+        //Until a tunnel is dug through it
+        let mut tunnel = SectionVisibility::NONE;
+        tunnel.connect_all((1 << UP) | (1 << DOWN));
+        chunks[&[0, 0]].load().visibility.write()[4] = tunnel;
+
+        let visible = visible_sections(&chunks, camera_position, &frustum()).unwrap();
+
+        assert!(visible.contains(&([0, 0], 3)));
+        assert!(visible.contains(&([0, 0], 0)));
+    }
+}
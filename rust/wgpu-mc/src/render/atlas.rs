@@ -1,26 +1,70 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
 use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use arc_swap::ArcSwap;
 use bytemuck::{Pod, Zeroable};
 use guillotiere::euclid::Size2D;
 use guillotiere::AtlasAllocator;
-use image::imageops::overlay;
-use image::{GenericImageView, ImageBuffer, Rgba};
-use minecraft_assets::schemas;
+use image::imageops::{overlay, replace, resize, FilterType};
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage};
 use parking_lot::RwLock;
+use serde_derive::Deserialize;
 use wgpu::Extent3d;
 
+use crate::mc::block::EMISSIVE_SUFFIX;
 use crate::mc::resource::{ResourcePath, ResourceProvider};
-use crate::render::pipeline::WmPipelines;
+use crate::render::pipeline::{WmPipelines, BLOCK_ATLAS, ENTITY_ATLAS, GUI_ATLAS, PARTICLE_ATLAS};
 use crate::texture::{BindableTexture, TextureSamplerView, UV};
 use crate::{WgpuState, WmRenderer};
 
 /// The width and height of an [atlas](Atlas];
 pub const ATLAS_DIMENSIONS: u32 = 2048;
 
+/// How far baked UVs are moved inside the edges of their texture in an atlas without [padding](Atlas::padding),
+/// in atlas pixels
+const UNPADDED_UV_INSET: f32 = 1.0 / 32.0;
+
+#[derive(Debug)]
+pub enum AtlasError {
+    /// The texture hasn't been allocated in this atlas
+    NotAllocated(ResourcePath),
+    /// The new texture is a different size than the one in the atlas; the atlas has to be repacked instead
+    SizeMismatch {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// The pixel data is too short for the given dimensions
+    InvalidPixelData,
+    /// There's no space left for the texture, and the atlas can't grow any larger
+    Full { max_size: u32 },
+}
+
+impl Display for AtlasError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotAllocated(path) => write!(f, "{path} hasn't been allocated in the atlas"),
+            Self::SizeMismatch { expected, actual } => write!(
+                f,
+                "The texture is {}x{}, but the one in the atlas is {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Self::InvalidPixelData => write!(f, "The pixel data is too short for its size"),
+            Self::Full { max_size } => write!(
+                f,
+                "The atlas is full and can't grow past {max_size}x{max_size}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
 /// A texture atlas. This is used in many places, most notably terrain and entity rendering.
 /// Combines multiple small textures into a single big one, which can help improve performance.
 ///
@@ -53,7 +97,7 @@ pub const ATLAS_DIMENSIONS: u32 = 2048;
 ///             &resource_provider.get_bytes(&dirt).unwrap()
 ///         )
 ///     ], &*resource_provider
-/// );
+/// ).unwrap();
 ///
 /// atlas.upload(&wm_renderer);
 /// ```
@@ -66,13 +110,27 @@ pub struct Atlas {
     pub uv_map: RwLock<HashMap<ResourcePath, UV>>,
     /// The representation of the [Atlas]'s image buffer on the GPU, which can be bound to a draw call
     pub bindable_texture: Arc<ArcSwap<BindableTexture>>,
-    /// Not every [Atlas] is used for block textures, but the ones that are store the information for each animated texture here
-    pub animated_textures: RwLock<Vec<schemas::texture::TextureAnimation>>,
+    /// The atlases of the LabPBR maps of the textures, with the same layout as this one. Empty unless they're
+    /// added with [Atlas::with_pbr_maps].
+    pub pbr_maps: Vec<PbrAtlas>,
+    /// Where the pixels of each texture came from, see [Atlas::refresh]
+    pub sprites: RwLock<HashMap<ResourcePath, SpriteSource>>,
+    /// The textures animated by their `.png.mcmeta`, see [Atlas::tick_animations]
+    pub animated_textures: RwLock<Vec<AnimatedTexture>>,
     ///
     pub animated_texture_offsets: RwLock<HashMap<ResourcePath, u32>>,
     pub resizes: bool,
+    /// How many pixels each texture is extruded by on every side, repeating its edge pixels, so that sampling at
+    /// the edge of a texture doesn't bleed into its neighbours. Set with [Atlas::with_padding].
+    pub padding: u32,
+    /// The largest this atlas is allowed to grow to, if it [resizes](Atlas::resizes)
+    pub max_size: u32,
     size: RwLock<u32>,
     gpu_size: RwLock<u32>,
+    /// Incremented every time the atlas grows, see [Atlas::generation]
+    generation: AtomicU64,
+    /// How many times [Atlas::tick_animations] has been called
+    animation_ticks: AtomicU64,
 }
 
 impl Debug for Atlas {
@@ -83,66 +141,187 @@ impl Debug for Atlas {
 
 impl Atlas {
     pub fn new(wgpu_state: &WgpuState, pipelines: &WmPipelines, resizes: bool) -> Self {
-        let bindable_texture = BindableTexture::from_tsv(
-            wgpu_state,
-            pipelines,
-            TextureSamplerView::from_rgb_bytes(
-                wgpu_state,
-                &vec![0u8; (ATLAS_DIMENSIONS * ATLAS_DIMENSIONS) as usize * 4],
-                Extent3d {
-                    width: ATLAS_DIMENSIONS,
-                    height: ATLAS_DIMENSIONS,
-                    depth_or_array_layers: 1,
-                },
-                None,
-                wgpu::TextureFormat::Rgba8Unorm,
-            )
-            .unwrap(),
-            false,
-        );
+        Self::with_size(wgpu_state, pipelines, ATLAS_DIMENSIONS, resizes)
+    }
+
+    /// Create an atlas with the given width and height, which must be a power of two. If the atlas resizes,
+    /// it can grow up to the device's maximum texture size, or the size set with [Atlas::with_max_size].
+    pub fn with_size(
+        wgpu_state: &WgpuState,
+        pipelines: &WmPipelines,
+        size: u32,
+        resizes: bool,
+    ) -> Self {
+        assert!(size.is_power_of_two(), "Atlas size must be a power of two");
+
+        let bindable_texture = create_texture(wgpu_state, pipelines, &ImageBuffer::new(size, size));
 
         Self {
-            allocator: RwLock::new(AtlasAllocator::new(Size2D::new(
-                ATLAS_DIMENSIONS as i32,
-                ATLAS_DIMENSIONS as i32,
-            ))),
-            image: RwLock::new(ImageBuffer::new(ATLAS_DIMENSIONS, ATLAS_DIMENSIONS)),
+            allocator: RwLock::new(AtlasAllocator::new(Size2D::new(size as i32, size as i32))),
+            image: RwLock::new(ImageBuffer::new(size, size)),
             uv_map: Default::default(),
+            pbr_maps: Vec::new(),
+            sprites: Default::default(),
             bindable_texture: Arc::new(ArcSwap::new(Arc::new(bindable_texture))),
             animated_textures: RwLock::new(Vec::new()),
             animated_texture_offsets: Default::default(),
-            size: RwLock::new(ATLAS_DIMENSIONS),
-            gpu_size: RwLock::new(ATLAS_DIMENSIONS),
+            size: RwLock::new(size),
+            gpu_size: RwLock::new(size),
+            generation: AtomicU64::new(0),
+            animation_ticks: AtomicU64::new(0),
             resizes,
+            padding: 0,
+            max_size: wgpu_state.device.limits().max_texture_dimension_2d,
         }
     }
 
-    /// Add multiple textures to the atlas. This automatically handles .mcmeta files when dealing with block textures
+    /// Adds an atlas for each of the [PbrMap]s, see [Atlas::set_pbr_map]
+    #[must_use]
+    pub fn with_pbr_maps(mut self, wgpu_state: &WgpuState, pipelines: &WmPipelines) -> Self {
+        let size = self.size();
+
+        self.pbr_maps = PbrMap::ALL
+            .into_iter()
+            .map(|map| {
+                let image = RgbaImage::from_pixel(size, size, map.default_pixel());
+
+                PbrAtlas {
+                    map,
+                    bindable_texture: Arc::new(ArcSwap::new(Arc::new(create_texture(
+                        wgpu_state, pipelines, &image,
+                    )))),
+                    image: RwLock::new(image),
+                }
+            })
+            .collect();
+
+        self
+    }
+
+    /// Extrudes the textures allocated from now on by the padding, see [Atlas::padding]
+    #[must_use]
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Stops the atlas from growing past the size, see [Atlas::max_size]. An atlas created with the size doesn't
+    /// grow at all.
+    #[must_use]
+    pub fn with_max_size(mut self, max_size: u32) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// The current width and height of the atlas image
+    pub fn size(&self) -> u32 {
+        *self.size.read()
+    }
+
+    /// How far the emissive overlay of the texture is from it, from 0 to 1, if both are in the atlas. The overlay
+    /// is allocated with the name of the texture followed by [crate::mc::block::EMISSIVE_SUFFIX].
+    pub fn emissive_offset(&self, texture: &ResourcePath) -> Option<[f32; 2]> {
+        let uv_map = self.uv_map.read();
+        let ((min_x, min_y), _) = uv_map.get(texture)?;
+        let ((emissive_x, emissive_y), _) = uv_map.get(&texture.append(EMISSIVE_SUFFIX))?;
+        let size = self.size() as f32;
+
+        Some([(emissive_x - min_x) / size, (emissive_y - min_y) / size])
+    }
+
+    /// How far the UVs of faces are moved inside the edges of their texture when they're baked, in atlas pixels.
+    /// Padded textures don't need it, as their edge pixels are repeated past the edges.
+    pub fn uv_inset(&self) -> f32 {
+        if self.padding == 0 {
+            UNPADDED_UV_INSET
+        } else {
+            0.0
+        }
+    }
+
+    /// Changes whenever the atlas grows. Growing repacks the textures and changes the size UVs are divided by, so
+    /// anything which baked UVs of this atlas in a previous generation has to bake them again.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Add multiple textures to the atlas. This automatically handles .mcmeta files when dealing with block textures,
+    /// and the LabPBR maps of the textures if the atlas has [PbrAtlas]es, see [pbr_map_file]
     pub fn allocate<'a, T>(
         &self,
         images: impl IntoIterator<Item = (&'a ResourcePath, &'a T)>,
         resource_provider: &dyn ResourceProvider,
-    ) where
+    ) -> Result<(), AtlasError>
+    where
         T: AsRef<[u8]> + 'a,
     {
-        let mut allocator = self.allocator.write();
-        let mut image_buffer = self.image.write();
-        let mut map = self.uv_map.write();
-
-        let mut animated_textures = self.animated_textures.write();
-        // let mut animated_texture_offsets = self.animated_texture_offsets.write();
-
-        images.into_iter().for_each(|(name, slice)| {
-            self.allocate_one(
-                &mut image_buffer,
-                &mut map,
-                &mut allocator,
-                &mut animated_textures,
-                name,
-                slice.as_ref(),
-                resource_provider,
-            );
-        });
+        self.allocate_images(images, resource_provider, false)
+    }
+
+    /// Like [Atlas::allocate], but skips the textures which are already in the atlas. Whether they are is checked
+    /// while the atlas is locked for the allocation, so threads baking models in parallel which need the same
+    /// texture don't both allocate it.
+    pub fn allocate_missing<'a, T>(
+        &self,
+        images: impl IntoIterator<Item = (&'a ResourcePath, &'a T)>,
+        resource_provider: &dyn ResourceProvider,
+    ) -> Result<(), AtlasError>
+    where
+        T: AsRef<[u8]> + 'a,
+    {
+        self.allocate_images(images, resource_provider, true)
+    }
+
+    fn allocate_images<'a, T>(
+        &self,
+        images: impl IntoIterator<Item = (&'a ResourcePath, &'a T)>,
+        resource_provider: &dyn ResourceProvider,
+        skip_allocated: bool,
+    ) -> Result<(), AtlasError>
+    where
+        T: AsRef<[u8]> + 'a,
+    {
+        let mut allocated = Vec::new();
+
+        {
+            let mut allocator = self.allocator.write();
+            let mut image_buffer = self.image.write();
+            let mut map = self.uv_map.write();
+
+            let mut animated_textures = self.animated_textures.write();
+            // let mut animated_texture_offsets = self.animated_texture_offsets.write();
+
+            images.into_iter().try_for_each(|(name, slice)| {
+                if skip_allocated && map.contains_key(name) {
+                    return Ok(());
+                }
+
+                allocated.push(name);
+
+                self.allocate_one(
+                    &mut image_buffer,
+                    &mut map,
+                    &mut allocator,
+                    &mut animated_textures,
+                    name,
+                    slice.as_ref(),
+                    resource_provider,
+                )
+            })?;
+        }
+
+        //The maps go into the atlases of the maps, at the same place as their texture
+        for pbr in &self.pbr_maps {
+            for path in &allocated {
+                if let Some(bytes) =
+                    pbr_map_file(path, pbr.map).and_then(|file| resource_provider.get_bytes(&file))
+                {
+                    self.set_pbr_map(path, pbr.map, &bytes)?;
+                }
+            }
+        }
+
+        Ok(())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -151,36 +330,83 @@ impl Atlas {
         image_buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>,
         map: &mut HashMap<ResourcePath, UV>,
         allocator: &mut AtlasAllocator,
-        animated_textures: &mut Vec<schemas::texture::TextureAnimation>,
+        animated_textures: &mut Vec<AnimatedTexture>,
         path: &ResourcePath,
         image_bytes: &[u8],
         resource_provider: &dyn ResourceProvider,
-    ) {
+    ) -> Result<(), AtlasError> {
         let image = image::load_from_memory(image_bytes).unwrap();
 
+        let animation = resource_provider
+            .get_string(&path.append(".mcmeta"))
+            .and_then(|string| serde_json::from_str::<TextureMeta>(&string).ok())
+            .and_then(|meta| meta.animation)
+            .and_then(|animation| AnimatedTexture::new(path.clone(), image.to_rgba8(), animation));
+
+        //Only the frame which is shown takes up space in the atlas
+        let image = match &animation {
+            Some(animation) => DynamicImage::ImageRgba8(animation.frame_at(0).into_owned()),
+            None => image,
+        };
+
+        let padding = self.padding;
+
         let allocation = match (
-            allocator.allocate(Size2D::new(image.width() as i32, image.height() as i32)),
+            allocator.allocate(Size2D::new(
+                (image.width() + 2 * padding) as i32,
+                (image.height() + 2 * padding) as i32,
+            )),
             self.resizes,
         ) {
             (Some(alloc), _) => alloc,
             (None, true) => {
                 let mut size = self.size.write();
                 let old_size = *size;
-                let new_size = old_size + 1024;
-                *size = new_size;
 
+                if old_size >= self.max_size {
+                    return Err(AtlasError::Full {
+                        max_size: self.max_size,
+                    });
+                }
+
+                //The textures which are already allocated have to fit as well as the new one
+                let mut new_size = old_size;
+
+                let (new_allocator, new_image, new_map) = loop {
+                    new_size = (new_size * 2).min(self.max_size);
+
+                    if let Some(repacked) = repack(image_buffer, map, new_size, padding) {
+                        break repacked;
+                    }
+
+                    if new_size >= self.max_size {
+                        return Err(AtlasError::Full {
+                            max_size: self.max_size,
+                        });
+                    }
+                };
+
+                *size = new_size;
                 drop(size);
 
-                allocator.grow(Size2D::new(new_size as i32, new_size as i32));
+                //The PBR maps move along with their textures
+                for pbr in &self.pbr_maps {
+                    let mut pbr_image = pbr.image.write();
+                    *pbr_image = relayout(
+                        &pbr_image,
+                        map,
+                        &new_map,
+                        new_size,
+                        padding,
+                        pbr.map.default_pixel(),
+                    );
+                }
 
-                let mut new_image = ImageBuffer::new(new_size, new_size);
-                overlay(
-                    &mut new_image,
-                    &*image_buffer.view(0, 0, old_size, old_size),
-                    0,
-                    0,
-                );
+                *allocator = new_allocator;
                 *image_buffer = new_image;
+                *map = new_map;
+
+                self.generation.fetch_add(1, Ordering::AcqRel);
 
                 return self.allocate_one(
                     image_buffer,
@@ -192,105 +418,716 @@ impl Atlas {
                     resource_provider,
                 );
             }
-            (None, false) => panic!("Atlas allocation failed: no more space"),
+            (None, false) => {
+                return Err(AtlasError::Full {
+                    max_size: *self.size.read(),
+                })
+            }
         };
 
         overlay(
             image_buffer,
-            &image,
+            &extrude(&image.to_rgba8(), padding),
             allocation.rectangle.min.x as i64,
             allocation.rectangle.min.y as i64,
         );
 
-        let mcmeta_path = path.append(".mcmeta");
+        animated_textures.retain(|animated| animated.path != *path);
+        animated_textures.extend(animation);
 
-        let mcmeta = resource_provider
-            .get_string(&mcmeta_path)
-            .and_then(|string| serde_json::from_str::<schemas::texture::Texture>(&string).ok());
+        self.sprites
+            .write()
+            .insert(path.clone(), SpriteSource::new(None, image_bytes));
 
-        if let Some(texture) = mcmeta {
-            if let Some(animation) = texture.animation {
-                animated_textures.push(animation)
-            }
-        }
+        //The UVs are of the texture itself, without the padding around it
+        let padding = padding as i32;
 
         map.insert(
             path.clone(),
             (
                 (
-                    allocation.rectangle.min.x as f32,
-                    allocation.rectangle.min.y as f32,
+                    (allocation.rectangle.min.x + padding) as f32,
+                    (allocation.rectangle.min.y + padding) as f32,
                 ),
                 (
-                    allocation.rectangle.max.x as f32,
-                    allocation.rectangle.max.y as f32,
+                    (allocation.rectangle.max.x - padding) as f32,
+                    (allocation.rectangle.max.y - padding) as f32,
                 ),
             ),
         );
+
+        Ok(())
     }
 
     /// Upload the atlas texture to the GPU. If the Atlas has to resize the texture on the GPU, then the bindable_texture that this struct provides may
     /// become obsolete if you .load() the BindableTexture before calling upload(), so you should get the BindableTexture after calling this function and not before-hand.
     /// Returns true if the atlas was resized.
     pub fn upload(&self, wm: &WmRenderer) -> bool {
-        if self.resizes && *self.size.read() != *self.gpu_size.read() {
-            let size = *self.size.read();
-
-            let bindable_texture = BindableTexture::from_tsv(
-                &wm.wgpu_state,
-                &wm.pipelines.load(),
-                TextureSamplerView::from_rgb_bytes(
-                    &wm.wgpu_state,
-                    self.image.read().as_raw(),
-                    Extent3d {
-                        width: size,
-                        height: size,
-                        depth_or_array_layers: 1,
-                    },
-                    None,
-                    wgpu::TextureFormat::Rgba8Unorm,
-                )
-                .unwrap(),
-                false,
-            );
+        let size = *self.size.read();
+        let resized = self.resizes && size != *self.gpu_size.read();
 
-            self.bindable_texture.store(Arc::new(bindable_texture));
+        upload_image(wm, &self.image.read(), &self.bindable_texture, resized);
 
+        for pbr in &self.pbr_maps {
+            upload_image(wm, &pbr.image.read(), &pbr.bindable_texture, resized);
+        }
+
+        if resized {
             *self.gpu_size.write() = size;
+        }
+
+        resized
+    }
 
-            return true;
+    /// Writes the LabPBR map of a texture which is already in the atlas into the atlas of the map, at the same
+    /// place as the texture. Maps of another size than the texture, like animated ones with their frames below
+    /// each other, are cropped to a square and scaled to fit. Does nothing if the atlas has no atlases for PBR
+    /// maps. Uploaded with [Atlas::upload].
+    pub fn set_pbr_map(
+        &self,
+        path: &ResourcePath,
+        map: PbrMap,
+        image_bytes: &[u8],
+    ) -> Result<(), AtlasError> {
+        let pbr = match self.pbr_maps.iter().find(|pbr| pbr.map == map) {
+            Some(pbr) => pbr,
+            None => return Ok(()),
+        };
+
+        let ((min_x, min_y), (max_x, max_y)) = *self
+            .uv_map
+            .read()
+            .get(path)
+            .ok_or_else(|| AtlasError::NotAllocated(path.clone()))?;
+
+        let (width, height) = ((max_x - min_x) as u32, (max_y - min_y) as u32);
+
+        let image = image::load_from_memory(image_bytes)
+            .map_err(|_| AtlasError::InvalidPixelData)?
+            .to_rgba8();
+
+        let frame = image.view(0, 0, image.width(), image.height().min(image.width()));
+        let image = if frame.dimensions() == (width, height) {
+            frame.to_image()
+        } else {
+            resize(&*frame, width, height, FilterType::Nearest)
+        };
+
+        replace(
+            &mut *pbr.image.write(),
+            &extrude(&image, self.padding),
+            (min_x as u32 - self.padding) as i64,
+            (min_y as u32 - self.padding) as i64,
+        );
+
+        Ok(())
+    }
+
+    /// Replace the pixels of a texture which has already been allocated, without repacking the atlas. The new
+    /// pixels are written into the same region of both the CPU image and the GPU texture, along with its
+    /// [padding](Atlas::padding). As the GPU texture is updated in place, any existing bind groups stay valid.
+    pub fn update_texture(
+        &self,
+        wm: &WmRenderer,
+        id: &ResourcePath,
+        new_pixels: &[u8],
+        width: u32,
+        height: u32,
+    ) -> Result<(), AtlasError> {
+        let ((min_x, min_y), (max_x, max_y)) = *self
+            .uv_map
+            .read()
+            .get(id)
+            .ok_or_else(|| AtlasError::NotAllocated(id.clone()))?;
+
+        let expected = ((max_x - min_x) as u32, (max_y - min_y) as u32);
+
+        if expected != (width, height) {
+            return Err(AtlasError::SizeMismatch {
+                expected,
+                actual: (width, height),
+            });
         }
 
-        let size = *self.size.read();
+        let pixels = new_pixels
+            .get(..(width * height * 4) as usize)
+            .ok_or(AtlasError::InvalidPixelData)?;
+
+        let new_image: ImageBuffer<Rgba<u8>, &[u8]> =
+            ImageBuffer::from_raw(width, height, pixels).ok_or(AtlasError::InvalidPixelData)?;
+
+        let padding = self.padding;
+        let padded = extrude(&new_image, padding);
+        let (x, y) = (min_x as u32 - padding, min_y as u32 - padding);
+
+        replace(&mut *self.image.write(), &padded, x as i64, y as i64);
+
+        //The atlas grew since it was uploaded, the next upload creates the new texture from the image
+        if *self.gpu_size.read() != *self.size.read() {
+            return Ok(());
+        }
 
         wm.wgpu_state.queue.write_texture(
-            self.bindable_texture.load().tsv.texture.as_image_copy(),
-            self.image.read().as_raw(),
+            wgpu::ImageCopyTexture {
+                texture: &self.bindable_texture.load().tsv.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            padded.as_raw(),
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * size),
-                rows_per_image: NonZeroU32::new(size),
+                bytes_per_row: NonZeroU32::new(4 * padded.width()),
+                rows_per_image: NonZeroU32::new(padded.height()),
             },
             Extent3d {
-                width: size,
-                height: size,
+                width: padded.width(),
+                height: padded.height(),
                 depth_or_array_layers: 1,
             },
         );
 
-        false
+        Ok(())
+    }
+
+    /// Advances the animated textures by a tick, which vanilla does 20 times a second, and writes the frames which
+    /// changed into the atlas
+    pub fn tick_animations(&self, wm: &WmRenderer) {
+        let tick = self.animation_ticks.fetch_add(1, Ordering::Relaxed) + 1;
+
+        for animation in self.animated_textures.read().iter() {
+            if !animation.changes_at(tick) {
+                continue;
+            }
+
+            let frame = animation.frame_at(tick);
+
+            //The texture can only be missing if the atlas was cleared in between
+            let _ = self.update_texture(
+                wm,
+                &animation.path,
+                frame.as_raw(),
+                frame.width(),
+                frame.height(),
+            );
+        }
+    }
+
+    /// Clears the atlas and allocates the textures into it again, then uploads it. The texture on the GPU is kept
+    /// unless it has to grow, so the bind groups of the atlas stay valid, and other atlases aren't touched.
+    pub fn rebuild<'a, T>(
+        &self,
+        wm: &WmRenderer,
+        images: impl IntoIterator<Item = (&'a ResourcePath, &'a T)>,
+        resource_provider: &dyn ResourceProvider,
+    ) -> Result<(), AtlasError>
+    where
+        T: AsRef<[u8]> + 'a,
+    {
+        self.clear();
+        self.allocate(images, resource_provider)?;
+        self.upload(wm);
+
+        Ok(())
+    }
+
+    /// Loads every texture which is in the atlas again and rebuilds it with them, see [Atlas::rebuild]. `file` maps
+    /// a texture to the file it's loaded from, like [texture_file]. Textures whose file is gone are dropped. As the
+    /// textures are packed again and their UVs move, the [Atlas::generation] changes.
+    pub fn reload(
+        &self,
+        wm: &WmRenderer,
+        resource_provider: &dyn ResourceProvider,
+        file: impl Fn(&ResourcePath) -> ResourcePath,
+    ) -> Result<(), AtlasError> {
+        let images: Vec<(ResourcePath, Vec<u8>)> = self
+            .uv_map
+            .read()
+            .keys()
+            .filter_map(|texture| {
+                Some((
+                    texture.clone(),
+                    resource_provider.get_bytes(&file(texture))?,
+                ))
+            })
+            .collect();
+
+        self.rebuild(
+            wm,
+            images.iter().map(|(texture, bytes)| (texture, bytes)),
+            resource_provider,
+        )?;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+
+        Ok(())
+    }
+
+    /// Loads the textures which are already in the atlas again, like [Atlas::rebuild] but only touching the ones
+    /// whose file changed. `file` maps a texture to the file it's loaded from. Textures which are the same size as
+    /// before are written over in place, others are allocated again and their UV moves, so only the latter need
+    /// anything baked against the atlas to be baked again. That's also the case if the atlas had to grow, see
+    /// [Atlas::generation]. The space of the textures which moved stays unused until the atlas is cleared. The
+    /// caller uploads the atlas afterwards if any UV moved.
+    pub fn refresh(
+        &self,
+        wm: &WmRenderer,
+        resource_provider: &dyn ResourceProvider,
+        file: impl Fn(&ResourcePath) -> ResourcePath,
+    ) -> Result<AtlasRefresh, AtlasError> {
+        let mut refresh = AtlasRefresh::default();
+        let paths: Vec<ResourcePath> = self.uv_map.read().keys().cloned().collect();
+
+        for path in paths {
+            let file = file(&path);
+
+            let bytes = match resource_provider.get_bytes(&file) {
+                Some(bytes) => bytes,
+                None => {
+                    refresh.missing.push(path);
+                    continue;
+                }
+            };
+
+            let source = SpriteSource::new(resource_provider.source(&file), &bytes);
+
+            let animated = self
+                .animated_textures
+                .read()
+                .iter()
+                .any(|animated| animated.path == path);
+
+            let change = sprite_change(
+                self.sprites.read().get(&path),
+                &source,
+                &bytes,
+                self.sprite_size(&path),
+                animated,
+            );
+
+            match change {
+                SpriteChange::Unchanged => {}
+                SpriteChange::Update(image) => {
+                    self.update_texture(wm, &path, image.as_raw(), image.width(), image.height())?;
+                    refresh.updated.push(path.clone());
+                }
+                SpriteChange::Move => {
+                    self.allocate([(&path, &bytes)], resource_provider)?;
+                    refresh.moved.push(path.clone());
+                }
+            }
+
+            self.sprites.write().insert(path, source);
+        }
+
+        Ok(refresh)
+    }
+
+    /// The width and height of the texture in the atlas
+    fn sprite_size(&self, path: &ResourcePath) -> Option<(u32, u32)> {
+        let ((min_x, min_y), (max_x, max_y)) = *self.uv_map.read().get(path)?;
+
+        Some(((max_x - min_x) as u32, (max_y - min_y) as u32))
     }
 
     pub fn clear(&self) {
         let size = *self.size.read();
 
         self.allocator.write().clear();
+        self.uv_map.write().clear();
+        self.sprites.write().clear();
+
+        for pbr in &self.pbr_maps {
+            *pbr.image.write() = RgbaImage::from_pixel(size, size, pbr.map.default_pixel());
+        }
+
         self.animated_texture_offsets.write().clear();
         self.animated_textures.write().clear();
         *self.image.write() = ImageBuffer::new(size, size);
     }
 }
 
+/// The image with its edge pixels repeated outwards by the padding on every side
+fn extrude<I: GenericImageView<Pixel = Rgba<u8>>>(image: &I, padding: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+
+    RgbaImage::from_fn(width + 2 * padding, height + 2 * padding, |x, y| {
+        image.get_pixel(
+            x.saturating_sub(padding).min(width - 1),
+            y.saturating_sub(padding).min(height - 1),
+        )
+    })
+}
+
+/// The LabPBR maps which packs can give textures, in a file next to the texture with the suffix of the map
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PbrMap {
+    /// `_n`, the normal in red and green, ambient occlusion in blue and the height in alpha
+    Normal,
+    /// `_s`, the smoothness in red, the reflectance in green, porosity or subsurface scattering in blue and
+    /// emission in alpha
+    Specular,
+}
+
+impl PbrMap {
+    pub const ALL: [PbrMap; 2] = [PbrMap::Normal, PbrMap::Specular];
+
+    pub fn suffix(self) -> &'static str {
+        match self {
+            PbrMap::Normal => "_n",
+            PbrMap::Specular => "_s",
+        }
+    }
+
+    /// The resource of the shader graph which binds the map of the atlas, like
+    /// `wm_texture_atlas_blocks_normal`
+    pub fn resource(self, kind: AtlasKind) -> String {
+        match self {
+            PbrMap::Normal => format!("{}_normal", kind.resource()),
+            PbrMap::Specular => format!("{}_specular", kind.resource()),
+        }
+    }
+
+    /// What textures without the map have: a flat surface facing straight out without any occlusion, or no
+    /// specular at all
+    fn default_pixel(self) -> Rgba<u8> {
+        match self {
+            PbrMap::Normal => Rgba([128, 128, 255, 255]),
+            PbrMap::Specular => Rgba([0, 0, 0, 0]),
+        }
+    }
+
+    /// A single pixel of [PbrMap::default_pixel], for atlases without the map
+    #[must_use]
+    pub fn default_texture(
+        self,
+        wgpu_state: &WgpuState,
+        pipelines: &WmPipelines,
+    ) -> BindableTexture {
+        create_texture(
+            wgpu_state,
+            pipelines,
+            &RgbaImage::from_pixel(1, 1, self.default_pixel()),
+        )
+    }
+}
+
+/// The file of the map of a texture in an atlas, next to the texture. Textures are either named by their file, like
+/// the ones of entities, or by their path in `textures/` without the extension, like the ones of block models.
+/// Emissive overlays have no maps of their own.
+fn pbr_map_file(texture: &ResourcePath, map: PbrMap) -> Option<ResourcePath> {
+    let (path, is_file) = match texture.0.strip_suffix(".png") {
+        Some(path) => (path, true),
+        None => (&texture.0[..], false),
+    };
+
+    if path.ends_with(EMISSIVE_SUFFIX) {
+        return None;
+    }
+
+    let map_path = ResourcePath(format!("{path}{}", map.suffix()));
+
+    Some(if is_file {
+        map_path.append(".png")
+    } else {
+        map_path.prepend("textures/").append(".png")
+    })
+}
+
+/// The atlas of a [PbrMap] of an [Atlas]
+pub struct PbrAtlas {
+    pub map: PbrMap,
+    pub image: RwLock<RgbaImage>,
+    /// Replaced when the atlas grows, like [Atlas::bindable_texture]
+    pub bindable_texture: Arc<ArcSwap<BindableTexture>>,
+}
+
+/// A texture of the image, of the `texture` layout
+fn create_texture(
+    wgpu_state: &WgpuState,
+    pipelines: &WmPipelines,
+    image: &RgbaImage,
+) -> BindableTexture {
+    BindableTexture::from_tsv(
+        wgpu_state,
+        pipelines,
+        TextureSamplerView::from_rgb_bytes(
+            wgpu_state,
+            image.as_raw(),
+            Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            },
+            None,
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+        .unwrap(),
+        false,
+    )
+}
+
+/// Writes the whole image to the texture, or replaces the texture with a new one if the image changed size
+fn upload_image(
+    wm: &WmRenderer,
+    image: &RgbaImage,
+    texture: &ArcSwap<BindableTexture>,
+    resized: bool,
+) {
+    if resized {
+        texture.store(Arc::new(create_texture(
+            &wm.wgpu_state,
+            &wm.pipelines.load(),
+            image,
+        )));
+
+        return;
+    }
+
+    wm.wgpu_state.queue.write_texture(
+        texture.load().tsv.texture.as_image_copy(),
+        image.as_raw(),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(4 * image.width()),
+            rows_per_image: NonZeroU32::new(image.height()),
+        },
+        Extent3d {
+            width: image.width(),
+            height: image.height(),
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+/// Moves each texture and its padding from where it is in the old layout to where it is in the new one, for the
+/// atlases which follow the layout of another, like the [PbrAtlas]es. The rest is filled with the background.
+fn relayout(
+    image: &RgbaImage,
+    old_map: &HashMap<ResourcePath, UV>,
+    new_map: &HashMap<ResourcePath, UV>,
+    size: u32,
+    padding: u32,
+    background: Rgba<u8>,
+) -> RgbaImage {
+    let mut new_image = RgbaImage::from_pixel(size, size, background);
+
+    for (path, ((min_x, min_y), (max_x, max_y))) in old_map {
+        let ((new_x, new_y), _) = match new_map.get(path) {
+            Some(uv) => *uv,
+            None => continue,
+        };
+
+        replace(
+            &mut new_image,
+            &*image.view(
+                *min_x as u32 - padding,
+                *min_y as u32 - padding,
+                (max_x - min_x) as u32 + 2 * padding,
+                (max_y - min_y) as u32 + 2 * padding,
+            ),
+            (new_x as u32 - padding) as i64,
+            (new_y as u32 - padding) as i64,
+        );
+    }
+
+    new_image
+}
+
+/// Which file the pixels of a texture in an [Atlas] were loaded from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpriteSource {
+    /// The name of the provider which had the file, see [ResourceProvider::source]. [None] until the atlas is
+    /// [refreshed](Atlas::refresh), as allocating only gets the bytes.
+    pub provider: Option<String>,
+    /// Of the bytes of the file, to tell whether it changed
+    hash: u64,
+}
+
+impl SpriteSource {
+    fn new(provider: Option<String>, bytes: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+
+        Self {
+            provider,
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// What [Atlas::refresh] does with a texture whose file was read again
+#[derive(Debug, PartialEq)]
+enum SpriteChange {
+    /// The file is the same as before
+    Unchanged,
+    /// The pixels changed but not the size, they're written over the old ones in place
+    Update(RgbaImage),
+    /// The texture is allocated again
+    Move,
+}
+
+/// Compares the file of a texture with the one it was loaded from before, `size` being its size in the atlas
+fn sprite_change(
+    previous: Option<&SpriteSource>,
+    source: &SpriteSource,
+    bytes: &[u8],
+    size: Option<(u32, u32)>,
+    animated: bool,
+) -> SpriteChange {
+    if previous.map(|previous| previous.hash) == Some(source.hash) {
+        return SpriteChange::Unchanged;
+    }
+
+    match image::load_from_memory(bytes).map(|image| image.to_rgba8()) {
+        //Animated textures have their frames cut out of the image again, which might change their size
+        Ok(image) if !animated && size == Some(image.dimensions()) => SpriteChange::Update(image),
+        _ => SpriteChange::Move,
+    }
+}
+
+/// The width and height of every texture the resource provider lists in the directories, like
+/// `minecraft:textures/block`. Only the header of each image is decoded. Animated textures count with all their
+/// frames, so this errs on the side of too much space.
+pub fn texture_dimensions(
+    resource_provider: &dyn ResourceProvider,
+    directories: &[ResourcePath],
+) -> Vec<(u32, u32)> {
+    directories
+        .iter()
+        .flat_map(|directory| resource_provider.list(directory))
+        .filter(|texture| texture.0.ends_with(".png"))
+        .filter_map(|texture| {
+            let bytes = resource_provider.get_bytes(&texture)?;
+
+            image::io::Reader::new(std::io::Cursor::new(bytes))
+                .with_guessed_format()
+                .ok()?
+                .into_dimensions()
+                .ok()
+        })
+        .collect()
+}
+
+/// The file a texture of an atlas is loaded from. Textures named after their file, like entity textures and
+/// connected texture tiles, are loaded from it, others like `minecraft:block/stone` from
+/// `minecraft:textures/block/stone.png`.
+pub fn texture_file(texture: &ResourcePath) -> ResourcePath {
+    if texture.0.ends_with(".png") {
+        texture.clone()
+    } else {
+        texture.prepend("textures/").append(".png")
+    }
+}
+
+/// What [Atlas::refresh] did to the textures of the atlas
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AtlasRefresh {
+    /// Textures whose pixels changed and were written over in place
+    pub updated: Vec<ResourcePath>,
+    /// Textures which were allocated again as their size changed, so their UVs moved
+    pub moved: Vec<ResourcePath>,
+    /// Textures whose file is gone, they keep their old pixels
+    pub missing: Vec<ResourcePath>,
+}
+
+/// Packs the textures of an atlas into a new one of the given size, largest first so that they pack more tightly
+/// than the order they were allocated in. The padding around each texture moves along with it. Returns [None] if
+/// they don't all fit.
+fn repack(
+    image: &RgbaImage,
+    map: &HashMap<ResourcePath, UV>,
+    size: u32,
+    padding: u32,
+) -> Option<(AtlasAllocator, RgbaImage, HashMap<ResourcePath, UV>)> {
+    let mut allocator = AtlasAllocator::new(Size2D::new(size as i32, size as i32));
+    let mut new_image = ImageBuffer::new(size, size);
+
+    let mut textures: Vec<(&ResourcePath, &UV)> = map.iter().collect();
+    textures.sort_by(|(a_path, a), (b_path, b)| {
+        let height = |((_, min_y), (_, max_y)): &UV| (max_y - min_y) as u32;
+        let width = |((min_x, _), (max_x, _)): &UV| (max_x - min_x) as u32;
+
+        (height(b), width(b), &a_path.0).cmp(&(height(a), width(a), &b_path.0))
+    });
+
+    let new_map = textures
+        .into_iter()
+        .map(|(path, &((min_x, min_y), (max_x, max_y)))| {
+            let (width, height) = ((max_x - min_x) as u32, (max_y - min_y) as u32);
+            let (padded_width, padded_height) = (width + 2 * padding, height + 2 * padding);
+            let allocation =
+                allocator.allocate(Size2D::new(padded_width as i32, padded_height as i32))?;
+
+            replace(
+                &mut new_image,
+                &*image.view(
+                    min_x as u32 - padding,
+                    min_y as u32 - padding,
+                    padded_width,
+                    padded_height,
+                ),
+                allocation.rectangle.min.x as i64,
+                allocation.rectangle.min.y as i64,
+            );
+
+            let (x, y) = (
+                allocation.rectangle.min.x + padding as i32,
+                allocation.rectangle.min.y + padding as i32,
+            );
+
+            Some((
+                path.clone(),
+                (
+                    (x as f32, y as f32),
+                    ((x + width as i32) as f32, (y + height as i32) as f32),
+                ),
+            ))
+        })
+        .collect::<Option<HashMap<_, _>>>()?;
+
+    Some((allocator, new_image, new_map))
+}
+
+/// The atlases every [TextureManager] has, each created by [WmRenderer::init]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AtlasKind {
+    /// Block textures, the cracks of blocks being broken and anything else baked into chunks
+    Block,
+    /// Entity skins
+    Entity,
+    Particle,
+    Gui,
+}
+
+impl AtlasKind {
+    pub const ALL: [AtlasKind; 4] = [
+        AtlasKind::Block,
+        AtlasKind::Entity,
+        AtlasKind::Particle,
+        AtlasKind::Gui,
+    ];
+
+    /// The label of the atlas, like [BLOCK_ATLAS]
+    pub fn name(self) -> &'static str {
+        match self {
+            AtlasKind::Block => BLOCK_ATLAS,
+            AtlasKind::Entity => ENTITY_ATLAS,
+            AtlasKind::Particle => PARTICLE_ATLAS,
+            AtlasKind::Gui => GUI_ATLAS,
+        }
+    }
+
+    /// The resource of the shader graph which binds the texture of the atlas
+    pub fn resource(self) -> &'static str {
+        match self {
+            AtlasKind::Block => "wm_texture_atlas_blocks",
+            AtlasKind::Entity => "wm_texture_atlas_entities",
+            AtlasKind::Particle => "wm_texture_atlas_particles",
+            AtlasKind::Gui => "wm_texture_atlas_gui",
+        }
+    }
+}
+
 /// Stores uploaded textures which will be automatically updated whenever necessary
 #[derive(Debug)]
 pub struct TextureManager {
@@ -300,7 +1137,7 @@ pub struct TextureManager {
     /// readers for a bit to update the whole map
     pub textures: RwLock<HashMap<ResourcePath, Arc<BindableTexture>>>,
 
-    pub atlases: ArcSwap<HashMap<String, Arc<ArcSwap<Atlas>>>>,
+    pub atlases: ArcSwap<HashMap<AtlasKind, Arc<ArcSwap<Atlas>>>>,
 }
 
 impl TextureManager {
@@ -311,6 +1148,20 @@ impl TextureManager {
             atlases: ArcSwap::new(Arc::new(HashMap::new())),
         }
     }
+
+    /// Panics before [WmRenderer::init] has created the atlases
+    pub fn atlas(&self, kind: AtlasKind) -> Arc<Atlas> {
+        self.atlases
+            .load()
+            .get(&kind)
+            .unwrap_or_else(|| panic!("{} hasn't been created yet", kind.name()))
+            .load_full()
+    }
+
+    /// The texture of the atlas with its bind group, of the `texture` layout. It changes when the atlas grows.
+    pub fn bindable_texture(&self, kind: AtlasKind) -> Arc<BindableTexture> {
+        self.atlas(kind).bindable_texture.load_full()
+    }
 }
 
 impl Default for TextureManager {
@@ -319,6 +1170,147 @@ impl Default for TextureManager {
     }
 }
 
+#[derive(Deserialize)]
+struct TextureMeta {
+    animation: Option<AnimationMeta>,
+}
+
+/// The `animation` section of a `.png.mcmeta`
+#[derive(Deserialize)]
+struct AnimationMeta {
+    #[serde(default)]
+    interpolate: bool,
+    width: Option<u32>,
+    height: Option<u32>,
+    #[serde(default = "default_frametime")]
+    frametime: u32,
+    frames: Option<Vec<FrameMeta>>,
+}
+
+fn default_frametime() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FrameMeta {
+    Index(usize),
+    Timed { index: usize, time: u32 },
+}
+
+/// A texture of an [Atlas] whose `.png.mcmeta` animates it. Its frames are laid out in the image left to right and
+/// top to bottom, and are square unless the `.mcmeta` gives their size. Only the frame which is shown is allocated in
+/// the atlas, the others are kept here to be written over it.
+pub struct AnimatedTexture {
+    pub path: ResourcePath,
+    frames: Vec<RgbaImage>,
+    /// The index of each frame which is shown in turn, and for how many ticks
+    sequence: Vec<(usize, u32)>,
+    /// Whether the frames fade into the next one over their time instead of switching at once
+    interpolate: bool,
+}
+
+impl AnimatedTexture {
+    /// Returns [None] if the image has no frames of the size
+    fn new(path: ResourcePath, image: RgbaImage, meta: AnimationMeta) -> Option<Self> {
+        let width = meta
+            .width
+            .unwrap_or_else(|| image.width().min(image.height()));
+        let height = meta.height.unwrap_or(width);
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let columns = image.width() / width;
+        let frames: Vec<RgbaImage> = (0..columns * (image.height() / height))
+            .map(|frame| {
+                image
+                    .view(
+                        (frame % columns) * width,
+                        (frame / columns) * height,
+                        width,
+                        height,
+                    )
+                    .to_image()
+            })
+            .collect();
+
+        let frametime = meta.frametime.max(1);
+
+        let sequence: Vec<(usize, u32)> = match meta.frames {
+            Some(order) => order
+                .into_iter()
+                .map(|frame| match frame {
+                    FrameMeta::Index(index) => (index, frametime),
+                    FrameMeta::Timed { index, time } => (index, time.max(1)),
+                })
+                .filter(|(index, _)| *index < frames.len())
+                .collect(),
+            None => (0..frames.len()).map(|index| (index, frametime)).collect(),
+        };
+
+        if sequence.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            path,
+            frames,
+            sequence,
+            interpolate: meta.interpolate,
+        })
+    }
+
+    /// Where the tick is in the sequence: the index into it, and how many ticks into that frame
+    fn position(&self, tick: u64) -> (usize, u32) {
+        let period: u64 = self.sequence.iter().map(|(_, time)| *time as u64).sum();
+        let mut remaining = tick % period;
+
+        for (position, (_, time)) in self.sequence.iter().enumerate() {
+            if remaining < *time as u64 {
+                return (position, remaining as u32);
+            }
+
+            remaining -= *time as u64;
+        }
+
+        unreachable!()
+    }
+
+    /// Whether the pixels at the tick are different from the ones a tick before
+    fn changes_at(&self, tick: u64) -> bool {
+        let (position, ticks_in) = self.position(tick);
+        let (previous, _) = self.position(tick.wrapping_sub(1));
+
+        self.interpolate
+            || (ticks_in == 0 && self.sequence[position].0 != self.sequence[previous].0)
+    }
+
+    /// The pixels which are shown at the tick
+    fn frame_at(&self, tick: u64) -> Cow<RgbaImage> {
+        let (position, ticks_in) = self.position(tick);
+        let (index, time) = self.sequence[position];
+        let frame = &self.frames[index];
+
+        if !self.interpolate || ticks_in == 0 {
+            return Cow::Borrowed(frame);
+        }
+
+        let next = &self.frames[self.sequence[(position + 1) % self.sequence.len()].0];
+        let blend = ticks_in as f32 / time as f32;
+
+        let mut blended = frame.clone();
+        for (pixel, next) in blended.pixels_mut().zip(next.pixels()) {
+            for (channel, next) in pixel.0.iter_mut().zip(next.0) {
+                *channel = (*channel as f32 * (1.0 - blend) + next as f32 * blend).round() as u8;
+            }
+        }
+
+        Cow::Owned(blended)
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Zeroable, Pod)]
 struct AnimatedUV {
@@ -372,3 +1364,242 @@ struct AnimatedUV {
 //         out
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageOutputFormat, Rgba, RgbaImage};
+
+    use std::collections::{HashMap, HashSet};
+    use std::io::Cursor;
+
+    use super::{
+        extrude, pbr_map_file, relayout, repack, sprite_change, AnimatedTexture, AnimationMeta,
+        AtlasError, AtlasKind, FrameMeta, PbrMap, SpriteChange, SpriteSource,
+    };
+    use crate::mc::resource::ResourcePath;
+
+    #[test]
+    fn pbr_maps_are_next_to_block_and_entity_textures() {
+        let file = |texture: &str, map| pbr_map_file(&texture.into(), map).map(|file| file.0);
+
+        assert_eq!(
+            file("minecraft:block/stone", PbrMap::Normal).as_deref(),
+            Some("minecraft:textures/block/stone_n.png")
+        );
+        assert_eq!(
+            file("minecraft:textures/entity/pig/pig.png", PbrMap::Specular).as_deref(),
+            Some("minecraft:textures/entity/pig/pig_s.png")
+        );
+        assert_eq!(file("minecraft:block/sea_lantern_e", PbrMap::Normal), None);
+    }
+
+    /// A strip of 1x1 frames, with the red channel of each frame being its index times 10
+    fn strip(frames: u32) -> RgbaImage {
+        RgbaImage::from_fn(1, frames, |_, y| Rgba([y as u8 * 10, 0, 0, 255]))
+    }
+
+    fn meta(frametime: u32, frames: Option<Vec<FrameMeta>>) -> AnimationMeta {
+        AnimationMeta {
+            interpolate: false,
+            width: None,
+            height: None,
+            frametime,
+            frames,
+        }
+    }
+
+    fn red(animation: &AnimatedTexture, tick: u64) -> u8 {
+        animation.frame_at(tick).get_pixel(0, 0).0[0]
+    }
+
+    #[test]
+    fn atlases_have_their_own_names_and_resources() {
+        let names: HashSet<&str> = AtlasKind::ALL.into_iter().map(AtlasKind::name).collect();
+        let resources: HashSet<&str> = AtlasKind::ALL
+            .into_iter()
+            .map(AtlasKind::resource)
+            .collect();
+
+        assert_eq!(names.len(), AtlasKind::ALL.len());
+        assert_eq!(resources.len(), AtlasKind::ALL.len());
+    }
+
+    #[test]
+    fn frames_follow_the_sequence() {
+        let path = ResourcePath::from("minecraft:textures/block/water_still.png");
+
+        let animation = AnimatedTexture::new(path.clone(), strip(3), meta(2, None)).unwrap();
+        assert_eq!(
+            (0..7).map(|tick| red(&animation, tick)).collect::<Vec<_>>(),
+            [0, 0, 10, 10, 20, 20, 0]
+        );
+        assert!(animation.changes_at(2));
+        assert!(!animation.changes_at(3));
+        //Back to the first frame
+        assert!(animation.changes_at(6));
+
+        let animation = AnimatedTexture::new(
+            path.clone(),
+            strip(3),
+            meta(
+                1,
+                Some(vec![
+                    FrameMeta::Index(2),
+                    FrameMeta::Timed { index: 0, time: 3 },
+                    //Not a frame of the texture
+                    FrameMeta::Index(5),
+                ]),
+            ),
+        )
+        .unwrap();
+        assert_eq!(
+            (0..5).map(|tick| red(&animation, tick)).collect::<Vec<_>>(),
+            [20, 0, 0, 0, 20]
+        );
+
+        let mut interpolated = meta(4, None);
+        interpolated.interpolate = true;
+        let animation = AnimatedTexture::new(path, strip(2), interpolated).unwrap();
+        assert_eq!(red(&animation, 2), 5);
+        assert!(animation.changes_at(1));
+    }
+
+    #[test]
+    fn repacking_keeps_the_pixels_of_each_texture() {
+        //Two 4x4 textures side by side in an 8x8 atlas
+        let image = RgbaImage::from_fn(8, 8, |x, _| Rgba([if x < 4 { 1 } else { 2 }, 0, 0, 255]));
+        let map = HashMap::from([
+            (ResourcePath("a".into()), ((0.0, 0.0), (4.0, 4.0))),
+            (ResourcePath("b".into()), ((4.0, 0.0), (8.0, 4.0))),
+        ]);
+
+        assert!(repack(&image, &map, 2, 0).is_none());
+
+        let (_, new_image, new_map) = repack(&image, &map, 16, 0).unwrap();
+
+        for (path, red) in [("a", 1), ("b", 2)] {
+            let ((min_x, min_y), (max_x, max_y)) = new_map[&ResourcePath(path.into())];
+            assert_eq!((max_x - min_x, max_y - min_y), (4.0, 4.0));
+            assert_eq!(new_image.get_pixel(min_x as u32, min_y as u32).0[0], red);
+            assert_eq!(
+                new_image.get_pixel(max_x as u32 - 1, max_y as u32 - 1).0[0],
+                red
+            );
+        }
+    }
+
+    #[test]
+    fn padding_repeats_the_edge_pixels() {
+        let image = RgbaImage::from_fn(2, 2, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let padded = extrude(&image, 2);
+
+        assert_eq!(padded.dimensions(), (6, 6));
+        //The corners take the corner pixel, the sides the pixel on the edge next to them
+        assert_eq!(padded.get_pixel(0, 0).0, [0, 0, 0, 255]);
+        assert_eq!(padded.get_pixel(5, 0).0, [1, 0, 0, 255]);
+        assert_eq!(padded.get_pixel(2, 5).0, [0, 1, 0, 255]);
+        assert_eq!(padded.get_pixel(3, 3).0, [1, 1, 0, 255]);
+    }
+
+    #[test]
+    fn repacking_moves_the_padding_along() {
+        //A 2x2 texture with a pixel of padding in the corner of a 4x4 atlas
+        let image = extrude(
+            &RgbaImage::from_fn(2, 2, |x, y| Rgba([1 + x as u8 + 2 * y as u8, 0, 0, 255])),
+            1,
+        );
+        let map = HashMap::from([(ResourcePath("a".into()), ((1.0, 1.0), (3.0, 3.0)))]);
+
+        let (_, new_image, new_map) = repack(&image, &map, 8, 1).unwrap();
+        let ((min_x, min_y), (max_x, max_y)) = new_map[&ResourcePath("a".into())];
+
+        assert_eq!((max_x - min_x, max_y - min_y), (2.0, 2.0));
+        assert_eq!(new_image.get_pixel(min_x as u32, min_y as u32).0[0], 1);
+        assert_eq!(
+            new_image.get_pixel(max_x as u32 - 1, max_y as u32 - 1).0[0],
+            4
+        );
+        assert_eq!(
+            new_image.get_pixel(min_x as u32 - 1, min_y as u32 - 1).0[0],
+            1
+        );
+        assert_eq!(new_image.get_pixel(max_x as u32, max_y as u32).0[0], 4);
+    }
+
+    #[test]
+    fn sprites_change_with_their_bytes() {
+        let stone = SpriteSource::new(None, b"stone");
+
+        //Only the bytes tell whether a texture has to be loaded again
+        assert_eq!(
+            SpriteSource::new(Some("vanilla".into()), b"stone").hash,
+            stone.hash
+        );
+        assert_ne!(SpriteSource::new(None, b"dirt").hash, stone.hash);
+    }
+
+    #[test]
+    fn pbr_maps_follow_the_layout_of_their_atlas() {
+        //A 2x2 map with a pixel of padding in the corner of a 4x4 atlas
+        let image = extrude(&RgbaImage::from_pixel(2, 2, Rgba([1, 0, 0, 255])), 1);
+        let old_map = HashMap::from([(ResourcePath("a".into()), ((1.0, 1.0), (3.0, 3.0)))]);
+        let new_map = HashMap::from([(ResourcePath("a".into()), ((5.0, 3.0), (7.0, 5.0)))]);
+
+        let background = Rgba([0, 0, 255, 255]);
+        let new_image = relayout(&image, &old_map, &new_map, 8, 1, background);
+
+        assert_eq!(new_image.dimensions(), (8, 8));
+        assert_eq!(*new_image.get_pixel(5, 3), Rgba([1, 0, 0, 255]));
+        //The padding moves too
+        assert_eq!(*new_image.get_pixel(4, 2), Rgba([1, 0, 0, 255]));
+        assert_eq!(*new_image.get_pixel(7, 5), Rgba([1, 0, 0, 255]));
+        assert_eq!(*new_image.get_pixel(0, 0), background);
+    }
+
+    #[test]
+    fn refreshed_textures_are_only_moved_when_their_size_changes() {
+        let png = |image: &RgbaImage| {
+            let mut bytes = Cursor::new(Vec::new());
+            image.write_to(&mut bytes, ImageOutputFormat::Png).unwrap();
+
+            bytes.into_inner()
+        };
+
+        let stone = RgbaImage::from_pixel(16, 16, Rgba([128, 128, 128, 255]));
+        let mossy = RgbaImage::from_pixel(16, 16, Rgba([64, 128, 64, 255]));
+        let large = RgbaImage::from_pixel(32, 32, Rgba([128, 128, 128, 255]));
+
+        let previous = SpriteSource::new(Some("vanilla".into()), &png(&stone));
+        let change = |image: &RgbaImage, animated| {
+            let bytes = png(image);
+            let source = SpriteSource::new(Some("faithful".into()), &bytes);
+
+            sprite_change(Some(&previous), &source, &bytes, Some((16, 16)), animated)
+        };
+
+        //The same file from another pack
+        assert_eq!(change(&stone, false), SpriteChange::Unchanged);
+        assert_eq!(change(&mossy, false), SpriteChange::Update(mossy.clone()));
+        assert_eq!(change(&large, false), SpriteChange::Move);
+        //Animated textures could have another number of frames
+        assert_eq!(change(&mossy, true), SpriteChange::Move);
+
+        //Textures which weren't refreshed before are always loaded again
+        let bytes = png(&stone);
+        let source = SpriteSource::new(None, &bytes);
+        assert_eq!(
+            sprite_change(None, &source, &bytes, Some((16, 16)), false),
+            SpriteChange::Update(stone)
+        );
+    }
+
+    #[test]
+    fn atlas_errors_can_be_boxed() {
+        let error: Box<dyn std::error::Error> = Box::new(AtlasError::Full { max_size: 4096 });
+
+        assert_eq!(
+            error.to_string(),
+            "The atlas is full and can't grow past 4096x4096"
+        );
+    }
+}
@@ -0,0 +1,426 @@
+//! Sub-allocates chunk meshes from a few large buffers instead of creating buffers for every chunk layer.
+//!
+//! Chunks are loaded and unloaded constantly while moving around, which would otherwise mean thousands of
+//! buffer creations and destructions. Each [ChunkAllocation] returns its range to the free-list of its page
+//! when it's dropped.
+//!
+//! Over a long session the free space of the pages gets scattered into gaps too small for most meshes. Once
+//! [ChunkBufferAllocator::fragmentation_ratio] goes above [DEFRAGMENT_THRESHOLD],
+//! [crate::mc::chunk::ChunkManager::defragment] moves allocations into the gaps before them a batch at a time, see
+//! [ChunkBufferAllocator::relocate].
+
+use std::ops::Range;
+use std::sync::{Arc, Weak};
+
+use parking_lot::Mutex;
+use wgpu::{BufferDescriptor, BufferSlice, BufferUsages};
+
+use crate::WmRenderer;
+
+/// 32 MiB
+pub const DEFAULT_PAGE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// [crate::mc::chunk::ChunkManager::defragment] only moves allocations while the pages are more fragmented than this
+pub const DEFRAGMENT_THRESHOLD: f32 = 0.2;
+
+/// Allocations are aligned to this, which satisfies the alignment of vertex and index buffer offsets as well
+/// as buffer copies
+const ALIGNMENT: u64 = wgpu::COPY_BUFFER_ALIGNMENT * 4;
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let gcd = |mut a: u64, mut b: u64| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+
+    a / gcd(a, b) * b
+}
+
+/// The unused ranges of a page, sorted by their start. Neighbouring ranges are always merged.
+#[derive(Debug)]
+struct FreeList(Vec<Range<u64>>);
+
+fn align_up(offset: u64, alignment: u64) -> u64 {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+impl FreeList {
+    /// Takes the first range with room for `size` bytes starting at a multiple of `alignment`
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<Range<u64>> {
+        self.allocate_below(size, alignment, u64::MAX)
+    }
+
+    /// Like [FreeList::allocate], but only from the ranges starting before `below`
+    fn allocate_below(&mut self, size: u64, alignment: u64, below: u64) -> Option<Range<u64>> {
+        let (index, start) = self
+            .0
+            .iter()
+            .take_while(|range| range.start < below)
+            .enumerate()
+            .find_map(|(index, range)| {
+                let start = align_up(range.start, alignment);
+
+                (start + size <= range.end).then_some((index, start))
+            })?;
+
+        let allocated = start..start + size;
+        let range = self.0[index].clone();
+
+        //The part skipped for the alignment stays free
+        let remaining = [range.start..start, allocated.end..range.end];
+        self.0.splice(
+            index..=index,
+            remaining.into_iter().filter(|range| !range.is_empty()),
+        );
+
+        Some(allocated)
+    }
+
+    fn free(&mut self, freed: Range<u64>) {
+        let index = self.0.partition_point(|range| range.start < freed.start);
+
+        self.0.insert(index, freed);
+
+        //Merge with the next range, then the previous one
+        if index + 1 < self.0.len() && self.0[index].end == self.0[index + 1].start {
+            self.0[index].end = self.0[index + 1].end;
+            self.0.remove(index + 1);
+        }
+
+        if index > 0 && self.0[index - 1].end == self.0[index].start {
+            self.0[index - 1].end = self.0[index].end;
+            self.0.remove(index);
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.0.iter().map(|range| range.end - range.start).sum()
+    }
+
+    fn largest(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|range| range.end - range.start)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// See [ChunkBufferAllocator::fragmentation_ratio]
+fn fragmentation_ratio<'a>(free_lists: impl IntoIterator<Item = &'a FreeList>) -> f32 {
+    let (total, largest) = free_lists
+        .into_iter()
+        .fold((0, 0), |(total, largest), free| {
+            (total + free.total(), largest + free.largest())
+        });
+
+    if total == 0 {
+        return 0.0;
+    }
+
+    1.0 - largest as f32 / total as f32
+}
+
+#[derive(Debug)]
+struct Page {
+    buffer: Arc<wgpu::Buffer>,
+    free: FreeList,
+}
+
+#[derive(Debug)]
+pub struct ChunkBufferAllocator {
+    pages: Arc<Mutex<Vec<Page>>>,
+    page_size: u64,
+}
+
+impl Default for ChunkBufferAllocator {
+    fn default() -> Self {
+        Self::new(DEFAULT_PAGE_SIZE)
+    }
+}
+
+impl ChunkBufferAllocator {
+    pub fn new(page_size: u64) -> Self {
+        Self {
+            pages: Arc::new(Mutex::new(Vec::new())),
+            page_size,
+        }
+    }
+
+    /// Copies the data into a free range of one of the pages, creating a new page if none of them have enough
+    /// space left. Data larger than the page size gets a page of its own.
+    ///
+    /// The copy goes through [WmRenderer::queue_upload], so it only reaches the GPU once the uploads are flushed.
+    pub fn allocate(&self, wm: &WmRenderer, data: &[u8]) -> ChunkAllocation {
+        self.allocate_aligned(wm, data, ALIGNMENT)
+    }
+
+    /// Like [ChunkBufferAllocator::allocate], but the allocation starts at a multiple of `alignment` as well,
+    /// e.g. the size of a vertex
+    pub fn allocate_aligned(
+        &self,
+        wm: &WmRenderer,
+        data: &[u8],
+        alignment: u64,
+    ) -> ChunkAllocation {
+        let alignment = lcm(alignment, ALIGNMENT);
+
+        //Empty allocations still take up some space, so that slicing the buffer is never done with an empty range
+        let size = align_up((data.len() as u64).max(1), ALIGNMENT);
+
+        let mut pages = self.pages.lock();
+
+        let existing = pages.iter_mut().enumerate().find_map(|(index, page)| {
+            page.free
+                .allocate(size, alignment)
+                .map(|range| (index, range))
+        });
+
+        let (page, range) = match existing {
+            Some(allocation) => allocation,
+            None => {
+                let page_size = self.page_size.max(size);
+
+                let buffer = wm.wgpu_state.device.create_buffer(&BufferDescriptor {
+                    label: Some("Chunk buffer page"),
+                    size: page_size,
+                    usage: BufferUsages::VERTEX
+                        | BufferUsages::INDEX
+                        | BufferUsages::COPY_DST
+                        //Defragmentation copies allocations to other places
+                        | BufferUsages::COPY_SRC,
+                    mapped_at_creation: false,
+                });
+
+                let mut page = Page {
+                    buffer: Arc::new(buffer),
+                    free: FreeList(vec![0..page_size]),
+                };
+
+                let range = page.free.allocate(size, alignment).unwrap();
+                pages.push(page);
+
+                (pages.len() - 1, range)
+            }
+        };
+
+        let buffer = pages[page].buffer.clone();
+
+        drop(pages);
+
+        //Copies need the size to be a multiple of 4 as well
+        if data.len() as u64 % wgpu::COPY_BUFFER_ALIGNMENT == 0 {
+            wm.queue_upload(&buffer, range.start, data);
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(
+                (data.len() + wgpu::COPY_BUFFER_ALIGNMENT as usize - 1)
+                    / wgpu::COPY_BUFFER_ALIGNMENT as usize
+                    * wgpu::COPY_BUFFER_ALIGNMENT as usize,
+                0,
+            );
+            wm.queue_upload(&buffer, range.start, &padded);
+        }
+
+        ChunkAllocation {
+            buffer,
+            range: range.start..range.start + data.len() as u64,
+            allocated: range,
+            alignment,
+            page,
+            pages: Arc::downgrade(&self.pages),
+        }
+    }
+
+    /// Moves the allocation into the first gap before it which it fits in, in an earlier page or further towards the
+    /// start of its own page, so that the free space of the pages comes together towards their ends. The data is
+    /// copied on the GPU with the encoder, and the old allocation is freed once it's dropped. [None] if there's no
+    /// such gap.
+    pub fn relocate(
+        &self,
+        wm: &WmRenderer,
+        allocation: &ChunkAllocation,
+        encoder: &mut wgpu::CommandEncoder,
+    ) -> Option<ChunkAllocation> {
+        let size = allocation.size();
+
+        let mut pages = self.pages.lock();
+
+        let (page, range) =
+            pages[..=allocation.page]
+                .iter_mut()
+                .enumerate()
+                .find_map(|(index, page)| {
+                    let below = if index == allocation.page {
+                        allocation.allocated.start
+                    } else {
+                        u64::MAX
+                    };
+
+                    page.free
+                        .allocate_below(size, allocation.alignment, below)
+                        .map(|range| (index, range))
+                })?;
+
+        let buffer = pages[page].buffer.clone();
+
+        drop(pages);
+
+        if page == allocation.page {
+            //Copies within the same buffer aren't allowed, so the data goes through a staging buffer
+            let staging = wm.wgpu_state.device.create_buffer(&BufferDescriptor {
+                label: Some("Chunk buffer defragmentation"),
+                size,
+                usage: BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            encoder.copy_buffer_to_buffer(
+                &allocation.buffer,
+                allocation.allocated.start,
+                &staging,
+                0,
+                size,
+            );
+            encoder.copy_buffer_to_buffer(&staging, 0, &buffer, range.start, size);
+        } else {
+            encoder.copy_buffer_to_buffer(
+                &allocation.buffer,
+                allocation.allocated.start,
+                &buffer,
+                range.start,
+                size,
+            );
+        }
+
+        Some(ChunkAllocation {
+            buffer,
+            range: range.start..range.start + (allocation.range.end - allocation.range.start),
+            allocated: range,
+            alignment: allocation.alignment,
+            page,
+            pages: Arc::downgrade(&self.pages),
+        })
+    }
+
+    /// How much of the free space of the pages is split off from the largest gap of its page, from 0 when each page
+    /// has a single gap to almost 1 when the free space is scattered into many small gaps
+    pub fn fragmentation_ratio(&self) -> f32 {
+        fragmentation_ratio(self.pages.lock().iter().map(|page| &page.free))
+    }
+
+    /// The total size of all pages, and how much of it is in use
+    pub fn usage(&self) -> (u64, u64) {
+        let pages = self.pages.lock();
+
+        let total: u64 = pages.iter().map(|page| page.buffer.size()).sum();
+        let free: u64 = pages
+            .iter()
+            .flat_map(|page| page.free.0.iter())
+            .map(|range| range.end - range.start)
+            .sum();
+
+        (total, total - free)
+    }
+}
+
+/// A range of one of the pages of a [ChunkBufferAllocator], which is freed when this is dropped
+#[derive(Debug)]
+pub struct ChunkAllocation {
+    pub buffer: Arc<wgpu::Buffer>,
+    /// The range of the buffer which contains the data
+    pub range: Range<u64>,
+    /// The range which was reserved, including padding
+    allocated: Range<u64>,
+    alignment: u64,
+    page: usize,
+    pages: Weak<Mutex<Vec<Page>>>,
+}
+
+impl ChunkAllocation {
+    pub fn slice(&self) -> BufferSlice {
+        self.buffer.slice(self.allocated.start..self.allocated.end)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.allocated.end - self.allocated.start
+    }
+}
+
+impl Drop for ChunkAllocation {
+    fn drop(&mut self) {
+        if let Some(pages) = self.pages.upgrade() {
+            pages.lock()[self.page].free.free(self.allocated.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fragmentation_ratio, lcm, FreeList, ALIGNMENT};
+
+    #[test]
+    fn freed_ranges_are_merged() {
+        let mut free = FreeList(vec![0..64]);
+
+        let a = free.allocate(16, 16).unwrap();
+        let b = free.allocate(16, 16).unwrap();
+        let c = free.allocate(16, 16).unwrap();
+
+        assert_eq!(free.0, vec![48..64]);
+        assert_eq!(free.allocate(32, 16), None);
+
+        free.free(b);
+        assert_eq!(free.0, vec![16..32, 48..64]);
+
+        free.free(c);
+        assert_eq!(free.0, vec![16..64]);
+
+        free.free(a);
+        assert_eq!(free.0, vec![0..64]);
+    }
+
+    #[test]
+    fn aligned_allocations_leave_the_skipped_part_free() {
+        let mut free = FreeList(vec![16..256]);
+
+        assert_eq!(free.allocate(80, 80), Some(80..160));
+        assert_eq!(free.0, vec![16..80, 160..256]);
+
+        assert_eq!(free.allocate(16, 16), Some(16..32));
+        assert_eq!(free.0, vec![32..80, 160..256]);
+    }
+
+    #[test]
+    fn allocations_below_an_offset_only_use_earlier_gaps() {
+        let mut free = FreeList(vec![0..16, 32..64, 96..256]);
+
+        assert_eq!(free.allocate_below(48, 16, 96), None);
+        assert_eq!(free.allocate_below(16, 16, 96), Some(0..16));
+        assert_eq!(free.allocate_below(16, 16, 96), Some(32..48));
+        assert_eq!(free.0, vec![48..64, 96..256]);
+    }
+
+    #[test]
+    fn scattered_free_space_is_fragmented() {
+        assert_eq!(fragmentation_ratio([&FreeList(vec![0..64])]), 0.0);
+        assert_eq!(fragmentation_ratio([&FreeList(vec![])]), 0.0);
+
+        //Every page having a single gap isn't fragmented
+        assert_eq!(
+            fragmentation_ratio([&FreeList(vec![0..64]), &FreeList(vec![32..64])]),
+            0.0
+        );
+
+        assert_eq!(fragmentation_ratio([&FreeList(vec![0..16, 64..112])]), 0.25);
+    }
+
+    #[test]
+    fn lcm_of_vertex_stride_and_alignment() {
+        assert_eq!(lcm(80, ALIGNMENT), 80);
+        assert_eq!(lcm(20, ALIGNMENT), 80);
+        assert_eq!(lcm(16, ALIGNMENT), 16);
+    }
+}
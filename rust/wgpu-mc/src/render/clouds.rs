@@ -0,0 +1,443 @@
+//! # Clouds
+//!
+//! Pipelines with the `wm_geo_clouds` geometry draw vanilla's clouds as a triangle list of [CloudVertex], built every
+//! frame around the camera from the [CloudState] set with [Clouds::set_state]. Each pixel of `clouds.png` is a cell
+//! of [CLOUD_CELL_SIZE] blocks, the texture repeats forever, and the clouds drift towards +X by [CLOUD_SPEED] blocks a
+//! tick. In [CloudMode::Fast] they're a flat plane textured with `clouds.png`, and in [CloudMode::Fancy] every cell
+//! which isn't empty is a box [CLOUD_THICKNESS] blocks high, its faces shaded like vanilla's, without the sides
+//! between neighbouring cells.
+//!
+//! The texture is allocated in the block atlas along with the textures of the blocks, and the plane of fast clouds
+//! has texture coordinates relative to it which keep going past 1, so the shader wraps them into the `region` the
+//! texture has in the atlas, like beams, whose shader draws clouds as well. Fancy clouds aren't textured, the color
+//! of each cell is read from the texture once when it's loaded, see [Clouds::load].
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use bytemuck::{Pod, Zeroable};
+use image::GenericImageView;
+
+use crate::mc::chunk::ChunkPos;
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::mc::MinecraftState;
+use crate::render::atlas::AtlasKind;
+
+/// How wide a pixel of the texture is in the sky, in blocks
+pub const CLOUD_CELL_SIZE: f32 = 12.0;
+
+/// How high fancy clouds are, in blocks
+pub const CLOUD_THICKNESS: f32 = 4.0;
+
+/// How far the clouds drift each tick, in blocks
+pub const CLOUD_SPEED: f32 = 0.03;
+
+/// How many cells the clouds reach from the camera
+const CLOUD_RADIUS: i32 = 32;
+
+/// Fancy clouds are more vertices per cell, so they stop closer
+const FANCY_CLOUD_RADIUS: i32 = 24;
+
+/// Vanilla's shades of the faces of fancy clouds, the top, bottom, east and west, and north and south ones
+const TOP_SHADE: f32 = 1.0;
+const BOTTOM_SHADE: f32 = 0.7;
+const X_SHADE: f32 = 0.9;
+const Z_SHADE: f32 = 0.8;
+
+/// The texture of the clouds, allocated in the block atlas
+pub fn clouds_texture() -> ResourcePath {
+    ResourcePath("minecraft:environment/clouds".into())
+}
+
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+pub struct CloudVertex {
+    pub position: [f32; 3],
+    /// Relative to the texture, wrapped into the region by the shader
+    pub tex_coords: [f32; 2],
+    /// The min and max UVs of the texture in the block atlas, negative if the vertex isn't textured
+    pub region: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl CloudVertex {
+    const VAA: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4,
+        3 => Float32x4
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<CloudVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CloudMode {
+    /// A flat textured plane
+    Fast,
+    /// A box for every cell
+    Fancy,
+}
+
+/// How the clouds are drawn this frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloudState {
+    pub enabled: bool,
+    pub mode: CloudMode,
+    /// The height of the bottom of the clouds, in blocks
+    pub height: f32,
+    /// The age of the world in ticks, interpolated with the partial tick of the frame, which the clouds drift with
+    pub ticks: f32,
+    /// Multiplied with the clouds, darkened at night and in the rain like vanilla's `getCloudsColor`
+    pub color: [f32; 4],
+}
+
+impl Default for CloudState {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            mode: CloudMode::Fancy,
+            height: 192.0,
+            ticks: 0.0,
+            color: [1.0, 1.0, 1.0, 0.8],
+        }
+    }
+}
+
+/// The color of each pixel of the cloud texture, [None] where there's no cloud
+#[derive(Clone, Debug, PartialEq)]
+pub struct CloudCells {
+    pub width: u32,
+    pub height: u32,
+    pub cells: Vec<Option<[f32; 4]>>,
+}
+
+impl CloudCells {
+    #[must_use]
+    pub fn from_image(image: &image::DynamicImage) -> Self {
+        let (width, height) = image.dimensions();
+
+        let cells = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let [r, g, b, a] = image.get_pixel(x, y).0;
+
+                (a > 0).then(|| [r, g, b, a].map(|channel| channel as f32 / 255.0))
+            })
+            .collect();
+
+        Self {
+            width,
+            height,
+            cells,
+        }
+    }
+
+    /// The cell at the coordinates, which repeat forever
+    #[must_use]
+    pub fn get(&self, x: i32, z: i32) -> Option<[f32; 4]> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let x = x.rem_euclid(self.width as i32) as u32;
+        let z = z.rem_euclid(self.height as i32) as u32;
+
+        self.cells[(z * self.width + x) as usize]
+    }
+}
+
+/// The clouds of the world, see [crate::mc::MinecraftState::clouds]
+pub struct Clouds {
+    pub state: ArcSwap<CloudState>,
+    cells: ArcSwap<Option<CloudCells>>,
+}
+
+impl Default for Clouds {
+    fn default() -> Self {
+        Self {
+            state: ArcSwap::new(Arc::new(CloudState::default())),
+            cells: ArcSwap::new(Arc::new(None)),
+        }
+    }
+}
+
+impl Clouds {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces how the clouds are drawn, usually every frame as they drift with [CloudState::ticks]
+    pub fn set_state(&self, state: CloudState) {
+        self.state.store(Arc::new(state));
+    }
+
+    /// Reads the cells of fancy clouds from `clouds.png`. There are no fancy clouds if it's missing or isn't an
+    /// image.
+    pub fn load(&self, resource_provider: &dyn ResourceProvider) {
+        let cells = resource_provider
+            .get_bytes(&clouds_texture().prepend("textures/").append(".png"))
+            .and_then(|bytes| image::load_from_memory(&bytes).ok())
+            .map(|image| CloudCells::from_image(&image));
+
+        self.cells.store(Arc::new(cells));
+    }
+}
+
+/// How far the clouds have drifted, wrapped around once the texture has gone by so it stays precise
+fn drift(state: &CloudState, cells: u32) -> f32 {
+    (state.ticks * CLOUD_SPEED).rem_euclid(cells.max(1) as f32 * CLOUD_CELL_SIZE)
+}
+
+/// A plane of clouds around the camera, double sided so it's seen from above and below
+fn fast_clouds(
+    state: &CloudState,
+    center: [f32; 2],
+    texture_size: u32,
+    region: [f32; 4],
+) -> Vec<CloudVertex> {
+    let drift = drift(state, texture_size);
+    let reach = CLOUD_RADIUS as f32 * CLOUD_CELL_SIZE;
+    let texture_length = texture_size.max(1) as f32 * CLOUD_CELL_SIZE;
+
+    let vertex = |x: f32, z: f32| CloudVertex {
+        position: [x, state.height, z],
+        tex_coords: [(x - drift) / texture_length, z / texture_length],
+        region,
+        color: state.color,
+    };
+
+    let [x, z] = center;
+    let a = vertex(x - reach, z - reach);
+    let b = vertex(x + reach, z - reach);
+    let c = vertex(x + reach, z + reach);
+    let d = vertex(x - reach, z + reach);
+
+    vec![a, b, c, a, c, d, a, c, b, a, d, c]
+}
+
+/// A box for every cell of clouds around the camera, without the faces between neighbouring cells
+fn fancy_clouds(state: &CloudState, center: [f32; 2], cells: &CloudCells) -> Vec<CloudVertex> {
+    let drift = drift(state, cells.width);
+    let [center_x, center_z] = center;
+    let center_x = ((center_x - drift) / CLOUD_CELL_SIZE).floor() as i32;
+    let center_z = (center_z / CLOUD_CELL_SIZE).floor() as i32;
+
+    let bottom = state.height;
+    let top = state.height + CLOUD_THICKNESS;
+    let mut vertices = Vec::new();
+
+    for cell_z in center_z - FANCY_CLOUD_RADIUS..=center_z + FANCY_CLOUD_RADIUS {
+        for cell_x in center_x - FANCY_CLOUD_RADIUS..=center_x + FANCY_CLOUD_RADIUS {
+            let color = match cells.get(cell_x, cell_z) {
+                Some(color) => color,
+                None => continue,
+            };
+
+            let min_x = cell_x as f32 * CLOUD_CELL_SIZE + drift;
+            let min_z = cell_z as f32 * CLOUD_CELL_SIZE;
+            let max_x = min_x + CLOUD_CELL_SIZE;
+            let max_z = min_z + CLOUD_CELL_SIZE;
+
+            let mut face = |corners: [[f32; 3]; 4], shade: f32| {
+                let color = [0, 1, 2, 3].map(|channel| {
+                    let shade = if channel == 3 { 1.0 } else { shade };
+
+                    color[channel] * state.color[channel] * shade
+                });
+                let vertex = |index: usize| CloudVertex {
+                    position: corners[index],
+                    tex_coords: [0.0; 2],
+                    region: [-1.0; 4],
+                    color,
+                };
+
+                vertices.extend([0, 1, 2, 0, 2, 3].map(vertex));
+            };
+
+            //Counter-clockwise from the outside
+            face(
+                [
+                    [min_x, top, min_z],
+                    [min_x, top, max_z],
+                    [max_x, top, max_z],
+                    [max_x, top, min_z],
+                ],
+                TOP_SHADE,
+            );
+            face(
+                [
+                    [min_x, bottom, min_z],
+                    [max_x, bottom, min_z],
+                    [max_x, bottom, max_z],
+                    [min_x, bottom, max_z],
+                ],
+                BOTTOM_SHADE,
+            );
+
+            if cells.get(cell_x - 1, cell_z).is_none() {
+                face(
+                    [
+                        [min_x, bottom, min_z],
+                        [min_x, bottom, max_z],
+                        [min_x, top, max_z],
+                        [min_x, top, min_z],
+                    ],
+                    X_SHADE,
+                );
+            }
+
+            if cells.get(cell_x + 1, cell_z).is_none() {
+                face(
+                    [
+                        [max_x, bottom, min_z],
+                        [max_x, top, min_z],
+                        [max_x, top, max_z],
+                        [max_x, bottom, max_z],
+                    ],
+                    X_SHADE,
+                );
+            }
+
+            if cells.get(cell_x, cell_z - 1).is_none() {
+                face(
+                    [
+                        [min_x, bottom, min_z],
+                        [min_x, top, min_z],
+                        [max_x, top, min_z],
+                        [max_x, bottom, min_z],
+                    ],
+                    Z_SHADE,
+                );
+            }
+
+            if cells.get(cell_x, cell_z + 1).is_none() {
+                face(
+                    [
+                        [min_x, bottom, max_z],
+                        [max_x, bottom, max_z],
+                        [max_x, top, max_z],
+                        [min_x, top, max_z],
+                    ],
+                    Z_SHADE,
+                );
+            }
+        }
+    }
+
+    vertices
+}
+
+/// The vertices of the clouds around the camera, relative to the chunk offset like terrain
+pub fn cloud_vertices(
+    mc: &MinecraftState,
+    camera: [f32; 3],
+    chunk_offset: ChunkPos,
+) -> Vec<CloudVertex> {
+    let state = mc.clouds.state.load();
+
+    if !state.enabled {
+        return Vec::new();
+    }
+
+    let offset = [(chunk_offset[0] * 16) as f32, (chunk_offset[1] * 16) as f32];
+    let center = [camera[0] + offset[0], camera[2] + offset[1]];
+
+    let mut vertices = match (state.mode, &**mc.clouds.cells.load()) {
+        (CloudMode::Fancy, Some(cells)) => fancy_clouds(&state, center, cells),
+        (_, cells) => {
+            let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+            let atlas_size = block_atlas.size() as f32;
+
+            //Without the texture there's nothing to draw
+            let region = match block_atlas.uv_map.read().get(&clouds_texture()) {
+                Some(&((min_x, min_y), (max_x, max_y))) => [
+                    min_x / atlas_size,
+                    min_y / atlas_size,
+                    max_x / atlas_size,
+                    max_y / atlas_size,
+                ],
+                None => return Vec::new(),
+            };
+
+            let texture_size = cells.as_ref().map_or(256, |cells| cells.width);
+
+            fast_clouds(&state, center, texture_size, region)
+        }
+    };
+
+    for vertex in &mut vertices {
+        vertex.position[0] -= offset[0];
+        vertex.position[2] -= offset[1];
+    }
+
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        drift, fancy_clouds, fast_clouds, CloudCells, CloudState, CLOUD_CELL_SIZE, CLOUD_SPEED,
+    };
+
+    fn cells() -> CloudCells {
+        //Two neighbouring cells of cloud and an empty one
+        CloudCells {
+            width: 3,
+            height: 1,
+            cells: vec![Some([1.0; 4]), Some([1.0; 4]), None],
+        }
+    }
+
+    #[test]
+    fn clouds_drift_and_wrap() {
+        let state = CloudState {
+            ticks: 100.0,
+            ..CloudState::default()
+        };
+
+        assert_eq!(drift(&state, 256), 100.0 * CLOUD_SPEED);
+
+        //The plane's texture coordinates are relative to the texture, shifted by the drift
+        let plane = fast_clouds(&state, [0.0, 0.0], 256, [0.0, 0.0, 1.0, 1.0]);
+        assert_eq!(plane.len(), 12);
+
+        let length = 256.0 * CLOUD_CELL_SIZE;
+        let wrapped = CloudState {
+            ticks: length / CLOUD_SPEED + 100.0,
+            ..state
+        };
+        assert!((drift(&wrapped, 256) - 100.0 * CLOUD_SPEED).abs() < 1e-2);
+    }
+
+    #[test]
+    fn fancy_clouds_hide_the_faces_between_cells() {
+        let cells = cells();
+
+        assert_eq!(cells.get(-1, 0), None);
+        assert_eq!(cells.get(3, 5), Some([1.0; 4]));
+
+        //Rows repeat along Z, so each cell only keeps the side facing the empty cell next to it. 33 of the 49 cells
+        //in each of the 49 rows are clouds, with a top, a bottom and one side of 6 vertices each.
+        let vertices = fancy_clouds(&CloudState::default(), [18.0, 6.0], &cells);
+        assert_eq!(vertices.len(), 33 * 49 * 3 * 6);
+        assert!(vertices.iter().all(|vertex| vertex.region[0] < 0.0));
+
+        //Clouds everywhere are one slab, with only the top and bottom of each cell
+        let slab = CloudCells {
+            width: 1,
+            height: 1,
+            cells: vec![Some([1.0; 4])],
+        };
+        let vertices = fancy_clouds(&CloudState::default(), [18.0, 6.0], &slab);
+        assert_eq!(vertices.len(), 49 * 49 * 2 * 6);
+    }
+}
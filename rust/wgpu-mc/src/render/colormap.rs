@@ -0,0 +1,86 @@
+//! # Colormaps
+//!
+//! The colormaps in `textures/colormap/` are what [crate::mc::biome] tints grass and foliage with while baking.
+//! They're also uploaded as textures, so that shaders which tint by biome themselves can sample them with the
+//! same coordinates as [crate::mc::biome::Colormap::sample]. Each one is bound as the resource of its
+//! [colormap_resource], like `wm_texture_colormap_grass`, and is replaced whenever the resources are reloaded.
+//!
+//! The images are only decoded once, into the [crate::mc::biome::BlockColors] of the block manager, and uploaded
+//! from there.
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use image::{Rgba, RgbaImage};
+use wgpu::Extent3d;
+
+use crate::mc::biome::{Colormap, DEFAULT_COLOR};
+use crate::texture::{BindableTexture, TextureSamplerView};
+use crate::WmRenderer;
+
+/// The colormaps which are uploaded, by the name of their file in `minecraft:textures/colormap/`
+pub const COLORMAPS: [&str; 2] = ["grass", "foliage"];
+
+/// The resource of the shader graph which binds the colormap
+#[must_use]
+pub fn colormap_resource(name: &str) -> String {
+    format!("wm_texture_colormap_{name}")
+}
+
+/// The pixels of the colormap, or a single pixel of the default grass color if it couldn't be loaded
+fn colormap_image(colormap: Option<&Colormap>) -> RgbaImage {
+    match colormap {
+        Some(colormap) => colormap.image().clone(),
+        None => {
+            let [r, g, b] = DEFAULT_COLOR;
+            RgbaImage::from_pixel(1, 1, Rgba([r, g, b, 255]))
+        }
+    }
+}
+
+/// Loads every colormap of [COLORMAPS] from the resources as they are now into the
+/// [crate::mc::biome::BlockColors] which blocks are tinted with while baking, and uploads it. Colormaps which were
+/// uploaded before are swapped in place, so that the shader graph binds the new ones.
+pub fn load_colormaps(wm: &WmRenderer) {
+    let pipelines = wm.pipelines.load();
+    let mut colormaps = (**wm.mc.colormaps.load()).clone();
+
+    let mut block_manager = wm.mc.block_manager.write();
+    block_manager
+        .colors
+        .load_colormaps(&*wm.mc.resource_provider);
+
+    let colors = &block_manager.colors;
+
+    //In the same order as COLORMAPS
+    for (name, colormap) in COLORMAPS.into_iter().zip([&colors.grass, &colors.foliage]) {
+        let image = colormap_image(colormap.as_ref());
+
+        let texture = BindableTexture::from_tsv(
+            &wm.wgpu_state,
+            &pipelines,
+            TextureSamplerView::from_rgb_bytes(
+                &wm.wgpu_state,
+                image.as_raw(),
+                Extent3d {
+                    width: image.width(),
+                    height: image.height(),
+                    depth_or_array_layers: 1,
+                },
+                Some(name),
+                wgpu::TextureFormat::Rgba8Unorm,
+            )
+            .unwrap(),
+            false,
+        );
+
+        match colormaps.get(name) {
+            Some(existing) => existing.store(Arc::new(texture)),
+            None => {
+                colormaps.insert(name, Arc::new(ArcSwap::new(Arc::new(texture))));
+            }
+        }
+    }
+
+    wm.mc.colormaps.store(Arc::new(colormaps));
+}
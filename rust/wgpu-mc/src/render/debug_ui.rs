@@ -0,0 +1,424 @@
+//! In-renderer debug panels using [egui](https://github.com/emilk/egui). Only available with the `egui` feature.
+//!
+//! The [EguiPipeline] is owned by the [WmRenderer], and registered in its [crate::WmRenderer::pipeline_registry] as
+//! [EGUI_PIPELINE] in [crate::render::registry::RenderPhase::Overlay], so that it draws on top of whatever the [ShaderGraph] rendered.
+//! The panels are laid out by [EguiPipeline::run] before each frame, and window events have to be forwarded with
+//! [WmRenderer::egui_input] for them to be interactive.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use parking_lot::Mutex;
+use wgpu::{LoadOp, Operations, RenderPassColorAttachment, RenderPassDescriptor, TextureFormat};
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use crate::mc::Block;
+use crate::render::graph::{CustomResource, ShaderGraph};
+use crate::render::registry::WmPipeline;
+use crate::{WgpuState, WmRenderer};
+
+/// The name the [EguiPipeline] is registered under
+pub const EGUI_PIPELINE: &str = "wm_egui";
+
+/// How many frames are averaged for the fps counter
+const FRAME_TIME_SAMPLES: usize = 60;
+
+const MIB: f64 = 1024.0 * 1024.0;
+
+/// The output of [EguiPipeline::run], drawn by the next frame
+struct EguiFrame {
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    textures_delta: egui::TexturesDelta,
+    screen_descriptor: egui_wgpu::renderer::ScreenDescriptor,
+}
+
+/// The pipeline draws into the multisampled frame texture, so there's a renderer for every sample count the
+/// device supports, which all get the same textures. They're created again when the format of the surface changes.
+struct EguiRenderers {
+    format: TextureFormat,
+    renderers: Vec<(u32, egui_wgpu::Renderer)>,
+}
+
+impl EguiRenderers {
+    fn new(wgpu_state: &WgpuState, format: TextureFormat) -> Self {
+        Self {
+            format,
+            renderers: [1, 2, 4, 8]
+                .into_iter()
+                .filter(|&samples| wgpu_state.supports_msaa_samples(samples))
+                .map(|samples| {
+                    (
+                        samples,
+                        egui_wgpu::Renderer::new(&wgpu_state.device, format, None, samples),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+pub struct EguiPipeline {
+    pub ctx: egui::Context,
+    state: Mutex<egui_winit::State>,
+    renderers: Mutex<EguiRenderers>,
+    /// The whole image of every texture egui allocated, as egui only sends them again when they change. New
+    /// renderers are given these.
+    textures: Mutex<HashMap<egui::TextureId, egui::epaint::ImageDelta>>,
+    frame: Mutex<Option<EguiFrame>>,
+    frame_times: Mutex<(Instant, VecDeque<f32>)>,
+}
+
+impl EguiPipeline {
+    #[must_use]
+    pub fn new(wgpu_state: &WgpuState, output_format: TextureFormat) -> Self {
+        Self {
+            ctx: egui::Context::default(),
+            state: Mutex::new(egui_winit::State::new_with_wayland_display(None)),
+            renderers: Mutex::new(EguiRenderers::new(wgpu_state, output_format)),
+            textures: Mutex::new(HashMap::new()),
+            frame: Mutex::new(None),
+            frame_times: Mutex::new((Instant::now(), VecDeque::with_capacity(FRAME_TIME_SAMPLES))),
+        }
+    }
+
+    /// Returns true if egui consumed the event, in which case it shouldn't be handled by anything else
+    pub fn on_event(&self, event: &WindowEvent) -> bool {
+        self.state.lock().on_event(&self.ctx, event).consumed
+    }
+
+    fn fps(&self) -> f32 {
+        let mut frame_times = self.frame_times.lock();
+        let (last_frame, samples) = &mut *frame_times;
+
+        let now = Instant::now();
+        let frame_time = now.duration_since(*last_frame).as_secs_f32();
+        *last_frame = now;
+
+        if samples.len() == FRAME_TIME_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(frame_time);
+
+        let average = samples.iter().sum::<f32>() / samples.len() as f32;
+
+        if average > 0.0 {
+            1.0 / average
+        } else {
+            0.0
+        }
+    }
+
+    /// Lays out the debug panels along with any additional UI, which are drawn by the next [WmRenderer::render].
+    /// Nothing is drawn in frames without a call to this before them.
+    pub fn run(
+        &self,
+        wm: &WmRenderer,
+        window: &Window,
+        graph: &ShaderGraph,
+        ui: impl FnOnce(&egui::Context),
+    ) {
+        let fps = self.fps();
+
+        let raw_input = self.state.lock().take_egui_input(window);
+
+        let full_output = self.ctx.run(raw_input, |ctx| {
+            debug_panel(ctx, wm, graph, fps);
+            ui(ctx);
+        });
+
+        self.state
+            .lock()
+            .handle_platform_output(window, &self.ctx, full_output.platform_output);
+
+        let surface_config = &wm.wgpu_state.surface.read().1;
+
+        let mut frame = self.frame.lock();
+
+        //Textures of a frame which wasn't drawn still have to be uploaded
+        let mut textures_delta = frame
+            .take()
+            .map(|frame| frame.textures_delta)
+            .unwrap_or_default();
+        textures_delta.append(full_output.textures_delta);
+
+        *frame = Some(EguiFrame {
+            paint_jobs: self.ctx.tessellate(full_output.shapes),
+            textures_delta,
+            screen_descriptor: egui_wgpu::renderer::ScreenDescriptor {
+                size_in_pixels: [surface_config.width, surface_config.height],
+                pixels_per_point: window.scale_factor() as f32,
+            },
+        });
+    }
+}
+
+impl WmPipeline for EguiPipeline {
+    fn render(
+        &self,
+        wm: &WmRenderer,
+        _graph: &ShaderGraph,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        _resources: &HashMap<String, CustomResource>,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let frame = match self.frame.lock().take() {
+            None => return Vec::new(),
+            Some(frame) => frame,
+        };
+
+        let device = &wm.wgpu_state.device;
+        let queue = &wm.wgpu_state.queue;
+        let samples = **wm.msaa_samples.load();
+        let format = wm.wgpu_state.surface.read().1.format;
+
+        let mut renderers = self.renderers.lock();
+        let mut textures = self.textures.lock();
+
+        if renderers.format != format {
+            *renderers = EguiRenderers::new(&wm.wgpu_state, format);
+
+            for (_, renderer) in renderers.renderers.iter_mut() {
+                for (id, image_delta) in textures.iter() {
+                    renderer.update_texture(device, queue, *id, image_delta);
+                }
+            }
+        }
+
+        for (_, renderer) in renderers.renderers.iter_mut() {
+            for (id, image_delta) in &frame.textures_delta.set {
+                renderer.update_texture(device, queue, *id, image_delta);
+            }
+        }
+
+        for (id, image_delta) in &frame.textures_delta.set {
+            match image_delta.pos {
+                None => {
+                    textures.insert(*id, image_delta.clone());
+                }
+                Some(pos) => {
+                    if let Some(texture) = textures.get_mut(id) {
+                        patch_image(&mut texture.image, &image_delta.image, pos);
+                    }
+                }
+            }
+        }
+
+        let mut command_buffers = Vec::new();
+
+        if let Some((_, renderer)) = renderers
+            .renderers
+            .iter_mut()
+            .find(|(renderer_samples, _)| *renderer_samples == samples)
+        {
+            //Buffers of paint callbacks, which the graph submits ahead of the frame
+            command_buffers = renderer.update_buffers(
+                device,
+                queue,
+                encoder,
+                &frame.paint_jobs,
+                &frame.screen_descriptor,
+            );
+
+            let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("egui"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+
+            renderer.render(
+                &mut render_pass,
+                &frame.paint_jobs,
+                &frame.screen_descriptor,
+            );
+        }
+
+        for (_, renderer) in renderers.renderers.iter_mut() {
+            for id in &frame.textures_delta.free {
+                renderer.free_texture(id);
+            }
+        }
+
+        for id in &frame.textures_delta.free {
+            textures.remove(id);
+        }
+
+        command_buffers
+    }
+}
+
+/// Writes the part of a texture egui sent to where it goes in the whole image
+fn patch_image(
+    image: &mut egui::epaint::ImageData,
+    part: &egui::epaint::ImageData,
+    pos: [usize; 2],
+) {
+    match (image, part) {
+        (egui::epaint::ImageData::Color(image), egui::epaint::ImageData::Color(part)) => {
+            let width = image.size[0];
+            patch_pixels(&mut image.pixels, width, &part.pixels, part.size, pos);
+        }
+        (egui::epaint::ImageData::Font(image), egui::epaint::ImageData::Font(part)) => {
+            let width = image.size[0];
+            patch_pixels(&mut image.pixels, width, &part.pixels, part.size, pos);
+        }
+        _ => {}
+    }
+}
+
+fn patch_pixels<T: Copy>(
+    pixels: &mut [T],
+    width: usize,
+    part: &[T],
+    size: [usize; 2],
+    pos: [usize; 2],
+) {
+    if size[0] == 0 {
+        return;
+    }
+
+    for (row, part_row) in part.chunks_exact(size[0]).enumerate() {
+        let start = (pos[1] + row) * width + pos[0];
+        pixels[start..start + size[0]].copy_from_slice(part_row);
+    }
+}
+
+/// The memory used by the texture, including its mip levels
+fn texture_memory(texture: &wgpu::Texture) -> u64 {
+    let size = texture.size();
+    let block_size = texture.format().describe().block_size as u64;
+
+    (0..texture.mip_level_count())
+        .map(|level| {
+            let width = (size.width >> level).max(1) as u64;
+            let height = (size.height >> level).max(1) as u64;
+
+            width * height * size.depth_or_array_layers as u64 * block_size
+        })
+        .sum()
+}
+
+fn debug_panel(ctx: &egui::Context, wm: &WmRenderer, graph: &ShaderGraph, fps: f32) {
+    egui::Window::new("wgpu-mc").show(ctx, |ui| {
+        ui.label(format!("FPS: {fps:.0}"));
+        ui.label(wm.get_backend_description());
+
+        ui.separator();
+
+        ui.label(format!(
+            "Chunks: {} visible / {} loaded",
+            graph.visible_chunks(),
+            wm.mc.chunks.loaded_chunks.read().len()
+        ));
+
+        ui.separator();
+
+        let block_manager = wm.mc.block_manager.read();
+
+        let (multiparts, multipart_keys) = block_manager
+            .blocks
+            .values()
+            .filter_map(|block| match block {
+                Block::Multipart(multipart) => Some(multipart.keys.read().len()),
+                Block::Variants(_) => None,
+            })
+            .fold((0, 0), |(count, keys), len| (count + 1, keys + len));
+
+        ui.label(format!("Blocks: {}", block_manager.blocks.len()));
+        ui.label(format!(
+            "Multipart blocks: {multiparts} ({multipart_keys} baked states)"
+        ));
+
+        egui::CollapsingHeader::new("GPU memory").show(ui, |ui| memory_panel(ui, wm));
+        egui::CollapsingHeader::new("GPU timings").show(ui, |ui| timings_panel(ui, wm));
+    });
+}
+
+fn memory_panel(ui: &mut egui::Ui, wm: &WmRenderer) {
+    let (allocated, used) = wm.mc.chunks.buffer_allocator.usage();
+
+    let atlases: u64 = wm
+        .mc
+        .texture_manager
+        .atlases
+        .load()
+        .values()
+        .map(|atlas| {
+            atlas
+                .pbr_maps
+                .iter()
+                .map(|pbr_atlas| &pbr_atlas.bindable_texture)
+                .chain([&atlas.bindable_texture])
+                .map(|texture| texture_memory(&texture.load().tsv.texture))
+                .sum::<u64>()
+        })
+        .sum();
+
+    let targets: u64 = wm
+        .texture_handles
+        .read()
+        .values()
+        .map(|handle| texture_memory(&handle.bindable_texture.load().tsv.texture))
+        .sum();
+
+    ui.label(format!(
+        "Chunk buffer pages: {:.2} / {:.2} MiB used",
+        used as f64 / MIB,
+        allocated as f64 / MIB
+    ));
+    ui.label(format!("Atlases: {:.2} MiB", atlases as f64 / MIB));
+    ui.label(format!("Render targets: {:.2} MiB", targets as f64 / MIB));
+    ui.label(format!(
+        "Total: {:.2} MiB",
+        (allocated + atlases + targets) as f64 / MIB
+    ));
+}
+
+fn timings_panel(ui: &mut egui::Ui, wm: &WmRenderer) {
+    let profile = match wm.last_frame_profile() {
+        None => {
+            ui.label("Requires WmConfig::gpu_profiling and timestamp queries");
+            return;
+        }
+        Some(profile) => profile,
+    };
+
+    for pass in &profile.passes {
+        ui.label(format!(
+            "{}{}: {:.3} ms",
+            pass.name,
+            if pass.depth_prepass {
+                " (depth pre-pass)"
+            } else {
+                ""
+            },
+            pass.gpu_time.as_secs_f64() * 1000.0
+        ));
+    }
+
+    ui.label(format!(
+        "Total: {:.3} ms",
+        profile.total().as_secs_f64() * 1000.0
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::patch_pixels;
+
+    #[test]
+    fn parts_of_textures_are_patched_into_the_whole_image() {
+        let mut pixels = vec![0; 4 * 3];
+
+        patch_pixels(&mut pixels, 4, &[1, 2, 3, 4], [2, 2], [1, 1]);
+
+        assert_eq!(pixels, vec![0, 0, 0, 0, 0, 1, 2, 0, 0, 3, 4, 0]);
+    }
+}
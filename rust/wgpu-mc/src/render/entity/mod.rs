@@ -5,6 +5,8 @@ pub struct EntityVertex {
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
     pub part_id: u32,
+    /// Where the emissive overlay of the texture is at this vertex, or [crate::mc::block::NO_EMISSIVE]
+    pub emissive_tex_coords: [f32; 2],
 }
 
 impl EntityVertex {
@@ -39,6 +41,12 @@ impl EntityVertex {
                     shader_location: 3,
                     format: wgpu::VertexFormat::Uint32,
                 },
+                //Emissive texcoords
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
             ],
         }
     }
@@ -0,0 +1,154 @@
+//! # Fonts
+//!
+//! Text drawn into the world, like name tags, uses vanilla's default font for ASCII, `minecraft:font/ascii`. Its
+//! texture is a grid of 16 by 16 glyphs, each in a cell 8 font pixels across, and is kept in the block atlas. Like
+//! vanilla's `BitmapFont`, a glyph is as wide as its rightmost column with an opaque pixel, and is followed by one
+//! pixel of spacing. Characters outside of ASCII are drawn as `?`.
+
+use image::RgbaImage;
+
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::render::atlas::texture_file;
+
+/// The width and height of a glyph's cell, in font pixels
+pub const GLYPH_SIZE: f32 = 8.0;
+
+/// How far a space advances, in font pixels, which vanilla gives a provider of its own
+const SPACE_ADVANCE: f32 = 4.0;
+
+/// The block atlas texture the font is drawn with
+pub fn font_texture() -> ResourcePath {
+    ResourcePath("minecraft:font/ascii".into())
+}
+
+/// A glyph of a line of text
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Glyph {
+    /// The left of the glyph's cell, in font pixels from the start of the line
+    pub x: f32,
+    /// The corners of the cell in the font texture, from 0 to 1
+    pub min_uv: [f32; 2],
+    pub max_uv: [f32; 2],
+}
+
+/// The advances of the glyphs of `minecraft:font/ascii`
+#[derive(Clone, Debug)]
+pub struct Font {
+    /// In font pixels, including the spacing after the glyph
+    advances: [f32; 256],
+}
+
+impl Font {
+    /// Measures the glyphs of the font texture
+    #[must_use]
+    pub fn from_image(image: &RgbaImage) -> Self {
+        let cell_width = image.width() / 16;
+        let cell_height = image.height() / 16;
+        let scale = GLYPH_SIZE / cell_height as f32;
+
+        let mut advances = [0.0; 256];
+
+        for (index, advance) in advances.iter_mut().enumerate() {
+            let cell_x = (index as u32 % 16) * cell_width;
+            let cell_y = (index as u32 / 16) * cell_height;
+
+            let width = (0..cell_width)
+                .rev()
+                .find(|&column| {
+                    (0..cell_height)
+                        .any(|row| image.get_pixel(cell_x + column, cell_y + row)[3] != 0)
+                })
+                .map_or(0, |column| column + 1);
+
+            *advance = (0.5 + width as f32 * scale).floor() + 1.0;
+        }
+
+        advances[b' ' as usize] = SPACE_ADVANCE;
+
+        Self { advances }
+    }
+
+    /// Loads and measures the font texture, or returns [None] if it can't be read
+    pub fn load(resource_provider: &dyn ResourceProvider) -> Option<Self> {
+        let bytes = resource_provider.get_bytes(&texture_file(&font_texture()))?;
+        let image = image::load_from_memory(&bytes).ok()?.to_rgba8();
+
+        Some(Self::from_image(&image))
+    }
+
+    fn index(character: char) -> usize {
+        if character.is_ascii() && !character.is_ascii_control() {
+            character as usize
+        } else {
+            b'?' as usize
+        }
+    }
+
+    /// The width of a line of the text, in font pixels. The spacing after the last glyph is included, like vanilla
+    #[must_use]
+    pub fn width(&self, text: &str) -> f32 {
+        text.chars()
+            .map(|character| self.advances[Self::index(character)])
+            .sum()
+    }
+
+    /// The glyphs of a line of the text. Spaces have no glyph.
+    #[must_use]
+    pub fn glyphs(&self, text: &str) -> Vec<Glyph> {
+        let mut x = 0.0;
+
+        text.chars()
+            .filter_map(|character| {
+                let index = Self::index(character);
+                let glyph_x = x;
+                x += self.advances[index];
+
+                (character != ' ').then(|| {
+                    let min_uv = [(index % 16) as f32 / 16.0, (index / 16) as f32 / 16.0];
+
+                    Glyph {
+                        x: glyph_x,
+                        min_uv,
+                        max_uv: [min_uv[0] + 1.0 / 16.0, min_uv[1] + 1.0 / 16.0],
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::Font;
+
+    #[test]
+    fn glyphs_are_as_wide_as_their_opaque_pixels() {
+        //A 128x128 font, where the glyph of 'A' is 5 pixels wide and 'i' is 1 pixel wide
+        let image = RgbaImage::from_fn(128, 128, |x, y| {
+            let (column, row) = (x % 8, y % 8);
+            let alpha = match (x / 8 + y / 8 * 16) as u8 {
+                b'A' if column < 5 && row < 7 => 255,
+                b'i' if column == 0 => 255,
+                _ => 0,
+            };
+
+            Rgba([255, 255, 255, alpha])
+        });
+
+        let font = Font::from_image(&image);
+
+        assert_eq!(font.width("A"), 6.0);
+        assert_eq!(font.width("Ai A"), 6.0 + 2.0 + 4.0 + 6.0);
+
+        let glyphs = font.glyphs("Ai A");
+        assert_eq!(glyphs.len(), 3);
+        assert_eq!(
+            glyphs.iter().map(|glyph| glyph.x).collect::<Vec<_>>(),
+            [0.0, 6.0, 12.0]
+        );
+        assert_eq!(glyphs[0].min_uv, [1.0 / 16.0, 4.0 / 16.0]);
+        assert_eq!(glyphs[0].max_uv, [2.0 / 16.0, 5.0 / 16.0]);
+    }
+}
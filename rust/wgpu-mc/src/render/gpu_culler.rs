@@ -0,0 +1,273 @@
+//! # GPU section culling
+//!
+//! With enough chunks loaded, testing every section against the frustum on the CPU takes a noticeable part of
+//! the frame. Instead, every section which might be visible gets a draw in the indirect buffer together with its
+//! bounding box, and a compute shader (`wgpu_mc:shaders/section_culler.wgsl`) tests the boxes against the planes
+//! of the frustum. The draws of sections outside of it are written with an instance count of zero, so the draw
+//! calls of a batch stay where [wgpu::RenderPass::multi_draw_indexed_indirect] expects them.
+//!
+//! Chunks are still tested against the frustum on the CPU, and sections which can't be seen through the cave
+//! culling graph are skipped before they reach the GPU. There's no occlusion culling against a depth pyramid.
+//!
+//! Requires multi-draw indirect and compute shaders, and is enabled with [crate::WmConfig::gpu_culling].
+
+use bytemuck::{Pod, Zeroable};
+use std::sync::Arc;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingType, BufferBindingType, BufferUsages, CommandEncoderDescriptor, ShaderStages,
+};
+
+use crate::mc::resource::ResourcePath;
+use crate::render::pipeline::compute::{dispatch, workgroup_count};
+use crate::{WgpuState, WmRenderer};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The corners of the bounding box of a section in world space, the layout matches `Bounds` in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct SectionBounds {
+    pub min: [f32; 4],
+    pub max: [f32; 4],
+}
+
+impl SectionBounds {
+    #[must_use]
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> Self {
+        Self {
+            min: [min[0], min[1], min[2], 1.0],
+            max: [max[0], max[1], max[2], 1.0],
+        }
+    }
+
+    /// Whether any part of the box is on the inner side of every plane, the same as `is_visible` in the shader
+    #[must_use]
+    pub fn is_visible(&self, planes: &[[f32; 4]; 6]) -> bool {
+        planes.iter().all(|plane| {
+            //The corner furthest along the plane's normal
+            let corner: [f32; 3] = std::array::from_fn(|axis| {
+                if plane[axis] >= 0.0 {
+                    self.max[axis]
+                } else {
+                    self.min[axis]
+                }
+            });
+
+            plane[0] * corner[0] + plane[1] * corner[1] + plane[2] * corner[2] + plane[3] >= 0.0
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Params {
+    planes: [[f32; 4]; 6],
+    draw_count: u32,
+    padding: [u32; 3],
+}
+
+/// The left, right, bottom, top, near and far planes of the frustum of a column-major view projection matrix.
+/// The near plane assumes depth from -1 to 1, which is never tighter than the range wgpu uses.
+#[must_use]
+pub fn frustum_planes(view_projection: [[f32; 4]; 4]) -> [[f32; 4]; 6] {
+    let row =
+        |index: usize| -> [f32; 4] { std::array::from_fn(|column| view_projection[column][index]) };
+    let add = |a: [f32; 4], b: [f32; 4]| -> [f32; 4] { std::array::from_fn(|i| a[i] + b[i]) };
+    let sub = |a: [f32; 4], b: [f32; 4]| -> [f32; 4] { std::array::from_fn(|i| a[i] - b[i]) };
+
+    let [x, y, z, w] = [row(0), row(1), row(2), row(3)];
+
+    [
+        add(w, x),
+        sub(w, x),
+        add(w, y),
+        sub(w, y),
+        add(w, z),
+        sub(w, z),
+    ]
+}
+
+#[derive(Debug)]
+pub struct GpuCuller {
+    pipeline: Arc<wgpu::ComputePipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+}
+
+impl GpuCuller {
+    /// Returns [None] if the device doesn't support compute shaders or multi-draw indirect, or the shader is
+    /// missing from the resource provider or doesn't compile
+    #[must_use]
+    pub fn new(wm: &WmRenderer) -> Option<Self> {
+        let wgpu_state = &wm.wgpu_state;
+
+        if !wgpu_state
+            .adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            || !wgpu_state.device.features().contains(
+                wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE,
+            )
+        {
+            log::warn!("The device can't cull sections on the GPU, they will be culled on the CPU");
+            return None;
+        }
+
+        let device = &wgpu_state.device;
+
+        let entry = |binding: u32, ty: BufferBindingType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Section culler"),
+            entries: &[
+                entry(0, BufferBindingType::Uniform),
+                entry(1, BufferBindingType::Storage { read_only: true }),
+                entry(2, BufferBindingType::Storage { read_only: true }),
+                entry(3, BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+
+        let pipeline = match wm.pipelines.load().create_compute_pipeline(
+            wm,
+            "wgpu_mc:section_culler",
+            &ResourcePath::from("wgpu_mc:shaders/section_culler.wgsl"),
+            "cull",
+            &[&bind_group_layout],
+        ) {
+            Ok(pipeline) => pipeline,
+            Err(error) => {
+                log::warn!(
+                    "The section culling shader couldn't be loaded, sections will be culled on the CPU: {error}"
+                );
+                return None;
+            }
+        };
+
+        Some(Self {
+            pipeline,
+            bind_group_layout,
+        })
+    }
+
+    /// Returns an indirect buffer with the draws, where the ones with bounds outside the frustum of the view
+    /// projection matrix don't draw anything. `draws` is made of [wgpu::RenderPass::draw_indexed_indirect]
+    /// arguments, one for each of the bounds. The culling is submitted right away, so it's done before any
+    /// command buffer submitted after it.
+    pub fn cull(
+        &self,
+        wgpu_state: &WgpuState,
+        draws: &[u8],
+        bounds: &[SectionBounds],
+        view_projection: [[f32; 4]; 4],
+    ) -> wgpu::Buffer {
+        let device = &wgpu_state.device;
+
+        let params = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Section culler params"),
+            contents: bytemuck::bytes_of(&Params {
+                planes: frustum_planes(view_projection),
+                draw_count: bounds.len() as u32,
+                padding: [0; 3],
+            }),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let draws_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Section culler draws"),
+            contents: draws,
+            usage: BufferUsages::STORAGE,
+        });
+
+        let bounds_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Section culler bounds"),
+            contents: bytemuck::cast_slice(bounds),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let culled = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Section culler output"),
+            size: draws.len() as u64,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Section culler"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: draws_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: bounds_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: culled.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Section culler"),
+        });
+
+        dispatch(
+            &mut encoder,
+            "Section culler",
+            &self.pipeline,
+            &[&bind_group],
+            [workgroup_count(bounds.len() as u32, WORKGROUP_SIZE), 1, 1],
+        );
+
+        wgpu_state.queue.submit([encoder.finish()]);
+
+        culled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frustum_planes, SectionBounds};
+
+    /// The identity matrix sees everything from -1 to 1 on each axis
+    const IDENTITY: [[f32; 4]; 4] = [
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ];
+
+    #[test]
+    fn bounds_are_culled_by_the_frustum() {
+        let planes = frustum_planes(IDENTITY);
+
+        assert!(SectionBounds::new([-0.5; 3], [0.5; 3]).is_visible(&planes));
+        //Partly inside
+        assert!(SectionBounds::new([0.5, 0.0, 0.0], [3.0, 0.5, 0.5]).is_visible(&planes));
+        //Enclosing the whole frustum
+        assert!(SectionBounds::new([-5.0; 3], [5.0; 3]).is_visible(&planes));
+
+        assert!(!SectionBounds::new([2.0, 0.0, 0.0], [3.0, 0.5, 0.5]).is_visible(&planes));
+        assert!(!SectionBounds::new([0.0, -3.0, 0.0], [0.5, -2.0, 0.5]).is_visible(&planes));
+        assert!(!SectionBounds::new([0.0, 0.0, 1.5], [0.5, 0.5, 2.0]).is_visible(&planes));
+    }
+}
@@ -0,0 +1,964 @@
+//! # GPU chunk meshing
+//!
+//! An experimental alternative to [Chunk::bake_sections] for very large render distances, where baking on the
+//! CPU can't keep up. The blocks of a chunk, with a border of one block from each neighbour, are uploaded to a
+//! storage buffer as indices into a palette of the block states in it. A compute shader
+//! (`wgpu_mc:shaders/chunk_mesher.wgsl`) emits every visible face into a vertex and index buffer, reserving room
+//! for each quad with an atomic counter. The quads are then read back and split into sections, so the result is
+//! used exactly like a chunk baked on the CPU.
+//!
+//! Only chunks made of solid cubes without emissive overlays or biome tints can be meshed on the GPU, and only with a single [RenderLayer] whose mapper offsets the vertices by the block position, like the ones in
+//! the demo and Electrum. Faces aren't greedy meshed. The shader only knows the texture each face was baked with,
+//! so faces with connected textures get their tile once they're read back, see [crate::mc::ctm].
+//! Anything else, and devices without compute shaders, falls back to baking on the CPU.
+//!
+//! Enabled with [crate::WmConfig::gpu_meshing].
+//!
+//! [Chunk::bake_sections]: crate::mc::chunk::Chunk::bake_sections
+//! [RenderLayer]: crate::mc::chunk::RenderLayer
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use bytemuck::{Pod, Zeroable};
+use parking_lot::Mutex;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingType, BufferBindingType, BufferDescriptor, BufferUsages, ComputePipelineDescriptor,
+    PipelineLayoutDescriptor, ShaderModuleDescriptor, ShaderStages,
+};
+
+use crate::mc::block::{
+    remap_tex_coords, BlockMeshVertex, BlockModelFaces, ChunkBlockState, CubeOrComplexMesh,
+    ModelMesh, RenderType, NO_EMISSIVE, NO_TINT,
+};
+use crate::mc::chunk::{
+    chunk_origin, get_block, BakedSection, BlockStateProvider, ChunkPos, CHUNK_HEIGHT,
+    CHUNK_SECTIONS_PER, CHUNK_SECTION_HEIGHT, CHUNK_VOLUME, CHUNK_WIDTH, FACE_NORMALS,
+};
+use crate::mc::resource::ResourcePath;
+use crate::mc::visibility::SectionVisibility;
+use crate::mc::BlockManager;
+use crate::render::pipeline::compute::{dispatch, workgroup_count};
+use crate::render::pipeline::Vertex;
+use crate::{WgpuState, WmRenderer};
+
+/// Chunks with more visible faces than this are baked on the CPU instead
+pub const MAX_QUADS: u32 = 1 << 16;
+
+/// How many chunks can be meshed at the same time, each set of buffers takes up a few dozen megabytes
+pub const MAX_BUFFER_SETS: usize = 4;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The chunk plus a block of each neighbour on both sides
+const BORDERED_WIDTH: i32 = CHUNK_WIDTH as i32 + 2;
+
+const MESHED: u32 = 1;
+const OCCLUDES: u32 = 2;
+
+/// The corners of each face of the unit cube in the order they're emitted in, which must match `face_corner` in
+/// the shader. Faces are in the order north, east, south, west, up, down.
+const FACE_CORNERS: [[[f32; 3]; 4]; 6] = [
+    [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+    ],
+    [
+        [1.0, 0.0, 0.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 1.0, 1.0],
+        [1.0, 1.0, 0.0],
+    ],
+    [
+        [1.0, 0.0, 1.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 1.0],
+        [1.0, 1.0, 1.0],
+    ],
+    [
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 1.0],
+    ],
+    [
+        [0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0],
+        [1.0, 1.0, 1.0],
+        [0.0, 1.0, 1.0],
+    ],
+    [
+        [0.0, 0.0, 1.0],
+        [1.0, 0.0, 1.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 0.0, 0.0],
+    ],
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Params {
+    max_quads: u32,
+    ambient_occlusion: u32,
+    padding: [u32; 2],
+}
+
+/// A block state in the chunk, the layout matches `PaletteEntry` in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct PaletteEntry {
+    /// [MESHED], [OCCLUDES], and a bit for each face the model has starting at bit 2
+    flags: u32,
+    padding: [u32; 3],
+    /// Two corners in each element, in the order of [FACE_CORNERS]
+    tex_coords: [[f32; 4]; 12],
+    animation_uv_offsets: [[u32; 4]; 2],
+}
+
+impl PaletteEntry {
+    /// The model if the GPU can mesh the block
+    fn cube(mesh: &ModelMesh) -> Option<&BlockModelFaces> {
+        match &mesh.models[..] {
+            [(CubeOrComplexMesh::Cube(faces), _)]
+                if mesh.render_type == RenderType::Solid && !Self::has_overlays(faces) =>
+            {
+                Some(faces)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the model has emissive overlays or tinted faces, which the shader doesn't know about
+    fn has_overlays(faces: &BlockModelFaces) -> bool {
+        Self::faces(faces)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .any(|vertex| vertex.emissive_tex_coords != NO_EMISSIVE || vertex.tint_index != NO_TINT)
+    }
+
+    /// The faces of the model in the order of [FACE_CORNERS]
+    fn faces(faces: &BlockModelFaces) -> [&Option<[BlockMeshVertex; 6]>; 6] {
+        [
+            &faces.north,
+            &faces.east,
+            &faces.south,
+            &faces.west,
+            &faces.up,
+            &faces.down,
+        ]
+    }
+
+    fn new(mesh: Option<&ModelMesh>) -> Self {
+        let mut entry = Self::zeroed();
+
+        let mesh = match mesh {
+            None => return entry,
+            Some(mesh) => mesh,
+        };
+
+        if mesh.is_full_opaque_cube {
+            entry.flags |= OCCLUDES;
+        }
+
+        let faces = match Self::cube(mesh) {
+            None => return entry,
+            Some(faces) => faces,
+        };
+
+        entry.flags |= MESHED;
+
+        for (face, vertices) in Self::faces(faces).into_iter().enumerate() {
+            let vertices = match vertices {
+                None => continue,
+                Some(vertices) => vertices,
+            };
+
+            entry.flags |= 4 << face;
+            entry.animation_uv_offsets[face / 4][face % 4] = vertices[0].animation_uv_offset;
+
+            //The model's vertex closest to each corner has its texture coordinates
+            for (corner, corner_position) in FACE_CORNERS[face].iter().enumerate() {
+                let distance = |position: &[f32; 3]| -> f32 {
+                    (0..3)
+                        .map(|axis| (position[axis] - corner_position[axis]).abs())
+                        .sum()
+                };
+
+                let closest = vertices
+                    .iter()
+                    .min_by(|a, b| distance(&a.position).total_cmp(&distance(&b.position)))
+                    .unwrap();
+
+                let pair = &mut entry.tex_coords[face * 2 + corner / 2];
+                pair[corner % 2 * 2] = closest.tex_coords[0];
+                pair[corner % 2 * 2 + 1] = closest.tex_coords[1];
+            }
+        }
+
+        entry
+    }
+}
+
+/// The palette and bordered block array of a chunk, or [None] if it has blocks which can't be meshed on the GPU or
+/// is out of the addressable range, which the CPU baking skips
+fn collect_blocks(
+    block_manager: &BlockManager,
+    provider: &impl BlockStateProvider,
+    pos: ChunkPos,
+) -> Option<(Vec<PaletteEntry>, Vec<u32>)> {
+    let [origin_x, origin_z] = chunk_origin(pos)?;
+
+    //Air is always the first entry
+    let mut palette = vec![PaletteEntry::zeroed()];
+    let mut palette_indices = HashMap::new();
+    let mut blocks = Vec::with_capacity((BORDERED_WIDTH * BORDERED_WIDTH) as usize * CHUNK_HEIGHT);
+
+    let column = |x: i32, z: i32| (origin_x + x, origin_z + z);
+
+    let heights: Vec<i16> = (-1..=CHUNK_WIDTH as i32)
+        .flat_map(|z| (-1..=CHUNK_WIDTH as i32).map(move |x| (x, z)))
+        .map(|(x, z)| {
+            let (absolute_x, absolute_z) = column(x, z);
+
+            provider
+                .column_height(absolute_x, absolute_z)
+                .unwrap_or(CHUNK_HEIGHT as i16)
+        })
+        .collect();
+
+    for y in 0..CHUNK_HEIGHT as i16 {
+        let section_empty = provider.is_section_empty(y as usize / CHUNK_SECTION_HEIGHT);
+
+        for z in -1..=CHUNK_WIDTH as i32 {
+            for x in -1..=CHUNK_WIDTH as i32 {
+                let (absolute_x, absolute_z) = column(x, z);
+
+                let inside =
+                    (0..CHUNK_WIDTH as i32).contains(&x) && (0..CHUNK_WIDTH as i32).contains(&z);
+
+                //Light is still needed above the terrain, for the faces below it
+                let skipped = (inside && section_empty)
+                    || y >= heights[((z + 1) * BORDERED_WIDTH + x + 1) as usize];
+
+                let state = if skipped {
+                    ChunkBlockState::Air
+                } else {
+                    provider.get_state(absolute_x, y, absolute_z)
+                };
+
+                let palette_index = match state {
+                    ChunkBlockState::Air => 0,
+                    ChunkBlockState::State(key) => {
+                        *palette_indices.entry(key).or_insert_with(|| {
+                            palette.push(PaletteEntry::new(
+                                get_block(block_manager, ChunkBlockState::State(key)).as_deref(),
+                            ));
+
+                            palette.len() as u32 - 1
+                        })
+                    }
+                };
+
+                if inside
+                    && palette_index != 0
+                    && palette[palette_index as usize].flags & MESHED == 0
+                {
+                    return None;
+                }
+
+                let (block_light, sky_light) = provider.get_light(absolute_x, y, absolute_z);
+
+                blocks.push(
+                    palette_index
+                        | (block_light.min(15) as u32) << 16
+                        | (sky_light.min(15) as u32) << 20,
+                );
+            }
+        }
+    }
+
+    Some((palette, blocks))
+}
+
+/// Splits the quads read back from the GPU into chunk sections, by the block each face belongs to
+fn split_sections(vertices: &[Vertex], indices: &[u32]) -> Vec<(Vec<Vertex>, Vec<u32>)> {
+    let mut sections = vec![(Vec::new(), Vec::new()); CHUNK_SECTIONS_PER];
+
+    for (quad, (quad_vertices, quad_indices)) in vertices
+        .chunks_exact(4)
+        .zip(indices.chunks_exact(6))
+        .enumerate()
+    {
+        //The block is half a block behind the centre of its face
+        let y = quad_vertices
+            .iter()
+            .map(|vertex| vertex.position[1])
+            .sum::<f32>()
+            / 4.0
+            - quad_vertices[0].normal[1] * 0.5;
+
+        let section = (y.max(0.0) as usize / CHUNK_SECTION_HEIGHT).min(CHUNK_SECTIONS_PER - 1);
+
+        let (section_vertices, section_indices): &mut (Vec<Vertex>, Vec<u32>) =
+            &mut sections[section];
+        let base = section_vertices.len() as u32;
+
+        //The shader doesn't write tangents
+        let triangle = [0, 1, 2]
+            .map(|corner| quad_vertices[(quad_indices[corner] - quad as u32 * 4) as usize]);
+        let tangent = Vertex::face_tangent(&triangle);
+
+        section_vertices.extend(
+            quad_vertices
+                .iter()
+                .map(|vertex| Vertex { tangent, ..*vertex }),
+        );
+        section_indices.extend(
+            quad_indices
+                .iter()
+                .map(|index| index - quad as u32 * 4 + base),
+        );
+    }
+
+    sections
+}
+
+/// Swaps the texture of the faces with connected textures for the tile of the blocks they connect to, like
+/// [crate::mc::chunk::Chunk::bake_sections] does while baking
+fn connect_faces(
+    vertices: &mut [Vertex],
+    block_manager: &BlockManager,
+    provider: &impl BlockStateProvider,
+    pos: ChunkPos,
+) {
+    let [origin_x, origin_z] = match chunk_origin(pos) {
+        Some(origin) => origin,
+        None => return,
+    };
+
+    for quad in vertices.chunks_exact_mut(4) {
+        //The block is half a block behind the centre of its face
+        let block: [i32; 3] = std::array::from_fn(|axis| {
+            (quad.iter().map(|vertex| vertex.position[axis]).sum::<f32>() / 4.0
+                - quad[0].normal[axis] * 0.5)
+                .floor() as i32
+        });
+
+        let direction = match FACE_NORMALS.iter().position(|normal| {
+            (0..3).all(|axis| normal[axis] as f32 == quad[0].normal[axis].round())
+        }) {
+            Some(direction) => direction,
+            None => continue,
+        };
+
+        let position = [origin_x + block[0], block[1], origin_z + block[2]];
+
+        let state = provider.get_state(position[0], position[1] as i16, position[2]);
+
+        let (key, mesh) = match (state, get_block(block_manager, state)) {
+            (ChunkBlockState::State(key), Some(mesh)) => (key, mesh),
+            _ => continue,
+        };
+
+        let faces = match PaletteEntry::cube(&mesh) {
+            Some(faces) => faces,
+            None => continue,
+        };
+
+        let (connected, face_vertices) = match (
+            &faces.connected[direction],
+            PaletteEntry::faces(faces)[direction],
+        ) {
+            (Some(connected), Some(face_vertices)) => (connected, face_vertices),
+            _ => continue,
+        };
+
+        let tile = connected.connected_tile(face_vertices, |offset| {
+            connected.connects_at(block_manager, provider, key, direction, position, offset)
+        });
+
+        if let Some(tile) = tile {
+            for vertex in quad {
+                vertex.tex_coords = remap_tex_coords(vertex.tex_coords, connected.texture, tile);
+            }
+        }
+    }
+}
+
+/// How long [read_buffer] sleeps on the mapping callback in between polls of the device
+const READBACK_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Reads the start of a buffer which has [BufferUsages::MAP_READ] once the GPU is done with it. The device is
+/// polled without blocking, so other threads can keep submitting and mapping while this one waits, and the thread
+/// sleeps on the callback in between polls instead of spinning a core.
+fn read_buffer<T: Pod>(wgpu_state: &WgpuState, buffer: &wgpu::Buffer, size: u64) -> Option<Vec<T>> {
+    let slice = buffer.slice(..size);
+    let (sender, receiver) = mpsc::channel();
+
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+
+    let result = loop {
+        wgpu_state.device.poll(wgpu::Maintain::Poll);
+
+        match receiver.recv_timeout(READBACK_POLL_INTERVAL) {
+            Ok(result) => break result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return None,
+        }
+    };
+
+    result.ok()?;
+
+    let data = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    buffer.unmap();
+
+    Some(data)
+}
+
+/// The buffers the shader writes to, and the buffers they're copied to for reading them back. Each bake takes a set
+/// from the pool of the [GpuMesher] and returns it when done, so chunks are meshed in parallel.
+#[derive(Debug)]
+struct MesherBuffers {
+    params: wgpu::Buffer,
+    vertices: wgpu::Buffer,
+    indices: wgpu::Buffer,
+    quad_count: wgpu::Buffer,
+    vertices_readback: wgpu::Buffer,
+    indices_readback: wgpu::Buffer,
+    quad_count_readback: wgpu::Buffer,
+}
+
+impl MesherBuffers {
+    fn new(device: &wgpu::Device) -> Self {
+        let buffer = |label: &str, size: u64, usage: BufferUsages| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        let vertices_size = MAX_QUADS as u64 * 4 * std::mem::size_of::<Vertex>() as u64;
+        let indices_size = MAX_QUADS as u64 * 6 * 4;
+
+        Self {
+            params: buffer(
+                "Chunk mesher params",
+                std::mem::size_of::<Params>() as u64,
+                BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            ),
+            vertices: buffer(
+                "Chunk mesher vertices",
+                vertices_size,
+                BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            ),
+            indices: buffer(
+                "Chunk mesher indices",
+                indices_size,
+                BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            ),
+            quad_count: buffer(
+                "Chunk mesher quad count",
+                4,
+                BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            ),
+            vertices_readback: buffer(
+                "Chunk mesher vertices readback",
+                vertices_size,
+                BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            ),
+            indices_readback: buffer(
+                "Chunk mesher indices readback",
+                indices_size,
+                BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            ),
+            quad_count_readback: buffer(
+                "Chunk mesher quad count readback",
+                4,
+                BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct BufferPool {
+    free: Vec<MesherBuffers>,
+    created: usize,
+}
+
+#[derive(Debug)]
+pub struct GpuMesher {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    buffers: Mutex<BufferPool>,
+}
+
+impl GpuMesher {
+    /// Returns [None] if the device doesn't support compute shaders, or the shader is missing from the
+    /// resource provider
+    #[must_use]
+    pub fn new(wm: &WmRenderer) -> Option<Self> {
+        let wgpu_state = &wm.wgpu_state;
+
+        if !wgpu_state
+            .adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            || wgpu_state
+                .device
+                .limits()
+                .max_storage_buffers_per_shader_stage
+                < 5
+        {
+            log::warn!(
+                "The device doesn't support compute shaders, chunks will be baked on the CPU"
+            );
+            return None;
+        }
+
+        let source = wm
+            .mc
+            .resource_provider
+            .get_string(&ResourcePath::from("wgpu_mc:shaders/chunk_mesher.wgsl"));
+
+        let source = match source {
+            None => {
+                log::warn!("The chunk meshing shader is missing, chunks will be baked on the CPU");
+                return None;
+            }
+            Some(source) => source,
+        };
+
+        let device = &wgpu_state.device;
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Chunk mesher"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let entry = |binding: u32, ty: BufferBindingType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Chunk mesher"),
+            entries: &[
+                entry(0, BufferBindingType::Uniform),
+                entry(1, BufferBindingType::Storage { read_only: true }),
+                entry(2, BufferBindingType::Storage { read_only: true }),
+                entry(3, BufferBindingType::Storage { read_only: false }),
+                entry(4, BufferBindingType::Storage { read_only: false }),
+                entry(5, BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Chunk mesher"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Chunk mesher"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "mesh",
+        });
+
+        Some(Self {
+            pipeline,
+            bind_group_layout,
+            buffers: Mutex::new(BufferPool::default()),
+        })
+    }
+
+    /// Bakes every section of the chunk into `layer_name`, the same as [crate::mc::chunk::Chunk::bake_sections].
+    /// Blocks until the GPU is done. Returns [None] if the chunk has to be baked on the CPU, which is also the case
+    /// when [MAX_BUFFER_SETS] chunks are already being meshed.
+    pub fn bake_sections(
+        &self,
+        wgpu_state: &WgpuState,
+        pos: ChunkPos,
+        layer_name: &str,
+        block_manager: &BlockManager,
+        provider: &impl BlockStateProvider,
+        ambient_occlusion: bool,
+    ) -> Option<Vec<BakedSection>> {
+        let (palette, blocks) = collect_blocks(block_manager, provider, pos)?;
+
+        let buffers = {
+            let mut pool = self.buffers.lock();
+
+            match pool.free.pop() {
+                Some(buffers) => buffers,
+                None if pool.created < MAX_BUFFER_SETS => {
+                    pool.created += 1;
+                    MesherBuffers::new(&wgpu_state.device)
+                }
+                None => return None,
+            }
+        };
+
+        let meshed = self.mesh(wgpu_state, &buffers, &palette, &blocks, ambient_occlusion);
+        self.buffers.lock().free.push(buffers);
+        let (mut vertices, indices) = meshed?;
+
+        connect_faces(&mut vertices, block_manager, provider, pos);
+
+        let sections = split_sections(&vertices, &indices)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (vertices, indices))| {
+                let mut mesh = HashMap::new();
+
+                if !vertices.is_empty() {
+                    mesh.insert((layer_name.into(), RenderType::Solid), (vertices, indices));
+                }
+
+                BakedSection {
+                    index,
+                    mesh,
+                    visibility: SectionVisibility::compute(block_manager, provider, pos, index),
+                }
+            })
+            .collect();
+
+        Some(sections)
+    }
+
+    /// Runs the shader on the blocks of a chunk and reads back the vertices and indices of its faces
+    fn mesh(
+        &self,
+        wgpu_state: &WgpuState,
+        buffers: &MesherBuffers,
+        palette: &[PaletteEntry],
+        blocks: &[u32],
+        ambient_occlusion: bool,
+    ) -> Option<(Vec<Vertex>, Vec<u32>)> {
+        let device = &wgpu_state.device;
+        let queue = &wgpu_state.queue;
+
+        let palette_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Chunk mesher palette"),
+            contents: bytemuck::cast_slice(palette),
+            usage: BufferUsages::STORAGE,
+        });
+        let blocks_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Chunk mesher blocks"),
+            contents: bytemuck::cast_slice(blocks),
+            usage: BufferUsages::STORAGE,
+        });
+
+        queue.write_buffer(
+            &buffers.params,
+            0,
+            bytemuck::bytes_of(&Params {
+                max_quads: MAX_QUADS,
+                ambient_occlusion: ambient_occlusion as u32,
+                padding: [0; 2],
+            }),
+        );
+        queue.write_buffer(&buffers.quad_count, 0, &[0; 4]);
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Chunk mesher"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                &buffers.params,
+                &palette_buffer,
+                &blocks_buffer,
+                &buffers.vertices,
+                &buffers.indices,
+                &buffers.quad_count,
+            ]
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect::<Vec<_>>(),
+        });
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+
+        dispatch(
+            &mut encoder,
+            "Chunk mesher",
+            &self.pipeline,
+            &[&bind_group],
+            [workgroup_count(CHUNK_VOLUME as u32, WORKGROUP_SIZE), 1, 1],
+        );
+
+        encoder.copy_buffer_to_buffer(&buffers.quad_count, 0, &buffers.quad_count_readback, 0, 4);
+        queue.submit([encoder.finish()]);
+
+        let quads = read_buffer::<u32>(wgpu_state, &buffers.quad_count_readback, 4)?[0];
+
+        if quads > MAX_QUADS {
+            return None;
+        }
+
+        if quads == 0 {
+            Some((Vec::new(), Vec::new()))
+        } else {
+            let vertices_size = quads as u64 * 4 * std::mem::size_of::<Vertex>() as u64;
+            let indices_size = quads as u64 * 6 * 4;
+
+            let mut encoder = device.create_command_encoder(&Default::default());
+            encoder.copy_buffer_to_buffer(
+                &buffers.vertices,
+                0,
+                &buffers.vertices_readback,
+                0,
+                vertices_size,
+            );
+            encoder.copy_buffer_to_buffer(
+                &buffers.indices,
+                0,
+                &buffers.indices_readback,
+                0,
+                indices_size,
+            );
+            queue.submit([encoder.finish()]);
+
+            Some((
+                read_buffer::<Vertex>(wgpu_state, &buffers.vertices_readback, vertices_size)?,
+                read_buffer::<u32>(wgpu_state, &buffers.indices_readback, indices_size)?,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use indexmap::IndexMap;
+
+    use super::{
+        collect_blocks, connect_faces, split_sections, PaletteEntry, FACE_CORNERS, MESHED, OCCLUDES,
+    };
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::{
+        BlockMeshVertex, BlockModelFaces, BlockstateKey, ChunkBlockState, CubeOrComplexMesh,
+        ModelMesh, RenderType, NO_EMISSIVE, NO_TINT,
+    };
+    use crate::mc::chunk::BlockStateProvider;
+    use crate::mc::ctm::{Connect, ConnectedFace, CtmMethod, CtmRule};
+    use crate::mc::{Block, BlockManager};
+    use crate::render::pipeline::Vertex;
+
+    /// Contains the same block at each of the positions, and air everywhere else
+    #[derive(Debug)]
+    struct BlocksProvider(Vec<(i32, i16, i32)>);
+
+    impl BlockStateProvider for BlocksProvider {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            if self.0.contains(&(x, y, z)) {
+                ChunkBlockState::State(BlockstateKey {
+                    block: 0,
+                    augment: 0,
+                })
+            } else {
+                ChunkBlockState::Air
+            }
+        }
+
+        fn is_section_empty(&self, _index: usize) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn palette_entry_takes_tex_coords_from_the_closest_vertices() {
+        //The up face of the model, with the texture rotated relative to FACE_CORNERS
+        let corners = FACE_CORNERS[4];
+        let tex_coords = [[0.0, 1.0], [0.0, 0.0], [1.0, 0.0], [1.0, 1.0]];
+        let up = [0, 1, 2, 2, 3, 0].map(|corner| BlockMeshVertex {
+            position: corners[corner],
+            tex_coords: tex_coords[corner],
+            normal: [0.0, 1.0, 0.0, 0.0],
+            animation_uv_offset: 7,
+            emissive_tex_coords: NO_EMISSIVE,
+            tint_index: NO_TINT,
+        });
+
+        let mesh = ModelMesh {
+            models: vec![(
+                CubeOrComplexMesh::Cube(Box::new(BlockModelFaces {
+                    north: None,
+                    east: None,
+                    south: None,
+                    west: None,
+                    up: Some(up),
+                    down: None,
+                    connected: Default::default(),
+                })),
+                true,
+            )],
+            is_full_opaque_cube: false,
+            render_type: RenderType::Solid,
+            weights: vec![1],
+        };
+
+        let entry = PaletteEntry::new(Some(&mesh));
+
+        assert_eq!(entry.flags, MESHED | 4 << 4);
+        assert_eq!(entry.tex_coords[8], [0.0, 1.0, 0.0, 0.0]);
+        assert_eq!(entry.tex_coords[9], [1.0, 0.0, 1.0, 1.0]);
+        assert_eq!(entry.animation_uv_offsets[1][0], 7);
+
+        //Translucent blocks aren't meshed on the GPU
+        let translucent = ModelMesh {
+            render_type: RenderType::Translucent,
+            is_full_opaque_cube: true,
+            ..mesh
+        };
+
+        assert_eq!(PaletteEntry::new(Some(&translucent)).flags, OCCLUDES);
+    }
+
+    #[test]
+    fn chunks_past_the_addressable_range_are_left_to_the_cpu() {
+        let block_manager = BlockManager {
+            blocks: IndexMap::new(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        };
+        let provider = BlocksProvider(Vec::new());
+
+        assert!(collect_blocks(&block_manager, &provider, [0, 0]).is_some());
+        assert!(collect_blocks(&block_manager, &provider, [i32::MAX, 0]).is_none());
+        assert!(collect_blocks(&block_manager, &provider, [0, i32::MIN]).is_none());
+    }
+
+    #[test]
+    fn quads_are_split_by_the_section_of_their_block() {
+        let quad = |y: f32, normal_y: f32| {
+            [0.0, 1.0, 1.0, 0.0].map(|x| Vertex {
+                position: [x, y, 0.0],
+                normal: [0.0, normal_y, 0.0, 0.0],
+                ..bytemuck::Zeroable::zeroed()
+            })
+        };
+
+        //The up face of the block at y 15 lies on the border of the second section
+        let vertices: Vec<Vertex> = [quad(16.0, 1.0), quad(16.0, -1.0), quad(3.0, 1.0)].concat();
+        let indices: Vec<u32> = (0..3)
+            .flat_map(|quad| [0, 1, 2, 2, 3, 0].map(|index| quad * 4 + index))
+            .collect();
+
+        let sections = split_sections(&vertices, &indices);
+
+        assert_eq!(sections[0].0.len(), 8);
+        assert_eq!(sections[1].0.len(), 4);
+        assert_eq!(sections[0].1, [0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]);
+        assert_eq!(sections[1].1, [0, 1, 2, 2, 3, 0]);
+    }
+
+    #[test]
+    fn connected_faces_get_their_tile_once_read_back() {
+        //The north face, with the texture going along +x and down along -y
+        let corners = FACE_CORNERS[0];
+        let tex_coords = [[0.0, 1.0], [1.0, 1.0], [1.0, 0.0], [0.0, 0.0]];
+        let north = [0, 1, 2, 2, 3, 0].map(|corner| BlockMeshVertex {
+            position: corners[corner],
+            tex_coords: tex_coords[corner],
+            normal: [0.0, 0.0, -1.0, 0.0],
+            animation_uv_offset: 0,
+            emissive_tex_coords: NO_EMISSIVE,
+            tint_index: NO_TINT,
+        });
+
+        //Each tile is right of the one before it in the atlas
+        let connected = Arc::new(ConnectedFace {
+            rule: Arc::new(CtmRule {
+                method: CtmMethod::Ctm,
+                connect: Connect::Block,
+                match_tiles: Vec::new(),
+                match_blocks: Vec::new(),
+                tiles: Vec::new(),
+            }),
+            texture: ((0.0, 0.0), (1.0, 1.0)),
+            tiles: (0..47)
+                .map(|tile| ((tile as f32 + 1.0, 0.0), (tile as f32 + 2.0, 1.0)))
+                .collect(),
+        });
+
+        let mut connected_faces: [Option<Arc<ConnectedFace>>; 6] = Default::default();
+        connected_faces[0] = Some(connected.clone());
+
+        let mesh = Arc::new(ModelMesh {
+            models: vec![(
+                CubeOrComplexMesh::Cube(Box::new(BlockModelFaces {
+                    north: Some(north),
+                    east: None,
+                    south: None,
+                    west: None,
+                    up: None,
+                    down: None,
+                    connected: connected_faces,
+                })),
+                true,
+            )],
+            is_full_opaque_cube: true,
+            render_type: RenderType::Solid,
+            weights: vec![1],
+        });
+
+        let block_manager = BlockManager {
+            blocks: [(
+                "wgpu_mc:test".into(),
+                Block::Variants(IndexMap::from([("".into(), mesh)])),
+            )]
+            .into_iter()
+            .collect(),
+            shapes: HashMap::new(),
+            colors: BlockColors::default(),
+        };
+
+        //The face of the block at [16, 0, 16] with the next one along +x, as the shader emits it
+        let mut vertices = [0, 1, 2, 3].map(|corner| Vertex {
+            position: corners[corner],
+            tex_coords: tex_coords[corner],
+            normal: [0.0, 0.0, -1.0, 0.0],
+            ..bytemuck::Zeroable::zeroed()
+        });
+
+        connect_faces(
+            &mut vertices,
+            &block_manager,
+            &BlocksProvider(vec![(16, 0, 16), (17, 0, 16)]),
+            [1, 1],
+        );
+
+        let ((min_u, _), (max_u, _)) = connected
+            .connected_tile(&north, |offset| offset == [1, 0, 0])
+            .unwrap();
+
+        assert_eq!(
+            vertices.map(|vertex| vertex.tex_coords),
+            [[min_u, 1.0], [max_u, 1.0], [max_u, 0.0], [min_u, 0.0]]
+        );
+    }
+}
@@ -2,35 +2,75 @@
 //!
 //! This is about the rendering pipeline, and implements the logic behind
 //! [shaderpack::ShaderPackConfig].
+//!
+//! The pipelines of the pack are the passes of the graph. They don't run in the order they're declared in, but in
+//! the one given by [passes::resolve_order], from the textures they output to and the uniforms they read.
+
+pub mod passes;
 
 use arc_swap::ArcSwap;
 use cgmath::{Matrix3, Matrix4, SquareMatrix};
-use parking_lot::RwLock;
-use std::collections::HashMap;
+use indexmap::IndexMap;
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
 use treeculler::{BVol, Frustum, Vec3, AABB};
 
-use crate::mc::chunk::{Chunk, ChunkPos};
-use crate::mc::resource::ResourcePath;
-use crate::render::pipeline::{QuadVertex, Vertex, BLOCK_ATLAS};
-use crate::render::shader::WgslShader;
+use crate::mc::block::{BlockPos, BlockShape, RenderType};
+use crate::mc::chunk::{ChunkPos, SectionBuffers, CHUNK_SECTION_HEIGHT};
+use crate::mc::entity::culling::EntityCamera;
+use crate::mc::lod::LodLevel;
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::mc::visibility::visible_sections;
+use crate::render::atlas::{AtlasKind, PbrMap};
+use crate::render::clouds::{cloud_vertices, CloudVertex};
+use crate::render::colormap::colormap_resource;
+use crate::render::entity::EntityVertex;
+use crate::render::gpu_culler::{frustum_planes, GpuCuller, SectionBounds};
+use crate::render::graph::passes::{resolve_order, PassNode};
+use crate::render::particle::gpu::{GpuParticle, GPU_PARTICLE_CAPACITY};
+use crate::render::particle::ParticleInstance;
+use crate::render::pipeline::beam::{beam_vertices, BeamVertex};
+use crate::render::pipeline::block_breaking::{breaking_vertices, BreakingVertex};
+use crate::render::pipeline::block_outline::outline_vertices;
+use crate::render::pipeline::debug_lines::{
+    chunk_border_vertices, line_depth_offset, DebugLineVertex, DepthBiasPresets,
+};
+use crate::render::pipeline::entity::{ENTITY_INSTANCES, ENTITY_TEXTURE};
+use crate::render::pipeline::entity_shadow::{
+    name_tag_vertices, shadow_vertices, NameTagVertex, ShadowVertex,
+};
+use crate::render::pipeline::first_person::FIRST_PERSON_PROJECTION;
+use crate::render::pipeline::{ChunkInstance, ChunkVertexFormat, QuadVertex};
+use crate::render::registry::{phase_positions, RenderPhase};
+use crate::render::reverse_z::{clear_depth, depth_bias, depth_compare};
+use crate::render::shader::{
+    load_pipeline_shader, shader_files, MissingShaderError, ShaderError, ShaderFeatures,
+};
+use crate::render::shaderpack::pack::ShaderPack;
 use crate::render::shaderpack::{
-    LonghandResourceConfig, Mat3ValueOrMult, Mat4ValueOrMult, PipelineConfig, ShaderPackConfig,
-    ShorthandResourceConfig, TypeResourceConfig,
+    DepthCompare, LonghandResourceConfig, Mat3ValueOrMult, Mat4ValueOrMult, PipelineConfig,
+    ShaderPackConfig, ShaderPackError, ShorthandResourceConfig, Topology, TypeResourceConfig,
 };
-use crate::texture::{BindableTexture, TextureHandle};
-use crate::util::{BindableBuffer, WmArena};
+use crate::render::sky::{sky_vertices, SkyVertex};
+use crate::render::uniforms::{FrameUniforms, FRAME_UNIFORMS};
+use crate::texture::{BindableTexture, TextureHandle, TextureSamplerView};
+use crate::util::{BindableBuffer, UniformAllocator, UniformSlot, WmArena};
 use crate::WmRenderer;
 
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::{
-    BufferUsages, ColorTargetState, CommandEncoderDescriptor, DepthStencilState, FragmentState,
-    LoadOp, Operations, PipelineLayoutDescriptor, PushConstantRange, RenderPass,
+    BufferUsages, ColorTargetState, CommandEncoderDescriptor, DepthBiasState, DepthStencilState,
+    Extent3d, FragmentState, IndexFormat, LoadOp, MultisampleState, Operations,
+    PipelineLayoutDescriptor, PolygonMode, PrimitiveState, PushConstantRange, RenderPass,
     RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, ShaderStages, SurfaceConfiguration, TextureFormat,
-    VertexBufferLayout, VertexState,
+    RenderPipeline, RenderPipelineDescriptor, ShaderStages, StencilFaceState, StencilOperation,
+    StencilState, SurfaceConfiguration, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages, VertexBufferLayout, VertexState,
 };
 
 pub trait GeometryCallback: Send + Sync {
@@ -49,12 +89,12 @@ pub trait GeometryCallback: Send + Sync {
 
 fn mat3_update(
     resource: &CustomResource,
-    wm: &WmRenderer,
+    _wm: &WmRenderer,
     resources: &HashMap<String, CustomResource>,
 ) {
     let mut mat3 = Matrix3::<f32>::identity();
 
-    if let ResourceInternal::Mat3(Mat3ValueOrMult::Mult { mult }, lock, ssbo) = &*resource.data {
+    if let ResourceInternal::Mat3(Mat3ValueOrMult::Mult { mult }, lock, _) = &*resource.data {
         mult.iter().for_each(|mat_name| {
             let resource = resources.get(mat_name).unwrap();
 
@@ -66,24 +106,19 @@ fn mat3_update(
             }
         });
 
+        //Uploaded along with the other matrices, see [ShaderGraph::write_uniforms]
         *lock.write() = mat3;
-
-        let mat3_array: [[f32; 3]; 3] = mat3.into();
-
-        wm.wgpu_state
-            .queue
-            .write_buffer(&ssbo.buffer, 0, bytemuck::cast_slice(&mat3_array));
     }
 }
 
 fn mat4_update(
     resource: &CustomResource,
-    wm: &WmRenderer,
+    _wm: &WmRenderer,
     resources: &HashMap<String, CustomResource>,
 ) {
     let mut mat4 = Matrix4::<f32>::identity();
 
-    if let ResourceInternal::Mat4(Mat4ValueOrMult::Mult { mult }, lock, ssbo) = &*resource.data {
+    if let ResourceInternal::Mat4(Mat4ValueOrMult::Mult { mult }, lock, _) = &*resource.data {
         mult.iter().for_each(|mat_name| {
             {
                 let resource = resources.get(mat_name).expect(mat_name);
@@ -96,13 +131,8 @@ fn mat4_update(
             };
         });
 
+        //Uploaded along with the other matrices, see [ShaderGraph::write_uniforms]
         *lock.write() = mat4;
-
-        let mat4_array: [[f32; 4]; 4] = mat4.into();
-
-        wm.wgpu_state
-            .queue
-            .write_buffer(&ssbo.buffer, 0, bytemuck::cast_slice(&mat4_array));
     }
 }
 
@@ -116,21 +146,18 @@ pub enum TextureResource {
 pub enum ResourceInternal {
     Texture(TextureResource, bool),
     Blob(BindableBuffer),
-    Mat3(
-        Mat3ValueOrMult,
-        Arc<RwLock<Matrix3<f32>>>,
-        Arc<BindableBuffer>,
-    ),
-    Mat4(
-        Mat4ValueOrMult,
-        Arc<RwLock<Matrix4<f32>>>,
-        Arc<BindableBuffer>,
-    ),
+    /// The matrix is written to [WmRenderer::uniforms] every frame, see [ShaderGraph::write_uniforms]
+    Mat3(Mat3ValueOrMult, Arc<RwLock<Matrix3<f32>>>, Arc<UniformSlot>),
+    /// The matrix is written to [WmRenderer::uniforms] every frame, see [ShaderGraph::write_uniforms]
+    Mat4(Mat4ValueOrMult, Arc<RwLock<Matrix4<f32>>>, Arc<UniformSlot>),
     F32(f32, BindableBuffer),
     F64(f64, BindableBuffer),
     U32(u32, BindableBuffer),
     I32(i32, BindableBuffer),
     I64(i64, BindableBuffer),
+    /// A uniform for the vertex and fragment stages which is written to [WmRenderer::uniforms] every frame, like
+    /// [crate::render::uniforms::FRAME_UNIFORMS]
+    Uniform(UniformSlot),
 }
 
 pub struct CustomResource {
@@ -149,13 +176,93 @@ impl CustomResource {
     }
 }
 
+/// The textures a [ShaderGraph] draws into instead of the framebuffer and its depth textures while MSAA is on.
+/// Depth textures which are bound as uniforms aren't written to then.
+struct MsaaTargets {
+    samples: u32,
+    format: TextureFormat,
+    size: [u32; 2],
+    color: wgpu::TextureView,
+    depth: HashMap<String, wgpu::TextureView>,
+}
+
+/// The vertices of [WmRenderer::block_outline], which are only made again when the outline or the chunk offset changes
+struct OutlineBuffer {
+    outline: Arc<Option<(BlockPos, BlockShape)>>,
+    chunk_offset: ChunkPos,
+    buffer: Arc<wgpu::Buffer>,
+    vertices: u32,
+}
+
+/// The settings of the [WmRenderer] which every pipeline of the graph is built for
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct PipelineTargets {
+    surface_format: TextureFormat,
+    /// [WmRenderer::msaa_samples]
+    samples: u32,
+    /// [WmRenderer::depth_prepass]
+    depth_prepass: bool,
+    /// [WmRenderer::debug_polygon_mode]
+    polygon_mode: PolygonMode,
+    /// [WmRenderer::reverse_z]
+    reverse_z: bool,
+}
+
+impl PipelineTargets {
+    fn new(wm: &WmRenderer, surface_format: TextureFormat) -> Self {
+        Self {
+            surface_format,
+            samples: **wm.msaa_samples.load(),
+            depth_prepass: **wm.depth_prepass.load(),
+            polygon_mode: **wm.debug_polygon_mode.load(),
+            reverse_z: **wm.reverse_z.load(),
+        }
+    }
+}
+
+/// A pipeline of the pack and its depth pre-pass, see [ShaderGraph::depth_prepasses]
+type Permutation = (Arc<RenderPipeline>, Option<Arc<RenderPipeline>>);
+
 /// This struct holds information on the entirety of the rendering pipeline.
 pub struct ShaderGraph {
+    /// The pipelines which are drawn, those of [ShaderGraph::shader_pack] if there is one
     pub pack: ShaderPackConfig,
-    pub pipelines: HashMap<String, RenderPipeline>,
+    /// The pipelines the graph was created with, which a shaderpack is applied to
+    builtin: ShaderPackConfig,
+    shader_pack: Option<Arc<ShaderPack>>,
+    /// Replaced as a whole by [ShaderGraph::rebuild_pipelines] and [ShaderGraph::reload_shader], so a frame
+    /// never mixes old and new pipelines
+    pub pipelines: ArcSwap<HashMap<String, Arc<RenderPipeline>>>,
     pub resources: HashMap<String, CustomResource>,
     pub geometry: HashMap<String, Box<dyn GeometryCallback>>,
     quad: Option<wgpu::Buffer>,
+    /// Set by [ShaderGraph::init] if [crate::WmConfig::gpu_culling] is enabled and the device supports it
+    gpu_culler: Option<GpuCuller>,
+    visible_chunks: AtomicUsize,
+    /// The bind group layout of each resource which the graph doesn't create, passed to [ShaderGraph::init]
+    resource_types: HashMap<String, String>,
+    /// The vertex layout of each geometry which the graph doesn't know, passed to [ShaderGraph::init]
+    additional_geometry: HashMap<String, VertexBufferLayout<'static>>,
+    /// The depth-only pipelines drawn before the pipelines of the same name, see [WmRenderer::depth_prepass].
+    /// Replaced along with the pipelines.
+    depth_prepasses: ArcSwap<HashMap<String, Arc<RenderPipeline>>>,
+    /// What the pipelines were built for, they're rebuilt when any of it changes
+    built_for: Mutex<Option<PipelineTargets>>,
+    /// [WmRenderer::shader_features] when the pipelines were built
+    built_features: Mutex<Arc<ShaderFeatures>>,
+    /// [crate::mc::MinecraftState::shader_generation] when the shaders were last compiled
+    shader_generation: AtomicU64,
+    /// The pipeline and depth pre-pass built for each pipeline of the pack and permutation of its shader, so that
+    /// switching back to a set of features doesn't compile its shaders again. Emptied when the targets change.
+    permutations: Mutex<HashMap<(String, ShaderFeatures), Permutation>>,
+    msaa_targets: Mutex<Option<Arc<MsaaTargets>>>,
+    outline_buffer: Mutex<Option<Arc<OutlineBuffer>>>,
+    /// [WmRenderer::uniforms], set by [ShaderGraph::init] if the device doesn't support push constants
+    push_constant_fallback: Option<Arc<UniformAllocator>>,
+    /// The indices of the pipelines of the pack in the order they're drawn in, see [ShaderGraph::pass_order]
+    order: Vec<usize>,
+    /// When the graph was created, for [FrameUniforms::elapsed_time]
+    created: Instant,
 }
 
 impl ShaderGraph {
@@ -164,23 +271,131 @@ impl ShaderGraph {
         resources: HashMap<String, CustomResource>,
         geometry: HashMap<String, Box<dyn GeometryCallback>>,
     ) -> Self {
+        let order = pipeline_order(&pack);
+
         Self {
+            builtin: pack.clone(),
             pack,
-            pipelines: HashMap::new(),
+            shader_pack: None,
+            pipelines: ArcSwap::new(Arc::new(HashMap::new())),
             resources,
             geometry,
             quad: None,
+            gpu_culler: None,
+            visible_chunks: AtomicUsize::new(0),
+            resource_types: HashMap::new(),
+            additional_geometry: HashMap::new(),
+            depth_prepasses: ArcSwap::new(Arc::new(HashMap::new())),
+            built_for: Mutex::new(None),
+            built_features: Mutex::new(Arc::new(ShaderFeatures::new())),
+            shader_generation: AtomicU64::new(0),
+            permutations: Mutex::new(HashMap::new()),
+            msaa_targets: Mutex::new(None),
+            outline_buffer: Mutex::new(None),
+            push_constant_fallback: None,
+            order,
+            created: Instant::now(),
+        }
+    }
+
+    /// The names of the pipelines of the pack in the order they're drawn in
+    pub fn pass_order(&self) -> Vec<&str> {
+        let names: Vec<&String> = self.pack.pipelines.pipelines.keys().collect();
+
+        self.order.iter().map(|&index| &names[index][..]).collect()
+    }
+
+    /// The shaderpack set with [ShaderGraph::set_shader_pack]
+    pub fn shader_pack(&self) -> Option<&Arc<ShaderPack>> {
+        self.shader_pack.as_ref()
+    }
+
+    /// Where the shaders of the pipelines are looked up, the shaderpack first if there is one
+    pub fn resource_provider(&self, wm: &WmRenderer) -> Arc<dyn ResourceProvider> {
+        match &self.shader_pack {
+            Some(shader_pack) => shader_pack.resource_provider(wm.mc.resource_provider.clone()),
+            None => wm.mc.resource_provider.clone(),
+        }
+    }
+
+    /// The number of chunks which passed frustum culling in the last frame
+    pub fn visible_chunks(&self) -> usize {
+        self.visible_chunks.load(Ordering::Relaxed)
+    }
+
+    /// Checks that the shader of every pipeline in the pack can be found
+    pub fn validate_shaders(
+        &self,
+        resource_provider: &dyn ResourceProvider,
+    ) -> Result<(), MissingShaderError> {
+        let missing: Vec<ResourcePath> = self
+            .pack
+            .pipelines
+            .pipelines
+            .keys()
+            .flat_map(|name| shader_files(&self.pack.shader_path(name)))
+            .filter(|path| resource_provider.get_bytes(path).is_none())
+            .collect();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(MissingShaderError { missing })
         }
     }
 
+    /// Creates the builtin resources and builds every pipeline of the pack. Fails if a shader of the pack is
+    /// missing or doesn't compile, in which case nothing can be drawn.
     pub fn init(
         &mut self,
         wm: &WmRenderer,
         resource_types: Option<&HashMap<String, String>>,
-        mut additional_geometry: Option<HashMap<String, VertexBufferLayout>>,
-    ) {
+        additional_geometry: Option<HashMap<String, VertexBufferLayout<'static>>>,
+    ) -> Result<(), ShaderPackError> {
+        self.resource_types = resource_types.cloned().unwrap_or_default();
+        self.shader_generation = AtomicU64::new(wm.mc.shader_generation.load(Ordering::Acquire));
+
+        //Bound by the entity pipeline for each draw
+        for (uniform, layout) in [
+            (ENTITY_INSTANCES, "entity_instances"),
+            (ENTITY_TEXTURE, "texture"),
+        ] {
+            self.resource_types
+                .entry(uniform.into())
+                .or_insert_with(|| layout.into());
+        }
+        self.additional_geometry = additional_geometry.unwrap_or_default();
+
+        //Without PBR, the device only has the bind groups for the other uniforms, see
+        //WmRenderer::compute_required_limits
+        if !wm.config.pbr {
+            self.pack
+                .pipelines
+                .pipelines
+                .values_mut()
+                .for_each(drop_trailing_pbr_maps);
+        }
+
+        self.order = pipeline_order(&self.pack);
+
+        self.validate_shaders(&*self.resource_provider(wm))
+            .map_err(ShaderPackError::MissingShaders)?;
+
         let mut resources = HashMap::new();
 
+        if wm.config.gpu_culling {
+            self.gpu_culler = GpuCuller::new(wm);
+        }
+
+        if !wm
+            .wgpu_state
+            .device
+            .features()
+            .contains(wgpu::Features::PUSH_CONSTANTS)
+        {
+            self.push_constant_fallback = Some(wm.uniforms.clone());
+        }
+
         self.quad = Some(
             wm.wgpu_state
                 .device
@@ -193,23 +408,77 @@ impl ShaderGraph {
                 }),
         );
 
-        let block_atlas = wm
-            .mc
-            .texture_manager
-            .atlases
-            .load()
-            .get(BLOCK_ATLAS)
-            .unwrap()
-            .load();
+        for kind in AtlasKind::ALL {
+            let atlas = wm.mc.texture_manager.atlas(kind);
+
+            resources.insert(
+                kind.resource().into(),
+                CustomResource {
+                    update: None,
+                    data: Arc::new(ResourceInternal::Texture(
+                        TextureResource::Bindable(atlas.bindable_texture.clone()),
+                        false,
+                    )),
+                },
+            );
+
+            //Bound even without WmConfig::pbr, so that packs don't need another graph for it, unless they're the
+            //last uniforms of a pipeline, see drop_trailing_pbr_maps
+            for map in PbrMap::ALL {
+                let texture = match atlas.pbr_maps.iter().find(|pbr| pbr.map == map) {
+                    Some(pbr) => pbr.bindable_texture.clone(),
+                    None => Arc::new(ArcSwap::new(Arc::new(
+                        map.default_texture(&wm.wgpu_state, &wm.pipelines.load()),
+                    ))),
+                };
+
+                resources.insert(
+                    map.resource(kind),
+                    CustomResource {
+                        update: None,
+                        data: Arc::new(ResourceInternal::Texture(
+                            TextureResource::Bindable(texture),
+                            false,
+                        )),
+                    },
+                );
+            }
+        }
+
+        if let Some(lightmap) = &**wm.mc.lightmap.load() {
+            resources.insert(
+                "wm_texture_lightmap".into(),
+                CustomResource {
+                    update: None,
+                    data: Arc::new(ResourceInternal::Texture(
+                        TextureResource::Bindable(Arc::new(ArcSwap::new(lightmap.clone()))),
+                        false,
+                    )),
+                },
+            );
+        }
+
+        for (name, colormap) in wm.mc.colormaps.load().iter() {
+            resources.insert(
+                colormap_resource(name),
+                CustomResource {
+                    update: None,
+                    data: Arc::new(ResourceInternal::Texture(
+                        TextureResource::Bindable(colormap.clone()),
+                        false,
+                    )),
+                },
+            );
+        }
+
+        Self::insert_pack_uniforms(wm, &mut resources);
+        Self::insert_first_person_projection(&mut resources);
 
         resources.insert(
-            "wm_texture_atlas_blocks".into(),
+            FRAME_UNIFORMS.into(),
             CustomResource {
                 update: None,
-                data: Arc::new(ResourceInternal::Texture(
-                    TextureResource::Bindable(block_atlas.bindable_texture.clone()),
-                    false,
-                )),
+                data: Arc::new(ResourceInternal::Uniform(UniformSlot::default())),
             },
         );
 
@@ -219,154 +488,707 @@ impl ShaderGraph {
             Self::insert_resources(&wm, &mut resources, definition, resource_id);
         }
 
+        self.resources.extend(resources.into_iter());
+
+        self.rebuild_pipelines(wm).map_err(ShaderPackError::Shader)
+    }
+
+    /// Switches to the pipelines and shaders of the shaderpack, or back to the builtin ones with [None], and
+    /// rebuilds every pipeline. Resources only the pack declares are created. If a shader is missing or doesn't
+    /// compile, the current pack is kept.
+    pub fn set_shader_pack(
+        &mut self,
+        wm: &WmRenderer,
+        shader_pack: Option<Arc<ShaderPack>>,
+    ) -> Result<(), ShaderPackError> {
+        let pack = match &shader_pack {
+            Some(shader_pack) => shader_pack.apply(&self.builtin)?,
+            None => self.builtin.clone(),
+        };
+
+        for (resource_id, definition) in &pack.resources.resources {
+            if !self.resources.contains_key(resource_id) {
+                Self::insert_resources(&wm, &mut self.resources, definition, resource_id.clone());
+            }
+        }
+
+        let previous_pack = std::mem::replace(&mut self.pack, pack);
+        let previous_shader_pack = std::mem::replace(&mut self.shader_pack, shader_pack);
+
+        let result = self
+            .validate_shaders(&*self.resource_provider(wm))
+            .map_err(ShaderPackError::MissingShaders)
+            .and_then(|_| self.rebuild_pipelines(wm).map_err(ShaderPackError::Shader));
+
+        match result {
+            Ok(()) => {
+                self.order = pipeline_order(&self.pack);
+                //The pack can draw into other depth textures
+                *self.msaa_targets.lock() = None;
+
+                Ok(())
+            }
+            Err(error) => {
+                self.pack = previous_pack;
+                self.shader_pack = previous_shader_pack;
+
+                Err(error)
+            }
+        }
+    }
+
+    /// Uploads [WmRenderer::frame_uniforms] with the time, the size of the surface and the camera filled in, along
+    /// with the resources of the ones shaderpacks bind on their own
+    fn write_frame_uniforms(
+        &self,
+        wm: &WmRenderer,
+        surface_config: &SurfaceConfiguration,
+        camera_position: Option<[f32; 3]>,
+    ) {
+        let frame_uniforms = **wm.frame_uniforms.load();
+
+        let uniforms = FrameUniforms {
+            elapsed_time: self.created.elapsed().as_secs_f32(),
+            viewport_size: [surface_config.width as f32, surface_config.height as f32],
+            camera_position: camera_position.unwrap_or(frame_uniforms.camera_position),
+            ..frame_uniforms
+        };
+
+        if let Some(resource) = self.resources.get(FRAME_UNIFORMS) {
+            if let ResourceInternal::Uniform(slot) = &*resource.data {
+                slot.write(wm, &uniforms.to_bytes());
+            }
+        }
+
+        let camera_position = [
+            uniforms.camera_position[0],
+            uniforms.camera_position[1],
+            uniforms.camera_position[2],
+            0.0,
+        ];
+
+        let values: [(&str, &[u8]); 3] = [
+            (
+                "wm_f32_time_of_day",
+                bytemuck::bytes_of(&uniforms.time_of_day),
+            ),
+            ("wm_f32_sun_angle", bytemuck::bytes_of(&uniforms.sun_angle)),
+            (
+                "wm_vec3_camera_position",
+                bytemuck::cast_slice(&camera_position),
+            ),
+        ];
+
+        for (name, data) in values {
+            if let Some(resource) = self.resources.get(name) {
+                if let ResourceInternal::F32(_, buffer) | ResourceInternal::Blob(buffer) =
+                    &*resource.data
+                {
+                    wm.wgpu_state.queue.write_buffer(&buffer.buffer, 0, data);
+                }
+            }
+        }
+    }
+
+    /// Writes the current value of every matrix resource to [WmRenderer::uniforms], after the frontend and the
+    /// `update` of each resource have set them for the frame
+    fn write_uniforms(&self, wm: &WmRenderer) {
+        for resource in self.resources.values() {
+            match &*resource.data {
+                ResourceInternal::Mat3(_, lock, slot) => {
+                    let matrix: [[f32; 3]; 3] = (*lock.read()).into();
+                    slot.write(wm, bytemuck::cast_slice(&matrix));
+                }
+                ResourceInternal::Mat4(_, lock, slot) => {
+                    let matrix: [[f32; 4]; 4] = (*lock.read()).into();
+                    slot.write(wm, bytemuck::cast_slice(&matrix));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The resources of the [FrameUniforms] shaderpacks bind on their own, which are written each frame by
+    /// [ShaderGraph::write_frame_uniforms]
+    fn insert_pack_uniforms(wm: &WmRenderer, resources: &mut HashMap<String, CustomResource>) {
+        let buffer = |size: usize| {
+            BindableBuffer::new(
+                wm,
+                &vec![0; size],
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                "ssbo",
+            )
+        };
+
+        for name in ["wm_f32_time_of_day", "wm_f32_sun_angle"] {
+            resources.insert(
+                name.into(),
+                CustomResource {
+                    update: None,
+                    data: Arc::new(ResourceInternal::F32(0.0, buffer(4))),
+                },
+            );
+        }
+
+        resources.insert(
+            "wm_vec3_camera_position".into(),
+            CustomResource {
+                update: None,
+                data: Arc::new(ResourceInternal::Blob(buffer(16))),
+            },
+        );
+    }
+
+    /// The resource of [FIRST_PERSON_PROJECTION], which is the identity until [ShaderGraph::write_first_person]
+    fn insert_first_person_projection(resources: &mut HashMap<String, CustomResource>) {
+        let identity: [[f32; 4]; 4] = Matrix4::<f32>::identity().into();
+
+        resources.insert(
+            FIRST_PERSON_PROJECTION.into(),
+            CustomResource {
+                update: None,
+                data: Arc::new(ResourceInternal::Mat4(
+                    Mat4ValueOrMult::Value { value: identity },
+                    Arc::new(RwLock::new(Matrix4::identity())),
+                    Arc::new(UniformSlot::default()),
+                )),
+            },
+        );
+    }
+
+    /// Writes [FIRST_PERSON_PROJECTION] for the size of the surface and streams the instances of
+    /// [WmRenderer::first_person], if any pipeline draws them
+    fn write_first_person(
+        &self,
+        wm: &WmRenderer,
+        surface_config: &SurfaceConfiguration,
+        configs: &[(&String, &PipelineConfig)],
+    ) {
+        if !configs
+            .iter()
+            .any(|(_, config)| config.geometry == "wm_geo_first_person")
+        {
+            return;
+        }
+
+        if let Some(resource) = self.resources.get(FIRST_PERSON_PROJECTION) {
+            if let ResourceInternal::Mat4(_, lock, _) = &*resource.data {
+                let aspect = surface_config.width as f32 / surface_config.height.max(1) as f32;
+
+                *lock.write() = wm.first_person.projection(aspect, **wm.reverse_z.load());
+            }
+        }
+
+        wm.first_person.prepare(wm);
+    }
+
+    /// Compiles the shader of every pipeline again and replaces all of the pipelines at once. If any shader is
+    /// missing or doesn't compile, the current pipelines are kept.
+    pub fn rebuild_pipelines(&self, wm: &WmRenderer) -> Result<(), ShaderError> {
+        self.replace_pipelines(wm, |_, _| true).map(|_| ())
+    }
+
+    /// Rebuilds the pipelines which use the shader at `path`, e.g. after a file watcher or a resource reload
+    /// noticed that it changed. Returns the number of pipelines which were rebuilt.
+    pub fn reload_shader(
+        &self,
+        wm: &WmRenderer,
+        path: &ResourcePath,
+    ) -> Result<usize, ShaderError> {
+        self.replace_pipelines(wm, |name, _| {
+            shader_files(&self.pack.shader_path(name)).contains(path)
+        })
+    }
+
+    /// Builds the pipelines which the filter picks, and the ones whose permutation changed with
+    /// [WmRenderer::shader_features], unless that permutation was built before. Every pipeline is built if the
+    /// targets changed.
+    fn replace_pipelines(
+        &self,
+        wm: &WmRenderer,
+        filter: impl Fn(&str, &PipelineConfig) -> bool,
+    ) -> Result<usize, ShaderError> {
+        let targets = PipelineTargets::new(wm, wm.wgpu_state.surface.read().1.format);
+        let features = wm.shader_features.load_full();
+
+        let mut permutations = self.permutations.lock();
+
+        //Every pipeline has to draw into the new format, with the new sample count and depth passes
+        if *self.built_for.lock() != Some(targets) {
+            permutations.clear();
+        }
+
+        //Pipelines whose shaders changed, and ones which a previous shaderpack added
+        permutations.retain(
+            |(name, _), _| match self.pack.pipelines.pipelines.get(name) {
+                Some(definition) => !filter(name, definition),
+                None => false,
+            },
+        );
+
+        let mut count = 0;
+
+        //Frames which already started keep drawing with the old pipelines
+        let mut pipelines = HashMap::new();
+        let mut depth_prepasses = HashMap::new();
+
+        for (name, definition) in &self.pack.pipelines.pipelines {
+            let key = (name.clone(), pipeline_features(wm, definition, &features));
+
+            let (pipeline, prepass) = match permutations.get(&key) {
+                Some(permutation) => permutation.clone(),
+                None => {
+                    let depth_prepass = targets.depth_prepass && has_depth_prepass(definition);
+
+                    let pipeline = |kind| {
+                        self.create_pipeline(wm, name, definition, &targets, kind, &key.1)
+                            .map(Arc::new)
+                    };
+
+                    let permutation = if depth_prepass {
+                        (
+                            pipeline(PassKind::AfterDepthPrepass)?,
+                            Some(pipeline(PassKind::DepthPrepass)?),
+                        )
+                    } else {
+                        (pipeline(PassKind::Full)?, None)
+                    };
+
+                    count += 1;
+                    permutations.insert(key, permutation.clone());
+                    permutation
+                }
+            };
+
+            if let Some(prepass) = prepass {
+                depth_prepasses.insert(name.clone(), prepass);
+            }
+
+            pipelines.insert(name.clone(), pipeline);
+        }
+
+        self.depth_prepasses.store(Arc::new(depth_prepasses));
+        self.pipelines.store(Arc::new(pipelines));
+        *self.built_for.lock() = Some(targets);
+        *self.built_features.lock() = features;
+
+        Ok(count)
+    }
+
+    /// Builds the pipeline, and returns the validation error of the device instead if wgpu rejects it. naga's
+    /// validation of the shader alone doesn't catch everything, e.g. vertex inputs which don't match the geometry.
+    fn create_pipeline(
+        &self,
+        wm: &WmRenderer,
+        name: &str,
+        definition: &PipelineConfig,
+        targets: &PipelineTargets,
+        kind: PassKind,
+        features: &ShaderFeatures,
+    ) -> Result<RenderPipeline, ShaderError> {
+        let device = &wm.wgpu_state.device;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let pipeline = self.build_pipeline(wm, name, definition, targets, kind, features);
+        let error = futures::executor::block_on(device.pop_error_scope());
+
+        match error {
+            Some(error) if pipeline.is_ok() => {
+                Err(ShaderError::Rejected(name.into(), error.to_string()))
+            }
+            _ => pipeline,
+        }
+    }
+
+    fn build_pipeline(
+        &self,
+        wm: &WmRenderer,
+        name: &str,
+        definition: &PipelineConfig,
+        targets: &PipelineTargets,
+        kind: PassKind,
+        features: &ShaderFeatures,
+    ) -> Result<RenderPipeline, ShaderError> {
+        //Without push constants, they're read from the bind group after the uniforms
+        let fallback_layout = self
+            .push_constant_fallback
+            .as_ref()
+            .filter(|_| !definition.push_constants.is_empty())
+            .map(|uniforms| uniforms.layout());
+
+        let shader = load_pipeline_shader(
+            &self.pack.shader_path(name),
+            &*self.resource_provider(wm),
+            &wm.wgpu_state.device,
+            fallback_layout.map(|_| definition.uniforms.len() as u32),
+            features,
+        )?;
+        let (vertex_module, vertex_entry) = shader.get_vert();
+        let (fragment_module, fragment_entry) = shader.get_frag();
+
         let pipelines = wm.pipelines.load();
         let layouts = pipelines.bind_group_layouts.read();
 
-        self.pack
-            .pipelines
-            .pipelines
+        let bind_group_layouts = definition
+            .uniforms
             .iter()
-            .for_each(|(name, definition)| match &self.pack.support[..] {
-                "wgsl" => {
-                    let shader = WgslShader::init(
-                        &ResourcePath(format!("wgpu_mc:shaders/{name}.wgsl")),
-                        &*wm.mc.resource_provider,
-                        &wm.wgpu_state.device,
-                        "frag".into(),
-                        "vert".into(),
-                    )
-                    .unwrap();
-
-                    let pipeline_layout =
-                        wm.wgpu_state
-                            .device
-                            .create_pipeline_layout(&PipelineLayoutDescriptor {
-                                label: None,
-                                bind_group_layouts: &definition
-                                    .uniforms
-                                    .iter()
-                                    .map(|(_index, uniform)| {
-                                        if let Some(resource) = resources.get(uniform) {
-                                            match &*resource.data {
-                                                ResourceInternal::Texture(_, depth) => layouts
-                                                    .get(if *depth {
-                                                        "texture_depth"
-                                                    } else {
-                                                        "texture"
-                                                    })
-                                                    .unwrap(),
-                                                ResourceInternal::Mat3(..)
-                                                | ResourceInternal::Mat4(..) => {
-                                                    layouts.get("matrix").unwrap()
-                                                }
-                                                ResourceInternal::Blob(..)
-                                                | ResourceInternal::F32(..)
-                                                | ResourceInternal::F64(..)
-                                                | ResourceInternal::U32(..)
-                                                | ResourceInternal::I32(..)
-                                                | ResourceInternal::I64(..) => {
-                                                    layouts.get("ssbo").unwrap()
-                                                }
-                                            }
-                                        } else {
-                                            layouts
-                                                .get(
-                                                    resource_types
-                                                        .as_ref()
-                                                        .unwrap()
-                                                        .get(uniform)
-                                                        .expect(uniform),
-                                                )
-                                                .unwrap()
-                                        }
-                                    })
-                                    .collect::<Vec<_>>(),
-                                push_constant_ranges: &definition
-                                    .push_constants
-                                    .iter()
-                                    .map(|(offset, resource)| match &resource[..] {
-                                        "wm_pc_chunk_position" => PushConstantRange {
-                                            stages: ShaderStages::VERTEX,
-                                            range: *offset as u32..*offset as u32 + 8,
-                                        },
-                                        "wm_pc_framebuffer_size" => PushConstantRange {
-                                            stages: ShaderStages::FRAGMENT,
-                                            range: *offset as u32..*offset as u32 + 8,
-                                        },
-                                        _ => unimplemented!("Unknown push constant resource value"),
-                                    })
-                                    .collect::<Vec<_>>(),
-                            });
+            .map(|(_index, uniform)| {
+                let unknown = || ShaderError::UnknownResource(name.into(), uniform.clone());
 
-                    let pipeline =
-                        wm.wgpu_state
-                            .device
-                            .create_render_pipeline(&RenderPipelineDescriptor {
-                                label: None,
-                                layout: Some(&pipeline_layout),
-                                vertex: VertexState {
-                                    module: &shader.shader,
-                                    entry_point: "vert",
-                                    buffers: &[match &definition.geometry[..] {
-                                        "wm_geo_terrain" => Vertex::desc(),
-                                        "wm_geo_quad" => QuadVertex::desc(),
-                                        _ => {
-                                            if let Some(additional_geometry) =
-                                                &mut additional_geometry
-                                            {
-                                                additional_geometry
-                                                    .remove(&definition.geometry)
-                                                    .unwrap()
-                                            } else {
-                                                unimplemented!("Unknown geometry");
-                                            }
-                                        }
-                                    }],
-                                },
-                                primitive: Default::default(),
-                                depth_stencil: definition.depth.as_ref().map(|_| {
-                                    DepthStencilState {
-                                        format: TextureFormat::Depth32Float,
-                                        depth_write_enabled: true,
-                                        depth_compare: wgpu::CompareFunction::Less,
-                                        stencil: Default::default(),
-                                        bias: Default::default(),
-                                    }
-                                }),
-                                multisample: Default::default(),
-                                fragment: Some(FragmentState {
-                                    module: &shader.shader,
-                                    entry_point: "frag",
-                                    targets: &definition
-                                        .output
-                                        .iter()
-                                        .map(|_| {
-                                            Some(ColorTargetState {
-                                            format: TextureFormat::Bgra8Unorm,
-                                            blend: Some(match &definition.blending[..] {
-                                                "alpha_blending" => {
-                                                    wgpu::BlendState::ALPHA_BLENDING
-                                                }
-                                                "premultiplied_alpha_blending" => {
-                                                    wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
-                                                }
-                                                _ => unimplemented!("Unknown blend state"),
-                                            }),
-                                            write_mask: Default::default(),
-                                        })
-                                        })
-                                        .collect::<Vec<_>>(),
-                                }),
-                                multiview: None,
-                            });
+                let layout = if let Some(resource) = self.resources.get(uniform) {
+                    match &*resource.data {
+                        ResourceInternal::Texture(_, depth) => {
+                            layouts.get(if *depth { "texture_depth" } else { "texture" })
+                        }
+                        ResourceInternal::Mat3(..)
+                        | ResourceInternal::Mat4(..)
+                        | ResourceInternal::Uniform(..) => Some(wm.uniforms.layout()),
+                        ResourceInternal::Blob(..)
+                        | ResourceInternal::F32(..)
+                        | ResourceInternal::F64(..)
+                        | ResourceInternal::U32(..)
+                        | ResourceInternal::I32(..)
+                        | ResourceInternal::I64(..) => layouts.get("ssbo"),
+                    }
+                } else {
+                    match &self.resource_types.get(uniform).ok_or_else(unknown)?[..] {
+                        UniformAllocator::LAYOUT => Some(wm.uniforms.layout()),
+                        layout => layouts.get(layout),
+                    }
+                };
 
-                    self.pipelines.insert(name.clone(), pipeline);
-                }
-                "glsl" => todo!(),
-                _ => unimplemented!("{}", self.pack.support),
+                layout.ok_or_else(unknown)
+            })
+            .chain(fallback_layout.map(Ok))
+            .collect::<Result<Vec<_>, ShaderError>>()?;
+
+        let push_constant_ranges = definition
+            .push_constants
+            .iter()
+            .map(|(offset, resource)| {
+                let (stages, size) = push_constant_layout(resource).ok_or_else(|| {
+                    ShaderError::UnknownPushConstant(name.into(), resource.clone())
+                })?;
+
+                Ok(PushConstantRange {
+                    stages,
+                    range: *offset as u32..*offset as u32 + size,
+                })
+            })
+            .collect::<Result<Vec<_>, ShaderError>>()?;
+
+        let pipeline_layout =
+            wm.wgpu_state
+                .device
+                .create_pipeline_layout(&PipelineLayoutDescriptor {
+                    label: None,
+                    bind_group_layouts: &bind_group_layouts,
+                    //Moved to the bind group after the uniforms without push constants
+                    push_constant_ranges: if fallback_layout.is_some() {
+                        &[]
+                    } else {
+                        &push_constant_ranges
+                    },
+                });
+
+        let buffers = match &definition.geometry[..] {
+            geometry if TERRAIN_GEOMETRY.contains(&geometry) => {
+                vec![wm.config.chunk_vertex_format.desc(), ChunkInstance::desc()]
+            }
+            "wm_geo_quad" | "wm_geo_transparent" | "wm_geo_fluid" | "wm_geo_skybox" => {
+                vec![QuadVertex::desc()]
+            }
+            "wm_geo_block_outline" | "wm_geo_chunk_borders" => vec![DebugLineVertex::desc()],
+            "wm_geo_block_breaking" => vec![BreakingVertex::desc()],
+            "wm_geo_entity_shadows" => vec![ShadowVertex::desc()],
+            "wm_geo_name_tags" => vec![NameTagVertex::desc()],
+            "wm_geo_beams" => vec![BeamVertex::desc()],
+            "wm_geo_sky" => vec![SkyVertex::desc()],
+            "wm_geo_clouds" => vec![CloudVertex::desc()],
+            "wm_geo_particles" => vec![ParticleInstance::desc()],
+            "wm_geo_gpu_particles" => vec![GpuParticle::desc()],
+            "wm_geo_entities" | "wm_geo_first_person" => vec![EntityVertex::desc()],
+            //Drawn by the GeometryCallback of the frontend
+            geometry => match self.additional_geometry.get(geometry) {
+                Some(layout) if self.geometry.contains_key(geometry) => vec![layout.clone()],
+                _ => return Err(ShaderError::UnknownGeometry(name.into(), geometry.into())),
+            },
+        };
+
+        let targets: Vec<Option<ColorTargetState>> = definition
+            .output
+            .iter()
+            .map(|_| {
+                Some(ColorTargetState {
+                    format: targets.surface_format,
+                    blend: Some(definition.blending.into()),
+                    write_mask: definition.color_writes(),
+                })
+            })
+            .collect();
+
+        let pipeline = wm
+            .wgpu_state
+            .device
+            .create_render_pipeline(&RenderPipelineDescriptor {
+                label: None,
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: vertex_module,
+                    entry_point: vertex_entry,
+                    buffers: &buffers,
+                },
+                primitive: PrimitiveState {
+                    topology: definition.topology.into(),
+                    cull_mode: definition.cull_mode.map(Into::into),
+                    polygon_mode: if shows_polygon_mode(definition) {
+                        targets.polygon_mode
+                    } else {
+                        PolygonMode::Fill
+                    },
+                    ..Default::default()
+                },
+                depth_stencil: definition.depth.as_ref().map(|_| DepthStencilState {
+                    format: wm.wgpu_state.depth_format,
+                    depth_write_enabled: match kind {
+                        PassKind::Full => writes_depth(definition),
+                        PassKind::DepthPrepass => true,
+                        PassKind::AfterDepthPrepass => false,
+                    },
+                    depth_compare: match kind {
+                        PassKind::AfterDepthPrepass => wgpu::CompareFunction::Equal,
+                        _ => depth_compare(definition.depth_compare.into(), targets.reverse_z),
+                    },
+                    stencil: pipeline_stencil(definition, wm.wgpu_state.depth_format),
+                    bias: depth_bias(pipeline_depth_bias(definition), targets.reverse_z),
+                }),
+                multisample: MultisampleState {
+                    count: if multisampled(definition) {
+                        targets.samples
+                    } else {
+                        1
+                    },
+                    ..Default::default()
+                },
+                fragment: (kind != PassKind::DepthPrepass).then_some(FragmentState {
+                    module: fragment_module,
+                    entry_point: fragment_entry,
+                    targets: &targets,
+                }),
+                multiview: None,
             });
 
-        self.resources.extend(resources.into_iter());
+        Ok(pipeline)
+    }
+
+    /// Sets the push constants of the pipeline for the next draws. Without [wgpu::Features::PUSH_CONSTANTS], the
+    /// values are written to a slot of [WmRenderer::uniforms] instead, which is bound after the uniforms of the
+    /// pipeline. If this fails, the draws have to be skipped, as they wouldn't pass validation.
+    pub fn set_push_constants<'pass>(
+        &self,
+        wm: &WmRenderer,
+        config: &PipelineConfig,
+        arena: &WmArena<'pass>,
+        render_pass: &mut RenderPass<'pass>,
+        values: &PushConstantValues,
+    ) -> Result<(), PushConstantError> {
+        if config.push_constants.is_empty() {
+            return Ok(());
+        }
+
+        let uniforms = match &self.push_constant_fallback {
+            Some(uniforms) => uniforms,
+            None => {
+                for (offset, resource) in &config.push_constants {
+                    let (stages, _) = push_constant_layout(resource)
+                        .ok_or_else(|| PushConstantError::Unknown(resource.clone()))?;
+
+                    render_pass.set_push_constants(
+                        stages,
+                        *offset as u32,
+                        &push_constant_data(resource, values)?,
+                    );
+                }
+
+                return Ok(());
+            }
+        };
+
+        let mut data = Vec::new();
+
+        for (offset, resource) in &config.push_constants {
+            let bytes = push_constant_data(resource, values)?;
+            let end = *offset as usize + bytes.len();
+
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+
+            data[*offset as usize..end].copy_from_slice(&bytes);
+        }
+
+        let size = data.len();
+        let allocation = uniforms
+            .allocate(&wm.wgpu_state.device, &wm.wgpu_state.queue, &data)
+            .ok_or(PushConstantError::TooLarge(size))?;
+        let allocation = arena.alloc(allocation);
+
+        render_pass.set_bind_group(
+            config.uniforms.len() as u32,
+            &allocation.block.bind_group,
+            &[allocation.offset],
+        );
+
+        Ok(())
+    }
+
+    /// The vertex buffer of the block outline, or [None] if there is no outline
+    fn outline_buffer(
+        &self,
+        wm: &WmRenderer,
+        chunk_offset: ChunkPos,
+    ) -> Option<Arc<OutlineBuffer>> {
+        let outline = wm.block_outline.load_full();
+        let mut outline_buffer = self.outline_buffer.lock();
+
+        let (pos, shape) = match &*outline {
+            Some(outline) => outline,
+            None => {
+                *outline_buffer = None;
+                return None;
+            }
+        };
+
+        if let Some(buffer) = &*outline_buffer {
+            if Arc::ptr_eq(&buffer.outline, &outline) && buffer.chunk_offset == chunk_offset {
+                return Some(buffer.clone());
+            }
+        }
+
+        let vertices = outline_vertices(*pos, shape, chunk_offset);
+        let buffer = Arc::new(OutlineBuffer {
+            buffer: Arc::new(
+                wm.wgpu_state
+                    .device
+                    .create_buffer_init(&BufferInitDescriptor {
+                        label: Some("block_outline"),
+                        contents: bytemuck::cast_slice(&vertices),
+                        usage: BufferUsages::VERTEX,
+                    }),
+            ),
+            vertices: vertices.len() as u32,
+            outline,
+            chunk_offset,
+        });
+
+        *outline_buffer = Some(buffer.clone());
+
+        Some(buffer)
+    }
+
+    /// The multisampled targets for the sample count, or [None] if it's 1. They're created again when the size or
+    /// format of the surface changes.
+    fn msaa_targets(
+        &self,
+        wm: &WmRenderer,
+        surface_config: &SurfaceConfiguration,
+        samples: u32,
+    ) -> Option<Arc<MsaaTargets>> {
+        let mut msaa_targets = self.msaa_targets.lock();
+
+        if samples <= 1 {
+            *msaa_targets = None;
+            return None;
+        }
+
+        let size = [surface_config.width, surface_config.height];
+
+        if let Some(targets) = &*msaa_targets {
+            if targets.samples == samples
+                && targets.format == surface_config.format
+                && targets.size == size
+            {
+                return Some(targets.clone());
+            }
+        }
+
+        let create_view = |label: &str, format| {
+            wm.wgpu_state
+                .device
+                .create_texture(&TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: size[0],
+                        height: size[1],
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: samples,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage: TextureUsages::RENDER_ATTACHMENT,
+                    view_formats: &[],
+                })
+                .create_view(&Default::default())
+        };
+
+        let depth = self
+            .pack
+            .pipelines
+            .pipelines
+            .values()
+            .filter(|definition| multisampled(definition))
+            .filter_map(|definition| definition.depth.clone())
+            .map(|name| {
+                let view = create_view(&name, wm.wgpu_state.depth_format);
+
+                (name, view)
+            })
+            .collect();
+
+        let targets = Arc::new(MsaaTargets {
+            samples,
+            format: surface_config.format,
+            size,
+            color: create_view("wm_framebuffer_texture", surface_config.format),
+            depth,
+        });
+
+        *msaa_targets = Some(targets.clone());
+
+        Some(targets)
+    }
+
+    /// Draws the pipelines of the [WmRenderer::pipeline_registry] whose phase goes right before the pipeline at
+    /// `index` of the pack, and returns the command buffers they have to be submitted with
+    fn render_registered(
+        &self,
+        wm: &WmRenderer,
+        phase_positions: [usize; 4],
+        index: usize,
+        encoder: &mut wgpu::CommandEncoder,
+        output_texture: &wgpu::TextureView,
+    ) -> Vec<wgpu::CommandBuffer> {
+        let mut command_buffers = Vec::new();
+
+        for (phase, _) in RenderPhase::ALL
+            .into_iter()
+            .zip(phase_positions)
+            .filter(|(_, position)| *position == index)
+        {
+            for pipeline in wm.pipeline_registry.in_phase(phase) {
+                command_buffers.extend(pipeline.render(
+                    wm,
+                    self,
+                    encoder,
+                    output_texture,
+                    &self.resources,
+                ));
+            }
+        }
+
+        command_buffers
     }
 
     /// Matches on the definition, inserting the resource depending on which variant it is.
@@ -410,13 +1232,6 @@ impl ShaderGraph {
                 );
             }
             ShorthandResourceConfig::Mat3(mat3) => {
-                let ssbo = BindableBuffer::new(
-                    wm,
-                    bytemuck::cast_slice(&mat3[..]),
-                    BufferUsages::UNIFORM,
-                    "matrix",
-                );
-
                 let matrix3: Matrix3<f32> = (*mat3).into();
 
                 resources.insert(
@@ -426,19 +1241,12 @@ impl ShaderGraph {
                         data: Arc::new(ResourceInternal::Mat3(
                             Mat3ValueOrMult::Value { value: *mat3 },
                             Arc::new(RwLock::new(matrix3)),
-                            Arc::new(ssbo),
-                        )),
-                    },
-                );
-            }
-            ShorthandResourceConfig::Mat4(mat4) => {
-                let ssbo = BindableBuffer::new(
-                    wm,
-                    bytemuck::cast_slice(&mat4[..]),
-                    BufferUsages::UNIFORM,
-                    "matrix",
+                            Arc::new(UniformSlot::default()),
+                        )),
+                    },
                 );
-
+            }
+            ShorthandResourceConfig::Mat4(mat4) => {
                 let matrix4: Matrix4<f32> = (*mat4).into();
 
                 resources.insert(
@@ -448,7 +1256,7 @@ impl ShaderGraph {
                         data: Arc::new(ResourceInternal::Mat4(
                             Mat4ValueOrMult::Value { value: *mat4 },
                             Arc::new(RwLock::new(matrix4)),
-                            Arc::new(ssbo),
+                            Arc::new(UniformSlot::default()),
                         )),
                     },
                 );
@@ -459,9 +1267,10 @@ impl ShaderGraph {
                     if !src.is_empty() {
                         todo!()
                     } else {
+                        //Pipelines draw into these in the format of the surface
                         let handle = wm.create_texture_handle(
                             resource_id.clone(),
-                            TextureFormat::Bgra8Unorm,
+                            wm.wgpu_state.surface.read().1.format,
                             &wm.wgpu_state.surface.read().1,
                         );
                         resources.insert(
@@ -479,7 +1288,7 @@ impl ShaderGraph {
                 TypeResourceConfig::TextureDepth { .. } => {
                     let handle = wm.create_texture_handle(
                         resource_id.clone(),
-                        TextureFormat::Depth32Float,
+                        wm.wgpu_state.depth_format,
                         &wm.wgpu_state.surface.read().1,
                     );
                     resources.insert(
@@ -563,13 +1372,6 @@ impl ShaderGraph {
                         Mat3ValueOrMult::Mult { .. } => [[0.0; 3]; 3],
                     };
 
-                    let ssbo = BindableBuffer::new(
-                        wm,
-                        bytemuck::cast_slice(&value),
-                        BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                        "matrix",
-                    );
-
                     resources.insert(
                         resource_id,
                         CustomResource {
@@ -580,7 +1382,7 @@ impl ShaderGraph {
                             data: Arc::new(ResourceInternal::Mat3(
                                 mat3.clone(),
                                 Arc::new(RwLock::new(value.into())),
-                                Arc::new(ssbo),
+                                Arc::new(UniformSlot::default()),
                             )),
                         },
                     );
@@ -591,13 +1393,6 @@ impl ShaderGraph {
                         Mat4ValueOrMult::Mult { .. } => [[0.0; 4]; 4],
                     };
 
-                    let ssbo = BindableBuffer::new(
-                        wm,
-                        bytemuck::cast_slice(&value),
-                        BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-                        "matrix",
-                    );
-
                     resources.insert(
                         resource_id,
                         CustomResource {
@@ -608,7 +1403,7 @@ impl ShaderGraph {
                             data: Arc::new(ResourceInternal::Mat4(
                                 mat4.clone(),
                                 Arc::new(RwLock::new(value.into())),
-                                Arc::new(ssbo),
+                                Arc::new(UniformSlot::default()),
                             )),
                         },
                     );
@@ -630,6 +1425,8 @@ impl ShaderGraph {
             .wgpu_state
             .device
             .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        //Returned by the registered pipelines, submitted ahead of the encoder
+        let mut command_buffers = Vec::new();
 
         self.resources
             .iter()
@@ -640,10 +1437,45 @@ impl ShaderGraph {
 
         let resource_borrow = self.resources.iter().collect();
 
+        let built_for = PipelineTargets::new(wm, surface_config.format);
+        let samples = built_for.samples;
+        let reverse_z = built_for.reverse_z;
+
+        //The resources were reloaded, so the shaders might have changed
+        let shader_generation = wm.mc.shader_generation.load(Ordering::Acquire);
+
+        if self
+            .shader_generation
+            .swap(shader_generation, Ordering::AcqRel)
+            != shader_generation
+        {
+            if let Err(error) = self.rebuild_pipelines(wm) {
+                log::error!("Couldn't compile the reloaded shaders: {error}");
+            }
+        }
+
+        if *self.built_for.lock() != Some(built_for)
+            || **self.built_features.lock() != **wm.shader_features.load()
+        {
+            //Only builds the pipelines which need to be, see [ShaderGraph::replace_pipelines]
+            if let Err(error) = self.replace_pipelines(wm, |_, _| false) {
+                log::error!("Couldn't rebuild the pipelines for the new settings: {error}");
+            }
+        }
+
+        let depth_prepasses = &**arena.alloc(self.depth_prepasses.load_full());
+        let pipelines = &**arena.alloc(self.pipelines.load_full());
+
+        let msaa = self
+            .msaa_targets(wm, surface_config, samples)
+            .map(|targets| &**arena.alloc(targets));
+        //Multisampled pipelines draw into the MSAA targets, which are resolved to the output at the end of the frame
+        let frame_texture = msaa.map_or(output_texture, |msaa| &msaa.color);
+
         let texture_handles = wm.texture_handles.read();
 
-        //The first render pass that uses the framebuffer's depth buffer should clear it
-        let mut should_clear_depth = true;
+        //The first render pass that uses a depth texture should clear it
+        let mut cleared_depth = HashSet::new();
 
         let _chunk_offset = *wm.mc.chunks.chunk_offset.lock();
 
@@ -661,13 +1493,124 @@ impl ShaderGraph {
             .unwrap();
 
         let frustum = Frustum::from_modelview_projection((projection_matrix * view_matrix).into());
+        let view_projection: [[f32; 4]; 4] = (projection_matrix * view_matrix).into();
+
+        let camera_position = view_matrix
+            .invert()
+            .map(|inverse_view| [inverse_view.w.x, inverse_view.w.y, inverse_view.w.z]);
+
+        //Entities uploaded for the next frame are culled against this one
+        wm.entities
+            .set_camera(camera_position.map(|position| EntityCamera {
+                view_projection,
+                position,
+            }));
+
+        let camera_chunk = camera_position.map(|camera_position| {
+            [
+                (camera_position[0] / 16.0).floor() as i32,
+                (camera_position[2] / 16.0).floor() as i32,
+            ]
+        });
+
+        //None if cave culling isn't possible this frame, in which case only frustum culling is used
+        let reachable_sections = camera_position.and_then(|camera_position| {
+            visible_sections(
+                &wm.mc.chunks.loaded_chunks.read(),
+                camera_position,
+                &frustum,
+            )
+        });
+
+        //A chunk is drawn by every terrain pipeline and layer, but only counted once
+        let mut visible_chunks = HashSet::new();
+
+        //Otherwise every batch of terrain draws is split up into separate draw calls
+        let multi_draw_indirect = wm.wgpu_state.device.features().contains(
+            wgpu::Features::MULTI_DRAW_INDIRECT | wgpu::Features::INDIRECT_FIRST_INSTANCE,
+        );
+
+        let pipeline_configs: Vec<(&String, &PipelineConfig)> =
+            self.pack.pipelines.pipelines.iter().collect();
+        let ordered_configs: Vec<(&String, &PipelineConfig)> = self
+            .order
+            .iter()
+            .map(|&index| pipeline_configs[index])
+            .collect();
+
+        let phase_positions = phase_positions(
+            ordered_configs
+                .iter()
+                .map(|(_, config)| &config.geometry[..]),
+        );
+
+        let push_constant_values = PushConstantValues {
+            framebuffer_size: [surface_config.width, surface_config.height],
+            line_depth_offset: line_depth_offset(
+                &projection_matrix,
+                DepthBiasPresets::LINE_VIEW_OFFSET,
+            ),
+            ..Default::default()
+        };
+
+        self.write_frame_uniforms(wm, surface_config);
+
+        if let Some(gpu_particles) = &**wm.mc.particles.gpu.load() {
+            gpu_particles.simulate(wm);
+        }
+
+        self.write_first_person(wm, surface_config, &ordered_configs);
+        self.write_uniforms(wm);
+
+        //Pipelines with a depth pre-pass are drawn twice, into the depth texture first
+        let passes: Vec<(usize, &String, &PipelineConfig, bool)> = ordered_configs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, &(name, config))| {
+                let depth_prepass = depth_prepasses
+                    .contains_key(name)
+                    .then_some((index, name, config, true));
+
+                depth_prepass
+                    .into_iter()
+                    .chain([(index, name, config, false)])
+            })
+            .collect();
+
+        let mut profiler_frame = wm
+            .profiler
+            .as_ref()
+            .and_then(|profiler| profiler.begin_frame());
+
+        for &(index, name, config, depth_prepass) in &passes {
+            if depth_prepass || !depth_prepasses.contains_key(name) {
+                command_buffers.extend(self.render_registered(
+                    wm,
+                    phase_positions,
+                    index,
+                    &mut encoder,
+                    frame_texture,
+                ));
+            }
+
+            if let Some(profiler_frame) = &mut profiler_frame {
+                profiler_frame.timestamp(&mut encoder, name, depth_prepass);
+            }
+
+            let pass_msaa = msaa.filter(|_| multisampled(config));
+
+            let pipeline = if depth_prepass {
+                &depth_prepasses[name]
+            } else {
+                &pipelines[name]
+            };
 
-        for (name, config) in &self.pack.pipelines.pipelines {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: None,
                 color_attachments: &config
                     .output
                     .iter()
+                    .filter(|_| !depth_prepass)
                     .map(|texture_name| {
                         let resource_definition = self.pack.resources.resources.get(texture_name);
 
@@ -686,7 +1629,7 @@ impl ShaderGraph {
 
                         Some(RenderPassColorAttachment {
                             view: match &texture_name[..] {
-                                "wm_framebuffer_texture" => output_texture,
+                                "wm_framebuffer_texture" => frame_texture,
                                 name => {
                                     &arena
                                         .alloc(
@@ -709,94 +1652,469 @@ impl ShaderGraph {
                     })
                     .collect::<Vec<_>>(),
                 depth_stencil_attachment: config.depth.as_ref().map(|depth_texture| {
-                    let will_clear_depth = should_clear_depth;
-                    should_clear_depth = false;
+                    let will_clear_depth =
+                        cleared_depth.insert((depth_texture, pass_msaa.is_some()));
 
                     RenderPassDepthStencilAttachment {
-                        view: &arena
-                            .alloc(
-                                texture_handles
-                                    .get(depth_texture)
-                                    .unwrap()
-                                    .bindable_texture
-                                    .load(),
-                            )
-                            .tsv
-                            .view,
+                        view: match pass_msaa {
+                            Some(msaa) => &msaa.depth[depth_texture],
+                            None => {
+                                &arena
+                                    .alloc(
+                                        texture_handles
+                                            .get(depth_texture)
+                                            .unwrap()
+                                            .bindable_texture
+                                            .load(),
+                                    )
+                                    .tsv
+                                    .view
+                            }
+                        },
                         depth_ops: Some(Operations {
-                            // load: if will_clear_depth { LoadOp::Clear(1.0) } else { LoadOp::Load },
-                            load: LoadOp::Clear(1.0),
-                            store: will_clear_depth,
+                            load: if will_clear_depth {
+                                LoadOp::Clear(clear_depth(reverse_z))
+                            } else {
+                                LoadOp::Load
+                            },
+                            store: true,
                         }),
-                        stencil_ops: None,
+                        //Cleared along with the depth, the block outline writes to it
+                        stencil_ops: TextureSamplerView::has_stencil(wm.wgpu_state.depth_format)
+                            .then_some(Operations {
+                                load: if will_clear_depth {
+                                    LoadOp::Clear(0)
+                                } else {
+                                    LoadOp::Load
+                                },
+                                store: true,
+                            }),
                     }
                 }),
             });
 
             let chunk_offset = [0, 0];
 
-            render_pass.set_pipeline(self.pipelines.get(name).unwrap());
+            render_pass.set_pipeline(pipeline);
+
+            //Custom geometry binds its own uniforms, see GeometryCallback
+            if BUILTIN_GEOMETRY.contains(&&config.geometry[..]) {
+                bind_uniforms(config, &resource_borrow, &arena, &mut render_pass);
+
+                if let Err(error) = self.set_push_constants(
+                    wm,
+                    config,
+                    &arena,
+                    &mut render_pass,
+                    &push_constant_values,
+                ) {
+                    log::error!("Skipped the draws of the pipeline {name}: {error:?}");
+                    continue;
+                }
+            }
 
             match &config.geometry[..] {
-                "wm_geo_terrain" => {
+                "wm_geo_terrain" | "wm_geo_terrain_cutout" | "wm_geo_terrain_translucent" => {
+                    let render_type = terrain_render_type(&config.geometry).unwrap();
                     let layers = wm.pipelines.load().chunk_layers.load();
                     let chunks = wm.mc.chunks.loaded_chunks.read();
+                    let lod_distance = **wm.mc.lod_distance.load();
+                    let stride = wm.config.chunk_vertex_format.stride();
+
+                    let mut instances = Vec::new();
+                    let mut batches = TerrainBatches::new();
+                    let mut translucent_sections = Vec::new();
+                    let planes = frustum_planes(view_projection);
 
-                    for layer in &**layers {
+                    for (layer_index, layer) in layers.iter().enumerate() {
                         for (_pos, chunk_swap) in &*chunks {
                             let chunk = arena.alloc(chunk_swap.load());
 
                             let min = Vec3::new(
-                                (chunk.pos[0] * 16) as f32,
+                                chunk.pos[0] as f32 * 16.0,
                                 0.0,
-                                (chunk.pos[1] * 16) as f32,
+                                chunk.pos[1] as f32 * 16.0,
                             );
                             let max = min + Vec3::new(16.0, 384.0, 16.0);
 
                             let aabb = AABB::<f32>::new(min, max);
+                            let chunk_bounds =
+                                SectionBounds::new([min.x, min.y, min.z], [max.x, max.y, max.z]);
 
                             if aabb.test_against_frustum(&frustum, 0) == u8::MAX {
                                 continue;
                             }
 
-                            let (chunk_vbo, verts) =
-                                match arena.alloc(chunk.baked_layers.read()).get(layer.name()) {
+                            visible_chunks.insert(chunk.pos);
+
+                            let first_instance = instances.len() as u32;
+                            instances.push(ChunkInstance {
+                                position: [
+                                    chunk.pos[0] - chunk_offset[0],
+                                    chunk.pos[1] - chunk_offset[1],
+                                ],
+                            });
+
+                            let lod_level = camera_chunk.zip(lod_distance).and_then(
+                                |(camera_chunk, lod_distance)| {
+                                    let distance = (chunk.pos[0] - camera_chunk[0])
+                                        .unsigned_abs()
+                                        .max((chunk.pos[1] - camera_chunk[1]).unsigned_abs());
+
+                                    LodLevel::for_distance(distance, lod_distance)
+                                },
+                            );
+
+                            //Far away chunks are drawn once with their shell, unless it hasn't been baked yet
+                            if let Some(lod_layer) = lod_level
+                                .and_then(|level| arena.alloc(chunk.lod_layers.read()).get(&level))
+                            {
+                                if layer_index == 0 && render_type == RenderType::Solid {
+                                    for buffers in lod_layer.sections.iter().flatten() {
+                                        push_terrain_draws(
+                                            &mut batches,
+                                            first_instance,
+                                            buffers,
+                                            stride,
+                                            chunk_bounds,
+                                            false,
+                                        );
+                                    }
+                                }
+
+                                continue;
+                            }
+
+                            let baked_layer = match arena
+                                .alloc(chunk.baked_layers.read())
+                                .get(&(layer.name().to_string(), render_type))
+                            {
+                                None => continue,
+                                Some(baked_layer) => baked_layer,
+                            };
+
+                            for (section, buffers) in baked_layer.sections.iter().enumerate() {
+                                let buffers = match buffers {
                                     None => continue,
-                                    Some(tuple) => tuple,
+                                    Some(buffers) => buffers,
                                 };
 
-                            bind_uniforms(config, &resource_borrow, &arena, &mut render_pass);
-                            set_push_constants(
-                                config,
-                                &mut render_pass,
-                                Some(chunk),
-                                surface_config,
-                                chunk_offset,
+                                let reachable =
+                                    reachable_sections.as_ref().map_or(true, |reachable| {
+                                        reachable.contains(&(chunk.pos, section))
+                                    });
+                                let bounds = section_bounds(min, section);
+
+                                //Frustum culling is left to the GPU culler if there is one
+                                if !reachable
+                                    || (self.gpu_culler.is_none() && !bounds.is_visible(&planes))
+                                {
+                                    continue;
+                                }
+
+                                //Translucent sections are drawn one by one, once all of them are sorted
+                                if render_type == RenderType::Translucent {
+                                    if let (Some(sortable_quads), Some(camera_position)) =
+                                        (&buffers.sortable_quads, camera_position)
+                                    {
+                                        let sorted = sortable_quads.sort_if_moved([
+                                            camera_position[0] - min.x,
+                                            camera_position[1],
+                                            camera_position[2] - min.z,
+                                        ]);
+
+                                        //Written before the encoder is submitted at the end of the frame
+                                        if let Some(sorted) = sorted {
+                                            wm.wgpu_state.queue.write_buffer(
+                                                &buffers.index_buffer.buffer,
+                                                buffers.index_buffer.range.start,
+                                                bytemuck::cast_slice(&sorted),
+                                            );
+                                        }
+                                    }
+
+                                    translucent_sections.push((&**buffers, first_instance, bounds));
+
+                                    continue;
+                                }
+
+                                push_terrain_draws(
+                                    &mut batches,
+                                    first_instance,
+                                    buffers,
+                                    stride,
+                                    bounds,
+                                    false,
+                                );
+                            }
+                        }
+                    }
+
+                    //Back to front, the quads within each section are already sorted
+                    if let Some(camera_position) = camera_position {
+                        translucent_sections.sort_by(|(_, _, a), (_, _, b)| {
+                            section_distance(b, camera_position)
+                                .total_cmp(&section_distance(a, camera_position))
+                        });
+                    }
+
+                    for (buffers, first_instance, bounds) in translucent_sections {
+                        push_terrain_draws(
+                            &mut batches,
+                            first_instance,
+                            buffers,
+                            stride,
+                            bounds,
+                            true,
+                        );
+                    }
+
+                    if batches.is_empty() {
+                        continue;
+                    }
+
+                    let instance_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: None,
+                            contents: bytemuck::cast_slice(&instances),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+                    render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+                    if multi_draw_indirect {
+                        let args: Vec<DrawIndexedIndirectArgs> = batches
+                            .values()
+                            .flat_map(|(_, _, args, _)| args.iter().copied())
+                            .collect();
+
+                        let indirect_buffer = match &self.gpu_culler {
+                            Some(gpu_culler) => {
+                                let bounds: Vec<SectionBounds> = batches
+                                    .values()
+                                    .flat_map(|(_, _, _, bounds)| bounds.iter().copied())
+                                    .collect();
+
+                                arena.alloc(gpu_culler.cull(
+                                    &wm.wgpu_state,
+                                    bytemuck::cast_slice(&args),
+                                    &bounds,
+                                    view_projection,
+                                ))
+                            }
+                            None => arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                                &BufferInitDescriptor {
+                                    label: None,
+                                    contents: bytemuck::cast_slice(&args),
+                                    usage: BufferUsages::INDIRECT,
+                                },
+                            )),
+                        };
+
+                        let mut offset = 0;
+
+                        for &(vertex_buffer, index_buffer, ref args, _) in batches.values() {
+                            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                            render_pass
+                                .set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
+                            render_pass.multi_draw_indexed_indirect(
+                                indirect_buffer,
+                                offset,
+                                args.len() as u32,
                             );
 
-                            render_pass.set_vertex_buffer(0, chunk_vbo.slice(..));
-                            render_pass.draw(0..verts.len() as u32, 0..1);
+                            offset += std::mem::size_of_val(&args[..]) as u64;
+                        }
+                    } else {
+                        for &(vertex_buffer, index_buffer, ref args, _) in batches.values() {
+                            render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                            render_pass
+                                .set_index_buffer(index_buffer.slice(..), IndexFormat::Uint32);
+
+                            for args in args {
+                                render_pass.draw_indexed(
+                                    args.first_index..args.first_index + args.index_count,
+                                    args.base_vertex,
+                                    args.first_instance..args.first_instance + 1,
+                                );
+                            }
                         }
                     }
                 }
-                "wm_geo_entities" | "wm_geo_transparent" | "wm_geo_fluid" | "wm_geo_skybox"
-                | "wm_geo_quad" => {
-                    bind_uniforms(config, &resource_borrow, &arena, &mut render_pass);
-                    set_push_constants(
-                        config,
-                        &mut render_pass,
-                        None,
-                        surface_config,
-                        chunk_offset,
-                    );
+                "wm_geo_block_outline" => {
+                    let outline = match self.outline_buffer(wm, chunk_offset) {
+                        None => continue,
+                        Some(outline) => arena.alloc(outline),
+                    };
+
+                    render_pass.set_stencil_reference(OUTLINE_STENCIL_REFERENCE);
+                    render_pass.set_vertex_buffer(0, outline.buffer.slice(..));
+                    render_pass.draw(0..outline.vertices, 0..1);
+                }
+                "wm_geo_chunk_borders" => {
+                    let camera_chunk = match camera_chunk {
+                        Some(camera_chunk) if **wm.chunk_borders.load() => camera_chunk,
+                        _ => continue,
+                    };
+
+                    let vertices = chunk_border_vertices(camera_chunk, chunk_offset);
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("chunk_borders"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_block_breaking" => {
+                    let vertices = breaking_vertices(&wm.mc, chunk_offset);
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("block_breaking"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_entity_shadows" => {
+                    let vertices = shadow_vertices(&wm.mc, chunk_offset);
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("entity_shadows"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_name_tags" => {
+                    let vertices = name_tag_vertices(&wm.mc, view_matrix.into(), chunk_offset);
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
 
-                    render_pass.set_pipeline(self.pipelines.get(name).unwrap());
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("name_tags"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_sky" => {
+                    let vertices = sky_vertices(&wm.mc, camera_position.unwrap_or([0.0; 3]));
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("sky"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_clouds" => {
+                    let vertices =
+                        cloud_vertices(&wm.mc, camera_position.unwrap_or([0.0; 3]), chunk_offset);
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("clouds"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_beams" => {
+                    let vertices = beam_vertices(&wm.mc, chunk_offset);
+
+                    if vertices.is_empty() {
+                        continue;
+                    }
+
+                    let vertex_buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(
+                        &BufferInitDescriptor {
+                            label: Some("beams"),
+                            contents: bytemuck::cast_slice(&vertices),
+                            usage: BufferUsages::VERTEX,
+                        },
+                    ));
+
+                    render_pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+                    render_pass.draw(0..vertices.len() as u32, 0..1);
+                }
+                "wm_geo_particles" => {
+                    let (instance_buffer, count) = match wm.mc.particles.upload(wm, chunk_offset) {
+                        Some(uploaded) => uploaded,
+                        None => continue,
+                    };
+                    let instance_buffer = arena.alloc(instance_buffer);
+
+                    render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+                    //The vertex shader makes the corners of each quad
+                    render_pass.draw(0..6, 0..count);
+                }
+                "wm_geo_gpu_particles" => {
+                    let instance_buffer = match &**wm.mc.particles.gpu.load() {
+                        Some(gpu_particles) => arena.alloc(gpu_particles.buffer().clone()),
+                        None => continue,
+                    };
+
+                    render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+                    //Every slot is drawn, the vertex shader collapses the ones which are free
+                    render_pass.draw(0..6, 0..GPU_PARTICLE_CAPACITY);
+                }
+                "wm_geo_entities" => {
+                    wm.entities.render(config, &arena, &mut render_pass);
+                }
+                "wm_geo_first_person" => {
+                    wm.first_person
+                        .entities
+                        .render(config, &arena, &mut render_pass);
+                }
+                "wm_geo_transparent" | "wm_geo_fluid" | "wm_geo_skybox" | "wm_geo_quad" => {
                     render_pass.set_vertex_buffer(0, self.quad.as_ref().unwrap().slice(..));
                     render_pass.draw(0..6, 0..1);
                 }
+                //Pipelines with a geometry nobody draws aren't built, see ShaderGraph::create_pipeline
                 _ => {
                     if let Some(geo) = self.geometry.get(&config.geometry) {
-                        render_pass.set_pipeline(self.pipelines.get(name).unwrap());
                         geo.render(
                             wm,
                             &mut render_pass,
@@ -807,17 +2125,355 @@ impl ShaderGraph {
                             surface_config,
                             chunk_offset,
                         );
-                    } else {
-                        unimplemented!("Unknown geometry {0}", &config.geometry);
                     }
                 }
             };
         }
 
-        wm.wgpu_state.queue.submit([encoder.finish()]);
+        command_buffers.extend(self.render_registered(
+            wm,
+            phase_positions,
+            ordered_configs.len(),
+            &mut encoder,
+            frame_texture,
+        ));
+
+        if let Some(profiler_frame) = profiler_frame {
+            profiler_frame.finish(&mut encoder);
+        }
+
+        if msaa.is_some() {
+            //A pass without draws, which only resolves the MSAA color target
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("msaa_resolve"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: frame_texture,
+                    resolve_target: Some(output_texture),
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+        }
+
+        wm.wgpu_state
+            .queue
+            .submit(command_buffers.into_iter().chain([encoder.finish()]));
+
+        if let Some(profiler) = &wm.profiler {
+            profiler.after_submit();
+        }
+
+        self.visible_chunks
+            .store(visible_chunks.len(), Ordering::Relaxed);
+    }
+}
+
+/// The geometry drawing chunk meshes, in [crate::WmConfig::chunk_vertex_format]
+const TERRAIN_GEOMETRY: [&str; 3] = [
+    "wm_geo_terrain",
+    "wm_geo_terrain_cutout",
+    "wm_geo_terrain_translucent",
+];
+
+/// The features of the permutation of the pipeline's shader with the enabled features. Terrain pipelines also get
+/// the [RenderType::feature] of their geometry, and [ChunkVertexFormat::PACKED_FEATURE] when chunks are packed,
+/// since their vertex inputs have to match.
+fn pipeline_features(
+    wm: &WmRenderer,
+    definition: &PipelineConfig,
+    enabled: &ShaderFeatures,
+) -> ShaderFeatures {
+    let mut features = definition.shader_features(enabled);
+
+    if let Some(render_type) = terrain_render_type(&definition.geometry) {
+        features.insert(render_type.feature().into());
+    }
+
+    if wm.config.chunk_vertex_format == ChunkVertexFormat::Packed
+        && TERRAIN_GEOMETRY.contains(&&definition.geometry[..])
+    {
+        features.insert(ChunkVertexFormat::PACKED_FEATURE.into());
+    }
+
+    features
+}
+
+/// The geometries the graph draws itself, any other is drawn by a [GeometryCallback]
+const BUILTIN_GEOMETRY: [&str; 19] = [
+    "wm_geo_terrain",
+    "wm_geo_terrain_cutout",
+    "wm_geo_terrain_translucent",
+    "wm_geo_block_outline",
+    "wm_geo_chunk_borders",
+    "wm_geo_block_breaking",
+    "wm_geo_entity_shadows",
+    "wm_geo_name_tags",
+    "wm_geo_sky",
+    "wm_geo_clouds",
+    "wm_geo_beams",
+    "wm_geo_particles",
+    "wm_geo_gpu_particles",
+    "wm_geo_entities",
+    "wm_geo_first_person",
+    "wm_geo_transparent",
+    "wm_geo_fluid",
+    "wm_geo_skybox",
+    "wm_geo_quad",
+];
+
+/// The arguments of [wgpu::RenderPass::draw_indexed_indirect], laid out the way the GPU expects them
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawIndexedIndirectArgs {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+/// The draws of a terrain pass, batched by the vertex and index buffer pages of the [ChunkBufferAllocator] they're
+/// in, so that each batch can be drawn with a single [wgpu::RenderPass::multi_draw_indexed_indirect]. The last part
+/// of the key is the run of consecutive draws in the same pages, which is only used when the draws have to keep
+/// their order, and is 0 otherwise.
+///
+/// [ChunkBufferAllocator]: crate::render::chunk_allocator::ChunkBufferAllocator
+type TerrainBatches<'a> = IndexMap<
+    (*const wgpu::Buffer, *const wgpu::Buffer, usize),
+    (
+        &'a wgpu::Buffer,
+        &'a wgpu::Buffer,
+        Vec<DrawIndexedIndirectArgs>,
+        Vec<SectionBounds>,
+    ),
+>;
+
+/// Adds a draw of the section's buffers, with the instance at `first_instance` for the position of its chunk. The
+/// bounds of the section are only used when culling on the GPU. If `ordered`, the draw is only batched with the ones
+/// right before it, so that the draws are drawn in the order they were pushed.
+fn push_terrain_draws<'a>(
+    batches: &mut TerrainBatches<'a>,
+    first_instance: u32,
+    buffers: &'a SectionBuffers,
+    stride: u64,
+    bounds: SectionBounds,
+    ordered: bool,
+) {
+    let vertex_buffer: &wgpu::Buffer = &buffers.vertex_buffer.buffer;
+    let index_buffer: &wgpu::Buffer = &buffers.index_buffer.buffer;
+    let pages = (vertex_buffer as *const _, index_buffer as *const _);
+
+    let run = match batches.last() {
+        _ if !ordered => 0,
+        Some((&(last_vertices, last_indices, run), _))
+            if (last_vertices, last_indices) == pages =>
+        {
+            run
+        }
+        _ => batches.len(),
+    };
+
+    let (_, _, args, batch_bounds) = batches
+        .entry((pages.0, pages.1, run))
+        .or_insert_with(|| (vertex_buffer, index_buffer, Vec::new(), Vec::new()));
+
+    args.push(DrawIndexedIndirectArgs {
+        index_count: buffers.index_count,
+        instance_count: 1,
+        first_index: (buffers.index_buffer.range.start / 4) as u32,
+        //Vertex buffers are allocated at whole vertices of their page
+        base_vertex: (buffers.vertex_buffer.range.start / stride) as i32,
+        first_instance,
+    });
+    batch_bounds.push(bounds);
+}
+
+/// The squared distance from the camera to the centre of the bounds, which translucent sections are sorted by
+fn section_distance(bounds: &SectionBounds, camera: [f32; 3]) -> f32 {
+    (0..3)
+        .map(|axis| ((bounds.min[axis] + bounds.max[axis]) / 2.0 - camera[axis]).powi(2))
+        .sum()
+}
+
+/// Which of its passes a pipeline of the pack is built for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PassKind {
+    /// Depth and color, when there's no depth pre-pass
+    Full,
+    /// Only depth, without a fragment stage
+    DepthPrepass,
+    /// Only the color of the fragments left by the depth pre-pass, with [wgpu::CompareFunction::Equal]. That only
+    /// works if the position the vertex shader outputs is `@invariant`, as the pipelines of both passes are
+    /// compiled separately.
+    AfterDepthPrepass,
+}
+
+/// Whether the pipeline gets a depth pre-pass while [WmRenderer::depth_prepass] is on. That's only the case for
+/// solid terrain, as cutout terrain discards fragments in the fragment shader and translucent terrain doesn't write
+/// depth.
+fn has_depth_prepass(definition: &PipelineConfig) -> bool {
+    terrain_render_type(&definition.geometry) == Some(RenderType::Solid)
+        && definition.depth.is_some()
+        && writes_depth(definition)
+        && matches!(
+            definition.depth_compare,
+            DepthCompare::Less | DepthCompare::LessEqual
+        )
+}
+
+/// Removes the normal and specular maps of the atlases from the end of the uniforms of the pipeline, which shaders
+/// only use with the `PBR` feature. Maps followed by other uniforms are kept, as the bind groups after them
+/// wouldn't line up otherwise.
+fn drop_trailing_pbr_maps(definition: &mut PipelineConfig) {
+    let is_pbr_map = |uniform: &String| {
+        AtlasKind::ALL.into_iter().any(|kind| {
+            PbrMap::ALL
+                .into_iter()
+                .any(|map| map.resource(kind) == *uniform)
+        })
+    };
+
+    while definition
+        .uniforms
+        .back()
+        .map_or(false, |(_, uniform)| is_pbr_map(uniform))
+    {
+        definition.uniforms.pop_back();
+    }
+}
+
+/// What the block outline writes to the stencil, see [pipeline_stencil]
+const OUTLINE_STENCIL_REFERENCE: u32 = 1;
+
+/// Pipelines of the block outline only draw the pixels they haven't drawn yet this frame, so that the pixels where
+/// its lines meet aren't blended twice. Without a stencil aspect, see [crate::WmConfig::stencil], the edges the boxes
+/// of the shape share are only drawn once instead, see [crate::render::pipeline::block_outline].
+fn pipeline_stencil(definition: &PipelineConfig, depth_format: TextureFormat) -> StencilState {
+    if definition.geometry != "wm_geo_block_outline"
+        || !TextureSamplerView::has_stencil(depth_format)
+    {
+        return StencilState::default();
+    }
+
+    let face = StencilFaceState {
+        compare: wgpu::CompareFunction::NotEqual,
+        fail_op: StencilOperation::Keep,
+        depth_fail_op: StencilOperation::Keep,
+        pass_op: StencilOperation::Replace,
+    };
+
+    StencilState {
+        front: face,
+        back: face,
+        read_mask: u32::MAX,
+        write_mask: u32::MAX,
+    }
+}
+
+/// The [PipelineConfig::depth_bias], or the builtin one of overlays. Points and lines are never biased, as WebGPU
+/// only allows a bias for triangles, they're moved by `wm_pc_line_depth_offset` in their vertex shader instead.
+fn pipeline_depth_bias(definition: &PipelineConfig) -> DepthBiasState {
+    if !matches!(
+        definition.topology,
+        Topology::TriangleList | Topology::TriangleStrip
+    ) {
+        return DepthBiasState::default();
+    }
+
+    match (definition.depth_bias, &definition.geometry[..]) {
+        (Some(bias), _) => bias.into(),
+        (None, "wm_geo_block_breaking" | "wm_geo_entity_shadows") => DepthBiasPresets::DECALS,
+        (None, _) => DepthBiasState::default(),
+    }
+}
+
+/// Whether the pipeline is drawn with [WmRenderer::debug_polygon_mode], which only the terrain and entities are, so
+/// that the sky and post-processing stay readable
+fn shows_polygon_mode(definition: &PipelineConfig) -> bool {
+    terrain_render_type(&definition.geometry).is_some() || definition.geometry == "wm_geo_entities"
+}
+
+/// Whether the pipeline draws into the framebuffer, and is multisampled with [WmRenderer::msaa_samples]. Its depth
+/// texture is then swapped for one of the [MsaaTargets] too.
+fn multisampled(definition: &PipelineConfig) -> bool {
+    definition
+        .output
+        .iter()
+        .any(|output| output == "wm_framebuffer_texture")
+}
+
+//Translucent geometry is blended over whatever is behind it
+fn writes_depth(definition: &PipelineConfig) -> bool {
+    definition
+        .depth_write
+        .unwrap_or(terrain_render_type(&definition.geometry) != Some(RenderType::Translucent))
+}
+
+/// The pipeline as a pass of the graph, writing to its outputs and reading its uniforms. The depth texture is
+/// written to if [writes_depth], and read otherwise.
+fn pass_node(name: &str, definition: &PipelineConfig) -> PassNode {
+    let mut reads: Vec<String> = definition.uniforms.values().cloned().collect();
+    let mut writes = definition.output.clone();
+
+    if let Some(depth) = &definition.depth {
+        if writes_depth(definition) {
+            writes.push(depth.clone());
+        } else {
+            reads.push(depth.clone());
+        }
+    }
+
+    PassNode {
+        name: name.into(),
+        reads,
+        writes,
+    }
+}
+
+/// The order of the pipelines of the pack, or the order they're declared in if they depend on each other in a
+/// circle
+fn pipeline_order(pack: &ShaderPackConfig) -> Vec<usize> {
+    let passes: Vec<PassNode> = pack
+        .pipelines
+        .pipelines
+        .iter()
+        .map(|(name, definition)| pass_node(name, definition))
+        .collect();
+
+    resolve_order(&passes).unwrap_or_else(|error| {
+        log::error!("Pipelines of the shaderpack can't be ordered: {error:?}");
+
+        (0..passes.len()).collect()
+    })
+}
+
+/// Which blocks a terrain geometry draws, or [None] if it isn't terrain
+pub(crate) fn terrain_render_type(geometry: &str) -> Option<RenderType> {
+    match geometry {
+        "wm_geo_terrain" => Some(RenderType::Solid),
+        "wm_geo_terrain_cutout" => Some(RenderType::Cutout),
+        "wm_geo_terrain_translucent" => Some(RenderType::Translucent),
+        _ => None,
     }
 }
 
+/// The bounds of the section of a chunk whose lowest corner is `chunk_min`
+fn section_bounds(chunk_min: Vec3<f32>, section: usize) -> SectionBounds {
+    let min_y = (section * CHUNK_SECTION_HEIGHT) as f32;
+
+    SectionBounds::new(
+        [chunk_min.x, min_y, chunk_min.z],
+        [
+            chunk_min.x + 16.0,
+            min_y + CHUNK_SECTION_HEIGHT as f32,
+            chunk_min.z + 16.0,
+        ],
+    )
+}
+
 pub fn bind_uniforms<'resource: 'pass, 'pass>(
     config: &PipelineConfig,
     resources: &'resource HashMap<&String, &'resource CustomResource>,
@@ -825,17 +2481,36 @@ pub fn bind_uniforms<'resource: 'pass, 'pass>(
     render_pass: &mut RenderPass<'pass>,
 ) {
     for (index, resource_name) in &config.uniforms {
-        let bind_group = match &*resources.get(resource_name).unwrap().data {
+        //Uniforms which aren't resources of the graph are bound by the geometry, like the ones of the entity pipeline
+        let resource = match resources.get(resource_name) {
+            Some(resource) => resource,
+            None => continue,
+        };
+
+        let bind_group = match &*resource.data {
             ResourceInternal::Texture(handle, _) => match handle {
                 TextureResource::Handle(handle) => {
                     &arena.alloc(handle.bindable_texture.load()).bind_group
                 }
                 TextureResource::Bindable(bindable) => &arena.alloc(bindable.load()).bind_group,
             },
-            ResourceInternal::Blob(BindableBuffer { bind_group, .. }) => bind_group,
-            ResourceInternal::Mat3(_, _, bindable) | ResourceInternal::Mat4(_, _, bindable) => {
-                &bindable.bind_group
+            ResourceInternal::Uniform(slot)
+            | ResourceInternal::Mat3(_, _, slot)
+            | ResourceInternal::Mat4(_, _, slot) => {
+                //Only missing if the uniform didn't fit its slot, which has been logged
+                if let Some(allocation) = slot.get() {
+                    let allocation = arena.alloc(allocation);
+
+                    render_pass.set_bind_group(
+                        *index as u32,
+                        &allocation.block.bind_group,
+                        &[allocation.offset],
+                    );
+                }
+
+                continue;
             }
+            ResourceInternal::Blob(BindableBuffer { bind_group, .. }) => bind_group,
             ResourceInternal::F32(_, BindableBuffer { bind_group, .. })
             | ResourceInternal::F64(_, BindableBuffer { bind_group, .. })
             | ResourceInternal::U32(_, BindableBuffer { bind_group, .. })
@@ -847,35 +2522,68 @@ pub fn bind_uniforms<'resource: 'pass, 'pass>(
     }
 }
 
-pub fn set_push_constants(
-    pipeline: &PipelineConfig,
-    render_pass: &mut RenderPass,
-    chunk: Option<&Chunk>,
-    surface_config: &SurfaceConfiguration,
-    chunk_offset: ChunkPos,
-) {
-    pipeline
-        .push_constants
-        .iter()
-        .for_each(|(offset, resource)| match &resource[..] {
-            "wm_pc_framebuffer_size" => {
-                render_pass.set_push_constants(
-                    ShaderStages::FRAGMENT,
-                    *offset as u32,
-                    bytemuck::cast_slice(&[
-                        surface_config.width as f32,
-                        surface_config.height as f32,
-                    ]),
-                );
-            }
-            "wm_pc_chunk_position" => render_pass.set_push_constants(
-                ShaderStages::VERTEX,
-                *offset as u32,
-                bytemuck::cast_slice(&[
-                    chunk.unwrap().pos[0] - chunk_offset[0],
-                    chunk.unwrap().pos[1] - chunk_offset[1],
-                ]),
-            ),
-            _ => unimplemented!("Unknown push constant resource value"),
-        });
+/// The values of the `wm_pc_*` push constants of a draw, see [ShaderGraph::set_push_constants]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PushConstantValues {
+    /// `wm_pc_framebuffer_size`, for the fragment stage
+    pub framebuffer_size: [u32; 2],
+    /// `wm_pc_chunk_position`, the position of the chunk relative to the chunk offset, for the vertex stage
+    pub chunk_position: Option<ChunkPos>,
+    /// `wm_pc_model_matrix`, for the vertex stage
+    pub model_matrix: Option<[[f32; 4]; 4]>,
+    /// `wm_pc_line_depth_offset`, what lines add to their clip space depth, see [line_depth_offset], for the vertex
+    /// stage
+    pub line_depth_offset: f32,
+}
+
+/// Why the push constants of a draw couldn't be set, see [ShaderGraph::set_push_constants]
+#[derive(Debug)]
+pub enum PushConstantError {
+    /// The push constants of the pipeline, of the size, don't fit in a slot of [WmRenderer::uniforms]
+    TooLarge(usize),
+    /// The push constant isn't one of the `wm_pc_*` ones, pipelines with one aren't built by the graph
+    Unknown(String),
+    /// The draws of the geometry don't have a value for the push constant, e.g. `wm_pc_chunk_position` outside of
+    /// the terrain
+    Unavailable(String),
+}
+
+/// The stages and size of a `wm_pc_*` push constant, [None] if there's no such push constant
+fn push_constant_layout(resource: &str) -> Option<(ShaderStages, u32)> {
+    match resource {
+        "wm_pc_framebuffer_size" => Some((ShaderStages::FRAGMENT, 8)),
+        "wm_pc_chunk_position" => Some((ShaderStages::VERTEX, 8)),
+        "wm_pc_model_matrix" => Some((ShaderStages::VERTEX, 64)),
+        "wm_pc_line_depth_offset" => Some((ShaderStages::VERTEX, 4)),
+        _ => None,
+    }
+}
+
+fn push_constant_data(
+    resource: &str,
+    values: &PushConstantValues,
+) -> Result<Vec<u8>, PushConstantError> {
+    Ok(match resource {
+        "wm_pc_framebuffer_size" => bytemuck::cast_slice(&[
+            values.framebuffer_size[0] as f32,
+            values.framebuffer_size[1] as f32,
+        ])
+        .to_vec(),
+        "wm_pc_chunk_position" => {
+            let chunk_position = values
+                .chunk_position
+                .ok_or_else(|| PushConstantError::Unavailable(resource.into()))?;
+
+            bytemuck::cast_slice(&chunk_position).to_vec()
+        }
+        "wm_pc_model_matrix" => {
+            let model_matrix = values
+                .model_matrix
+                .ok_or_else(|| PushConstantError::Unavailable(resource.into()))?;
+
+            bytemuck::cast_slice(&model_matrix).to_vec()
+        }
+        "wm_pc_line_depth_offset" => bytemuck::bytes_of(&values.line_depth_offset).to_vec(),
+        _ => return Err(PushConstantError::Unknown(resource.into())),
+    })
 }
@@ -0,0 +1,166 @@
+//! # Pass ordering
+//!
+//! Every pass names the attachments and buffers it reads and writes, and [resolve_order] sorts the passes so that
+//! each one runs after the passes writing what it reads. Passes which don't depend on each other keep the order
+//! they were declared in, so a shaderpack only has to care about the order where it matters, e.g. a shadow map
+//! can be declared after the terrain which samples it.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// The resources a pass reads and writes, by name. A resource which is read and written, like a depth buffer or a
+/// texture which is blended onto, only has to be in `writes`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PassNode {
+    pub name: String,
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PassGraphError {
+    /// The passes which depend on each other in a circle, and every pass depending on them
+    Cycle(Vec<String>),
+}
+
+/// The indices of the passes in the order they have to run in:
+///
+/// - writers of the same resource run in the order they were declared
+/// - a reader runs after the last writer declared before it, and before the next one
+/// - a reader declared before every writer of the resource runs after the last one
+pub fn resolve_order(passes: &[PassNode]) -> Result<Vec<usize>, PassGraphError> {
+    let mut dependencies: Vec<HashSet<usize>> = vec![HashSet::new(); passes.len()];
+
+    let mut writers: HashMap<&str, Vec<usize>> = HashMap::new();
+
+    for (index, pass) in passes.iter().enumerate() {
+        for resource in &pass.writes {
+            let resource_writers = writers.entry(resource).or_default();
+
+            if let Some(&previous) = resource_writers.last() {
+                dependencies[index].insert(previous);
+            }
+
+            resource_writers.push(index);
+        }
+    }
+
+    for (index, pass) in passes.iter().enumerate() {
+        for resource in &pass.reads {
+            if pass.writes.contains(resource) {
+                continue;
+            }
+
+            let resource_writers = match writers.get(&resource[..]) {
+                None => continue,
+                Some(resource_writers) => resource_writers,
+            };
+
+            let next = resource_writers.partition_point(|&writer| writer < index);
+
+            match next.checked_sub(1) {
+                Some(previous) => {
+                    dependencies[index].insert(resource_writers[previous]);
+
+                    if let Some(&next) = resource_writers.get(next) {
+                        dependencies[next].insert(index);
+                    }
+                }
+                None => {
+                    dependencies[index].insert(*resource_writers.last().unwrap());
+                }
+            }
+        }
+    }
+
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); passes.len()];
+    let mut remaining: Vec<usize> = dependencies.iter().map(HashSet::len).collect();
+
+    for (index, pass_dependencies) in dependencies.iter().enumerate() {
+        for &dependency in pass_dependencies {
+            dependents[dependency].push(index);
+        }
+    }
+
+    //Always runs the first declared pass which is ready
+    let mut ready: BinaryHeap<Reverse<usize>> = (0..passes.len())
+        .filter(|&index| remaining[index] == 0)
+        .map(Reverse)
+        .collect();
+
+    let mut order = Vec::with_capacity(passes.len());
+
+    while let Some(Reverse(index)) = ready.pop() {
+        order.push(index);
+
+        for &dependent in &dependents[index] {
+            remaining[dependent] -= 1;
+
+            if remaining[dependent] == 0 {
+                ready.push(Reverse(dependent));
+            }
+        }
+    }
+
+    if order.len() == passes.len() {
+        Ok(order)
+    } else {
+        Err(PassGraphError::Cycle(
+            (0..passes.len())
+                .filter(|&index| remaining[index] != 0)
+                .map(|index| passes[index].name.clone())
+                .collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_order, PassGraphError, PassNode};
+
+    fn pass(name: &str, reads: &[&str], writes: &[&str]) -> PassNode {
+        PassNode {
+            name: name.into(),
+            reads: reads.iter().map(|&read| read.into()).collect(),
+            writes: writes.iter().map(|&write| write.into()).collect(),
+        }
+    }
+
+    #[test]
+    fn readers_run_after_writers() {
+        let passes = [
+            pass("terrain", &["shadow_map"], &["framebuffer", "depth"]),
+            pass("entities", &[], &["framebuffer", "depth"]),
+            pass("shadows", &[], &["shadow_map"]),
+            pass("post", &["framebuffer"], &["output"]),
+        ];
+
+        //The shadow map is drawn before the terrain samples it, everything else keeps its order
+        assert_eq!(resolve_order(&passes), Ok(vec![2, 0, 1, 3]));
+    }
+
+    #[test]
+    fn readers_run_before_the_next_writer() {
+        let passes = [
+            pass("first", &[], &["texture"]),
+            pass("copy", &["texture"], &["copy"]),
+            pass("second", &[], &["texture"]),
+        ];
+
+        assert_eq!(resolve_order(&passes), Ok(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn cycles_are_reported() {
+        let passes = [
+            pass("a", &["b"], &["a"]),
+            pass("b", &["a"], &["b"]),
+            pass("c", &[], &["c"]),
+        ];
+
+        assert_eq!(
+            resolve_order(&passes),
+            Err(PassGraphError::Cycle(vec!["a".into(), "b".into()]))
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! # Lightmap
+//!
+//! Chunk vertices store the block light and sky light of the block their face is lit by as texture coordinates
+//! into a 16x16 lightmap texture, with block light along the x axis and sky light along the y axis. The terrain
+//! shader multiplies the color with the lightmap, which is bound as `wm_texture_lightmap`.
+//!
+//! Minecraft recomputes its lightmap every tick from the time of day, the gamma setting and potion effects.
+//! Integrators can either upload Minecraft's with [crate::WmRenderer::upload_lightmap], or generate the same one
+//! from [LightmapSettings] with [crate::WmRenderer::update_lightmap].
+
+/// Light levels go from 0 to 15
+pub const LIGHTMAP_SIZE: u32 = 16;
+
+/// The coordinates of the center of the lightmap texel for the light levels
+#[must_use]
+pub fn lightmap_coords(block_light: u8, sky_light: u8) -> [f32; 2] {
+    [
+        (block_light.min(15) as f32 + 0.5) / LIGHTMAP_SIZE as f32,
+        (sky_light.min(15) as f32 + 0.5) / LIGHTMAP_SIZE as f32,
+    ]
+}
+
+/// Brightness curve of a light level, the same as Minecraft's with no ambient light
+fn brightness(level: u32) -> f32 {
+    let level = level as f32 / 15.0;
+
+    level / (4.0 - 3.0 * level)
+}
+
+/// An RGBA lightmap for daytime without any gamma correction, used until one is uploaded
+#[must_use]
+pub fn default_lightmap() -> Vec<u8> {
+    (0..LIGHTMAP_SIZE)
+        .flat_map(|sky_light| {
+            (0..LIGHTMAP_SIZE).flat_map(move |block_light| {
+                //Block light is slightly warmer than sky light
+                let block = brightness(block_light);
+                let sky = brightness(sky_light);
+
+                let color = [
+                    block.max(sky),
+                    (block * 0.9).max(sky),
+                    (block * 0.75).max(sky),
+                ]
+                .map(|channel| (channel.max(0.05) * 255.0).round() as u8);
+
+                [color[0], color[1], color[2], 255]
+            })
+        })
+        .collect()
+}
+
+/// What Minecraft's `LightTexture` computes the lightmap from
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LightmapSettings {
+    /// How bright the sky is, from 0 at night to 1 during the day, Minecraft's `getSkyDarken`
+    pub sky_brightness: f32,
+    /// The random walk Minecraft adds to the red of block light every tick, 0 for steady light
+    pub block_flicker: f32,
+    /// The brightness option, from 0 (moody) to 1 (bright)
+    pub gamma: f32,
+    /// The strength of the night vision effect, from 0 to 1
+    pub night_vision: f32,
+    /// The sky is fully lit while lightning flashes
+    pub lightning_flash: bool,
+    /// The End's lightmap, which is bright regardless of the sky
+    pub force_bright: bool,
+}
+
+impl Default for LightmapSettings {
+    fn default() -> Self {
+        Self {
+            sky_brightness: 1.0,
+            block_flicker: 0.0,
+            gamma: 0.5,
+            night_vision: 0.0,
+            lightning_flash: false,
+            force_bright: false,
+        }
+    }
+}
+
+fn lerp(from: [f32; 3], to: [f32; 3], amount: f32) -> [f32; 3] {
+    [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * amount)
+}
+
+/// An RGBA lightmap the same as Minecraft's `LightTexture` makes with the settings
+#[must_use]
+pub fn generate_lightmap(settings: &LightmapSettings) -> Vec<u8> {
+    let sky_factor = if settings.lightning_flash {
+        1.0
+    } else {
+        settings.sky_brightness * 0.95 + 0.05
+    };
+
+    //The sky is bluer the darker it is
+    let sky_color = lerp(
+        [settings.sky_brightness, settings.sky_brightness, 1.0],
+        [1.0; 3],
+        0.35,
+    );
+
+    let block_factor = settings.block_flicker + 1.5;
+
+    (0..LIGHTMAP_SIZE)
+        .flat_map(|sky_light| {
+            (0..LIGHTMAP_SIZE).flat_map(move |block_light| {
+                let sky = brightness(sky_light) * sky_factor;
+
+                let red = brightness(block_light) * block_factor;
+                let green = red * ((red * 0.6 + 0.4) * 0.6 + 0.4);
+                let blue = red * (red * red * 0.6 + 0.4);
+
+                let mut color = [red, green, blue];
+
+                if settings.force_bright {
+                    color =
+                        lerp(color, [0.99, 1.12, 1.0], 0.25).map(|channel| channel.clamp(0.0, 1.0));
+                } else {
+                    color = [0, 1, 2].map(|channel| color[channel] + sky_color[channel] * sky);
+                    color = lerp(color, [0.75; 3], 0.04);
+                }
+
+                if settings.night_vision > 0.0 {
+                    let max = color[0].max(color[1]).max(color[2]);
+
+                    if max < 1.0 {
+                        color = lerp(
+                            color,
+                            color.map(|channel| channel / max),
+                            settings.night_vision,
+                        );
+                    }
+                }
+
+                color = color.map(|channel| channel.clamp(0.0, 1.0));
+
+                //Brighter settings lift the darker levels the most
+                let lifted = color.map(|channel| 1.0 - (1.0 - channel).powi(4));
+                color = lerp(color, lifted, settings.gamma.max(0.0));
+                color = lerp(color, [0.75; 3], 0.04).map(|channel| channel.clamp(0.0, 1.0));
+
+                let [r, g, b] = color.map(|channel| (channel * 255.0) as u8);
+
+                [r, g, b, 255]
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        default_lightmap, generate_lightmap, lightmap_coords, LightmapSettings, LIGHTMAP_SIZE,
+    };
+
+    #[test]
+    fn lightmap_coords_sample_texel_centers() {
+        assert_eq!(lightmap_coords(0, 15), [0.5 / 16.0, 15.5 / 16.0]);
+        assert_eq!(lightmap_coords(20, 0), [15.5 / 16.0, 0.5 / 16.0]);
+    }
+
+    #[test]
+    fn default_lightmap_gets_brighter_with_more_light() {
+        let lightmap = default_lightmap();
+        assert_eq!(lightmap.len(), (LIGHTMAP_SIZE * LIGHTMAP_SIZE * 4) as usize);
+
+        let red = |block_light: u32, sky_light: u32| {
+            lightmap[((sky_light * LIGHTMAP_SIZE + block_light) * 4) as usize]
+        };
+
+        assert!(red(0, 0) < red(7, 0));
+        assert!(red(7, 0) < red(15, 0));
+        assert!(red(0, 0) < red(0, 15));
+        assert_eq!(red(15, 15), 255);
+    }
+
+    #[test]
+    fn generated_lightmap_follows_the_settings() {
+        let texel = |lightmap: &[u8], block_light: u32, sky_light: u32| {
+            let index = ((sky_light * LIGHTMAP_SIZE + block_light) * 4) as usize;
+            [lightmap[index], lightmap[index + 1], lightmap[index + 2]]
+        };
+
+        let day = generate_lightmap(&LightmapSettings::default());
+        assert_eq!(day.len(), (LIGHTMAP_SIZE * LIGHTMAP_SIZE * 4) as usize);
+        assert!(texel(&day, 0, 0)[0] < texel(&day, 0, 15)[0]);
+        assert!(texel(&day, 0, 0)[0] < texel(&day, 15, 0)[0]);
+
+        //Block light is warmer than sky light
+        let [red, _, blue] = texel(&day, 8, 0);
+        assert!(red > blue);
+
+        let night = LightmapSettings {
+            sky_brightness: 0.0,
+            ..LightmapSettings::default()
+        };
+        assert!(texel(&generate_lightmap(&night), 0, 15)[0] < texel(&day, 0, 15)[0]);
+
+        let moody = LightmapSettings {
+            gamma: 0.0,
+            ..night
+        };
+        assert!(
+            texel(&generate_lightmap(&moody), 0, 8)[0] < texel(&generate_lightmap(&night), 0, 8)[0]
+        );
+
+        //Night vision brings the brightest channel up to nearly full
+        let night_vision = LightmapSettings {
+            night_vision: 1.0,
+            ..night
+        };
+        let brightest = |color: [u8; 3]| color.into_iter().max().unwrap();
+        assert!(brightest(texel(&generate_lightmap(&night_vision), 0, 8)) > 240);
+        assert!(brightest(texel(&generate_lightmap(&night), 0, 8)) < 64);
+    }
+}
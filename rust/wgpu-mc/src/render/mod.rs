@@ -1,7 +1,21 @@
 pub mod atlas;
+pub mod chunk_allocator;
+pub mod clouds;
+pub mod colormap;
+#[cfg(feature = "egui")]
+pub mod debug_ui;
 pub mod entity;
+pub mod font;
+pub mod gpu_culler;
+pub mod gpu_mesher;
 pub mod graph;
+pub mod lightmap;
+pub mod particle;
 pub mod pipeline;
+pub mod profiler;
+pub mod registry;
+pub mod reverse_z;
 pub mod shader;
 pub mod shaderpack;
 pub mod sky;
+pub mod uniforms;
@@ -0,0 +1,362 @@
+//! # GPU particles
+//!
+//! Effects with thousands of particles, like rain splashes, explosions or the smoke of many campfires, are simulated
+//! by a compute shader (`wgpu_mc:shaders/particle_simulation.wgsl`) rather than on the CPU when
+//! [crate::WmConfig::gpu_particles] is on. Every particle is a [GpuParticle] in a storage buffer with a slot for each
+//! of [GPU_PARTICLE_CAPACITY] particles, and every frame [GpuParticles::simulate] runs two passes over it:
+//! 1. `emit` writes the particles spawned since the last frame into free slots
+//! 2. `simulate` moves every live particle along by the ticks which passed, and frees the slots of the ones which
+//!    died
+//!
+//! The free slots are a stack in another storage buffer, popped and pushed with atomics. Particles spawned while
+//! every slot is taken are dropped, like vanilla's limit. The storage buffer is the instance buffer of the pipelines
+//! with the `wm_geo_gpu_particles` geometry too, which draw the live particles like the ones of the CPU.
+//!
+//! Requires compute shaders.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytemuck::{Pod, Zeroable};
+use parking_lot::Mutex;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::{
+    BindGroupDescriptor, BindGroupEntry, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    BindingType, BufferBindingType, BufferUsages, CommandEncoderDescriptor, ShaderStages,
+};
+
+use crate::mc::resource::ResourcePath;
+use crate::render::particle::Particle;
+use crate::render::pipeline::compute::{dispatch, workgroup_count};
+use crate::WmRenderer;
+
+/// How many particles can be alive on the GPU at once
+pub const GPU_PARTICLE_CAPACITY: u32 = 1 << 18;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The most ticks a frame simulates, so particles don't jump after a hitch
+const MAX_FRAME_TICKS: f32 = 10.0;
+
+/// A particle in the storage buffer, the layout matches `Particle` in the shader
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Pod, Zeroable)]
+pub struct GpuParticle {
+    pub position: [f32; 3],
+    /// In ticks
+    pub age: f32,
+    /// In blocks per tick
+    pub velocity: [f32; 3],
+    pub lifetime: f32,
+    pub color: [f32; 4],
+    /// The min and max UVs of the sprite in the particle atlas
+    pub uv: [f32; 4],
+    pub size: f32,
+    pub gravity: f32,
+    pub drag: f32,
+    /// Set by the shader, 0 for free slots
+    pub alive: u32,
+}
+
+impl GpuParticle {
+    #[must_use]
+    pub fn new(particle: &Particle, uv: [f32; 4]) -> Self {
+        Self {
+            position: [
+                particle.position.0,
+                particle.position.1,
+                particle.position.2,
+            ],
+            age: particle.age as f32,
+            velocity: particle.velocity,
+            lifetime: particle.lifetime as f32,
+            color: particle.color,
+            uv,
+            size: particle.size,
+            gravity: particle.gravity,
+            drag: particle.drag,
+            alive: 1,
+        }
+    }
+
+    /// The storage buffer as an instance buffer, laid out like [crate::render::particle::ParticleInstance] with
+    /// whether the particle is alive at location 4
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = [
+            //Position
+            wgpu::VertexAttribute {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float32x3,
+            },
+            //Size
+            wgpu::VertexAttribute {
+                offset: 64,
+                shader_location: 1,
+                format: wgpu::VertexFormat::Float32,
+            },
+            //UV
+            wgpu::VertexAttribute {
+                offset: 48,
+                shader_location: 2,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            //Color
+            wgpu::VertexAttribute {
+                offset: 32,
+                shader_location: 3,
+                format: wgpu::VertexFormat::Float32x4,
+            },
+            //Alive
+            wgpu::VertexAttribute {
+                offset: 76,
+                shader_location: 4,
+                format: wgpu::VertexFormat::Uint32,
+            },
+        ];
+
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<GpuParticle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct Params {
+    spawn_count: u32,
+    capacity: u32,
+    /// How many ticks passed since the last frame
+    delta: f32,
+    padding: u32,
+}
+
+/// The contents of the free list buffer with every slot free, the count followed by the slots
+fn free_list(capacity: u32) -> Vec<u32> {
+    std::iter::once(capacity).chain(0..capacity).collect()
+}
+
+pub struct GpuParticles {
+    emit: Arc<wgpu::ComputePipeline>,
+    simulate: Arc<wgpu::ComputePipeline>,
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Also the instance buffer they're drawn with
+    particles: Arc<wgpu::Buffer>,
+    free_list: wgpu::Buffer,
+    /// The particles spawned since the last frame
+    pending: Mutex<Vec<GpuParticle>>,
+    last_frame: Mutex<Option<Instant>>,
+}
+
+impl GpuParticles {
+    /// Returns [None] if the device doesn't support compute shaders, or the shader is missing from the resource
+    /// provider or doesn't compile
+    #[must_use]
+    pub fn new(wm: &WmRenderer) -> Option<Self> {
+        let wgpu_state = &wm.wgpu_state;
+
+        if !wgpu_state
+            .adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            log::warn!(
+                "The device can't simulate particles on the GPU, they will be simulated on the CPU"
+            );
+            return None;
+        }
+
+        let device = &wgpu_state.device;
+
+        let entry = |binding: u32, ty: BufferBindingType| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Particle simulation"),
+            entries: &[
+                entry(0, BufferBindingType::Uniform),
+                entry(1, BufferBindingType::Storage { read_only: true }),
+                entry(2, BufferBindingType::Storage { read_only: false }),
+                entry(3, BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+
+        let pipelines = wm.pipelines.load();
+        let shader = ResourcePath::from("wgpu_mc:shaders/particle_simulation.wgsl");
+
+        let create = |name: &str, entry_point: &str| {
+            pipelines.create_compute_pipeline(wm, name, &shader, entry_point, &[&bind_group_layout])
+        };
+
+        let (emit, simulate) = match create("wgpu_mc:particle_emit", "emit").and_then(|emit| {
+            create("wgpu_mc:particle_simulate", "simulate").map(|simulate| (emit, simulate))
+        }) {
+            Ok(pipelines) => pipelines,
+            Err(error) => {
+                log::warn!(
+                    "The particle simulation shader couldn't be loaded, particles will be simulated on the CPU: {error}"
+                );
+                return None;
+            }
+        };
+
+        let particles = Arc::new(device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("GPU particles"),
+            contents: bytemuck::cast_slice(&vec![
+                GpuParticle::zeroed();
+                GPU_PARTICLE_CAPACITY as usize
+            ]),
+            usage: BufferUsages::STORAGE | BufferUsages::VERTEX,
+        }));
+
+        let free_list = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("GPU particle free list"),
+            contents: bytemuck::cast_slice(&free_list(GPU_PARTICLE_CAPACITY)),
+            usage: BufferUsages::STORAGE,
+        });
+
+        Some(Self {
+            emit,
+            simulate,
+            bind_group_layout,
+            particles,
+            free_list,
+            pending: Mutex::new(Vec::new()),
+            last_frame: Mutex::new(None),
+        })
+    }
+
+    /// Writes the particle into a free slot in the next [GpuParticles::simulate]
+    pub fn spawn(&self, particle: GpuParticle) {
+        let mut pending = self.pending.lock();
+
+        //Any more than that couldn't find a slot anyway
+        if pending.len() < GPU_PARTICLE_CAPACITY as usize {
+            pending.push(particle);
+        }
+    }
+
+    /// The storage buffer of the particles, and the instance buffer they're drawn with
+    #[must_use]
+    pub fn buffer(&self) -> &Arc<wgpu::Buffer> {
+        &self.particles
+    }
+
+    /// Spawns the pending particles and moves every particle along by the ticks since the last frame. The passes are
+    /// submitted right away, so they're done before any command buffer submitted after them.
+    pub fn simulate(&self, wm: &WmRenderer) {
+        let now = Instant::now();
+        let delta = self.last_frame.lock().replace(now).map_or(0.0, |last| {
+            (now.duration_since(last).as_secs_f32() * 20.0).min(MAX_FRAME_TICKS)
+        });
+
+        let mut spawns = std::mem::take(&mut *self.pending.lock());
+        let spawn_count = spawns.len() as u32;
+
+        //Storage buffers can't be empty
+        if spawns.is_empty() {
+            spawns.push(GpuParticle::zeroed());
+        }
+
+        let device = &wm.wgpu_state.device;
+
+        let params = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Particle simulation params"),
+            contents: bytemuck::bytes_of(&Params {
+                spawn_count,
+                capacity: GPU_PARTICLE_CAPACITY,
+                delta,
+                padding: 0,
+            }),
+            usage: BufferUsages::UNIFORM,
+        });
+
+        let spawns = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("Particle spawns"),
+            contents: bytemuck::cast_slice(&spawns),
+            usage: BufferUsages::STORAGE,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Particle simulation"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: params.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: spawns.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: self.particles.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.free_list.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Particle simulation"),
+        });
+
+        //In passes of their own, so the slots are only popped in one and only pushed in the other
+        if spawn_count > 0 {
+            dispatch(
+                &mut encoder,
+                "Particle emit",
+                &self.emit,
+                &[&bind_group],
+                [workgroup_count(spawn_count, WORKGROUP_SIZE), 1, 1],
+            );
+        }
+
+        dispatch(
+            &mut encoder,
+            "Particle simulate",
+            &self.simulate,
+            &[&bind_group],
+            [workgroup_count(GPU_PARTICLE_CAPACITY, WORKGROUP_SIZE), 1, 1],
+        );
+
+        wm.wgpu_state.queue.submit([encoder.finish()]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{free_list, GpuParticle};
+
+    #[test]
+    fn particles_match_the_shader_layout() {
+        //vec3 and f32 pairs, two vec4s and four scalars
+        assert_eq!(std::mem::size_of::<GpuParticle>(), 80);
+        assert_eq!(memoffset(|particle| &particle.uv), 48);
+        assert_eq!(memoffset(|particle| &particle.alive), 76);
+
+        assert_eq!(free_list(3), [3, 0, 1, 2]);
+    }
+
+    fn memoffset<T>(field: impl Fn(&GpuParticle) -> &T) -> usize {
+        let particle: GpuParticle = bytemuck::Zeroable::zeroed();
+
+        field(&particle) as *const T as usize - &particle as *const GpuParticle as usize
+    }
+}
@@ -0,0 +1,521 @@
+//! # Particles
+//!
+//! The [ParticleManager] of the [crate::mc::MinecraftState] keeps the particles which are drawn, and pipelines with
+//! the `wm_geo_particles` geometry draw them as camera-facing billboards, one [ParticleInstance] per particle in an
+//! instance buffer which is written every frame. Particles come from two places:
+//! - [Particle]s simulated by wgpu-mc, spawned with [ParticleManager::spawn] or by [ParticleEmitter]s, and moved
+//!   every [ParticleManager::tick] like vanilla's, without colliding with blocks
+//! - [ParticleState]s of particles the frontend simulates itself, set every frame with
+//!   [ParticleManager::set_external]
+//!
+//! Their textures are in the particle atlas, see [ParticleManager::load_textures]. The vertex shader expands each
+//! instance into a quad facing the camera, using the axes of the view matrix, so the pipelines bind the view matrix
+//! as well as the projection. They're meant to be drawn after the translucent terrain, alpha blended without writing
+//! depth.
+//!
+//! Heavy effects with many more particles can be simulated on the GPU instead with [ParticleManager::spawn_gpu], see
+//! [gpu].
+
+pub mod gpu;
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use bytemuck::{Pod, Zeroable};
+use parking_lot::{Mutex, RwLock};
+
+use crate::mc::chunk::ChunkPos;
+use crate::mc::entity::Position;
+use crate::mc::resource::ResourcePath;
+use crate::mc::MinecraftState;
+use crate::render::atlas::{AtlasError, AtlasKind};
+use crate::render::particle::gpu::{GpuParticle, GpuParticles};
+use crate::WmRenderer;
+
+/// The most particles wgpu-mc simulates at once, like vanilla's limit. Particles spawned past it are dropped.
+pub const MAX_PARTICLES: usize = 16384;
+
+/// The downwards acceleration of particles with a `gravity` of 1, in blocks per tick per tick, like vanilla's
+const GRAVITY: f32 = 0.04;
+
+/// A particle as it's drawn, expanded into a quad by the vertex shader
+#[derive(Copy, Clone, Debug, PartialEq, Zeroable, Pod)]
+#[repr(C)]
+pub struct ParticleInstance {
+    pub position: [f32; 3],
+    /// Half the width of the quad, in blocks
+    pub size: f32,
+    /// The min and max UVs of the sprite in the particle atlas
+    pub uv: [f32; 4],
+    pub color: [f32; 4],
+    /// Rotation of the quad around the direction the camera looks in, in radians
+    pub roll: f32,
+}
+
+impl ParticleInstance {
+    const VAA: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32,
+        2 => Float32x4,
+        3 => Float32x4,
+        4 => Float32
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// A particle at a moment, with the sprite it's drawn with
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleState {
+    pub position: Position,
+    /// Half the width of the quad, in blocks, like vanilla's `scale`
+    pub size: f32,
+    /// In the particle atlas, like `minecraft:particle/flame`
+    pub sprite: ResourcePath,
+    pub color: [f32; 4],
+    /// In radians
+    pub roll: f32,
+}
+
+/// A particle simulated by wgpu-mc
+#[derive(Clone, Debug)]
+pub struct Particle {
+    pub position: Position,
+    /// Where the particle was at the last tick, which it's interpolated from
+    pub previous_position: Position,
+    /// In blocks per tick
+    pub velocity: [f32; 3],
+    /// How strongly the particle falls, 0 for particles which float like smoke
+    pub gravity: f32,
+    /// What the velocity is multiplied by every tick, like vanilla's `velocityMultiplier`
+    pub drag: f32,
+    /// In ticks
+    pub age: u32,
+    pub lifetime: u32,
+    pub size: f32,
+    pub color: [f32; 4],
+    pub roll: f32,
+    /// How far the roll changes every tick, in radians
+    pub roll_velocity: f32,
+    /// The frames of the particle's animation, played once over its lifetime, like `minecraft:particle/generic_7`
+    /// to `generic_0` for smoke
+    pub sprites: Arc<Vec<ResourcePath>>,
+}
+
+impl Particle {
+    #[must_use]
+    pub fn new(
+        position: Position,
+        velocity: [f32; 3],
+        lifetime: u32,
+        sprites: Arc<Vec<ResourcePath>>,
+    ) -> Self {
+        Self {
+            position,
+            previous_position: position,
+            velocity,
+            gravity: 0.0,
+            drag: 0.98,
+            age: 0,
+            lifetime,
+            size: 0.1,
+            color: [1.0; 4],
+            roll: 0.0,
+            roll_velocity: 0.0,
+            sprites,
+        }
+    }
+
+    /// Moves the particle along by a tick. Returns whether it's still alive.
+    fn tick(&mut self) -> bool {
+        self.previous_position = self.position;
+        self.age += 1;
+
+        if self.age >= self.lifetime {
+            return false;
+        }
+
+        self.velocity[1] -= GRAVITY * self.gravity;
+        self.position = (
+            self.position.0 + self.velocity[0],
+            self.position.1 + self.velocity[1],
+            self.position.2 + self.velocity[2],
+        );
+        self.velocity = self.velocity.map(|velocity| velocity * self.drag);
+        self.roll += self.roll_velocity;
+
+        true
+    }
+
+    /// The sprite of the frame of the animation the particle is at
+    fn sprite(&self) -> Option<&ResourcePath> {
+        let frames = self.sprites.len();
+        let frame = (self.age as usize * frames) / self.lifetime.max(1) as usize;
+
+        self.sprites.get(frame.min(frames.saturating_sub(1)))
+    }
+
+    /// The particle interpolated between the last tick and the next one
+    fn state(&self, tick_delta: f32) -> Option<ParticleState> {
+        let lerp = |from: f32, to: f32| from + (to - from) * tick_delta;
+
+        Some(ParticleState {
+            position: (
+                lerp(self.previous_position.0, self.position.0),
+                lerp(self.previous_position.1, self.position.1),
+                lerp(self.previous_position.2, self.position.2),
+            ),
+            size: self.size,
+            sprite: self.sprite()?.clone(),
+            color: self.color,
+            roll: lerp(self.roll - self.roll_velocity, self.roll),
+        })
+    }
+}
+
+/// Spawns particles every tick, see [ParticleManager::add_emitter]
+pub trait ParticleEmitter: Send {
+    /// Spawns the particles of this tick. Returns whether the emitter keeps going, it's removed otherwise.
+    fn tick(&mut self, spawn: &mut dyn FnMut(Particle)) -> bool;
+}
+
+/// Spawns copies of a particle at its position for a number of ticks, flying out in all directions
+#[derive(Clone, Debug)]
+pub struct PointEmitter {
+    pub particle: Particle,
+    pub per_tick: u32,
+    /// How many more ticks it spawns particles for
+    pub ticks: u32,
+    /// The most the speed of each particle is changed by in every direction, in blocks per tick
+    pub spread: f32,
+    seed: u32,
+}
+
+impl PointEmitter {
+    #[must_use]
+    pub fn new(particle: Particle, per_tick: u32, ticks: u32, spread: f32) -> Self {
+        Self {
+            particle,
+            per_tick,
+            ticks,
+            spread,
+            seed: 0x9e37_79b9,
+        }
+    }
+
+    /// From -1 to 1, with xorshift
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+
+        (self.seed as f32 / u32::MAX as f32) * 2.0 - 1.0
+    }
+}
+
+impl ParticleEmitter for PointEmitter {
+    fn tick(&mut self, spawn: &mut dyn FnMut(Particle)) -> bool {
+        if self.ticks == 0 {
+            return false;
+        }
+
+        self.ticks -= 1;
+
+        for _ in 0..self.per_tick {
+            let mut particle = self.particle.clone();
+            particle.velocity = particle
+                .velocity
+                .map(|velocity| velocity + self.random() * self.spread);
+
+            spawn(particle);
+        }
+
+        self.ticks > 0
+    }
+}
+
+/// The particles which are drawn, see [crate::render::particle]
+#[derive(Default)]
+pub struct ParticleManager {
+    particles: Mutex<Vec<Particle>>,
+    emitters: Mutex<Vec<Box<dyn ParticleEmitter>>>,
+    external: RwLock<Vec<ParticleState>>,
+    /// The instance buffer, which grows to fit the particles
+    buffer: Mutex<Option<Arc<wgpu::Buffer>>>,
+    /// The particles simulated on the GPU, created by [WmRenderer::init] if [crate::WmConfig::gpu_particles] is on
+    pub gpu: ArcSwap<Option<GpuParticles>>,
+}
+
+impl ParticleManager {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a particle to the ones wgpu-mc simulates, unless there are already [MAX_PARTICLES]
+    pub fn spawn(&self, particle: Particle) {
+        let mut particles = self.particles.lock();
+
+        if particles.len() < MAX_PARTICLES {
+            particles.push(particle);
+        }
+    }
+
+    /// Adds a particle to the ones simulated on the GPU, or to the ones simulated by wgpu-mc if that's off. On the
+    /// GPU, particles don't animate their sprites and are drawn with the first of them, which has to be in the
+    /// particle atlas.
+    pub fn spawn_gpu(&self, wm: &WmRenderer, particle: Particle) {
+        let gpu = self.gpu.load();
+
+        let gpu = match &**gpu {
+            Some(gpu) => gpu,
+            None => return self.spawn(particle),
+        };
+
+        let atlas = wm.mc.texture_manager.atlas(AtlasKind::Particle);
+        let atlas_size = atlas.size() as f32;
+
+        let uv = particle
+            .sprites
+            .first()
+            .and_then(|sprite| atlas.uv_map.read().get(sprite).copied());
+
+        if let Some(((min_x, min_y), (max_x, max_y))) = uv {
+            gpu.spawn(GpuParticle::new(
+                &particle,
+                [
+                    min_x / atlas_size,
+                    min_y / atlas_size,
+                    max_x / atlas_size,
+                    max_y / atlas_size,
+                ],
+            ));
+        }
+    }
+
+    /// Ticks the emitter along with the particles until it's done
+    pub fn add_emitter(&self, emitter: Box<dyn ParticleEmitter>) {
+        self.emitters.lock().push(emitter);
+    }
+
+    /// Replaces the particles the frontend simulates itself, usually every frame
+    pub fn set_external(&self, particles: Vec<ParticleState>) {
+        *self.external.write() = particles;
+    }
+
+    /// Moves every particle along by a tick and removes the ones which died, then ticks the emitters. Called 20
+    /// times a second, like the game ticks.
+    pub fn tick(&self) {
+        let mut particles = self.particles.lock();
+        particles.retain_mut(Particle::tick);
+
+        self.emitters.lock().retain_mut(|emitter| {
+            emitter.tick(&mut |particle| {
+                if particles.len() < MAX_PARTICLES {
+                    particles.push(particle);
+                }
+            })
+        });
+    }
+
+    /// Removes every particle and emitter, like when the world changes
+    pub fn clear(&self) {
+        self.particles.lock().clear();
+        self.emitters.lock().clear();
+        self.external.write().clear();
+    }
+
+    /// How many particles wgpu-mc simulates
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.particles.lock().len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the sprites to the particle atlas from the resources, like `minecraft:particle/flame` from
+    /// `minecraft:textures/particle/flame.png`. Sprites which are missing from the resources are skipped.
+    pub fn load_textures(
+        &self,
+        wm: &WmRenderer,
+        sprites: impl IntoIterator<Item = ResourcePath>,
+    ) -> Result<(), AtlasError> {
+        let atlas = wm.mc.texture_manager.atlas(AtlasKind::Particle);
+
+        let images: Vec<(ResourcePath, Vec<u8>)> = sprites
+            .into_iter()
+            .filter(|sprite| !atlas.uv_map.read().contains_key(sprite))
+            .filter_map(|sprite| {
+                let bytes = wm
+                    .mc
+                    .resource_provider
+                    .get_bytes(&sprite.prepend("textures/").append(".png"))?;
+
+                Some((sprite, bytes))
+            })
+            .collect();
+
+        atlas.allocate(
+            images.iter().map(|(sprite, bytes)| (sprite, bytes)),
+            &*wm.mc.resource_provider,
+        )?;
+        atlas.upload(wm);
+
+        Ok(())
+    }
+
+    /// The instances of every particle, relative to the chunk offset like terrain. Particles whose sprite isn't in
+    /// the particle atlas are skipped.
+    pub fn instances(
+        &self,
+        mc: &MinecraftState,
+        tick_delta: f32,
+        chunk_offset: ChunkPos,
+    ) -> Vec<ParticleInstance> {
+        let atlas = mc.texture_manager.atlas(AtlasKind::Particle);
+        let atlas_size = atlas.size() as f32;
+        let uv_map = atlas.uv_map.read();
+
+        let offset = [(chunk_offset[0] * 16) as f32, (chunk_offset[1] * 16) as f32];
+
+        let instance = |state: &ParticleState| {
+            let &((min_x, min_y), (max_x, max_y)) = uv_map.get(&state.sprite)?;
+
+            Some(ParticleInstance {
+                position: [
+                    state.position.0 - offset[0],
+                    state.position.1,
+                    state.position.2 - offset[1],
+                ],
+                size: state.size,
+                uv: [
+                    min_x / atlas_size,
+                    min_y / atlas_size,
+                    max_x / atlas_size,
+                    max_y / atlas_size,
+                ],
+                color: state.color,
+                roll: state.roll,
+            })
+        };
+
+        let simulated: Vec<ParticleState> = self
+            .particles
+            .lock()
+            .iter()
+            .filter_map(|particle| particle.state(tick_delta))
+            .collect();
+
+        simulated
+            .iter()
+            .chain(self.external.read().iter())
+            .filter_map(instance)
+            .collect()
+    }
+
+    /// Writes the instances of every particle into the instance buffer, returning it along with the number of
+    /// instances, or [None] if there aren't any
+    pub fn upload(
+        &self,
+        wm: &WmRenderer,
+        chunk_offset: ChunkPos,
+    ) -> Option<(Arc<wgpu::Buffer>, u32)> {
+        let tick_delta = wm.frame_uniforms.load().tick_delta;
+        let instances = self.instances(&wm.mc, tick_delta, chunk_offset);
+
+        if instances.is_empty() {
+            return None;
+        }
+
+        let bytes: &[u8] = bytemuck::cast_slice(&instances);
+        let mut buffer = self.buffer.lock();
+
+        let buffer = match &*buffer {
+            Some(existing) if existing.size() >= bytes.len() as u64 => existing.clone(),
+            _ => {
+                let created =
+                    Arc::new(wm.wgpu_state.device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("particles"),
+                        size: (bytes.len() as u64).next_power_of_two(),
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    }));
+
+                *buffer = Some(created.clone());
+                created
+            }
+        };
+
+        wm.wgpu_state.queue.write_buffer(&buffer, 0, bytes);
+
+        Some((buffer, instances.len() as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Particle, ParticleEmitter, PointEmitter};
+    use crate::mc::resource::ResourcePath;
+
+    fn particle() -> Particle {
+        let sprites = (0..4)
+            .map(|frame| ResourcePath(format!("minecraft:particle/generic_{frame}")))
+            .collect();
+
+        Particle {
+            gravity: 1.0,
+            ..Particle::new((0.0, 64.0, 0.0), [0.1, 0.0, 0.0], 8, Arc::new(sprites))
+        }
+    }
+
+    #[test]
+    fn particles_fall_and_play_their_sprites() {
+        let mut particle = particle();
+
+        assert!(particle.tick());
+        assert_eq!(particle.previous_position, (0.0, 64.0, 0.0));
+        assert!((particle.position.0 - 0.1).abs() < 1e-6);
+        assert!((particle.position.1 - 63.96).abs() < 1e-6);
+        assert!((particle.velocity[0] - 0.098).abs() < 1e-6);
+
+        //Two ticks per frame of the four
+        assert_eq!(particle.sprite().unwrap().0, "minecraft:particle/generic_0");
+        particle.tick();
+        particle.tick();
+        assert_eq!(particle.sprite().unwrap().0, "minecraft:particle/generic_1");
+
+        let halfway = particle.state(0.5).unwrap();
+        assert!(halfway.position.1 < particle.previous_position.1);
+        assert!(halfway.position.1 > particle.position.1);
+
+        while particle.tick() {}
+        assert_eq!(particle.age, 8);
+    }
+
+    #[test]
+    fn emitters_spread_their_particles() {
+        let mut emitter = PointEmitter::new(particle(), 3, 2, 0.05);
+        let mut spawned = Vec::new();
+
+        assert!(emitter.tick(&mut |particle| spawned.push(particle)));
+        assert!(!emitter.tick(&mut |particle| spawned.push(particle)));
+        assert_eq!(spawned.len(), 6);
+
+        assert!(spawned
+            .iter()
+            .all(|particle| (particle.velocity[0] - 0.1).abs() <= 0.05));
+        assert_ne!(spawned[0].velocity, spawned[1].velocity);
+    }
+}
@@ -0,0 +1,246 @@
+//! # Beams
+//!
+//! Lines between two points which aren't part of any model, like leashes, fishing lines and the beams of guardians
+//! and end crystals. They're [BeamInstance]s, set every frame with [crate::mc::MinecraftState::set_beams], and drawn
+//! by pipelines with the `wm_geo_beams` geometry as a triangle list of [BeamVertex].
+//!
+//! Each beam is a strip of quads along its path, doubled up with a second strip at right angles to the first like
+//! vanilla's leashes, so it's seen from every side without facing the camera. Leashes and fishing lines sag between
+//! their ends, which is approximated with a parabola through the strip's segments.
+//!
+//! Textured beams repeat their texture along their length and scroll it, so the texture coordinates of the vertices
+//! are relative to the texture and keep going past 1, and the shader wraps them into the `region` the texture has in
+//! the block atlas. The textures are the [beam_textures], which are allocated in the block atlas along with the
+//! textures of the blocks.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{InnerSpace, Vector3};
+
+use crate::mc::chunk::ChunkPos;
+use crate::mc::entity::Position;
+use crate::mc::resource::ResourcePath;
+use crate::mc::MinecraftState;
+use crate::render::atlas::AtlasKind;
+
+/// The most segments a beam is split into
+pub const MAX_BEAM_SEGMENTS: u32 = 64;
+
+/// The textures beams can be drawn with, allocated in the block atlas
+pub fn beam_textures() -> [ResourcePath; 3] {
+    [
+        ResourcePath("minecraft:entity/guardian_beam".into()),
+        ResourcePath("minecraft:entity/end_crystal/end_crystal_beam".into()),
+        ResourcePath("minecraft:entity/beacon_beam".into()),
+    ]
+}
+
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+pub struct BeamVertex {
+    pub position: [f32; 3],
+    /// Relative to the texture, U across the beam and V along it, wrapped into the region by the shader
+    pub tex_coords: [f32; 2],
+    /// The min and max UVs of the texture in the block atlas, negative if the beam isn't textured
+    pub region: [f32; 4],
+    pub color: [f32; 4],
+}
+
+impl BeamVertex {
+    const VAA: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4,
+        3 => Float32x4
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<BeamVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// A line between two points, drawn for a frame
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeamInstance {
+    pub from: Position,
+    pub to: Position,
+    /// In blocks
+    pub width: f32,
+    /// Multiplied with the texture, or the color of the beam if it isn't textured
+    pub color: [f32; 4],
+    /// One of the [beam_textures], or [None] for a plain line like a leash
+    pub texture: Option<ResourcePath>,
+    /// How far along the beam one repeat of the texture reaches, in blocks
+    pub texture_length: f32,
+    /// How far the texture has scrolled along the beam, in repeats of the texture, usually the age of the beam times
+    /// its speed
+    pub scroll: f32,
+    /// How far the middle of the beam hangs below the straight line between its ends, in blocks
+    pub sag: f32,
+    /// How many segments the beam is made of, more of them making a sagging beam smoother
+    pub segments: u32,
+}
+
+impl BeamInstance {
+    /// The points along the beam the segments are between
+    fn points(&self) -> Vec<Vector3<f32>> {
+        let from = Vector3::new(self.from.0, self.from.1, self.from.2);
+        let to = Vector3::new(self.to.0, self.to.1, self.to.2);
+
+        let segments = if self.sag == 0.0 {
+            1
+        } else {
+            self.segments.clamp(1, MAX_BEAM_SEGMENTS)
+        };
+
+        (0..=segments)
+            .map(|segment| {
+                let t = segment as f32 / segments as f32;
+
+                from + (to - from) * t - Vector3::unit_y() * (self.sag * 4.0 * t * (1.0 - t))
+            })
+            .collect()
+    }
+
+    /// The vertices of the beam in world coordinates, with the texture coordinates relative to the texture
+    fn vertices(&self, region: [f32; 4]) -> Vec<BeamVertex> {
+        let points = self.points();
+        let texture_length = self.texture_length.max(f32::EPSILON);
+        let mut vertices = Vec::new();
+        let mut distance = 0.0;
+
+        for segment in points.windows(2) {
+            let (start, end) = (segment[0], segment[1]);
+            let direction = end - start;
+            let length = direction.magnitude();
+
+            if length <= f32::EPSILON {
+                continue;
+            }
+
+            let direction = direction / length;
+
+            //Vertical beams can't be crossed with up, so they're crossed with X instead
+            let first = if direction.cross(Vector3::unit_y()).magnitude2() > 1e-6 {
+                direction.cross(Vector3::unit_y()).normalize()
+            } else {
+                direction.cross(Vector3::unit_x()).normalize()
+            };
+            let second = direction.cross(first).normalize();
+
+            let start_v = distance / texture_length + self.scroll;
+            let end_v = (distance + length) / texture_length + self.scroll;
+            distance += length;
+
+            for side in [first, second] {
+                let side = side * (self.width / 2.0);
+                let vertex = |position: Vector3<f32>, u: f32, v: f32| BeamVertex {
+                    position: position.into(),
+                    tex_coords: [u, v],
+                    region,
+                    color: self.color,
+                };
+
+                let a = vertex(start - side, 0.0, start_v);
+                let b = vertex(start + side, 1.0, start_v);
+                let c = vertex(end + side, 1.0, end_v);
+                let d = vertex(end - side, 0.0, end_v);
+
+                //Both faces, as it's seen from either side
+                vertices.extend([a, b, c, a, c, d, a, c, b, a, d, c]);
+            }
+        }
+
+        vertices
+    }
+}
+
+/// The vertices of every beam, relative to the chunk offset like terrain
+pub fn beam_vertices(mc: &MinecraftState, chunk_offset: ChunkPos) -> Vec<BeamVertex> {
+    let beams = mc.beams.load();
+
+    if beams.is_empty() {
+        return Vec::new();
+    }
+
+    let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+    let atlas_size = block_atlas.size() as f32;
+    let uv_map = block_atlas.uv_map.read();
+
+    let offset = Vector3::new(
+        (chunk_offset[0] * 16) as f32,
+        0.0,
+        (chunk_offset[1] * 16) as f32,
+    );
+
+    beams
+        .iter()
+        .flat_map(|beam| {
+            //Textures which aren't in the atlas are drawn as plain lines rather than not at all
+            let region = beam
+                .texture
+                .as_ref()
+                .and_then(|texture| uv_map.get(texture))
+                .map_or([-1.0; 4], |&((min_x, min_y), (max_x, max_y))| {
+                    [
+                        min_x / atlas_size,
+                        min_y / atlas_size,
+                        max_x / atlas_size,
+                        max_y / atlas_size,
+                    ]
+                });
+
+            beam.vertices(region).into_iter().map(|mut vertex| {
+                vertex.position = (Vector3::from(vertex.position) - offset).into();
+                vertex
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BeamInstance;
+
+    fn beam(sag: f32) -> BeamInstance {
+        BeamInstance {
+            from: (0.0, 64.0, 0.0),
+            to: (4.0, 64.0, 0.0),
+            width: 0.1,
+            color: [1.0; 4],
+            texture: None,
+            texture_length: 2.0,
+            scroll: 0.5,
+            sag,
+            segments: 8,
+        }
+    }
+
+    #[test]
+    fn straight_beams_repeat_their_texture() {
+        let vertices = beam(0.0).vertices([-1.0; 4]);
+
+        //One segment, two crossed strips with both faces
+        assert_eq!(vertices.len(), 24);
+
+        let max_v = vertices
+            .iter()
+            .map(|vertex| vertex.tex_coords[1])
+            .fold(f32::MIN, f32::max);
+        assert_eq!(max_v, 2.5);
+    }
+
+    #[test]
+    fn sagging_beams_hang_in_the_middle() {
+        let points = beam(1.0).points();
+
+        assert_eq!(points.len(), 9);
+        assert_eq!(points[0].y, 64.0);
+        assert_eq!(points[4].y, 63.0);
+        assert_eq!(points[8].y, 64.0);
+    }
+}
@@ -0,0 +1,175 @@
+//! The cracks on blocks which are being broken, set with [crate::mc::MinecraftState::set_block_breaking] and drawn by
+//! pipelines with the `wm_geo_block_breaking` geometry as a triangle list of [BreakingVertex].
+//!
+//! The faces of the block's model are drawn again with the `destroy_stage_N` texture of the block atlas, which is
+//! projected onto each face by its position like vanilla does, so that the cracks line up across the faces whatever
+//! the UVs of the model are. The pipeline is given [DepthBiasPresets::DECALS] so that the cracks don't Z-fight with
+//! the terrain underneath.
+//!
+//! [DepthBiasPresets::DECALS]: crate::render::pipeline::debug_lines::DepthBiasPresets::DECALS
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::mc::block::{BlockPos, CubeOrComplexMesh, ModelMesh};
+use crate::mc::chunk::ChunkPos;
+use crate::mc::resource::ResourcePath;
+use crate::mc::MinecraftState;
+use crate::render::atlas::AtlasKind;
+use crate::texture::UV;
+
+/// How many stages of cracks there are, `destroy_stage_0` to `destroy_stage_9`
+pub const DESTROY_STAGES: u8 = 10;
+
+/// The block atlas texture of the stage
+pub fn destroy_stage_texture(stage: u8) -> ResourcePath {
+    ResourcePath(format!("minecraft:block/destroy_stage_{stage}"))
+}
+
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+pub struct BreakingVertex {
+    pub position: [f32; 3],
+    /// In the block atlas
+    pub tex_coords: [f32; 2],
+}
+
+impl BreakingVertex {
+    const VAA: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<BreakingVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// Where on the crack texture the point of the block lies, from 0 to 1. The point is projected along the axis the
+/// face points towards the most.
+fn face_uv(position: [f32; 3], normal: [f32; 4]) -> [f32; 2] {
+    let [x, y, z] = position.map(|coordinate| coordinate.clamp(0.0, 1.0));
+    let [nx, ny, nz] = [normal[0].abs(), normal[1].abs(), normal[2].abs()];
+
+    if ny >= nx && ny >= nz {
+        [x, z]
+    } else if nx >= nz {
+        [z, 1.0 - y]
+    } else {
+        [x, 1.0 - y]
+    }
+}
+
+/// The faces of the model the block at the position uses, with the crack texture in `stage_uv` projected onto them.
+/// `stage_uv` is normalized to the size of the atlas.
+pub fn block_vertices(
+    pos: BlockPos,
+    mesh: &ModelMesh,
+    stage_uv: UV,
+    chunk_offset: ChunkPos,
+) -> Vec<BreakingVertex> {
+    let origin = [
+        (pos.0 - chunk_offset[0] * 16) as f32,
+        pos.1 as f32,
+        (pos.2 - chunk_offset[1] * 16) as f32,
+    ];
+
+    let (model, _) = match mesh.models.get(mesh.pick_model(pos.0, pos.1 as i32, pos.2)) {
+        Some(model) => model,
+        None => return Vec::new(),
+    };
+
+    let faces = match model {
+        CubeOrComplexMesh::Cube(faces) => std::slice::from_ref(&**faces),
+        CubeOrComplexMesh::Complex(faces) => &faces[..],
+    };
+
+    let ((min_u, min_v), (max_u, max_v)) = stage_uv;
+
+    faces
+        .iter()
+        .flat_map(|faces| {
+            [
+                &faces.north,
+                &faces.east,
+                &faces.south,
+                &faces.west,
+                &faces.up,
+                &faces.down,
+            ]
+        })
+        .flatten()
+        .flatten()
+        .map(|vertex| {
+            let [u, v] = face_uv(vertex.position, vertex.normal);
+
+            BreakingVertex {
+                position: std::array::from_fn(|axis| origin[axis] + vertex.position[axis]),
+                tex_coords: [min_u + (max_u - min_u) * u, min_v + (max_v - min_v) * v],
+            }
+        })
+        .collect()
+}
+
+/// The vertices of the cracks of every block which is being broken
+pub fn breaking_vertices(mc: &MinecraftState, chunk_offset: ChunkPos) -> Vec<BreakingVertex> {
+    let breaking = mc.block_breaking.load();
+
+    if breaking.is_empty() {
+        return Vec::new();
+    }
+
+    let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+    let atlas_size = block_atlas.size() as f32;
+    let uv_map = block_atlas.uv_map.read();
+
+    let block_manager = mc.block_manager.read();
+
+    breaking
+        .iter()
+        .filter_map(|(&pos, &(state, stage))| {
+            let ((min_x, min_y), (max_x, max_y)) = *uv_map.get(&destroy_stage_texture(stage))?;
+            let (_, block) = block_manager.blocks.get_index(state.block as usize)?;
+
+            Some(block_vertices(
+                pos,
+                &block.get_model(state.augment),
+                (
+                    (min_x / atlas_size, min_y / atlas_size),
+                    (max_x / atlas_size, max_y / atlas_size),
+                ),
+                chunk_offset,
+            ))
+        })
+        .flatten()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::face_uv;
+
+    #[test]
+    fn cracks_are_projected_onto_the_face() {
+        //The top of a slab and the top of a full block get the same part of the texture
+        assert_eq!(
+            face_uv([0.25, 0.5, 0.75], [0.0, 1.0, 0.0, 0.0]),
+            [0.25, 0.75]
+        );
+        assert_eq!(
+            face_uv([0.25, 1.0, 0.75], [0.0, 1.0, 0.0, 0.0]),
+            [0.25, 0.75]
+        );
+
+        //Sides have the top of the texture at the top of the block
+        assert_eq!(
+            face_uv([0.0, 1.0, 0.25], [-1.0, 0.0, 0.0, 0.0]),
+            [0.25, 0.0]
+        );
+        assert_eq!(face_uv([0.5, 0.0, 1.0], [0.0, 0.0, 1.0, 0.0]), [0.5, 1.0]);
+    }
+}
@@ -0,0 +1,151 @@
+//! The outline around the block the player is looking at, set with [crate::WmRenderer::set_block_outline] and drawn
+//! by pipelines with the `wm_geo_block_outline` geometry as a line list of [DebugLineVertex].
+//!
+//! The outline is drawn with alpha blending, so the pixels where lines meet would come out darker than the others.
+//! Like vanilla, the graph keeps that from happening with the stencil buffer if the depth textures have a stencil
+//! aspect, see [crate::WmConfig::stencil]. Without one, an edge shared by two boxes of the shape would still be drawn
+//! twice, so the shared edges are dropped when the lines are built either way. That's done before the lines are
+//! pushed out of the shape, as the edge of one box where it meets another would be pushed a different way by each
+//! of them.
+
+use crate::mc::block::{BlockPos, BlockShape};
+use crate::mc::chunk::ChunkPos;
+use crate::render::pipeline::debug_lines::DebugLineVertex;
+
+/// How far the outline sits outside of the shape's bounds, so that it isn't hidden by the faces of the block
+const OUTLINE_OFFSET: f32 = 0.002;
+
+/// The two corners of each of the 12 edges of a box, as indices into its 8 corners, where bit 0 of a corner picks
+/// the max X, bit 1 the max Y and bit 2 the max Z
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// The lines of the outline of the block's shape, relative to the chunk offset like terrain
+pub fn outline_vertices(
+    pos: BlockPos,
+    shape: &BlockShape,
+    chunk_offset: ChunkPos,
+) -> Vec<DebugLineVertex> {
+    let origin = [
+        (pos.0 - chunk_offset[0] * 16) as f32,
+        pos.1 as f32,
+        (pos.2 - chunk_offset[1] * 16) as f32,
+    ];
+
+    let aabbs = shape.aabbs();
+
+    //In block space, so that the edges two boxes share are exactly the same
+    let mut edges: Vec<([f32; 3], [f32; 3])> = Vec::new();
+
+    for aabb in &aabbs {
+        let corner = |index: usize| -> [f32; 3] {
+            std::array::from_fn(|axis| {
+                if index & (1 << axis) != 0 {
+                    aabb[axis + 3]
+                } else {
+                    aabb[axis]
+                }
+            })
+        };
+
+        for (from, to) in BOX_EDGES {
+            let edge = (corner(from), corner(to));
+
+            if !edges.contains(&edge) {
+                edges.push(edge);
+            }
+        }
+    }
+
+    //Every corner is pushed away from the middle of the shape's bounds, corners in line with it stay where they are
+    let middle: [f32; 3] = std::array::from_fn(|axis| {
+        let min = aabbs.iter().map(|aabb| aabb[axis]).fold(f32::MAX, f32::min);
+        let max = aabbs
+            .iter()
+            .map(|aabb| aabb[axis + 3])
+            .fold(f32::MIN, f32::max);
+
+        (min + max) / 2.0
+    });
+
+    let offset = |corner: [f32; 3]| -> [f32; 3] {
+        std::array::from_fn(|axis| {
+            let push = if corner[axis] > middle[axis] {
+                OUTLINE_OFFSET
+            } else if corner[axis] < middle[axis] {
+                -OUTLINE_OFFSET
+            } else {
+                0.0
+            };
+
+            origin[axis] + corner[axis] + push
+        })
+    };
+
+    edges
+        .into_iter()
+        .flat_map(|(from, to)| [offset(from), offset(to)])
+        .map(|position| DebugLineVertex {
+            position,
+            color: [0.0; 3],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::outline_vertices;
+    use crate::mc::block::BlockShape;
+
+    #[test]
+    fn shared_edges_are_drawn_once() {
+        let cube = outline_vertices((17, 64, -3), &BlockShape::FullCube, [1, -1]);
+        assert_eq!(cube.len(), 24);
+
+        let xs: Vec<f32> = cube.iter().map(|vertex| vertex.position[0]).collect();
+        assert!(xs
+            .iter()
+            .all(|&x| (x - 1.0).abs() < 0.01 || (x - 2.0).abs() < 0.01));
+
+        //Boxes sharing edges, like the ones of custom shapes made up of several parts
+        let twice = outline_vertices(
+            (0, 0, 0),
+            &BlockShape::Custom(vec![[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]; 2]),
+            [0, 0],
+        );
+        assert_eq!(twice.len(), 24);
+    }
+
+    #[test]
+    fn edges_where_boxes_meet_are_drawn_once() {
+        //The bottom and top halves of a block, which meet at y = 0.5
+        let halves = outline_vertices(
+            (0, 0, 0),
+            &BlockShape::Custom(vec![
+                [0.0, 0.0, 0.0, 1.0, 0.5, 1.0],
+                [0.0, 0.5, 0.0, 1.0, 1.0, 1.0],
+            ]),
+            [0, 0],
+        );
+
+        //The 4 edges around the middle are shared by both halves
+        assert_eq!(halves.len(), (12 + 12 - 4) * 2);
+
+        //And stay in the middle rather than being pushed up by one half and down by the other
+        let ys: Vec<f32> = halves.iter().map(|vertex| vertex.position[1]).collect();
+        assert!(ys.iter().all(|&y| y == 0.5 || y < 0.0 || y > 1.0));
+        assert_eq!(ys.iter().filter(|&&y| y == 0.5).count(), 16);
+    }
+}
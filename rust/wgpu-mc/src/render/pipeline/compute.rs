@@ -0,0 +1,51 @@
+//! # Compute pipelines
+//!
+//! Compute pipelines are registered under a name with [WmPipelines::create_compute_pipeline], which loads and
+//! validates the shader like the ones of the shader graph, and looked up again with
+//! [WmPipelines::compute_pipeline]. [dispatch] records a pass running one of them. GPU culling goes through here,
+//! and it's where mipmap generation or particle simulation would go too.
+//!
+//! [WmPipelines::create_compute_pipeline]: super::WmPipelines::create_compute_pipeline
+//! [WmPipelines::compute_pipeline]: super::WmPipelines::compute_pipeline
+
+use wgpu::{BindGroup, CommandEncoder, ComputePassDescriptor, ComputePipeline};
+
+/// How many workgroups of the size it takes to run at least the number of invocations
+pub fn workgroup_count(invocations: u32, workgroup_size: u32) -> u32 {
+    invocations.div_ceil(workgroup_size)
+}
+
+/// Records a compute pass which runs the pipeline over the workgroups, with each of the bind groups at the group of
+/// its index
+pub fn dispatch(
+    encoder: &mut CommandEncoder,
+    label: &str,
+    pipeline: &ComputePipeline,
+    bind_groups: &[&BindGroup],
+    workgroups: [u32; 3],
+) {
+    let mut compute_pass =
+        encoder.begin_compute_pass(&ComputePassDescriptor { label: Some(label) });
+
+    compute_pass.set_pipeline(pipeline);
+
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        compute_pass.set_bind_group(index as u32, bind_group, &[]);
+    }
+
+    let [x, y, z] = workgroups;
+    compute_pass.dispatch_workgroups(x, y, z);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::workgroup_count;
+
+    #[test]
+    fn workgroups_cover_every_invocation() {
+        assert_eq!(workgroup_count(0, 64), 0);
+        assert_eq!(workgroup_count(1, 64), 1);
+        assert_eq!(workgroup_count(64, 64), 1);
+        assert_eq!(workgroup_count(65, 64), 2);
+    }
+}
@@ -1,6 +1,12 @@
-//! Implements the debug lines that replace your crosshair in the debug menu.
+//! Implements the debug lines that replace your crosshair in the debug menu, and the borders of the chunk the camera
+//! is in, which pipelines with the `wm_geo_chunk_borders` geometry draw as a line list of [DebugLineVertex] while
+//! [WmRenderer::chunk_borders](crate::WmRenderer::chunk_borders) is on.
 
 use bytemuck::{Pod, Zeroable};
+use cgmath::Matrix4;
+use wgpu::DepthBiasState;
+
+use crate::mc::chunk::{ChunkPos, CHUNK_HEIGHT, CHUNK_SECTION_HEIGHT, CHUNK_WIDTH};
 
 #[derive(Copy, Clone, Zeroable, Pod)]
 #[repr(C)]
@@ -33,3 +39,248 @@ pub const LINES: &[f32] = &[
     0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -0.2, 0.0, 0.0, 1.0, 0.0, 0.0, //-Z is north in mc
     0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, -0.2, 0.0, 0.0, 1.0,
 ];
+
+/// The corners of the chunk the camera is in
+const CHUNK_CORNER_COLOR: [f32; 3] = [1.0, 1.0, 0.0];
+/// The corners of its neighbours
+const NEIGHBOUR_CORNER_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+/// The boundaries between its sections
+const SECTION_COLOR: [f32; 3] = [0.25, 0.25, 1.0];
+
+/// The lines along the borders of the chunk the camera is in, like vanilla's F3 + G, relative to the chunk offset
+/// like terrain
+pub fn chunk_border_vertices(
+    camera_chunk: ChunkPos,
+    chunk_offset: ChunkPos,
+) -> Vec<DebugLineVertex> {
+    let width = CHUNK_WIDTH as f32;
+    let origin = [
+        (camera_chunk[0] - chunk_offset[0]) as f32 * width,
+        (camera_chunk[1] - chunk_offset[1]) as f32 * width,
+    ];
+
+    let mut vertices = Vec::new();
+    let mut line = |from: [f32; 3], to: [f32; 3], color: [f32; 3]| {
+        vertices.push(DebugLineVertex {
+            position: from,
+            color,
+        });
+        vertices.push(DebugLineVertex {
+            position: to,
+            color,
+        });
+    };
+
+    //Vertical lines at the corners of the chunk and of the ones around it
+    for z in -1..=2 {
+        for x in -1..=2 {
+            let inner = (0..=1).contains(&x) && (0..=1).contains(&z);
+            let corner = [origin[0] + x as f32 * width, origin[1] + z as f32 * width];
+
+            line(
+                [corner[0], 0.0, corner[1]],
+                [corner[0], CHUNK_HEIGHT as f32, corner[1]],
+                if inner {
+                    CHUNK_CORNER_COLOR
+                } else {
+                    NEIGHBOUR_CORNER_COLOR
+                },
+            );
+        }
+    }
+
+    //Horizontal lines around the chunk between its sections
+    for section in 0..=CHUNK_HEIGHT / CHUNK_SECTION_HEIGHT {
+        let y = (section * CHUNK_SECTION_HEIGHT) as f32;
+        let corners = [
+            [origin[0], y, origin[1]],
+            [origin[0] + width, y, origin[1]],
+            [origin[0] + width, y, origin[1] + width],
+            [origin[0], y, origin[1] + width],
+        ];
+
+        for (from, to) in corners.iter().zip(corners.iter().cycle().skip(1)) {
+            line(*from, *to, SECTION_COLOR);
+        }
+    }
+
+    vertices
+}
+
+/// Depth bias presets for pipelines which draw on top of existing geometry, which would otherwise Z-fight with the
+/// faces it lies on. WebGPU only biases triangles, so pipelines with a line topology, like the block outline, are
+/// moved towards the camera in their vertex shader instead, by the `wm_pc_line_depth_offset` push constant, see
+/// [DepthBiasPresets::LINE_VIEW_OFFSET]. That's the block outline and the chunk borders.
+///
+/// The presets are vanilla's `RenderPhase.POLYGON_OFFSET_LAYERING`, `glPolygonOffset(-1.0, -10.0)`. Its factor is
+/// the [slope scale](DepthBiasState::slope_scale) and its units the [constant](DepthBiasState::constant), which
+/// count in the same steps of depth as OpenGL's. They're meant for a
+/// [Depth32Float](wgpu::TextureFormat::Depth32Float) depth buffer. Negative values move the fragments towards the
+/// camera, as the depth compare function is [Less](wgpu::CompareFunction::Less). The shader graph flips them with
+/// [crate::render::reverse_z::depth_bias] if the depth is reversed.
+pub struct DepthBiasPresets;
+
+impl DepthBiasPresets {
+    /// The fraction of their distance to the camera lines are moved towards it by, like vanilla's lines which are
+    /// drawn with the view scaled by `0.99975586` (`RenderPhase.VIEW_OFFSET_Z_LAYERING`). Vanilla's lines are
+    /// several pixels wide quads, while WebGPU's are one pixel wide, so the value is calibrated by the tests below:
+    /// it's the smallest power of two which keeps a grid of lines in front of faces at 20° or more to the view, at
+    /// 1080 pixels high. Lines on faces at a flatter angle may still flicker at their edges.
+    pub const LINE_VIEW_OFFSET: f32 = 1.0 / 256.0;
+
+    /// For faces drawn over the faces they lie on, like the cracks of blocks being broken, which vanilla draws with
+    /// `RenderPhase.POLYGON_OFFSET_LAYERING`
+    pub const DECALS: DepthBiasState = DepthBiasState {
+        constant: -10,
+        slope_scale: -1.0,
+        clamp: 0.0,
+    };
+}
+
+/// What to add to the clip space depth of the vertices of lines to move them towards the camera by the fraction
+/// `view_offset` of their distance, e.g. [DepthBiasPresets::LINE_VIEW_OFFSET]. As the projection is perspective,
+/// scaling the view position by `1 - view_offset` only changes the depth, which doesn't depend on the vertex. This
+/// works the same with a reversed depth, as it's taken from the projection the lines are drawn with.
+pub fn line_depth_offset(projection: &Matrix4<f32>, view_offset: f32) -> f32 {
+    projection.w.z * view_offset / (1.0 - view_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{
+        perspective, Deg, InnerSpace, Matrix2, Matrix4, Rad, SquareMatrix, Vector2, Vector3,
+    };
+
+    use super::{chunk_border_vertices, line_depth_offset, DepthBiasPresets};
+
+    const WIDTH: f64 = 1920.0;
+    const HEIGHT: f64 = 1080.0;
+
+    fn projection() -> Matrix4<f64> {
+        perspective(Deg(70.0), WIDTH / HEIGHT, 0.05, 1000.0)
+    }
+
+    /// The pixel the point is drawn at, relative to the center of the screen
+    fn screen(projection: &Matrix4<f64>, point: Vector3<f64>) -> Vector2<f64> {
+        let clip = projection * point.extend(1.0);
+
+        Vector2::new(
+            clip.x / clip.w * WIDTH / 2.0,
+            clip.y / clip.w * HEIGHT / 2.0,
+        )
+    }
+
+    /// The depth the point is drawn with in a Depth32Float depth buffer, with the clip space depth offset by
+    /// `depth_offset` like the vertex shaders of lines do
+    fn depth(projection: &Matrix4<f32>, point: Vector3<f64>, depth_offset: f32) -> f32 {
+        let clip = projection * point.cast::<f32>().unwrap().extend(1.0);
+
+        (clip.z + depth_offset) / clip.w
+    }
+
+    /// Whether every line of a grid on a face, at the angle to the view and the distance to the camera, is drawn in
+    /// front of the face with the view offset. The lines cover the pixels up to half a pixel to either side of them,
+    /// where the depth of the face differs the most from theirs.
+    fn lines_in_front(angle: Deg<f64>, distance: f64, view_offset: f32) -> bool {
+        let projection = projection();
+        let projection_f32 = projection.cast::<f32>().unwrap();
+        let depth_offset = line_depth_offset(&projection_f32, view_offset);
+
+        let angle = Rad::from(angle).0;
+        let normal = Vector3::new(0.0, angle.cos(), angle.sin());
+        let across = Vector3::unit_x();
+        let along = normal.cross(across);
+        let center = Vector3::new(0.0, 0.0, -distance);
+
+        for x in -4..=4 {
+            for y in -4..=4 {
+                let point = center + across * x as f64 + along * y as f64;
+
+                for (line, perpendicular) in [(across, along), (along, across)] {
+                    //How the point moves on the screen when it's moved on the face
+                    let step = 1e-4;
+                    let origin = screen(&projection, point);
+                    let line_step = (screen(&projection, point + line * step) - origin) / step;
+                    let perpendicular_step =
+                        (screen(&projection, point + perpendicular * step) - origin) / step;
+                    let to_face = Matrix2::from_cols(line_step, perpendicular_step)
+                        .invert()
+                        .unwrap();
+
+                    let line_depth = depth(&projection_f32, point, depth_offset);
+                    let normal = line_step.normalize();
+                    let normal = Vector2::new(-normal.y, normal.x);
+
+                    for pixels in [-0.5, -0.25, 0.25, 0.5] {
+                        let moved = to_face * (normal * pixels);
+                        let face_point = point + line * moved.x + perpendicular * moved.y;
+
+                        if line_depth >= depth(&projection_f32, face_point, 0.0) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    #[test]
+    fn lines_are_drawn_in_front_of_faces() {
+        for angle in [90.0, 60.0, 45.0, 30.0, 20.0] {
+            for distance in [4.0, 16.0, 64.0] {
+                assert!(
+                    lines_in_front(Deg(angle), distance, DepthBiasPresets::LINE_VIEW_OFFSET),
+                    "{angle}° at {distance} blocks"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn line_view_offset_is_the_smallest_that_works() {
+        //Without an offset, the lines Z-fight with every face that isn't facing the camera
+        assert!(!lines_in_front(Deg(60.0), 16.0, 0.0));
+
+        let half = DepthBiasPresets::LINE_VIEW_OFFSET / 2.0;
+        assert!([4.0, 16.0, 64.0]
+            .into_iter()
+            .any(|distance| !lines_in_front(Deg(20.0), distance, half)));
+    }
+
+    #[test]
+    fn chunk_borders_surround_the_camera_chunk() {
+        let vertices = chunk_border_vertices([3, -2], [1, 0]);
+
+        //16 corners, and 4 edges at each of the 25 section boundaries
+        assert_eq!(vertices.len(), (16 + 4 * 25) * 2);
+
+        let (min, max) =
+            vertices
+                .iter()
+                .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), vertex| {
+                    (
+                        std::array::from_fn(|axis| min[axis].min(vertex.position[axis])),
+                        std::array::from_fn(|axis| max[axis].max(vertex.position[axis])),
+                    )
+                });
+
+        //From the far corners of the neighbours, relative to the chunk offset
+        assert_eq!(min, [16.0, 0.0, -48.0]);
+        assert_eq!(max, [64.0, 384.0, 0.0]);
+    }
+
+    #[test]
+    fn depth_offset_moves_the_view_position() {
+        let projection = projection().cast::<f32>().unwrap();
+        let point = Vector3::new(1.0, -2.0, -10.0);
+        let offset = DepthBiasPresets::LINE_VIEW_OFFSET;
+
+        let moved = depth(&projection, point * (1.0 - offset as f64), 0.0);
+        let offset_depth = depth(&projection, point, line_depth_offset(&projection, offset));
+
+        assert!((moved - offset_depth).abs() < 1e-6);
+        assert!(offset_depth < depth(&projection, point, 0.0));
+    }
+}
@@ -0,0 +1,157 @@
+//! # Entities
+//!
+//! Pipelines with the `wm_geo_entities` geometry draw the [EntityInstances] set with
+//! [EntityPipeline::set_instances], with one instanced draw of the model per kind of entity, however many of them
+//! there are. Entities whose transforms change every frame can be streamed with
+//! [EntityPipeline::stream_instances] instead, see [crate::mc::entity::stream]. The vertices are [crate::render::entity::EntityVertex], and each instance is an
+//! [EntityInstanceData] at the `instance_index`.
+//!
+//! The pipelines bind two uniforms which change between the draws, instead of being resources of the graph:
+//! - [ENTITY_INSTANCES], the `entity_instances` bind group of the instances, which has the [EntityInstanceData] at
+//!   binding 0 and the transforms of every part of every instance at binding 1, `parts` matrices per instance
+//!   starting at `first_part`, and the tint of every part of every instance at binding 2, indexed like the
+//!   transforms
+//! - [ENTITY_TEXTURE], the texture of the model
+//!
+//! [EntityInstanceData]: crate::mc::entity::EntityInstanceData
+
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use parking_lot::RwLock;
+use wgpu::RenderPass;
+
+use crate::mc::entity::culling::EntityCamera;
+use crate::mc::entity::stream::{InstanceStream, StreamedFrame};
+use crate::mc::entity::{Entity, EntityInstanceTransforms, EntityInstances};
+use crate::render::shaderpack::PipelineConfig;
+use crate::util::WmArena;
+use crate::WmRenderer;
+
+/// The uniform of a pipeline which is bound to the instances of each draw
+pub const ENTITY_INSTANCES: &str = "wm_ssbo_entity_instances";
+/// The uniform of a pipeline which is bound to the texture of the model of each draw
+pub const ENTITY_TEXTURE: &str = "wm_texture_entity";
+
+/// The entities drawn by pipelines with the `wm_geo_entities` geometry, see [crate::render::pipeline::entity]
+pub struct EntityPipeline {
+    instances: RwLock<Vec<Arc<EntityInstances>>>,
+    stream: InstanceStream,
+    streamed: RwLock<Option<StreamedFrame>>,
+    /// What the instances are culled against before they're uploaded, see [crate::mc::entity::culling]
+    pub camera: ArcSwap<Option<EntityCamera>>,
+}
+
+impl Default for EntityPipeline {
+    fn default() -> Self {
+        Self {
+            instances: RwLock::new(Vec::new()),
+            stream: InstanceStream::default(),
+            streamed: RwLock::new(None),
+            camera: ArcSwap::new(Arc::new(None)),
+        }
+    }
+}
+
+impl EntityPipeline {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the camera the instances are culled against, or [None] to draw all of them. The shader graph sets it to
+    /// the camera of the frame it's drawing, so instances uploaded in between are culled against the last frame's.
+    pub fn set_camera(&self, camera: Option<EntityCamera>) {
+        self.camera.store(Arc::new(camera));
+    }
+
+    /// Replaces the entities which are drawn, with a draw for each of the [EntityInstances]. Only the ones which have
+    /// been [uploaded](EntityInstances::upload) are drawn, as they were last uploaded.
+    pub fn set_instances(&self, instances: Vec<Arc<EntityInstances>>) {
+        *self.instances.write() = instances;
+    }
+
+    /// Replaces the entities which are streamed, drawn along with the [EntityInstances] which are set. The instances
+    /// of every model which are in view are written into the next buffers of the [InstanceStream], with the LODs of
+    /// the model picked by their distance, and drawn with one bind group. While the renderer is paused the entities
+    /// which were streamed last are kept.
+    pub fn stream_instances<'a>(
+        &self,
+        wm: &WmRenderer,
+        models: impl IntoIterator<Item = (Arc<Entity>, &'a [EntityInstanceTransforms])>,
+    ) {
+        if wm.is_paused() {
+            return;
+        }
+
+        let camera = **self.camera.load();
+
+        *self.streamed.write() = self.stream.upload(wm, camera.as_ref(), models);
+    }
+
+    /// Draws the entities with the pipeline which is set, after its other uniforms have been bound
+    pub fn render<'pass>(
+        &self,
+        config: &PipelineConfig,
+        arena: &WmArena<'pass>,
+        render_pass: &mut RenderPass<'pass>,
+    ) {
+        let uniform_index = |name: &str| {
+            config
+                .uniforms
+                .iter()
+                .find(|(_, uniform)| *uniform == name)
+                .map(|(index, _)| *index as u32)
+        };
+
+        let instances_index = uniform_index(ENTITY_INSTANCES);
+        let texture_index = uniform_index(ENTITY_TEXTURE);
+
+        if let Some(streamed) = &*self.streamed.read() {
+            let streamed = arena.alloc(streamed.clone());
+
+            if let Some(index) = instances_index {
+                render_pass.set_bind_group(index, &streamed.bind_group, &[]);
+            }
+
+            //The batches are sorted by texture, so it's only bound when it changes
+            let mut bound_texture = None;
+
+            for batch in &streamed.batches {
+                if let Some(index) = texture_index {
+                    if bound_texture != Some(Arc::as_ptr(&batch.entity.texture)) {
+                        let texture = arena.alloc(batch.entity.texture.load_full());
+
+                        render_pass.set_bind_group(index, &texture.bind_group, &[]);
+                        bound_texture = Some(Arc::as_ptr(&batch.entity.texture));
+                    }
+                }
+
+                render_pass.set_vertex_buffer(0, batch.entity.mesh.slice(..));
+                render_pass.draw(0..batch.entity.vertices, batch.instances.clone());
+            }
+        }
+
+        for instances in self.instances.read().iter() {
+            let uploaded = match &*instances.uploaded.read() {
+                Some(uploaded) if uploaded.count > 0 => uploaded.clone(),
+                _ => continue,
+            };
+            let entity = arena.alloc(instances.entity.clone());
+            let uploaded = arena.alloc(uploaded);
+
+            if let Some(index) = instances_index {
+                render_pass.set_bind_group(index, &uploaded.bind_group, &[]);
+            }
+
+            if let Some(index) = texture_index {
+                let texture = arena.alloc(entity.texture.load_full());
+
+                render_pass.set_bind_group(index, &texture.bind_group, &[]);
+            }
+
+            render_pass.set_vertex_buffer(0, entity.mesh.slice(..));
+            render_pass.draw(0..entity.vertices, 0..uploaded.count);
+        }
+    }
+}
@@ -0,0 +1,471 @@
+//! The round shadows under entities, set with [crate::mc::MinecraftState::set_entity_shadows] and drawn by pipelines
+//! with the `wm_geo_entity_shadows` geometry as a triangle list of [ShadowVertex].
+//!
+//! Like vanilla's, a shadow is the `misc/shadow` texture laid onto the tops of the full blocks underneath the entity,
+//! within its radius. It fades out the further the block is below the entity, and isn't cast onto anything which
+//! isn't a full block. The pipeline is given [DepthBiasPresets::DECALS] so that the shadows don't Z-fight with the
+//! terrain underneath, and is meant to be alpha blended without writing depth.
+//!
+//! The name tags over entities are set with [crate::mc::MinecraftState::set_name_tags] and drawn by pipelines with
+//! the `wm_geo_name_tags` geometry as a triangle list of [NameTagVertex]. Like vanilla's, a tag is a line of text in
+//! the [crate::render::font] on a translucent black background, facing the same way as the camera. Its vertices
+//! are made when it's drawn, as they depend on the camera.
+//!
+//! [DepthBiasPresets::DECALS]: crate::render::pipeline::debug_lines::DepthBiasPresets::DECALS
+
+use bytemuck::{Pod, Zeroable};
+
+use crate::mc::block::{BlockShape, ChunkBlockState};
+use crate::mc::chunk::{BlockStateProvider, ChunkPos};
+use crate::mc::entity::Position;
+use crate::mc::resource::ResourcePath;
+use crate::mc::{BlockManager, MinecraftState};
+use crate::render::atlas::AtlasKind;
+use crate::render::font::{font_texture, Font, GLYPH_SIZE};
+
+/// The size of a pixel of a name tag's font, in blocks
+pub const NAME_TAG_SCALE: f32 = 0.025;
+
+/// The color of the background of name tags, like vanilla's default text background opacity
+const NAME_TAG_BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.25];
+
+/// The largest radius of a shadow, like vanilla's
+pub const MAX_SHADOW_RADIUS: f32 = 32.0;
+
+/// The block atlas texture shadows are drawn with
+pub fn shadow_texture() -> ResourcePath {
+    ResourcePath("minecraft:misc/shadow".into())
+}
+
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+pub struct ShadowVertex {
+    pub position: [f32; 3],
+    /// In the block atlas
+    pub tex_coords: [f32; 2],
+    pub alpha: f32,
+}
+
+impl ShadowVertex {
+    const VAA: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ShadowVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// The shadow of an entity instance. Instances without one, like those of invisible entities, aren't given one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EntityShadow {
+    /// Of the feet of the entity
+    pub position: Position,
+    /// In blocks, like half the width of most mobs
+    pub radius: f32,
+    /// From 0 to 1, vanilla makes it darker the closer the camera is
+    pub opacity: f32,
+}
+
+/// The part of a shadow on the top of one block, in world coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ShadowQuad {
+    /// The X and Z the quad covers
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub y: f32,
+    /// Where the corners lie on the shadow texture, from 0 to 1
+    pub min_uv: [f32; 2],
+    pub max_uv: [f32; 2],
+    pub alpha: f32,
+}
+
+impl EntityShadow {
+    /// The quads the shadow is made of, on the tops of the full blocks at most a block below the entity
+    pub fn quads(
+        &self,
+        block_manager: &BlockManager,
+        provider: &dyn BlockStateProvider,
+    ) -> Vec<ShadowQuad> {
+        let radius = self.radius.min(MAX_SHADOW_RADIUS);
+
+        if radius <= 0.0 || self.opacity <= 0.0 {
+            return Vec::new();
+        }
+
+        let (x, y, z) = self.position;
+        let (min_x, max_x) = (x - radius, x + radius);
+        let (min_z, max_z) = (z - radius, z + radius);
+
+        let mut quads = Vec::new();
+
+        for block_x in min_x.floor() as i32..=max_x.floor() as i32 {
+            for block_z in min_z.floor() as i32..=max_z.floor() as i32 {
+                for block_y in (y - 1.0).floor() as i32 - 1..(y.floor() as i32) {
+                    let full_cube = match provider.get_state(block_x, block_y as i16, block_z) {
+                        ChunkBlockState::Air => false,
+                        ChunkBlockState::State(key) => {
+                            *block_manager.get_shape(key.block) == BlockShape::FullCube
+                        }
+                    };
+
+                    if !full_cube {
+                        continue;
+                    }
+
+                    let top = (block_y + 1) as f32;
+                    let alpha = ((self.opacity - (y - top) / 2.0) * 0.5).min(1.0);
+
+                    if alpha <= 0.0 {
+                        continue;
+                    }
+
+                    let min = [(block_x as f32).max(min_x), (block_z as f32).max(min_z)];
+                    let max = [
+                        ((block_x + 1) as f32).min(max_x),
+                        ((block_z + 1) as f32).min(max_z),
+                    ];
+                    let uv = |[quad_x, quad_z]: [f32; 2]| {
+                        [
+                            (quad_x - min_x) / (2.0 * radius),
+                            (quad_z - min_z) / (2.0 * radius),
+                        ]
+                    };
+
+                    quads.push(ShadowQuad {
+                        min,
+                        max,
+                        y: top,
+                        min_uv: uv(min),
+                        max_uv: uv(max),
+                        alpha,
+                    });
+                }
+            }
+        }
+
+        quads
+    }
+}
+
+#[derive(Copy, Clone, Debug, Zeroable, Pod)]
+#[repr(C)]
+pub struct NameTagVertex {
+    pub position: [f32; 3],
+    /// In the block atlas, negative for the background, which isn't textured
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
+}
+
+impl NameTagVertex {
+    const VAA: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4
+    ];
+
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<NameTagVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// The name tag over an entity instance. Instances without one, like most mobs, aren't given one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NameTag {
+    /// Of the middle of the top of the tag, vanilla puts it half a block above the entity's head
+    pub position: Position,
+    pub text: String,
+}
+
+/// A quad of a name tag, in world coordinates
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NameTagQuad {
+    /// Top left, top right, bottom right and bottom left as seen from the camera
+    pub corners: [[f32; 3]; 4],
+    /// The corners of the glyph in the font texture, from 0 to 1, or [None] for the background
+    pub uv: Option<([f32; 2], [f32; 2])>,
+    pub color: [f32; 4],
+}
+
+impl NameTag {
+    /// The background and then the glyphs of the tag, laid out along the `right` and `up` of the camera in world
+    /// space. Like vanilla, the background reaches a pixel past the text on the left and top.
+    #[must_use]
+    pub fn quads(&self, font: &Font, right: [f32; 3], up: [f32; 3]) -> Vec<NameTagQuad> {
+        let left = -font.width(&self.text) / 2.0;
+
+        //From font pixels, with y growing downwards from the top of the tag
+        let corner = |x: f32, y: f32| {
+            let (origin_x, origin_y, origin_z) = self.position;
+            let (x, y) = (x * NAME_TAG_SCALE, -y * NAME_TAG_SCALE);
+
+            [
+                origin_x + right[0] * x + up[0] * y,
+                origin_y + right[1] * x + up[1] * y,
+                origin_z + right[2] * x + up[2] * y,
+            ]
+        };
+        let quad = |min_x: f32, min_y: f32, max_x: f32, max_y: f32| {
+            [
+                corner(min_x, min_y),
+                corner(max_x, min_y),
+                corner(max_x, max_y),
+                corner(min_x, max_y),
+            ]
+        };
+
+        let background = NameTagQuad {
+            corners: quad(
+                left - 1.0,
+                -1.0,
+                left + font.width(&self.text),
+                GLYPH_SIZE + 1.0,
+            ),
+            uv: None,
+            color: NAME_TAG_BACKGROUND,
+        };
+
+        let glyphs = font.glyphs(&self.text).into_iter().map(|glyph| {
+            let x = left + glyph.x;
+
+            NameTagQuad {
+                corners: quad(x, 0.0, x + GLYPH_SIZE, GLYPH_SIZE),
+                uv: Some((glyph.min_uv, glyph.max_uv)),
+                color: [1.0; 4],
+            }
+        });
+
+        [background].into_iter().chain(glyphs).collect()
+    }
+}
+
+/// The vertices of every name tag, relative to the chunk offset like terrain, facing the camera of the view matrix
+pub fn name_tag_vertices(
+    mc: &MinecraftState,
+    view: [[f32; 4]; 4],
+    chunk_offset: ChunkPos,
+) -> Vec<NameTagVertex> {
+    let name_tags = mc.name_tags.load();
+    let font = mc.font.read();
+
+    let font = match &*font {
+        Some(font) if !name_tags.is_empty() => font,
+        _ => return Vec::new(),
+    };
+
+    let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+    let atlas_size = block_atlas.size() as f32;
+    let ((texture_min_x, texture_min_y), (texture_max_x, texture_max_y)) =
+        match block_atlas.uv_map.read().get(&font_texture()) {
+            Some(&uv) => uv,
+            None => return Vec::new(),
+        };
+
+    //The rows of the rotation of the view matrix are the camera's axes in world space
+    let right = [view[0][0], view[1][0], view[2][0]];
+    let up = [view[0][1], view[1][1], view[2][1]];
+
+    let offset = [(chunk_offset[0] * 16) as f32, (chunk_offset[1] * 16) as f32];
+
+    name_tags
+        .iter()
+        .flat_map(|name_tag| name_tag.quads(font, right, up))
+        .flat_map(|quad| {
+            let vertex = |[x, y, z]: [f32; 3], [u, v]: [f32; 2]| NameTagVertex {
+                position: [x - offset[0], y, z - offset[1]],
+                tex_coords: match quad.uv {
+                    Some(_) => [
+                        (texture_min_x + (texture_max_x - texture_min_x) * u) / atlas_size,
+                        (texture_min_y + (texture_max_y - texture_min_y) * v) / atlas_size,
+                    ],
+                    None => [-1.0, -1.0],
+                },
+                color: quad.color,
+            };
+
+            let ([min_u, min_v], [max_u, max_v]) = quad.uv.unwrap_or(([0.0; 2], [0.0; 2]));
+            let [top_left, top_right, bottom_right, bottom_left] = quad.corners;
+
+            let a = vertex(top_left, [min_u, min_v]);
+            let b = vertex(top_right, [max_u, min_v]);
+            let c = vertex(bottom_right, [max_u, max_v]);
+            let d = vertex(bottom_left, [min_u, max_v]);
+
+            //Facing the camera
+            [a, d, c, a, c, b]
+        })
+        .collect()
+}
+
+/// The vertices of every shadow, relative to the chunk offset like terrain
+pub fn shadow_vertices(mc: &MinecraftState, chunk_offset: ChunkPos) -> Vec<ShadowVertex> {
+    let quads = mc.entity_shadows.load();
+
+    if quads.is_empty() {
+        return Vec::new();
+    }
+
+    let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+    let atlas_size = block_atlas.size() as f32;
+    let ((texture_min_x, texture_min_y), (texture_max_x, texture_max_y)) =
+        match block_atlas.uv_map.read().get(&shadow_texture()) {
+            Some(&uv) => uv,
+            None => return Vec::new(),
+        };
+
+    let offset = [(chunk_offset[0] * 16) as f32, (chunk_offset[1] * 16) as f32];
+
+    quads
+        .iter()
+        .flat_map(|quad| {
+            let vertex = |[x, z]: [f32; 2], [u, v]: [f32; 2]| ShadowVertex {
+                position: [x - offset[0], quad.y, z - offset[1]],
+                tex_coords: [
+                    (texture_min_x + (texture_max_x - texture_min_x) * u) / atlas_size,
+                    (texture_min_y + (texture_max_y - texture_min_y) * v) / atlas_size,
+                ],
+                alpha: quad.alpha,
+            };
+
+            let [min_x, min_z] = quad.min;
+            let [max_x, max_z] = quad.max;
+            let [min_u, min_v] = quad.min_uv;
+            let [max_u, max_v] = quad.max_uv;
+
+            let a = vertex([min_x, min_z], [min_u, min_v]);
+            let b = vertex([max_x, min_z], [max_u, min_v]);
+            let c = vertex([max_x, max_z], [max_u, max_v]);
+            let d = vertex([min_x, max_z], [min_u, max_v]);
+
+            //Facing up
+            [a, d, c, a, c, b]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::{Debug, Formatter};
+
+    use image::{Rgba, RgbaImage};
+    use indexmap::IndexMap;
+
+    use super::{EntityShadow, NameTag, NAME_TAG_SCALE};
+    use crate::mc::biome::BlockColors;
+    use crate::mc::block::{BlockShape, BlockstateKey, ChunkBlockState, Direction};
+    use crate::mc::chunk::BlockStateProvider;
+    use crate::mc::BlockManager;
+    use crate::render::font::Font;
+
+    /// A floor of full blocks at y = 63, with a slab at (1, 63, 0)
+    struct Floor;
+
+    impl Debug for Floor {
+        fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+            f.write_str("Floor")
+        }
+    }
+
+    impl BlockStateProvider for Floor {
+        fn get_state(&self, x: i32, y: i16, z: i32) -> ChunkBlockState {
+            match (x, y, z) {
+                (1, 63, 0) => ChunkBlockState::State(BlockstateKey {
+                    block: 1,
+                    augment: 0,
+                }),
+                (_, 63, _) => ChunkBlockState::State(BlockstateKey {
+                    block: 0,
+                    augment: 0,
+                }),
+                _ => ChunkBlockState::Air,
+            }
+        }
+
+        fn is_section_empty(&self, _index: usize) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn shadows_cover_the_full_blocks_underneath() {
+        let block_manager = BlockManager {
+            blocks: IndexMap::new(),
+            shapes: [(1, BlockShape::HalfSlab(Direction::Down))].into(),
+            colors: BlockColors::default(),
+        };
+
+        let shadow = EntityShadow {
+            position: (0.5, 64.0, 0.5),
+            radius: 0.75,
+            opacity: 1.0,
+        };
+
+        let quads = shadow.quads(&block_manager, &Floor);
+
+        //The radius reaches into the 8 neighbours of the block, but not onto the slab
+        assert_eq!(quads.len(), 8);
+        assert!(quads.iter().all(|quad| quad.y == 64.0 && quad.alpha == 0.5));
+
+        let center = quads.iter().find(|quad| quad.min == [0.0, 0.0]).unwrap();
+        assert_eq!(center.max, [1.0, 1.0]);
+        assert_eq!(center.min_uv, [0.25 / 1.5, 0.25 / 1.5]);
+
+        //Further below, the shadow fades out
+        let higher = EntityShadow {
+            position: (0.5, 64.9, 0.5),
+            ..shadow
+        };
+        assert!(higher
+            .quads(&block_manager, &Floor)
+            .iter()
+            .all(|quad| quad.alpha < 0.5));
+    }
+
+    #[test]
+    fn name_tags_are_centered_and_face_the_camera() {
+        //Every glyph is 5 pixels wide, so advances 6
+        let font = Font::from_image(&RgbaImage::from_fn(128, 128, |x, _| {
+            Rgba([255, 255, 255, if x % 8 < 5 { 255 } else { 0 }])
+        }));
+
+        let name_tag = NameTag {
+            position: (0.0, 66.0, 0.0),
+            text: "Alex".into(),
+        };
+
+        //Looking along -z, right is +x and up is +y
+        let quads = name_tag.quads(&font, [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]);
+        assert_eq!(quads.len(), 5);
+
+        let background = quads[0];
+        assert_eq!(background.uv, None);
+        assert_eq!(
+            background.corners[0],
+            [-13.0 * NAME_TAG_SCALE, 66.0 + NAME_TAG_SCALE, 0.0]
+        );
+        assert_eq!(
+            background.corners[2],
+            [12.0 * NAME_TAG_SCALE, 66.0 - 9.0 * NAME_TAG_SCALE, 0.0]
+        );
+
+        //The glyphs start at the left of the text, a glyph cell apart
+        assert_eq!(quads[1].corners[0], [-12.0 * NAME_TAG_SCALE, 66.0, 0.0]);
+        assert_eq!(quads[2].corners[0], [-6.0 * NAME_TAG_SCALE, 66.0, 0.0]);
+
+        //Looking along +x, right is +z
+        let turned = name_tag.quads(&font, [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]);
+        assert_eq!(turned[1].corners[0], [0.0, 66.0, -12.0 * NAME_TAG_SCALE]);
+    }
+}
@@ -0,0 +1,345 @@
+//! # First person
+//!
+//! The player's arm and the items in their hands are drawn by pipelines with the `wm_geo_first_person` geometry,
+//! after the world and into a depth texture of their own, so they never clip into the blocks the player stands
+//! against. They're drawn in view space with [FIRST_PERSON_PROJECTION] rather than the projection of the world,
+//! whose FOV changes with sprinting and the FOV setting, while the hand keeps vanilla's 70 degrees unless
+//! [FirstPerson::set_fov] says otherwise.
+//!
+//! The frontend sets what's in each hand with [FirstPerson::set_item], and drives the animations with
+//! [FirstPerson::set_swing_progress] and [FirstPerson::set_equip_progress], interpolated with the partial tick like
+//! vanilla's `HeldItemRenderer` does. Items are drawn with their `firstperson_righthand` and
+//! `firstperson_lefthand` display transforms, and the [FirstPerson::set_arm] model is drawn in the main hand when it's
+//! empty. The instances are streamed every frame with the [EntityPipeline] of the pass, so the pipelines bind the
+//! same uniforms as those of the `wm_geo_entities` geometry.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use arc_swap::ArcSwap;
+use cgmath::{perspective, Deg, Matrix4, Vector3};
+use parking_lot::RwLock;
+
+use crate::mc::entity::{Entity, EntityInstanceTransforms};
+use crate::mc::item::ItemModel;
+use crate::mc::item_entity::item_entity;
+use crate::render::atlas::AtlasKind;
+use crate::render::pipeline::entity::EntityPipeline;
+use crate::render::reverse_z::reverse_z_projection;
+use crate::WmRenderer;
+
+/// The resource of the projection the first person pipelines are drawn with, written by the graph every frame
+pub const FIRST_PERSON_PROJECTION: &str = "wm_mat4_first_person_projection";
+
+/// Vanilla's FOV for the hand, which doesn't change with the FOV setting
+pub const DEFAULT_FIRST_PERSON_FOV: f32 = 70.0;
+
+const NEAR_PLANE: f32 = 0.05;
+const FAR_PLANE: f32 = 100.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Arm {
+    Right,
+    Left,
+}
+
+impl Arm {
+    /// Mirrors the offsets of the left arm
+    fn sign(self) -> f32 {
+        match self {
+            Arm::Right => 1.0,
+            Arm::Left => -1.0,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Arm::Right => 0,
+            Arm::Left => 1,
+        }
+    }
+
+    /// The display context of the items held in the arm
+    fn context(self) -> &'static str {
+        match self {
+            Arm::Right => "firstperson_righthand",
+            Arm::Left => "firstperson_lefthand",
+        }
+    }
+}
+
+/// What one of the hands holds and how it's animated
+#[derive(Clone, Debug, PartialEq)]
+pub struct HandState {
+    /// Like `minecraft:stick`, see [crate::mc::item::ItemManager], or [None] if the hand is empty
+    pub item: Option<String>,
+    /// From 0 when the swing starts to 1 when it ends, like vanilla's `getHandSwingProgress`
+    pub swing_progress: f32,
+    /// From 0 when the hand is lowered out of view to 1 when it's fully raised, like vanilla's `equipProgress`
+    pub equip_progress: f32,
+}
+
+impl Default for HandState {
+    fn default() -> Self {
+        Self {
+            item: None,
+            swing_progress: 0.0,
+            equip_progress: 1.0,
+        }
+    }
+}
+
+/// The model of the player's arm, drawn in the main hand when it's empty
+#[derive(Clone, Debug)]
+pub struct ArmModel {
+    pub entity: Arc<Entity>,
+    /// Where the player's skin is in the texture of the model, like the `uv_offset` of an entity instance
+    pub uv_offset: (f32, f32),
+}
+
+/// Offsets the held item like vanilla's `applyEquipOffset` and `applySwingOffset`, in view space
+fn item_matrix(arm: Arm, hand: &HandState) -> Matrix4<f32> {
+    let sign = arm.sign();
+    let swing = hand.swing_progress.clamp(0.0, 1.0);
+    let lowered = 1.0 - hand.equip_progress.clamp(0.0, 1.0);
+    let swing_root = swing.sqrt() * std::f32::consts::PI;
+
+    let swing_translation = Matrix4::from_translation(Vector3::new(
+        sign * -0.4 * swing_root.sin(),
+        0.2 * (swing_root * 2.0).sin(),
+        -0.2 * (swing * std::f32::consts::PI).sin(),
+    ));
+
+    let equip = Matrix4::from_translation(Vector3::new(sign * 0.56, -0.52 + lowered * -0.6, -0.72));
+
+    let squared = (swing * swing * std::f32::consts::PI).sin();
+    let root = swing_root.sin();
+
+    swing_translation
+        * equip
+        * Matrix4::from_angle_y(Deg(sign * (45.0 + squared * -20.0)))
+        * Matrix4::from_angle_z(Deg(sign * root * -20.0))
+        * Matrix4::from_angle_x(Deg(root * -80.0))
+        * Matrix4::from_angle_y(Deg(sign * -45.0))
+}
+
+/// Places the empty arm like vanilla's `renderArmHoldingItem`, in view space
+fn arm_matrix(arm: Arm, hand: &HandState) -> Matrix4<f32> {
+    let sign = arm.sign();
+    let swing = hand.swing_progress.clamp(0.0, 1.0);
+    let lowered = 1.0 - hand.equip_progress.clamp(0.0, 1.0);
+    let swing_root = swing.sqrt() * std::f32::consts::PI;
+
+    let translation = Matrix4::from_translation(Vector3::new(
+        sign * (-0.3 * swing_root.sin() + 0.64),
+        0.4 * (swing_root * 2.0).sin() - 0.6 + lowered * -0.6,
+        -0.4 * (swing * std::f32::consts::PI).sin() - 0.72,
+    ));
+
+    let squared = (swing * swing * std::f32::consts::PI).sin();
+    let root = swing_root.sin();
+
+    translation
+        * Matrix4::from_angle_y(Deg(sign * 45.0))
+        * Matrix4::from_angle_y(Deg(sign * root * 70.0))
+        * Matrix4::from_angle_z(Deg(sign * squared * -20.0))
+        * Matrix4::from_translation(Vector3::new(sign * -1.0, 3.6, 3.5))
+        * Matrix4::from_angle_z(Deg(sign * 120.0))
+        * Matrix4::from_angle_x(Deg(200.0))
+        * Matrix4::from_angle_y(Deg(sign * -135.0))
+        * Matrix4::from_translation(Vector3::new(sign * 5.6, 0.0, 0.0))
+}
+
+/// The instance of a model drawn in view space with the matrix
+fn view_instance(matrix: Matrix4<f32>, uv_offset: (f32, f32)) -> EntityInstanceTransforms {
+    EntityInstanceTransforms {
+        position: (0.0, 0.0, 0.0),
+        looking_yaw: 0.0,
+        uv_offset,
+        overlay: [0.0; 4],
+        part_transforms: Vec::new(),
+        part_tints: Vec::new(),
+        attached_to: Some(matrix.into()),
+    }
+}
+
+/// The arm and held items drawn in first person, see [crate::render::pipeline::first_person]
+pub struct FirstPerson {
+    /// In degrees
+    pub fov: ArcSwap<f32>,
+    pub main_arm: ArcSwap<Arm>,
+    hands: RwLock<[HandState; 2]>,
+    arm: ArcSwap<Option<ArmModel>>,
+    /// The model of each item in each arm's display context, along with the baked model it was made from
+    items: RwLock<HashMap<(String, Arm), (Arc<ItemModel>, Arc<Entity>)>>,
+    /// Draws the streamed instances of the frame
+    pub entities: EntityPipeline,
+}
+
+impl Default for FirstPerson {
+    fn default() -> Self {
+        Self {
+            fov: ArcSwap::new(Arc::new(DEFAULT_FIRST_PERSON_FOV)),
+            main_arm: ArcSwap::new(Arc::new(Arm::Right)),
+            hands: RwLock::new(Default::default()),
+            arm: ArcSwap::new(Arc::new(None)),
+            items: RwLock::new(HashMap::new()),
+            entities: EntityPipeline::new(),
+        }
+    }
+}
+
+impl FirstPerson {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the FOV of [FIRST_PERSON_PROJECTION], in degrees
+    pub fn set_fov(&self, fov: f32) {
+        self.fov.store(Arc::new(fov));
+    }
+
+    /// Sets the arm the main hand is, like vanilla's main hand option
+    pub fn set_main_arm(&self, arm: Arm) {
+        self.main_arm.store(Arc::new(arm));
+    }
+
+    /// Sets the model drawn in the main hand when it's empty, or [None] to draw nothing then
+    pub fn set_arm(&self, arm: Option<ArmModel>) {
+        self.arm.store(Arc::new(arm));
+    }
+
+    /// Sets the item held in the arm, or [None] if it's empty
+    pub fn set_item(&self, arm: Arm, item: Option<String>) {
+        self.hands.write()[arm.index()].item = item;
+    }
+
+    /// Sets how far along the swing of the arm is, from 0 to 1
+    pub fn set_swing_progress(&self, arm: Arm, progress: f32) {
+        self.hands.write()[arm.index()].swing_progress = progress;
+    }
+
+    /// Sets how far the arm is raised, from 0 when it's out of view to 1, e.g. while switching items
+    pub fn set_equip_progress(&self, arm: Arm, progress: f32) {
+        self.hands.write()[arm.index()].equip_progress = progress;
+    }
+
+    #[must_use]
+    pub fn hand(&self, arm: Arm) -> HandState {
+        self.hands.read()[arm.index()].clone()
+    }
+
+    /// The projection of the hand for a surface with the aspect ratio
+    #[must_use]
+    pub fn projection(&self, aspect: f32, reverse_z: bool) -> Matrix4<f32> {
+        let projection = perspective(Deg(**self.fov.load()), aspect, NEAR_PLANE, FAR_PLANE);
+
+        if reverse_z {
+            reverse_z_projection(projection)
+        } else {
+            projection
+        }
+    }
+
+    /// The model of the item in the arm's display context, if the item has been baked
+    fn item(&self, wm: &WmRenderer, item: &str, arm: Arm) -> Option<Arc<Entity>> {
+        let model = wm.mc.item_manager.read().get(item)?;
+        let texture = wm.mc.texture_manager.bindable_texture(AtlasKind::Block);
+        let key = (item.to_string(), arm);
+
+        if let Some((baked, entity)) = self.items.read().get(&key) {
+            if Arc::ptr_eq(baked, &model) && Arc::ptr_eq(&entity.texture.load(), &texture) {
+                return Some(entity.clone());
+            }
+        }
+
+        let entity = Arc::new(item_entity(wm, item, &model, arm.context(), texture)?);
+
+        self.items.write().insert(key, (model, entity.clone()));
+
+        Some(entity)
+    }
+
+    /// Streams the instances of the arm and the held items for the frame
+    pub fn prepare(&self, wm: &WmRenderer) {
+        let hands = self.hands.read().clone();
+        let main_arm = **self.main_arm.load();
+        let mut models: Vec<(Arc<Entity>, Vec<EntityInstanceTransforms>)> = Vec::new();
+
+        for arm in [Arm::Right, Arm::Left] {
+            let hand = &hands[arm.index()];
+
+            match &hand.item {
+                Some(item) => {
+                    if let Some(entity) = self.item(wm, item, arm) {
+                        models.push((
+                            entity,
+                            vec![view_instance(item_matrix(arm, hand), (0.0, 0.0))],
+                        ));
+                    }
+                }
+                //Like vanilla, only the main hand is drawn when it's empty
+                None if arm == main_arm => {
+                    if let Some(model) = &**self.arm.load() {
+                        models.push((
+                            model.entity.clone(),
+                            vec![view_instance(arm_matrix(arm, hand), model.uv_offset)],
+                        ));
+                    }
+                }
+                None => {}
+            }
+        }
+
+        self.entities.stream_instances(
+            wm,
+            models
+                .iter()
+                .map(|(entity, instances)| (entity.clone(), &instances[..])),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Matrix4, Vector4};
+
+    use super::{item_matrix, Arm, HandState};
+
+    fn held_at(matrix: Matrix4<f32>) -> Vector4<f32> {
+        matrix * Vector4::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    #[test]
+    fn items_are_lowered_and_mirrored() {
+        let raised = HandState::default();
+        let right = held_at(item_matrix(Arm::Right, &raised));
+        let left = held_at(item_matrix(Arm::Left, &raised));
+
+        //In front of the camera, to the side of the arm
+        assert!(right.z < 0.0);
+        assert!((right.x - 0.56).abs() < 1e-5);
+        assert!((left.x + right.x).abs() < 1e-5);
+
+        let lowered = held_at(item_matrix(
+            Arm::Right,
+            &HandState {
+                equip_progress: 0.0,
+                ..HandState::default()
+            },
+        ));
+        assert!((lowered.y - (right.y - 0.6)).abs() < 1e-5);
+
+        //Back where it started at the end of a swing
+        let swung = item_matrix(
+            Arm::Right,
+            &HandState {
+                swing_progress: 1.0,
+                ..HandState::default()
+            },
+        );
+        let difference = held_at(swung) - right;
+        assert!(difference.magnitude2() < 1e-6);
+    }
+}
@@ -1,25 +1,40 @@
+pub mod beam;
+pub mod block_breaking;
+pub mod block_outline;
+pub mod compute;
 pub mod debug_lines;
+pub mod entity;
+pub mod entity_shadow;
+pub mod first_person;
 
-use crate::render::shader::WmShader;
+use crate::render::shader::{ShaderError, ShaderSource, WmShader};
 use wgpu::{BindGroupLayout, ComputePipeline, PipelineLayout, SamplerBindingType};
 
 use crate::mc::chunk::RenderLayer;
 use arc_swap::ArcSwap;
-use parking_lot::RwLock;
+use cgmath::{InnerSpace, Vector3};
+use parking_lot::{Mutex, RwLock};
+use std::any::Any;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 
 use crate::WmRenderer;
 
-use crate::mc::resource::ResourceProvider;
+use crate::mc::resource::{ResourcePath, ResourceProvider};
 
 use crate::wgpu::RenderPipeline;
 
 pub const BLOCK_ATLAS: &str = "wgpu_mc:atlases/block";
 pub const ENTITY_ATLAS: &str = "wgpu_mc:atlases/entity";
+pub const PARTICLE_ATLAS: &str = "wgpu_mc:atlases/particle";
+pub const GUI_ATLAS: &str = "wgpu_mc:atlases/gui";
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_derive::Serialize, serde_derive::Deserialize)
+)]
 pub struct Vertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
@@ -28,19 +43,76 @@ pub struct Vertex {
     pub color: [f32; 4],
     pub tangent: [f32; 4],
     pub uv_offset: u32,
+    /// The texture coordinates of the emissive overlay, which shaders add on top without the lightmap, or
+    /// [crate::mc::block::NO_EMISSIVE]
+    pub emissive_tex_coords: [f32; 2],
+    /// The region of the atlas the texture repeats in, as the min and max texture coordinates, for quads which
+    /// repeat it, see [crate::mc::chunk::ShadedVertex::repeat_texture]. Their `tex_coords` are relative to the
+    /// region and keep going past 1, and the terrain shaders wrap them into it. Empty for every other vertex.
+    pub region: [f32; 4],
 }
 
 impl Vertex {
-    const VAA: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+    //Location 7 is the ChunkInstance
+    const VAA: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
         0 => Float32x3,
         1 => Float32x2,
         2 => Float32x2,
         3 => Float32x4,
         4 => Float32x4,
         5 => Float32x4,
-        6 => Uint32
+        6 => Uint32,
+        8 => Float32x2,
+        9 => Float32x4
     ];
 
+    /// The tangent of a face along the direction its u texture coordinate grows in, from its first triangle, with
+    /// the handedness of the bitangent in w. Normal maps are sampled in this space with the `PBR` feature. Zero if
+    /// the texture coordinates of the face don't span an area.
+    #[must_use]
+    pub fn face_tangent(face: &[Vertex]) -> [f32; 4] {
+        let [a, b, c] = match face {
+            [a, b, c, ..] => [a, b, c],
+            _ => return [0.0; 4],
+        };
+
+        let edge = |to: &Vertex| Vector3::from(to.position) - Vector3::from(a.position);
+        let (edge1, edge2) = (edge(b), edge(c));
+        let (du1, dv1) = (
+            b.tex_coords[0] - a.tex_coords[0],
+            b.tex_coords[1] - a.tex_coords[1],
+        );
+        let (du2, dv2) = (
+            c.tex_coords[0] - a.tex_coords[0],
+            c.tex_coords[1] - a.tex_coords[1],
+        );
+
+        let determinant = du1 * dv2 - du2 * dv1;
+
+        if determinant.abs() <= f32::EPSILON {
+            return [0.0; 4];
+        }
+
+        let tangent = (edge1 * dv2 - edge2 * dv1) / determinant;
+        let bitangent = (edge2 * du1 - edge1 * du2) / determinant;
+        let normal = Vector3::new(a.normal[0], a.normal[1], a.normal[2]);
+
+        let tangent = tangent.normalize();
+        let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+
+        [tangent.x, tangent.y, tangent.z, handedness]
+    }
+
+    /// Whether the texture coordinates are relative to the [Vertex::region], which is the case if it isn't empty
+    #[must_use]
+    pub fn repeats_texture(&self) -> bool {
+        self.region[2] > self.region[0] || self.region[3] > self.region[1]
+    }
+
     #[must_use]
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
@@ -52,6 +124,151 @@ impl Vertex {
     }
 }
 
+/// A compact alternative to [Vertex] for chunk meshes, about a third of the size. Only the tangent is dropped, so
+/// it doesn't work with the `PBR` feature. Terrain shaders are compiled with [ChunkVertexFormat::PACKED_FEATURE]
+/// defined and decode the position with `vec3<f32>(position.xyz) / 64.0 - 8.0`, and the texture coordinates of
+/// quads which repeat their texture with `tex_coords * 16.0`, see [PackedVertex::REPEAT_SCALE]. The other
+/// attributes are normalized by the GPU into the same types as the ones of [Vertex].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedVertex {
+    /// Chunk-local position in 64ths of a block, offset by 8 blocks so that models reaching a bit outside of
+    /// their block don't go negative. The fourth component is unused, 16 bit attributes only come in pairs.
+    pub position: [u16; 4],
+    pub tex_coords: [u16; 2],
+    /// Snorm, so that [crate::mc::block::NO_EMISSIVE] stays negative
+    pub emissive_tex_coords: [i16; 2],
+    pub color: [u8; 4],
+    pub normal: [i8; 4],
+    pub uv_offset: u32,
+    pub lightmap_coords: [u8; 2],
+    pub padding: [u8; 2],
+    pub region: [u16; 4],
+}
+
+impl PackedVertex {
+    pub const POSITION_SCALE: f32 = 64.0;
+    pub const POSITION_OFFSET: f32 = 8.0;
+    /// Quads repeat their texture at most once per block of a section, so their texture coordinates are divided
+    /// by its size to fit into a unorm
+    pub const REPEAT_SCALE: f32 = 16.0;
+
+    //Uses the same shader locations as the matching attributes of Vertex
+    const VAA: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+        0 => Uint16x4,
+        1 => Unorm16x2,
+        8 => Snorm16x2,
+        4 => Unorm8x4,
+        3 => Snorm8x4,
+        6 => Uint32,
+        2 => Unorm8x2,
+        9 => Unorm16x4
+    ];
+
+    #[must_use]
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::VAA,
+        }
+    }
+
+    #[must_use]
+    pub fn pack(vertex: &Vertex) -> Self {
+        let position = vertex.position.map(|axis| {
+            ((axis + Self::POSITION_OFFSET) * Self::POSITION_SCALE)
+                .round()
+                .clamp(0.0, u16::MAX as f32) as u16
+        });
+
+        let tex_coords = if vertex.repeats_texture() {
+            vertex.tex_coords.map(|uv| uv / Self::REPEAT_SCALE)
+        } else {
+            vertex.tex_coords
+        };
+
+        Self {
+            position: [position[0], position[1], position[2], 0],
+            tex_coords: tex_coords.map(unorm16),
+            emissive_tex_coords: vertex
+                .emissive_tex_coords
+                .map(|uv| (uv.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16),
+            color: vertex.color.map(unorm8),
+            normal: vertex
+                .normal
+                .map(|axis| (axis.clamp(-1.0, 1.0) * i8::MAX as f32).round() as i8),
+            uv_offset: vertex.uv_offset,
+            lightmap_coords: vertex.lightmap_coords.map(unorm8),
+            padding: [0; 2],
+            region: vertex.region.map(unorm16),
+        }
+    }
+}
+
+fn unorm16(value: f32) -> u16 {
+    (value.clamp(0.0, 1.0) * u16::MAX as f32).round() as u16
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * u8::MAX as f32).round() as u8
+}
+
+/// The vertex format chunk meshes are uploaded with, see [crate::WmConfig::chunk_vertex_format]. The terrain
+/// shaders have to declare matching vertex inputs.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ChunkVertexFormat {
+    #[default]
+    Full,
+    Packed,
+}
+
+impl ChunkVertexFormat {
+    /// Defined for the shaders of the terrain pipelines when chunks are [ChunkVertexFormat::Packed], which declare
+    /// the position as a `vec4<u32>` in that permutation, see [crate::render::shader::ShaderSource::with_features]
+    pub const PACKED_FEATURE: &'static str = "PACKED_VERTICES";
+
+    #[must_use]
+    pub fn desc<'a>(&self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            Self::Full => Vertex::desc(),
+            Self::Packed => PackedVertex::desc(),
+        }
+    }
+
+    /// The size of a vertex, vertex buffers of chunks are allocated at multiples of it so that they can be drawn
+    /// from their page with a base vertex
+    #[must_use]
+    pub fn stride(&self) -> u64 {
+        self.desc().array_stride
+    }
+}
+
+/// The position of the chunk a terrain draw belongs to, relative to [crate::mc::chunk::ChunkManager::chunk_offset].
+/// Bound as a second, per-instance vertex buffer so that the draws of many chunks can be batched together.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChunkInstance {
+    pub position: [i32; 2],
+}
+
+impl ChunkInstance {
+    const VAA: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![
+        7 => Sint32x2,
+    ];
+
+    #[must_use]
+    pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ChunkInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::VAA,
+        }
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct QuadVertex {
@@ -83,6 +300,7 @@ pub struct WmPipelines {
 
     pub shader_map: RwLock<HashMap<String, Box<dyn WmShader>>>,
     pub bind_group_layouts: RwLock<HashMap<String, BindGroupLayout>>,
+    pub bind_group_cache: BindGroupCache,
     pub resource_provider: Arc<dyn ResourceProvider>,
 }
 
@@ -193,6 +411,22 @@ impl WmPipelines {
                     }],
                 }),
             ),
+            (
+                "entity_instances".into(),
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("Entity Instances Bind Group Layout"),
+                    entries: &[0, 1, 2].map(|binding| wgpu::BindGroupLayoutEntry {
+                        binding,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }),
+                }),
+            ),
             (
                 "ssbo_mut".into(),
                 device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -236,6 +470,7 @@ impl WmPipelines {
             render_pipelines: ArcSwap::new(Arc::new(HashMap::new())),
             resource_provider,
             bind_group_layouts: RwLock::new(HashMap::new()),
+            bind_group_cache: BindGroupCache::new(),
             shader_map: RwLock::new(HashMap::new()),
             compute_pipelines: ArcSwap::new(Arc::new(HashMap::new())),
             chunk_layers: ArcSwap::new(Arc::new(vec![])),
@@ -249,4 +484,265 @@ impl WmPipelines {
                 .extend(Self::create_bind_group_layouts(&wm.wgpu_state.device).into_iter())
         }
     }
+
+    /// The bind group of one of the [WmPipelines::bind_group_layouts] with the resources, from the
+    /// [WmPipelines::bind_group_cache]
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout_name: &str,
+        bindings: &[CachedBinding],
+    ) -> Arc<wgpu::BindGroup> {
+        let layouts = self.bind_group_layouts.read();
+
+        self.bind_group_cache.get(
+            device,
+            layout_name,
+            layouts.get(layout_name).unwrap(),
+            bindings,
+        )
+    }
+
+    /// Compiles the compute shader and registers the pipeline under the name, replacing the one which had it before,
+    /// see [compute]. The bind groups of the pipeline have the layouts in order.
+    pub fn create_compute_pipeline(
+        &self,
+        wm: &WmRenderer,
+        name: &str,
+        shader: &ResourcePath,
+        entry_point: &str,
+        bind_group_layouts: &[&BindGroupLayout],
+    ) -> Result<Arc<ComputePipeline>, ShaderError> {
+        let device = &wm.wgpu_state.device;
+
+        let module = ShaderSource::load(shader, &*self.resource_provider)?.create_module(device);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(name),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Arc::new(
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(name),
+                layout: Some(&layout),
+                module: &module,
+                entry_point,
+            }),
+        );
+
+        self.compute_pipelines.rcu(|pipelines| {
+            let mut pipelines = HashMap::clone(pipelines);
+            pipelines.insert(name.into(), pipeline.clone());
+            pipelines
+        });
+
+        Ok(pipeline)
+    }
+
+    /// The compute pipeline registered under the name with [WmPipelines::create_compute_pipeline]
+    pub fn compute_pipeline(&self, name: &str) -> Option<Arc<ComputePipeline>> {
+        self.compute_pipelines.load().get(name).cloned()
+    }
+}
+
+/// A resource of a bind group from the [BindGroupCache]
+#[derive(Clone, Debug)]
+pub enum CachedBinding {
+    Buffer(Arc<wgpu::Buffer>),
+    TextureView(Arc<wgpu::TextureView>),
+    Sampler(Arc<wgpu::Sampler>),
+}
+
+impl CachedBinding {
+    /// The address of the resource, which identifies it for as long as it's alive
+    fn id(&self) -> usize {
+        match self {
+            Self::Buffer(buffer) => Arc::as_ptr(buffer) as usize,
+            Self::TextureView(view) => Arc::as_ptr(view) as usize,
+            Self::Sampler(sampler) => Arc::as_ptr(sampler) as usize,
+        }
+    }
+
+    fn downgrade(&self) -> Weak<dyn Any + Send + Sync> {
+        match self {
+            Self::Buffer(buffer) => Arc::downgrade(buffer) as Weak<dyn Any + Send + Sync>,
+            Self::TextureView(view) => Arc::downgrade(view) as Weak<dyn Any + Send + Sync>,
+            Self::Sampler(sampler) => Arc::downgrade(sampler) as Weak<dyn Any + Send + Sync>,
+        }
+    }
+
+    fn resource(&self) -> wgpu::BindingResource {
+        match self {
+            Self::Buffer(buffer) => buffer.as_entire_binding(),
+            Self::TextureView(view) => wgpu::BindingResource::TextureView(view),
+            Self::Sampler(sampler) => wgpu::BindingResource::Sampler(sampler),
+        }
+    }
+}
+
+struct CachedBindGroup<T> {
+    bindings: Vec<Weak<dyn Any + Send + Sync>>,
+    bind_group: T,
+}
+
+impl<T> CachedBindGroup<T> {
+    fn is_alive(&self) -> bool {
+        self.bindings
+            .iter()
+            .all(|binding| binding.strong_count() > 0)
+    }
+}
+
+/// Bind groups which are kept for as long as the resources they bind are alive, instead of being created again
+/// every frame. They're keyed by the name of the layout and the addresses of the resources, and only hold weak
+/// references to them, so a resource which is dropped takes its bind groups with it. A new resource at the same
+/// address gets a new bind group.
+pub struct BindGroupCache<T = Arc<wgpu::BindGroup>> {
+    entries: Mutex<HashMap<(String, Vec<usize>), CachedBindGroup<T>>>,
+}
+
+impl<T> Default for BindGroupCache<T> {
+    fn default() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl BindGroupCache {
+    /// Returns the cached bind group of the layout with the resources, or creates it. Each resource is bound at
+    /// the binding of its index.
+    pub fn get(
+        &self,
+        device: &wgpu::Device,
+        layout_name: &str,
+        layout: &BindGroupLayout,
+        bindings: &[CachedBinding],
+    ) -> Arc<wgpu::BindGroup> {
+        self.get_or_create(
+            layout_name,
+            bindings.iter().map(CachedBinding::id).collect(),
+            || bindings.iter().map(CachedBinding::downgrade).collect(),
+            || {
+                Arc::new(
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some(layout_name),
+                        layout,
+                        entries: &bindings
+                            .iter()
+                            .enumerate()
+                            .map(|(binding, resource)| wgpu::BindGroupEntry {
+                                binding: binding as u32,
+                                resource: resource.resource(),
+                            })
+                            .collect::<Vec<_>>(),
+                    }),
+                )
+            },
+        )
+    }
+}
+
+impl<T: Clone> BindGroupCache<T> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get_or_create(
+        &self,
+        layout_name: &str,
+        ids: Vec<usize>,
+        bindings: impl FnOnce() -> Vec<Weak<dyn Any + Send + Sync>>,
+        create: impl FnOnce() -> T,
+    ) -> T {
+        let key = (layout_name.to_string(), ids);
+
+        let mut entries = self.entries.lock();
+
+        if let Some(cached) = entries.get(&key).filter(|cached| cached.is_alive()) {
+            return cached.bind_group.clone();
+        }
+
+        let bind_group = create();
+
+        entries.insert(
+            key,
+            CachedBindGroup {
+                bindings: bindings(),
+                bind_group: bind_group.clone(),
+            },
+        );
+
+        bind_group
+    }
+
+    /// Drops the bind groups whose resources were dropped, as the GPU keeps the resources around for as long as a
+    /// bind group uses them. Called every frame by [WmRenderer::render]. Returns the number of bind groups which
+    /// were dropped.
+    pub fn evict_dropped(&self) -> usize {
+        let mut entries = self.entries.lock();
+        let count = entries.len();
+
+        entries.retain(|_, cached| cached.is_alive());
+
+        count - entries.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::sync::Arc;
+
+    use super::BindGroupCache;
+
+    type Resource = Arc<dyn Any + Send + Sync>;
+
+    fn get(cache: &BindGroupCache<u32>, resources: &[&Resource], bind_group: u32) -> u32 {
+        cache.get_or_create(
+            "layout",
+            resources
+                .iter()
+                .map(|resource| Arc::as_ptr(resource) as *const () as usize)
+                .collect(),
+            || {
+                resources
+                    .iter()
+                    .map(|resource| Arc::downgrade(resource))
+                    .collect()
+            },
+            || bind_group,
+        )
+    }
+
+    #[test]
+    fn bind_groups_are_evicted_with_their_resources() {
+        let cache = BindGroupCache::new();
+        let kept: Resource = Arc::new(0u32);
+        let dropped: Resource = Arc::new(1u32);
+
+        assert_eq!(get(&cache, &[&kept], 1), 1);
+        //Cached, so it isn't created again
+        assert_eq!(get(&cache, &[&kept], 2), 1);
+        assert_eq!(get(&cache, &[&kept, &dropped], 3), 3);
+        assert_eq!(cache.len(), 2);
+
+        drop(dropped);
+
+        assert_eq!(cache.evict_dropped(), 1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&cache, &[&kept], 4), 1);
+        assert_eq!(cache.evict_dropped(), 0);
+    }
 }
@@ -0,0 +1,298 @@
+//! # GPU profiling
+//!
+//! With [crate::WmConfig::gpu_profiling] on a device with [wgpu::Features::TIMESTAMP_QUERY], the shader graph
+//! writes a timestamp before each of its passes and one after the last, so the time between two timestamps is
+//! how long the GPU spent on a pass. Pipelines of the [crate::WmRenderer::pipeline_registry] count towards the pass
+//! before them, and the ones drawn before the first pass aren't timed.
+//!
+//! The timestamps are read back without waiting for the GPU, so [crate::WmRenderer::last_frame_profile] is the last
+//! frame whose timestamps came back, usually one or two frames behind the one which was just drawn.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwapOption;
+use parking_lot::Mutex;
+
+use crate::WgpuState;
+
+/// How many timestamps a frame can have, passes after that aren't timed
+const MAX_TIMESTAMPS: u32 = 256;
+
+/// How many frames can be waiting for their timestamps to be read back at once. Frames are only profiled while one
+/// of the readback buffers is free.
+const READBACKS: usize = 3;
+
+const WAITING: u8 = 0;
+const MAPPED: u8 = 1;
+const FAILED: u8 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PassTiming {
+    /// The name of the pipeline in the shader graph
+    pub name: String,
+    /// Whether it's the depth pre-pass of the pipeline, see [crate::WmRenderer::depth_prepass]
+    pub depth_prepass: bool,
+    pub gpu_time: Duration,
+}
+
+/// How long the GPU spent on each pass of a frame, in the order they were drawn
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RenderProfile {
+    pub passes: Vec<PassTiming>,
+}
+
+impl RenderProfile {
+    /// `timestamps` has one more timestamp than there are passes, `period` is the nanoseconds per tick of the
+    /// timestamps
+    fn from_timestamps(passes: Vec<(String, bool)>, timestamps: &[u64], period: f32) -> Self {
+        Self {
+            passes: passes
+                .into_iter()
+                .zip(timestamps.windows(2))
+                .map(|((name, depth_prepass), window)| PassTiming {
+                    name,
+                    depth_prepass,
+                    gpu_time: Duration::from_nanos(
+                        (window[1].saturating_sub(window[0]) as f64 * period as f64) as u64,
+                    ),
+                })
+                .collect(),
+        }
+    }
+
+    /// The time of the whole frame
+    pub fn total(&self) -> Duration {
+        self.passes.iter().map(|pass| pass.gpu_time).sum()
+    }
+
+    /// The time of the pipeline, including its depth pre-pass
+    pub fn pipeline_time(&self, name: &str) -> Duration {
+        self.passes
+            .iter()
+            .filter(|pass| pass.name == name)
+            .map(|pass| pass.gpu_time)
+            .sum()
+    }
+}
+
+struct Readback {
+    buffer: wgpu::Buffer,
+    /// The passes of the frame whose timestamps are copied into the buffer, [None] while the buffer is free
+    passes: Option<Vec<(String, bool)>>,
+    /// Whether the buffer is being mapped
+    mapping: bool,
+    /// Set by the callback of [wgpu::BufferSlice::map_async]
+    state: Arc<AtomicU8>,
+}
+
+/// See [crate::render::profiler]
+pub struct GpuProfiler {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    /// Nanoseconds per tick of the timestamps
+    period: f32,
+    readbacks: Mutex<Vec<Readback>>,
+    last_profile: ArcSwapOption<RenderProfile>,
+}
+
+impl GpuProfiler {
+    /// Returns [None] if the device doesn't support timestamp queries
+    #[must_use]
+    pub fn new(wgpu_state: &WgpuState) -> Option<Self> {
+        let device = &wgpu_state.device;
+
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let size = MAX_TIMESTAMPS as u64 * 8;
+
+        let buffer = |label: &str, usage: wgpu::BufferUsages| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        Some(Self {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("GPU profiler"),
+                ty: wgpu::QueryType::Timestamp,
+                count: MAX_TIMESTAMPS,
+            }),
+            resolve_buffer: buffer(
+                "GPU profiler resolve",
+                wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            ),
+            period: wgpu_state.queue.get_timestamp_period(),
+            readbacks: Mutex::new(
+                (0..READBACKS)
+                    .map(|_| Readback {
+                        buffer: buffer(
+                            "GPU profiler readback",
+                            wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+                        ),
+                        passes: None,
+                        mapping: false,
+                        state: Arc::new(AtomicU8::new(WAITING)),
+                    })
+                    .collect(),
+            ),
+            last_profile: ArcSwapOption::empty(),
+        })
+    }
+
+    /// Collects the timestamps which were read back, and starts profiling a frame if a readback buffer is free
+    pub fn begin_frame(&self) -> Option<ProfilerFrame> {
+        let mut readbacks = self.readbacks.lock();
+
+        for readback in readbacks.iter_mut().filter(|readback| readback.mapping) {
+            let state = readback.state.load(Ordering::Acquire);
+
+            if state == WAITING {
+                continue;
+            }
+
+            let passes = readback.passes.take().unwrap_or_default();
+
+            if state == MAPPED {
+                let timestamps: Vec<u64> = bytemuck::cast_slice(
+                    &readback.buffer.slice(..).get_mapped_range()[..(passes.len() + 1) * 8],
+                )
+                .to_vec();
+
+                self.last_profile
+                    .store(Some(Arc::new(RenderProfile::from_timestamps(
+                        passes,
+                        &timestamps,
+                        self.period,
+                    ))));
+
+                readback.buffer.unmap();
+            }
+
+            readback.mapping = false;
+            readback.state.store(WAITING, Ordering::Release);
+        }
+
+        let slot = readbacks
+            .iter()
+            .position(|readback| readback.passes.is_none())?;
+
+        Some(ProfilerFrame {
+            profiler: self,
+            slot,
+            passes: Vec::new(),
+        })
+    }
+
+    /// Starts reading back the timestamps of the frames which were submitted
+    pub fn after_submit(&self) {
+        for readback in self.readbacks.lock().iter_mut() {
+            if readback.passes.is_none() || readback.mapping {
+                continue;
+            }
+
+            readback.mapping = true;
+
+            let state = readback.state.clone();
+            readback
+                .buffer
+                .slice(..)
+                .map_async(wgpu::MapMode::Read, move |result| {
+                    state.store(
+                        if result.is_ok() { MAPPED } else { FAILED },
+                        Ordering::Release,
+                    );
+                });
+        }
+    }
+
+    pub fn last_profile(&self) -> Option<RenderProfile> {
+        self.last_profile.load().as_deref().cloned()
+    }
+}
+
+/// The timestamps of a frame which is being recorded, see [GpuProfiler::begin_frame]
+pub struct ProfilerFrame<'a> {
+    profiler: &'a GpuProfiler,
+    slot: usize,
+    passes: Vec<(String, bool)>,
+}
+
+impl ProfilerFrame<'_> {
+    /// Marks the start of a pass, which lasts until the next one starts. Has to be called outside of any pass.
+    pub fn timestamp(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        name: &str,
+        depth_prepass: bool,
+    ) {
+        //One is kept for the end of the frame
+        if self.passes.len() as u32 + 2 > MAX_TIMESTAMPS {
+            return;
+        }
+
+        encoder.write_timestamp(&self.profiler.query_set, self.passes.len() as u32);
+        self.passes.push((name.into(), depth_prepass));
+    }
+
+    /// Marks the end of the last pass and copies the timestamps to the readback buffer. The frame is read back
+    /// after [GpuProfiler::after_submit].
+    pub fn finish(self, encoder: &mut wgpu::CommandEncoder) {
+        if self.passes.is_empty() {
+            return;
+        }
+
+        let count = self.passes.len() as u32 + 1;
+        encoder.write_timestamp(&self.profiler.query_set, count - 1);
+        encoder.resolve_query_set(
+            &self.profiler.query_set,
+            0..count,
+            &self.profiler.resolve_buffer,
+            0,
+        );
+
+        let mut readbacks = self.profiler.readbacks.lock();
+        let readback = &mut readbacks[self.slot];
+
+        encoder.copy_buffer_to_buffer(
+            &self.profiler.resolve_buffer,
+            0,
+            &readback.buffer,
+            0,
+            count as u64 * 8,
+        );
+        readback.passes = Some(self.passes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RenderProfile;
+
+    #[test]
+    fn passes_last_until_the_next_timestamp() {
+        let profile = RenderProfile::from_timestamps(
+            vec![
+                ("terrain".into(), true),
+                ("terrain".into(), false),
+                ("entities".into(), false),
+            ],
+            &[1000, 1500, 3500, 3600],
+            2.0,
+        );
+
+        assert_eq!(profile.passes[0].gpu_time, Duration::from_nanos(1000));
+        assert!(!profile.passes[1].depth_prepass);
+        assert_eq!(profile.pipeline_time("terrain"), Duration::from_nanos(5000));
+        assert_eq!(profile.pipeline_time("entities"), Duration::from_nanos(200));
+        assert_eq!(profile.total(), Duration::from_nanos(5200));
+    }
+}
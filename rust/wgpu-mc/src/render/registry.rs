@@ -0,0 +1,252 @@
+//! Render passes added by users of the crate, like the Fabric mod, which are drawn in between the pipelines of the
+//! [ShaderGraph].
+//!
+//! Each [WmPipeline] is registered under a name with a [RenderPhase] and an order within the phase. The graph
+//! draws the pipelines of a phase, lowest order first, at the point of the pack given by [phase_positions].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+
+use crate::mc::block::RenderType;
+use crate::render::graph::{terrain_render_type, CustomResource, ShaderGraph};
+use crate::WmRenderer;
+
+/// A render pass drawn every frame, see [PipelineRegistry::register]
+pub trait WmPipeline: Send + Sync {
+    /// Records the pass into the encoder of the frame. `output` is the texture the frame is drawn into, which is
+    /// multisampled with [WmRenderer::msaa_samples] samples, and `resources` are the resources of the graph, the
+    /// same ones its pipelines bind. Returns command buffers the pass depends on, which the graph submits along
+    /// with the frame, ahead of its encoder. Pipelines never submit anything themselves.
+    fn render(
+        &self,
+        wm: &WmRenderer,
+        graph: &ShaderGraph,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &wgpu::TextureView,
+        resources: &HashMap<String, CustomResource>,
+    ) -> Vec<wgpu::CommandBuffer>;
+}
+
+/// When a [WmPipeline] is drawn, relative to the terrain pipelines of the pack
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderPhase {
+    /// Before the first terrain pipeline, e.g. for the sky
+    PreTerrain,
+    /// After the solid and cutout terrain, before translucent terrain
+    PostTerrain,
+    /// After translucent terrain
+    PostTranslucent,
+    /// After the last pipeline of the pack, e.g. for debug UI which shouldn't be post-processed
+    Overlay,
+}
+
+impl RenderPhase {
+    pub const ALL: [RenderPhase; 4] = [
+        RenderPhase::PreTerrain,
+        RenderPhase::PostTerrain,
+        RenderPhase::PostTranslucent,
+        RenderPhase::Overlay,
+    ];
+}
+
+struct RegisteredPipeline {
+    name: String,
+    phase: RenderPhase,
+    order: i32,
+    pipeline: Arc<dyn WmPipeline>,
+}
+
+/// The custom pipelines of a [WmRenderer], see [crate::render::registry]
+#[derive(Default)]
+pub struct PipelineRegistry {
+    /// Sorted by phase and order
+    pipelines: RwLock<Vec<RegisteredPipeline>>,
+}
+
+impl PipelineRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the pipeline, or replaces the one registered under the same name. Pipelines of the same phase and
+    /// order are drawn in the order they were registered.
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        phase: RenderPhase,
+        order: i32,
+        pipeline: Arc<dyn WmPipeline>,
+    ) {
+        let name = name.into();
+        let mut pipelines = self.pipelines.write();
+
+        pipelines.retain(|registered| registered.name != name);
+
+        let index = pipelines
+            .partition_point(|registered| (registered.phase, registered.order) <= (phase, order));
+
+        pipelines.insert(
+            index,
+            RegisteredPipeline {
+                name,
+                phase,
+                order,
+                pipeline,
+            },
+        );
+    }
+
+    /// Returns false if there was no pipeline with the name
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut pipelines = self.pipelines.write();
+        let count = pipelines.len();
+
+        pipelines.retain(|registered| registered.name != name);
+
+        pipelines.len() != count
+    }
+
+    /// The names of the pipelines of the phase, in the order they're drawn
+    pub fn names(&self, phase: RenderPhase) -> Vec<String> {
+        self.pipelines
+            .read()
+            .iter()
+            .filter(|registered| registered.phase == phase)
+            .map(|registered| registered.name.clone())
+            .collect()
+    }
+
+    /// The pipelines of the phase, in the order they're drawn. They're cloned out of the registry, so that a pipeline
+    /// can register others while it's drawn.
+    pub fn in_phase(&self, phase: RenderPhase) -> Vec<Arc<dyn WmPipeline>> {
+        self.pipelines
+            .read()
+            .iter()
+            .filter(|registered| registered.phase == phase)
+            .map(|registered| registered.pipeline.clone())
+            .collect()
+    }
+}
+
+/// The index of the pipeline of the pack before which each of [RenderPhase::ALL] is drawn, given the geometry of
+/// every pipeline in the pack. A phase whose terrain is missing from the pack is drawn where the phase before it
+/// is, and the length of the pack means after the last pipeline.
+pub fn phase_positions<'a>(geometry: impl IntoIterator<Item = &'a str>) -> [usize; 4] {
+    let render_types: Vec<Option<RenderType>> =
+        geometry.into_iter().map(terrain_render_type).collect();
+
+    let after_last = |matches: fn(RenderType) -> bool| {
+        render_types
+            .iter()
+            .rposition(|render_type| render_type.map_or(false, matches))
+            .map(|index| index + 1)
+    };
+
+    let pre_terrain = render_types.iter().position(Option::is_some).unwrap_or(0);
+    let post_terrain = after_last(|render_type| render_type != RenderType::Translucent)
+        .unwrap_or(pre_terrain)
+        .max(pre_terrain);
+    let post_translucent = after_last(|render_type| render_type == RenderType::Translucent)
+        .unwrap_or(post_terrain)
+        .max(post_terrain);
+
+    [
+        pre_terrain,
+        post_terrain,
+        post_translucent,
+        render_types.len(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use super::{phase_positions, PipelineRegistry, RenderPhase, WmPipeline};
+    use crate::render::graph::{CustomResource, ShaderGraph};
+    use crate::WmRenderer;
+
+    struct NoopPipeline;
+
+    impl WmPipeline for NoopPipeline {
+        fn render(
+            &self,
+            _wm: &WmRenderer,
+            _graph: &ShaderGraph,
+            _encoder: &mut wgpu::CommandEncoder,
+            _output: &wgpu::TextureView,
+            _resources: &HashMap<String, CustomResource>,
+        ) -> Vec<wgpu::CommandBuffer> {
+            Vec::new()
+        }
+    }
+
+    #[test]
+    fn pipelines_are_ordered_by_phase_and_order() {
+        let registry = PipelineRegistry::new();
+
+        registry.register(
+            "outline",
+            RenderPhase::PostTerrain,
+            10,
+            Arc::new(NoopPipeline),
+        );
+        registry.register("sky", RenderPhase::PreTerrain, 0, Arc::new(NoopPipeline));
+        registry.register(
+            "particles",
+            RenderPhase::PostTerrain,
+            0,
+            Arc::new(NoopPipeline),
+        );
+        registry.register(
+            "clouds",
+            RenderPhase::PostTerrain,
+            10,
+            Arc::new(NoopPipeline),
+        );
+        //Replaces the earlier one
+        registry.register(
+            "outline",
+            RenderPhase::PostTerrain,
+            20,
+            Arc::new(NoopPipeline),
+        );
+
+        assert_eq!(registry.names(RenderPhase::PreTerrain), ["sky"]);
+        assert_eq!(
+            registry.names(RenderPhase::PostTerrain),
+            ["particles", "clouds", "outline"]
+        );
+
+        assert!(registry.unregister("clouds"));
+        assert!(!registry.unregister("clouds"));
+        assert_eq!(registry.in_phase(RenderPhase::PostTerrain).len(), 2);
+    }
+
+    #[test]
+    fn phases_surround_the_terrain_of_the_pack() {
+        assert_eq!(
+            phase_positions([
+                "wm_geo_quad",
+                "wm_geo_terrain",
+                "wm_geo_terrain_cutout",
+                "wm_geo_entities",
+                "wm_geo_terrain_translucent",
+                "wm_geo_quad",
+            ]),
+            [1, 3, 5, 6]
+        );
+
+        //Without translucent terrain
+        assert_eq!(
+            phase_positions(["wm_geo_terrain", "wm_geo_quad"]),
+            [0, 1, 1, 2]
+        );
+        //Without any terrain
+        assert_eq!(phase_positions(["wm_geo_quad"]), [0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,87 @@
+//! # Reverse-Z
+//!
+//! Floats are most precise near 0, while a standard projection crams most of the depth range of a far away scene
+//! close to 1, so at large render distances far away faces start to Z-fight. With [crate::WmRenderer::reverse_z],
+//! the near plane is at a depth of 1 and the far plane at 0 instead, which spreads the precision of the
+//! [Depth32Float](wgpu::TextureFormat::Depth32Float) buffer evenly over the distance. The depth textures of the
+//! shader graph are then cleared to 0, and the depth compare functions of its pipelines and their depth biases are
+//! flipped.
+//!
+//! The frontend provides the projection matrix, so it has to pass it through [reverse_z_projection] as well.
+
+use cgmath::Matrix4;
+use wgpu::{CompareFunction, DepthBiasState};
+
+/// Maps the depth of an OpenGL style projection like the ones of Minecraft and [cgmath::perspective], which goes
+/// from -1 at the near plane to 1 at the far plane, to go from 1 at the near plane to 0 at the far plane instead
+#[rustfmt::skip]
+pub fn reverse_z_projection(projection: Matrix4<f32>) -> Matrix4<f32> {
+    //Column major, the depth becomes (w - z) / 2
+    let remap = Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, 1.0, 0.0, 0.0,
+        0.0, 0.0, -0.5, 0.0,
+        0.0, 0.0, 0.5, 1.0,
+    );
+
+    remap * projection
+}
+
+/// The depth textures are cleared to the far plane
+pub fn clear_depth(reverse_z: bool) -> f32 {
+    if reverse_z {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+/// The compare function which keeps the same fragments when the depth is reversed
+pub fn depth_compare(compare: CompareFunction, reverse_z: bool) -> CompareFunction {
+    if !reverse_z {
+        return compare;
+    }
+
+    match compare {
+        CompareFunction::Less => CompareFunction::Greater,
+        CompareFunction::LessEqual => CompareFunction::GreaterEqual,
+        CompareFunction::Greater => CompareFunction::Less,
+        CompareFunction::GreaterEqual => CompareFunction::LessEqual,
+        compare => compare,
+    }
+}
+
+/// The bias which moves fragments the same way when the depth is reversed
+pub fn depth_bias(bias: DepthBiasState, reverse_z: bool) -> DepthBiasState {
+    if !reverse_z {
+        return bias;
+    }
+
+    DepthBiasState {
+        constant: -bias.constant,
+        slope_scale: -bias.slope_scale,
+        clamp: -bias.clamp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{perspective, Deg, Vector4};
+
+    use super::reverse_z_projection;
+
+    #[test]
+    fn near_is_one_and_far_is_zero() {
+        let projection = reverse_z_projection(perspective(Deg(90.0), 1.0, 0.1, 1000.0));
+
+        let depth = |distance: f32| {
+            let clip = projection * Vector4::new(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+
+        assert!((depth(0.1) - 1.0).abs() < 1e-4);
+        assert!(depth(1000.0).abs() < 1e-4);
+        //Closer is larger
+        assert!(depth(10.0) > depth(500.0));
+    }
+}
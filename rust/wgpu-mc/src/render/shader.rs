@@ -1,14 +1,356 @@
 use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt::{Display, Formatter};
 
 use crate::mc::resource::{ResourcePath, ResourceProvider};
 use crate::wgpu::{ShaderModule, ShaderModuleDescriptor};
 
+/// Returned when shaders required by a [crate::render::shaderpack::ShaderPackConfig] could not be found
+#[derive(Debug)]
+pub struct MissingShaderError {
+    /// Every shader which is missing, not just the first one
+    pub missing: Vec<ResourcePath>,
+}
+
+impl Display for MissingShaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let missing: Vec<String> = self.missing.iter().map(ToString::to_string).collect();
+
+        write!(f, "Missing shaders: {}", missing.join(", "))
+    }
+}
+
+/// Why the shader of a pipeline couldn't be compiled, see [crate::render::graph::ShaderGraph::rebuild_pipelines]
+#[derive(Debug)]
+pub enum ShaderError {
+    Missing(ResourcePath),
+    /// The extension of the path isn't one of the languages of [ShaderSource]
+    UnknownLanguage(ResourcePath),
+    /// The shader didn't pass validation, with the error as naga prints it
+    Invalid(ResourcePath, String),
+    /// wgpu rejected the pipeline with this name, with the validation error of the device
+    Rejected(String, String),
+    /// The pipeline with this name binds a resource which neither the graph has nor the frontend gave a type for
+    UnknownResource(String, String),
+    /// The pipeline with this name has a geometry which neither the graph nor the frontend can draw
+    UnknownGeometry(String, String),
+    /// The pipeline with this name has a push constant which isn't one of the `wm_pc_*` ones, see
+    /// [crate::render::graph::PushConstantValues]
+    UnknownPushConstant(String, String),
+}
+
+impl Display for ShaderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "The shader {path} is missing"),
+            Self::UnknownLanguage(path) => {
+                write!(f, "The language of the shader {path} isn't supported")
+            }
+            Self::Invalid(path, message) => write!(f, "The shader {path} is invalid: {message}"),
+            Self::Rejected(pipeline, message) => {
+                write!(f, "The pipeline {pipeline} is invalid: {message}")
+            }
+            Self::UnknownResource(pipeline, resource) => {
+                write!(
+                    f,
+                    "The pipeline {pipeline} binds the unknown resource {resource}"
+                )
+            }
+            Self::UnknownGeometry(pipeline, geometry) => {
+                write!(
+                    f,
+                    "The pipeline {pipeline} has the unknown geometry {geometry}"
+                )
+            }
+            Self::UnknownPushConstant(pipeline, push_constant) => write!(
+                f,
+                "The pipeline {pipeline} has the unknown push constant {push_constant}"
+            ),
+        }
+    }
+}
+
+/// The features a permutation of a shader is compiled with, e.g. `FOG` or `SMOOTH_LIGHTING`. Each of them is defined
+/// for the preprocessor, see [ShaderSource::with_features].
+pub type ShaderFeatures = BTreeSet<String>;
+
+/// WGSL has no preprocessor, so the lines between `#ifdef NAME` or `#ifndef NAME`, `#else` and `#endif` are kept or
+/// blanked out here depending on whether the feature is enabled. The directives can be nested. Blanked lines are
+/// kept as empty lines, so that the errors of naga still point at the right line.
+fn preprocess_wgsl(source: &str, features: &ShaderFeatures) -> Result<String, String> {
+    //Whether the lines of each of the enclosing blocks are kept
+    let mut blocks: Vec<bool> = Vec::new();
+
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let kept = blocks.iter().all(|&kept| kept);
+            let mut directive = line.split_whitespace();
+
+            match (directive.next(), directive.next()) {
+                (Some("#ifdef"), Some(feature)) => blocks.push(features.contains(feature)),
+                (Some("#ifndef"), Some(feature)) => blocks.push(!features.contains(feature)),
+                (Some("#else"), _) => match blocks.last_mut() {
+                    Some(block) => *block = !*block,
+                    None => return Err(format!("#else without #ifdef on line {}", index + 1)),
+                },
+                (Some("#endif"), _) => {
+                    if blocks.pop().is_none() {
+                        return Err(format!("#endif without #ifdef on line {}", index + 1));
+                    }
+                }
+                _ => return Ok(if kept { line } else { "" }),
+            }
+
+            Ok("")
+        })
+        .collect::<Result<Vec<&str>, String>>()?;
+
+    if !blocks.is_empty() {
+        return Err("#ifdef without #endif".into());
+    }
+
+    Ok(lines.join("\n"))
+}
+
+/// Defines each of the features right after the `#version` directive, which has to come first in GLSL
+fn define_glsl_features(source: &str, features: &ShaderFeatures) -> String {
+    if features.is_empty() {
+        return source.into();
+    }
+
+    let defines: String = features
+        .iter()
+        .map(|feature| format!("#define {feature}\n"))
+        .collect();
+
+    match source.find("#version").map(|start| {
+        source[start..]
+            .find('\n')
+            .map_or(source.len(), |end| start + end + 1)
+    }) {
+        Some(end) if end == source.len() => format!("{source}\n{defines}"),
+        Some(end) => format!("{}{defines}{}", &source[..end], &source[end..]),
+        None => format!("{defines}{source}"),
+    }
+}
+
+/// Checks the module with naga, which doesn't panic on errors like creating the module does
+fn validate_module(module: &naga::Module) -> Result<naga::valid::ModuleInfo, String> {
+    naga::valid::Validator::new(
+        naga::valid::ValidationFlags::all(),
+        naga::valid::Capabilities::all(),
+    )
+    .validate(module)
+    .map_err(|error| error.into_inner().to_string())
+}
+
+fn validate_wgsl(source: &str) -> Result<(), String> {
+    let module =
+        naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))?;
+
+    validate_module(&module).map(|_| ())
+}
+
+/// Turns the push constants of the module into a uniform buffer at binding 0 of the group. The buffer holds the
+/// same bytes as the push constants would, so their types have to follow the layout rules of uniform buffers.
+fn move_push_constants(module: &mut naga::Module, group: u32) {
+    for (_, global) in module.global_variables.iter_mut() {
+        if global.space == naga::AddressSpace::PushConstant {
+            global.space = naga::AddressSpace::Uniform;
+            global.binding = Some(naga::ResourceBinding { group, binding: 0 });
+        }
+    }
+}
+
+/// The source of a single shader module, in the language given by the extension of its path:
+///
+/// - `.wgsl`: WGSL
+/// - `.vert`, `.frag` and `.comp`: GLSL, with one stage per file
+/// - `.spv`: SPIR-V
+#[derive(Debug)]
+pub enum ShaderSource {
+    Wgsl(String),
+    Glsl(String, naga::ShaderStage),
+    SpirV(Vec<u8>),
+}
+
+impl ShaderSource {
+    /// Reads the shader from the resource provider and validates it
+    pub fn load(resource: &ResourcePath, rp: &dyn ResourceProvider) -> Result<Self, ShaderError> {
+        Self::load_with_features(resource, rp, &ShaderFeatures::new())
+    }
+
+    /// Like [ShaderSource::load], but validates the permutation with the features defined
+    pub fn load_with_features(
+        resource: &ResourcePath,
+        rp: &dyn ResourceProvider,
+        features: &ShaderFeatures,
+    ) -> Result<Self, ShaderError> {
+        let source = Self::read(resource, rp)?
+            .with_features(features)
+            .map_err(|message| ShaderError::Invalid(resource.clone(), message))?;
+
+        source
+            .validate()
+            .map_err(|message| ShaderError::Invalid(resource.clone(), message))?;
+
+        Ok(source)
+    }
+
+    /// Reads the shader from the resource provider without validating it
+    pub fn read(resource: &ResourcePath, rp: &dyn ResourceProvider) -> Result<Self, ShaderError> {
+        let extension = resource.0.rsplit_once('.').map(|(_, extension)| extension);
+
+        let glsl_stage = match extension {
+            Some("vert") => Some(naga::ShaderStage::Vertex),
+            Some("frag") => Some(naga::ShaderStage::Fragment),
+            Some("comp") => Some(naga::ShaderStage::Compute),
+            _ => None,
+        };
+
+        let missing = || ShaderError::Missing(resource.clone());
+
+        let source = match (extension, glsl_stage) {
+            (_, Some(stage)) => Self::Glsl(rp.get_string(resource).ok_or_else(missing)?, stage),
+            (Some("wgsl"), _) => Self::Wgsl(rp.get_string(resource).ok_or_else(missing)?),
+            (Some("spv"), _) => Self::SpirV(rp.get_bytes(resource).ok_or_else(missing)?),
+            _ => return Err(ShaderError::UnknownLanguage(resource.clone())),
+        };
+
+        Ok(source)
+    }
+
+    /// The permutation of the shader with the features enabled. WGSL goes through a small preprocessor of
+    /// `#ifdef`s, while GLSL gets a `#define` for each feature for its own. SPIR-V is compiled already, so it's
+    /// left as it is.
+    pub fn with_features(self, features: &ShaderFeatures) -> Result<Self, String> {
+        Ok(match self {
+            Self::Wgsl(source) => Self::Wgsl(preprocess_wgsl(&source, features)?),
+            Self::Glsl(source, stage) => Self::Glsl(define_glsl_features(&source, features), stage),
+            Self::SpirV(bytes) => Self::SpirV(bytes),
+        })
+    }
+
+    fn parse(&self) -> Result<naga::Module, String> {
+        match self {
+            Self::Wgsl(source) => {
+                naga::front::wgsl::parse_str(source).map_err(|error| error.emit_to_string(source))
+            }
+            Self::Glsl(source, stage) => naga::front::glsl::Parser::default()
+                .parse(&naga::front::glsl::Options::from(*stage), source)
+                .map_err(|errors| {
+                    errors
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }),
+            Self::SpirV(bytes) => naga::front::spv::parse_u8_slice(bytes, &Default::default())
+                .map_err(|error| error.to_string()),
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        validate_module(&self.parse()?).map(|_| ())
+    }
+
+    /// Like [ShaderSource::create_module], but with the push constants moved to a uniform buffer at binding 0 of
+    /// the group, for devices without [wgpu::Features::PUSH_CONSTANTS]. The module is written back out as WGSL.
+    pub fn create_module_without_push_constants(
+        &self,
+        device: &wgpu::Device,
+        group: u32,
+    ) -> Result<ShaderModule, String> {
+        let mut module = self.parse()?;
+        move_push_constants(&mut module, group);
+
+        let info = validate_module(&module)?;
+        let source =
+            naga::back::wgsl::write_string(&module, &info, naga::back::wgsl::WriterFlags::empty())
+                .map_err(|error| error.to_string())?;
+
+        Ok(Self::Wgsl(source).create_module(device))
+    }
+
+    /// [ShaderSource::create_module], or [ShaderSource::create_module_without_push_constants] if there's a group to
+    /// move them to
+    fn create_module_for(
+        &self,
+        device: &wgpu::Device,
+        push_constant_group: Option<u32>,
+    ) -> Result<ShaderModule, String> {
+        match push_constant_group {
+            None => Ok(self.create_module(device)),
+            Some(group) => self.create_module_without_push_constants(device, group),
+        }
+    }
+
+    pub fn create_module(&self, device: &wgpu::Device) -> ShaderModule {
+        device.create_shader_module(ShaderModuleDescriptor {
+            label: None,
+            source: match self {
+                Self::Wgsl(source) => wgpu::ShaderSource::Wgsl(Cow::from(source)),
+                Self::Glsl(source, stage) => wgpu::ShaderSource::Glsl {
+                    shader: Cow::from(source),
+                    stage: *stage,
+                    defines: Default::default(),
+                },
+                Self::SpirV(bytes) => wgpu::ShaderSource::SpirV(wgpu::util::make_spirv_raw(bytes)),
+            },
+        })
+    }
+}
+
+/// The files making up the shader of a pipeline. WGSL and SPIR-V shaders have both stages in one module, while a
+/// `.glsl` path stands for the `.vert` and `.frag` files next to it.
+pub fn shader_files(resource: &ResourcePath) -> Vec<ResourcePath> {
+    match resource.0.strip_suffix(".glsl") {
+        Some(stem) => vec![
+            ResourcePath(format!("{stem}.vert")),
+            ResourcePath(format!("{stem}.frag")),
+        ],
+        None => vec![resource.clone()],
+    }
+}
+
+/// Loads the shader of a pipeline, see [shader_files]. On devices without push constants, `push_constant_group` is
+/// the bind group they're moved to, see [ShaderSource::create_module_without_push_constants].
+pub fn load_pipeline_shader(
+    resource: &ResourcePath,
+    rp: &dyn ResourceProvider,
+    device: &wgpu::Device,
+    push_constant_group: Option<u32>,
+    features: &ShaderFeatures,
+) -> Result<Box<dyn WmShader>, ShaderError> {
+    match &shader_files(resource)[..] {
+        [vert, frag] => Ok(Box::new(GlslShader::load(
+            frag,
+            vert,
+            rp,
+            device,
+            push_constant_group,
+            features,
+        )?)),
+        _ => Ok(Box::new(WgslShader::load(
+            resource,
+            rp,
+            device,
+            push_constant_group,
+            features,
+        )?)),
+    }
+}
+
 pub trait WmShader: Send + Sync {
     fn get_frag(&self) -> (&ShaderModule, &str);
 
     fn get_vert(&self) -> (&ShaderModule, &str);
 }
 
+/// A module with both a vertex and a fragment entry point. Despite the name, [WgslShader::load] also accepts
+/// SPIR-V.
 #[derive(Debug)]
 pub struct WgslShader {
     pub shader: ShaderModule,
@@ -39,6 +381,26 @@ impl WgslShader {
             vert_entry,
         })
     }
+
+    /// Like [WgslShader::init] with the `frag` and `vert` entry points, but goes through [ShaderSource], so a
+    /// broken shader is reported instead of taking down the device
+    pub fn load(
+        resource: &ResourcePath,
+        rp: &dyn ResourceProvider,
+        device: &wgpu::Device,
+        push_constant_group: Option<u32>,
+        features: &ShaderFeatures,
+    ) -> Result<Self, ShaderError> {
+        let shader = ShaderSource::load_with_features(resource, rp, features)?
+            .create_module_for(device, push_constant_group)
+            .map_err(|message| ShaderError::Invalid(resource.clone(), message))?;
+
+        Ok(Self {
+            shader,
+            frag_entry: "frag".into(),
+            vert_entry: "vert".into(),
+        })
+    }
 }
 
 impl WmShader for WgslShader {
@@ -93,6 +455,27 @@ impl GlslShader {
             vert: vert_module,
         }
     }
+
+    /// Like [GlslShader::init], but validates both stages first
+    pub fn load(
+        frag: &ResourcePath,
+        vert: &ResourcePath,
+        rp: &dyn ResourceProvider,
+        device: &wgpu::Device,
+        push_constant_group: Option<u32>,
+        features: &ShaderFeatures,
+    ) -> Result<Self, ShaderError> {
+        let create_module = |resource: &ResourcePath| {
+            ShaderSource::load_with_features(resource, rp, features)?
+                .create_module_for(device, push_constant_group)
+                .map_err(|message| ShaderError::Invalid(resource.clone(), message))
+        };
+
+        Ok(Self {
+            frag: create_module(frag)?,
+            vert: create_module(vert)?,
+        })
+    }
 }
 
 impl WmShader for GlslShader {
@@ -104,3 +487,148 @@ impl WmShader for GlslShader {
         (&self.vert, "main")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        define_glsl_features, move_push_constants, preprocess_wgsl, shader_files, validate_wgsl,
+        MissingShaderError, ShaderError, ShaderFeatures, ShaderSource,
+    };
+    use crate::mc::resource::{ResourcePath, ResourceProvider};
+
+    struct SourceProvider(&'static str);
+
+    impl ResourceProvider for SourceProvider {
+        fn get_bytes(&self, _id: &ResourcePath) -> Option<Vec<u8>> {
+            Some(self.0.as_bytes().to_vec())
+        }
+    }
+
+    #[test]
+    fn broken_shaders_are_rejected() {
+        assert!(validate_wgsl(
+            "@fragment fn frag() -> @location(0) vec4<f32> { return vec4<f32>(1.0); }"
+        )
+        .is_ok());
+
+        //Syntax error
+        assert!(validate_wgsl("@fragment fn frag( {").is_err());
+        //Returns the wrong type
+        assert!(validate_wgsl("fn f() -> f32 { return 1u; }").is_err());
+    }
+
+    #[test]
+    fn language_is_picked_by_extension() {
+        let glsl = SourceProvider("#version 450\nlayout(location = 0) out vec4 color;\nvoid main() { color = vec4(1.0); }");
+
+        assert!(matches!(
+            ShaderSource::load(&ResourcePath("wgpu_mc:shaders/sky.frag".into()), &glsl),
+            Ok(ShaderSource::Glsl(_, naga::ShaderStage::Fragment))
+        ));
+        //Not WGSL
+        assert!(matches!(
+            ShaderSource::load(&ResourcePath("wgpu_mc:shaders/sky.wgsl".into()), &glsl),
+            Err(ShaderError::Invalid(..))
+        ));
+        assert!(matches!(
+            ShaderSource::load(&ResourcePath("wgpu_mc:shaders/sky.hlsl".into()), &glsl),
+            Err(ShaderError::UnknownLanguage(_))
+        ));
+
+        assert_eq!(
+            shader_files(&ResourcePath("wgpu_mc:shaders/sky.glsl".into())),
+            [
+                ResourcePath("wgpu_mc:shaders/sky.vert".into()),
+                ResourcePath("wgpu_mc:shaders/sky.frag".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn push_constants_are_moved_to_a_uniform_buffer() {
+        let mut module = ShaderSource::Wgsl(
+            "struct PushConstants { framebuffer_size: vec2<f32> }
+var<push_constant> push_constants: PushConstants;
+@fragment fn frag() -> @location(0) vec4<f32> { return vec4<f32>(push_constants.framebuffer_size, 0.0, 1.0); }"
+                .into(),
+        )
+        .parse()
+        .unwrap();
+
+        move_push_constants(&mut module, 3);
+
+        let (_, global) = module.global_variables.iter().next().unwrap();
+        assert_eq!(global.space, naga::AddressSpace::Uniform);
+        assert_eq!(
+            global.binding,
+            Some(naga::ResourceBinding {
+                group: 3,
+                binding: 0
+            })
+        );
+    }
+
+    #[test]
+    fn features_pick_the_lines_of_the_permutation() {
+        let source = "a
+#ifdef FOG
+fog
+#ifndef SMOOTH_LIGHTING
+flat
+#else
+smooth
+#endif
+#endif
+b";
+        let features = |names: &[&str]| -> ShaderFeatures {
+            names.iter().map(|name| name.to_string()).collect()
+        };
+
+        let kept = |names: &[&str]| -> Vec<String> {
+            preprocess_wgsl(source, &features(names))
+                .unwrap()
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(String::from)
+                .collect()
+        };
+
+        assert_eq!(kept(&[]), ["a", "b"]);
+        assert_eq!(kept(&["FOG"]), ["a", "fog", "flat", "b"]);
+        assert_eq!(
+            kept(&["FOG", "SMOOTH_LIGHTING"]),
+            ["a", "fog", "smooth", "b"]
+        );
+        //Line numbers stay the same
+        assert_eq!(
+            preprocess_wgsl(source, &features(&[]))
+                .unwrap()
+                .lines()
+                .count(),
+            10
+        );
+
+        assert!(preprocess_wgsl("#ifdef FOG\nfog", &features(&[])).is_err());
+        assert!(preprocess_wgsl("#endif", &features(&[])).is_err());
+
+        assert_eq!(
+            define_glsl_features("#version 450\nvoid main() {}", &features(&["FOG"])),
+            "#version 450\n#define FOG\nvoid main() {}"
+        );
+    }
+
+    #[test]
+    fn missing_shaders_are_listed() {
+        let error = MissingShaderError {
+            missing: vec![
+                ResourcePath("wgpu_mc:shaders/sky.wgsl".into()),
+                ResourcePath("wgpu_mc:shaders/clouds.wgsl".into()),
+            ],
+        };
+
+        assert_eq!(
+            error.to_string(),
+            "Missing shaders: wgpu_mc:shaders/sky.wgsl, wgpu_mc:shaders/clouds.wgsl"
+        );
+    }
+}
@@ -1,8 +1,21 @@
 //! Serde implementation of the [shaderpack specification](https://github.com/wgpu-mc/shader-spec)
+//!
+//! Besides the pack itself, [ShaderPackConfig::load] reads [PIPELINE_OVERRIDES] from the resource provider, so
+//! mods and resource packs can add pipelines or replace ones of the pack without a new build of the renderer.
+//! Shaderpacks go further, see [pack].
+
+pub mod pack;
+
+use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
 
 use linked_hash_map::LinkedHashMap;
 use serde_derive::*;
 
+use crate::mc::resource::{ResourcePath, ResourceProvider};
+use crate::render::pipeline::debug_lines::DepthBiasPresets;
+use crate::render::shader::{MissingShaderError, ShaderError, ShaderFeatures};
+
 /// semver
 pub const CONFIG_VERSION: &str = "v0.0.1";
 /// (major, minor, patch)
@@ -11,7 +24,31 @@ pub const CONFIG_VERSION_TRIPLE: (u32, u32, u32) = (0, 0, 1);
 pub type Mat3 = [[f32; 3]; 3];
 pub type Mat4 = [[f32; 4]; 4];
 
-#[derive(Deserialize, Debug)]
+/// Pipelines which are added to the pack, or replace the pipeline of the same name
+pub const PIPELINE_OVERRIDES: &str = "wgpu_mc:pipelines.yaml";
+
+#[derive(Debug)]
+pub enum ShaderPackError {
+    Missing(ResourcePath),
+    Yaml(ResourcePath, serde_yaml::Error),
+    /// Shaders of a [pack::ShaderPack] which neither it nor the game has
+    MissingShaders(MissingShaderError),
+    /// A shader of a [pack::ShaderPack] doesn't compile
+    Shader(ShaderError),
+}
+
+impl Display for ShaderPackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "{path} is missing"),
+            Self::Yaml(path, error) => write!(f, "{path} isn't valid: {error}"),
+            Self::MissingShaders(error) => error.fmt(f),
+            Self::Shader(error) => error.fmt(f),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct ShaderPackConfig {
     pub version: String,
     pub support: String,
@@ -20,6 +57,70 @@ pub struct ShaderPackConfig {
 }
 
 impl ShaderPackConfig {
+    /// The shader of the pipeline, `wgpu_mc:shaders/<name>` with the extension of the pack's `support` language
+    /// unless it names one. See [crate::render::shader::ShaderSource] for the languages.
+    pub fn shader_path(&self, name: &str) -> ResourcePath {
+        let shader = self
+            .pipelines
+            .pipelines
+            .get(name)
+            .and_then(|pipeline| pipeline.shader.clone());
+
+        ResourcePath(shader.unwrap_or_else(|| {
+            let extension = match &self.support[..] {
+                "spirv" => "spv",
+                support => support,
+            };
+
+            format!("wgpu_mc:shaders/{name}.{extension}")
+        }))
+    }
+
+    /// Reads the pack at `path` and applies the [PIPELINE_OVERRIDES], if there are any
+    pub fn load(
+        resource_provider: &dyn ResourceProvider,
+        path: &ResourcePath,
+    ) -> Result<Self, ShaderPackError> {
+        let source = resource_provider
+            .get_string(path)
+            .ok_or_else(|| ShaderPackError::Missing(path.clone()))?;
+
+        let mut pack: Self = serde_yaml::from_str(&source)
+            .map_err(|error| ShaderPackError::Yaml(path.clone(), error))?;
+
+        pack.apply_overrides(resource_provider)?;
+
+        Ok(pack)
+    }
+
+    /// Adds the pipelines of [PIPELINE_OVERRIDES] to the pack. Pipelines with the name of one the pack already
+    /// has replace it, but keep its place in the draw order. New ones are drawn after the others.
+    pub fn apply_overrides(
+        &mut self,
+        resource_provider: &dyn ResourceProvider,
+    ) -> Result<(), ShaderPackError> {
+        let path = ResourcePath(PIPELINE_OVERRIDES.into());
+
+        let source = match resource_provider.get_string(&path) {
+            None => return Ok(()),
+            Some(source) => source,
+        };
+
+        let overrides: PipelinesConfig =
+            serde_yaml::from_str(&source).map_err(|error| ShaderPackError::Yaml(path, error))?;
+
+        for (name, pipeline) in overrides.pipelines {
+            match self.pipelines.pipelines.get_mut(&name) {
+                Some(existing) => *existing = pipeline,
+                None => {
+                    self.pipelines.pipelines.insert(name, pipeline);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// Returns true if the first two numbers (major and minor) are as expected.
     /// If the format is incorrect or they're different, this returns false.
     pub fn is_correct_version(&self) -> bool {
@@ -39,13 +140,13 @@ impl ShaderPackConfig {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ResourcesConfig {
     #[serde(flatten)]
     pub resources: LinkedHashMap<String, ShorthandResourceConfig>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum ShorthandResourceConfig {
     Int(i64),
@@ -55,7 +156,7 @@ pub enum ShorthandResourceConfig {
     Longhand(LonghandResourceConfig),
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct LonghandResourceConfig {
     #[serde(flatten)]
     pub common: CommonResourceConfig,
@@ -64,7 +165,7 @@ pub struct LonghandResourceConfig {
     pub typed: TypeResourceConfig,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct CommonResourceConfig {
     #[serde(default)]
     pub desc: String,
@@ -73,7 +174,7 @@ pub struct CommonResourceConfig {
     pub show: bool,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum TypeResourceConfig {
     Blob {
@@ -138,33 +239,381 @@ pub enum Mat4ValueOrMult {
     Mult { mult: Vec<String> },
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct PipelinesConfig {
     #[serde(flatten)]
     pub pipelines: LinkedHashMap<String, PipelineConfig>,
 }
 
-fn blend_default() -> String {
-    "alpha_blending".into()
-}
-
 #[derive(Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
 pub struct PipelineConfig {
+    /// The shader with `vert` and `frag` entry points, or the GLSL stages, see [ShaderPackConfig::shader_path]
+    pub shader: Option<String>,
+
     pub geometry: String,
 
+    #[serde(default)]
+    pub topology: Topology,
+
+    /// Which faces aren't drawn, none of them by default
+    pub cull_mode: Option<CullMode>,
+
     #[serde(default)]
     pub output: Vec<String>,
 
     pub depth: Option<String>,
 
+    #[serde(default)]
+    pub depth_compare: DepthCompare,
+
+    /// Whether the pipeline writes to the depth texture, which is the case for everything but translucent terrain
+    /// by default
+    pub depth_write: Option<bool>,
+
     #[serde(default)]
     pub uniforms: LinkedHashMap<u64, String>,
 
     #[serde(default)]
     pub push_constants: LinkedHashMap<u64, String>,
 
-    #[serde(default = "blend_default")]
-    pub blending: String,
+    #[serde(default)]
+    pub blending: Blending,
+
+    /// Pulls the fragments towards the camera so overlays don't Z-fight with the faces under them. Defaults to
+    /// [DepthBiasPresets::DECALS] for block cracks and entity shadows, and no bias for everything else. Only
+    /// triangles are biased, it's ignored for the point and line topologies, whose shaders can move their vertices
+    /// with the `wm_pc_line_depth_offset` push constant instead.
+    pub depth_bias: Option<DepthBias>,
+
+    /// The shader features the shader has permutations for, see [crate::WmRenderer::set_shader_feature]. Only
+    /// these are defined for it, so other features being toggled don't rebuild the pipeline.
+    #[serde(default)]
+    pub features: Vec<String>,
+
+    /// The channels of the outputs which are written to, all of them by default. An empty list only writes depth.
+    pub write_mask: Option<Vec<ColorChannel>>,
+}
+
+impl PipelineConfig {
+    /// The features of the permutation of the shader with the enabled features
+    pub fn shader_features(&self, enabled: &ShaderFeatures) -> ShaderFeatures {
+        self.features
+            .iter()
+            .filter(|feature| enabled.contains(*feature))
+            .cloned()
+            .collect()
+    }
+
+    pub fn color_writes(&self) -> wgpu::ColorWrites {
+        match &self.write_mask {
+            None => wgpu::ColorWrites::ALL,
+            Some(channels) => channels
+                .iter()
+                .map(|&channel| wgpu::ColorWrites::from(channel))
+                .fold(wgpu::ColorWrites::empty(), |writes, channel| {
+                    writes | channel
+                }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    PointList,
+    LineList,
+    LineStrip,
+    #[default]
+    TriangleList,
+    TriangleStrip,
+}
+
+impl From<Topology> for wgpu::PrimitiveTopology {
+    fn from(topology: Topology) -> Self {
+        match topology {
+            Topology::PointList => Self::PointList,
+            Topology::LineList => Self::LineList,
+            Topology::LineStrip => Self::LineStrip,
+            Topology::TriangleList => Self::TriangleList,
+            Topology::TriangleStrip => Self::TriangleStrip,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CullMode {
+    Front,
+    Back,
+}
+
+impl From<CullMode> for wgpu::Face {
+    fn from(cull_mode: CullMode) -> Self {
+        match cull_mode {
+            CullMode::Front => Self::Front,
+            CullMode::Back => Self::Back,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthCompare {
+    Never,
+    #[default]
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl From<DepthCompare> for wgpu::CompareFunction {
+    fn from(compare: DepthCompare) -> Self {
+        match compare {
+            DepthCompare::Never => Self::Never,
+            DepthCompare::Less => Self::Less,
+            DepthCompare::Equal => Self::Equal,
+            DepthCompare::LessEqual => Self::LessEqual,
+            DepthCompare::Greater => Self::Greater,
+            DepthCompare::NotEqual => Self::NotEqual,
+            DepthCompare::GreaterEqual => Self::GreaterEqual,
+            DepthCompare::Always => Self::Always,
+        }
+    }
+}
+
+/// How the colors a pipeline outputs are combined with what's already in its outputs, alpha blending by default.
+/// Either one of the [BlendPreset]s, or the factors and operations of the color and the alpha, for example
+///
+/// ```yaml
+/// blending:
+///   color: { src_factor: src_alpha, dst_factor: one }
+///   alpha: { src_factor: zero, dst_factor: one }
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Blending {
+    Preset(BlendPreset),
+    Custom {
+        color: BlendComponent,
+        alpha: BlendComponent,
+    },
+}
+
+impl Default for Blending {
+    fn default() -> Self {
+        Self::Preset(BlendPreset::AlphaBlending)
+    }
+}
+
+impl From<Blending> for wgpu::BlendState {
+    fn from(blending: Blending) -> Self {
+        match blending {
+            Blending::Preset(BlendPreset::AlphaBlending) => Self::ALPHA_BLENDING,
+            Blending::Preset(BlendPreset::PremultipliedAlphaBlending) => {
+                Self::PREMULTIPLIED_ALPHA_BLENDING
+            }
+            Blending::Preset(BlendPreset::Replace) => Self::REPLACE,
+            //Vanilla's crumbling blend, which darkens and lightens what's underneath
+            Blending::Preset(BlendPreset::Multiply) => Self {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::Src,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::OVER,
+            },
+            Blending::Custom { color, alpha } => Self {
+                color: color.into(),
+                alpha: alpha.into(),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendPreset {
+    AlphaBlending,
+    PremultipliedAlphaBlending,
+    Replace,
+    Multiply,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct BlendComponent {
+    pub src_factor: BlendFactor,
+    pub dst_factor: BlendFactor,
+    #[serde(default)]
+    pub operation: BlendOperation,
+}
+
+impl From<BlendComponent> for wgpu::BlendComponent {
+    fn from(component: BlendComponent) -> Self {
+        Self {
+            src_factor: component.src_factor.into(),
+            dst_factor: component.dst_factor.into(),
+            operation: component.operation.into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendFactor {
+    Zero,
+    One,
+    Src,
+    OneMinusSrc,
+    SrcAlpha,
+    OneMinusSrcAlpha,
+    Dst,
+    OneMinusDst,
+    DstAlpha,
+    OneMinusDstAlpha,
+    SrcAlphaSaturated,
+    Constant,
+    OneMinusConstant,
+}
+
+impl From<BlendFactor> for wgpu::BlendFactor {
+    fn from(factor: BlendFactor) -> Self {
+        match factor {
+            BlendFactor::Zero => Self::Zero,
+            BlendFactor::One => Self::One,
+            BlendFactor::Src => Self::Src,
+            BlendFactor::OneMinusSrc => Self::OneMinusSrc,
+            BlendFactor::SrcAlpha => Self::SrcAlpha,
+            BlendFactor::OneMinusSrcAlpha => Self::OneMinusSrcAlpha,
+            BlendFactor::Dst => Self::Dst,
+            BlendFactor::OneMinusDst => Self::OneMinusDst,
+            BlendFactor::DstAlpha => Self::DstAlpha,
+            BlendFactor::OneMinusDstAlpha => Self::OneMinusDstAlpha,
+            BlendFactor::SrcAlphaSaturated => Self::SrcAlphaSaturated,
+            BlendFactor::Constant => Self::Constant,
+            BlendFactor::OneMinusConstant => Self::OneMinusConstant,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendOperation {
+    #[default]
+    Add,
+    Subtract,
+    ReverseSubtract,
+    Min,
+    Max,
+}
+
+impl From<BlendOperation> for wgpu::BlendOperation {
+    fn from(operation: BlendOperation) -> Self {
+        match operation {
+            BlendOperation::Add => Self::Add,
+            BlendOperation::Subtract => Self::Subtract,
+            BlendOperation::ReverseSubtract => Self::ReverseSubtract,
+            BlendOperation::Min => Self::Min,
+            BlendOperation::Max => Self::Max,
+        }
+    }
+}
+
+/// Either one of the presets, or the bias itself, for example
+///
+/// ```yaml
+/// depth_bias: { constant: -2, slope_scale: -1.5 }
+/// ```
+///
+/// Negative values pull the fragments towards the camera.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(untagged)]
+pub enum DepthBias {
+    Preset(DepthBiasPreset),
+    Custom {
+        constant: i32,
+        #[serde(default)]
+        slope_scale: f32,
+        #[serde(default)]
+        clamp: f32,
+    },
+}
+
+impl From<DepthBias> for wgpu::DepthBiasState {
+    fn from(bias: DepthBias) -> Self {
+        match bias {
+            DepthBias::Preset(DepthBiasPreset::None) => Self::default(),
+            DepthBias::Preset(DepthBiasPreset::Decals) => DepthBiasPresets::DECALS,
+            DepthBias::Custom {
+                constant,
+                slope_scale,
+                clamp,
+            } => Self {
+                constant,
+                slope_scale,
+                clamp,
+            },
+        }
+    }
+}
+
+//Compared by the bits of the floats, as the config has to be hashable
+impl PartialEq for DepthBias {
+    fn eq(&self, other: &Self) -> bool {
+        let state = |bias: &Self| {
+            let state = wgpu::DepthBiasState::from(*bias);
+            (
+                state.constant,
+                state.slope_scale.to_bits(),
+                state.clamp.to_bits(),
+            )
+        };
+
+        state(self) == state(other)
+    }
+}
+
+impl Eq for DepthBias {}
+
+impl Hash for DepthBias {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let bias = wgpu::DepthBiasState::from(*self);
+
+        bias.constant.hash(state);
+        bias.slope_scale.to_bits().hash(state);
+        bias.clamp.to_bits().hash(state);
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthBiasPreset {
+    None,
+    /// [DepthBiasPresets::DECALS]
+    Decals,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorChannel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl From<ColorChannel> for wgpu::ColorWrites {
+    fn from(channel: ColorChannel) -> Self {
+        match channel {
+            ColorChannel::Red => Self::RED,
+            ColorChannel::Green => Self::GREEN,
+            ColorChannel::Blue => Self::BLUE,
+            ColorChannel::Alpha => Self::ALPHA,
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
@@ -186,7 +635,20 @@ mod tests {
 
     use serde::Deserialize;
 
-    use super::ShaderPackConfig;
+    use super::{
+        BlendPreset, Blending, CullMode, DepthBias, DepthBiasPreset, DepthCompare,
+        ShaderPackConfig, Topology, PIPELINE_OVERRIDES,
+    };
+    use crate::mc::resource::{ResourcePath, ResourceProvider};
+
+    /// Only has the [PIPELINE_OVERRIDES]
+    struct OverridesProvider(&'static str);
+
+    impl ResourceProvider for OverridesProvider {
+        fn get_bytes(&self, id: &ResourcePath) -> Option<Vec<u8>> {
+            (id.0 == PIPELINE_OVERRIDES).then(|| self.0.as_bytes().to_vec())
+        }
+    }
 
     fn deserialize_and_print_error<'a, T: Debug + Deserialize<'a>>(input: &'a str) {
         let config: Result<T, _> = serde_yaml::from_str(input);
@@ -293,4 +755,128 @@ pipelines:
     fn complete_file() {
         deserialize_and_print_error::<ShaderPackConfig>(FULL_YAML);
     }
+
+    #[test]
+    fn overrides_replace_and_add_pipelines() {
+        let mut pack: ShaderPackConfig = serde_yaml::from_str(FULL_YAML).unwrap();
+
+        pack.apply_overrides(&OverridesProvider(
+            r#"
+terrain:
+  shader: "mymod:shaders/terrain.wgsl"
+  geometry: wm_geo_terrain
+  depth: wm_framebuffer_depth
+  depth_compare: less_equal
+  cull_mode: back
+  output: [wm_framebuffer_texture]
+outlines:
+  geometry: wm_geo_quad
+  topology: line_strip
+"#,
+        ))
+        .unwrap();
+
+        let pipelines = &pack.pipelines.pipelines;
+        let names: Vec<&str> = pipelines.keys().map(String::as_str).collect();
+        assert_eq!(
+            names,
+            [
+                "terrain_shadows",
+                "entity_shadows",
+                "terrain",
+                "entities",
+                "outlines"
+            ]
+        );
+
+        assert_eq!(pack.shader_path("terrain").0, "mymod:shaders/terrain.wgsl");
+        //The pack is written in GLSL
+        assert_eq!(
+            pack.shader_path("outlines").0,
+            "wgpu_mc:shaders/outlines.glsl"
+        );
+
+        let terrain = &pipelines["terrain"];
+        assert_eq!(terrain.depth_compare, DepthCompare::LessEqual);
+        assert_eq!(terrain.cull_mode, Some(CullMode::Back));
+        assert_eq!(terrain.topology, Topology::TriangleList);
+
+        let outlines = &pipelines["outlines"];
+        assert_eq!(outlines.topology, Topology::LineStrip);
+        assert_eq!(outlines.depth_compare, DepthCompare::Less);
+    }
+
+    #[test]
+    fn blending_and_write_masks() {
+        let pack: ShaderPackConfig = serde_yaml::from_str(
+            r#"
+version: "0.0.1"
+support: wgsl
+resources: {}
+pipelines:
+  terrain:
+    geometry: wm_geo_terrain
+  breaking:
+    geometry: wm_geo_block_breaking
+    blending: multiply
+    write_mask: [red, green, blue]
+  glow:
+    geometry: wm_geo_quad
+    blending:
+      color: { src_factor: src_alpha, dst_factor: one }
+      alpha: { src_factor: zero, dst_factor: one, operation: max }
+    write_mask: []
+"#,
+        )
+        .unwrap();
+        let pipelines = &pack.pipelines.pipelines;
+
+        let terrain = &pipelines["terrain"];
+        assert_eq!(
+            terrain.blending,
+            Blending::Preset(BlendPreset::AlphaBlending)
+        );
+        assert_eq!(terrain.color_writes(), wgpu::ColorWrites::ALL);
+
+        let breaking = &pipelines["breaking"];
+        assert_eq!(breaking.blending, Blending::Preset(BlendPreset::Multiply));
+        assert_eq!(breaking.color_writes(), wgpu::ColorWrites::COLOR);
+
+        let glow = &pipelines["glow"];
+        let blend = wgpu::BlendState::from(glow.blending);
+        assert_eq!(blend.color.src_factor, wgpu::BlendFactor::SrcAlpha);
+        assert_eq!(blend.color.operation, wgpu::BlendOperation::Add);
+        assert_eq!(blend.alpha.operation, wgpu::BlendOperation::Max);
+        assert!(glow.color_writes().is_empty());
+    }
+
+    #[test]
+    fn depth_bias_presets_and_values() {
+        let pack: ShaderPackConfig = serde_yaml::from_str(
+            r#"
+version: "0.0.1"
+support: wgsl
+resources: {}
+pipelines:
+  wire:
+    geometry: wm_geo_redstone_wire
+    depth_bias: decals
+  outline:
+    geometry: wm_geo_block_outline
+    depth_bias: { constant: -1, slope_scale: -2.0 }
+"#,
+        )
+        .unwrap();
+        let pipelines = &pack.pipelines.pipelines;
+
+        assert_eq!(
+            pipelines["wire"].depth_bias,
+            Some(DepthBias::Preset(DepthBiasPreset::Decals))
+        );
+
+        let outline = wgpu::DepthBiasState::from(pipelines["outline"].depth_bias.unwrap());
+        assert_eq!(outline.constant, -1);
+        assert_eq!(outline.slope_scale, -2.0);
+        assert_eq!(outline.clamp, 0.0);
+    }
 }
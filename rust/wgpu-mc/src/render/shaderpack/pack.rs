@@ -0,0 +1,182 @@
+//! # Shaderpacks
+//!
+//! A [ShaderPack] is a set of resources which are looked up before the ones of the game, like a resource pack which
+//! only the renderer sees. It can replace the shaders of the pipelines, and add or replace pipelines with its own
+//! [super::PIPELINE_OVERRIDES], which are applied after the ones of mods. Its pipelines can also bind the
+//! [crate::render::uniforms::FrameUniforms], and the resources of the ones packs tend to need on their own.
+//!
+//! Packs come from any [ResourceProvider], so a frontend which can already read zipped resource packs can hand those
+//! over as they are. [DirectoryResourceProvider] reads unzipped packs, and [crate::mc::resource::ZipResourceProvider]
+//! zipped ones. See [crate::render::graph::ShaderGraph::set_shader_pack].
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::mc::resource::{ResourcePackStack, ResourcePath, ResourceProvider};
+use crate::render::shaderpack::{ShaderPackConfig, ShaderPackError};
+
+/// Reads resources from `<root>/assets/<namespace>/<path>`, the layout of a resource pack
+#[derive(Debug)]
+pub struct DirectoryResourceProvider {
+    root: PathBuf,
+}
+
+impl DirectoryResourceProvider {
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The file of the resource
+    fn file(&self, id: &ResourcePath) -> Option<PathBuf> {
+        let (namespace, path) = id.0.split_once(':')?;
+
+        //Resources can't reach out of the pack
+        if path.split('/').any(|component| component == "..") {
+            return None;
+        }
+
+        Some(self.root.join("assets").join(namespace).join(path))
+    }
+}
+
+impl ResourceProvider for DirectoryResourceProvider {
+    fn get_bytes(&self, id: &ResourcePath) -> Option<Vec<u8>> {
+        std::fs::read(self.file(id)?).ok()
+    }
+
+    fn contains(&self, id: &ResourcePath) -> bool {
+        self.file(id).map_or(false, |file| file.is_file())
+    }
+
+    fn list(&self, directory: &ResourcePath) -> Vec<ResourcePath> {
+        let (namespace, path) = match directory.0.split_once(':') {
+            Some(split) => split,
+            None => return Vec::new(),
+        };
+
+        if path.split('/').any(|component| component == "..") {
+            return Vec::new();
+        }
+
+        let mut resources = Vec::new();
+        let mut directories = vec![(
+            self.root.join("assets").join(namespace).join(path),
+            path.trim_end_matches('/').to_string(),
+        )];
+
+        while let Some((directory, path)) = directories.pop() {
+            let entries = match std::fs::read_dir(directory) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let path = format!("{path}/{name}");
+
+                match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => directories.push((entry.path(), path)),
+                    Ok(_) => resources.push(ResourcePath::from((namespace, &path[..]))),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        resources
+    }
+}
+
+/// See [crate::render::shaderpack::pack]
+pub struct ShaderPack {
+    pub name: String,
+    resources: Arc<dyn ResourceProvider>,
+}
+
+impl ShaderPack {
+    #[must_use]
+    pub fn new(name: impl Into<String>, resources: Arc<dyn ResourceProvider>) -> Self {
+        Self {
+            name: name.into(),
+            resources,
+        }
+    }
+
+    /// The resources of the pack over the ones of the game, as a [ResourcePackStack] with the pack on top
+    pub fn resource_provider(&self, game: Arc<dyn ResourceProvider>) -> Arc<dyn ResourceProvider> {
+        let stack = ResourcePackStack::new();
+        stack.insert("game", 0, game);
+        stack.insert(self.name.clone(), 1, self.resources.clone());
+
+        Arc::new(stack)
+    }
+
+    /// The builtin pipelines with the [super::PIPELINE_OVERRIDES] of the pack applied
+    pub fn apply(&self, builtin: &ShaderPackConfig) -> Result<ShaderPackConfig, ShaderPackError> {
+        let mut config = builtin.clone();
+        config.apply_overrides(&*self.resources)?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ShaderPack;
+    use crate::mc::resource::{MapProvider, ResourcePath, ResourceProvider};
+    use crate::render::shaderpack::{ShaderPackConfig, PIPELINE_OVERRIDES};
+
+    const BUILTIN: &str = r#"
+version: "0.0.1"
+support: wgsl
+resources: {}
+pipelines:
+  terrain:
+    geometry: wm_geo_terrain
+    depth: wm_framebuffer_depth
+    output: [wm_framebuffer_texture]
+"#;
+
+    #[test]
+    fn pack_resources_win_over_the_game() {
+        let game: Arc<dyn ResourceProvider> = Arc::new(MapProvider::new(&[
+            ("wgpu_mc:shaders/terrain.wgsl", "builtin"),
+            ("wgpu_mc:shaders/sky.wgsl", "builtin"),
+        ]));
+        let pack = ShaderPack::new(
+            "pack",
+            Arc::new(MapProvider::new(&[
+                ("wgpu_mc:shaders/terrain.wgsl", "pack"),
+                (
+                    PIPELINE_OVERRIDES,
+                    "bloom:\n  geometry: wm_geo_quad\n  uniforms:\n    0: wm_f32_time_of_day\n",
+                ),
+            ])),
+        );
+
+        let resources = pack.resource_provider(game);
+        let shader = |path: &str| resources.get_string(&ResourcePath(path.into()));
+
+        assert_eq!(shader("wgpu_mc:shaders/terrain.wgsl").unwrap(), "pack");
+        assert_eq!(shader("wgpu_mc:shaders/sky.wgsl").unwrap(), "builtin");
+        assert_eq!(
+            resources.source(&ResourcePath("wgpu_mc:shaders/terrain.wgsl".into())),
+            Some("pack".into())
+        );
+
+        let builtin: ShaderPackConfig = serde_yaml::from_str(BUILTIN).unwrap();
+        let config = pack.apply(&builtin).unwrap();
+
+        let names: Vec<&str> = config
+            .pipelines
+            .pipelines
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(names, ["terrain", "bloom"]);
+        //The builtin pipelines stay as they were
+        assert_eq!(builtin.pipelines.pipelines.len(), 1);
+    }
+}
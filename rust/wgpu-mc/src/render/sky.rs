@@ -1,48 +1,455 @@
+//! # Sky
+//!
+//! Pipelines with the `wm_geo_sky` geometry draw vanilla's sky as a triangle list of [SkyVertex], built every frame
+//! from the [SkyState] set with [crate::mc::MinecraftState::set_sky] and centered on the camera, so it's drawn
+//! before the terrain without writing depth. From the back to the front it's made of:
+//! 1. a dome which fades from the fog color at the horizon to the sky color overhead, darkened at night
+//! 2. the band of color at the horizon the sun rises and sets on, like vanilla's `getFogColorOverride`
+//! 3. vanilla's star field, which fades in at dusk
+//! 4. the sun and the phase of the moon, textured with the [sky_textures]
+//!
+//! The stars, the sun and the moon turn with the sky angle. The textures are allocated in the block atlas along
+//! with the textures of the blocks, and vertices which aren't textured have negative texture coordinates. The sun
+//! and moon textures are black where they're empty, as vanilla adds them onto the sky, so the shader uses the
+//! brightness of their texels as their alpha.
+
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Deg, InnerSpace, Matrix3, Rad, Vector3};
+
+use crate::mc::resource::ResourcePath;
+use crate::mc::MinecraftState;
+use crate::render::atlas::AtlasKind;
+
+/// How far the sky is from the camera, in blocks
+pub const SKY_DISTANCE: f32 = 100.0;
+
+/// How many stars vanilla tries to place, the ones which don't fit on the sphere are skipped
+pub const STAR_COUNT: usize = 1500;
+
+/// Vanilla's seed for the star field
+const STAR_SEED: u64 = 10842;
+
+/// How many segments the dome is split into around the horizon
+const DOME_SEGMENTS: u32 = 16;
+
+/// The elevations of the rings of the dome in degrees, and how far each is faded from the fog color to the sky
+/// color
+const DOME_RINGS: [(f32, f32); 5] = [
+    (-90.0, 0.0),
+    (-10.0, 0.0),
+    (0.0, 0.0),
+    (20.0, 0.7),
+    (90.0, 1.0),
+];
+
+/// Half the width of the sun and moon, like vanilla's
+const SUN_SIZE: f32 = 30.0;
+const MOON_SIZE: f32 = 20.0;
+
+/// The textures of the sky, allocated in the block atlas
+pub fn sky_textures() -> [ResourcePath; 2] {
+    [
+        ResourcePath("minecraft:environment/sun".into()),
+        ResourcePath("minecraft:environment/moon_phases".into()),
+    ]
+}
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct SkyVertex {
     pub position: [f32; 3],
+    /// In the block atlas, negative if the vertex isn't textured
+    pub tex_coords: [f32; 2],
+    pub color: [f32; 4],
 }
 
 impl SkyVertex {
+    const VAA: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4
+    ];
+
     #[must_use]
     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<SkyVertex>() as wgpu::BufferAddress,
             step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                //Position
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
-        }
-    }
-}
-
-// #[repr(C)]
-// #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
-// pub struct SkyboxVertex {
-//     pub position: [f32; 3],
-//     pub uv: [f32; 2]
-// }
-//
-// impl SkyboxVertex {
-//
-//     #[must_use]
-//     pub fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-//         use std::mem;
-//         wgpu::VertexBufferLayout {
-//             array_stride: mem::size_of::<SkyVertex>() as wgpu::BufferAddress,
-//             step_mode: wgpu::VertexStepMode::Vertex,
-//             attributes: &wgpu::vertex_attr_array![
-//                 0 => Float32x3,
-//                 1 => Float32x2
-//             ]
-//         }
-//     }
-//
-// }
+            attributes: &Self::VAA,
+        }
+    }
+}
+
+/// What the sky looks like this frame, see [crate::render::sky]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkyState {
+    /// The Nether and the End have no sky like the overworld's
+    pub enabled: bool,
+    /// How far the sun has turned, from 0 at noon through 0.5 at midnight, like vanilla's `getSkyAngle`
+    pub sky_angle: f32,
+    /// From 0 for a full moon to 7, like vanilla's `getMoonPhase`
+    pub moon_phase: u32,
+    /// The color of the sky overhead in the biome the camera is in, before it's darkened at night
+    pub sky_color: [f32; 3],
+    /// The color of the sky at the horizon, before it's darkened at night
+    pub fog_color: [f32; 3],
+    /// How hard it's raining, from 0 to 1, which hides the sun, moon and stars
+    pub rain: f32,
+    /// Multiplies the brightness of the stars, 0 hides them
+    pub star_visibility: f32,
+}
+
+impl Default for SkyState {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sky_angle: 0.0,
+            moon_phase: 0,
+            //Plains
+            sky_color: [0.47, 0.65, 1.0],
+            fog_color: [0.75, 0.85, 1.0],
+            rain: 0.0,
+            star_visibility: 1.0,
+        }
+    }
+}
+
+impl SkyState {
+    /// How bright the sky is, from 0 at night to 1 during the day
+    #[must_use]
+    pub fn brightness(&self) -> f32 {
+        ((self.sky_angle * std::f32::consts::TAU).cos() * 2.0 + 0.5).clamp(0.0, 1.0)
+    }
+
+    /// How bright the stars are, up to 0.5 in the middle of the night like vanilla's `getStarBrightness`
+    #[must_use]
+    pub fn star_brightness(&self) -> f32 {
+        let darkness =
+            (1.0 - ((self.sky_angle * std::f32::consts::TAU).cos() * 2.0 + 0.25)).clamp(0.0, 1.0);
+
+        darkness * darkness * 0.5 * (1.0 - self.rain) * self.star_visibility
+    }
+
+    /// The color of the horizon the sun is rising or setting on, with its alpha at the middle of the band, or [None]
+    /// if the sun isn't near the horizon
+    #[must_use]
+    pub fn sunrise_color(&self) -> Option<[f32; 4]> {
+        let height = (self.sky_angle * std::f32::consts::TAU).cos();
+
+        if !(-0.4..=0.4).contains(&height) {
+            return None;
+        }
+
+        let amount = height / 0.4 * 0.5 + 0.5;
+        let alpha = 1.0 - (1.0 - (amount * std::f32::consts::PI).sin()) * 0.99;
+
+        Some([
+            amount * 0.3 + 0.7,
+            amount * amount * 0.7 + 0.2,
+            0.2,
+            alpha * alpha,
+        ])
+    }
+
+    /// Turns the sun from overhead to where it is, and the moon and stars with it
+    fn rotation(&self) -> Matrix3<f32> {
+        Matrix3::from_angle_y(Deg(-90.0))
+            * Matrix3::from_angle_x(Rad(self.sky_angle * std::f32::consts::TAU))
+    }
+
+    /// The UVs of the phase of the moon within its texture, which has the phases in 4 columns and 2 rows
+    fn moon_uv(&self) -> [f32; 4] {
+        let column = (self.moon_phase % 4) as f32;
+        let row = (self.moon_phase / 4 % 2) as f32;
+
+        [
+            column / 4.0,
+            row / 2.0,
+            (column + 1.0) / 4.0,
+            (row + 1.0) / 2.0,
+        ]
+    }
+}
+
+/// Vanilla's `java.util.Random` isn't worth matching, the stars only have to stay in the same place between frames
+struct StarRandom(u64);
+
+impl StarRandom {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// The corners of each star in the sky before it's turned, like vanilla's `buildStarsBuffer`
+fn stars() -> Vec<[Vector3<f32>; 4]> {
+    let mut random = StarRandom(STAR_SEED);
+
+    (0..STAR_COUNT)
+        .filter_map(|_| {
+            let direction = Vector3::new(
+                random.next() * 2.0 - 1.0,
+                random.next() * 2.0 - 1.0,
+                random.next() * 2.0 - 1.0,
+            );
+            let size = 0.15 + random.next() * 0.1;
+            let roll = random.next() * std::f32::consts::TAU;
+
+            //Only the points inside the sphere are kept, so the stars are spread evenly over it
+            if !(0.01..1.0).contains(&direction.magnitude2()) {
+                return None;
+            }
+
+            let center = direction.normalize() * SKY_DISTANCE;
+            let across = if direction.cross(Vector3::unit_y()).magnitude2() > 1e-6 {
+                direction.cross(Vector3::unit_y()).normalize()
+            } else {
+                direction.cross(Vector3::unit_x()).normalize()
+            };
+            let up = direction.normalize().cross(across);
+
+            Some([0.0, 1.0, 2.0, 3.0].map(|corner: f32| {
+                let angle = roll + corner * std::f32::consts::FRAC_PI_2;
+
+                center + (across * angle.cos() + up * angle.sin()) * size
+            }))
+        })
+        .collect()
+}
+
+fn quad(vertices: &mut Vec<SkyVertex>, corners: [Vector3<f32>; 4], uv: [f32; 4], color: [f32; 4]) {
+    let [min_u, min_v, max_u, max_v] = uv;
+    let tex_coords = [
+        [min_u, min_v],
+        [max_u, min_v],
+        [max_u, max_v],
+        [min_u, max_v],
+    ];
+
+    let vertex = |index: usize| SkyVertex {
+        position: corners[index].into(),
+        tex_coords: tex_coords[index],
+        color,
+    };
+
+    vertices.extend([0, 1, 2, 0, 2, 3].map(vertex));
+}
+
+/// The dome of the sky, which fades from the fog color at the horizon to the sky color overhead
+fn dome(state: &SkyState, vertices: &mut Vec<SkyVertex>) {
+    let brightness = state.brightness();
+    let ring_color = |fade: f32| {
+        let [r, g, b] = [0, 1, 2].map(|channel| {
+            (state.fog_color[channel] * (1.0 - fade) + state.sky_color[channel] * fade) * brightness
+        });
+
+        [r, g, b, 1.0]
+    };
+
+    let point = |elevation: f32, segment: u32| {
+        let elevation = elevation.to_radians();
+        let heading = segment as f32 / DOME_SEGMENTS as f32 * std::f32::consts::TAU;
+
+        Vector3::new(
+            elevation.cos() * heading.cos(),
+            elevation.sin(),
+            elevation.cos() * heading.sin(),
+        ) * SKY_DISTANCE
+    };
+
+    for rings in DOME_RINGS.windows(2) {
+        let ((low, low_fade), (high, high_fade)) = (rings[0], rings[1]);
+        let (low_color, high_color) = (ring_color(low_fade), ring_color(high_fade));
+
+        for segment in 0..DOME_SEGMENTS {
+            let vertex = |elevation: f32, segment: u32, color: [f32; 4]| SkyVertex {
+                position: point(elevation, segment).into(),
+                tex_coords: [-1.0; 2],
+                color,
+            };
+
+            let a = vertex(low, segment, low_color);
+            let b = vertex(low, segment + 1, low_color);
+            let c = vertex(high, segment + 1, high_color);
+            let d = vertex(high, segment, high_color);
+
+            //Seen from the inside
+            vertices.extend([a, c, b, a, d, c]);
+        }
+    }
+}
+
+/// The fan of color over the horizon the sun is rising or setting on
+fn sunrise(state: &SkyState, vertices: &mut Vec<SkyVertex>) {
+    let color = match state.sunrise_color() {
+        Some(color) => color,
+        None => return,
+    };
+
+    //The sun sets in the west, which is where it is while the sky angle is below a half
+    let towards = if (state.sky_angle * std::f32::consts::TAU).sin() > 0.0 {
+        -Vector3::unit_x()
+    } else {
+        Vector3::unit_x()
+    };
+
+    let center = SkyVertex {
+        position: (towards * SKY_DISTANCE).into(),
+        tex_coords: [-1.0; 2],
+        color,
+    };
+
+    let edge = |segment: u32| {
+        let angle = segment as f32 / DOME_SEGMENTS as f32 * std::f32::consts::TAU;
+        let position = towards * (SKY_DISTANCE * 0.6)
+            + Vector3::unit_z() * (angle.sin() * SKY_DISTANCE * 1.2)
+            + Vector3::unit_y() * (angle.cos() * SKY_DISTANCE * 0.4 * color[3]);
+
+        SkyVertex {
+            position: position.into(),
+            tex_coords: [-1.0; 2],
+            color: [color[0], color[1], color[2], 0.0],
+        }
+    };
+
+    for segment in 0..DOME_SEGMENTS {
+        //Both faces, as the band faces either way depending on the side of the sky it's on
+        let (a, b) = (edge(segment), edge(segment + 1));
+        vertices.extend([center, a, b, center, b, a]);
+    }
+}
+
+/// The vertices of the sky around the camera, see [crate::render::sky]
+pub fn sky_vertices(mc: &MinecraftState, camera: [f32; 3]) -> Vec<SkyVertex> {
+    let state = mc.sky.load();
+
+    if !state.enabled {
+        return Vec::new();
+    }
+
+    let mut vertices = Vec::new();
+
+    dome(&state, &mut vertices);
+    sunrise(&state, &mut vertices);
+
+    let rotation = state.rotation();
+    let visible = 1.0 - state.rain.clamp(0.0, 1.0);
+    let star_brightness = state.star_brightness();
+
+    if star_brightness > 0.0 {
+        for star in stars() {
+            quad(
+                &mut vertices,
+                star.map(|corner| rotation * corner),
+                [-1.0; 4],
+                [star_brightness; 4],
+            );
+        }
+    }
+
+    let block_atlas = mc.texture_manager.atlas(AtlasKind::Block);
+    let atlas_size = block_atlas.size() as f32;
+    let uv_map = block_atlas.uv_map.read();
+    let [sun_texture, moon_texture] = sky_textures();
+
+    //The part of the texture in the atlas, or untextured if it isn't there
+    let region = |texture: &ResourcePath, [min_u, min_v, max_u, max_v]: [f32; 4]| {
+        uv_map
+            .get(texture)
+            .map_or([-1.0; 4], |&((min_x, min_y), (max_x, max_y))| {
+                let lerp =
+                    |min: f32, max: f32, amount: f32| (min + (max - min) * amount) / atlas_size;
+
+                [
+                    lerp(min_x, max_x, min_u),
+                    lerp(min_y, max_y, min_v),
+                    lerp(min_x, max_x, max_u),
+                    lerp(min_y, max_y, max_v),
+                ]
+            })
+    };
+
+    let celestial = |height: f32, size: f32| {
+        [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .map(|(x, z)| rotation * Vector3::new(x * size, height, z * size))
+    };
+
+    quad(
+        &mut vertices,
+        celestial(SKY_DISTANCE, SUN_SIZE),
+        region(&sun_texture, [0.0, 0.0, 1.0, 1.0]),
+        [1.0, 1.0, 1.0, visible],
+    );
+    quad(
+        &mut vertices,
+        celestial(-SKY_DISTANCE, MOON_SIZE),
+        region(&moon_texture, state.moon_uv()),
+        [1.0, 1.0, 1.0, visible],
+    );
+
+    let camera = Vector3::from(camera);
+
+    for vertex in &mut vertices {
+        vertex.position = (Vector3::from(vertex.position) + camera).into();
+    }
+
+    vertices
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::InnerSpace;
+
+    use super::{stars, SkyState, SKY_DISTANCE, STAR_COUNT};
+
+    fn at(sky_angle: f32) -> SkyState {
+        SkyState {
+            sky_angle,
+            ..SkyState::default()
+        }
+    }
+
+    #[test]
+    fn the_sky_darkens_at_night() {
+        assert_eq!(at(0.0).brightness(), 1.0);
+        assert_eq!(at(0.5).brightness(), 0.0);
+
+        assert_eq!(at(0.0).star_brightness(), 0.0);
+        assert_eq!(at(0.5).star_brightness(), 0.5);
+
+        //The sun is at the horizon a quarter of the way round
+        assert!(at(0.0).sunrise_color().is_none());
+        let sunset = at(0.25).sunrise_color().unwrap();
+        assert!(sunset[3] > 0.9);
+    }
+
+    #[test]
+    fn moon_phases_are_in_a_grid() {
+        let phase = |moon_phase| {
+            SkyState {
+                moon_phase,
+                ..SkyState::default()
+            }
+            .moon_uv()
+        };
+
+        assert_eq!(phase(0), [0.0, 0.0, 0.25, 0.5]);
+        assert_eq!(phase(5), [0.25, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn stars_are_on_the_sky() {
+        let stars = stars();
+
+        assert!(!stars.is_empty() && stars.len() < STAR_COUNT);
+        assert_eq!(stars, super::stars());
+
+        for corner in stars.iter().flatten() {
+            assert!((corner.magnitude() - SKY_DISTANCE).abs() < 0.5);
+        }
+    }
+}
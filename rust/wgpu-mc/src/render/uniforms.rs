@@ -0,0 +1,133 @@
+//! # Frame uniforms
+//!
+//! Every frame, the [crate::render::graph::ShaderGraph] uploads the [FrameUniforms] to the `wm_frame_uniforms`
+//! resource once, which pipelines bind like any other uniform of the pack. Custom pipelines find it in the resources
+//! of the graph under the same name. In WGSL it's declared as
+//!
+//! ```wgsl
+//! struct FrameUniforms {
+//!     fog_color: vec4<f32>,
+//!     sun_direction: vec3<f32>,
+//!     elapsed_time: f32,
+//!     viewport_size: vec2<f32>,
+//!     fog_start: f32,
+//!     fog_end: f32,
+//!     tick_delta: f32,
+//!     dimension: i32,
+//!     time_of_day: f32,
+//!     sun_angle: f32,
+//!     camera_position: vec3<f32>,
+//! }
+//! ```
+//!
+//! For shaderpacks, a few of them are also resources of their own, which are bound like other resources of the
+//! pack:
+//!
+//! - `wm_f32_time_of_day`: [FrameUniforms::time_of_day]
+//! - `wm_f32_sun_angle`: [FrameUniforms::sun_angle]
+//! - `wm_vec3_camera_position`: [FrameUniforms::camera_position], padded to 16 bytes
+
+use bytemuck::{Pod, Zeroable};
+
+/// The name of the resource holding the [FrameUniforms]
+pub const FRAME_UNIFORMS: &str = "wm_frame_uniforms";
+
+/// Set through [crate::WmRenderer::frame_uniforms]. `elapsed_time`, `viewport_size` and `camera_position` are
+/// filled in by the graph.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameUniforms {
+    /// Seconds since the graph was created
+    pub elapsed_time: f32,
+    /// How far the game is between the last tick and the next one, from 0 to 1
+    pub tick_delta: f32,
+    /// The distances from the camera where fog starts and where it's opaque
+    pub fog_start: f32,
+    pub fog_end: f32,
+    pub fog_color: [f32; 4],
+    /// The size of the surface in pixels
+    pub viewport_size: [f32; 2],
+    /// Points from the camera towards the sun, normalized
+    pub sun_direction: [f32; 3],
+    /// The dimension the camera is in, as the frontend numbers them. Minecraft has -1 for the nether, 0 for the
+    /// overworld and 1 for the end.
+    pub dimension: i32,
+    /// How far the day is along, from 0 at sunrise to 1 at the next one
+    pub time_of_day: f32,
+    /// The angle of the sun in radians, 0 at sunrise
+    pub sun_angle: f32,
+    /// The position of the camera in the world
+    pub camera_position: [f32; 3],
+}
+
+/// [FrameUniforms] in the layout of the WGSL struct in [crate::render::uniforms]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Pod, Zeroable)]
+struct FrameUniformsLayout {
+    fog_color: [f32; 4],
+    sun_direction: [f32; 3],
+    elapsed_time: f32,
+    viewport_size: [f32; 2],
+    fog_start: f32,
+    fog_end: f32,
+    tick_delta: f32,
+    dimension: i32,
+    time_of_day: f32,
+    sun_angle: f32,
+    camera_position: [f32; 3],
+    _padding: u32,
+}
+
+impl FrameUniforms {
+    /// The size of the uniform buffer
+    pub const SIZE: usize = std::mem::size_of::<FrameUniformsLayout>();
+
+    /// The contents of the uniform buffer
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        bytemuck::cast(FrameUniformsLayout {
+            fog_color: self.fog_color,
+            sun_direction: self.sun_direction,
+            elapsed_time: self.elapsed_time,
+            viewport_size: self.viewport_size,
+            fog_start: self.fog_start,
+            fog_end: self.fog_end,
+            tick_delta: self.tick_delta,
+            dimension: self.dimension,
+            time_of_day: self.time_of_day,
+            sun_angle: self.sun_angle,
+            camera_position: self.camera_position,
+            _padding: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameUniforms;
+
+    #[test]
+    fn frame_uniforms_match_the_wgsl_layout() {
+        //Uniform buffers are sized in multiples of 16 bytes
+        assert_eq!(FrameUniforms::SIZE, 80);
+
+        let bytes = FrameUniforms {
+            elapsed_time: 1.5,
+            viewport_size: [1920.0, 1080.0],
+            dimension: -1,
+            sun_angle: 0.5,
+            camera_position: [1.0, 2.0, 3.0],
+            ..Default::default()
+        }
+        .to_bytes();
+
+        let float =
+            |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(float(28), 1.5);
+        assert_eq!(float(32), 1920.0);
+        assert_eq!(float(36), 1080.0);
+        assert_eq!(i32::from_le_bytes(bytes[52..56].try_into().unwrap()), -1);
+        assert_eq!(float(60), 0.5);
+        //vec3 is aligned to 16 bytes
+        assert_eq!([float(64), float(68), float(72)], [1.0, 2.0, 3.0]);
+    }
+}
@@ -23,6 +23,39 @@ pub struct TextureSamplerView {
 impl TextureSamplerView {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    /// The format of the depth textures of the shader graph, [TextureSamplerView::DEPTH_FORMAT] unless they need a
+    /// stencil aspect for [crate::WmConfig::stencil]. The depth stays a 32 bit float if the device has
+    /// [wgpu::Features::DEPTH32FLOAT_STENCIL8], every other device supports a 24 bit depth with a stencil.
+    pub fn depth_format(stencil: bool, features: wgpu::Features) -> wgpu::TextureFormat {
+        match (
+            stencil,
+            features.contains(wgpu::Features::DEPTH32FLOAT_STENCIL8),
+        ) {
+            (false, _) => Self::DEPTH_FORMAT,
+            (true, true) => wgpu::TextureFormat::Depth32FloatStencil8,
+            (true, false) => wgpu::TextureFormat::Depth24PlusStencil8,
+        }
+    }
+
+    /// Whether textures of the format are bound as depth textures
+    pub fn is_depth_format(format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format,
+            wgpu::TextureFormat::Depth16Unorm
+                | wgpu::TextureFormat::Depth24Plus
+                | wgpu::TextureFormat::Depth32Float
+                | wgpu::TextureFormat::Depth24PlusStencil8
+                | wgpu::TextureFormat::Depth32FloatStencil8
+        )
+    }
+
+    pub fn has_stencil(format: wgpu::TextureFormat) -> bool {
+        matches!(
+            format,
+            wgpu::TextureFormat::Depth24PlusStencil8 | wgpu::TextureFormat::Depth32FloatStencil8
+        )
+    }
+
     pub fn from_image_file_bytes(
         wgpu_state: &WgpuState,
         bytes: &[u8],
@@ -133,6 +166,14 @@ impl BindableTexture {
         texture: TextureSamplerView,
         depth: bool,
     ) -> Self {
+        //Textures with a stencil aspect can only be sampled through a view of their depth
+        let depth_view = (depth && TextureSamplerView::has_stencil(texture.format)).then(|| {
+            texture.texture.create_view(&wgpu::TextureViewDescriptor {
+                aspect: wgpu::TextureAspect::DepthOnly,
+                ..Default::default()
+            })
+        });
+
         let bind_group = wgpu_state
             .device
             .create_bind_group(&wgpu::BindGroupDescriptor {
@@ -145,7 +186,9 @@ impl BindableTexture {
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&texture.view),
+                        resource: wgpu::BindingResource::TextureView(
+                            depth_view.as_ref().unwrap_or(&texture.view),
+                        ),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
@@ -1,15 +1,106 @@
 use crate::WmRenderer;
+use parking_lot::Mutex;
 use std::alloc::{alloc_zeroed, dealloc, Layout};
 use std::cell::RefCell;
 use std::cmp::min;
 use std::marker::PhantomData;
 use std::mem::{align_of, size_of};
 use std::ptr::drop_in_place;
-use wgpu::util::{BufferInitDescriptor, DeviceExt};
-use wgpu::{BindGroupDescriptor, BindGroupEntry};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wgpu::util::{BufferInitDescriptor, DeviceExt, StagingBelt};
+use wgpu::{BindGroupDescriptor, BindGroupEntry, BufferSize, CommandEncoderDescriptor};
 
 const ALIGN: usize = 8;
 
+/// 4 MiB, larger uploads get a staging buffer of their own
+const UPLOAD_BELT_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+///Buffer uploads which are copied through a [StagingBelt] into their destination before the next frame is
+/// rendered, instead of each one going through [wgpu::Queue::write_buffer]. See [WmRenderer::queue_upload]
+/// and [WmRenderer::flush_uploads]
+pub struct UploadBelt {
+    belt: StagingBelt,
+    encoder: Option<wgpu::CommandEncoder>,
+    /// The number of flushes so far
+    flushed: u64,
+    /// The last generation whose uploads the GPU has finished, set from [wgpu::Queue::on_submitted_work_done]
+    completed: Arc<AtomicU64>,
+}
+
+impl Default for UploadBelt {
+    fn default() -> Self {
+        Self {
+            belt: StagingBelt::new(UPLOAD_BELT_CHUNK_SIZE),
+            encoder: None,
+            flushed: 0,
+            completed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl UploadBelt {
+    ///The data must not be empty, and both the offset and the length of the data have to be multiples of
+    /// [wgpu::COPY_BUFFER_ALIGNMENT]
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        target: &wgpu::Buffer,
+        offset: u64,
+        data: &[u8],
+    ) {
+        let size = BufferSize::new(data.len() as u64).expect("Uploads must not be empty");
+
+        let encoder = self.encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Upload belt encoder"),
+            })
+        });
+
+        self.belt
+            .write_buffer(encoder, target, offset, size, device)
+            .copy_from_slice(data);
+    }
+
+    ///Submits every upload written since the last flush. Returns false if there was nothing to upload
+    pub fn flush(&mut self, queue: &wgpu::Queue) -> bool {
+        self.flushed += 1;
+
+        let generation = self.flushed;
+        let completed = self.completed.clone();
+
+        let submitted = match self.encoder.take() {
+            None => false,
+            Some(encoder) => {
+                self.belt.finish();
+                queue.submit([encoder.finish()]);
+                self.belt.recall();
+
+                true
+            }
+        };
+
+        //Even an empty generation is only complete once the ones before it are, so it waits for the work which was
+        // submitted so far too
+        queue.on_submitted_work_done(move || {
+            completed.fetch_max(generation, Ordering::Release);
+        });
+
+        submitted
+    }
+
+    ///The generation of the uploads written right now, which is submitted by the next flush
+    pub fn generation(&self) -> u64 {
+        self.flushed + 1
+    }
+
+    ///The last generation which the GPU has finished copying. The callbacks which update it only run when the
+    /// device is polled
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Acquire)
+    }
+}
+
 #[derive(Debug)]
 ///There are a couple bind group layouts which are roughly the same, such as `ssbo` or `matrix` but have slightly different semantics; this
 /// is a convenience struct to deduplicate code
@@ -49,9 +140,292 @@ impl BindableBuffer {
     }
 }
 
+fn align_up(size: u64, alignment: u64) -> u64 {
+    size.div_ceil(alignment) * alignment
+}
+
+/// Hands out the offsets of [UniformAllocator], see there
+#[derive(Debug)]
+struct UniformRing {
+    /// The size of the region of each frame
+    region_size: u64,
+    alignment: u64,
+    binding_size: u64,
+    /// The frames handed out so far, the region of a frame is `frame % FRAMES_IN_FLIGHT`
+    frame: u64,
+    /// The next free offset within the region of the current frame
+    next: u64,
+}
+
+impl UniformRing {
+    /// The offset of the next slot of the current frame, or [None] if its region is full
+    fn allocate(&mut self) -> Option<u64> {
+        if self.next + self.binding_size > self.region_size {
+            return None;
+        }
+
+        let offset =
+            (self.frame % UniformAllocator::FRAMES_IN_FLIGHT) * self.region_size + self.next;
+        self.next += align_up(self.binding_size, self.alignment);
+
+        Some(offset)
+    }
+
+    /// Doubles the size of the regions, for a new buffer which the current frame goes on in from its start
+    fn grow(&mut self) {
+        self.region_size *= 2;
+        self.next = 0;
+    }
+
+    fn next_frame(&mut self) {
+        self.frame += 1;
+        self.next = 0;
+    }
+}
+
+/// The buffer of a [UniformAllocator] and the bind group its slots are bound with
+#[derive(Debug)]
+pub struct UniformBlock {
+    pub buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+/// A slot of a [UniformAllocator], bound with [UniformAllocation::offset] as the dynamic offset of the bind group of
+/// its block. The block is kept alive by the allocation, even once the allocator has grown into a new one.
+#[derive(Clone, Debug)]
+pub struct UniformAllocation {
+    pub block: Arc<UniformBlock>,
+    pub offset: u32,
+}
+
+///Per-frame uniform data, like camera matrices, the [crate::render::uniforms::FrameUniforms] or the push constants
+/// of devices without them, sub-allocated from a single uniform buffer and bound with dynamic offsets. The buffer has
+/// a region for each of [UniformAllocator::FRAMES_IN_FLIGHT], so the data of one frame is never written over while
+/// the GPU could still be reading it for an earlier one. Every allocation is bound with the same size, the largest one
+/// the allocator was created for.
+///
+/// When a frame runs out of slots, the allocator moves on to a new buffer with twice the capacity. Allocations made
+/// before that keep the old buffer alive for as long as they're bound.
+pub struct UniformAllocator {
+    label: String,
+    layout: wgpu::BindGroupLayout,
+    state: Mutex<(UniformRing, Arc<UniformBlock>)>,
+}
+
+impl UniformAllocator {
+    pub const FRAMES_IN_FLIGHT: u64 = 2;
+    /// The name uniforms which are bound with the layout of [WmRenderer::uniforms] are given in the resource types
+    /// of [crate::render::graph::ShaderGraph::init]
+    pub const LAYOUT: &'static str = "dynamic_uniform";
+
+    /// `binding_size` is the size of each allocation, and `capacity` the number of allocations per frame it starts
+    /// out with
+    #[must_use]
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        visibility: wgpu::ShaderStages,
+        binding_size: u64,
+        capacity: u64,
+    ) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: BufferSize::new(binding_size),
+                },
+                count: None,
+            }],
+        });
+
+        let ring = UniformRing {
+            region_size: align_up(binding_size, alignment) * capacity,
+            alignment,
+            binding_size,
+            frame: 0,
+            next: 0,
+        };
+
+        let block = Self::create_block(device, label, &layout, &ring);
+
+        Self {
+            label: label.into(),
+            layout,
+            state: Mutex::new((ring, Arc::new(block))),
+        }
+    }
+
+    fn create_block(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        ring: &UniformRing,
+    ) -> UniformBlock {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: ring.region_size * Self::FRAMES_IN_FLIGHT,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some(label),
+            layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: BufferSize::new(ring.binding_size),
+                }),
+            }],
+        });
+
+        UniformBlock { buffer, bind_group }
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    /// The size every allocation is bound with
+    pub fn binding_size(&self) -> u64 {
+        self.state.lock().0.binding_size
+    }
+
+    ///Writes the data to the next free slot of the current frame, growing the allocator if there's none left.
+    /// Returns [None] if the data is larger than the binding size
+    pub fn allocate(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[u8],
+    ) -> Option<UniformAllocation> {
+        let mut state = self.state.lock();
+        let (ring, block) = &mut *state;
+
+        if data.len() as u64 > ring.binding_size {
+            return None;
+        }
+
+        let offset = match ring.allocate() {
+            Some(offset) => offset,
+            None => {
+                ring.grow();
+                *block = Arc::new(Self::create_block(device, &self.label, &self.layout, ring));
+
+                log::debug!(
+                    "Grew the uniform allocator {} to {} bytes per frame",
+                    self.label,
+                    ring.region_size
+                );
+
+                ring.allocate()?
+            }
+        };
+
+        queue.write_buffer(&block.buffer, offset, data);
+
+        Some(UniformAllocation {
+            block: block.clone(),
+            offset: offset as u32,
+        })
+    }
+
+    ///Moves on to the region of the next frame, which [WmRenderer::render] does before every frame
+    pub fn next_frame(&self) {
+        self.state.lock().0.next_frame();
+    }
+}
+
+/// Where a uniform of the shader graph, like a matrix of the camera, was written to in [WmRenderer::uniforms] for
+/// the current frame, see [UniformSlot::write]
+#[derive(Debug, Default)]
+pub struct UniformSlot(Mutex<Option<UniformAllocation>>);
+
+impl UniformSlot {
+    /// Writes the data to a new slot of [WmRenderer::uniforms], which draws recorded from now on are bound to
+    pub fn write(&self, wm: &WmRenderer, data: &[u8]) {
+        let allocation = wm
+            .uniforms
+            .allocate(&wm.wgpu_state.device, &wm.wgpu_state.queue, data);
+
+        if allocation.is_none() {
+            log::error!(
+                "A uniform of {} bytes doesn't fit in the {} bytes of a slot",
+                data.len(),
+                wm.uniforms.binding_size()
+            );
+        }
+
+        *self.0.lock() = allocation;
+    }
+
+    /// The slot the uniform was last written to, or [None] if it hasn't been
+    pub fn get(&self) -> Option<UniformAllocation> {
+        self.0.lock().clone()
+    }
+}
+
 type WmArenaObject = (*mut u8, unsafe fn(*mut u8));
 
 /// Untyped arena for render passes
+///
+/// A [wgpu::RenderPass] only borrows the buffers, bind groups and pipelines set on it, and those borrows have to
+/// last until the pass is finished. Anything created while recording the pass, such as a per-frame vertex buffer,
+/// can be moved into the arena so that it lives as long as the pass instead of being dropped at the end of the
+/// function which made it. Everything in the arena is dropped together with the arena.
+///
+/// `'a` is the lifetime of the references handed out by [WmArena::alloc], which is the lifetime of the arena's
+/// backing storage. It must never outlive the arena itself, which is why the arena is always passed around as
+/// `&'resource WmArena<'resource>`. [crate::render::graph::ShaderGraph::render] creates a new arena every frame,
+/// so nothing allocated in it is kept around for the next one.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use wgpu_mc::mc::chunk::ChunkPos;
+/// use wgpu_mc::render::graph::{CustomResource, GeometryCallback, ShaderGraph};
+/// use wgpu_mc::render::shaderpack::PipelineConfig;
+/// use wgpu_mc::util::WmArena;
+/// use wgpu_mc::wgpu::util::{BufferInitDescriptor, DeviceExt};
+/// use wgpu_mc::wgpu::{BufferUsages, RenderPass, SurfaceConfiguration};
+/// use wgpu_mc::WmRenderer;
+///
+/// struct Triangle;
+///
+/// impl GeometryCallback for Triangle {
+///     fn render<'pass, 'resource: 'pass>(
+///         &self,
+///         wm: &WmRenderer,
+///         pass: &mut RenderPass<'pass>,
+///         _graph: &'pass ShaderGraph,
+///         _config: &PipelineConfig,
+///         _resources: &'resource HashMap<String, CustomResource>,
+///         arena: &'resource WmArena<'resource>,
+///         _surface_config: &SurfaceConfiguration,
+///         _chunk_offset: ChunkPos,
+///     ) {
+///         //A local buffer would be dropped before the pass is done with it, the arena keeps it alive
+///         let buffer = arena.alloc(wm.wgpu_state.device.create_buffer_init(&BufferInitDescriptor {
+///             label: None,
+///             contents: bytemuck::cast_slice(&[0.0f32; 9]),
+///             usage: BufferUsages::VERTEX,
+///         }));
+///
+///         pass.set_vertex_buffer(0, buffer.slice(..));
+///         pass.draw(0..3, 0..1);
+///     }
+/// }
+/// ```
 pub struct WmArena<'a> {
     heap: RefCell<*mut u8>,
     capacity: RefCell<usize>,
@@ -229,3 +603,59 @@ impl<'a> Drop for WmArena<'a> {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::{UniformAllocator, UniformRing};
+
+    #[test]
+    fn uniform_frames_use_their_own_region() {
+        let mut ring = UniformRing {
+            region_size: 512,
+            alignment: 256,
+            binding_size: 128,
+            frame: 0,
+            next: 0,
+        };
+
+        assert_eq!(ring.allocate(), Some(0));
+        assert_eq!(ring.allocate(), Some(256));
+        //The region is full
+        assert_eq!(ring.allocate(), None);
+
+        ring.next_frame();
+        assert_eq!(ring.allocate(), Some(512));
+
+        //Back to the region of the first frame, which the GPU is done with by now
+        ring.next_frame();
+        assert_eq!(UniformAllocator::FRAMES_IN_FLIGHT, 2);
+        assert_eq!(ring.allocate(), Some(0));
+    }
+
+    #[test]
+    fn full_frames_grow_into_a_new_buffer() {
+        let mut ring = UniformRing {
+            region_size: 512,
+            alignment: 256,
+            binding_size: 128,
+            frame: 1,
+            next: 0,
+        };
+
+        assert_eq!(ring.allocate(), Some(512));
+        assert_eq!(ring.allocate(), Some(768));
+        assert_eq!(ring.allocate(), None);
+
+        //The frame goes on from the start of its region in the new buffer
+        ring.grow();
+        assert_eq!(ring.region_size, 1024);
+        assert_eq!(ring.allocate(), Some(1024));
+        assert_eq!(ring.allocate(), Some(1280));
+        assert_eq!(ring.allocate(), Some(1536));
+        assert_eq!(ring.allocate(), Some(1792));
+        assert_eq!(ring.allocate(), None);
+
+        ring.next_frame();
+        assert_eq!(ring.allocate(), Some(0));
+    }
+}